@@ -0,0 +1,156 @@
+//! Latency of the quote -> sign -> send path and throughput of concurrent
+//! tool calls, with Jupiter and the Solana RPC served by a mock server and
+//! the mock signer, so runs compare the code and not the network
+//!
+//! cargo bench --bench quotes --features test-utils
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use criterion::{
+    criterion_group, criterion_main, BenchmarkId, Criterion, Throughput,
+};
+use futures::future::join_all;
+use listen_kit::dispatch::dispatch;
+use listen_kit::fixtures::fixture;
+use listen_kit::signer::mock::MockSigner;
+use listen_kit::signer::SignerContext;
+use listen_kit::solana::blockhash::BlockhashCache;
+use listen_kit::solana::jup::Jupiter;
+use listen_kit::solana::util::execute_solana_transaction;
+use listen_kit::tool_error::ToolError;
+use solana_sdk::pubkey::Pubkey;
+use tokio::runtime::Runtime;
+use wiremock::matchers::{body_partial_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const WSOL: &str = "So11111111111111111111111111111111111111112";
+const USDC: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+async fn mock_server() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/quote"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(fixture("jupiter/quote")),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/swap-instructions"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(fixture("jupiter/swap_instructions")),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(body_partial_json(
+            serde_json::json!({ "method": "getLatestBlockhash" }),
+        ))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(fixture("jupiter/latest_blockhash")),
+        )
+        .mount(&server)
+        .await;
+    server
+}
+
+/// The trade tool minus the LLM: quote, swap transaction, sign and send
+async fn quote_sign_send(jupiter: Arc<Jupiter>) -> anyhow::Result<String> {
+    execute_solana_transaction(move |owner| async move {
+        let quote = jupiter.fetch_quote(WSOL, USDC, 1_000_000, 50).await?;
+        jupiter.swap(quote, &owner, None).await
+    })
+    .await
+}
+
+fn bench_quotes(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let server = runtime.block_on(mock_server());
+    // refreshed in the background of the runtime
+    let blockhashes =
+        runtime.block_on(async { BlockhashCache::new(&server.uri()) });
+    let jupiter =
+        Arc::new(Jupiter::new(&server.uri(), Arc::new(blockhashes)));
+    let owner = Pubkey::from_str(WSOL).unwrap();
+
+    c.bench_function("fetch_quote", |b| {
+        b.to_async(&runtime).iter(|| async {
+            jupiter
+                .fetch_quote(WSOL, USDC, 1_000_000, 50)
+                .await
+                .unwrap()
+        })
+    });
+
+    c.bench_function("quote_and_swap", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let quote = jupiter
+                .fetch_quote(WSOL, USDC, 1_000_000, 50)
+                .await
+                .unwrap();
+            jupiter.swap(quote, &owner, None).await.unwrap()
+        })
+    });
+
+    let signer = Arc::new(MockSigner::new(Duration::ZERO));
+    c.bench_function("quote_sign_send", |b| {
+        b.to_async(&runtime).iter(|| {
+            SignerContext::with_signer(
+                signer.clone(),
+                quote_sign_send(jupiter.clone()),
+            )
+        })
+    });
+
+    // tool calls through the dispatcher, so the session checks, rate limits
+    // and audit are part of the numbers. Every call has its own user and
+    // arguments to stay clear of the rate limits and the deduplication
+    let calls = AtomicU64::new(0);
+    let mut group = c.benchmark_group("concurrent_tool_calls");
+    for concurrency in [1, 8, 32, 128] {
+        group.throughput(Throughput::Elements(concurrency));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(concurrency),
+            &concurrency,
+            |b, &concurrency| {
+                b.to_async(&runtime).iter(|| {
+                    join_all((0..concurrency).map(|_| {
+                        let call = calls.fetch_add(1, Ordering::Relaxed);
+                        let signer =
+                            MockSigner::new(Duration::from_millis(5))
+                                .with_user(&format!("bench-{}", call));
+                        let jupiter = jupiter.clone();
+                        SignerContext::with_signer(
+                            Arc::new(signer),
+                            async move {
+                                dispatch(
+                                    "perform_jupiter_swap",
+                                    serde_json::json!({ "call": call })
+                                        .to_string(),
+                                    |_| async move {
+                                        quote_sign_send(jupiter)
+                                            .await
+                                            .map_err(|e| {
+                                                ToolError::from_anyhow(&e)
+                                            })
+                                    },
+                                )
+                                .await
+                                .unwrap()
+                            },
+                        )
+                    }))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_quotes);
+criterion_main!(benches);
@@ -0,0 +1,4 @@
+ALTER TABLE kv_store ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS kv_store_expires_at ON kv_store (expires_at)
+    WHERE expires_at IS NOT NULL;
@@ -0,0 +1,163 @@
+//! Per-session tool access. Operators can restrict which tools a session may
+//! call (e.g. a read-only tier), the restriction is enforced when the tool
+//! call is dispatched, the prompt is not relied upon
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::tool_error::{ErrorCode, ToolError};
+
+/// Tools that sign transactions or change persisted settings
+const WRITE_TOOLS: &[&str] = &[
+    "approve_token_for_router_spend",
+    "trade",
+    "transfer_eth",
+    "transfer_erc20",
+    "approve_token_for_position_manager",
+    "mint_lp_position",
+    "increase_lp_liquidity",
+    "decrease_lp_liquidity",
+    "collect_lp_fees",
+    "bridge_via_gateway",
+    "claim_gateway_transfer",
+    "perform_jupiter_swap",
+    "transfer_sol",
+    "transfer_spl_token",
+    "deploy_pump_fun_token",
+    "buy_pump_fun_token",
+    "sell_pump_fun_token",
+    "watch_pump_launches",
+    "stop_watching_pump_launches",
+    "multichain_swap",
+    "approve_token",
+    "confirm_action",
+    "execute_quote",
+    "twap_swap",
+    "cancel_twap_order",
+    "move_to_savings",
+    "withdraw_from_savings",
+    "set_risk_limits",
+    "block_token",
+    "allow_token",
+    "set_execution_mode",
+    "schedule_task",
+    "cancel_scheduled_task",
+    "set_price_alert",
+    "cancel_alert",
+    "set_trigger_order",
+    "cancel_trigger_order",
+];
+
+const PUMP_FUN_TOOLS: &[&str] = &[
+    "deploy_pump_fun_token",
+    "buy_pump_fun_token",
+    "sell_pump_fun_token",
+    "watch_pump_launches",
+    "list_pump_launch_watches",
+    "stop_watching_pump_launches",
+];
+
+const BRIDGING_TOOLS: &[&str] = &[
+    "bridge_via_gateway",
+    "claim_gateway_transfer",
+    "multichain_swap",
+    "quote_multichain_swap",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolAccess {
+    #[default]
+    Full,
+    ReadOnly,
+    NoPumpFun,
+    NoBridging,
+    /// only the listed tools
+    Only(Vec<String>),
+    /// every tool except the listed ones
+    Except(Vec<String>),
+}
+
+impl ToolAccess {
+    /// Default access of new sessions, TOOL_ACCESS takes the same values as
+    /// `FromStr`, unset means full access
+    pub fn from_env() -> Result<Self> {
+        match std::env::var("TOOL_ACCESS") {
+            Ok(access) => access.parse(),
+            Err(_) => Ok(Self::Full),
+        }
+    }
+
+    pub fn allows(&self, tool: &str) -> bool {
+        match self {
+            Self::Full => true,
+            Self::ReadOnly => !WRITE_TOOLS.contains(&tool),
+            Self::NoPumpFun => !PUMP_FUN_TOOLS.contains(&tool),
+            Self::NoBridging => !BRIDGING_TOOLS.contains(&tool),
+            Self::Only(tools) => tools.iter().any(|t| t == tool),
+            Self::Except(tools) => !tools.iter().any(|t| t == tool),
+        }
+    }
+
+    pub fn check(&self, tool: &str) -> Result<()> {
+        if !self.allows(tool) {
+            return Err(ToolError::new(
+                ErrorCode::AccessDenied,
+                format!("Tool {} is not available in this session", tool),
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Parses full, read_only, no_pump_fun, no_bridging, only:<tool>,<tool>..
+/// and except:<tool>,<tool>..
+impl FromStr for ToolAccess {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let tools = |list: &str| -> Vec<String> {
+            list.split(',')
+                .map(|tool| tool.trim().to_string())
+                .filter(|tool| !tool.is_empty())
+                .collect()
+        };
+        match s.trim() {
+            "full" => Ok(Self::Full),
+            "read_only" => Ok(Self::ReadOnly),
+            "no_pump_fun" => Ok(Self::NoPumpFun),
+            "no_bridging" => Ok(Self::NoBridging),
+            s => {
+                if let Some(list) = s.strip_prefix("only:") {
+                    Ok(Self::Only(tools(list)))
+                } else if let Some(list) = s.strip_prefix("except:") {
+                    Ok(Self::Except(tools(list)))
+                } else {
+                    Err(anyhow!("Invalid tool access: {}", s))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_access() {
+        assert!(ToolAccess::Full.allows("transfer_sol"));
+        assert!(!ToolAccess::ReadOnly.allows("transfer_sol"));
+        assert!(ToolAccess::ReadOnly.allows("get_portfolio"));
+        assert!(!ToolAccess::NoPumpFun.allows("buy_pump_fun_token"));
+        assert!(ToolAccess::NoBridging.allows("trade"));
+
+        let access: ToolAccess =
+            "only:get_portfolio, get_sol_balance".parse().unwrap();
+        assert!(access.allows("get_sol_balance"));
+        assert!(!access.allows("trade"));
+        assert!("admin".parse::<ToolAccess>().is_err());
+    }
+}
@@ -0,0 +1,183 @@
+//! Wallet activity export for operators. Every transaction the agent sends
+//! is queued as a job that waits for it to confirm (or fail) on chain and
+//! then posts it to activity.webhook_url, signed with
+//! activity.webhook_secret like the other webhooks (see
+//! `notify::sign_webhook`), so accounting and fraud monitoring systems stay
+//! in sync. The job queue persists the events and retries the deliveries.
+//! A transaction still pending on the last retry is exported as dropped so
+//! the receiver doesn't wait for it forever. Off unless the webhook is
+//! configured, paper signers never export anything
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use solana_sdk::commitment_config::CommitmentLevel;
+
+use crate::config::config;
+use crate::confirmation::{tx_status, TxStatus};
+use crate::jobs::{Job, RetryPolicy, JOBS};
+use crate::notify::{
+    Notification, NotificationKind, NotificationSink, WebhookSink,
+};
+use crate::signer::TransactionSigner;
+
+pub const ACTIVITY_JOB: &str = "activity_webhook";
+/// Covers the confirmation of slow chains and a webhook down for a while
+const ACTIVITY_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: 20,
+    backoff_secs: 10,
+    max_backoff_secs: 30 * 60,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityStatus {
+    Pending,
+    Confirmed,
+    Failed,
+    /// still unseen or unconfirmed when the retries ran out
+    Dropped,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActivityEvent {
+    /// signature or transaction hash
+    pub hash: String,
+    /// "sol" or the key of the EVM chain
+    pub chain: String,
+    /// EVM chain id, the chain of the local signer without one
+    pub chain_id: Option<u64>,
+    pub user_id: Option<String>,
+    pub wallet: Option<String>,
+    /// what the transaction does, when it could be explained
+    pub description: Option<String>,
+    pub status: ActivityStatus,
+    pub sent_at: DateTime<Utc>,
+}
+
+impl ActivityEvent {
+    pub fn new(
+        signer: &dyn TransactionSigner,
+        chain: &str,
+        chain_id: Option<u64>,
+        hash: &str,
+    ) -> Self {
+        Self {
+            hash: hash.to_string(),
+            chain: chain.to_string(),
+            chain_id,
+            user_id: signer.user_id(),
+            wallet: signer.wallet_id(),
+            description: None,
+            status: ActivityStatus::Pending,
+            sent_at: Utc::now(),
+        }
+    }
+
+    pub fn with_description(mut self, description: Option<String>) -> Self {
+        self.description = description;
+        self
+    }
+
+    /// Where the transaction stands on chain
+    async fn fetch_status(&self) -> Result<ActivityStatus> {
+        let status =
+            tx_status(&self.hash, self.chain_id, CommitmentLevel::Processed)
+                .await?;
+        Ok(match status {
+            TxStatus::Confirmed { .. } => ActivityStatus::Confirmed,
+            TxStatus::Failed { .. } => ActivityStatus::Failed,
+            TxStatus::Pending | TxStatus::TimedOut => ActivityStatus::Pending,
+        })
+    }
+
+    fn notification(&self) -> Result<Notification> {
+        let title = match self.status {
+            ActivityStatus::Failed => "Transaction failed",
+            ActivityStatus::Dropped => "Transaction dropped",
+            _ => "Transaction confirmed",
+        };
+        Ok(Notification::new(
+            NotificationKind::Transaction,
+            self.user_id.clone(),
+            title,
+            format!("{} on {}", self.hash, self.chain),
+        )
+        .with_data(serde_json::to_value(self)?))
+    }
+}
+
+/// Queues the export of a transaction the agent just sent, failures are
+/// logged and never fail the caller
+pub async fn record(signer: &dyn TransactionSigner, event: ActivityEvent) {
+    if signer.is_paper() || config().activity.webhook_url.is_none() {
+        return;
+    }
+    let job = match serde_json::to_value(&event) {
+        Ok(payload) => Job::new(ACTIVITY_JOB, payload)
+            .with_retry(ACTIVITY_RETRY)
+            .with_delay(ACTIVITY_RETRY.delay(1)),
+        Err(e) => {
+            tracing::warn!(?e, hash = %event.hash, "activity not serialized");
+            return;
+        }
+    };
+    if let Err(e) = JOBS.enqueue(job).await {
+        tracing::warn!(?e, hash = %event.hash, "activity export not queued");
+    }
+}
+
+/// Posts the event once its transaction settled, fails (and is retried)
+/// while it is pending
+pub async fn run_activity_job(job: Job) -> Result<String> {
+    let last_attempt = job.attempts + 1 >= job.retry.max_attempts;
+    let mut event: ActivityEvent = serde_json::from_value(job.payload)?;
+    event.status = event.fetch_status().await?;
+    if event.status == ActivityStatus::Pending {
+        if !last_attempt {
+            return Err(anyhow!("{} is not confirmed yet", event.hash));
+        }
+        event.status = ActivityStatus::Dropped;
+    }
+    let settings = config().activity.clone();
+    let Some(url) = settings.webhook_url else {
+        return Ok("activity webhook not configured".to_string());
+    };
+    let mut webhook = WebhookSink::new(url);
+    if let Some(secret) = settings.webhook_secret {
+        webhook = webhook.with_secret(secret);
+    }
+    webhook.send(&event.notification()?).await?;
+    Ok(format!("{} exported as {:?}", event.hash, event.status))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::signer::mock::MockSigner;
+
+    #[test]
+    fn test_notification() {
+        let signer = MockSigner::new(Duration::ZERO).with_user("alice");
+        let mut event =
+            ActivityEvent::new(&signer, "sonic", Some(146), "0x1")
+                .with_description(Some("Transfer 1 S".to_string()));
+        event.status = ActivityStatus::Confirmed;
+        let notification = event.notification().unwrap();
+        assert_eq!(notification.title, "Transaction confirmed");
+        assert_eq!(notification.data["status"], "confirmed");
+        assert_eq!(notification.data["chain_id"], 146);
+        assert_eq!(notification.user_id.as_deref(), Some("alice"));
+        assert_eq!(
+            serde_json::from_value::<ActivityEvent>(notification.data)
+                .unwrap(),
+            event
+        );
+
+        event.status = ActivityStatus::Dropped;
+        let notification = event.notification().unwrap();
+        assert_eq!(notification.title, "Transaction dropped");
+        assert_eq!(notification.data["status"], "dropped");
+    }
+}
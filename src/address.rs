@@ -0,0 +1,201 @@
+//! Addresses of both chain families behind one type, parsed and validated
+//! once at the tool boundary so the rest of the code routes on the variant
+//! instead of comparing chain keys. EVM addresses display EIP-55
+//! checksummed, mixed case input has to carry a valid checksum
+use std::fmt;
+use std::str::FromStr;
+
+use alloy::primitives::Address as EvmAddress;
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::chains::Chain;
+use crate::error::Error;
+use crate::signer::TransactionSigner;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Address {
+    Solana(Pubkey),
+    Evm(EvmAddress),
+}
+
+impl Address {
+    /// Parses either format, 0x-prefixed values are EVM addresses
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        let value = value.trim();
+        if value.starts_with("0x") {
+            parse_evm(value).map(Self::Evm)
+        } else {
+            parse_solana(value).map(Self::Solana)
+        }
+    }
+
+    /// Parses an address of the chain key of the tools (sol, sonic, eth..)
+    pub fn parse_on(chain: &str, value: &str) -> Result<Self, Error> {
+        let value = value.trim();
+        match Chain::of_key(chain) {
+            Chain::Solana => parse_solana(value).map(Self::Solana),
+            Chain::Evm => parse_evm(value).map(Self::Evm),
+        }
+    }
+
+    /// Address of the signer on `chain`, an error when it has no wallet
+    /// there
+    pub fn of_signer(
+        signer: &dyn TransactionSigner,
+        chain: Chain,
+    ) -> Result<Self, Error> {
+        let (key, address) = match chain {
+            Chain::Solana => ("sol", signer.pubkey()),
+            Chain::Evm => ("evm", signer.address()),
+        };
+        if address.is_empty() {
+            return Err(Error::Signer(format!(
+                "The wallet has no {} address",
+                chain
+            )));
+        }
+        Self::parse_on(key, &address)
+    }
+
+    pub fn chain(&self) -> Chain {
+        match self {
+            Self::Solana(_) => Chain::Solana,
+            Self::Evm(_) => Chain::Evm,
+        }
+    }
+
+    pub fn as_solana(&self) -> Result<Pubkey, Error> {
+        match self {
+            Self::Solana(pubkey) => Ok(*pubkey),
+            Self::Evm(address) => Err(Error::UserInput(format!(
+                "{} is an EVM address, expected a Solana one",
+                address
+            ))),
+        }
+    }
+
+    pub fn as_evm(&self) -> Result<EvmAddress, Error> {
+        match self {
+            Self::Evm(address) => Ok(*address),
+            Self::Solana(pubkey) => Err(Error::UserInput(format!(
+                "{} is a Solana address, expected an EVM one",
+                pubkey
+            ))),
+        }
+    }
+}
+
+fn parse_solana(value: &str) -> Result<Pubkey, Error> {
+    let valid = bs58::decode(value)
+        .into_vec()
+        .is_ok_and(|bytes| bytes.len() == 32);
+    if !valid {
+        return Err(Error::UserInput(format!(
+            "{:?} is not a base58 Solana public key",
+            value
+        )));
+    }
+    Pubkey::from_str(value).map_err(|e| Error::UserInput(e.to_string()))
+}
+
+fn parse_evm(value: &str) -> Result<EvmAddress, Error> {
+    let hex = value.strip_prefix("0x").unwrap_or_default();
+    if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::UserInput(format!(
+            "{:?} is not a 0x-prefixed 40 hex digit address",
+            value
+        )));
+    }
+    let mixed_case = hex.chars().any(|c| c.is_ascii_lowercase())
+        && hex.chars().any(|c| c.is_ascii_uppercase());
+    if mixed_case {
+        EvmAddress::parse_checksummed(value, None).map_err(|_| {
+            Error::UserInput(format!("{} has an invalid checksum", value))
+        })
+    } else {
+        EvmAddress::from_str(value)
+            .map_err(|e| Error::UserInput(e.to_string()))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Solana(pubkey) => write!(f, "{}", pubkey),
+            Self::Evm(address) => f.write_str(&address.to_checksum(None)),
+        }
+    }
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Self::parse(s)
+    }
+}
+
+impl From<Pubkey> for Address {
+    fn from(pubkey: Pubkey) -> Self {
+        Self::Solana(pubkey)
+    }
+}
+
+impl From<EvmAddress> for Address {
+    fn from(address: EvmAddress) -> Self {
+        Self::Evm(address)
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(d)?;
+        Self::parse(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let sol = "So11111111111111111111111111111111111111112";
+        let address = Address::parse(sol).unwrap();
+        assert_eq!(address.chain(), Chain::Solana);
+        assert_eq!(address.to_string(), sol);
+        assert!(address.as_evm().is_err());
+
+        let lowercase = "0x1f9840a85d5af5bf1d1762f925bdaddc4201f984";
+        let address = Address::parse_on("sonic", lowercase).unwrap();
+        assert_eq!(
+            address.to_string(),
+            "0x1f9840a85d5aF5bf1D1762F925BDADdC4201F984"
+        );
+        assert_eq!(Address::parse(&address.to_string()).unwrap(), address);
+
+        assert!(Address::parse_on("sol", lowercase).is_err());
+        assert!(Address::parse_on("arb", sol).is_err());
+        // checksum broken by lowering one letter
+        assert!(Address::parse("0x1f9840a85d5af5bf1D1762F925BDADdC4201F984")
+            .is_err());
+    }
+
+    #[test]
+    fn test_serde() {
+        let address =
+            Address::parse("0x1f9840a85d5aF5bf1D1762F925BDADdC4201F984")
+                .unwrap();
+        let json = serde_json::to_string(&address).unwrap();
+        assert_eq!(json, "\"0x1f9840a85d5aF5bf1D1762F925BDADdC4201F984\"");
+        assert_eq!(serde_json::from_str::<Address>(&json).unwrap(), address);
+    }
+}
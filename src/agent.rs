@@ -0,0 +1,255 @@
+//! Agents assembled from the config: the tools of a kind of agent, narrowed
+//! to agents.tools and without agents.disabled_tools, on agents.model. Hosts
+//! pick a kind instead of importing and registering every tool function.
+//! The tools reach the signer of the call, the registries and the risk
+//! policies through the crate's shared state, the agent holds none of it
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Result};
+use rig::agent::Agent;
+use rig::providers::anthropic::completion::CompletionModel as AnthropicCompletionModel;
+use rig::tool::Tool;
+use serde::{Deserialize, Serialize};
+
+use crate::chains::{Chain, CHAINS};
+use crate::common::{ToolRegistry, PREAMBLE_COMMON};
+use crate::config::{config, Config};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentKind {
+    Solana,
+    Evm,
+    /// the omni agent of the server, LiFi quotes across chains
+    CrossChain,
+}
+
+impl AgentKind {
+    pub const ALL: [AgentKind; 3] =
+        [AgentKind::Solana, AgentKind::Evm, AgentKind::CrossChain];
+
+    /// Chain the tools of the agent need, None for cross-chain
+    pub fn chain(&self) -> Option<Chain> {
+        match self {
+            Self::Solana => Some(Chain::Solana),
+            Self::Evm => Some(Chain::Evm),
+            Self::CrossChain => None,
+        }
+    }
+
+    fn preamble(&self) -> &'static str {
+        match self {
+            Self::Solana => {
+                "you are a solana trading agent that can also interact with pump.fun;"
+            }
+            Self::Evm => "you are an ethereum trading agent",
+            Self::CrossChain => "you are a cross-chain trading agent",
+        }
+    }
+
+    /// Every tool of the agent, the selection of the config aside
+    pub fn register_all<R: ToolRegistry>(&self, registry: R) -> R {
+        match self {
+            Self::Solana => {
+                crate::solana::agent::register_solana_tools(registry)
+            }
+            Self::Evm => crate::evm::agent::register_evm_tools(registry),
+            Self::CrossChain => {
+                crate::cross_chain::agent::register_cross_chain_tools(
+                    registry,
+                )
+            }
+        }
+    }
+
+    /// Names of every tool of the agent
+    pub fn tool_names(&self) -> Vec<&'static str> {
+        self.register_all(ToolNames::default()).0
+    }
+}
+
+#[derive(Default)]
+struct ToolNames(Vec<&'static str>);
+
+impl ToolRegistry for ToolNames {
+    fn tool<T: Tool + 'static>(mut self, _tool: T) -> Self {
+        self.0.push(T::NAME);
+        self
+    }
+}
+
+/// Tools kept out of an agent or picked for it by name
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolSelection {
+    /// every tool when None
+    only: Option<HashSet<String>>,
+    disabled: HashSet<String>,
+}
+
+impl ToolSelection {
+    pub fn from_config(config: &Config) -> Self {
+        let agents = &config.agents;
+        Self {
+            only: (!agents.tools.is_empty())
+                .then(|| agents.tools.iter().cloned().collect()),
+            disabled: agents.disabled_tools.iter().cloned().collect(),
+        }
+    }
+
+    pub fn allows(&self, tool: &str) -> bool {
+        !self.disabled.contains(tool)
+            && self.only.as_ref().map_or(true, |only| only.contains(tool))
+    }
+
+    /// Fails on names no agent has, most likely typos
+    pub fn validate(&self) -> Result<()> {
+        let known: HashSet<&str> = AgentKind::ALL
+            .iter()
+            .flat_map(|kind| kind.tool_names())
+            .collect();
+        let mut unknown: Vec<&str> = self
+            .only
+            .iter()
+            .flatten()
+            .chain(&self.disabled)
+            .map(String::as_str)
+            .filter(|name| !known.contains(name))
+            .collect();
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        unknown.sort_unstable();
+        Err(anyhow!("Unknown tools {}", unknown.join(", ")))
+    }
+}
+
+/// Registry taking the tools of the selection only
+struct Selected<'a, R> {
+    registry: R,
+    selection: &'a ToolSelection,
+}
+
+impl<R: ToolRegistry> ToolRegistry for Selected<'_, R> {
+    fn tool<T: Tool + 'static>(self, tool: T) -> Self {
+        if !self.selection.allows(T::NAME) {
+            return self;
+        }
+        Self {
+            registry: self.registry.tool(tool),
+            selection: self.selection,
+        }
+    }
+}
+
+pub struct AgentBuilder {
+    kind: AgentKind,
+    model: String,
+    max_tokens: u64,
+    preamble: Option<String>,
+    selection: ToolSelection,
+}
+
+impl AgentBuilder {
+    /// Agent of `kind` as the loaded config sets it up
+    pub fn new(kind: AgentKind) -> Self {
+        Self::from_config(kind, &config())
+    }
+
+    pub fn from_config(kind: AgentKind, config: &Config) -> Self {
+        Self {
+            kind,
+            model: config.agents.model.clone(),
+            max_tokens: config.agents.max_tokens,
+            preamble: None,
+            selection: ToolSelection::from_config(config),
+        }
+    }
+
+    pub fn model(mut self, model: &str) -> Self {
+        self.model = model.to_string();
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: u64) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Replaces the preamble of the kind
+    pub fn preamble(mut self, preamble: &str) -> Self {
+        self.preamble = Some(preamble.to_string());
+        self
+    }
+
+    /// Narrows the agent to `tools`, on top of agents.tools
+    pub fn only_tools<S: AsRef<str>>(mut self, tools: &[S]) -> Self {
+        let tools: HashSet<String> =
+            tools.iter().map(|tool| tool.as_ref().to_string()).collect();
+        self.selection.only = Some(match self.selection.only.take() {
+            Some(only) => only.intersection(&tools).cloned().collect(),
+            None => tools,
+        });
+        self
+    }
+
+    pub fn without_tools<S: AsRef<str>>(mut self, tools: &[S]) -> Self {
+        self.selection
+            .disabled
+            .extend(tools.iter().map(|tool| tool.as_ref().to_string()));
+        self
+    }
+
+    /// Registers the selected tools of the agent with `registry`, e.g. the
+    /// MCP server
+    pub fn register<R: ToolRegistry>(&self, registry: R) -> R {
+        self.kind
+            .register_all(Selected {
+                registry,
+                selection: &self.selection,
+            })
+            .registry
+    }
+
+    pub fn build(self) -> Result<Agent<AnthropicCompletionModel>> {
+        if let Some(chain) = self.kind.chain() {
+            CHAINS.require(chain)?;
+        }
+        self.selection.validate()?;
+        let preamble = self.preamble.clone().unwrap_or_else(|| {
+            format!("{} {}", self.kind.preamble(), PREAMBLE_COMMON)
+        });
+        let builder = rig::providers::anthropic::Client::from_env()
+            .agent(&self.model)
+            .preamble(&preamble)
+            .max_tokens(self.max_tokens);
+        Ok(self.register(builder).build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_selection() {
+        let mut config = Config::default();
+        config.agents.tools =
+            vec!["get_sol_balance".into(), "get_public_key".into()];
+        config.agents.disabled_tools = vec!["get_public_key".into()];
+        let builder = AgentBuilder::from_config(AgentKind::Solana, &config);
+        assert_eq!(
+            builder.register(ToolNames::default()).0,
+            vec!["get_sol_balance"]
+        );
+        assert!(builder.selection.validate().is_ok());
+
+        let all =
+            AgentBuilder::from_config(AgentKind::Evm, &Config::default());
+        assert_eq!(
+            all.register(ToolNames::default()).0,
+            AgentKind::Evm.tool_names()
+        );
+        let typo = all.without_tools(&["get_sol_balanse"]);
+        assert!(typo.selection.validate().is_err());
+    }
+}
@@ -0,0 +1,367 @@
+//! Price alerts. Alerts are persisted in the kv store so they survive
+//! restarts, a background watcher polls the prices of the watched tokens and
+//! fires every alert whose threshold was crossed exactly once
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::kv_store::{KVStore, KV_STORE};
+use crate::notify::{Notification, NotificationKind, NOTIFIER};
+use crate::pricing::token_price;
+use crate::signer::{SignerContext, SignerRef};
+
+const ALERTS_KEY: &str = "alerts:price";
+const DEFAULT_TICK_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertCondition {
+    Above,
+    Below,
+}
+
+impl FromStr for AlertCondition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "above" | ">" | ">=" => Ok(Self::Above),
+            "below" | "<" | "<=" => Ok(Self::Below),
+            _ => Err(anyhow!("Invalid condition {}, use above or below", s)),
+        }
+    }
+}
+
+impl fmt::Display for AlertCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Above => write!(f, "above"),
+            Self::Below => write!(f, "below"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriceAlert {
+    pub id: String,
+    pub owner: SignerRef,
+    pub chain: String,
+    pub token: String,
+    pub symbol: String,
+    pub condition: AlertCondition,
+    /// USD price
+    pub target: f64,
+    pub created_at: DateTime<Utc>,
+    pub triggered_at: Option<DateTime<Utc>>,
+    pub triggered_price: Option<f64>,
+}
+
+impl PriceAlert {
+    pub fn is_active(&self) -> bool {
+        self.triggered_at.is_none()
+    }
+
+    pub fn is_crossed(&self, price: f64) -> bool {
+        match self.condition {
+            AlertCondition::Above => price >= self.target,
+            AlertCondition::Below => price <= self.target,
+        }
+    }
+
+    fn price_key(&self) -> (String, String) {
+        (self.chain.clone(), self.token.clone())
+    }
+}
+
+pub struct PriceAlerts {
+    store: Arc<dyn KVStore>,
+    /// alerts are stored as a single value, writes have to be serialized
+    lock: tokio::sync::Mutex<()>,
+    notifications: broadcast::Sender<PriceAlert>,
+}
+
+pub static ALERTS: Lazy<PriceAlerts> =
+    Lazy::new(|| PriceAlerts::new(KV_STORE.clone()));
+
+impl PriceAlerts {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self {
+            store,
+            lock: tokio::sync::Mutex::new(()),
+            notifications: broadcast::channel(64).0,
+        }
+    }
+
+    /// Triggered alerts are sent here as they fire, GET /v1/alerts/stream
+    /// streams them to their owners
+    pub fn subscribe(&self) -> broadcast::Receiver<PriceAlert> {
+        self.notifications.subscribe()
+    }
+
+    async fn load(&self) -> Result<Vec<PriceAlert>> {
+        match self.store.get(ALERTS_KEY).await? {
+            Some(alerts) => Ok(serde_json::from_str(&alerts)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    async fn save(&self, alerts: &[PriceAlert]) -> Result<()> {
+        self.store
+            .set(ALERTS_KEY, &serde_json::to_string(alerts)?)
+            .await
+    }
+
+    pub async fn add(&self, alert: PriceAlert) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut alerts = self.load().await?;
+        alerts.push(alert);
+        self.save(&alerts).await
+    }
+
+    pub async fn list(&self, owner: &SignerRef) -> Result<Vec<PriceAlert>> {
+        Ok(self
+            .load()
+            .await?
+            .into_iter()
+            .filter(|alert| alert.owner.same_user(owner))
+            .collect())
+    }
+
+    pub async fn cancel(
+        &self,
+        id: &str,
+        owner: &SignerRef,
+    ) -> Result<PriceAlert> {
+        let _guard = self.lock.lock().await;
+        let mut alerts = self.load().await?;
+        let index = alerts
+            .iter()
+            .position(|alert| alert.id == id && alert.owner.same_user(owner))
+            .ok_or_else(|| anyhow!("Alert {} not found", id))?;
+        let alert = alerts.remove(index);
+        self.save(&alerts).await?;
+        Ok(alert)
+    }
+
+    /// Marks the active alerts crossed at `prices` (keyed by chain and
+    /// token) as triggered and returns them
+    pub async fn trigger(
+        &self,
+        prices: &HashMap<(String, String), f64>,
+    ) -> Result<Vec<PriceAlert>> {
+        let _guard = self.lock.lock().await;
+        let mut alerts = self.load().await?;
+        let mut triggered = vec![];
+        for alert in alerts.iter_mut().filter(|alert| alert.is_active()) {
+            let Some(price) = prices.get(&alert.price_key()) else {
+                continue;
+            };
+            if alert.is_crossed(*price) {
+                alert.triggered_at = Some(Utc::now());
+                alert.triggered_price = Some(*price);
+                triggered.push(alert.clone());
+            }
+        }
+        if !triggered.is_empty() {
+            self.save(&alerts).await?;
+        }
+        Ok(triggered)
+    }
+
+    /// Fetches the price of every watched token once and fires the crossed
+    /// alerts, tokens that fail to price are retried on the next check
+    pub async fn check(&self) -> Result<Vec<PriceAlert>> {
+        let mut prices = HashMap::new();
+        for alert in self.load().await? {
+            if !alert.is_active() || prices.contains_key(&alert.price_key()) {
+                continue;
+            }
+            match token_price(&alert.chain, &alert.token).await {
+                Ok(price) => {
+                    prices.insert(alert.price_key(), price.price_usd);
+                }
+                Err(e) => {
+                    tracing::warn!(?e, token = %alert.token, "price failed")
+                }
+            }
+        }
+
+        let triggered = self.trigger(&prices).await?;
+        for alert in &triggered {
+            tracing::info!(
+                id = %alert.id,
+                symbol = %alert.symbol,
+                price = alert.triggered_price,
+                "price alert triggered"
+            );
+            // no receivers is fine, the alert stays listed as triggered
+            let _ = self.notifications.send(alert.clone());
+            let notification = Notification::new(
+                NotificationKind::PriceAlert,
+                alert.owner.user_id.clone(),
+                format!(
+                    "{} is {} {} USD",
+                    alert.symbol, alert.condition, alert.target
+                ),
+                format!(
+                    "{} is at {} USD",
+                    alert.symbol,
+                    alert.triggered_price.unwrap_or_default()
+                ),
+            )
+            .with_data(serde_json::to_value(alert)?);
+            NOTIFIER.notify(&notification).await;
+        }
+        Ok(triggered)
+    }
+
+    /// Checks the alerts every ALERTS_TICK_SECS (60 by default)
+    pub fn spawn(&'static self) -> tokio::task::JoinHandle<()> {
+        let tick = std::env::var("ALERTS_TICK_SECS")
+            .ok()
+            .and_then(|tick| tick.parse().ok())
+            .unwrap_or(DEFAULT_TICK_SECS);
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(std::time::Duration::from_secs(tick));
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.check().await {
+                    tracing::error!(?e, "price alert check failed");
+                }
+            }
+        })
+    }
+}
+
+async fn current_owner() -> SignerRef {
+    SignerRef::of(SignerContext::current().await.as_ref())
+}
+
+#[tool(description = "
+Sets a price alert that fires once the USD price of a token crosses the
+target, e.g. notify when SOL goes above 300.
+
+chain is the chain of the token, e.g. sol, sonic, eth, arb, base
+token is the token address or mint
+condition is either above or below
+target is the USD price
+")]
+pub async fn set_price_alert(
+    chain: String,
+    token: String,
+    condition: String,
+    target: f64,
+) -> Result<PriceAlert> {
+    let condition = AlertCondition::from_str(&condition)?;
+    if target <= 0. {
+        return Err(anyhow!("Target price has to be positive"));
+    }
+    let price = token_price(&chain, &token).await?;
+
+    let alert = PriceAlert {
+        id: format!("{:016x}", rand::random::<u64>()),
+        owner: current_owner().await,
+        chain,
+        token,
+        symbol: price.symbol,
+        condition,
+        target,
+        created_at: Utc::now(),
+        triggered_at: None,
+        triggered_price: None,
+    };
+    if alert.is_crossed(price.price_usd) {
+        return Err(anyhow!(
+            "{} is already {} {} at {}",
+            alert.symbol,
+            condition,
+            target,
+            price.price_usd
+        ));
+    }
+    ALERTS.add(alert.clone()).await?;
+    Ok(alert)
+}
+
+#[tool(description = "
+Lists the price alerts of the user, triggered alerts include the time and the
+price they fired at
+")]
+pub async fn list_alerts() -> Result<Vec<PriceAlert>> {
+    ALERTS.list(&current_owner().await).await
+}
+
+#[tool(description = "
+Cancels a price alert
+")]
+pub async fn cancel_alert(id: String) -> Result<String> {
+    let alert = ALERTS.cancel(&id, &current_owner().await).await?;
+    Ok(format!(
+        "Cancelled alert for {} {} {}",
+        alert.symbol, alert.condition, alert.target
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    fn alert(condition: AlertCondition, target: f64) -> PriceAlert {
+        PriceAlert {
+            id: format!("{:016x}", rand::random::<u64>()),
+            owner: SignerRef {
+                user_id: Some("alice".to_string()),
+                session_id: None,
+            },
+            chain: "sol".to_string(),
+            token: "So11111111111111111111111111111111111111112".to_string(),
+            symbol: "SOL".to_string(),
+            condition,
+            target,
+            created_at: Utc::now(),
+            triggered_at: None,
+            triggered_price: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        assert_eq!(
+            AlertCondition::from_str("Above").unwrap(),
+            AlertCondition::Above
+        );
+        assert_eq!(
+            AlertCondition::from_str("<").unwrap(),
+            AlertCondition::Below
+        );
+        assert!(AlertCondition::from_str("crosses").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_alert_triggers_once() {
+        let alerts = PriceAlerts::new(Arc::new(InMemoryKVStore::new()));
+        let above = alert(AlertCondition::Above, 300.);
+        let below = alert(AlertCondition::Below, 100.);
+        alerts.add(above.clone()).await.unwrap();
+        alerts.add(below).await.unwrap();
+
+        let prices = HashMap::from([(above.price_key(), 310.)]);
+        let triggered = alerts.trigger(&prices).await.unwrap();
+        assert_eq!(triggered.len(), 1);
+        assert_eq!(triggered[0].id, above.id);
+        assert_eq!(triggered[0].triggered_price, Some(310.));
+
+        assert!(alerts.trigger(&prices).await.unwrap().is_empty());
+        assert_eq!(alerts.list(&above.owner).await.unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,630 @@
+//! Token amounts. `Amount` is an exact amount, the integer in the smallest
+//! unit of the token and its decimals, the builders and balance tools work
+//! with it instead of lamports, f64 SOL and decimal strings. `UserAmount` is
+//! an amount as users say it: "0.5 SOL", "100 USDC", "half my balance",
+//! "25%" or "all". Transfer and swap tools take these and resolve them to
+//! an `Amount` with the decimals and the balance of the token, so the LLM
+//! never scales by the decimals itself
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Result;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use serde_json::json;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::common::solana_rpc;
+use crate::config::config;
+use crate::error::Error;
+use crate::pricing::token_price;
+use crate::signer::SignerContext;
+use crate::solana::balance::token_balance;
+use crate::solana::constants::WSOL;
+use crate::tool_error::ToolError;
+
+const FULL_SHARE: u16 = 10_000;
+
+const FILLERS: &[&str] = &[
+    "my", "of", "the", "a", "an", "balance", "entire", "whole", "full",
+    "wallet", "holdings", "token", "tokens",
+];
+
+/// Wrapped and native symbols that name the same token
+const WRAPPED: &[(&str, &str)] =
+    &[("WSOL", "SOL"), ("WETH", "ETH"), ("WS", "S")];
+
+const HINT: &str =
+    "Pass the amount as the user said it, e.g. 0.5 SOL, 100 USDC, half, 25% or all";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Quantity {
+    /// whole tokens as a decimal string, e.g. "0.5"
+    Tokens(String),
+    /// integer amount in the smallest unit (lamports, wei..)
+    Raw(u128),
+    /// share of the balance in basis points, 10000 is all of it
+    Share(u16),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserAmount {
+    pub quantity: Quantity,
+    /// symbol the amount was given in, checked against the token
+    pub symbol: Option<String>,
+}
+
+/// Exact amount of a token, `raw` in its smallest unit (lamports, wei..)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Amount {
+    raw: u128,
+    decimals: u8,
+    symbol: Option<String>,
+}
+
+impl Amount {
+    pub fn new(raw: u128, decimals: u8) -> Self {
+        Self {
+            raw,
+            decimals,
+            symbol: None,
+        }
+    }
+
+    pub fn with_symbol(mut self, symbol: impl Into<String>) -> Self {
+        self.symbol = Some(symbol.into());
+        self
+    }
+
+    /// Amount of whole tokens as a decimal string, e.g. "1.5", fails on
+    /// digits the token can't hold
+    pub fn parse(tokens: &str, decimals: u8) -> Result<Self> {
+        let tokens = tokens.trim();
+        if !is_decimal(tokens) {
+            return Err(invalid(format!("{:?} is not a number", tokens)));
+        }
+        Ok(Self::new(to_raw_amount(tokens, decimals)?, decimals))
+    }
+
+    pub fn raw(&self) -> u128 {
+        self.raw
+    }
+
+    pub fn decimals(&self) -> u8 {
+        self.decimals
+    }
+
+    pub fn symbol(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.raw == 0
+    }
+
+    /// Raw amount of the instructions taking u64 (SPL, system transfers)
+    pub fn to_u64(&self) -> Result<u64> {
+        u64::try_from(self.raw)
+            .map_err(|_| invalid(format!("Amount {} is too large", self)))
+    }
+
+    /// Whole tokens as an exact decimal string, e.g. "1.5"
+    pub fn to_ui_string(&self) -> String {
+        let decimals = self.decimals as usize;
+        let digits = format!("{:0>width$}", self.raw, width = decimals + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - decimals);
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            whole.to_string()
+        } else {
+            format!("{}.{}", whole, fraction)
+        }
+    }
+
+    /// Whole tokens, lossy, only for prices and display
+    pub fn to_f64(&self) -> f64 {
+        self.raw as f64 / 10f64.powi(self.decimals as i32)
+    }
+
+    fn same_token(&self, other: &Amount) -> bool {
+        self.decimals == other.decimals
+            && match (&self.symbol, &other.symbol) {
+                (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+                _ => true,
+            }
+    }
+
+    fn with_raw(&self, other: &Amount, raw: u128) -> Self {
+        Self {
+            raw,
+            decimals: self.decimals,
+            symbol: self.symbol.clone().or_else(|| other.symbol.clone()),
+        }
+    }
+
+    /// None on overflow or amounts of different tokens
+    pub fn checked_add(&self, other: &Amount) -> Option<Amount> {
+        if !self.same_token(other) {
+            return None;
+        }
+        let raw = self.raw.checked_add(other.raw)?;
+        Some(self.with_raw(other, raw))
+    }
+
+    /// None when `other` is larger or of a different token
+    pub fn checked_sub(&self, other: &Amount) -> Option<Amount> {
+        if !self.same_token(other) {
+            return None;
+        }
+        let raw = self.raw.checked_sub(other.raw)?;
+        Some(self.with_raw(other, raw))
+    }
+
+    /// Part of the amount in basis points (10000 is all of it), rounded
+    /// down
+    pub fn share(&self, bps: u16) -> Amount {
+        Self {
+            raw: share_of(self.raw, bps.min(FULL_SHARE)),
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.symbol {
+            Some(symbol) => write!(f, "{} {}", self.to_ui_string(), symbol),
+            None => f.write_str(&self.to_ui_string()),
+        }
+    }
+}
+
+/// The raw amount is a string, JSON numbers lose precision past 2^53
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut amount = serializer.serialize_struct("Amount", 4)?;
+        amount.serialize_field("amount", &self.to_ui_string())?;
+        amount.serialize_field("raw", &self.raw.to_string())?;
+        amount.serialize_field("decimals", &self.decimals)?;
+        amount.serialize_field("symbol", &self.symbol)?;
+        amount.end()
+    }
+}
+
+fn invalid(message: String) -> anyhow::Error {
+    ToolError::invalid_input(message).with_hint(HINT).into()
+}
+
+fn is_decimal(value: &str) -> bool {
+    let mut parts = value.splitn(2, '.');
+    let whole = parts.next().unwrap_or_default();
+    let fraction = parts.next().unwrap_or_default();
+    !(whole.is_empty() && fraction.is_empty())
+        && whole.chars().all(|c| c.is_ascii_digit())
+        && fraction.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Drops the thousands separators of "1,000,000" or "1_000", "0,5" is not
+/// taken for 5
+fn strip_separators(word: &str) -> Option<String> {
+    let (whole, fraction) = match word.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (word, None),
+    };
+    let whole = whole.replace('_', "");
+    let mut groups = whole.split(',');
+    let first = groups.next().unwrap_or_default();
+    let mut digits = first.to_string();
+    for group in groups {
+        if first.is_empty() || group.len() != 3 {
+            return None;
+        }
+        digits.push_str(group);
+    }
+    let number = match fraction {
+        Some(fraction) => format!("{}.{}", digits, fraction),
+        None => digits,
+    };
+    is_decimal(&number).then_some(number)
+}
+
+impl FromStr for UserAmount {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let mut number: Option<String> = None;
+        let mut share = None;
+        let mut percent = false;
+        let mut raw = false;
+        let mut lamports = false;
+        let mut symbol: Option<String> = None;
+
+        for word in input.split_whitespace() {
+            let mut word = word.to_string();
+            if let Some(value) = word.strip_suffix('%') {
+                percent = true;
+                word = value.to_string();
+                if word.is_empty() {
+                    continue;
+                }
+            }
+            let lower = word.to_lowercase();
+            let number_like = strip_separators(&lower);
+            match lower.as_str() {
+                "all" | "max" | "everything" => share = Some(FULL_SHARE),
+                "half" => share = Some(5_000),
+                "third" => share = Some(3_333),
+                "quarter" => share = Some(2_500),
+                "percent" => percent = true,
+                "raw" | "wei" => raw = true,
+                // lamports are of SOL only, not of any token on Solana
+                "lamports" | "lamport" => {
+                    raw = true;
+                    lamports = true
+                }
+                filler if FILLERS.contains(&filler) => {}
+                _ if number_like.is_some() => {
+                    if number.is_some() {
+                        return Err(invalid(format!(
+                            "Amount {:?} has more than one number",
+                            input
+                        )));
+                    }
+                    number = number_like
+                }
+                _ if symbol.is_none()
+                    && word.len() <= 12
+                    && word.chars().all(|c| c.is_ascii_alphanumeric())
+                    && word.chars().any(|c| c.is_ascii_alphabetic()) =>
+                {
+                    symbol = Some(word.to_uppercase())
+                }
+                _ => {
+                    return Err(invalid(format!(
+                        "Amount {:?} is not understood",
+                        input
+                    )))
+                }
+            }
+        }
+
+        if lamports {
+            match symbol.as_deref() {
+                None => symbol = Some("SOL".to_string()),
+                Some("SOL" | "WSOL") => {}
+                Some(other) => {
+                    return Err(invalid(format!(
+                        "Amount {:?} is in lamports but of {}, lamports \
+                         are of SOL, use raw for the smallest unit of a token",
+                        input, other
+                    )))
+                }
+            }
+        }
+
+        let quantity = match (number, share, percent, raw) {
+            (Some(number), None, true, false) => {
+                let percent: f64 = number.parse()?;
+                if percent <= 0. || percent > 100. {
+                    return Err(invalid(format!(
+                        "{}% is out of bounds (0-100)",
+                        number
+                    )));
+                }
+                Quantity::Share((percent * 100.).round().max(1.) as u16)
+            }
+            (Some(number), None, false, true) => {
+                let raw = number.parse::<u128>().map_err(|_| {
+                    invalid(format!(
+                        "Raw amount {} is not an integer",
+                        number
+                    ))
+                })?;
+                Quantity::Raw(raw)
+            }
+            (Some(number), None, false, false) => Quantity::Tokens(number),
+            (None, Some(share), false, false) => Quantity::Share(share),
+            _ => {
+                return Err(invalid(format!(
+                    "Amount {:?} is not understood",
+                    input
+                )))
+            }
+        };
+        let zero = match &quantity {
+            Quantity::Tokens(tokens) => {
+                tokens.chars().all(|c| c == '0' || c == '.')
+            }
+            Quantity::Raw(raw) => *raw == 0,
+            Quantity::Share(_) => false,
+        };
+        if zero {
+            return Err(invalid(format!("Amount {:?} is zero", input)));
+        }
+
+        Ok(Self { quantity, symbol })
+    }
+}
+
+/// Scales a decimal amount by the decimals, e.g. 1.5 with 6 decimals is
+/// 1500000. Exact, fails rather than rounding off digits the token can't hold
+pub fn to_raw_amount(amount: &str, decimals: u8) -> Result<u128> {
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.len() > decimals as usize {
+        return Err(invalid(format!(
+            "{} has more decimal places than the token ({})",
+            amount, decimals
+        )));
+    }
+    let digits = format!(
+        "{}{}{}",
+        whole,
+        fraction,
+        "0".repeat(decimals as usize - fraction.len())
+    );
+    let digits = digits.trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    digits
+        .parse()
+        .map_err(|_| invalid(format!("Amount {} is too large", amount)))
+}
+
+fn share_of(balance: u128, share: u16) -> u128 {
+    if share == FULL_SHARE {
+        return balance;
+    }
+    balance / FULL_SHARE as u128 * share as u128
+        + balance % FULL_SHARE as u128 * share as u128 / FULL_SHARE as u128
+}
+
+struct Holding {
+    balance: u128,
+    decimals: u8,
+    /// part of the balance kept for the fees
+    reserve: u128,
+}
+
+async fn solana_holding(token: &str) -> Result<Holding> {
+    let owner = Pubkey::from_str(&SignerContext::current().await.pubkey())?;
+    let mint = Pubkey::from_str(token)?;
+    let (balance, decimals) =
+        token_balance(&solana_rpc(), &owner, &mint).await?;
+    Ok(Holding {
+        balance: balance as u128,
+        decimals,
+        // kept out of "all" of SOL to pay the fees
+        reserve: if token == WSOL {
+            config().trading.sol_fee_reserve_lamports as u128
+        } else {
+            0
+        },
+    })
+}
+
+async fn evm_holding(token: &str) -> Result<Holding> {
+    use crate::evm::balance::{balance, token_balance, token_decimals};
+    use crate::evm::tools::NATIVE_TOKEN;
+    use crate::evm::util::make_provider;
+
+    let owner = SignerContext::current().await.address();
+    let provider = make_provider()?;
+    if token == NATIVE_TOKEN {
+        return Ok(Holding {
+            balance: balance(&provider, owner).await?.parse()?,
+            decimals: 18,
+            reserve: config().trading.evm_fee_reserve_wei as u128,
+        });
+    }
+    Ok(Holding {
+        balance: token_balance(owner, token.to_string(), &provider)
+            .await?
+            .parse()?,
+        decimals: token_decimals(token.to_string(), &provider).await?,
+        reserve: 0,
+    })
+}
+
+async fn holding(chain: &str, token: &str) -> Result<Holding> {
+    match chain {
+        "sol" => solana_holding(token).await,
+        _ => evm_holding(token).await,
+    }
+}
+
+/// Balance of `token` on `chain` held by the current signer
+pub async fn balance_of(chain: &str, token: &str) -> Result<Amount> {
+    let holding = holding(chain, token).await?;
+    Ok(Amount::new(holding.balance, holding.decimals))
+}
+
+fn same_symbol(a: &str, b: &str) -> bool {
+    let native = |s: &str| {
+        let s = s.to_uppercase();
+        WRAPPED
+            .iter()
+            .find(|(wrapped, _)| *wrapped == s)
+            .map_or(s, |(_, native)| native.to_string())
+    };
+    native(a) == native(b)
+}
+
+/// The symbol the user gave has to be the one of the token, see `WRAPPED`
+/// for the wrapped symbols that are the same. Tokens LiFi doesn't know are
+/// let through
+async fn check_symbol(chain: &str, token: &str, symbol: &str) -> Result<()> {
+    let Ok(info) = token_price(chain, token).await else {
+        return Ok(());
+    };
+    if !same_symbol(&info.symbol, symbol) {
+        return Err(invalid(format!(
+            "The amount is in {} but the token {} is {}",
+            symbol, token, info.symbol
+        )));
+    }
+    Ok(())
+}
+
+/// Resolves `amount` of `token` on `chain` as the user said it, reading
+/// the decimals and the balance of the current signer. Numbers are whole
+/// tokens, only "raw" (lamports, wei) makes them the smallest unit. Shares
+/// of a native token keep a reserve for the fees
+pub async fn resolve(
+    chain: &str,
+    token: &str,
+    amount: &str,
+) -> Result<Amount> {
+    let amount = UserAmount::from_str(amount)?;
+    if let Some(symbol) = &amount.symbol {
+        check_symbol(chain, token, symbol).await?;
+    }
+
+    let holding = holding(chain, token).await?;
+    let balance = Amount::new(holding.balance, holding.decimals);
+    let resolved = match &amount.quantity {
+        Quantity::Raw(raw) => Amount::new(*raw, holding.decimals),
+        Quantity::Tokens(tokens) => Amount::parse(tokens, holding.decimals)?,
+        Quantity::Share(share) => Amount::new(
+            holding.balance.saturating_sub(holding.reserve),
+            holding.decimals,
+        )
+        .share(*share),
+    };
+    if resolved.is_zero() || resolved.raw() > balance.raw() {
+        let ui_balance = balance.to_ui_string();
+        let error = Error::InsufficientBalance(format!(
+            "The balance of {} is only {}",
+            token, ui_balance
+        ));
+        // a raw amount passed as whole tokens is not guessed at
+        let looks_raw = matches!(
+            &amount.quantity,
+            Quantity::Tokens(tokens) if !tokens.contains('.')
+                && tokens.parse::<u128>().is_ok_and(|raw| raw <= balance.raw())
+        );
+        return Err(error.detailed(|error| {
+            let error = error.with_details(json!({ "balance": ui_balance }));
+            if looks_raw {
+                error.with_hint(
+                    "Amounts are in whole tokens, if the user gave the \
+                     smallest unit pass it with raw, e.g. 1500 raw",
+                )
+            } else {
+                error
+            }
+        }));
+    }
+    Ok(match amount.symbol {
+        Some(symbol) => resolved.with_symbol(symbol),
+        None => resolved,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> UserAmount {
+        input.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("0.5 SOL"),
+            UserAmount {
+                quantity: Quantity::Tokens("0.5".to_string()),
+                symbol: Some("SOL".to_string()),
+            }
+        );
+        assert_eq!(
+            parse("1,000 usdc").quantity,
+            Quantity::Tokens("1000".into())
+        );
+        assert_eq!(parse("half my balance").quantity, Quantity::Share(5_000));
+        assert_eq!(parse("all").quantity, Quantity::Share(FULL_SHARE));
+        assert_eq!(parse("all my BONK").symbol.as_deref(), Some("BONK"));
+        assert_eq!(parse("25%").quantity, Quantity::Share(2_500));
+        assert_eq!(
+            parse("25 percent of my USDC").quantity,
+            Quantity::Share(2_500)
+        );
+        assert_eq!(parse("1500 lamports").quantity, Quantity::Raw(1_500));
+        assert_eq!(parse("1500 lamports").symbol.as_deref(), Some("SOL"));
+        assert_eq!(parse("1500 raw").quantity, Quantity::Raw(1_500));
+
+        assert!(UserAmount::from_str("0 SOL").is_err());
+        assert!(UserAmount::from_str("0,5 SOL").is_err());
+        assert!(UserAmount::from_str("150%").is_err());
+        assert!(UserAmount::from_str("half of 10 SOL").is_err());
+        assert!(UserAmount::from_str("1.5 wei").is_err());
+        assert!(UserAmount::from_str("1500 USDC lamports").is_err());
+        assert!(UserAmount::from_str("5 10").is_err());
+        assert!(UserAmount::from_str("0.5 SOL 5").is_err());
+        assert!(UserAmount::from_str("").is_err());
+    }
+
+    #[test]
+    fn test_same_symbol() {
+        assert!(same_symbol("SOL", "wsol"));
+        assert!(same_symbol("WETH", "ETH"));
+        assert!(same_symbol("wS", "S"));
+        assert!(same_symbol("USDC", "usdc"));
+        assert!(!same_symbol("WIF", "IF"));
+        assert!(!same_symbol("WBTC", "BTC"));
+        assert!(!same_symbol("SOL", "ETH"));
+    }
+
+    #[test]
+    fn test_to_raw_amount() {
+        assert_eq!(to_raw_amount("0.5", 9).unwrap(), 500_000_000);
+        assert_eq!(to_raw_amount("100", 6).unwrap(), 100_000_000);
+        assert_eq!(to_raw_amount("1.50", 1).unwrap(), 15);
+        assert_eq!(
+            to_raw_amount("0.1", 18).unwrap(),
+            100_000_000_000_000_000
+        );
+        assert!(to_raw_amount("0.0000001", 6).is_err());
+        assert_eq!(share_of(1_000_000_001, 5_000), 500_000_000);
+        assert_eq!(share_of(u128::MAX, FULL_SHARE), u128::MAX);
+    }
+
+    #[test]
+    fn test_amount() {
+        let amount = Amount::parse("1.5", 9).unwrap().with_symbol("SOL");
+        assert_eq!(amount.raw(), 1_500_000_000);
+        assert_eq!(amount.to_string(), "1.5 SOL");
+        assert_eq!(Amount::new(42, 6).to_ui_string(), "0.000042");
+        assert_eq!(Amount::new(100_000_000, 6).to_ui_string(), "100");
+        assert_eq!(Amount::new(7, 0).to_ui_string(), "7");
+
+        let fee = Amount::new(5_000, 9);
+        assert_eq!(
+            amount.checked_sub(&fee).unwrap().to_string(),
+            "1.499995 SOL"
+        );
+        assert!(fee.checked_sub(&amount).is_none());
+        assert!(amount.checked_add(&Amount::new(1, 6)).is_none());
+        assert!(amount
+            .checked_add(&Amount::new(1, 9).with_symbol("USDC"))
+            .is_none());
+        assert!(Amount::new(u128::MAX, 9).checked_add(&fee).is_none());
+        assert_eq!(amount.share(5_000).raw(), 750_000_000);
+        assert!(Amount::new(u64::MAX as u128 + 1, 9).to_u64().is_err());
+
+        assert_eq!(
+            serde_json::to_value(&amount).unwrap(),
+            json!({
+                "amount": "1.5",
+                "raw": "1500000000",
+                "decimals": 9,
+                "symbol": "SOL",
+            })
+        );
+    }
+}
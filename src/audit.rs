@@ -0,0 +1,249 @@
+//! Persistent log of every tool invocation (arguments, result, duration and
+//! the wallet it ran for) with a query API, and a replay facility that runs
+//! a logged call again against the paper signer to debug what the agent did
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use rig::tool::ToolSet;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::confirmation::preconfirmed;
+use crate::kv_store::{KVStore, KV_STORE};
+use crate::signer::paper::PaperSigner;
+use crate::signer::{SignerContext, TransactionSigner};
+
+/// Tool calls kept per user, older ones are dropped
+pub const MAX_RECORDS: usize = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCallRecord {
+    pub id: String,
+    pub tool: String,
+    pub args: Value,
+    pub result: Option<String>,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+    pub wallet: Option<String>,
+    pub session_id: Option<String>,
+    pub paper: bool,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditQuery {
+    pub tool: Option<String>,
+    #[serde(default)]
+    pub errors_only: bool,
+    pub since: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, record: &ToolCallRecord) -> bool {
+        self.tool.as_ref().map_or(true, |tool| &record.tool == tool)
+            && (!self.errors_only || record.error.is_some())
+            && self.since.map_or(true, |since| record.at >= since)
+    }
+}
+
+pub struct ToolAudit {
+    store: Arc<dyn KVStore>,
+    /// records are stored as a single value per user
+    lock: tokio::sync::Mutex<()>,
+}
+
+pub static AUDIT: Lazy<ToolAudit> =
+    Lazy::new(|| ToolAudit::new(KV_STORE.clone()));
+
+impl ToolAudit {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self {
+            store,
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn key(user: &str) -> String {
+        format!("audit:tool_calls:{}", user)
+    }
+
+    async fn records(&self, user: &str) -> Result<Vec<ToolCallRecord>> {
+        match self.store.get(&Self::key(user)).await? {
+            Some(records) => Ok(serde_json::from_str(&records)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    pub async fn record(
+        &self,
+        user: &str,
+        record: ToolCallRecord,
+    ) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut records = self.records(user).await?;
+        records.insert(0, record);
+        records.truncate(MAX_RECORDS);
+        self.store
+            .set(&Self::key(user), &serde_json::to_string(&records)?)
+            .await
+    }
+
+    /// Records of `user` matching `query`, newest first
+    pub async fn query(
+        &self,
+        user: &str,
+        query: &AuditQuery,
+    ) -> Result<Vec<ToolCallRecord>> {
+        Ok(self
+            .records(user)
+            .await?
+            .into_iter()
+            .filter(|record| query.matches(record))
+            .take(query.limit.unwrap_or(MAX_RECORDS))
+            .collect())
+    }
+
+    /// Date of the oldest call kept once the log of `user` is full and
+    /// older calls were dropped, None while it holds every call
+    pub async fn complete_since(
+        &self,
+        user: &str,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let records = self.records(user).await?;
+        Ok(match records.last() {
+            Some(oldest) if records.len() >= MAX_RECORDS => Some(oldest.at),
+            _ => None,
+        })
+    }
+
+    pub async fn get(&self, user: &str, id: &str) -> Result<ToolCallRecord> {
+        self.records(user)
+            .await?
+            .into_iter()
+            .find(|record| record.id == id)
+            .ok_or_else(|| anyhow!("Tool call {} not found", id))
+    }
+}
+
+/// Audit log user of a signer, local signers share one log
+pub fn audit_user(signer: &dyn TransactionSigner) -> String {
+    signer.user_id().unwrap_or_else(|| "local".to_string())
+}
+
+/// Logs a tool call of the current signer, failures are only logged so the
+/// audit never fails a tool call
+pub async fn record_tool_call(
+    tool: &str,
+    args: &str,
+    result: &Result<String>,
+    duration: Duration,
+) {
+    let Some(signer) = SignerContext::try_current() else {
+        return;
+    };
+    let (result, error) = match result {
+        Ok(result) => (Some(result.clone()), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let record = ToolCallRecord {
+        id: format!("{:016x}", rand::random::<u64>()),
+        tool: tool.to_string(),
+        args: serde_json::from_str(args).unwrap_or(Value::Null),
+        result,
+        error,
+        duration_ms: duration.as_millis() as u64,
+        wallet: signer.wallet_id(),
+        session_id: signer.session_id(),
+        paper: signer.is_paper(),
+        at: Utc::now(),
+    };
+    if let Err(e) = AUDIT.record(&audit_user(signer.as_ref()), record).await {
+        tracing::warn!(?e, tool, "failed to audit tool call");
+    }
+}
+
+/// Runs a logged call again with the same arguments under a paper signer
+/// wrapping the current one, nothing is signed nor sent. Confirmations are
+/// skipped as nothing can be executed for real
+pub async fn replay(
+    tools: &ToolSet,
+    record: &ToolCallRecord,
+) -> Result<String> {
+    let signer = SignerContext::current().await;
+    signer.tool_access().check(&record.tool)?;
+    let paper: Arc<dyn TransactionSigner> =
+        Arc::new(PaperSigner::new(signer));
+
+    let args = record.args.to_string();
+    SignerContext::with_signer(
+        paper,
+        preconfirmed(async { Ok(tools.call(&record.tool, args).await?) }),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    fn record(tool: &str, error: Option<&str>) -> ToolCallRecord {
+        ToolCallRecord {
+            id: format!("{:016x}", rand::random::<u64>()),
+            tool: tool.to_string(),
+            args: Value::Null,
+            result: None,
+            error: error.map(str::to_string),
+            duration_ms: 10,
+            wallet: None,
+            session_id: None,
+            paper: false,
+            at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let audit = ToolAudit::new(Arc::new(InMemoryKVStore::new()));
+        audit.record("alice", record("trade", None)).await.unwrap();
+        let failed = record("trade", Some("slippage"));
+        audit.record("alice", failed.clone()).await.unwrap();
+        audit
+            .record("alice", record("get_portfolio", None))
+            .await
+            .unwrap();
+        audit.record("bob", record("trade", None)).await.unwrap();
+
+        let query = AuditQuery {
+            tool: Some("trade".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(audit.query("alice", &query).await.unwrap().len(), 2);
+
+        let query = AuditQuery {
+            errors_only: true,
+            ..Default::default()
+        };
+        let errors = audit.query("alice", &query).await.unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].id, failed.id);
+        assert!(audit.get("bob", &failed.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_complete_since() {
+        let audit = ToolAudit::new(Arc::new(InMemoryKVStore::new()));
+        let oldest = record("trade", None);
+        audit.record("alice", oldest.clone()).await.unwrap();
+        assert_eq!(audit.complete_since("alice").await.unwrap(), None);
+        for _ in 1..=MAX_RECORDS {
+            audit.record("alice", record("trade", None)).await.unwrap();
+        }
+        let since = audit.complete_since("alice").await.unwrap().unwrap();
+        assert!(since >= oldest.at);
+    }
+}
@@ -0,0 +1,400 @@
+//! Automation API: external systems (TradingView alerts, cron services)
+//! trigger a whitelisted tool on behalf of a user with
+//! `POST /v1/automation/{id}`. Users create a hook with
+//! `create_automation_hook`, it keeps a reference to their signer, the tools
+//! it may call and how many calls a day it may make. Callers authenticate
+//! with the token returned once when the hook is created, as a bearer token
+//! or in the `token` field of the body for senders that can't set headers.
+//! The tools run through the regular dispatch pipeline without asking for
+//! confirmation, tool access and risk limits still apply
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use rig::agent::Agent;
+use rig::providers::anthropic::completion::CompletionModel;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::confirmation::confirm_or_execute;
+use crate::kv_store::{namespaced, KVStore};
+use crate::scheduler::run_tool_as;
+use crate::signer::{SignerContext, SignerRef, SignerResolver};
+use crate::tool_error::{ErrorCode, ToolError};
+
+const HOOKS_KEY: &str = "hooks";
+/// Daily call counts are kept a little longer than the day they count
+const CALLS_TTL: Duration = Duration::from_secs(2 * 24 * 3600);
+/// The only tools a hook can call: trades, transfers and reads. Tools
+/// that change settings, create hooks, schedules or orders or confirm
+/// actions would let a hook widen what it is allowed to do
+const AUTOMATION_TOOLS: &[&str] = &[
+    "perform_jupiter_swap",
+    "trade",
+    "multichain_swap",
+    "buy_pump_fun_token",
+    "sell_pump_fun_token",
+    "twap_swap",
+    "transfer_sol",
+    "transfer_spl_token",
+    "transfer_eth",
+    "transfer_erc20",
+    "get_evm_token_price",
+    "get_portfolio_history",
+    "get_paper_portfolio",
+    "get_transaction_status",
+    "search_token",
+    "get_trending_tokens",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutomationHook {
+    pub id: String,
+    pub owner: SignerRef,
+    pub name: String,
+    /// the only tools the hook can call
+    pub tools: Vec<String>,
+    pub max_calls_per_day: u32,
+    /// SHA-256 of the token, the token itself is never stored
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub token_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub last_call: Option<DateTime<Utc>>,
+}
+
+impl AutomationHook {
+    /// Hook without the token hash, as shown to users
+    fn redacted(mut self) -> Self {
+        self.token_hash.clear();
+        self
+    }
+}
+
+/// Body of a call to a hook
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AutomationCall {
+    pub token: Option<String>,
+    /// can be left out when the hook has a single tool
+    pub tool: Option<String>,
+    #[serde(default)]
+    pub args: Value,
+}
+
+/// Hook returned on creation, the token is not shown again
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatedHook {
+    pub hook: AutomationHook,
+    pub token: String,
+    pub path: String,
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub struct Automation {
+    store: Arc<dyn KVStore>,
+    /// hooks are stored as a single value and call counts are read and
+    /// written back, writes have to be serialized
+    lock: tokio::sync::Mutex<()>,
+}
+
+pub static AUTOMATION: Lazy<Automation> =
+    Lazy::new(|| Automation::new(Arc::new(namespaced("automation"))));
+
+impl Automation {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self {
+            store,
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    async fn load(&self) -> Result<Vec<AutomationHook>> {
+        match self.store.get(HOOKS_KEY).await? {
+            Some(hooks) => Ok(serde_json::from_str(&hooks)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    async fn save(&self, hooks: &[AutomationHook]) -> Result<()> {
+        self.store
+            .set(HOOKS_KEY, &serde_json::to_string(hooks)?)
+            .await
+    }
+
+    pub async fn add(&self, hook: AutomationHook) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut hooks = self.load().await?;
+        hooks.push(hook);
+        self.save(&hooks).await
+    }
+
+    pub async fn list(
+        &self,
+        owner: &SignerRef,
+    ) -> Result<Vec<AutomationHook>> {
+        Ok(self
+            .load()
+            .await?
+            .into_iter()
+            .filter(|hook| hook.owner.same_user(owner))
+            .map(AutomationHook::redacted)
+            .collect())
+    }
+
+    pub async fn revoke(
+        &self,
+        id: &str,
+        owner: &SignerRef,
+    ) -> Result<AutomationHook> {
+        let _guard = self.lock.lock().await;
+        let mut hooks = self.load().await?;
+        let index = hooks
+            .iter()
+            .position(|hook| hook.id == id && hook.owner.same_user(owner))
+            .ok_or_else(|| not_found(id))?;
+        let hook = hooks.remove(index);
+        self.save(&hooks).await?;
+        Ok(hook.redacted())
+    }
+
+    /// Checks a call to the hook `id` and counts it against the daily
+    /// limit, returns the hook and the tool to run
+    pub async fn authorize(
+        &self,
+        id: &str,
+        token: &str,
+        tool: Option<&str>,
+    ) -> Result<(AutomationHook, String)> {
+        let _guard = self.lock.lock().await;
+        let mut hooks = self.load().await?;
+        let hook = hooks
+            .iter_mut()
+            .find(|hook| hook.id == id)
+            .ok_or_else(|| not_found(id))?;
+        if hash_token(token) != hook.token_hash {
+            return Err(ToolError::new(
+                ErrorCode::Unauthorized,
+                format!("Invalid token for automation hook {}", id),
+            )
+            .into());
+        }
+        let tool = match (tool, hook.tools.as_slice()) {
+            (Some(tool), _) => tool.to_string(),
+            (None, [only]) => only.clone(),
+            (None, _) => {
+                return Err(ToolError::invalid_input("tool is required")
+                    .with_hint(format!(
+                        "The hook can call {}",
+                        hook.tools.join(", ")
+                    ))
+                    .into())
+            }
+        };
+        // hooks created before a tool left the allowlist can't call it
+        if !hook.tools.contains(&tool)
+            || !AUTOMATION_TOOLS.contains(&tool.as_str())
+        {
+            return Err(ToolError::new(
+                ErrorCode::AccessDenied,
+                format!("Automation hook {} can't call {}", id, tool),
+            )
+            .into());
+        }
+
+        let now = Utc::now();
+        let key = format!("calls:{}:{}", id, now.format("%Y-%m-%d"));
+        // counted in one step, concurrent calls can't pass the limit
+        let calls = self.store.increment(&key, 1., Some(CALLS_TTL)).await?;
+        if calls > hook.max_calls_per_day as f64 {
+            return Err(ToolError::new(
+                ErrorCode::RateLimited,
+                format!(
+                    "Automation hook {} made its {} calls of the day",
+                    id, hook.max_calls_per_day
+                ),
+            )
+            .into());
+        }
+        hook.last_call = Some(now);
+        let hook = hook.clone();
+        self.save(&hooks).await?;
+        Ok((hook, tool))
+    }
+}
+
+fn not_found(id: &str) -> anyhow::Error {
+    ToolError::not_found(format!("Automation hook {} not found", id)).into()
+}
+
+/// Runs a call to the hook `id` under the signer of its owner, `bearer` is
+/// the token of the Authorization header
+pub async fn trigger(
+    id: &str,
+    call: AutomationCall,
+    bearer: Option<String>,
+    agents: &[Arc<Agent<CompletionModel>>],
+    resolver: &dyn SignerResolver,
+) -> Result<String> {
+    let token = bearer.or(call.token).ok_or_else(|| {
+        ToolError::new(ErrorCode::Unauthorized, "Missing automation token")
+    })?;
+    let (hook, tool) = AUTOMATION
+        .authorize(id, &token, call.tool.as_deref())
+        .await?;
+    tracing::info!(hook = %hook.id, tool, "automation call");
+    run_tool_as(&hook.owner, &tool, &call.args, agents, resolver).await
+}
+
+#[tool(description = "
+Creates an automation hook: external systems like TradingView alerts or cron
+services can then call the given tools on behalf of the user, without asking
+for confirmation again. Requires the user's confirmation. The result holds
+the token callers authenticate with, show it to the user once, it can't be
+retrieved later.
+
+name describes the hook, e.g. tradingview btc signals
+tools is a comma separated list of the tools the hook can call, e.g.
+perform_jupiter_swap. Only swaps, transfers and price, portfolio or
+transaction lookups can be automated, savings moves always need the user's
+confirmation
+max_calls_per_day caps how often the hook can run a tool
+")]
+pub async fn create_automation_hook(
+    name: String,
+    tools: String,
+    max_calls_per_day: u32,
+) -> Result<String> {
+    let tools = tools
+        .split(',')
+        .map(|tool| tool.trim().to_string())
+        .filter(|tool| !tool.is_empty())
+        .collect::<Vec<_>>();
+    if tools.is_empty() || max_calls_per_day == 0 {
+        return Err(ToolError::invalid_input(
+            "A hook needs at least one tool and one call a day",
+        )
+        .into());
+    }
+    let signer = SignerContext::current().await;
+    for tool in &tools {
+        if !AUTOMATION_TOOLS.contains(&tool.as_str()) {
+            return Err(ToolError::invalid_input(format!(
+                "{} can't be called by automation hooks",
+                tool
+            ))
+            .into());
+        }
+        signer.tool_access().check(tool)?;
+    }
+
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    let hook = AutomationHook {
+        id: format!("{:016x}", rand::random::<u64>()),
+        owner: SignerRef::of(signer.as_ref()),
+        name,
+        tools,
+        max_calls_per_day,
+        token_hash: hash_token(&token),
+        created_at: Utc::now(),
+        last_call: None,
+    };
+    let summary = format!(
+        "Let external systems call {} up to {} times a day ({})",
+        hook.tools.join(", "),
+        hook.max_calls_per_day,
+        hook.name
+    );
+    confirm_or_execute(
+        "create_automation_hook",
+        summary,
+        move || async move {
+            AUTOMATION.add(hook.clone()).await?;
+            Ok(serde_json::to_string(&CreatedHook {
+                path: format!("/v1/automation/{}", hook.id),
+                hook: hook.redacted(),
+                token,
+            })?)
+        },
+    )
+    .await
+}
+
+#[tool(description = "
+Lists the automation hooks of the user with the tools they can call
+")]
+pub async fn list_automation_hooks() -> Result<Vec<AutomationHook>> {
+    let owner = SignerRef::of(SignerContext::current().await.as_ref());
+    AUTOMATION.list(&owner).await
+}
+
+#[tool(description = "
+Revokes an automation hook, calls to it fail from then on
+")]
+pub async fn revoke_automation_hook(id: String) -> Result<String> {
+    let owner = SignerRef::of(SignerContext::current().await.as_ref());
+    let hook = AUTOMATION.revoke(&id, &owner).await?;
+    Ok(format!("Revoked {} ({})", hook.id, hook.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    #[tokio::test]
+    async fn test_authorize() {
+        let automation = Automation::new(Arc::new(InMemoryKVStore::new()));
+        let owner = SignerRef {
+            user_id: Some("alice".to_string()),
+            session_id: None,
+        };
+        automation
+            .add(AutomationHook {
+                id: "hook".to_string(),
+                owner: owner.clone(),
+                name: "signals".to_string(),
+                tools: vec!["perform_jupiter_swap".to_string()],
+                max_calls_per_day: 2,
+                token_hash: hash_token("secret"),
+                created_at: Utc::now(),
+                last_call: None,
+            })
+            .await
+            .unwrap();
+
+        let code = |result: Result<(AutomationHook, String)>| {
+            ToolError::from_anyhow(&result.unwrap_err()).code
+        };
+        assert_eq!(
+            code(automation.authorize("hook", "guess", None).await),
+            ErrorCode::Unauthorized
+        );
+        assert_eq!(
+            code(automation.authorize("hook", "secret", Some("trade")).await),
+            ErrorCode::AccessDenied
+        );
+        let (hook, tool) =
+            automation.authorize("hook", "secret", None).await.unwrap();
+        assert_eq!(tool, "perform_jupiter_swap");
+        assert!(hook.last_call.is_some());
+        automation.authorize("hook", "secret", None).await.unwrap();
+        assert_eq!(
+            code(automation.authorize("hook", "secret", None).await),
+            ErrorCode::RateLimited
+        );
+
+        let hooks = automation.list(&owner).await.unwrap();
+        assert!(hooks[0].token_hash.is_empty());
+        automation.revoke("hook", &owner).await.unwrap();
+        assert_eq!(
+            code(automation.authorize("hook", "secret", None).await),
+            ErrorCode::NotFound
+        );
+    }
+}
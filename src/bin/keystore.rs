@@ -0,0 +1,36 @@
+//! Encrypts the plaintext keypair of the local Solana signer into a
+//! keystore, see `signer::keystore`. The passphrase is read from
+//! SOLANA_KEYSTORE_PASSPHRASE, the keystore is opened again before the
+//! public key is printed. The plaintext source is left in place, delete it
+//! once the signer runs with SOLANA_KEYSTORE
+//!
+//! SOLANA_KEYSTORE_PASSPHRASE=... cargo run --bin keystore -- \
+//!     migrate ~/.config/solana/id.json keystore.json
+use anyhow::{anyhow, Result};
+use listen_kit::signer::keystore::{migrate, Keystore};
+
+const USAGE: &str = "Usage: keystore migrate <plaintext keypair> <keystore>";
+
+fn main() -> Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let [command, source, destination] = args.as_slice() else {
+        return Err(anyhow!(USAGE));
+    };
+    if command != "migrate" {
+        return Err(anyhow!("Unknown command {}\n{}", command, USAGE));
+    }
+    if std::path::Path::new(destination).exists() {
+        return Err(anyhow!("{} exists already", destination));
+    }
+    let passphrase = std::env::var("SOLANA_KEYSTORE_PASSPHRASE")
+        .map_err(|_| anyhow!("SOLANA_KEYSTORE_PASSPHRASE env var not set"))?;
+
+    let pubkey = migrate(source, destination, &passphrase)?;
+    Keystore::load(destination)?.open(&passphrase)?;
+    println!("Encrypted {} into {}", pubkey, destination);
+    println!(
+        "Set SOLANA_KEYSTORE={} and delete {} once the signer runs with it",
+        destination, source
+    );
+    Ok(())
+}
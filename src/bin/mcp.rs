@@ -0,0 +1,35 @@
+//! MCP server over stdio for local clients (Claude Desktop etc.), tool calls
+//! run under the local Solana signer, see `LocalSolanaSigner::from_env`.
+//! The SSE transport for remote clients is served by the server binary
+//!
+//! cargo run --bin mcp -- --stdio
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use listen_kit::mcp::McpServer;
+use listen_kit::signer::local_solana::LocalSolanaSigner;
+use listen_kit::signer::SignerContext;
+use tokio::io::BufReader;
+
+const USAGE: &str = "Usage: mcp --stdio";
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args != ["--stdio"] {
+        return Err(anyhow!(USAGE));
+    }
+    // the library logs to stderr, stdout carries the protocol
+    listen_kit::config::init()?;
+
+    let signer = Arc::new(LocalSolanaSigner::from_env()?);
+    let server = McpServer::with_all_tools();
+    SignerContext::with_signer(
+        signer,
+        server.serve_lines(
+            BufReader::new(tokio::io::stdin()),
+            tokio::io::stdout(),
+        ),
+    )
+    .await
+}
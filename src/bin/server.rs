@@ -4,44 +4,53 @@ use listen_kit::http::server::run_server;
 #[cfg(feature = "http")]
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    use listen_kit::chains::{Chain, CHAINS};
     use listen_kit::wallet_manager::config::PrivyConfig;
+    use listen_kit::wallet_manager::store::wallet_store;
     use listen_kit::wallet_manager::WalletManager;
 
+    // Fail on an invalid config before anything uses it
+    let config = listen_kit::config::init()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    listen_kit::config::spawn_reloader();
+
     // Initialize wallet manager
-    let wallet_manager =
-        WalletManager::new(PrivyConfig::from_env().map_err(|e| {
-            std::io::Error::new(std::io::ErrorKind::Other, e)
-        })?);
+    let store = wallet_store(&config.database)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let wallet_manager = WalletManager::new(
+        PrivyConfig::from_env()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?,
+        store,
+    );
 
     let omni_agent =
         listen_kit::cross_chain::agent::create_cross_chain_agent()
             .await
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-    // Create agents based on enabled features
-    #[cfg(feature = "solana")]
-    let solana_agent = listen_kit::solana::agent::create_solana_agent()
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-    #[cfg(feature = "evm")]
-    let evm_agent = listen_kit::evm::agent::create_evm_agent()
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-    // Run server with appropriate agents based on features
-    #[cfg(all(feature = "solana", feature = "evm"))]
-    return run_server(solana_agent, evm_agent, wallet_manager, omni_agent)
-        .await;
-
-    #[cfg(all(feature = "solana", not(feature = "evm")))]
-    return run_server(solana_agent, wallet_manager, omni_agent).await;
-
-    #[cfg(all(feature = "evm", not(feature = "solana")))]
-    return run_server(evm_agent, wallet_manager, omni_agent).await;
-
-    #[cfg(not(any(feature = "solana", feature = "evm")))]
-    return run_server(wallet_manager, omni_agent).await;
+    // Create agents of the chains enabled in the config
+    let solana_agent = if CHAINS.is_enabled(Chain::Solana) {
+        Some(
+            listen_kit::solana::agent::create_solana_agent()
+                .await
+                .map_err(|e| {
+                    std::io::Error::new(std::io::ErrorKind::Other, e)
+                })?,
+        )
+    } else {
+        None
+    };
+
+    let evm_agent =
+        if CHAINS.is_enabled(Chain::Evm) {
+            Some(listen_kit::evm::agent::create_evm_agent().await.map_err(
+                |e| std::io::Error::new(std::io::ErrorKind::Other, e),
+            )?)
+        } else {
+            None
+        };
+
+    run_server(solana_agent, evm_agent, wallet_manager, omni_agent).await
 }
 
 #[cfg(not(feature = "http"))]
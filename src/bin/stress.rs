@@ -0,0 +1,165 @@
+//! Load test of the quote -> sign -> send path. Runs `--requests` swaps
+//! through the tool dispatcher, `--concurrency` at a time, each with its own
+//! mock signer waiting `--sign-latency-ms` instead of signing, and prints
+//! the throughput and the latency percentiles of the quotes and of the
+//! whole calls. Quotes come from Jupiter and the Solana RPC of the config,
+//! or from `--jupiter-url` (e.g. a mock server)
+//!
+//! cargo run --release --features test-utils --bin stress -- \
+//!     --requests 500 --concurrency 50
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use futures::stream::{self, StreamExt};
+use listen_kit::dispatch::dispatch;
+use listen_kit::signer::mock::MockSigner;
+use listen_kit::signer::SignerContext;
+use listen_kit::solana::blockhash::BLOCKHASH_CACHE;
+use listen_kit::solana::jup::Jupiter;
+use listen_kit::solana::util::execute_solana_transaction;
+use listen_kit::tool_error::ToolError;
+
+const WSOL: &str = "So11111111111111111111111111111111111111112";
+const USDC: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+struct Args {
+    requests: usize,
+    concurrency: usize,
+    sign_latency: Duration,
+    jupiter_url: Option<String>,
+    amount: u64,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut args = Self {
+            requests: 100,
+            concurrency: 10,
+            sign_latency: Duration::from_millis(50),
+            jupiter_url: None,
+            amount: 10_000_000,
+        };
+        let mut argv = std::env::args().skip(1);
+        while let Some(flag) = argv.next() {
+            let value = argv
+                .next()
+                .ok_or_else(|| anyhow!("{} is missing its value", flag))?;
+            match flag.as_str() {
+                "--requests" => args.requests = value.parse()?,
+                "--concurrency" => args.concurrency = value.parse()?,
+                "--sign-latency-ms" => {
+                    args.sign_latency = Duration::from_millis(value.parse()?)
+                }
+                "--jupiter-url" => args.jupiter_url = Some(value),
+                "--lamports" => args.amount = value.parse()?,
+                _ => return Err(anyhow!("Unknown flag {}", flag)),
+            }
+        }
+        Ok(args)
+    }
+}
+
+#[derive(Default)]
+struct Samples {
+    quotes: Vec<Duration>,
+    calls: Vec<Duration>,
+    errors: Vec<String>,
+}
+
+fn percentile(samples: &mut [Duration], p: f64) -> Duration {
+    if samples.is_empty() {
+        return Duration::ZERO;
+    }
+    samples.sort();
+    let index = ((samples.len() - 1) as f64 * p).round() as usize;
+    samples[index]
+}
+
+fn print_latencies(name: &str, samples: &mut [Duration]) {
+    println!(
+        "{:<8} p50 {:>8.1?}  p90 {:>8.1?}  p99 {:>8.1?}  max {:>8.1?}",
+        name,
+        percentile(samples, 0.5),
+        percentile(samples, 0.9),
+        percentile(samples, 0.99),
+        percentile(samples, 1.),
+    );
+}
+
+async fn swap(
+    call: usize,
+    args: &Args,
+    jupiter: Arc<Jupiter>,
+    samples: Arc<Mutex<Samples>>,
+) {
+    let signer = MockSigner::new(args.sign_latency)
+        .with_user(&format!("stress-{}", call));
+    let amount = args.amount;
+    let quotes = samples.clone();
+    let started = Instant::now();
+    let result = SignerContext::with_signer(Arc::new(signer), async move {
+        dispatch(
+            "perform_jupiter_swap",
+            serde_json::json!({ "call": call }).to_string(),
+            |_| async move {
+                execute_solana_transaction(move |owner| async move {
+                    let started = Instant::now();
+                    let quote =
+                        jupiter.fetch_quote(WSOL, USDC, amount, 50).await?;
+                    quotes.lock().unwrap().quotes.push(started.elapsed());
+                    jupiter.swap(quote, &owner, None).await
+                })
+                .await
+                .map_err(|e| ToolError::from_anyhow(&e))
+            },
+        )
+        .await
+    })
+    .await;
+    let mut samples = samples.lock().unwrap();
+    match result {
+        Ok(_) => samples.calls.push(started.elapsed()),
+        Err(e) => samples.errors.push(e.to_string()),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse()?;
+    // fail on an invalid config before the first call uses it
+    listen_kit::config::init()?;
+    let jupiter = Arc::new(match &args.jupiter_url {
+        Some(url) => Jupiter::new(url, BLOCKHASH_CACHE.clone()),
+        None => Jupiter::default(),
+    });
+    let samples = Arc::new(Mutex::new(Samples::default()));
+
+    let started = Instant::now();
+    stream::iter(0..args.requests)
+        .for_each_concurrent(args.concurrency, |call| {
+            swap(call, &args, jupiter.clone(), samples.clone())
+        })
+        .await;
+    let elapsed = started.elapsed();
+
+    let mut samples = samples.lock().unwrap();
+    println!(
+        "{} calls, {} concurrent, signing {:?}: {:.1} calls/s over {:.1?}",
+        args.requests,
+        args.concurrency,
+        args.sign_latency,
+        samples.calls.len() as f64 / elapsed.as_secs_f64(),
+        elapsed,
+    );
+    print_latencies("quote", &mut samples.quotes);
+    print_latencies("call", &mut samples.calls);
+    if !samples.errors.is_empty() {
+        println!(
+            "{} failed, first: {}",
+            samples.errors.len(),
+            samples.errors[0]
+        );
+    }
+    Ok(())
+}
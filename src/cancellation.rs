@@ -0,0 +1,406 @@
+//! Timeouts and cancellation of tool calls. Every dispatched call runs with
+//! a timeout configured per tool and a `CancellationToken` carried next to
+//! the signer by `SignerContext`. Cancelling (the `cancel_tool_calls` tool or
+//! POST /v1/cancel) fires the tokens of the user's in-flight calls and drops
+//! their pending actions. A call stops right away unless it is signing, a
+//! transaction already handed to the signer is seen through so its result
+//! isn't lost. Nonces are only fetched while a transaction is built, a
+//! cancelled call leaves none reserved
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::Serialize;
+use tokio::sync::Notify;
+
+use crate::confirmation::CONFIRMATIONS;
+use crate::freeze::check_signer;
+use crate::signer::{SignerContext, TransactionSigner};
+use crate::tool_error::{ErrorCode, ToolError};
+
+const DEFAULT_TIMEOUT_SECS: u64 = 120;
+
+/// Tools waiting for confirmations on chain or running other tools
+const LONG_RUNNING: &[(&str, u64)] = &[
+    ("bridge_via_gateway", 900),
+    ("claim_gateway_transfer", 600),
+    ("multichain_swap", 600),
+    ("execute_trade_plan", 900),
+    ("confirm_action", 900),
+    ("backtest_strategy", 300),
+];
+
+#[derive(Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    /// transactions being signed, the call can't be dropped meanwhile
+    signing: AtomicUsize,
+    /// a transaction was handed to the signer, it may have been sent
+    signed: AtomicBool,
+    notify: Notify,
+}
+
+/// Cancels a tool call at the next point it can stop at
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<TokenState>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// True while the call holds a `SigningGuard`
+    pub fn is_signing(&self) -> bool {
+        self.0.signing.load(Ordering::SeqCst) > 0
+    }
+
+    /// True once the call held a `SigningGuard`, a transaction of it may
+    /// be on chain
+    pub fn has_signed(&self) -> bool {
+        self.0.signed.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once cancelled and no transaction is being signed
+    pub async fn interrupted(&self) {
+        loop {
+            let notified = self.0.notify.notified();
+            if self.is_cancelled()
+                && self.0.signing.load(Ordering::SeqCst) == 0
+            {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    fn same(&self, other: &CancellationToken) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Held while a transaction is signed and sent
+pub struct SigningGuard(Option<CancellationToken>);
+
+impl Drop for SigningGuard {
+    fn drop(&mut self) {
+        if let Some(token) = &self.0 {
+            token.0.signing.fetch_sub(1, Ordering::SeqCst);
+            token.0.notify.notify_waiters();
+        }
+    }
+}
+
+fn cancelled() -> anyhow::Error {
+    ToolError::new(ErrorCode::Cancelled, "The call was cancelled").into()
+}
+
+/// Last point a tool call can be cancelled at, fails when it was or the
+/// wallet of the signer is frozen and otherwise keeps the call alive until
+/// the guard is dropped
+pub async fn begin_signing() -> Result<SigningGuard> {
+    if let Some(signer) = SignerContext::try_current() {
+        check_signer(signer.as_ref()).await?;
+    }
+    let Some(token) = SignerContext::cancellation() else {
+        return Ok(SigningGuard(None));
+    };
+    token.0.signing.fetch_add(1, Ordering::SeqCst);
+    let guard = SigningGuard(Some(token.clone()));
+    if token.is_cancelled() {
+        return Err(cancelled());
+    }
+    token.0.signed.store(true, Ordering::SeqCst);
+    Ok(guard)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolTimeouts {
+    default: Duration,
+    per_tool: HashMap<String, Duration>,
+}
+
+impl ToolTimeouts {
+    /// TOOL_TIMEOUT_SECS overrides the default timeout, TOOL_TIMEOUTS the
+    /// ones of single tools as e.g. "multichain_swap=300,trade=60"
+    pub fn from_env() -> Self {
+        Self::parse(
+            std::env::var("TOOL_TIMEOUT_SECS").ok().as_deref(),
+            std::env::var("TOOL_TIMEOUTS").ok().as_deref(),
+        )
+    }
+
+    fn parse(default: Option<&str>, per_tool: Option<&str>) -> Self {
+        let default = default
+            .and_then(|secs| secs.trim().parse().ok())
+            .unwrap_or(DEFAULT_TIMEOUT_SECS);
+        let mut timeouts: HashMap<String, Duration> = LONG_RUNNING
+            .iter()
+            .map(|&(tool, secs)| {
+                (tool.to_string(), Duration::from_secs(secs))
+            })
+            .collect();
+        for entry in per_tool.unwrap_or_default().split(',') {
+            let Some((tool, secs)) = entry.split_once('=') else {
+                continue;
+            };
+            match secs.trim().parse() {
+                Ok(secs) => {
+                    timeouts.insert(
+                        tool.trim().to_string(),
+                        Duration::from_secs(secs),
+                    );
+                }
+                Err(_) => tracing::warn!(entry, "invalid tool timeout"),
+            }
+        }
+        Self {
+            default: Duration::from_secs(default),
+            per_tool: timeouts,
+        }
+    }
+
+    pub fn of(&self, tool: &str) -> Duration {
+        self.per_tool.get(tool).copied().unwrap_or(self.default)
+    }
+}
+
+pub static TOOL_TIMEOUTS: Lazy<ToolTimeouts> =
+    Lazy::new(ToolTimeouts::from_env);
+
+/// Runs `call` until it finishes, `token` interrupts it or its timeout
+/// passes. A call timing out while signing is cancelled and awaited, one
+/// that signed is reported as possibly landed
+pub async fn with_timeout<T>(
+    tool: &str,
+    token: &CancellationToken,
+    call: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let timeout = TOOL_TIMEOUTS.of(tool);
+    tokio::pin!(call);
+    tokio::select! {
+        result = &mut call => return result,
+        _ = token.interrupted() => return Err(cancelled()),
+        _ = tokio::time::sleep(timeout) => token.cancel(),
+    }
+    tokio::select! {
+        result = &mut call => result,
+        _ = token.interrupted() => Err(timed_out(tool, timeout, token)),
+    }
+}
+
+/// Timeout of a call, which must not be retried blindly once it signed: its
+/// transaction may still land and a retry would send it twice
+fn timed_out(
+    tool: &str,
+    timeout: Duration,
+    token: &CancellationToken,
+) -> anyhow::Error {
+    let message = format!("{} timed out after {}s", tool, timeout.as_secs());
+    if !token.has_signed() {
+        return ToolError::new(ErrorCode::Timeout, message).into();
+    }
+    let mut error = ToolError::new(
+        ErrorCode::Timeout,
+        format!(
+            "{} after sending a transaction, it may have landed",
+            message
+        ),
+    )
+    .with_hint(
+        "Do not retry, check the transaction with get_transaction_status \
+         first and tell the user",
+    );
+    error.retryable = false;
+    error.into()
+}
+
+/// Calls are cancelled per user, like confirmations
+pub(crate) fn owner_key(signer: &dyn TransactionSigner) -> String {
+    signer
+        .user_id()
+        .or_else(|| signer.session_id())
+        .unwrap_or_else(|| "local".to_string())
+}
+
+#[derive(Default)]
+pub struct Cancellations {
+    active: Mutex<HashMap<String, Vec<CancellationToken>>>,
+}
+
+pub static CANCELLATIONS: Lazy<Cancellations> =
+    Lazy::new(Cancellations::default);
+
+/// Registration of an in-flight call, removed on drop
+pub struct ActiveCall<'a> {
+    cancellations: &'a Cancellations,
+    owner: String,
+    token: CancellationToken,
+}
+
+impl Drop for ActiveCall<'_> {
+    fn drop(&mut self) {
+        let mut active = self.cancellations.active.lock().unwrap();
+        if let Some(tokens) = active.get_mut(&self.owner) {
+            tokens.retain(|token| !token.same(&self.token));
+            if tokens.is_empty() {
+                active.remove(&self.owner);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CancelReport {
+    pub cancelled_calls: usize,
+    /// summaries of the pending actions that were dropped
+    pub rejected_actions: Vec<String>,
+}
+
+impl Cancellations {
+    pub fn register(
+        &self,
+        signer: &dyn TransactionSigner,
+        token: &CancellationToken,
+    ) -> ActiveCall<'_> {
+        let owner = owner_key(signer);
+        self.active
+            .lock()
+            .unwrap()
+            .entry(owner.clone())
+            .or_default()
+            .push(token.clone());
+        ActiveCall {
+            cancellations: self,
+            owner,
+            token: token.clone(),
+        }
+    }
+
+    /// Cancels the in-flight calls of the user behind `signer` but
+    /// `except`, the call asking for it
+    pub fn cancel(
+        &self,
+        signer: &dyn TransactionSigner,
+        except: Option<&CancellationToken>,
+    ) -> usize {
+        self.cancel_owner(&owner_key(signer), except)
+    }
+
+    /// Cancels the in-flight calls of `owner`, a user id, but `except`
+    pub fn cancel_owner(
+        &self,
+        owner: &str,
+        except: Option<&CancellationToken>,
+    ) -> usize {
+        let active = self.active.lock().unwrap();
+        let tokens = active.get(owner).into_iter().flatten();
+        let mut cancelled = 0;
+        for token in tokens {
+            if except.is_some_and(|except| except.same(token)) {
+                continue;
+            }
+            token.cancel();
+            cancelled += 1;
+        }
+        cancelled
+    }
+}
+
+/// Cancels the in-flight tool calls and pending actions of the user behind
+/// `signer`
+pub fn cancel_user_calls(signer: &dyn TransactionSigner) -> CancelReport {
+    let current = SignerContext::cancellation();
+    CancelReport {
+        cancelled_calls: CANCELLATIONS.cancel(signer, current.as_ref()),
+        rejected_actions: CONFIRMATIONS
+            .reject_all(&signer.user_id())
+            .into_iter()
+            .map(|action| action.summary)
+            .collect(),
+    }
+}
+
+#[tool(description = "
+Cancels everything in flight for the user: running tool calls (quotes,
+swaps, bridges) stop before they sign and pending actions are rejected. Use
+it when the user says cancel, stop or abort. Transactions already sent can't
+be undone
+")]
+pub async fn cancel_tool_calls() -> Result<CancelReport> {
+    let signer = SignerContext::current().await;
+    Ok(cancel_user_calls(signer.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_waits_for_signing() {
+        let token = CancellationToken::new();
+        let call = SignerContext::with_cancellation(token.clone(), async {
+            let _guard = begin_signing().await?;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok("signature")
+        });
+        let cancel = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            token.cancel();
+        };
+        let (result, _) =
+            tokio::join!(with_timeout("trade", &token, call), cancel);
+        assert_eq!(result.unwrap(), "signature");
+
+        let result = SignerContext::with_cancellation(token.clone(), async {
+            begin_signing().await.map(|_| ())
+        })
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_timed_out() {
+        let token = CancellationToken::new();
+        let timeout = Duration::from_secs(5);
+        let error =
+            ToolError::from_anyhow(&timed_out("trade", timeout, &token));
+        assert!(error.retryable);
+
+        SignerContext::with_cancellation(token.clone(), async {
+            begin_signing().await.map(|_| ())
+        })
+        .await
+        .unwrap();
+        let error =
+            ToolError::from_anyhow(&timed_out("trade", timeout, &token));
+        assert_eq!(error.code, ErrorCode::Timeout);
+        assert!(!error.retryable);
+        assert!(error.hint.unwrap().contains("get_transaction_status"));
+    }
+
+    #[test]
+    fn test_tool_timeouts() {
+        let timeouts =
+            ToolTimeouts::parse(Some("30"), Some("trade=5, bad, x=y"));
+        assert_eq!(timeouts.of("trade"), Duration::from_secs(5));
+        assert_eq!(timeouts.of("get_sol_balance"), Duration::from_secs(30));
+        assert_eq!(
+            timeouts.of("bridge_via_gateway"),
+            Duration::from_secs(900)
+        );
+    }
+}
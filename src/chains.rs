@@ -0,0 +1,127 @@
+//! Chains served by a deployment. Solana and EVM support are both compiled
+//! in, chains.enabled of the config (ENABLED_CHAINS) picks the ones an
+//! instance runs: only their agents and tools are registered and only their
+//! transactions are signed. Changing it needs a restart
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{config, Config};
+use crate::tool_error::ToolError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Chain {
+    Solana,
+    Evm,
+}
+
+impl Chain {
+    pub const ALL: [Chain; 2] = [Chain::Solana, Chain::Evm];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Solana => "solana",
+            Self::Evm => "evm",
+        }
+    }
+
+    /// Family of a chain key of the tools (sol, sonic, eth, arb..)
+    pub fn of_key(key: &str) -> Self {
+        if matches!(key, "sol" | "solana") {
+            Self::Solana
+        } else {
+            Self::Evm
+        }
+    }
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Chain {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "solana" | "sol" => Ok(Self::Solana),
+            "evm" => Ok(Self::Evm),
+            other => Err(anyhow::anyhow!("Unknown chain {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChainCapability {
+    pub chain: Chain,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainRegistry {
+    capabilities: Vec<ChainCapability>,
+}
+
+/// Chains of the config loaded at startup
+pub static CHAINS: Lazy<ChainRegistry> =
+    Lazy::new(|| ChainRegistry::from_config(&config()));
+
+impl ChainRegistry {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            capabilities: Chain::ALL
+                .iter()
+                .map(|&chain| ChainCapability {
+                    chain,
+                    enabled: config.chains.enabled.contains(&chain),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn capabilities(&self) -> &[ChainCapability] {
+        &self.capabilities
+    }
+
+    pub fn is_enabled(&self, chain: Chain) -> bool {
+        self.capabilities
+            .iter()
+            .any(|capability| capability.chain == chain && capability.enabled)
+    }
+
+    /// Fails tool calls that need a chain this deployment doesn't serve
+    pub fn require(&self, chain: Chain) -> Result<()> {
+        if self.is_enabled(chain) {
+            return Ok(());
+        }
+        Err(ToolError::invalid_input(format!(
+            "{} is not enabled in this deployment",
+            chain
+        ))
+        .with_hint("Tell the user this chain is not supported here")
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let mut config = Config::default();
+        config.chains.enabled = vec![Chain::Solana];
+        let registry = ChainRegistry::from_config(&config);
+        assert!(registry.is_enabled(Chain::Solana));
+        assert!(!registry.is_enabled(Chain::Evm));
+        assert!(registry.require(Chain::Evm).is_err());
+        assert_eq!(Chain::of_key("sonic"), Chain::Evm);
+        assert_eq!("sol".parse::<Chain>().unwrap(), Chain::Solana);
+    }
+}
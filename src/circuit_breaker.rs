@@ -0,0 +1,244 @@
+//! Circuit breakers of the external APIs (Privy, Jupiter, Raydium, LiFi and
+//! the price providers), one per api name of `observe_api`. After
+//! breakers.failure_threshold consecutive outages (timeouts, connection
+//! errors, 5xx and 429) the breaker opens and the calls to the API fail
+//! at once with `Error::Unavailable` instead of waiting for the timeouts
+//! again. Once breakers.open_secs passed a single probe goes through, it
+//! closes the breaker when the API answers and keeps it open otherwise.
+//! Errors the API answered with (bad input, no route..) count as answers
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+
+use crate::config::BreakerSettings;
+use crate::error::Error;
+use crate::tool_error::{ErrorCode, ToolError};
+
+/// Status lines of the upstream errors that only keep their message
+const OUTAGE_STATUSES: &[&str] = &[
+    "500 internal server error",
+    "bad gateway",
+    "service unavailable",
+    "gateway timeout",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        since: Instant,
+    },
+    /// a probe is in flight
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+pub struct CircuitBreakers {
+    states: Mutex<HashMap<&'static str, State>>,
+}
+
+pub static BREAKERS: Lazy<CircuitBreakers> = Lazy::new(CircuitBreakers::new);
+
+impl Default for CircuitBreakers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CircuitBreakers {
+    pub fn new() -> Self {
+        Self {
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Lets a call to `api` through unless its breaker is open
+    pub fn check(
+        &self,
+        api: &'static str,
+        settings: &BreakerSettings,
+    ) -> Result<(), Error> {
+        if !settings.enabled {
+            return Ok(());
+        }
+        let open_for = Duration::from_secs(settings.open_secs);
+        let mut states = self.states.lock().unwrap();
+        let state =
+            states.entry(api).or_insert(State::Closed { failures: 0 });
+        match *state {
+            State::Closed { .. } => Ok(()),
+            // a probe lost on the way (cancelled call) doesn't keep the
+            // breaker half-open forever
+            State::Open { since } | State::HalfOpen { since }
+                if since.elapsed() >= open_for =>
+            {
+                tracing::info!(api, "circuit breaker half-open, probing");
+                *state = State::HalfOpen {
+                    since: Instant::now(),
+                };
+                Ok(())
+            }
+            State::Open { since } | State::HalfOpen { since } => {
+                let retry_in = open_for.saturating_sub(since.elapsed());
+                Err(Error::Unavailable(format!(
+                    "{} is failing, try again in {}s",
+                    api,
+                    retry_in.as_secs().max(1)
+                )))
+            }
+        }
+    }
+
+    /// Records the outcome of a call let through by `check`
+    pub fn record(
+        &self,
+        api: &'static str,
+        outage: bool,
+        settings: &BreakerSettings,
+    ) {
+        if !settings.enabled {
+            return;
+        }
+        let mut states = self.states.lock().unwrap();
+        let state =
+            states.entry(api).or_insert(State::Closed { failures: 0 });
+        *state = match (*state, outage) {
+            (State::Closed { .. }, false) => State::Closed { failures: 0 },
+            (State::Open { .. } | State::HalfOpen { .. }, false) => {
+                tracing::info!(api, "circuit breaker closed");
+                State::Closed { failures: 0 }
+            }
+            (State::Closed { failures }, true)
+                if failures + 1 < settings.failure_threshold =>
+            {
+                State::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (_, true) => {
+                tracing::warn!(api, "circuit breaker open");
+                State::Open {
+                    since: Instant::now(),
+                }
+            }
+        };
+    }
+
+    /// closed, open or half_open
+    pub fn state(&self, api: &str) -> &'static str {
+        match self.states.lock().unwrap().get(api) {
+            None | Some(State::Closed { .. }) => "closed",
+            Some(State::Open { .. }) => "open",
+            Some(State::HalfOpen { .. }) => "half_open",
+        }
+    }
+}
+
+/// Whether the error means the API is down rather than that it refused
+/// the request
+pub fn is_outage(error: &anyhow::Error) -> bool {
+    let http =
+        error
+            .chain()
+            .find_map(|cause| match cause.downcast_ref::<Error>() {
+                Some(Error::Http(e)) => Some(e),
+                _ => cause.downcast_ref::<reqwest::Error>(),
+            });
+    if let Some(e) = http {
+        if e.is_timeout() || e.is_connect() {
+            return true;
+        }
+        if let Some(status) = e.status() {
+            return status.is_server_error() || status.as_u16() == 429;
+        }
+    }
+    let tool_error = ToolError::from_anyhow(error);
+    let message = tool_error.message.to_lowercase();
+    matches!(
+        tool_error.code,
+        ErrorCode::Timeout | ErrorCode::Network | ErrorCode::RateLimited
+    ) || OUTAGE_STATUSES
+        .iter()
+        .any(|status| message.contains(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[test]
+    fn test_open_and_probe() {
+        let breakers = CircuitBreakers::new();
+        let settings = BreakerSettings {
+            failure_threshold: 3,
+            open_secs: 0,
+            ..Default::default()
+        };
+        for _ in 0..2 {
+            breakers.check("jupiter", &settings).unwrap();
+            breakers.record("jupiter", true, &settings);
+        }
+        // answered, the count starts over
+        breakers.record("jupiter", false, &settings);
+        for _ in 0..3 {
+            breakers.record("jupiter", true, &settings);
+        }
+        assert_eq!(breakers.state("jupiter"), "open");
+        assert_eq!(breakers.state("lifi"), "closed");
+
+        // open_secs passed, one probe goes through and fails
+        breakers.check("jupiter", &settings).unwrap();
+        assert_eq!(breakers.state("jupiter"), "half_open");
+        breakers.record("jupiter", true, &settings);
+        assert_eq!(breakers.state("jupiter"), "open");
+
+        breakers.check("jupiter", &settings).unwrap();
+        breakers.record("jupiter", false, &settings);
+        assert_eq!(breakers.state("jupiter"), "closed");
+    }
+
+    #[test]
+    fn test_fail_fast() {
+        let breakers = CircuitBreakers::new();
+        let settings = BreakerSettings {
+            failure_threshold: 1,
+            ..Default::default()
+        };
+        breakers.record("privy", true, &settings);
+        let error = breakers.check("privy", &settings).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Unavailable);
+        assert!(error.to_string().starts_with("Service unavailable: privy"));
+
+        let disabled = BreakerSettings {
+            enabled: false,
+            ..settings
+        };
+        assert!(breakers.check("privy", &disabled).is_ok());
+    }
+
+    #[test]
+    fn test_is_outage() {
+        assert!(is_outage(&anyhow!("operation timed out")));
+        assert!(is_outage(
+            &Error::Quote(
+                "Request failed with status code 503 Service Unavailable, "
+                    .to_string()
+            )
+            .into()
+        ));
+        assert!(!is_outage(
+            &Error::Quote(
+                "Jupiter Quote Error: TOKEN_NOT_TRADABLE".to_string()
+            )
+            .into()
+        ));
+        assert!(!is_outage(&anyhow!("insufficient funds for rent")));
+    }
+}
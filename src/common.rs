@@ -1,26 +1,16 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use solana_client::nonblocking::rpc_client::RpcClient;
 use std::future::Future;
-use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-use crate::signer::{SignerContext, TransactionSigner};
-
-pub async fn wrap_unsafe<F, Fut, T>(f: F) -> Result<T>
-where
-    F: FnOnce() -> Fut + Send + 'static,
-    Fut: Future<Output = Result<T>> + Send + 'static,
-    T: Send + 'static,
-{
-    let (tx, mut rx) = mpsc::channel(1);
-
-    tokio::spawn(async move {
-        let result = f().await;
-        let _ = tx.send(result).await;
-    });
-
-    rx.recv().await.ok_or_else(|| anyhow!("Channel closed"))?
-}
+use crate::config::{config, SolanaCluster};
+use crate::signer::registry::SIGNERS;
+use crate::signer::TransactionSigner;
 
+/// Runs the turn `f` of a session on a task of its own, the signer stays
+/// registered in `SIGNERS` while it runs
 pub async fn spawn_with_signer<F, Fut, T>(
     signer: Arc<dyn TransactionSigner>,
     f: F,
@@ -30,13 +20,119 @@ where
     Fut: Future<Output = Result<T>> + Send + 'static,
     T: Send + 'static,
 {
-    tokio::spawn(async move {
-        SignerContext::with_signer(signer, async { f().await }).await
-    })
+    tokio::spawn(
+        async move { SIGNERS.run(signer, async { f().await }).await },
+    )
 }
 
 use rig::agent::{Agent, AgentBuilder};
+use rig::completion::CompletionModel;
 use rig::providers::anthropic::completion::CompletionModel as AnthropicCompletionModel;
+use rig::tool::Tool;
+
+use crate::alerts::{CancelAlert, ListAlerts, SetPriceAlert};
+use crate::automation::{
+    CreateAutomationHook, ListAutomationHooks, RevokeAutomationHook,
+};
+use crate::backtest::BacktestStrategy;
+use crate::cancellation::CancelToolCalls;
+use crate::confirmation::status::GetTransactionStatus;
+use crate::confirmation::{ConfirmAction, ListPendingActions, RejectAction};
+use crate::costs::GetCostReport;
+use crate::deposits::{StopWatchingDeposits, WatchDeposits};
+use crate::execution::{GetExecutionMode, SetExecutionMode};
+use crate::explain::ExplainTransaction;
+use crate::fee_estimate::EstimateFees;
+use crate::freeze::FreezeMyWallet;
+use crate::history::ExportHistory;
+use crate::jobs::GetJob;
+use crate::memory::RecallToolResults;
+use crate::paper_trading::{GetPaperPortfolio, ResetPaperPortfolio};
+use crate::portfolio::{GetPortfolioHistory, InspectWallet};
+use crate::position_sizing::SuggestPositionSize;
+use crate::preferences::{GetPreferences, SetPreference};
+use crate::revenue::{GetReferralCode, GetReferralEarnings, SetReferrer};
+use crate::risk::{
+    AllowToken, BlockToken, GetRiskLimits, ListBlockedTokens, SetRiskLimits,
+};
+use crate::scheduler::{
+    CancelScheduledTask, ListScheduledTasks, ScheduleTask,
+};
+use crate::token_search::SearchToken;
+use crate::trade_plan::ExecuteTradePlan;
+use crate::trending::GetTrendingTokens;
+use crate::triggers::{
+    CancelTriggerOrder, ListTriggerOrders, SetTriggerOrder,
+};
+use crate::twap::{CancelTwapOrder, GetTwapOrder, TwapSwap};
+
+/// Anything tools can be registered with, agents and the MCP server share
+/// the tool lists through it
+pub trait ToolRegistry: Sized {
+    fn tool<T: Tool + 'static>(self, tool: T) -> Self;
+}
+
+impl<M: CompletionModel> ToolRegistry for AgentBuilder<M> {
+    fn tool<T: Tool + 'static>(self, tool: T) -> Self {
+        AgentBuilder::tool(self, tool)
+    }
+}
+
+/// Tools of every agent: confirmations, risk limits, session memory and the
+/// background jobs
+pub fn register_common_tools<R: ToolRegistry>(registry: R) -> R {
+    registry
+        .tool(ConfirmAction)
+        .tool(RejectAction)
+        .tool(ListPendingActions)
+        .tool(GetTransactionStatus)
+        .tool(CancelToolCalls)
+        .tool(FreezeMyWallet)
+        .tool(GetRiskLimits)
+        .tool(SetRiskLimits)
+        .tool(BlockToken)
+        .tool(AllowToken)
+        .tool(ListBlockedTokens)
+        .tool(RecallToolResults)
+        .tool(ExplainTransaction)
+        .tool(ExportHistory)
+        .tool(GetCostReport)
+        .tool(EstimateFees)
+        .tool(GetExecutionMode)
+        .tool(SetExecutionMode)
+        .tool(GetPaperPortfolio)
+        .tool(ResetPaperPortfolio)
+        .tool(GetPortfolioHistory)
+        .tool(InspectWallet)
+        .tool(GetPreferences)
+        .tool(SetPreference)
+        .tool(BacktestStrategy)
+        .tool(SuggestPositionSize)
+        .tool(GetTrendingTokens)
+        .tool(SearchToken)
+        .tool(ExecuteTradePlan)
+        .tool(TwapSwap)
+        .tool(GetTwapOrder)
+        .tool(CancelTwapOrder)
+        .tool(ScheduleTask)
+        .tool(ListScheduledTasks)
+        .tool(CancelScheduledTask)
+        .tool(GetJob)
+        .tool(CreateAutomationHook)
+        .tool(ListAutomationHooks)
+        .tool(RevokeAutomationHook)
+        .tool(GetReferralCode)
+        .tool(SetReferrer)
+        .tool(GetReferralEarnings)
+        .tool(SetPriceAlert)
+        .tool(ListAlerts)
+        .tool(CancelAlert)
+        .tool(SetTriggerOrder)
+        .tool(ListTriggerOrders)
+        .tool(CancelTriggerOrder)
+        .tool(WatchDeposits)
+        .tool(StopWatchingDeposits)
+}
 
 pub fn claude_agent_builder() -> AgentBuilder<AnthropicCompletionModel> {
     rig::providers::anthropic::Client::from_env()
@@ -51,3 +147,174 @@ pub async fn plain_agent() -> Result<Agent<AnthropicCompletionModel>> {
 }
 
 pub const PREAMBLE_COMMON: &str = "";
+
+/// Returns a block explorer link for a transaction hash/signature or an
+/// address, chain keys follow the LiFi ones ("sol", "arb") with "sonic"
+/// being the default EVM chain
+pub fn explorer_url(chain: &str, tx_or_address: &str) -> Option<String> {
+    // made up by the paper signer of dry runs
+    if tx_or_address.starts_with("paper-") {
+        return None;
+    }
+    let base = match chain.to_lowercase().as_str() {
+        "sol" | "solana" => "https://solscan.io",
+        "sonic" | "s" | "146" => "https://sonicscan.org",
+        "eth" | "ethereum" | "1" => "https://etherscan.io",
+        "arb" | "arbitrum" | "42161" => "https://arbiscan.io",
+        "base" | "8453" => "https://basescan.org",
+        _ => return None,
+    };
+    let is_solana = base == "https://solscan.io";
+    // evm tx hashes are 32 bytes hex, solana signatures are 64 bytes base58,
+    // both are way longer than the respective addresses
+    let kind = if (is_solana && tx_or_address.len() > 44)
+        || (!is_solana && tx_or_address.len() == 66)
+    {
+        "tx"
+    } else if is_solana {
+        "account"
+    } else {
+        "address"
+    };
+    // solscan shows mainnet unless told otherwise
+    let cluster = config().rpc.solana_cluster;
+    let query = if is_solana && cluster != SolanaCluster::Mainnet {
+        format!("?cluster={}", cluster.as_str())
+    } else {
+        String::new()
+    };
+    Some(format!("{}/{}/{}{}", base, kind, tx_or_address, query))
+}
+
+/// Chain key of ETHEREUM_RPC_URL used for explorer links, Sonic unless
+/// rpc.evm_chain (EVM_CHAIN) says otherwise
+pub fn evm_chain() -> String {
+    config().rpc.evm_chain.clone()
+}
+
+/// RPC used to broadcast EVM transactions for the chain id, public
+/// endpoints unless configured
+pub fn evm_rpc_url(chain_id: u64) -> Option<String> {
+    let config = config();
+    let rpc = &config.rpc;
+    match chain_id {
+        146 => Some(rpc.sonic.clone()),
+        1 => Some(rpc.ethereum_mainnet.clone()),
+        _ => None,
+    }
+}
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+const HTTP_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const HTTP_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// Idle connections kept open per host, the APIs are called in bursts
+const HTTP_MAX_IDLE_PER_HOST: usize = 16;
+const SOLANA_RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(HTTP_TIMEOUT)
+        .connect_timeout(HTTP_CONNECT_TIMEOUT)
+        .pool_idle_timeout(HTTP_POOL_IDLE_TIMEOUT)
+        .pool_max_idle_per_host(HTTP_MAX_IDLE_PER_HOST)
+        .tcp_keepalive(Duration::from_secs(60))
+        .build()
+        .expect("Failed to build the HTTP client")
+});
+
+/// HTTP client of every API call (Jupiter, LiFi, DexScreener, Tenderly..),
+/// clones share the connection pool so TLS handshakes are paid once per
+/// host instead of once per call
+pub fn http_client() -> reqwest::Client {
+    HTTP_CLIENT.clone()
+}
+
+static SOLANA_RPC: Lazy<RwLock<Option<(String, Arc<RpcClient>)>>> =
+    Lazy::new(|| RwLock::new(None));
+
+/// Solana RPC client shared by the tools, rebuilt when rpc.solana changes
+/// on a config reload
+pub fn solana_rpc() -> Arc<RpcClient> {
+    let config = config();
+    let url = &config.rpc.solana;
+    if let Some((current, client)) = SOLANA_RPC.read().unwrap().as_ref() {
+        if current == url {
+            return client.clone();
+        }
+    }
+    let mut shared = SOLANA_RPC.write().unwrap();
+    match shared.as_ref() {
+        Some((current, client)) if current == url => client.clone(),
+        _ => {
+            let client = Arc::new(RpcClient::new_with_timeout(
+                url.clone(),
+                SOLANA_RPC_TIMEOUT,
+            ));
+            *shared = Some((url.clone(), client.clone()));
+            client
+        }
+    }
+}
+
+/// Appends the explorer link to a transaction hash returned by a tool
+pub fn with_explorer_link(chain: &str, tx_hash: &str) -> String {
+    match explorer_url(chain, tx_hash) {
+        Some(url) => format!("{} ({})", tx_hash, url),
+        None => tx_hash.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explorer_url() {
+        let tx = format!("0x{}", "ab".repeat(32));
+        assert_eq!(
+            explorer_url("sonic", &tx).unwrap(),
+            format!("https://sonicscan.org/tx/{}", tx)
+        );
+        assert_eq!(
+            explorer_url("arb", "0x2fAA30d5EdDF1e4fa126aEdA79159878D58A2438")
+                .unwrap(),
+            "https://arbiscan.io/address/0x2fAA30d5EdDF1e4fa126aEdA79159878D58A2438"
+        );
+        assert_eq!(
+            explorer_url("sol", "So11111111111111111111111111111111111111112")
+                .unwrap(),
+            "https://solscan.io/account/So11111111111111111111111111111111111111112"
+        );
+        assert!(explorer_url("unknown", &tx).is_none());
+    }
+
+    #[test]
+    fn test_shared_clients() {
+        assert!(Arc::ptr_eq(&solana_rpc(), &solana_rpc()));
+        assert_eq!(solana_rpc().url(), config().rpc.solana);
+    }
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    /// Tools run on the runtime of the caller, spawned ones need `Send`
+    #[test]
+    fn test_tool_futures_are_send() {
+        use crate::solana::tools::{perform_jupiter_swap, transfer_sol};
+
+        assert_send(&transfer_sol(String::new(), String::new()));
+        assert_send(&perform_jupiter_swap(
+            String::new(),
+            String::new(),
+            String::new(),
+            0,
+        ));
+        assert_send(&crate::trade_plan::execute_trade_plan(
+            String::new(),
+            String::new(),
+        ));
+        assert_send(&crate::evm::tools::transfer_eth(
+            String::new(),
+            String::new(),
+        ));
+    }
+}
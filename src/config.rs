@@ -0,0 +1,1484 @@
+//! Configuration of the kit, read at startup from a single file
+//! (CONFIG_FILE, otherwise config.toml or config.yaml in the working
+//! directory) and validated. The environment variables the modules used to
+//! read on their own override the file, so existing deployments keep working
+//! without one. The RPC endpoints, rate limits, risk limits, token lists,
+//! the shutdown timeout, the quote prefetching, the swap venues, the fee
+//! tuning, the circuit breakers, the outbox retries, the deposit watcher,
+//! the address screening, the portfolio snapshots, the operator fees and the
+//! gas tank are reloaded on SIGHUP or when the file changes, modules caching
+//! them follow the changes with `subscribe`
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Context, Result};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgConnectOptions;
+use tokio::sync::watch;
+
+use crate::address::Address;
+use crate::chains::Chain;
+use crate::db::Backend;
+use crate::kv_encryption::LocalMasterKey;
+use crate::solana::constants::{USDC, WSOL};
+use crate::validation::MAX_SLIPPAGE_BPS;
+
+const DEFAULT_FILES: &[&str] = &["config.toml", "config.yaml", "config.yml"];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub chains: ChainSettings,
+    pub agents: AgentSettings,
+    pub privy: PrivySettings,
+    pub rpc: RpcSettings,
+    pub database: DatabaseSettings,
+    pub trading: TradingSettings,
+    pub rate_limits: RateLimitSettings,
+    pub risk: RiskSettings,
+    pub tokens: TokenLists,
+    pub features: FeatureToggles,
+    pub shutdown: ShutdownSettings,
+    pub prefetch: PrefetchSettings,
+    pub venues: VenueSettings,
+    pub fees: FeeSettings,
+    pub breakers: BreakerSettings,
+    pub outbox: OutboxSettings,
+    pub deposits: DepositSettings,
+    pub screening: ScreeningSettings,
+    pub snapshots: SnapshotSettings,
+    pub revenue: RevenueSettings,
+    pub gas_tank: GasTankSettings,
+    pub activity: ActivitySettings,
+    pub launches: LaunchSettings,
+    pub admin: AdminSettings,
+    pub http: HttpSettings,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainSettings {
+    pub enabled: Vec<Chain>,
+}
+
+impl Default for ChainSettings {
+    fn default() -> Self {
+        Self {
+            enabled: Chain::ALL.to_vec(),
+        }
+    }
+}
+
+/// Model and tools of the agents, see `agent`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentSettings {
+    /// Anthropic model of the agents
+    pub model: String,
+    pub max_tokens: u64,
+    /// tools the agents get, every tool of their chain when empty
+    pub tools: Vec<String>,
+    /// tools taken away from the agents and the MCP server
+    pub disabled_tools: Vec<String>,
+}
+
+impl Default for AgentSettings {
+    fn default() -> Self {
+        Self {
+            model: rig::providers::anthropic::CLAUDE_3_5_SONNET.to_string(),
+            max_tokens: 1024,
+            tools: Vec::new(),
+            disabled_tools: Vec::new(),
+        }
+    }
+}
+
+/// Credentials of the Privy app, required by the server only
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivySettings {
+    pub app_id: Option<String>,
+    pub app_secret: Option<String>,
+    pub verification_key: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcSettings {
+    pub solana: String,
+    /// EVM chain of the local signer
+    pub ethereum: Option<String>,
+    /// subscriptions to EVM events, polled over `ethereum` without it
+    pub ethereum_ws: Option<String>,
+    pub sonic: String,
+    pub ethereum_mainnet: String,
+    /// chain key of `ethereum`, used for explorer links and risk checks
+    pub evm_chain: String,
+    /// how often the shared Solana blockhash is refreshed
+    pub blockhash_refresh_ms: u64,
+    /// cluster `solana` belongs to, the faucet tools only work off mainnet
+    pub solana_cluster: SolanaCluster,
+}
+
+impl Default for RpcSettings {
+    fn default() -> Self {
+        Self {
+            solana: "https://api.mainnet-beta.solana.com".to_string(),
+            ethereum: None,
+            ethereum_ws: None,
+            sonic: "https://rpc.soniclabs.com".to_string(),
+            ethereum_mainnet: "https://eth.llamarpc.com".to_string(),
+            evm_chain: "sonic".to_string(),
+            blockhash_refresh_ms: 400,
+            solana_cluster: SolanaCluster::Mainnet,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SolanaCluster {
+    #[default]
+    Mainnet,
+    Devnet,
+    Testnet,
+}
+
+impl SolanaCluster {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Mainnet => "mainnet",
+            Self::Devnet => "devnet",
+            Self::Testnet => "testnet",
+        }
+    }
+}
+
+impl FromStr for SolanaCluster {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "mainnet" | "mainnet-beta" => Ok(Self::Mainnet),
+            "devnet" => Ok(Self::Devnet),
+            "testnet" => Ok(Self::Testnet),
+            other => Err(anyhow!("Unknown Solana cluster {}", other)),
+        }
+    }
+}
+
+impl RpcSettings {
+    pub fn ethereum_url(&self) -> Result<&str> {
+        self.ethereum.as_deref().ok_or_else(|| {
+            anyhow!("rpc.ethereum (ETHEREUM_RPC_URL) is not set")
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSettings {
+    pub url: String,
+    pub max_connections: u32,
+}
+
+impl Default for DatabaseSettings {
+    fn default() -> Self {
+        Self {
+            url: "postgres://127.0.0.1:5432/wallets".to_string(),
+            max_connections: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TradingSettings {
+    /// used when neither the call nor the session sets one
+    pub default_slippage_bps: u16,
+    /// kept out of "all" of SOL for the fees and the rent of token accounts
+    pub sol_fee_reserve_lamports: u64,
+    /// kept out of "all" of the native token of EVM chains
+    pub evm_fee_reserve_wei: u64,
+    /// fee of every trade of a backtest
+    pub backtest_fee_bps: u16,
+}
+
+impl Default for TradingSettings {
+    fn default() -> Self {
+        Self {
+            default_slippage_bps: 50,
+            sol_fee_reserve_lamports: 10_000_000,
+            evm_fee_reserve_wei: 10_000_000_000_000_000,
+            backtest_fee_bps: 30,
+        }
+    }
+}
+
+/// Limits of value-moving tool calls per user, 0 disables a limit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitSettings {
+    pub swaps_per_minute: usize,
+    pub transactions_per_hour: usize,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            swaps_per_minute: 5,
+            transactions_per_hour: 60,
+        }
+    }
+}
+
+/// Risk limits of users that didn't set their own, 0 disables a USD cap
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RiskSettings {
+    pub max_trade_usd: f64,
+    pub max_daily_volume_usd: f64,
+    pub max_slippage_bps: u16,
+    pub new_token_hours: f64,
+}
+
+impl Default for RiskSettings {
+    fn default() -> Self {
+        Self {
+            max_trade_usd: 5_000.,
+            max_daily_volume_usd: 20_000.,
+            max_slippage_bps: 500,
+            new_token_hours: 72.,
+        }
+    }
+}
+
+/// Tokens of every user, on top of their own lists
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokenLists {
+    /// when not empty only these tokens can be traded
+    pub allowlist: Vec<String>,
+    pub denylist: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeatureToggles {
+    /// sends Solana transactions without simulating them first
+    pub skip_simulation: bool,
+    /// simulates EVM transactions before sending them
+    pub evm_simulate: bool,
+    /// simulates Solana transactions before signing them, whatever the
+    /// signer is, a failing one is refused with why
+    pub solana_simulate: bool,
+    /// parks value-moving actions without simulating their balance changes
+    pub skip_balance_preview: bool,
+}
+
+/// Graceful shutdown on SIGTERM, see `shutdown`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownSettings {
+    /// how long signing tool calls are waited for before exiting
+    pub drain_timeout_secs: u64,
+}
+
+impl Default for ShutdownSettings {
+    fn default() -> Self {
+        Self {
+            drain_timeout_secs: 60,
+        }
+    }
+}
+
+/// Background Jupiter quotes of the hot pairs, see `solana::prefetch`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrefetchSettings {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    /// older prefetched quotes are never executed
+    pub max_age_secs: u64,
+    pub pairs: Vec<HotPair>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HotPair {
+    pub input_mint: String,
+    pub output_mint: String,
+    /// in base units of the input token, swaps of other amounts aren't
+    /// prefetched
+    pub amounts: Vec<u64>,
+}
+
+impl Default for PrefetchSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 10,
+            max_age_secs: 15,
+            pairs: vec![
+                HotPair {
+                    input_mint: WSOL.to_string(),
+                    output_mint: USDC.to_string(),
+                    amounts: vec![100_000_000, 1_000_000_000],
+                },
+                HotPair {
+                    input_mint: USDC.to_string(),
+                    output_mint: WSOL.to_string(),
+                    amounts: vec![10_000_000, 100_000_000],
+                },
+            ],
+        }
+    }
+}
+
+/// Aggregators quoted in parallel for Solana swaps, see `solana::venues`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VenueSettings {
+    pub solana: Vec<SolanaVenue>,
+    /// quotes slower than their budget are left out
+    pub jupiter_timeout_ms: u64,
+    pub raydium_timeout_ms: u64,
+    pub okx_timeout_ms: u64,
+    /// credentials of the OKX DEX API, required to quote on okx
+    pub okx_api_key: Option<String>,
+    pub okx_secret_key: Option<String>,
+    pub okx_passphrase: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SolanaVenue {
+    Jupiter,
+    Raydium,
+    Okx,
+}
+
+impl FromStr for SolanaVenue {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "jupiter" => Ok(Self::Jupiter),
+            "raydium" => Ok(Self::Raydium),
+            "okx" => Ok(Self::Okx),
+            other => Err(anyhow!("Unknown Solana venue {}", other)),
+        }
+    }
+}
+
+impl VenueSettings {
+    pub fn timeout(&self, venue: SolanaVenue) -> Duration {
+        Duration::from_millis(match venue {
+            SolanaVenue::Jupiter => self.jupiter_timeout_ms,
+            SolanaVenue::Raydium => self.raydium_timeout_ms,
+            SolanaVenue::Okx => self.okx_timeout_ms,
+        })
+    }
+}
+
+impl Default for VenueSettings {
+    fn default() -> Self {
+        Self {
+            solana: vec![SolanaVenue::Jupiter, SolanaVenue::Raydium],
+            jupiter_timeout_ms: 2_500,
+            raydium_timeout_ms: 1_500,
+            okx_timeout_ms: 2_000,
+            okx_api_key: None,
+            okx_secret_key: None,
+            okx_passphrase: None,
+        }
+    }
+}
+
+/// Priority fees of Solana transactions following the congestion of the
+/// network, see `solana::congestion`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeSettings {
+    /// raises the priority fee of the session when the network is busy
+    pub auto: bool,
+    pub sample_secs: u64,
+    /// cap of the automatic priority fee of a transaction
+    pub max_priority_fee_lamports: u64,
+}
+
+impl Default for FeeSettings {
+    fn default() -> Self {
+        Self {
+            auto: true,
+            sample_secs: 10,
+            max_priority_fee_lamports: 5_000_000,
+        }
+    }
+}
+
+/// Circuit breakers of the external APIs, see `circuit_breaker`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BreakerSettings {
+    pub enabled: bool,
+    /// consecutive outages of an API opening its breaker
+    pub failure_threshold: u32,
+    /// how long an open breaker fails calls before letting a probe through
+    pub open_secs: u64,
+}
+
+impl Default for BreakerSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            failure_threshold: 5,
+            open_secs: 30,
+        }
+    }
+}
+
+/// Rebroadcasting of the signed transactions of the outbox, see `outbox`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutboxSettings {
+    /// delay between two broadcasts of a transaction that hasn't landed
+    pub retry_secs: u64,
+    /// broadcasts after which a transaction is given up
+    pub max_attempts: u32,
+}
+
+impl Default for OutboxSettings {
+    fn default() -> Self {
+        Self {
+            retry_secs: 5,
+            max_attempts: 30,
+        }
+    }
+}
+
+/// Watching of the user wallets for incoming transfers, see `deposits`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DepositSettings {
+    pub enabled: bool,
+    /// smaller deposits are ignored, deposits of unpriced tokens never are
+    pub min_usd: f64,
+    /// websocket of the Solana RPC, derived from rpc.solana without it
+    pub solana_ws: Option<String>,
+    /// receives every deposit signed with `webhook_secret`, on top of the
+    /// notification sinks
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
+}
+
+impl Default for DepositSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_usd: 10.,
+            solana_ws: None,
+            webhook_url: None,
+            webhook_secret: None,
+        }
+    }
+}
+
+impl DepositSettings {
+    pub fn solana_ws_url(&self, rpc: &RpcSettings) -> String {
+        match &self.solana_ws {
+            Some(url) => url.clone(),
+            None => rpc
+                .solana
+                .replacen("https://", "wss://", 1)
+                .replacen("http://", "ws://", 1),
+        }
+    }
+}
+
+/// Screening of the destinations of transfers and bridges, see `screening`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScreeningSettings {
+    pub enabled: bool,
+    pub mode: ScreeningMode,
+    /// addresses never sent to, checked on every transfer
+    pub denylist: Vec<String>,
+    /// sanctions API of Chainalysis, consulted when `api_key` is set
+    pub api_url: String,
+    pub api_key: Option<String>,
+    /// destinations that screened clear are screened again after this long
+    pub recheck_hours: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScreeningMode {
+    /// flagged destinations are rejected, so are the ones the API couldn't
+    /// screen
+    Block,
+    /// flagged destinations are sent to with a warning
+    Warn,
+}
+
+impl FromStr for ScreeningMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "block" => Ok(Self::Block),
+            "warn" => Ok(Self::Warn),
+            other => Err(anyhow!("Unknown screening mode {}", other)),
+        }
+    }
+}
+
+impl Default for ScreeningSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: ScreeningMode::Block,
+            denylist: vec![],
+            api_url: "https://public.chainalysis.com/api/v1/address"
+                .to_string(),
+            api_key: None,
+            recheck_hours: 24,
+        }
+    }
+}
+
+/// Periodic snapshots of the user portfolios, see `portfolio`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnapshotSettings {
+    pub enabled: bool,
+    pub interval_mins: u64,
+    pub retention_days: u64,
+    /// ERC20 tokens valued on top of the native balance, EVM wallets
+    /// aren't indexed
+    pub evm_tokens: Vec<String>,
+}
+
+impl Default for SnapshotSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_mins: 60,
+            retention_days: 365,
+            evm_tokens: vec![],
+        }
+    }
+}
+
+/// Fees the operator collects on swaps and the share of them paid to
+/// referrers, see `revenue`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RevenueSettings {
+    /// Jupiter platform fee, taken from the output token
+    pub jupiter_fee_bps: u16,
+    /// wallet collecting the Jupiter fees, it needs a token account of
+    /// every output token
+    pub jupiter_fee_owner: Option<String>,
+    /// integrator name registered with LiFi
+    pub lifi_integrator: Option<String>,
+    /// LiFi integrator fee as a fraction of the input, 0.003 is 0.3%
+    pub lifi_fee: f64,
+    /// percentage of the fees credited to the referrer of the user
+    pub referrer_share_pct: f64,
+    /// bearer token of the revenue report, the report is off without one
+    pub api_token: Option<String>,
+}
+
+/// Operator treasury topping up the native balance of users who can't pay
+/// the fees of an action, see `gas_tank`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GasTankSettings {
+    pub enabled: bool,
+    /// base58 secret key of the Solana treasury
+    pub solana_key: Option<String>,
+    /// hex private key of the EVM treasury, the same on every chain
+    pub evm_key: Option<String>,
+    /// most a user is topped up on Solana per day (UTC)
+    pub max_lamports_per_day: u64,
+    /// most a user is topped up per EVM chain per day (UTC)
+    pub max_wei_per_day: u64,
+}
+
+impl Default for GasTankSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            solana_key: None,
+            evm_key: None,
+            max_lamports_per_day: 20_000_000,
+            max_wei_per_day: 5_000_000_000_000_000,
+        }
+    }
+}
+
+/// Export of the transactions of the agent to the operator, see `activity`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ActivitySettings {
+    /// receives every settled transaction signed with `webhook_secret`
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
+}
+
+/// Feed of new pump.fun launches, see `solana::launches`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LaunchSettings {
+    pub enabled: bool,
+    /// SOL a watch can buy a launch with, 0 disables sniping
+    pub max_snipe_sol: f64,
+    /// snipes of a user per day (UTC)
+    pub max_snipes_per_day: u32,
+}
+
+impl Default for LaunchSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_snipe_sol: 0.1,
+            max_snipes_per_day: 5,
+        }
+    }
+}
+
+/// Operator endpoints of the server, see `freeze`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminSettings {
+    /// bearer token of /v1/admin, the endpoints are off without one
+    pub api_token: Option<String>,
+}
+
+/// Retries and per-host rate limits of the outbound HTTP calls, see
+/// `http::client`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpSettings {
+    /// retries of a request after its first attempt, 0 disables them
+    pub max_retries: u32,
+    /// delay before the first retry, doubled on every retry
+    pub base_delay_ms: u64,
+    /// cap of the delay between two attempts
+    pub max_delay_ms: u64,
+    /// requests per second sent to a host, 0 leaves them unlimited
+    pub requests_per_sec: u32,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay_ms: 250,
+            max_delay_ms: 5000,
+            requests_per_sec: 10,
+        }
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn parse_var<T: FromStr>(name: &str, errors: &mut Vec<String>) -> Option<T> {
+    let value = env_var(name)?;
+    match value.trim().parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            errors.push(format!("{} has an invalid value {}", name, value));
+            None
+        }
+    }
+}
+
+fn list_var(name: &str) -> Option<Vec<String>> {
+    Some(
+        env_var(name)?
+            .split(',')
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty())
+            .collect(),
+    )
+}
+
+fn flag(value: &str) -> bool {
+    matches!(value, "true" | "1" | "yes")
+}
+
+fn check_url(errors: &mut Vec<String>, name: &str, url: &str) {
+    if reqwest::Url::parse(url).is_err() {
+        errors.push(format!("{} is not a valid URL: {}", name, url));
+    }
+}
+
+/// KV_STORE=postgres keeps the kv_store in the database of database.url,
+/// which then has to be Postgres
+fn check_kv_store(
+    errors: &mut Vec<String>,
+    kv_store: Option<&str>,
+    url: &str,
+) {
+    if kv_store == Some("postgres")
+        && Backend::of(url) != Some(Backend::Postgres)
+    {
+        errors.push(format!(
+            "KV_STORE=postgres needs a postgres database.url, not {}",
+            url.split(':').next().unwrap_or_default()
+        ));
+    }
+}
+
+impl Config {
+    /// Parses a TOML or, by the extension, YAML config file
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let yaml = path
+            .extension()
+            .is_some_and(|ext| ext == "yaml" || ext == "yml");
+        let config = if yaml {
+            serde_yaml::from_str(&content)?
+        } else {
+            toml::from_str(&content)?
+        };
+        Ok(config)
+    }
+
+    /// The config file, its defaults without one, overridden by the
+    /// environment and validated
+    pub fn load() -> Result<Self> {
+        let path = config_path();
+        let mut config = match &path {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        let mut errors = config.override_from_env();
+        errors.extend(config.problems());
+        check_kv_store(
+            &mut errors,
+            env_var("KV_STORE").as_deref(),
+            &config.database.url,
+        );
+        if let Err(e) = LocalMasterKey::from_env() {
+            errors.push(format!("KV_ENCRYPTION_KEY is invalid: {}", e));
+        }
+        if !errors.is_empty() {
+            return Err(anyhow!(
+                "Invalid configuration: {}",
+                errors.join(", ")
+            ));
+        }
+        tracing::info!(file = ?path, "configuration loaded");
+        Ok(config)
+    }
+
+    /// Applies the environment variables, returns the ones that don't parse
+    fn override_from_env(&mut self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if let Some(chains) = env_var("ENABLED_CHAINS") {
+            match chains
+                .split(',')
+                .filter(|chain| !chain.trim().is_empty())
+                .map(str::parse)
+                .collect()
+            {
+                Ok(enabled) => self.chains.enabled = enabled,
+                Err(e) => errors.push(format!("ENABLED_CHAINS: {}", e)),
+            }
+        }
+
+        let agents = &mut self.agents;
+        if let Some(model) = env_var("AGENT_MODEL") {
+            agents.model = model;
+        }
+        if let Some(max_tokens) = parse_var("AGENT_MAX_TOKENS", &mut errors) {
+            agents.max_tokens = max_tokens;
+        }
+        if let Some(tools) = list_var("AGENT_TOOLS") {
+            agents.tools = tools;
+        }
+        if let Some(tools) = list_var("DISABLED_TOOLS") {
+            agents.disabled_tools = tools;
+        }
+
+        let privy = &mut self.privy;
+        privy.app_id = env_var("PRIVY_APP_ID").or(privy.app_id.take());
+        privy.app_secret =
+            env_var("PRIVY_APP_SECRET").or(privy.app_secret.take());
+        privy.verification_key = env_var("PRIVY_VERIFICATION_KEY")
+            .or(privy.verification_key.take());
+
+        let rpc = &mut self.rpc;
+        if let Some(url) = env_var("SOLANA_RPC_URL") {
+            rpc.solana = url;
+        }
+        rpc.ethereum = env_var("ETHEREUM_RPC_URL").or(rpc.ethereum.take());
+        rpc.ethereum_ws =
+            env_var("ETHEREUM_WS_URL").or(rpc.ethereum_ws.take());
+        if let Some(url) = env_var("SONIC_RPC_URL") {
+            rpc.sonic = url;
+        }
+        if let Some(url) = env_var("ETHEREUM_MAINNET_RPC_URL") {
+            rpc.ethereum_mainnet = url;
+        }
+        if let Some(chain) = env_var("EVM_CHAIN") {
+            rpc.evm_chain = chain;
+        }
+        if let Some(ms) = parse_var("BLOCKHASH_REFRESH_MS", &mut errors) {
+            rpc.blockhash_refresh_ms = ms;
+        }
+        if let Some(cluster) = parse_var("SOLANA_CLUSTER", &mut errors) {
+            rpc.solana_cluster = cluster;
+        }
+
+        if let Some(url) = env_var("DATABASE_URL") {
+            self.database.url = url;
+        }
+        if let Some(max) = parse_var("DATABASE_MAX_CONNECTIONS", &mut errors)
+        {
+            self.database.max_connections = max;
+        }
+
+        let trading = &mut self.trading;
+        if let Some(bps) = parse_var("DEFAULT_SLIPPAGE_BPS", &mut errors) {
+            trading.default_slippage_bps = bps;
+        }
+        if let Some(lamports) = parse_var("SOL_FEE_RESERVE", &mut errors) {
+            trading.sol_fee_reserve_lamports = lamports;
+        }
+        if let Some(wei) = parse_var("EVM_FEE_RESERVE", &mut errors) {
+            trading.evm_fee_reserve_wei = wei;
+        }
+
+        let rate_limits = &mut self.rate_limits;
+        if let Some(max) =
+            parse_var("RATE_LIMIT_SWAPS_PER_MINUTE", &mut errors)
+        {
+            rate_limits.swaps_per_minute = max;
+        }
+        if let Some(max) =
+            parse_var("RATE_LIMIT_TRANSACTIONS_PER_HOUR", &mut errors)
+        {
+            rate_limits.transactions_per_hour = max;
+        }
+
+        if let Some(bps) = parse_var("MAX_SLIPPAGE_BPS", &mut errors) {
+            self.risk.max_slippage_bps = bps;
+        }
+        if let Some(tokens) = list_var("TOKEN_ALLOWLIST") {
+            self.tokens.allowlist = tokens;
+        }
+        if let Some(tokens) = list_var("TOKEN_DENYLIST") {
+            self.tokens.denylist = tokens;
+        }
+
+        // set at all used to be enough to skip the simulation
+        if std::env::var("SKIP_SIMULATION").is_ok() {
+            self.features.skip_simulation = true;
+        }
+        if let Some(simulate) = env_var("EVM_SIMULATE") {
+            self.features.evm_simulate = flag(&simulate);
+        }
+        if let Some(simulate) = env_var("SOLANA_SIMULATE") {
+            self.features.solana_simulate = flag(&simulate);
+        }
+        if let Some(skip) = env_var("SKIP_BALANCE_PREVIEW") {
+            self.features.skip_balance_preview = flag(&skip);
+        }
+
+        if let Some(secs) = parse_var("SHUTDOWN_TIMEOUT_SECS", &mut errors) {
+            self.shutdown.drain_timeout_secs = secs;
+        }
+        if let Some(prefetch) = env_var("QUOTE_PREFETCH") {
+            self.prefetch.enabled = flag(&prefetch);
+        }
+        if let Some(auto) = env_var("PRIORITY_FEE_AUTO") {
+            self.fees.auto = flag(&auto);
+        }
+        if let Some(max) = parse_var("MAX_PRIORITY_FEE", &mut errors) {
+            self.fees.max_priority_fee_lamports = max;
+        }
+        if let Some(enabled) = env_var("CIRCUIT_BREAKERS") {
+            self.breakers.enabled = flag(&enabled);
+        }
+        if let Some(n) = parse_var("BREAKER_FAILURE_THRESHOLD", &mut errors) {
+            self.breakers.failure_threshold = n;
+        }
+        if let Some(secs) = parse_var("BREAKER_OPEN_SECS", &mut errors) {
+            self.breakers.open_secs = secs;
+        }
+        if let Some(secs) = parse_var("OUTBOX_RETRY_SECS", &mut errors) {
+            self.outbox.retry_secs = secs;
+        }
+
+        let deposits = &mut self.deposits;
+        if let Some(enabled) = env_var("DEPOSIT_WATCHER") {
+            deposits.enabled = flag(&enabled);
+        }
+        if let Some(min) = parse_var("DEPOSIT_MIN_USD", &mut errors) {
+            deposits.min_usd = min;
+        }
+        deposits.solana_ws =
+            env_var("SOLANA_WS_URL").or(deposits.solana_ws.take());
+        deposits.webhook_url =
+            env_var("DEPOSIT_WEBHOOK_URL").or(deposits.webhook_url.take());
+        deposits.webhook_secret = env_var("DEPOSIT_WEBHOOK_SECRET")
+            .or(deposits.webhook_secret.take());
+
+        let screening = &mut self.screening;
+        if let Some(enabled) = env_var("ADDRESS_SCREENING") {
+            screening.enabled = flag(&enabled);
+        }
+        if let Some(mode) = parse_var("SCREENING_MODE", &mut errors) {
+            screening.mode = mode;
+        }
+        if let Some(addresses) = list_var("SCREENING_DENYLIST") {
+            screening.denylist = addresses;
+        }
+        if let Some(url) = env_var("SCREENING_API_URL") {
+            screening.api_url = url;
+        }
+        screening.api_key =
+            env_var("SCREENING_API_KEY").or(screening.api_key.take());
+
+        let snapshots = &mut self.snapshots;
+        if let Some(enabled) = env_var("PORTFOLIO_SNAPSHOTS") {
+            snapshots.enabled = flag(&enabled);
+        }
+        if let Some(mins) = parse_var("SNAPSHOT_INTERVAL_MINS", &mut errors) {
+            snapshots.interval_mins = mins;
+        }
+        if let Some(days) = parse_var("SNAPSHOT_RETENTION_DAYS", &mut errors)
+        {
+            snapshots.retention_days = days;
+        }
+        if let Some(tokens) = list_var("SNAPSHOT_EVM_TOKENS") {
+            snapshots.evm_tokens = tokens;
+        }
+
+        let revenue = &mut self.revenue;
+        if let Some(bps) = parse_var("JUPITER_FEE_BPS", &mut errors) {
+            revenue.jupiter_fee_bps = bps;
+        }
+        revenue.jupiter_fee_owner =
+            env_var("JUPITER_FEE_OWNER").or(revenue.jupiter_fee_owner.take());
+        revenue.lifi_integrator =
+            env_var("LIFI_INTEGRATOR").or(revenue.lifi_integrator.take());
+        if let Some(fee) = parse_var("LIFI_FEE", &mut errors) {
+            revenue.lifi_fee = fee;
+        }
+        if let Some(pct) = parse_var("REFERRER_SHARE_PCT", &mut errors) {
+            revenue.referrer_share_pct = pct;
+        }
+        revenue.api_token =
+            env_var("REVENUE_API_TOKEN").or(revenue.api_token.take());
+
+        let gas_tank = &mut self.gas_tank;
+        if let Some(enabled) = env_var("GAS_TANK") {
+            gas_tank.enabled = flag(&enabled);
+        }
+        gas_tank.solana_key =
+            env_var("GAS_TANK_SOLANA_KEY").or(gas_tank.solana_key.take());
+        gas_tank.evm_key =
+            env_var("GAS_TANK_EVM_KEY").or(gas_tank.evm_key.take());
+        if let Some(lamports) =
+            parse_var("GAS_TANK_MAX_LAMPORTS_PER_DAY", &mut errors)
+        {
+            gas_tank.max_lamports_per_day = lamports;
+        }
+        if let Some(wei) = parse_var("GAS_TANK_MAX_WEI_PER_DAY", &mut errors)
+        {
+            gas_tank.max_wei_per_day = wei;
+        }
+        let activity = &mut self.activity;
+        activity.webhook_url =
+            env_var("ACTIVITY_WEBHOOK_URL").or(activity.webhook_url.take());
+        activity.webhook_secret = env_var("ACTIVITY_WEBHOOK_SECRET")
+            .or(activity.webhook_secret.take());
+        let launches = &mut self.launches;
+        if let Some(enabled) = env_var("PUMP_LAUNCHES") {
+            launches.enabled = flag(&enabled);
+        }
+        if let Some(sol) = parse_var("SNIPE_MAX_SOL", &mut errors) {
+            launches.max_snipe_sol = sol;
+        }
+        if let Some(snipes) = parse_var("SNIPE_MAX_PER_DAY", &mut errors) {
+            launches.max_snipes_per_day = snipes;
+        }
+        self.admin.api_token =
+            env_var("ADMIN_API_TOKEN").or(self.admin.api_token.take());
+        if let Some(retries) = parse_var("HTTP_MAX_RETRIES", &mut errors) {
+            self.http.max_retries = retries;
+        }
+        if let Some(rate) = parse_var("HTTP_REQUESTS_PER_SEC", &mut errors) {
+            self.http.requests_per_sec = rate;
+        }
+        if let Some(venues) = list_var("SOLANA_VENUES") {
+            match venues.iter().map(|venue| venue.parse()).collect() {
+                Ok(venues) => self.venues.solana = venues,
+                Err(e) => errors.push(format!("SOLANA_VENUES: {}", e)),
+            }
+        }
+        let venues = &mut self.venues;
+        venues.okx_api_key =
+            env_var("OKX_API_KEY").or(venues.okx_api_key.take());
+        venues.okx_secret_key =
+            env_var("OKX_SECRET_KEY").or(venues.okx_secret_key.take());
+        venues.okx_passphrase =
+            env_var("OKX_PASSPHRASE").or(venues.okx_passphrase.take());
+        errors
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.chains.enabled.is_empty() {
+            errors.push("chains.enabled needs at least one chain".into());
+        }
+        if self.agents.model.trim().is_empty() {
+            errors.push("agents.model can't be empty".into());
+        }
+        if self.agents.max_tokens == 0 {
+            errors.push("agents.max_tokens has to be positive".into());
+        }
+        check_url(&mut errors, "rpc.solana", &self.rpc.solana);
+        check_url(&mut errors, "rpc.sonic", &self.rpc.sonic);
+        check_url(
+            &mut errors,
+            "rpc.ethereum_mainnet",
+            &self.rpc.ethereum_mainnet,
+        );
+        if let Some(url) = &self.rpc.ethereum {
+            check_url(&mut errors, "rpc.ethereum", url);
+        }
+        if let Some(url) = &self.rpc.ethereum_ws {
+            check_url(&mut errors, "rpc.ethereum_ws", url);
+        }
+        if self.rpc.blockhash_refresh_ms == 0 {
+            errors.push("rpc.blockhash_refresh_ms has to be positive".into());
+        }
+        if self.rpc.solana_cluster != SolanaCluster::Mainnet
+            && self.rpc.solana.contains("mainnet")
+        {
+            errors.push(format!(
+                "rpc.solana points at mainnet but rpc.solana_cluster is {}",
+                self.rpc.solana_cluster.as_str()
+            ));
+        }
+        check_url(&mut errors, "database.url", &self.database.url);
+        match Backend::of(&self.database.url) {
+            None => errors.push(
+                "database.url has to be a postgres, sqlite or memory URL"
+                    .into(),
+            ),
+            Some(Backend::Postgres) => {
+                if let Err(e) = PgConnectOptions::from_str(&self.database.url)
+                {
+                    errors.push(format!(
+                        "database.url is not a valid postgres URL: {}",
+                        e
+                    ));
+                }
+            }
+            Some(_) => {}
+        }
+        if self.database.max_connections == 0 {
+            errors.push("database.max_connections has to be positive".into());
+        }
+
+        let trading = &self.trading;
+        if self.risk.max_slippage_bps > MAX_SLIPPAGE_BPS {
+            errors.push(format!(
+                "risk.max_slippage_bps can't be above {}",
+                MAX_SLIPPAGE_BPS
+            ));
+        }
+        if trading.default_slippage_bps == 0
+            || trading.default_slippage_bps > self.risk.max_slippage_bps
+        {
+            errors.push(
+                "trading.default_slippage_bps has to be between 1 and risk.max_slippage_bps"
+                    .into(),
+            );
+        }
+        if self.risk.max_trade_usd < 0.
+            || self.risk.max_daily_volume_usd < 0.
+            || self.risk.new_token_hours < 0.
+        {
+            errors.push("risk limits can't be negative".into());
+        }
+        if trading.backtest_fee_bps >= 10_000 {
+            errors.push(
+                "trading.backtest_fee_bps has to be below 10000".into(),
+            );
+        }
+        if self.prefetch.interval_secs == 0 {
+            errors.push("prefetch.interval_secs has to be positive".into());
+        }
+        if self.fees.sample_secs == 0 {
+            errors.push("fees.sample_secs has to be positive".into());
+        }
+        if self.breakers.failure_threshold == 0 {
+            errors
+                .push("breakers.failure_threshold has to be positive".into());
+        }
+        if self.outbox.retry_secs == 0 {
+            errors.push("outbox.retry_secs has to be positive".into());
+        }
+        if self.http.base_delay_ms > self.http.max_delay_ms {
+            errors.push(
+                "http.base_delay_ms can't exceed http.max_delay_ms".into(),
+            );
+        }
+
+        let deposits = &self.deposits;
+        if deposits.min_usd.is_nan() || deposits.min_usd < 0. {
+            errors.push("deposits.min_usd can't be negative".into());
+        }
+        if let Some(url) = &deposits.solana_ws {
+            check_url(&mut errors, "deposits.solana_ws", url);
+        }
+        if let Some(url) = &deposits.webhook_url {
+            check_url(&mut errors, "deposits.webhook_url", url);
+            if deposits.webhook_secret.is_none() {
+                errors.push(
+                    "deposits.webhook_secret is required to sign the \
+                     deposit webhooks"
+                        .into(),
+                );
+            }
+        }
+        let activity = &self.activity;
+        if let Some(url) = &activity.webhook_url {
+            check_url(&mut errors, "activity.webhook_url", url);
+            if activity.webhook_secret.is_none() {
+                errors.push(
+                    "activity.webhook_secret is required to sign the \
+                     activity webhooks"
+                        .into(),
+                );
+            }
+        }
+        let max_snipe_sol = self.launches.max_snipe_sol;
+        if max_snipe_sol.is_nan() || max_snipe_sol < 0. {
+            errors.push("launches.max_snipe_sol can't be negative".into());
+        }
+        check_url(&mut errors, "screening.api_url", &self.screening.api_url);
+        if self.screening.recheck_hours == 0 {
+            errors.push("screening.recheck_hours has to be positive".into());
+        }
+        if self.snapshots.interval_mins == 0 {
+            errors.push("snapshots.interval_mins has to be positive".into());
+        }
+        if self.snapshots.retention_days == 0 {
+            errors.push("snapshots.retention_days has to be positive".into());
+        }
+        let revenue = &self.revenue;
+        if revenue.jupiter_fee_bps > 0 {
+            match &revenue.jupiter_fee_owner {
+                Some(owner) if Address::parse_on("sol", owner).is_ok() => {}
+                _ => errors.push(
+                    "revenue.jupiter_fee_owner has to be a Solana address \
+                     to collect Jupiter fees"
+                        .into(),
+                ),
+            }
+        }
+        if revenue.jupiter_fee_bps > 1000 {
+            errors.push("revenue.jupiter_fee_bps is capped at 1000".into());
+        }
+        if !(0.0..0.1).contains(&revenue.lifi_fee) {
+            errors.push("revenue.lifi_fee has to be below 0.1".into());
+        }
+        if revenue.lifi_fee > 0.0 && revenue.lifi_integrator.is_none() {
+            errors.push(
+                "revenue.lifi_integrator is required to collect LiFi fees"
+                    .into(),
+            );
+        }
+        if !(0.0..=100.0).contains(&revenue.referrer_share_pct) {
+            errors.push(
+                "revenue.referrer_share_pct has to be between 0 and 100"
+                    .into(),
+            );
+        }
+        if self.venues.solana.is_empty() {
+            errors.push("venues.solana needs at least one venue".into());
+        }
+        let venues = &self.venues;
+        if venues.solana.contains(&SolanaVenue::Okx)
+            && [
+                &venues.okx_api_key,
+                &venues.okx_secret_key,
+                &venues.okx_passphrase,
+            ]
+            .iter()
+            .any(|value| value.is_none())
+        {
+            errors.push(
+                "venues.okx_api_key, okx_secret_key and okx_passphrase are \
+                 required to quote on okx"
+                    .into(),
+            );
+        }
+
+        let gas_tank = &self.gas_tank;
+        if gas_tank.enabled
+            && gas_tank.solana_key.is_none()
+            && gas_tank.evm_key.is_none()
+        {
+            errors.push(
+                "gas_tank needs solana_key or evm_key to be enabled".into(),
+            );
+        }
+
+        let privy = &self.privy;
+        let set = [&privy.app_id, &privy.app_secret, &privy.verification_key]
+            .iter()
+            .filter(|value| value.is_some())
+            .count();
+        if set != 0 && set != 3 {
+            errors.push(
+                "privy needs app_id, app_secret and verification_key".into(),
+            );
+        }
+        errors
+    }
+
+    /// Takes the sections of `new` that can change at runtime, returns the
+    /// other ones that differ and need a restart
+    fn apply_runtime(&mut self, new: Config) -> Vec<&'static str> {
+        let mut restart = Vec::new();
+        if new.chains != self.chains {
+            restart.push("chains");
+        }
+        if new.agents != self.agents {
+            restart.push("agents");
+        }
+        if new.privy != self.privy {
+            restart.push("privy");
+        }
+        if new.database != self.database {
+            restart.push("database");
+        }
+        if new.trading != self.trading {
+            restart.push("trading");
+        }
+        if new.features != self.features {
+            restart.push("features");
+        }
+        self.rpc = new.rpc;
+        self.rate_limits = new.rate_limits;
+        self.risk = new.risk;
+        self.tokens = new.tokens;
+        self.shutdown = new.shutdown;
+        self.prefetch = new.prefetch;
+        self.venues = new.venues;
+        self.fees = new.fees;
+        self.breakers = new.breakers;
+        self.outbox = new.outbox;
+        self.deposits = new.deposits;
+        self.screening = new.screening;
+        self.snapshots = new.snapshots;
+        self.revenue = new.revenue;
+        self.gas_tank = new.gas_tank;
+        self.activity = new.activity;
+        self.launches = new.launches;
+        self.admin = new.admin;
+        self.http = new.http;
+        restart
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    match env_var("CONFIG_FILE") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => DEFAULT_FILES
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.exists()),
+    }
+}
+
+static CONFIG: OnceCell<watch::Sender<Arc<Config>>> = OnceCell::new();
+
+/// Loads the config, the binaries call it at startup to fail on a bad one
+/// before anything uses it
+pub fn init() -> Result<Arc<Config>> {
+    let config = CONFIG.get_or_try_init(|| {
+        Config::load().map(|config| watch::Sender::new(Arc::new(config)))
+    })?;
+    Ok(config.borrow().clone())
+}
+
+/// The loaded config. Code that runs without `init` (tests, other crates)
+/// loads it on first use, an invalid one is logged and the defaults apply
+/// rather than failing whatever tool call or task got there first
+fn sender() -> &'static watch::Sender<Arc<Config>> {
+    CONFIG.get_or_init(|| {
+        let config = Config::load().unwrap_or_else(|e| {
+            tracing::error!(?e, "invalid configuration, using the defaults");
+            Config::default()
+        });
+        watch::Sender::new(Arc::new(config))
+    })
+}
+
+/// The current config
+pub fn config() -> Arc<Config> {
+    sender().borrow().clone()
+}
+
+/// Changes of the config, for modules that keep values derived from it
+pub fn subscribe() -> watch::Receiver<Arc<Config>> {
+    sender().subscribe()
+}
+
+/// Reloads the file and applies the runtime sections, an invalid file
+/// keeps the current config. True when anything changed
+pub fn reload() -> Result<bool> {
+    let new = Config::load()?;
+    let sender =
+        CONFIG.get_or_init(|| watch::Sender::new(Arc::new(new.clone())));
+    let mut config = Config::clone(&sender.borrow());
+    let restart = config.apply_runtime(new);
+    if !restart.is_empty() {
+        tracing::warn!(?restart, "config sections changed, restart to apply");
+    }
+    Ok(sender.send_if_modified(|current| {
+        if **current == config {
+            return false;
+        }
+        *current = Arc::new(config);
+        true
+    }))
+}
+
+fn reload_logged(trigger: &str) {
+    match reload() {
+        Ok(true) => tracing::info!(trigger, "config reloaded"),
+        Ok(false) => {}
+        Err(e) => tracing::error!(?e, trigger, "config not reloaded"),
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Reloads the config on SIGHUP and when the file changes, polled every
+/// CONFIG_POLL_SECS (5 by default, 0 only reloads on SIGHUP)
+pub fn spawn_reloader() -> tokio::task::JoinHandle<()> {
+    let poll = env_var("CONFIG_POLL_SECS")
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(5);
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        let mut hangup = tokio::signal::unix::signal(
+            tokio::signal::unix::SignalKind::hangup(),
+        )
+        .map_err(|e| tracing::error!(?e, "SIGHUP handler not installed"))
+        .ok();
+        let path = config_path();
+        let mut last_modified = path.as_deref().and_then(modified);
+        let mut tick =
+            tokio::time::interval(Duration::from_secs(poll.max(1)));
+        loop {
+            #[cfg(unix)]
+            let hangup_received = async {
+                match hangup.as_mut() {
+                    Some(hangup) => hangup.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            #[cfg(not(unix))]
+            let hangup_received = std::future::pending::<Option<()>>();
+            tokio::select! {
+                _ = hangup_received => reload_logged("SIGHUP"),
+                _ = tick.tick(), if poll > 0 => {
+                    let current = path.as_deref().and_then(modified);
+                    if current != last_modified {
+                        last_modified = current;
+                        reload_logged("file change");
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_files() {
+        let toml: Config = toml::from_str(
+            r#"
+            [rpc]
+            solana = "http://localhost:8899"
+
+            [trading]
+            default_slippage_bps = 100
+            "#,
+        )
+        .unwrap();
+        let yaml: Config = serde_yaml::from_str(
+            "rpc:\n  solana: http://localhost:8899\ntrading:\n  default_slippage_bps: 100\n",
+        )
+        .unwrap();
+        assert_eq!(toml, yaml);
+        assert_eq!(toml.risk.max_slippage_bps, 500);
+        assert_eq!(toml.rpc.evm_chain, "sonic");
+        assert!(toml::from_str::<Config>("[rpc]\nsolan = \"x\"").is_err());
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(Config::default().problems().is_empty());
+
+        let mut config = Config::default();
+        config.rpc.solana = "not a url".to_string();
+        config.trading.default_slippage_bps = 600;
+        config.privy.app_id = Some("app".to_string());
+        assert_eq!(config.problems().len(), 3);
+
+        let mut config = Config::default();
+        config.database.url = "postgres://db/agent?sslmode=maybe".to_string();
+        assert_eq!(config.problems().len(), 1);
+
+        let mut errors = Vec::new();
+        check_kv_store(&mut errors, Some("postgres"), "postgres://db/agent");
+        check_kv_store(&mut errors, None, "sqlite://agent.db");
+        assert!(errors.is_empty());
+        check_kv_store(&mut errors, Some("postgres"), "sqlite://agent.db");
+        check_kv_store(&mut errors, Some("postgres"), "memory://");
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn test_apply_runtime() {
+        let mut config = Config::default();
+        let mut new = Config::default();
+        new.rpc.solana = "http://localhost:8899".to_string();
+        new.rate_limits.swaps_per_minute = 1;
+        new.database.max_connections = 20;
+
+        assert_eq!(config.apply_runtime(new), ["database"]);
+        assert_eq!(config.rpc.solana, "http://localhost:8899");
+        assert_eq!(config.rate_limits.swaps_per_minute, 1);
+        assert_eq!(config.database.max_connections, 5);
+    }
+}
@@ -0,0 +1,421 @@
+//! Human-in-the-loop confirmation for tools that move funds. Depending on
+//! the policy a value-moving tool either executes right away or parks the
+//! action as a `PendingAction` which the user has to approve through
+//! `confirm_action` (or discard through `reject_action`) before it expires.
+//! Value-moving actions are simulated before being parked, the pending
+//! action shows how they change the balances of the wallet. Moves in and
+//! out of savings are parked whatever the policy. Whether a sent
+//! transaction made it on chain is told by `status`
+pub mod status;
+
+pub use status::{
+    await_confirmation, await_confirmation_on, await_evm_confirmation,
+    tx_status, TxStatus,
+};
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::config::config;
+use crate::execution::moves_value;
+use crate::memory::{last_pending_action_id, LAST};
+use crate::notify::{Notification, NotificationKind, NOTIFIER};
+use crate::preview::{balance_preview, BalanceDelta};
+use crate::signer::{SignerContext, TransactionSigner};
+use crate::tool_error::{ErrorCode, ToolError};
+
+const DEFAULT_TTL_SECS: i64 = 300;
+/// Tools confirmed even when the policy is never or the caller
+/// preconfirmed them
+pub(crate) const ALWAYS_CONFIRMED_TOOLS: &[&str] =
+    &["move_to_savings", "withdraw_from_savings", "allow_token"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmationPolicy {
+    /// every value-moving tool call has to be confirmed
+    Always,
+    /// execute right away, meant for local signers and tests
+    Never,
+}
+
+impl ConfirmationPolicy {
+    /// CONFIRMATION_POLICY=never disables confirmations, anything else
+    /// (including unset) requires them
+    pub fn from_env() -> Self {
+        match std::env::var("CONFIRMATION_POLICY").as_deref() {
+            Ok("never") => Self::Never,
+            _ => Self::Always,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingAction {
+    pub id: String,
+    pub tool: String,
+    pub summary: String,
+    pub expires_at: DateTime<Utc>,
+    /// simulated changes of the balances of the wallet
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub balance_changes: Vec<BalanceDelta>,
+    /// why the action could not be simulated, it may fail when confirmed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub simulation_error: Option<String>,
+}
+
+pub(crate) type ActionFuture =
+    Pin<Box<dyn Future<Output = Result<String>> + Send>>;
+pub(crate) type BoxedAction = Box<dyn FnOnce() -> ActionFuture + Send>;
+/// Balance changes of an action, or why they could not be simulated
+type Preview = (Vec<BalanceDelta>, Option<String>);
+
+struct StoredAction {
+    pending: PendingAction,
+    owner: Option<String>,
+    signer: Arc<dyn TransactionSigner>,
+    run: BoxedAction,
+}
+
+pub struct ConfirmationStore {
+    actions: Mutex<HashMap<String, StoredAction>>,
+    ttl: Duration,
+}
+
+pub static CONFIRMATIONS: Lazy<ConfirmationStore> = Lazy::new(|| {
+    let ttl = std::env::var("CONFIRMATION_TTL_SECS")
+        .ok()
+        .and_then(|ttl| ttl.parse().ok())
+        .unwrap_or(DEFAULT_TTL_SECS);
+    ConfirmationStore::new(Duration::seconds(ttl))
+});
+
+impl ConfirmationStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            actions: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    fn insert(
+        &self,
+        tool: &str,
+        summary: String,
+        (balance_changes, simulation_error): Preview,
+        signer: Arc<dyn TransactionSigner>,
+        run: BoxedAction,
+    ) -> PendingAction {
+        let pending = PendingAction {
+            id: format!("{:016x}", rand::random::<u64>()),
+            tool: tool.to_string(),
+            summary,
+            expires_at: Utc::now() + self.ttl,
+            balance_changes,
+            simulation_error,
+        };
+
+        let mut actions = self.actions.lock().unwrap();
+        actions.retain(|_, action| action.pending.expires_at > Utc::now());
+        actions.insert(
+            pending.id.clone(),
+            StoredAction {
+                pending: pending.clone(),
+                owner: signer.user_id(),
+                signer,
+                run,
+            },
+        );
+        pending
+    }
+
+    /// Removes the action if it exists and belongs to `owner`, expired
+    /// actions are removed but reported as such
+    fn take(&self, id: &str, owner: &Option<String>) -> Result<StoredAction> {
+        let mut actions = self.actions.lock().unwrap();
+        match actions.get(id) {
+            Some(action) if action.owner != *owner => {
+                return Err(not_found(id));
+            }
+            None => return Err(not_found(id)),
+            _ => {}
+        }
+
+        let action = actions.remove(id).unwrap();
+        if action.pending.expires_at <= Utc::now() {
+            return Err(ToolError::new(
+                ErrorCode::Expired,
+                format!("Pending action {} expired", id),
+            )
+            .with_hint("Ask the user again, the action has to be redone")
+            .into());
+        }
+        Ok(action)
+    }
+
+    pub fn list(&self, owner: &Option<String>) -> Vec<PendingAction> {
+        let now = Utc::now();
+        self.actions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|a| a.owner == *owner && a.pending.expires_at > now)
+            .map(|a| a.pending.clone())
+            .collect()
+    }
+
+    pub async fn confirm(
+        &self,
+        id: &str,
+        owner: &Option<String>,
+    ) -> Result<String> {
+        let action = self.take(id, owner)?;
+        // confirming from a dry run keeps the dry run's paper signer
+        let signer = match SignerContext::try_current() {
+            Some(current) if current.is_paper() => current,
+            _ => action.signer,
+        };
+        SignerContext::with_signer(signer, (action.run)()).await
+    }
+
+    pub fn reject(
+        &self,
+        id: &str,
+        owner: &Option<String>,
+    ) -> Result<PendingAction> {
+        Ok(self.take(id, owner)?.pending)
+    }
+
+    /// Removes every action of `owner`, returns the ones not expired yet
+    pub fn reject_all(&self, owner: &Option<String>) -> Vec<PendingAction> {
+        let now = Utc::now();
+        let mut actions = self.actions.lock().unwrap();
+        let ids: Vec<String> = actions
+            .iter()
+            .filter(|(_, action)| action.owner == *owner)
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.iter()
+            .filter_map(|id| actions.remove(id))
+            .map(|action| action.pending)
+            .filter(|pending| pending.expires_at > now)
+            .collect()
+    }
+}
+
+fn not_found(id: &str) -> anyhow::Error {
+    ToolError::not_found(format!("Pending action {} not found", id))
+        .with_hint("List the pending actions, it may have been confirmed or rejected already")
+        .into()
+}
+
+tokio::task_local! {
+    static PRE_CONFIRMED: bool;
+}
+
+/// Runs `f` with confirmations skipped, for actions the user already agreed
+/// to ahead of time like scheduled jobs
+pub async fn preconfirmed<T>(
+    f: impl Future<Output = Result<T>> + Send,
+) -> Result<T> {
+    PRE_CONFIRMED.scope(true, f).await
+}
+
+/// Simulates value-moving actions under a paper signer, the action runs
+/// again for real once confirmed
+async fn preview<F, Fut>(
+    tool: &str,
+    signer: &Arc<dyn TransactionSigner>,
+    action: F,
+) -> Preview
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<String>> + Send,
+{
+    if !moves_value(tool) || config().features.skip_balance_preview {
+        return (vec![], None);
+    }
+    match balance_preview(signer.clone(), action()).await {
+        Ok(balance_changes) => (balance_changes, None),
+        Err(e) => {
+            tracing::warn!(?e, tool, "pending action not simulated");
+            (vec![], Some(e.to_string()))
+        }
+    }
+}
+
+/// Runs `action` right away when the policy allows it, otherwise parks it
+/// and returns the pending action for the LLM to present to the user
+pub async fn confirm_or_execute<F, Fut>(
+    tool: &str,
+    summary: String,
+    action: F,
+) -> Result<String>
+where
+    F: FnOnce() -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Result<String>> + Send + 'static,
+{
+    let skip = ConfirmationPolicy::from_env() == ConfirmationPolicy::Never
+        || PRE_CONFIRMED
+            .try_with(|confirmed| *confirmed)
+            .unwrap_or(false);
+    if skip && !ALWAYS_CONFIRMED_TOOLS.contains(&tool) {
+        return action().await;
+    }
+
+    let signer = SignerContext::current().await;
+    let user_id = signer.user_id();
+    let preview = preview(tool, &signer, action.clone()).await;
+    let pending = CONFIRMATIONS.insert(
+        tool,
+        summary,
+        preview,
+        signer,
+        Box::new(move || Box::pin(action()) as ActionFuture),
+    );
+    NOTIFIER.spawn_notify(
+        Notification::new(
+            NotificationKind::ConfirmationRequired,
+            user_id,
+            format!("Confirm {}", tool),
+            pending.summary.clone(),
+        )
+        .with_data(json!(pending)),
+    );
+
+    Ok(json!({
+        "status": "confirmation_required",
+        "action": pending,
+        "message": "Nothing was executed yet. Show the summary and the balance changes to the user and call confirm_action with the id only after they explicitly agree, otherwise call reject_action",
+    })
+    .to_string())
+}
+
+/// Whether `result` of `confirm_or_execute` is a parked action rather than
+/// the output of the executed one
+pub fn is_pending(result: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(result)
+        .is_ok_and(|result| result["status"] == "confirmation_required")
+}
+
+async fn resolve_id(id: String) -> Result<String> {
+    match id.as_str() {
+        LAST => last_pending_action_id().await,
+        _ => Ok(id),
+    }
+}
+
+async fn current_owner() -> Option<String> {
+    SignerContext::current().await.user_id()
+}
+
+#[tool(description = "
+Executes a pending action (swap, transfer, bridge..) previously returned with
+status confirmation_required. Only call this after the user explicitly
+confirmed the summary of the action.
+
+id can be \"last\" to confirm the most recent pending action
+")]
+pub async fn confirm_action(id: String) -> Result<String> {
+    let id = resolve_id(id).await?;
+    CONFIRMATIONS.confirm(&id, &current_owner().await).await
+}
+
+#[tool(description = "
+Discards a pending action the user did not agree to, id can be \"last\"
+")]
+pub async fn reject_action(id: String) -> Result<String> {
+    let id = resolve_id(id).await?;
+    let pending = CONFIRMATIONS.reject(&id, &current_owner().await)?;
+    Ok(format!("Rejected: {}", pending.summary))
+}
+
+#[tool(description = "
+Lists the actions waiting for the user's confirmation
+")]
+pub async fn list_pending_actions() -> Result<Vec<PendingAction>> {
+    Ok(CONFIRMATIONS.list(&current_owner().await))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestSigner(&'static str);
+
+    impl TransactionSigner for TestSigner {
+        fn user_id(&self) -> Option<String> {
+            Some(self.0.to_string())
+        }
+    }
+
+    fn store_action(store: &ConfirmationStore, user: &'static str) -> String {
+        store
+            .insert(
+                "transfer_sol",
+                "Transfer 1 SOL".to_string(),
+                (vec![], None),
+                Arc::new(TestSigner(user)),
+                Box::new(|| Box::pin(async { Ok("sig".to_string()) })),
+            )
+            .id
+    }
+
+    #[tokio::test]
+    async fn test_confirm_runs_action_once() {
+        let store = ConfirmationStore::new(Duration::seconds(60));
+        let id = store_action(&store, "alice");
+        let owner = Some("alice".to_string());
+
+        assert_eq!(store.list(&owner).len(), 1);
+        assert_eq!(store.confirm(&id, &owner).await.unwrap(), "sig");
+        assert!(store.confirm(&id, &owner).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_other_user_cannot_confirm() {
+        let store = ConfirmationStore::new(Duration::seconds(60));
+        let id = store_action(&store, "alice");
+
+        let bob = Some("bob".to_string());
+        assert!(store.confirm(&id, &bob).await.is_err());
+        assert!(store.list(&bob).is_empty());
+        assert!(store.reject(&id, &Some("alice".to_string())).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_expired_action_is_not_executed() {
+        let store = ConfirmationStore::new(Duration::seconds(-1));
+        let id = store_action(&store, "alice");
+
+        let err = store
+            .confirm(&id, &Some("alice".to_string()))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("expired"));
+    }
+
+    #[tokio::test]
+    async fn test_savings_moves_are_always_confirmed() {
+        let response = SignerContext::with_signer(
+            Arc::new(TestSigner("carol")),
+            preconfirmed(confirm_or_execute(
+                "move_to_savings",
+                "Move 1 SOL to savings".to_string(),
+                || async { Ok("sig".to_string()) },
+            )),
+        )
+        .await
+        .unwrap();
+        assert!(response.contains("confirmation_required"));
+        assert_eq!(CONFIRMATIONS.list(&Some("carol".to_string())).len(), 1);
+    }
+}
@@ -0,0 +1,357 @@
+//! Confirmation of the transactions on chain. The signers return the
+//! signature or hash as soon as the transaction is sent, `await_confirmation`
+//! polls until it reached a commitment, failed or the timeout passed:
+//! Solana through the signature statuses, EVM chains through the receipts.
+//! EVM chains have no commitment levels, processed and confirmed mean the
+//! transaction has a receipt and finalized that it is `EVM_FINALITY_DEPTH`
+//! blocks deep. The outbox and the activity feed look the transactions up
+//! through here too
+use std::future::Future;
+use std::str::FromStr;
+use std::time::Duration;
+
+use alloy::primitives::B256;
+use alloy::providers::Provider;
+use anyhow::Result;
+use rig_tool_macro::tool;
+use serde::Serialize;
+use solana_sdk::commitment_config::{CommitmentConfig, CommitmentLevel};
+use solana_sdk::signature::Signature;
+use solana_transaction_status::TransactionStatus;
+use tokio::time::Instant;
+
+use crate::common::solana_rpc;
+use crate::error::Error;
+use crate::evm::util::{make_provider, make_provider_for_chain, EvmProvider};
+
+const SOLANA_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const EVM_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Blocks on top of a transaction before it counts as finalized
+const EVM_FINALITY_DEPTH: u64 = 64;
+/// How long `get_transaction_status` waits for the confirmation
+const TOOL_WAIT: Duration = Duration::from_secs(30);
+
+/// Where a transaction stands, what the tools tell the user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TxStatus {
+    /// not seen yet or below the commitment
+    Pending,
+    /// at the commitment, in the slot (Solana) or block (EVM)
+    Confirmed { block: u64 },
+    /// landed and failed, or reverted
+    Failed { block: Option<u64>, error: String },
+    /// not at the commitment before the timeout, it may still land
+    TimedOut,
+}
+
+impl TxStatus {
+    /// Confirmed or failed, polling again won't change it
+    pub fn is_final(&self) -> bool {
+        matches!(self, Self::Confirmed { .. } | Self::Failed { .. })
+    }
+}
+
+fn solana_tx_status(
+    status: Option<TransactionStatus>,
+    commitment: CommitmentLevel,
+) -> TxStatus {
+    match status {
+        None => TxStatus::Pending,
+        Some(status) if status.err.is_some() => TxStatus::Failed {
+            block: Some(status.slot),
+            error: status.err.map(|e| e.to_string()).unwrap_or_default(),
+        },
+        Some(status)
+            if status
+                .satisfies_commitment(CommitmentConfig { commitment }) =>
+        {
+            TxStatus::Confirmed { block: status.slot }
+        }
+        Some(_) => TxStatus::Pending,
+    }
+}
+
+/// `receipt` is the block and the success of the receipt, `head` the
+/// latest block
+fn evm_tx_status(
+    receipt: Option<(u64, bool)>,
+    head: u64,
+    commitment: CommitmentLevel,
+) -> TxStatus {
+    match receipt {
+        None => TxStatus::Pending,
+        Some((block, false)) => TxStatus::Failed {
+            block: Some(block),
+            error: "reverted".to_string(),
+        },
+        Some((block, true))
+            if commitment == CommitmentLevel::Finalized
+                && head.saturating_sub(block) < EVM_FINALITY_DEPTH =>
+        {
+            TxStatus::Pending
+        }
+        Some((block, true)) => TxStatus::Confirmed { block },
+    }
+}
+
+pub async fn solana_status(
+    signature: &Signature,
+    commitment: CommitmentLevel,
+) -> Result<TxStatus> {
+    let statuses = solana_rpc()
+        .get_signature_statuses_with_history(&[*signature])
+        .await
+        .map_err(Error::from)?;
+    let status = statuses.value.into_iter().flatten().next();
+    Ok(solana_tx_status(status, commitment))
+}
+
+pub async fn evm_status(
+    provider: &EvmProvider,
+    hash: B256,
+    commitment: CommitmentLevel,
+) -> Result<TxStatus> {
+    let Some(receipt) = provider.get_transaction_receipt(hash).await? else {
+        return Ok(TxStatus::Pending);
+    };
+    let block = receipt.block_number.unwrap_or_default();
+    let head = match commitment {
+        CommitmentLevel::Finalized => provider.get_block_number().await?,
+        _ => block,
+    };
+    Ok(evm_tx_status(
+        Some((block, receipt.status())),
+        head,
+        commitment,
+    ))
+}
+
+/// A transaction to look up, parsed once so polling only retries the RPC
+enum Lookup {
+    Solana(Signature),
+    Evm(EvmProvider, B256),
+}
+
+impl Lookup {
+    fn new(hash: &str, chain_id: Option<u64>) -> Result<Self> {
+        if !hash.starts_with("0x") {
+            let signature = Signature::from_str(hash).map_err(|_| {
+                Error::UserInput(format!("{} is not a transaction", hash))
+            })?;
+            return Ok(Self::Solana(signature));
+        }
+        let hash = B256::from_str(hash).map_err(|_| {
+            Error::UserInput(format!("{} is not a hash", hash))
+        })?;
+        let provider = match chain_id {
+            Some(chain_id) => make_provider_for_chain(chain_id)?,
+            None => make_provider()?,
+        };
+        Ok(Self::Evm(provider, hash))
+    }
+
+    async fn status(&self, commitment: CommitmentLevel) -> Result<TxStatus> {
+        match self {
+            Self::Solana(signature) => {
+                solana_status(signature, commitment).await
+            }
+            Self::Evm(provider, hash) => {
+                evm_status(provider, *hash, commitment).await
+            }
+        }
+    }
+
+    async fn await_status(
+        &self,
+        commitment: CommitmentLevel,
+        timeout: Duration,
+    ) -> TxStatus {
+        let interval = match self {
+            Self::Solana(_) => SOLANA_POLL_INTERVAL,
+            Self::Evm(..) => EVM_POLL_INTERVAL,
+        };
+        poll_status(interval, timeout, || self.status(commitment)).await
+    }
+}
+
+/// Polls `status` until it is final, `TxStatus::TimedOut` once `timeout`
+/// passed. A failed poll is retried until then, the RPC may recover
+async fn poll_status<F, Fut>(
+    interval: Duration,
+    timeout: Duration,
+    mut status: F,
+) -> TxStatus
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<TxStatus>>,
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        match status().await {
+            Ok(status) if status.is_final() => return status,
+            Ok(_) => {}
+            Err(e) => tracing::warn!(?e, "transaction status not fetched"),
+        }
+        if Instant::now() + interval > deadline {
+            return TxStatus::TimedOut;
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Status of `hash`, a Solana signature or a 0x hash on the EVM chain of
+/// `chain_id` (the default one when None)
+pub async fn tx_status(
+    hash: &str,
+    chain_id: Option<u64>,
+    commitment: CommitmentLevel,
+) -> Result<TxStatus> {
+    Lookup::new(hash, chain_id)?.status(commitment).await
+}
+
+/// Polls the status of `hash` until it reaches `commitment` or failed,
+/// `TxStatus::TimedOut` once `timeout` passed. EVM hashes are looked up on
+/// the default chain
+pub async fn await_confirmation(
+    hash: &str,
+    commitment: CommitmentLevel,
+    timeout: Duration,
+) -> Result<TxStatus> {
+    await_confirmation_on(hash, None, commitment, timeout).await
+}
+
+/// `await_confirmation` on the EVM chain of `chain_id`, only a hash that
+/// is not one fails
+pub async fn await_confirmation_on(
+    hash: &str,
+    chain_id: Option<u64>,
+    commitment: CommitmentLevel,
+    timeout: Duration,
+) -> Result<TxStatus> {
+    let lookup = Lookup::new(hash, chain_id)?;
+    Ok(lookup.await_status(commitment, timeout).await)
+}
+
+/// `await_confirmation` of `hash` through `provider`, for transactions
+/// sent to an RPC of their own
+pub async fn await_evm_confirmation(
+    provider: EvmProvider,
+    hash: B256,
+    commitment: CommitmentLevel,
+    timeout: Duration,
+) -> TxStatus {
+    Lookup::Evm(provider, hash)
+        .await_status(commitment, timeout)
+        .await
+}
+
+#[tool(description = "
+Status of a transaction the agent sent: confirmed, failed, pending or
+timed_out. Waits up to 30 seconds for it to be confirmed. hash is the Solana
+signature or the 0x hash of an EVM transaction, chain_id the EVM chain id of
+the transaction or 0 for the default chain, it is ignored for Solana
+")]
+pub async fn get_transaction_status(
+    hash: String,
+    chain_id: u64,
+) -> Result<TxStatus> {
+    let chain_id = (chain_id != 0).then_some(chain_id);
+    await_confirmation_on(
+        hash.trim(),
+        chain_id,
+        CommitmentLevel::Confirmed,
+        TOOL_WAIT,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_transaction_status::TransactionConfirmationStatus;
+
+    use super::*;
+
+    #[test]
+    fn test_tx_status() {
+        let status = |confirmation_status, err| TransactionStatus {
+            slot: 7,
+            confirmations: None,
+            status: Ok(()),
+            err,
+            confirmation_status: Some(confirmation_status),
+        };
+        let processed =
+            status(TransactionConfirmationStatus::Processed, None);
+        assert_eq!(
+            solana_tx_status(
+                Some(processed.clone()),
+                CommitmentLevel::Processed
+            ),
+            TxStatus::Confirmed { block: 7 }
+        );
+        assert_eq!(
+            solana_tx_status(Some(processed), CommitmentLevel::Confirmed),
+            TxStatus::Pending
+        );
+        let failed = status(
+            TransactionConfirmationStatus::Confirmed,
+            Some(solana_sdk::transaction::TransactionError::AccountNotFound),
+        );
+        assert!(matches!(
+            solana_tx_status(Some(failed), CommitmentLevel::Confirmed),
+            TxStatus::Failed { block: Some(7), .. }
+        ));
+        assert_eq!(
+            solana_tx_status(None, CommitmentLevel::Processed),
+            TxStatus::Pending
+        );
+
+        let finalized = CommitmentLevel::Finalized;
+        assert_eq!(
+            evm_tx_status(Some((100, true)), 100, CommitmentLevel::Confirmed),
+            TxStatus::Confirmed { block: 100 }
+        );
+        assert_eq!(
+            evm_tx_status(Some((100, true)), 120, finalized),
+            TxStatus::Pending
+        );
+        assert_eq!(
+            evm_tx_status(Some((100, true)), 164, finalized),
+            TxStatus::Confirmed { block: 100 }
+        );
+        assert!(matches!(
+            evm_tx_status(Some((100, false)), 100, finalized),
+            TxStatus::Failed { .. }
+        ));
+        assert!(TxStatus::Confirmed { block: 1 }.is_final());
+        assert!(!TxStatus::TimedOut.is_final());
+    }
+
+    #[tokio::test]
+    async fn test_poll_status_retries_failed_polls() {
+        let interval = Duration::from_millis(1);
+        let mut polls = 0;
+        let status = poll_status(interval, Duration::from_secs(5), || {
+            polls += 1;
+            let polled = polls;
+            async move {
+                match polled {
+                    1 => Err(anyhow::anyhow!("429 Too Many Requests")),
+                    2 => Ok(TxStatus::Pending),
+                    _ => Ok(TxStatus::Confirmed { block: 3 }),
+                }
+            }
+        })
+        .await;
+        assert_eq!(status, TxStatus::Confirmed { block: 3 });
+        assert_eq!(polls, 3);
+
+        let status =
+            poll_status(interval, Duration::from_millis(5), || async {
+                Err(anyhow::anyhow!("connection refused"))
+            })
+            .await;
+        assert_eq!(status, TxStatus::TimedOut);
+    }
+}
@@ -0,0 +1,466 @@
+//! What executing trades cost the user over a period, for operators and
+//! users alike: network and priority fees, Jito tips, bridge fees and the
+//! platform fees of the aggregators. The transactions are the ones the
+//! tools sent according to the audit log, fees and tips are read from
+//! their receipts, bridge and platform fees from the quotes that were
+//! executed. EVM gas is reported as network fees, the priority part isn't
+//! split out. The audit log keeps the last 500 tool calls, costs of the
+//! transactions sent before the oldest one are missing and the report says
+//! since when (`truncated_before`). USD values are the daily close of the
+//! day the fee was paid
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+
+use alloy::primitives::{Address as EvmAddress, B256};
+use alloy::providers::Provider;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use rig_tool_macro::tool;
+use serde::Serialize;
+use serde_json::Value;
+use solana_sdk::signature::Signature;
+
+use crate::address::Address;
+use crate::audit::{audit_user, AuditQuery, ToolCallRecord, AUDIT};
+use crate::chains::{Chain, CHAINS};
+use crate::common::evm_chain;
+use crate::evm::tools::NATIVE_TOKEN;
+use crate::evm::util::make_provider;
+use crate::history::{token_infos, tx_hashes, Period};
+use crate::memory::LAST;
+use crate::signer::SignerContext;
+use crate::solana::balance::{transaction_balances, TransactionBalances};
+use crate::solana::constants::WSOL;
+use crate::solana::transaction::JITO_TIP_ACCOUNTS;
+
+/// Base fee of every signature of a Solana transaction
+pub(crate) const LAMPORTS_PER_SIGNATURE: u64 = 5000;
+const CONCURRENT_FETCHES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CostCategory {
+    /// base fee of Solana, gas on EVM chains
+    Network,
+    Priority,
+    JitoTip,
+    Bridge,
+    Platform,
+}
+
+/// A fee paid for a transaction, before it is priced
+#[derive(Debug, Clone, PartialEq)]
+struct Cost {
+    category: CostCategory,
+    chain: String,
+    tool: String,
+    date: DateTime<Utc>,
+    /// None when the provider only reported the USD value
+    token: Option<String>,
+    /// base units
+    amount: u128,
+    decimals: Option<u8>,
+    usd_value: Option<f64>,
+}
+
+/// Transaction sent by a tool
+#[derive(Debug, Clone, PartialEq)]
+struct Sent {
+    hash: String,
+    tool: String,
+    at: DateTime<Utc>,
+}
+
+/// Fees of a category paid in a token over the period
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CostLine {
+    pub category: CostCategory,
+    pub chain: String,
+    pub token: Option<String>,
+    pub symbol: Option<String>,
+    pub amount: Option<f64>,
+    /// None when the token has no price history
+    pub usd_value: Option<f64>,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CostReport {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub transactions: usize,
+    pub lines: Vec<CostLine>,
+    /// of the fees that could be priced
+    pub total_usd: f64,
+    pub usd_by_tool: BTreeMap<String, f64>,
+    /// costs of the transactions sent before are missing, the audit log
+    /// they are read from only keeps the last tool calls
+    pub truncated_before: Option<DateTime<Utc>>,
+}
+
+/// Fees, priority fees and tips paid by `wallet` in a Solana transaction,
+/// in lamports. Transactions paid for by someone else cost nothing
+fn solana_costs(
+    balances: &TransactionBalances,
+    wallet: &str,
+) -> Vec<(CostCategory, u64)> {
+    if !balances.paid_fee(wallet) {
+        return vec![];
+    }
+    let base =
+        (LAMPORTS_PER_SIGNATURE * balances.signers as u64).min(balances.fee);
+    let mut costs = vec![
+        (CostCategory::Network, base),
+        (CostCategory::Priority, balances.fee - base),
+    ];
+    for tip_account in JITO_TIP_ACCOUNTS {
+        for change in balances.changes(tip_account) {
+            if change.mint == WSOL && change.amount > 0 {
+                costs.push((CostCategory::JitoTip, change.amount as u64));
+            }
+        }
+    }
+    costs.retain(|(_, lamports)| *lamports > 0);
+    costs
+}
+
+/// Bridge and platform fees of an executed quote, read from its summary
+fn quote_costs(
+    summary: &Value,
+    chain: &str,
+    tool: &str,
+    date: DateTime<Utc>,
+) -> Vec<Cost> {
+    let cost = |category, token, amount, usd_value| Cost {
+        category,
+        chain: chain.to_string(),
+        tool: tool.to_string(),
+        date,
+        token,
+        amount,
+        decimals: None,
+        usd_value,
+    };
+    let mut costs = vec![];
+    if let Some(fees_usd) = summary["costs"]["fees_usd"].as_f64() {
+        if fees_usd > 0. {
+            costs.push(cost(CostCategory::Bridge, None, 0, Some(fees_usd)));
+        }
+    }
+    // Jupiter takes the platform fee out of the output token
+    let platform_fee = summary["costs"]["platform_fee"]["amount"]
+        .as_str()
+        .and_then(|amount| amount.parse::<u128>().ok())
+        .filter(|amount| *amount > 0);
+    if let (Some(amount), Some(mint)) =
+        (platform_fee, summary["to"]["mint"].as_str())
+    {
+        costs.push(cost(
+            CostCategory::Platform,
+            Some(mint.to_string()),
+            amount,
+            None,
+        ));
+    }
+    costs
+}
+
+/// Transactions sent and quotes executed by the tools, from the records
+/// of the audit log, oldest first
+fn sent(records: &[ToolCallRecord]) -> (Vec<Sent>, Vec<Cost>) {
+    let mut quotes = HashMap::<String, (String, Value)>::new();
+    let mut last_quote = None;
+    let mut seen = HashSet::new();
+    let mut transactions = vec![];
+    let mut costs = vec![];
+    for record in records {
+        let result = record.result.as_deref().unwrap_or_default();
+        if record.tool.starts_with("quote_") {
+            if let Ok(quote) = serde_json::from_str::<Value>(result) {
+                if let Some(id) = quote["id"].as_str() {
+                    last_quote = Some(id.to_string());
+                    quotes.insert(
+                        id.to_string(),
+                        (record.tool.clone(), quote["summary"].clone()),
+                    );
+                }
+            }
+            continue;
+        }
+        let hashes = tx_hashes(result)
+            .into_iter()
+            .filter(|hash| seen.insert(hash.clone()))
+            .collect::<Vec<_>>();
+        let Some(hash) = hashes.first() else {
+            continue;
+        };
+        if record.tool == "execute_quote" {
+            let id = match record.args["quote_id"].as_str() {
+                Some(LAST) => last_quote.clone(),
+                id => id.map(str::to_string),
+            };
+            if let Some((tool, summary)) = id.and_then(|id| quotes.get(&id)) {
+                let chain = if hash.starts_with("0x") {
+                    evm_chain()
+                } else {
+                    "sol".to_string()
+                };
+                costs.extend(quote_costs(summary, &chain, tool, record.at));
+            }
+        }
+        transactions.extend(hashes.into_iter().map(|hash| Sent {
+            hash,
+            tool: record.tool.clone(),
+            at: record.at,
+        }));
+    }
+    (transactions, costs)
+}
+
+async fn solana_fees(wallet: &str, sent: &[Sent]) -> Vec<Cost> {
+    let signatures = sent.iter().filter(|sent| !sent.hash.starts_with("0x"));
+    let fetched = stream::iter(signatures)
+        .map(|sent| async move {
+            let signature = Signature::from_str(&sent.hash)?;
+            let balances = transaction_balances(&signature).await?;
+            Ok::<_, anyhow::Error>((sent, balances))
+        })
+        .buffered(CONCURRENT_FETCHES)
+        .collect::<Vec<_>>()
+        .await;
+    let mut costs = vec![];
+    for transaction in fetched {
+        let (sent, balances) = match transaction {
+            Ok(transaction) => transaction,
+            Err(e) => {
+                tracing::warn!(?e, "transaction left out of the costs");
+                continue;
+            }
+        };
+        costs.extend(solana_costs(&balances, wallet).into_iter().map(
+            |(category, lamports)| Cost {
+                category,
+                chain: "sol".to_string(),
+                tool: sent.tool.clone(),
+                date: sent.at,
+                token: Some(WSOL.to_string()),
+                amount: lamports.into(),
+                decimals: Some(9),
+                usd_value: None,
+            },
+        ));
+    }
+    costs
+}
+
+async fn evm_fees(wallet: &str, sent: &[Sent]) -> Result<Vec<Cost>> {
+    let wallet = EvmAddress::from_str(wallet)?;
+    let provider = make_provider()?;
+    let chain = evm_chain();
+    let mut costs = vec![];
+    for sent in sent.iter().filter(|sent| sent.hash.starts_with("0x")) {
+        let hash = &sent.hash;
+        let receipt = match provider
+            .get_transaction_receipt(B256::from_str(hash)?)
+            .await
+        {
+            Ok(Some(receipt)) => receipt,
+            // sent on another chain (bridges) or dropped
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::warn!(?e, %hash, "left out of the costs");
+                continue;
+            }
+        };
+        if receipt.from != wallet {
+            continue;
+        }
+        costs.push(Cost {
+            category: CostCategory::Network,
+            chain: chain.clone(),
+            tool: sent.tool.clone(),
+            date: sent.at,
+            token: Some(NATIVE_TOKEN.to_string()),
+            amount: u128::from(receipt.gas_used)
+                * receipt.effective_gas_price,
+            decimals: Some(18),
+            usd_value: None,
+        });
+    }
+    Ok(costs)
+}
+
+/// Prices the costs and sums them up by category, chain and token
+async fn summarize(
+    costs: Vec<Cost>,
+) -> (Vec<CostLine>, f64, BTreeMap<String, f64>) {
+    let tokens = token_infos(
+        costs.iter().filter_map(|cost| {
+            Some((cost.chain.clone(), cost.token.clone()?))
+        }),
+        costs.iter().map(|cost| cost.date).min(),
+    )
+    .await;
+    let mut lines = BTreeMap::<_, CostLine>::new();
+    let mut usd_by_tool = BTreeMap::<String, f64>::new();
+    for cost in costs {
+        let info = cost.token.as_ref().and_then(|token| {
+            tokens.get(&(cost.chain.clone(), token.clone()))
+        });
+        let amount = cost.token.as_ref().map(|_| {
+            let decimals = cost
+                .decimals
+                .or(info.and_then(|info| info.decimals))
+                .unwrap_or(0);
+            cost.amount as f64 / 10f64.powi(decimals as i32)
+        });
+        let usd_value = cost
+            .usd_value
+            .or_else(|| Some(amount? * info?.price_at(cost.date)?));
+        let line = lines
+            .entry((cost.category, cost.chain.clone(), cost.token.clone()))
+            .or_insert_with(|| CostLine {
+                category: cost.category,
+                chain: cost.chain.clone(),
+                token: cost.token.clone(),
+                symbol: info.and_then(|info| info.symbol.clone()),
+                amount: amount.map(|_| 0.),
+                usd_value: None,
+                count: 0,
+            });
+        line.count += 1;
+        line.amount = line.amount.zip(amount).map(|(a, b)| a + b);
+        if let Some(usd_value) = usd_value {
+            line.usd_value = Some(line.usd_value.unwrap_or(0.) + usd_value);
+            *usd_by_tool.entry(cost.tool).or_default() += usd_value;
+        }
+    }
+    let total_usd = usd_by_tool.values().sum();
+    (lines.into_values().collect(), total_usd, usd_by_tool)
+}
+
+/// Execution costs of the current signer over `period`
+pub async fn cost_report(period: &Period) -> Result<CostReport> {
+    let signer = SignerContext::current().await;
+    let user = audit_user(signer.as_ref());
+    let truncated_before =
+        period.truncated_by(AUDIT.complete_since(&user).await?);
+    let mut records = AUDIT
+        .query(
+            &user,
+            &AuditQuery {
+                since: period.since,
+                ..Default::default()
+            },
+        )
+        .await?;
+    records.retain(|record| {
+        !record.paper && record.error.is_none() && period.contains(record.at)
+    });
+    records.sort_by_key(|record| record.at);
+    let (transactions, mut costs) = sent(&records);
+
+    if CHAINS.is_enabled(Chain::Solana) {
+        let owner = Address::of_signer(signer.as_ref(), Chain::Solana)?;
+        costs.extend(solana_fees(&owner.to_string(), &transactions).await);
+    }
+    if CHAINS.is_enabled(Chain::Evm) {
+        let owner = Address::of_signer(signer.as_ref(), Chain::Evm)?;
+        costs.extend(evm_fees(&owner.to_string(), &transactions).await?);
+    }
+    let (lines, total_usd, usd_by_tool) = summarize(costs).await;
+    Ok(CostReport {
+        since: period.since,
+        until: period.until,
+        transactions: transactions.len(),
+        lines,
+        total_usd,
+        usd_by_tool,
+        truncated_before,
+    })
+}
+
+#[tool(description = "
+Summarizes what executing transactions cost the user over a period: network
+and priority fees, Jito tips, bridge fees and aggregator platform fees, per
+chain and token with their USD value, and the USD total by tool. Costs of
+transactions sent before truncated_before are missing, tell the user when it
+is set.
+
+period is 7d, 30d (any number of days), ytd, a year like 2024 or all
+")]
+pub async fn get_cost_report(period: String) -> Result<CostReport> {
+    cost_report(&Period::from_str(&period)?).await
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const WALLET: &str = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+
+    #[test]
+    fn test_solana_costs() {
+        let tip = JITO_TIP_ACCOUNTS[2];
+        let balances = TransactionBalances {
+            keys: vec![WALLET.to_string(), tip.to_string()],
+            signers: 1,
+            fee: 15_000,
+            pre_lamports: vec![1_000_000, 500],
+            post_lamports: vec![935_000, 50_500],
+            ..Default::default()
+        };
+        assert_eq!(
+            solana_costs(&balances, WALLET),
+            [
+                (CostCategory::Network, 5000),
+                (CostCategory::Priority, 10_000),
+                (CostCategory::JitoTip, 50_000),
+            ]
+        );
+        assert!(solana_costs(&balances, tip).is_empty());
+    }
+
+    #[test]
+    fn test_executed_quotes() {
+        let record =
+            |tool: &str, args: Value, result: String| ToolCallRecord {
+                id: String::new(),
+                tool: tool.to_string(),
+                args,
+                result: Some(result),
+                error: None,
+                duration_ms: 0,
+                wallet: None,
+                session_id: None,
+                paper: false,
+                at: Utc::now(),
+            };
+        let hash = format!("0x{}", "ab".repeat(32));
+        let quote = json!({
+            "id": "q1",
+            "venue": "lifi",
+            "summary": { "costs": { "gas_usd": 0.2, "fees_usd": 1.5 } },
+        });
+        let records = [
+            record("quote_multichain_swap", json!({}), quote.to_string()),
+            record(
+                "execute_quote",
+                json!({ "quote_id": "last" }),
+                hash.clone(),
+            ),
+            // the same transaction mentioned again isn't counted twice
+            record("get_transaction", json!({}), hash.clone()),
+        ];
+        let (transactions, costs) = sent(&records);
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].tool, "execute_quote");
+        assert_eq!(costs.len(), 1);
+        assert_eq!(costs[0].category, CostCategory::Bridge);
+        assert_eq!(costs[0].tool, "quote_multichain_swap");
+        assert_eq!(costs[0].usd_value, Some(1.5));
+    }
+}
@@ -3,24 +3,29 @@ use rig::agent::Agent;
 use rig::providers::anthropic::completion::CompletionModel as AnthropicCompletionModel;
 
 use crate::{
-    common::{claude_agent_builder, PREAMBLE_COMMON},
+    agent::{AgentBuilder, AgentKind},
+    common::{register_common_tools, ToolRegistry},
     cross_chain::tools::{
-        ApproveToken, CheckApproval, GetMultichainQuote, MultichainSwap,
+        ApproveToken, CheckApproval, GetMultichainQuote, QuoteMultichainSwap,
     },
     dexscreener::tools::SearchOnDexScreener,
+    quotes::ExecuteQuote,
 };
 
+pub fn register_cross_chain_tools<R: ToolRegistry>(registry: R) -> R {
+    register_common_tools(
+        registry
+            .tool(SearchOnDexScreener)
+            .tool(GetMultichainQuote)
+            .tool(QuoteMultichainSwap)
+            .tool(ExecuteQuote)
+            .tool(ApproveToken)
+            .tool(CheckApproval),
+    )
+}
+
+/// See `AgentBuilder` for an agent with other tools or another model
 pub async fn create_cross_chain_agent(
 ) -> Result<Agent<AnthropicCompletionModel>> {
-    Ok(claude_agent_builder()
-        .preamble(&format!(
-            "{} {}",
-            "you are a cross-chain trading agent", PREAMBLE_COMMON,
-        ))
-        .tool(SearchOnDexScreener)
-        .tool(GetMultichainQuote)
-        .tool(MultichainSwap)
-        .tool(ApproveToken)
-        .tool(CheckApproval)
-        .build())
+    AgentBuilder::new(AgentKind::CrossChain).build()
 }
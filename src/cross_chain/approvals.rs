@@ -1,9 +1,23 @@
 use anyhow::Result;
 
+use crate::common::http_client;
+use crate::evm::util::rpc_url_for_chain;
+
 pub async fn get_allowance(
     token_address: &str,
     owner_address: &str,
     spender_address: &str,
+) -> Result<u128> {
+    get_allowance_on(None, token_address, owner_address, spender_address)
+        .await
+}
+
+/// Allowance on the chain id, ETHEREUM_RPC_URL without one
+pub async fn get_allowance_on(
+    chain_id: Option<u64>,
+    token_address: &str,
+    owner_address: &str,
+    spender_address: &str,
 ) -> Result<u128> {
     // Construct the allowance function call data
     let allowance_data = format!(
@@ -24,9 +38,9 @@ pub async fn get_allowance(
     });
 
     // Make the RPC call
-    let client = reqwest::Client::new();
+    let client = http_client();
     let res = client
-        .post(std::env::var("ETHEREUM_RPC_URL")?)
+        .post(rpc_url_for_chain(chain_id)?)
         .json(&rpc_request)
         .send()
         .await?;
@@ -3,6 +3,11 @@ use serde::{Deserialize, Serialize};
 
 use anyhow::Result;
 
+use crate::common::http_client;
+use crate::error::Error;
+use crate::http::client::{send, Retry};
+use crate::metrics::observe_api;
+
 const BASE_URL: &str = "https://li.quest/v1";
 
 pub struct LiFiClient {
@@ -13,7 +18,7 @@ pub struct LiFiClient {
 impl LiFiClient {
     pub fn new(api_key: Option<String>) -> Self {
         Self {
-            client: Client::new(),
+            client: http_client(),
             api_key,
         }
     }
@@ -30,17 +35,22 @@ impl LiFiClient {
             request = request.header("x-lifi-api-key", api_key);
         }
 
-        let response = request.query(params).send().await?;
-        let status = response.status();
-        tracing::info!(?status, "GET {}", endpoint);
-        if !status.is_success() {
-            return Err(anyhow::anyhow!(
-                "Request failed with status code {}, {}",
-                status,
-                response.text().await?
-            ));
-        }
-        let res: serde_json::Value = response.json().await?;
+        let res: serde_json::Value = observe_api("lifi", async {
+            let response =
+                send(request.query(params), Retry::Idempotent).await?;
+            let status = response.status();
+            tracing::info!(?status, "GET {}", endpoint);
+            if !status.is_success() {
+                return Err(Error::quote(format!(
+                    "Request failed with status code {}, {}",
+                    status,
+                    response.text().await?
+                ))
+                .into());
+            }
+            Ok(response.json().await?)
+        })
+        .await?;
         // TODO remove this later
         tracing::info!("{:#?}", res);
 
@@ -60,16 +70,22 @@ impl LiFiClient {
             request = request.header("x-lifi-api-key", api_key);
         }
 
-        let response = request.json(body).send().await?;
-        let status = response.status();
-        if !status.is_success() {
-            return Err(anyhow::anyhow!(
-                "Request failed with status code {}, {}",
-                status,
-                response.text().await?
-            ));
-        }
-        tracing::info!(?status, "POST {}", endpoint);
-        Ok(response.json().await?)
+        observe_api("lifi", async {
+            // the POST endpoints of LiFi are quotes and routes
+            let response =
+                send(request.json(body), Retry::Idempotent).await?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(Error::quote(format!(
+                    "Request failed with status code {}, {}",
+                    status,
+                    response.text().await?
+                ))
+                .into());
+            }
+            tracing::info!(?status, "POST {}", endpoint);
+            Ok(response.json().await?)
+        })
+        .await
     }
 }
@@ -1,12 +1,14 @@
 mod chains;
 mod client;
 mod connections;
-mod quote;
+pub mod quote;
 mod tokens;
 mod tools;
 
 use anyhow::Result;
 
+use crate::config::config;
+
 use chains::ChainsResponse;
 use client::LiFiClient;
 use connections::ConnectionsResponse;
@@ -97,9 +99,14 @@ impl LiFi {
         from_address: &str,
         to_address: &str,
         from_amount_with_decimals: &str,
+        slippage_bps: u16,
     ) -> Result<QuoteResponse> {
         let order = Order::Fastest.to_string();
-        let params = vec![
+        // LiFi takes the slippage as a fraction, 0.005 is 50 bps
+        let slippage = (slippage_bps as f64 / 10_000.).to_string();
+        let revenue = config().revenue.clone();
+        let fee = revenue.lifi_fee.to_string();
+        let mut params = vec![
             ("fromChain", from_chain),
             ("toChain", to_chain),
             ("fromToken", from_token),
@@ -108,7 +115,14 @@ impl LiFi {
             ("toAddress", to_address),
             ("fromAmount", from_amount_with_decimals),
             ("order", &order),
+            ("slippage", &slippage),
         ];
+        if let Some(integrator) = &revenue.lifi_integrator {
+            params.push(("integrator", integrator.as_str()));
+            if revenue.lifi_fee > 0.0 {
+                params.push(("fee", &fee));
+            }
+        }
 
         self.client.get("/quote", &params).await
     }
@@ -181,6 +195,7 @@ mod tests {
                 "aiamaErRMjbeNmf2b8BMZWFR3ofxrnZEf2mLKp935fM",
                 "0x2fAA30d5EdDF1e4fa126aEdA79159878D58A2438",
                 "1000000000",
+                50,
             )
             .await;
         assert!(quote.is_ok(), "{:?}", quote);
@@ -198,6 +213,7 @@ mod tests {
                 "0x2fAA30d5EdDF1e4fa126aEdA79159878D58A2438",
                 "aiamaErRMjbeNmf2b8BMZWFR3ofxrnZEf2mLKp935fM",
                 "1000000000",
+                50,
             )
             .await;
         assert!(quote.is_ok(), "{:?}", quote);
@@ -2,6 +2,8 @@ use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::{Number, Value};
 
+use crate::chains::Chain;
+
 #[allow(dead_code)]
 pub enum Order {
     Fastest,
@@ -127,6 +129,15 @@ impl TransactionRequest {
         !self.is_evm()
     }
 
+    /// Chain family the transaction is signed on
+    pub fn chain(&self) -> Chain {
+        if self.is_evm() {
+            Chain::Evm
+        } else {
+            Chain::Solana
+        }
+    }
+
     /// Converts the transaction request to a JSON-RPC compatible format
     /// Returns None for Solana transactions
     pub fn to_json_rpc(&self) -> Result<serde_json::Value> {
@@ -233,3 +244,24 @@ pub struct IncludedStep {
     pub estimate: Estimate,
     pub data: Option<Value>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::fixture;
+    use crate::snapshot::assert_snapshot;
+
+    #[test]
+    fn test_bridge_snapshot() {
+        let request: TransactionRequest =
+            serde_json::from_value(fixture("lifi/transaction_request"))
+                .unwrap();
+        let request = request.to_json_rpc().unwrap();
+        let rendered: String =
+            ["from", "to", "chainId", "gas", "gasPrice", "value", "data"]
+                .iter()
+                .map(|field| format!("{} {}\n", field, request[field]))
+                .collect();
+        assert_snapshot("lifi_bridge", &rendered);
+    }
+}
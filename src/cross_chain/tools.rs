@@ -1,11 +1,317 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use alloy::primitives::U256;
+use alloy::providers::Provider;
 use anyhow::{anyhow, Result};
 use rig_tool_macro::tool;
+use solana_sdk::commitment_config::CommitmentLevel;
+
+use crate::activity::{self, ActivityEvent};
+use crate::address::Address;
+use crate::cancellation::begin_signing;
+use crate::chains::{Chain, CHAINS};
+use crate::common::{evm_chain, with_explorer_link};
+use crate::confirmation::{
+    await_confirmation_on, confirm_or_execute, is_pending, TxStatus,
+};
+use crate::error::Error;
+use crate::evm::tools::NATIVE_TOKEN;
+use crate::evm::util::make_provider;
+use crate::metrics::record_sign;
+use crate::preferences;
+use crate::progress::{report, Stage};
+use crate::quotes::{cache_quote, Quote};
+use crate::revenue::{record_fee, CollectedFee};
+use crate::risk::{assess, RiskWarning, TradeIntent};
+use crate::signer::{SignerContext, TransactionSigner};
+use crate::spenders::check_spender;
+use crate::validation;
+
+use super::approvals::{
+    create_approval_transaction, get_allowance, get_allowance_on,
+};
+use super::lifi::quote::{QuoteResponse, TransactionRequest};
+use super::lifi::LiFi;
 
-use crate::common::wrap_unsafe;
-use crate::signer::SignerContext;
+/// How long an approval may take to land before the swap pulling the tokens
+/// is built
+const APPROVAL_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Fetches a LiFi quote at the slippage of the session, the current signer
+/// is both the sender and the recipient on the respective chains
+async fn fetch_lifi_quote(
+    from_token_symbol: &str,
+    to_token_symbol: &str,
+    amount: &str,
+    from_chain: &str,
+    to_chain: &str,
+) -> Result<QuoteResponse> {
+    let signer = SignerContext::current().await;
+    let lifi = LiFi::new(None);
+    let slippage_bps = preferences::slippage_bps(0).await?;
+
+    let from_address =
+        Address::of_signer(signer.as_ref(), Chain::of_key(from_chain))?;
+    let to_address =
+        Address::of_signer(signer.as_ref(), Chain::of_key(to_chain))?;
+
+    lifi.get_quote(
+        from_chain,
+        to_chain,
+        from_token_symbol,
+        to_token_symbol,
+        &from_address.to_string(),
+        &to_address.to_string(),
+        amount,
+        slippage_bps,
+    )
+    .await
+    .map_err(|e| {
+        Error::quote(e.to_string().chars().take(300).collect::<String>())
+            .into()
+    })
+}
 
-use super::approvals::{create_approval_transaction, get_allowance};
-use super::lifi::LiFi;
+/// Address of the signer receiving the funds on `to_chain`
+async fn destination(to_chain: &str) -> Result<String> {
+    let signer = SignerContext::current().await;
+    Ok(
+        Address::of_signer(signer.as_ref(), Chain::of_key(to_chain))?
+            .to_string(),
+    )
+}
+
+/// Validates the arguments shared by the multichain tools
+async fn validate_swap(
+    from_token_symbol: &str,
+    to_token_symbol: &str,
+    amount: &str,
+    from_chain: &str,
+    to_chain: &str,
+) -> Result<()> {
+    validation::token(from_chain, "from_token_symbol", from_token_symbol)?;
+    validation::token(to_chain, "to_token_symbol", to_token_symbol)?;
+    validation::raw_amount("amount", amount)?;
+    validation::amount_decimals(from_chain, from_token_symbol, amount).await
+}
+
+/// Allowance an EVM-origin route lacks to pull the tokens it swaps
+#[derive(Clone)]
+struct MissingApproval {
+    chain_id: u64,
+    token: String,
+    spender: String,
+    amount: u128,
+}
+
+/// The approval `quote` needs first, None for native tokens and routes the
+/// allowance of the signer already covers
+async fn missing_approval(
+    quote: &QuoteResponse,
+) -> Result<Option<MissingApproval>> {
+    let token = &quote.action.from_token.address;
+    if token.eq_ignore_ascii_case(NATIVE_TOKEN) {
+        return Ok(None);
+    }
+    // the allowance is read on the chain the route starts on, never on a
+    // default one
+    let chain_id = quote.action.from_chain_id.as_u64().ok_or_else(|| {
+        Error::Quote("The quote has no source chain id".into())
+    })?;
+    let amount =
+        quote.action.from_amount.parse::<u128>().map_err(|_| {
+            Error::Quote("Invalid amount in the quote".into())
+        })?;
+    let spender = &quote.estimate.approval_address;
+    let allowance = get_allowance_on(
+        Some(chain_id),
+        token,
+        &SignerContext::current().await.address(),
+        spender,
+    )
+    .await?;
+    Ok((allowance < amount).then(|| MissingApproval {
+        chain_id,
+        token: token.clone(),
+        spender: spender.clone(),
+        amount,
+    }))
+}
+
+/// Warnings for the contracts of an EVM-origin `quote` missing from the
+/// registry of known spenders, the approval target and the callee
+fn spender_warnings(quote: &QuoteResponse) -> Vec<RiskWarning> {
+    let Some(request) = &quote.transaction_request else {
+        return vec![];
+    };
+    let Some(chain_id) = request.chain_id.as_ref().and_then(|id| id.as_u64())
+    else {
+        return vec![];
+    };
+    let approval = &quote.estimate.approval_address;
+    let mut contracts = vec![approval];
+    contracts.extend(
+        request
+            .to
+            .iter()
+            .filter(|to| !to.eq_ignore_ascii_case(approval)),
+    );
+    contracts
+        .into_iter()
+        .filter_map(|contract| check_spender(chain_id, contract))
+        .collect()
+}
+
+/// Appends a warning to the summary of an approval of an unknown spender
+fn annotate_spender(summary: String, chain_id: u64, spender: &str) -> String {
+    match check_spender(chain_id, spender) {
+        Some(warning) => format!(
+            "{} (risk warnings: {})",
+            summary,
+            serde_json::json!([warning])
+        ),
+        None => summary,
+    }
+}
+
+/// Parks the approval of `approval` for the user to confirm, it is
+/// executed right away when no confirmation is needed
+async fn request_approval(approval: MissingApproval) -> Result<String> {
+    let description = format!(
+        "Approve {} to spend {} of token {} on chain {}",
+        approval.spender, approval.amount, approval.token, approval.chain_id
+    );
+    let summary = annotate_spender(
+        format!("{}, the swap needs it", description),
+        approval.chain_id,
+        &approval.spender,
+    );
+    confirm_or_execute("approve_token", summary, move || async move {
+        let signer = SignerContext::current().await;
+        let mut transaction = create_approval_transaction(
+            &approval.token,
+            &approval.spender,
+            approval.amount,
+            &signer.address(),
+        )?;
+        transaction["chainId"] = approval.chain_id.into();
+
+        let _signing = begin_signing().await?;
+        let tx_hash = signer
+            .sign_and_send_json_evm_transaction(transaction)
+            .await?;
+        let chain = approval.chain_id.to_string();
+        let event = ActivityEvent::new(
+            signer.as_ref(),
+            &chain,
+            Some(approval.chain_id),
+            &tx_hash,
+        )
+        .with_description(Some(description));
+        activity::record(signer.as_ref(), event).await;
+        // the swap is built right after an approval sent without asking,
+        // it reverts unless the allowance landed first
+        let status = if signer.is_paper() {
+            TxStatus::Confirmed { block: 0 }
+        } else {
+            await_confirmation_on(
+                &tx_hash,
+                Some(approval.chain_id),
+                CommitmentLevel::Confirmed,
+                APPROVAL_TIMEOUT,
+            )
+            .await?
+        };
+        match status {
+            TxStatus::Confirmed { .. } => {}
+            TxStatus::Failed { error, .. } => {
+                return Err(anyhow!(
+                    "The approval {} failed: {}",
+                    tx_hash,
+                    error
+                ))
+            }
+            _ => {
+                return Err(anyhow!(
+                    "The approval {} isn't confirmed yet, nothing was \
+                     swapped. Check it with get_transaction_status before \
+                     calling multichain_swap again",
+                    tx_hash
+                ))
+            }
+        }
+        Ok(format!("Approved {}", with_explorer_link(&chain, &tx_hash)))
+    })
+    .await
+}
+
+/// What the transaction will do, None when it can't be decoded
+async fn explain_request(
+    transaction_request: &TransactionRequest,
+) -> Option<String> {
+    if transaction_request.is_solana() {
+        let explanation =
+            crate::solana::explain::explain(&transaction_request.data).await;
+        return explanation.ok().map(|explanation| explanation.text());
+    }
+    let to = transaction_request.to.as_deref()?.parse().ok()?;
+    let value = match &transaction_request.value {
+        Some(value) => {
+            U256::from_str_radix(value.trim_start_matches("0x"), 16).ok()?
+        }
+        None => U256::ZERO,
+    };
+    let input =
+        hex::decode(transaction_request.data.trim_start_matches("0x"))
+            .ok()?;
+    let chain = transaction_request.chain_id.as_ref()?.to_string();
+    let explanation =
+        crate::evm::explain::explain_call(&chain, Some(to), value, &input);
+    Some(explanation.text())
+}
+
+async fn send_transaction_request(
+    signer: Arc<dyn TransactionSigner>,
+    transaction_request: TransactionRequest,
+) -> Result<String> {
+    CHAINS.require(transaction_request.chain())?;
+    let _signing = begin_signing().await?;
+    let explanation = explain_request(&transaction_request).await;
+    report(Stage::Signing, explanation.clone());
+    let chain_id = transaction_request
+        .chain_id
+        .as_ref()
+        .and_then(|chain_id| chain_id.as_u64());
+    let chain = if transaction_request.is_solana() {
+        "sol".to_string()
+    } else {
+        transaction_request
+            .chain_id
+            .as_ref()
+            .map_or_else(evm_chain, |chain_id| chain_id.to_string())
+    };
+    let started = Instant::now();
+    let hash = if transaction_request.is_solana() {
+        signer
+            .sign_and_send_encoded_solana_transaction(
+                transaction_request.data,
+            )
+            .await?
+    } else {
+        signer
+            .sign_and_send_json_evm_transaction(
+                transaction_request.to_json_rpc()?,
+            )
+            .await?
+    };
+    record_sign(&chain, started.elapsed());
+    report(Stage::Broadcast, Some(hash.clone()));
+    let event = ActivityEvent::new(signer.as_ref(), &chain, chain_id, &hash)
+        .with_description(explanation);
+    activity::record(signer.as_ref(), event).await;
+    Ok(hash)
+}
 
 // TODO support sponsored transactions here
 // it would save a lot of gas if we could drip on any chain,
@@ -26,7 +332,7 @@ the from_token_symbol and to_token_symbol can either be a solana public key, evm
 address or a symbol.
 
 The amount has to be a string to avoid precision loss. The amount is accounting
-for decimals, e.g. 1e6 for 1 USDC but 1e18 for 1 SOL.
+for decimals, e.g. 1000000 for 1 USDC but 1000000000 for 1 SOL.
 
 Note that sometimes the quote will return a transaction request, with an address that might require approval.
 In that case, you can use the approve_token tool to approve the token.
@@ -38,6 +344,8 @@ Supported from_chains:
 Supported to_chains:
 - sol
 - arb
+
+An empty from_chain or to_chain is the default chain of the session
 ")]
 pub async fn get_multichain_quote(
     from_token_symbol: String,
@@ -46,40 +354,108 @@ pub async fn get_multichain_quote(
     from_chain: String,
     to_chain: String,
 ) -> Result<serde_json::Value> {
-    let signer = SignerContext::current().await;
-    let lifi = LiFi::new(None);
+    let from_chain = preferences::chain(&from_chain).await?;
+    let to_chain = preferences::chain(&to_chain).await?;
+    validate_swap(
+        &from_token_symbol,
+        &to_token_symbol,
+        &amount,
+        &from_chain,
+        &to_chain,
+    )
+    .await?;
+    let quote = fetch_lifi_quote(
+        &from_token_symbol,
+        &to_token_symbol,
+        &amount,
+        &from_chain,
+        &to_chain,
+    )
+    .await?;
 
-    let from_address = if from_chain == "sol" {
-        signer.pubkey()
-    } else {
-        signer.address()
-    };
+    Ok(quote.summary())
+}
 
-    let to_address = if to_chain == "sol" {
-        signer.pubkey()
-    } else {
-        signer.address()
-    };
+#[tool(description = "
+Quote a multichain swap (or bridge), this is the first step of executing one.
 
-    let quote = lifi
-        .get_quote(
-            &from_chain,
-            &to_chain,
-            &from_token_symbol,
-            &to_token_symbol,
-            &from_address,
-            &to_address,
-            &amount,
-        )
-        .await
-        .map_err(|e| {
-            anyhow!(
-                "{:#?}",
-                e.to_string().chars().take(300).collect::<String>()
-            )
-        })?;
+This can be used for any swap, solana to solana, evm to evm, solana to evm,
+evm to solana, etc.
 
-    Ok(quote.summary())
+Returns a quote id together with a summary of the amounts, fees and
+slippage. Nothing is executed, show the summary to the user and call
+execute_quote with the id once they agree to it.
+
+from_token_symbol is the symbol of the token to swap from.
+to_token_symbol is the symbol of the token to swap to.
+amount is the amount of tokens to swap.
+
+the from_token_symbol and to_token_symbol can either be a solana public key, evm
+address or a symbol.
+
+The amount has to be a string to avoid precision loss. The amount is accounting
+for decimals, e.g. 1000000 for 1 USDC but 1000000000 for 1 SOL.
+
+Supported from_chains:
+- sol
+- arb
+
+Supported to_chains:
+- sol
+- arb
+
+An empty from_chain or to_chain is the default chain of the session
+")]
+pub async fn quote_multichain_swap(
+    from_token_symbol: String,
+    to_token_symbol: String,
+    amount: String,
+    from_chain: String,
+    to_chain: String,
+) -> Result<Quote> {
+    let from_chain = preferences::chain(&from_chain).await?;
+    let to_chain = preferences::chain(&to_chain).await?;
+    validate_swap(
+        &from_token_symbol,
+        &to_token_symbol,
+        &amount,
+        &from_chain,
+        &to_chain,
+    )
+    .await?;
+    let mut assessment = assess(
+        TradeIntent::new(&from_chain)
+            .spend(&from_token_symbol, &amount)
+            .receive(&to_token_symbol)
+            .destination(&destination(&to_chain).await?),
+    )
+    .await?;
+    let quote = fetch_lifi_quote(
+        &from_token_symbol,
+        &to_token_symbol,
+        &amount,
+        &from_chain,
+        &to_chain,
+    )
+    .await?;
+    assessment.warnings.extend(spender_warnings(&quote));
+
+    let mut summary = quote.summary();
+    summary["risk_warnings"] = serde_json::json!(assessment.warnings);
+    let fee = CollectedFee::lifi(&quote, &from_chain);
+    let transaction_request = quote
+        .transaction_request
+        .ok_or_else(|| Error::Quote("No transaction request".to_string()))?;
+
+    cache_quote("lifi", summary, move || async move {
+        let signer = SignerContext::current().await;
+        let hash =
+            send_transaction_request(signer, transaction_request).await?;
+        record_fee(fee, &hash).await;
+        assessment.record().await;
+        Ok(with_explorer_link(&from_chain, &hash))
+    })
+    .await
 }
 
 #[tool(description = "
@@ -94,12 +470,16 @@ chains, or would like to bridge the tokens
 Don't use this in case you are not certain about all of the params, use the
 get_multichain_quote tool instead to validate the params in that case.
 
+Routes from EVM chains check the allowance first, when it is missing an
+approval is returned to confirm (status approval_required) and the swap has
+to be called again once it is confirmed.
+
 from_token_symbol is the symbol of the token to bridge from.
 to_token_symbol is the symbol of the token to bridge to.
 amount is the amount of tokens to bridge.
 
 The amount has to be a string to avoid precision loss. The amount is accounting
-for decimals, e.g. 1e6 for 1 USDC but 1e18 for 1 SOL.
+for decimals, e.g. 1000000 for 1 USDC but 1000000000 for 1 SOL.
 
 Supported from_chains:
 - sol
@@ -108,6 +488,8 @@ Supported from_chains:
 Supported to_chains:
 - sol
 - arb
+
+An empty from_chain or to_chain is the default chain of the session
 ")]
 pub async fn multichain_swap(
     from_token_symbol: String,
@@ -116,60 +498,85 @@ pub async fn multichain_swap(
     from_chain: String,
     to_chain: String,
 ) -> Result<String> {
-    let signer = SignerContext::current().await;
-    let lifi = LiFi::new(None);
-
-    let from_address = if from_chain == "sol" {
-        signer.pubkey()
-    } else {
-        signer.address()
-    };
-
-    let to_address = if to_chain == "sol" {
-        signer.pubkey()
-    } else {
-        signer.address()
-    };
-
-    let quote = lifi
-        .get_quote(
-            &from_chain,
-            &to_chain,
+    let from_chain = preferences::chain(&from_chain).await?;
+    let to_chain = preferences::chain(&to_chain).await?;
+    validate_swap(
+        &from_token_symbol,
+        &to_token_symbol,
+        &amount,
+        &from_chain,
+        &to_chain,
+    )
+    .await?;
+    let mut assessment = assess(
+        TradeIntent::new(&from_chain)
+            .spend(&from_token_symbol, &amount)
+            .receive(&to_token_symbol)
+            .destination(&destination(&to_chain).await?),
+    )
+    .await?;
+    // routes from EVM chains pull the tokens, the allowance has to be there
+    // before the swap is sent
+    if Chain::of_key(&from_chain) == Chain::Evm {
+        let quote = fetch_lifi_quote(
             &from_token_symbol,
             &to_token_symbol,
-            &from_address,
-            &to_address,
             &amount,
+            &from_chain,
+            &to_chain,
         )
-        .await
-        .map_err(|e| {
-            anyhow!(
-                "{:#?}",
-                e.to_string().chars().take(300).collect::<String>()
-            )
-        })?;
-
-    match quote.transaction_request {
-        Some(transaction_request) => {
-            wrap_unsafe(move || async move {
-                if transaction_request.is_solana() {
-                    signer
-                        .sign_and_send_encoded_solana_transaction(
-                            transaction_request.data,
-                        )
-                        .await
-                } else {
-                    signer
-                        .sign_and_send_json_evm_transaction(
-                            transaction_request.to_json_rpc()?,
-                        )
-                        .await
-                }
-            })
-            .await
+        .await?;
+        assessment.warnings.extend(spender_warnings(&quote));
+        if let Some(approval) = missing_approval(&quote).await? {
+            let approved = request_approval(approval).await?;
+            if is_pending(&approved) {
+                let approval: serde_json::Value =
+                    serde_json::from_str(&approved)?;
+                return Ok(serde_json::json!({
+                    "status": "approval_required",
+                    "approval": approval["action"],
+                    "message": "Nothing was swapped yet. The swap needs the approval first, show it to the user, call confirm_action with its id once they agree, then call multichain_swap again",
+                })
+                .to_string());
+            }
         }
-        None => Err(anyhow!("No transaction request")),
     }
+    confirm_or_execute(
+        "multichain_swap",
+        assessment.annotate(format!(
+            "Swap {} of {} on {} for {} on {}",
+            amount, from_token_symbol, from_chain, to_token_symbol, to_chain
+        )),
+        move || async move {
+            let quote = fetch_lifi_quote(
+                &from_token_symbol,
+                &to_token_symbol,
+                &amount,
+                &from_chain,
+                &to_chain,
+            )
+            .await?;
+            report(Stage::QuoteFetched, None);
+            let fee = CollectedFee::lifi(&quote, &from_chain);
+
+            match quote.transaction_request {
+                Some(transaction_request) => {
+                    let signer = SignerContext::current().await;
+                    let hash =
+                        send_transaction_request(signer, transaction_request)
+                            .await?;
+                    record_fee(fee, &hash).await;
+                    assessment.record().await;
+                    Ok(with_explorer_link(&from_chain, &hash))
+                }
+                None => {
+                    Err(Error::Quote("No transaction request".to_string())
+                        .into())
+                }
+            }
+        },
+    )
+    .await
 }
 
 #[tool(description = "
@@ -194,7 +601,7 @@ pub async fn check_approval(
             .await?;
     let amount = amount
         .parse::<u128>()
-        .map_err(|_| anyhow!("Invalid amount"))?;
+        .map_err(|_| Error::UserInput("Invalid amount".to_string()))?;
 
     Ok((allowance >= amount).to_string())
 }
@@ -211,23 +618,44 @@ pub async fn approve_token(
     spender_address: String,
     amount: String,
 ) -> Result<String> {
-    let signer = SignerContext::current().await;
-    let owner_address = signer.address();
-
-    let transaction = create_approval_transaction(
-        &token_address,
-        &spender_address,
-        amount.parse::<u128>()?,
-        &owner_address,
-    )?;
-
-    wrap_unsafe(move || async move {
-        signer
+    validation::address(&evm_chain(), "token_address", &token_address)?;
+    validation::address(&evm_chain(), "spender_address", &spender_address)?;
+    validation::raw_amount("amount", &amount)?;
+    let chain_id = make_provider()?.get_chain_id().await?;
+    let description = format!(
+        "Approve {} to spend {} of token {}",
+        spender_address, amount, token_address
+    );
+    let summary =
+        annotate_spender(description.clone(), chain_id, &spender_address);
+    confirm_or_execute("approve_token", summary, move || async move {
+        let signer = SignerContext::current().await;
+        let owner_address = signer.address();
+
+        let transaction = create_approval_transaction(
+            &token_address,
+            &spender_address,
+            amount.parse::<u128>()?,
+            &owner_address,
+        )?;
+
+        let _signing = begin_signing().await?;
+        let tx_hash = signer
             .sign_and_send_json_evm_transaction(transaction)
-            .await
-            .map_err(|e| anyhow!(e.to_string()))
-    })
-    .await?;
+            .await?;
+        let event = ActivityEvent::new(
+            signer.as_ref(),
+            &evm_chain(),
+            Some(chain_id),
+            &tx_hash,
+        )
+        .with_description(Some(description));
+        activity::record(signer.as_ref(), event).await;
 
-    Ok("Approved".to_string())
+        Ok(format!(
+            "Approved {}",
+            with_explorer_link(&evm_chain(), &tx_hash)
+        ))
+    })
+    .await
 }
@@ -0,0 +1,44 @@
+//! Postgres pool shared by the wallet store and the Postgres kv_store, the
+//! connections are opened on first use
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+
+use crate::config::config;
+
+/// Sized by database.max_connections, the error of an invalid
+/// database.url
+static DB_POOL: Lazy<Result<PgPool, String>> = Lazy::new(|| {
+    let config = config();
+    let database = &config.database;
+    PgPoolOptions::new()
+        .max_connections(database.max_connections)
+        .connect_lazy(&database.url)
+        .map_err(|e| format!("Invalid database.url: {}", e))
+});
+
+/// The pool of database.url, fails when it is not a Postgres URL
+pub fn db_pool() -> Result<PgPool> {
+    DB_POOL.clone().map_err(|e| anyhow!(e))
+}
+
+/// Database of database.url, told by the scheme of the URL. The Postgres
+/// kv_store needs Postgres, the wallet store takes any of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Postgres,
+    Sqlite,
+    /// memory://, lost on restart
+    Memory,
+}
+
+impl Backend {
+    pub fn of(url: &str) -> Option<Self> {
+        match url.split(':').next()? {
+            "postgres" | "postgresql" => Some(Self::Postgres),
+            "sqlite" => Some(Self::Sqlite),
+            "memory" => Some(Self::Memory),
+            _ => None,
+        }
+    }
+}
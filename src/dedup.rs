@@ -0,0 +1,299 @@
+//! Deduplication of value-moving tool calls. The LLM retries calls that
+//! timed out or whose result got lost on the way, and clients resend
+//! requests whose response they lost, which used to send the same swap
+//! twice. Calls are keyed on the request id the client sent
+//! (X-Request-Id), which it keeps when resending the request: a call with
+//! the same session, request id, tool and arguments as one still running,
+//! or finished within a short window, gets the result of the first one
+//! instead of executing again. Identical trades the user asks for in new
+//! requests all execute, calls outside of a request with an id are never
+//! deduplicated. Failures are forgotten so genuine retries go through,
+//! except for timeouts and network errors as the first call may have been
+//! executed anyway
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use tokio::sync::watch;
+
+use crate::tool_error::{ErrorCode, ToolError};
+
+const DEFAULT_WINDOW_SECS: u64 = 60;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Runs `f` for the client request `id`, the value-moving calls it makes
+/// are deduplicated against those of the same request
+pub async fn with_request_id<T>(
+    id: Option<String>,
+    f: impl Future<Output = T>,
+) -> T {
+    match id {
+        Some(id) => REQUEST_ID.scope(id, f).await,
+        None => f.await,
+    }
+}
+
+/// Request id of the current call, None outside of a request with one
+pub fn request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DedupKey {
+    session: String,
+    request: String,
+    tool: String,
+    args_hash: u64,
+}
+
+impl DedupKey {
+    /// Arguments are compared as JSON, the order of the keys doesn't matter
+    pub fn new(session: &str, request: &str, tool: &str, args: &str) -> Self {
+        let args = serde_json::from_str::<Value>(args)
+            .map_or_else(|_| args.to_string(), |args| args.to_string());
+        let mut hasher = DefaultHasher::new();
+        args.hash(&mut hasher);
+        Self {
+            session: session.to_string(),
+            request: request.to_string(),
+            tool: tool.to_string(),
+            args_hash: hasher.finish(),
+        }
+    }
+}
+
+type Outcome = Option<Result<String, ToolError>>;
+
+enum Entry {
+    Running(watch::Receiver<Outcome>),
+    Done {
+        result: Result<String, ToolError>,
+        at: Instant,
+    },
+}
+
+pub struct Deduplicator {
+    window: Duration,
+    entries: Mutex<HashMap<DedupKey, Entry>>,
+}
+
+pub static DEDUP: Lazy<Deduplicator> = Lazy::new(|| {
+    let window = std::env::var("DEDUP_WINDOW_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(DEFAULT_WINDOW_SECS);
+    Deduplicator::new(Duration::from_secs(window))
+});
+
+/// Removes the entry of a call that was dropped before it finished
+struct Running<'a> {
+    dedup: &'a Deduplicator,
+    key: DedupKey,
+    tx: watch::Sender<Outcome>,
+    finished: bool,
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.dedup.entries.lock().unwrap().remove(&self.key);
+        }
+    }
+}
+
+/// Outcomes a retry must not execute again
+pub(crate) fn is_ambiguous(error: &ToolError) -> bool {
+    matches!(error.code, ErrorCode::Timeout | ErrorCode::Network)
+}
+
+fn duplicate_of(
+    result: Result<String, ToolError>,
+    since: Duration,
+) -> Result<String> {
+    match result {
+        Ok(result) => Ok(json!({
+            "status": "duplicate",
+            "message": format!(
+                "An identical call ran {}s ago, this is its result, nothing was executed again",
+                since.as_secs()
+            ),
+            // tools return serialized JSON, plain strings are passed as is
+            "result": serde_json::from_str::<Value>(&result)
+                .unwrap_or(Value::String(result)),
+        })
+        .to_string()),
+        Err(error) => Err(ToolError::new(
+            ErrorCode::Duplicate,
+            format!(
+                "An identical call failed {}s ago without a known outcome: {}",
+                since.as_secs(),
+                error.message
+            ),
+        )
+        .into()),
+    }
+}
+
+impl Deduplicator {
+    /// A zero `window` only deduplicates calls running at the same time
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs `call` unless an identical one is running or just finished,
+    /// then its result is returned
+    pub async fn run<Fut>(&self, key: DedupKey, call: Fut) -> Result<String>
+    where
+        Fut: Future<Output = Result<String>>,
+    {
+        let started = Instant::now();
+        let running = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.retain(|_, entry| match entry {
+                Entry::Running(_) => true,
+                Entry::Done { at, .. } => at.elapsed() < self.window,
+            });
+            match entries.get(&key) {
+                Some(Entry::Done { result, at }) => {
+                    tracing::warn!(?key, "duplicate tool call");
+                    return duplicate_of(result.clone(), at.elapsed());
+                }
+                Some(Entry::Running(rx)) => Err(rx.clone()),
+                None => {
+                    let (tx, rx) = watch::channel(None);
+                    entries.insert(key.clone(), Entry::Running(rx));
+                    Ok(Running {
+                        dedup: self,
+                        key,
+                        tx,
+                        finished: false,
+                    })
+                }
+            }
+        };
+
+        let mut running = match running {
+            Ok(running) => running,
+            Err(mut rx) => {
+                tracing::warn!("duplicate of a running tool call");
+                let outcome = rx
+                    .wait_for(Option::is_some)
+                    .await
+                    .map_err(|_| {
+                        ToolError::new(
+                            ErrorCode::Duplicate,
+                            "An identical call was interrupted, its outcome is unknown",
+                        )
+                    })?
+                    .clone()
+                    .unwrap();
+                return duplicate_of(outcome, started.elapsed());
+            }
+        };
+
+        let result = call.await;
+        let outcome =
+            result.as_ref().cloned().map_err(ToolError::from_anyhow);
+        let mut entries = self.entries.lock().unwrap();
+        match &outcome {
+            Err(error) if !is_ambiguous(error) => {
+                entries.remove(&running.key);
+            }
+            _ => {
+                entries.insert(
+                    running.key.clone(),
+                    Entry::Done {
+                        result: outcome.clone(),
+                        at: Instant::now(),
+                    },
+                );
+            }
+        }
+        running.finished = true;
+        let _ = running.tx.send(Some(outcome));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[tokio::test]
+    async fn test_dedup() {
+        let dedup = Deduplicator::new(Duration::from_secs(60));
+        let key =
+            DedupKey::new("session", "req", "trade", r#"{"a": 1, "b": 2}"#);
+        let same =
+            DedupKey::new("session", "req", "trade", r#"{"b":2,"a":1}"#);
+        assert_eq!(key, same);
+
+        let calls = &std::sync::atomic::AtomicUsize::new(0);
+        let call = move || async move {
+            calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok("hash".to_string())
+        };
+        let (first, second) = tokio::join!(
+            dedup.run(key.clone(), call()),
+            dedup.run(same.clone(), call())
+        );
+        assert_eq!(first.unwrap(), "hash");
+        assert!(second.unwrap().contains("duplicate"));
+        assert!(dedup.run(same, call()).await.unwrap().contains("duplicate"));
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let other =
+            DedupKey::new("other", "req", "trade", r#"{"a": 1, "b": 2}"#);
+        assert_eq!(dedup.run(other, call()).await.unwrap(), "hash");
+        // the same trade asked for again in a new request
+        let again =
+            DedupKey::new("session", "req2", "trade", r#"{"a": 1, "b": 2}"#);
+        assert_eq!(dedup.run(again, call()).await.unwrap(), "hash");
+
+        let scoped =
+            with_request_id(Some("req".to_string()), async { request_id() });
+        assert_eq!(scoped.await.as_deref(), Some("req"));
+        assert_eq!(with_request_id(None, async { request_id() }).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_retried_unless_ambiguous() {
+        let dedup = Deduplicator::new(Duration::from_secs(60));
+        let key = DedupKey::new("session", "req", "trade", "{}");
+        let failed = dedup
+            .run(key.clone(), async { Err(anyhow!("Invalid mint")) })
+            .await;
+        assert!(failed.is_err());
+        let retried = dedup
+            .run(key.clone(), async { Ok("hash".to_string()) })
+            .await;
+        assert_eq!(retried.unwrap(), "hash");
+
+        let key = DedupKey::new("session", "req", "transfer_sol", "{}");
+        let _ = dedup
+            .run(key.clone(), async { Err(anyhow!("request timed out")) })
+            .await;
+        let retried = dedup
+            .run(key, async { Ok("hash".to_string()) })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            ToolError::from_anyhow(&retried).code,
+            ErrorCode::Duplicate
+        );
+    }
+}
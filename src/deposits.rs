@@ -0,0 +1,617 @@
+//! Deposits to the user wallets. Users opt in per chain with
+//! `watch_deposits`, the watched wallets are persisted in the kv store and a
+//! background task per wallet follows them while deposits.enabled: Solana
+//! wallets over the websocket of the RPC (the wallet and its token
+//! accounts), EVM wallets through their ERC20 Transfer logs. Every incoming
+//! transfer worth deposits.min_usd or more goes to the notification sinks
+//! and, when deposits.webhook_url is set, to a webhook signed with
+//! deposits.webhook_secret so the host app can credit the user or start an
+//! auto-invest flow. Webhooks are queued before they are sent and stay
+//! queued until the host app accepts them, failed ones are sent again with
+//! every reconciliation. Transactions the wallet signed itself are not
+//! deposits
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use futures::stream::{select_all, StreamExt};
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_client::rpc_config::{
+    RpcTransactionLogsConfig, RpcTransactionLogsFilter,
+};
+use solana_client::rpc_request::TokenAccountsFilter;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use tokio::task::JoinHandle;
+
+use crate::chains::{Chain, CHAINS};
+use crate::common::{evm_chain, solana_rpc};
+use crate::config::config;
+use crate::error::Error;
+use crate::evm::events::{watch_address, TokenEventKind};
+use crate::kv_store::{namespaced, KVStore};
+use crate::notify::{
+    Notification, NotificationKind, NotificationSink, WebhookSink, NOTIFIER,
+};
+use crate::pricing::{to_ui_amount, token_price};
+use crate::signer::{SignerContext, SignerRef};
+use crate::solana::balance::transaction_balances;
+
+const WALLETS_KEY: &str = "wallets";
+/// Prefix of the webhooks not yet accepted by the host app
+const WEBHOOK_PREFIX: &str = "webhook:";
+/// Deposits are remembered this long, replays of the logs after a restart
+/// or a reconnect are not delivered twice. Webhooks are retried as long
+/// before they are dropped
+const SEEN_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const WEBHOOK_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchedWallet {
+    pub owner: SignerRef,
+    pub chain: Chain,
+    pub address: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl WatchedWallet {
+    fn key(&self) -> String {
+        format!("{}:{}", self.chain, self.address)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deposit {
+    pub id: String,
+    /// chain key, sol or the one of the EVM RPC (sonic, eth..)
+    pub chain: String,
+    pub address: String,
+    /// mint or token address, wrapped SOL for SOL
+    pub token: String,
+    /// raw amount
+    pub amount: String,
+    pub symbol: Option<String>,
+    pub ui_amount: Option<f64>,
+    pub usd_value: Option<f64>,
+    pub from: Option<String>,
+    pub tx: String,
+    pub detected_at: DateTime<Utc>,
+}
+
+impl Deposit {
+    fn new(
+        chain: String,
+        address: &str,
+        token: String,
+        amount: String,
+        from: Option<String>,
+        tx: String,
+    ) -> Self {
+        Self {
+            id: format!("{}:{}:{}", tx, address, token),
+            chain,
+            address: address.to_string(),
+            token,
+            amount,
+            symbol: None,
+            ui_amount: None,
+            usd_value: None,
+            from,
+            tx,
+            detected_at: Utc::now(),
+        }
+    }
+
+    /// Values the deposit, false when it is worth less than `min_usd`.
+    /// Tokens without a price are kept, they can't be told apart from dust
+    /// but a user can't be left uncredited
+    async fn price(&mut self, min_usd: f64) -> bool {
+        match token_price(&self.chain, &self.token).await {
+            Ok(price) => {
+                let ui_amount = to_ui_amount(&self.amount, price.decimals)
+                    .unwrap_or_default();
+                let usd_value = ui_amount * price.price_usd;
+                self.symbol = Some(price.symbol);
+                self.ui_amount = Some(ui_amount);
+                self.usd_value = Some(usd_value);
+                usd_value >= min_usd
+            }
+            Err(e) => {
+                tracing::debug!(?e, token = %self.token, "deposit not priced");
+                true
+            }
+        }
+    }
+}
+
+pub struct Deposits {
+    store: Arc<dyn KVStore>,
+    /// wallets are stored as a single value, writes have to be serialized
+    lock: tokio::sync::Mutex<()>,
+    /// watcher task of every watched wallet, by `WatchedWallet::key`
+    watchers: std::sync::Mutex<HashMap<String, JoinHandle<()>>>,
+}
+
+pub static DEPOSITS: Lazy<Deposits> =
+    Lazy::new(|| Deposits::new(Arc::new(namespaced("deposits"))));
+
+impl Deposits {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self {
+            store,
+            lock: tokio::sync::Mutex::new(()),
+            watchers: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn load(&self) -> Result<Vec<WatchedWallet>> {
+        match self.store.get(WALLETS_KEY).await? {
+            Some(wallets) => Ok(serde_json::from_str(&wallets)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    async fn save(&self, wallets: &[WatchedWallet]) -> Result<()> {
+        self.store
+            .set(WALLETS_KEY, &serde_json::to_string(wallets)?)
+            .await
+    }
+
+    /// Watches the wallet, a wallet already watched is left as is
+    pub async fn watch(
+        &self,
+        wallet: WatchedWallet,
+    ) -> Result<WatchedWallet> {
+        let _guard = self.lock.lock().await;
+        let mut wallets = self.load().await?;
+        if let Some(watched) =
+            wallets.iter().find(|w| w.key() == wallet.key())
+        {
+            return Ok(watched.clone());
+        }
+        wallets.push(wallet.clone());
+        self.save(&wallets).await?;
+        Ok(wallet)
+    }
+
+    pub async fn unwatch(
+        &self,
+        chain: Chain,
+        owner: &SignerRef,
+    ) -> Result<Vec<WatchedWallet>> {
+        let _guard = self.lock.lock().await;
+        let (removed, kept) =
+            self.load().await?.into_iter().partition::<Vec<_>, _>(|w| {
+                w.chain == chain && w.owner.same_user(owner)
+            });
+        if removed.is_empty() {
+            return Err(anyhow!("No {} wallet is watched", chain));
+        }
+        self.save(&kept).await?;
+        Ok(removed)
+    }
+
+    pub async fn list(
+        &self,
+        owner: &SignerRef,
+    ) -> Result<Vec<WatchedWallet>> {
+        Ok(self
+            .load()
+            .await?
+            .into_iter()
+            .filter(|wallet| wallet.owner.same_user(owner))
+            .collect())
+    }
+
+    /// Records the deposit, false when it was already seen
+    async fn admit(&self, deposit: &Deposit) -> Result<bool> {
+        let key = format!("seen:{}", deposit.id);
+        if self.store.get(&key).await?.is_some() {
+            return Ok(false);
+        }
+        self.store.set_with_ttl(&key, "1", SEEN_TTL).await?;
+        Ok(true)
+    }
+
+    /// Notifies the owner of the wallet and calls the webhook, once per
+    /// deposit
+    async fn deliver(
+        &self,
+        wallet: &WatchedWallet,
+        mut deposit: Deposit,
+    ) -> Result<()> {
+        let config = config();
+        let settings = &config.deposits;
+        if !deposit.price(settings.min_usd).await
+            || !self.admit(&deposit).await?
+        {
+            return Ok(());
+        }
+        tracing::info!(
+            id = %deposit.id,
+            usd_value = deposit.usd_value,
+            "deposit detected"
+        );
+        let symbol = deposit.symbol.as_deref().unwrap_or(&deposit.token);
+        let amount = match deposit.ui_amount {
+            Some(ui_amount) => ui_amount.to_string(),
+            None => deposit.amount.clone(),
+        };
+        let notification = Notification::new(
+            NotificationKind::Deposit,
+            wallet.owner.user_id.clone(),
+            format!("Received {} {}", amount, symbol),
+            format!(
+                "{} {} arrived in {} ({})",
+                amount, symbol, wallet.address, deposit.tx
+            ),
+        )
+        .with_data(serde_json::to_value(&deposit)?);
+        NOTIFIER.notify(&notification).await;
+
+        let (Some(url), Some(secret)) =
+            (&settings.webhook_url, &settings.webhook_secret)
+        else {
+            return Ok(());
+        };
+        let webhook =
+            WebhookSink::new(url.clone()).with_secret(secret.clone());
+        let key = self.queue_webhook(&deposit.id, &notification).await?;
+        self.send_webhook(&webhook, &key, &notification, WEBHOOK_ATTEMPTS)
+            .await?;
+        Ok(())
+    }
+
+    /// Queues the webhook of a deposit until the host app accepts it
+    async fn queue_webhook(
+        &self,
+        id: &str,
+        notification: &Notification,
+    ) -> Result<String> {
+        let key = format!("{}{}", WEBHOOK_PREFIX, id);
+        self.store
+            .set_with_ttl(
+                &key,
+                &serde_json::to_string(notification)?,
+                SEEN_TTL,
+            )
+            .await?;
+        Ok(key)
+    }
+
+    /// Sends the webhook queued as `key` and dequeues it once accepted,
+    /// false when every attempt failed and it stays queued
+    async fn send_webhook(
+        &self,
+        webhook: &WebhookSink,
+        key: &str,
+        notification: &Notification,
+        attempts: u32,
+    ) -> Result<bool> {
+        for attempt in 1..=attempts {
+            match webhook.send(notification).await {
+                Ok(()) => {
+                    self.store.expire(key, Duration::ZERO).await?;
+                    return Ok(true);
+                }
+                Err(e) if attempt == attempts => {
+                    tracing::warn!(?e, key, "deposit webhook queued again");
+                }
+                Err(e) => {
+                    tracing::warn!(?e, attempt, "deposit webhook failed");
+                    tokio::time::sleep(Duration::from_secs(1 << attempt))
+                        .await;
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Sends the queued webhooks again, once each
+    async fn retry_webhooks(&self) -> Result<()> {
+        let settings = &config().deposits;
+        let (Some(url), Some(secret)) =
+            (&settings.webhook_url, &settings.webhook_secret)
+        else {
+            return Ok(());
+        };
+        let webhook =
+            WebhookSink::new(url.clone()).with_secret(secret.clone());
+        for (key, value) in self.store.scan_prefix(WEBHOOK_PREFIX).await? {
+            let Ok(notification) = serde_json::from_str(&value) else {
+                tracing::warn!(%key, "queued deposit webhook unreadable");
+                continue;
+            };
+            self.send_webhook(&webhook, &key, &notification, 1).await?;
+        }
+        Ok(())
+    }
+
+    async fn follow(&'static self, wallet: &WatchedWallet) -> Result<()> {
+        match wallet.chain {
+            Chain::Solana => self.follow_solana(wallet).await,
+            Chain::Evm => self.follow_evm(wallet).await,
+        }
+    }
+
+    async fn follow_evm(&'static self, wallet: &WatchedWallet) -> Result<()> {
+        let chain = evm_chain();
+        let mut events = watch_address(&wallet.address).await?;
+        while let Some(event) = events.recv().await {
+            if event.kind != TokenEventKind::Transfer || !event.incoming {
+                continue;
+            }
+            let Some(tx) = event.tx_hash else {
+                continue;
+            };
+            let deposit = Deposit::new(
+                chain.clone(),
+                &wallet.address,
+                event.token,
+                event.amount,
+                Some(event.from),
+                tx,
+            );
+            self.deliver(wallet, deposit).await?;
+        }
+        Err(anyhow!("EVM event stream closed"))
+    }
+
+    /// Follows the logs mentioning the wallet or one of its token accounts.
+    /// Returns when the wallet got a new token account so the caller
+    /// subscribes again with it
+    async fn follow_solana(
+        &'static self,
+        wallet: &WatchedWallet,
+    ) -> Result<()> {
+        let owner = Pubkey::from_str(&wallet.address)?;
+        let mut accounts = vec![wallet.address.clone()];
+        for program in [spl_token::id(), spl_token_2022::id()] {
+            let token_accounts = solana_rpc()
+                .get_token_accounts_by_owner(
+                    &owner,
+                    TokenAccountsFilter::ProgramId(program),
+                )
+                .await
+                .map_err(Error::from)?;
+            accounts.extend(token_accounts.into_iter().map(|a| a.pubkey));
+        }
+
+        let config = config();
+        let client =
+            PubsubClient::new(&config.deposits.solana_ws_url(&config.rpc))
+                .await
+                .map_err(|e| Error::Rpc(e.to_string()))?;
+        let mut subscriptions = vec![];
+        for account in &accounts {
+            let (stream, _unsubscribe) = client
+                .logs_subscribe(
+                    RpcTransactionLogsFilter::Mentions(vec![account.clone()]),
+                    RpcTransactionLogsConfig {
+                        commitment: Some(CommitmentConfig::confirmed()),
+                    },
+                )
+                .await
+                .map_err(|e| Error::Rpc(e.to_string()))?;
+            subscriptions.push(stream);
+        }
+        let mut logs = select_all(subscriptions);
+        while let Some(log) = logs.next().await {
+            if log.value.err.is_some() {
+                continue;
+            }
+            // a transaction touching several accounts is logged once each
+            let signature = Signature::from_str(&log.value.signature)?;
+            let balances = match transaction_balances(&signature).await {
+                Ok(balances) => balances,
+                Err(e) => {
+                    tracing::warn!(?e, %signature, "deposit not checked");
+                    continue;
+                }
+            };
+            let new_account = balances.post_tokens.iter().any(|balance| {
+                balance.owner == wallet.address
+                    && !balances.pre_tokens.iter().any(|pre| {
+                        pre.owner == balance.owner && pre.mint == balance.mint
+                    })
+            });
+            for (mint, amount) in balances.received(&wallet.address) {
+                let deposit = Deposit::new(
+                    "sol".to_string(),
+                    &wallet.address,
+                    mint,
+                    amount.to_string(),
+                    balances.keys.first().cloned(),
+                    signature.to_string(),
+                );
+                self.deliver(wallet, deposit).await?;
+            }
+            if new_account {
+                return Ok(());
+            }
+        }
+        Err(anyhow!("Solana log subscription closed"))
+    }
+
+    /// Follows the wallet until its task is aborted, reconnecting when
+    /// the subscription drops
+    fn start(&'static self, wallet: WatchedWallet) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match self.follow(&wallet).await {
+                    Ok(()) => continue,
+                    Err(e) => tracing::warn!(
+                        ?e,
+                        wallet = %wallet.key(),
+                        "deposit watcher reconnecting"
+                    ),
+                }
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        })
+    }
+
+    /// Starts the watchers of the new wallets and stops the ones of the
+    /// wallets no longer watched, or all of them once deposits.enabled is
+    /// turned off
+    async fn reconcile(&'static self) -> Result<()> {
+        let wallets = if config().deposits.enabled {
+            self.load().await?
+        } else {
+            vec![]
+        };
+        let mut watchers = self.watchers.lock().unwrap();
+        let keys = wallets.iter().map(WatchedWallet::key).collect::<Vec<_>>();
+        watchers.retain(|key, watcher| {
+            let keep = keys.contains(key) && !watcher.is_finished();
+            if !keep {
+                watcher.abort();
+            }
+            keep
+        });
+        for wallet in wallets {
+            if !CHAINS.is_enabled(wallet.chain) {
+                continue;
+            }
+            if !watchers.contains_key(&wallet.key()) {
+                watchers.insert(wallet.key(), self.start(wallet));
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps a watcher running for every watched wallet and sends the
+    /// queued webhooks again
+    pub fn spawn(&'static self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RECONCILE_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.reconcile().await {
+                    tracing::error!(?e, "deposit watchers not reconciled");
+                }
+                if let Err(e) = self.retry_webhooks().await {
+                    tracing::error!(?e, "deposit webhooks not retried");
+                }
+            }
+        })
+    }
+}
+
+async fn current_signer_wallet(chain: Chain) -> Result<WatchedWallet> {
+    CHAINS.require(chain)?;
+    let signer = SignerContext::current().await;
+    Ok(WatchedWallet {
+        owner: SignerRef::of(signer.as_ref()),
+        chain,
+        address: match chain {
+            Chain::Solana => signer.pubkey(),
+            Chain::Evm => signer.address(),
+        },
+        created_at: Utc::now(),
+    })
+}
+
+#[tool(description = "
+Starts watching the wallet of the user on a chain for incoming transfers.
+Every deposit worth more than the configured minimum notifies the user and
+the app, e.g. to credit the account or invest it.
+
+chain is either solana or evm
+")]
+pub async fn watch_deposits(chain: String) -> Result<WatchedWallet> {
+    let chain = Chain::from_str(&chain)?;
+    if !config().deposits.enabled {
+        return Err(anyhow!("Deposit watching is disabled"));
+    }
+    DEPOSITS.watch(current_signer_wallet(chain).await?).await
+}
+
+#[tool(description = "
+Stops watching the wallet of the user on a chain for deposits
+
+chain is either solana or evm
+")]
+pub async fn stop_watching_deposits(chain: String) -> Result<String> {
+    let chain = Chain::from_str(&chain)?;
+    let owner = current_signer_wallet(chain).await?.owner;
+    let removed = DEPOSITS.unwatch(chain, &owner).await?;
+    Ok(format!(
+        "Stopped watching {}",
+        removed
+            .iter()
+            .map(|wallet| wallet.address.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    const USER: &str = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+    const USDC: &str = "EPjFWJd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+    #[tokio::test]
+    async fn test_watch_once() {
+        let deposits = Deposits::new(Arc::new(InMemoryKVStore::new()));
+        let owner = SignerRef {
+            user_id: Some("alice".to_string()),
+            session_id: None,
+        };
+        let wallet = WatchedWallet {
+            owner: owner.clone(),
+            chain: Chain::Solana,
+            address: USER.to_string(),
+            created_at: Utc::now(),
+        };
+        deposits.watch(wallet.clone()).await.unwrap();
+        deposits.watch(wallet).await.unwrap();
+        assert_eq!(deposits.list(&owner).await.unwrap().len(), 1);
+
+        let deposit = Deposit::new(
+            "sol".to_string(),
+            USER,
+            USDC.to_string(),
+            "25000000".to_string(),
+            None,
+            "5h6x".to_string(),
+        );
+        assert!(deposits.admit(&deposit).await.unwrap());
+        assert!(!deposits.admit(&deposit).await.unwrap());
+
+        // the host app is down, the webhook stays queued
+        let notification = Notification::new(
+            NotificationKind::Deposit,
+            owner.user_id.clone(),
+            "Received 25 USDC",
+            "",
+        );
+        let key = deposits
+            .queue_webhook(&deposit.id, &notification)
+            .await
+            .unwrap();
+        let webhook = WebhookSink::new("http://127.0.0.1:9".to_string());
+        assert!(!deposits
+            .send_webhook(&webhook, &key, &notification, 1)
+            .await
+            .unwrap());
+        let queued =
+            deposits.store.scan_prefix(WEBHOOK_PREFIX).await.unwrap();
+        assert_eq!(queued.len(), 1);
+
+        deposits.unwatch(Chain::Solana, &owner).await.unwrap();
+        assert!(deposits.list(&owner).await.unwrap().is_empty());
+        assert!(deposits.unwatch(Chain::Solana, &owner).await.is_err());
+    }
+}
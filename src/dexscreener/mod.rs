@@ -1,9 +1,11 @@
 pub mod tools;
 
 use anyhow::Result;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
+use crate::common::http_client;
+use crate::metrics::observe_api;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DexScreenerResponse {
     #[serde(rename = "schemaVersion")]
@@ -31,6 +33,8 @@ pub struct PairInfo {
     pub price_usd: String,
     pub liquidity: Liquidity,
     pub volume: Volume,
+    #[serde(rename = "pairCreatedAt", default)]
+    pub pair_created_at: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -60,20 +64,39 @@ pub struct TickerResponse {
 }
 
 pub async fn search_ticker(ticker: String) -> Result<DexScreenerResponse> {
-    let client = Client::new();
-    let url = format!(
-        "https://api.dexscreener.com/latest/dex/search/?q={}",
-        ticker
-    );
+    let client = http_client();
+    // the ticker comes from the user, it is percent-encoded
+    let url = reqwest::Url::parse_with_params(
+        "https://api.dexscreener.com/latest/dex/search/",
+        &[("q", ticker)],
+    )?;
+
+    observe_api("dexscreener", async {
+        Ok(client
+            .get(url)
+            .send()
+            .await?
+            .json::<DexScreenerResponse>()
+            .await?)
+    })
+    .await
+}
 
-    let response = client
-        .get(&url)
-        .send()
-        .await?
-        .json::<DexScreenerResponse>()
-        .await?;
+/// Hours since the oldest pool of the token was created, None when the
+/// token has no pools on DexScreener
+pub async fn token_age_hours(token: &str) -> Result<Option<f64>> {
+    let response = search_ticker(token.to_string()).await?;
+    let created_at = response
+        .pairs
+        .iter()
+        .filter(|pair| pair.base_token.address.eq_ignore_ascii_case(token))
+        .filter_map(|pair| pair.pair_created_at)
+        .min();
 
-    Ok(response)
+    Ok(created_at.map(|created_at| {
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        now.saturating_sub(created_at) as f64 / 3_600_000.
+    }))
 }
 
 #[cfg(test)]
@@ -0,0 +1,126 @@
+//! Single entry point for executing the tool calls the LLM makes, every
+//! call goes through here so session level checks apply regardless of the
+//! agent the tool belongs to, value-moving calls are rate limited (or only
+//! simulated in dry-run sessions, or booked on a virtual portfolio in paper
+//! sessions), every call is audited, successful results
+//! are recorded in the session memory and failures are returned as a
+//! structured `ToolError`. Calls run with the timeout of the tool and can be
+//! cancelled by the user until they sign, retried value-moving calls of a
+//! request are deduplicated. No calls are taken once a shutdown began
+use std::future::Future;
+use std::time::Instant;
+
+use anyhow::Result;
+use rig::tool::ToolSet;
+
+use crate::audit::record_tool_call;
+use crate::cancellation::{with_timeout, CancellationToken, CANCELLATIONS};
+use crate::dedup::{request_id, DedupKey, DEDUP};
+use crate::execution::{
+    dry_run, mode_of, moves_value, session_key, ExecutionMode,
+};
+use crate::memory::record_tool_result;
+use crate::metrics;
+use crate::paper_trading;
+use crate::rate_limit::{rate_limit_key, RATE_LIMITER};
+use crate::shutdown::SHUTDOWN;
+use crate::signer::SignerContext;
+use crate::tool_error::ToolError;
+
+pub async fn dispatch_tool_call(
+    tools: &ToolSet,
+    name: &str,
+    args: String,
+) -> Result<String> {
+    dispatch(name, args, |args| tools.call(name, args)).await
+}
+
+/// Runs `call` with the session checks applied and records its result, for
+/// callers holding the tools outside of a `ToolSet`
+pub async fn dispatch<F, Fut, E>(
+    name: &str,
+    args: String,
+    call: F,
+) -> Result<String>
+where
+    F: FnOnce(String) -> Fut + Send,
+    Fut: Future<Output = Result<String, E>> + Send,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let signer = SignerContext::try_current();
+    if let Some(signer) = &signer {
+        signer.tool_access().check(name)?;
+    }
+
+    let dedup_key = signer
+        .as_ref()
+        .filter(|_| moves_value(name))
+        .zip(request_id())
+        .map(|(signer, request)| {
+            DedupKey::new(
+                &session_key(signer.as_ref()),
+                &request,
+                name,
+                &args,
+            )
+        });
+    let token = CancellationToken::new();
+    // held until the call is audited, shutdowns wait for it
+    let _in_flight =
+        SHUTDOWN.begin(name, &args, signer.as_deref(), &token)?;
+    let _active = signer
+        .as_ref()
+        .map(|signer| CANCELLATIONS.register(signer.as_ref(), &token));
+
+    let started = Instant::now();
+    let call_args = args.clone();
+    let call =
+        async move { call(call_args).await.map_err(anyhow::Error::from) };
+    let run = async {
+        match signer {
+            Some(signer) if moves_value(name) && !signer.is_paper() => {
+                match mode_of(signer.as_ref()).await? {
+                    ExecutionMode::DryRun => dry_run(signer, call).await,
+                    ExecutionMode::Paper => {
+                        paper_trading::execute(signer.as_ref(), name, &args)
+                            .await
+                    }
+                    ExecutionMode::Live => {
+                        let user = rate_limit_key(signer.as_ref());
+                        let reservation = RATE_LIMITER
+                            .reserve(&user, name, Instant::now())?
+                            .keep_once_signed(&token);
+                        let result = call.await;
+                        // failed calls that sent nothing don't count
+                        if result.is_ok() {
+                            reservation.keep();
+                        }
+                        result
+                    }
+                }
+            }
+            _ => call.await,
+        }
+    };
+    let run = SignerContext::with_cancellation(
+        token.clone(),
+        with_timeout(name, &token, run),
+    );
+    let result = match dedup_key {
+        Some(key) => DEDUP.run(key, run).await,
+        None => run.await,
+    }
+    .map_err(|e| anyhow::Error::new(ToolError::from_anyhow(&e)));
+    let outcome = match &result {
+        Ok(_) => "ok",
+        Err(e) => e
+            .downcast_ref::<ToolError>()
+            .map_or("internal", |e| e.code.as_str()),
+    };
+    metrics::record_tool_call(name, outcome, started.elapsed());
+    record_tool_call(name, &args, &result, started.elapsed()).await;
+
+    let result = result?;
+    record_tool_result(name, &args, &result).await;
+    Ok(result)
+}
@@ -0,0 +1,222 @@
+//! Typed errors of the subsystems (wallet manager, signers, Solana, cross
+//! chain, tools). They are raised instead of ad-hoc anyhow strings and carry
+//! the code the LLM gets to see, `ToolError` takes it from the variant
+//! rather than guessing it from the message. Errors losing their type on
+//! the way through rig are still recognized by the prefix of the message.
+//! The HTTP client and the Privy API of the wallet manager return `Result`,
+//! functions mixing them with the errors of the SDKs keep returning
+//! `anyhow::Result`, `ToolError::from_anyhow` finds the `Error` in the chain.
+//! Errors with details for the LLM (balance checks) carry them in a
+//! `ToolError` context, see `Error::detailed`
+use alloy::transports::TransportError;
+use solana_client::client_error::ClientError;
+
+use crate::tool_error::{classify, ErrorCode, ToolError};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Solana or EVM node
+    #[error("RPC error: {0}")]
+    Rpc(String),
+    /// Privy API
+    #[error("Privy error: {0}")]
+    Privy(String),
+    /// Jupiter, LiFi and the other quote APIs
+    #[error("Quote error: {0}")]
+    Quote(String),
+    /// Refused by the risk limits, token lists or chains of the deployment
+    #[error("Rejected by policy: {0}")]
+    Policy(String),
+    /// Arguments the user or the LLM got wrong
+    #[error("Invalid input: {0}")]
+    UserInput(String),
+    #[error("Not found: {0}")]
+    NotFound(String),
+    /// Session or credentials missing or expired
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    /// Signer unable to sign the transaction
+    #[error("Signer error: {0}")]
+    Signer(String),
+    #[error("Simulation failed: {0}")]
+    Simulation(String),
+    /// Wallet short of the tokens or the gas the transaction needs
+    #[error("Insufficient balance: {0}")]
+    InsufficientBalance(String),
+    /// Price moved past the slippage tolerance of the swap
+    #[error("Slippage exceeded: {0}")]
+    Slippage(String),
+    /// External API failing fast behind its open circuit breaker
+    #[error("Service unavailable: {0}")]
+    Unavailable(String),
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// Prefixes of the variants whose code doesn't depend on the message
+const PREFIXES: &[(&str, ErrorCode)] = &[
+    ("Rejected by policy: ", ErrorCode::RiskRejected),
+    ("Invalid input: ", ErrorCode::InvalidInput),
+    ("Not found: ", ErrorCode::NotFound),
+    ("Unauthorized: ", ErrorCode::Unauthorized),
+    ("Service unavailable: ", ErrorCode::Unavailable),
+    ("Insufficient balance: ", ErrorCode::InsufficientFunds),
+    ("Slippage exceeded: ", ErrorCode::SlippageExceeded),
+];
+
+/// Result of the functions whose errors are all `Error`s
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Error of a quote API (Jupiter, LiFi), the slippage and balance
+    /// errors they return get their own variant
+    pub fn quote(message: impl Into<String>) -> Self {
+        let message = message.into();
+        match classify(&message) {
+            ErrorCode::SlippageExceeded => Self::Slippage(message),
+            ErrorCode::InsufficientFunds => {
+                Self::InsufficientBalance(message)
+            }
+            _ => Self::Quote(message),
+        }
+    }
+
+    /// The error with the details and the hint the LLM gets to see, the
+    /// `Error` stays in the chain for the callers matching on it
+    pub fn detailed(
+        self,
+        detail: impl FnOnce(ToolError) -> ToolError,
+    ) -> anyhow::Error {
+        let tool_error = detail(ToolError::from(&self));
+        anyhow::Error::new(self).context(tool_error)
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Policy(_) => ErrorCode::RiskRejected,
+            Self::UserInput(_) => ErrorCode::InvalidInput,
+            Self::NotFound(_) => ErrorCode::NotFound,
+            Self::Unauthorized(_) => ErrorCode::Unauthorized,
+            Self::Unavailable(_) => ErrorCode::Unavailable,
+            Self::InsufficientBalance(_) => ErrorCode::InsufficientFunds,
+            Self::Slippage(_) => ErrorCode::SlippageExceeded,
+            Self::Rpc(message) => match classify(message) {
+                ErrorCode::Internal => ErrorCode::Network,
+                code => code,
+            },
+            Self::Privy(message)
+            | Self::Quote(message)
+            | Self::Signer(message)
+            | Self::Simulation(message) => classify(message),
+            Self::Http(error) => {
+                if error.is_timeout() {
+                    ErrorCode::Timeout
+                } else {
+                    match error.status().map(|status| status.as_u16()) {
+                        Some(429) => ErrorCode::RateLimited,
+                        Some(401) => ErrorCode::Unauthorized,
+                        Some(404) => ErrorCode::NotFound,
+                        _ => ErrorCode::Network,
+                    }
+                }
+            }
+            Self::Serialization(_) => ErrorCode::Internal,
+        }
+    }
+}
+
+/// Code of an `Error` that reached the tool boundary as a plain message
+pub(crate) fn code_of_message(message: &str) -> Option<ErrorCode> {
+    PREFIXES
+        .iter()
+        .find(|(prefix, _)| message.starts_with(prefix))
+        .map(|&(_, code)| code)
+}
+
+impl From<ClientError> for Error {
+    fn from(error: ClientError) -> Self {
+        Self::Rpc(error.to_string())
+    }
+}
+
+/// Errors of the EVM providers
+impl From<TransportError> for Error {
+    fn from(error: TransportError) -> Self {
+        Self::Rpc(error.to_string())
+    }
+}
+
+impl From<&Error> for ToolError {
+    fn from(error: &Error) -> Self {
+        ToolError::new(error.code(), error.to_string())
+    }
+}
+
+impl From<Error> for ToolError {
+    fn from(error: Error) -> Self {
+        Self::from(&error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes() {
+        let error = Error::Rpc("Blockhash not found".to_string());
+        assert_eq!(error.code(), ErrorCode::NotFound);
+        let error = Error::Rpc("connection reset by peer".to_string());
+        assert_eq!(error.code(), ErrorCode::Network);
+        let error = Error::Rpc("node is behind".to_string());
+        assert_eq!(error.code(), ErrorCode::Network);
+        let error = Error::Simulation("custom program error: 0x1771".into());
+        assert_eq!(error.code(), ErrorCode::SlippageExceeded);
+        let error = Error::quote("Slippage tolerance exceeded");
+        assert!(matches!(error, Error::Slippage(_)));
+        let error = Error::quote("Insufficient balance for the route");
+        assert_eq!(error.code(), ErrorCode::InsufficientFunds);
+        assert!(matches!(Error::quote("No route"), Error::Quote(_)));
+
+        let tool_error = ToolError::from(Error::Policy(
+            "Trade of 9000 USD is above the limit".to_string(),
+        ));
+        assert_eq!(tool_error.code, ErrorCode::RiskRejected);
+        assert!(!tool_error.retryable);
+    }
+
+    #[test]
+    fn test_typed_errors_through_anyhow() {
+        let error: anyhow::Error =
+            Error::UserInput("Amount has to be positive".to_string()).into();
+        let tool_error = ToolError::from_anyhow(&error.context("trade"));
+        assert_eq!(tool_error.code, ErrorCode::InvalidInput);
+
+        // rig keeps the message only
+        let error = anyhow::anyhow!(
+            "ToolCallError: Rejected by policy: insufficient liquidity"
+        );
+        assert_eq!(
+            ToolError::from_anyhow(&error).code,
+            ErrorCode::RiskRejected
+        );
+        let error = anyhow::anyhow!("Slippage exceeded: price moved");
+        assert_eq!(
+            ToolError::from_anyhow(&error).code,
+            ErrorCode::SlippageExceeded
+        );
+
+        // balance checks keep their type and their details
+        let error = Error::InsufficientBalance("1 SOL short".to_string())
+            .detailed(|error| error.with_hint("Top up"));
+        assert!(matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::InsufficientBalance(_))
+        ));
+        let tool_error = ToolError::from_anyhow(&error);
+        assert_eq!(tool_error.code, ErrorCode::InsufficientFunds);
+        assert_eq!(tool_error.hint.as_deref(), Some("Top up"));
+    }
+}
@@ -3,25 +3,43 @@ use rig::agent::Agent;
 use rig::providers::anthropic::completion::CompletionModel as AnthropicCompletionModel;
 
 use super::tools::{
-    ApproveTokenForRouterSpend, GetErc20Balance, GetEthBalance, Trade,
-    TransferErc20, TransferEth, VerifySwapRouterHasAllowance, WalletAddress,
+    ApproveTokenForPositionManager, ApproveTokenForRouterSpend,
+    CollectLpFees, DecreaseLpLiquidity, GetErc20Balance, GetEthBalance,
+    GetEvmTokenPrice, IncreaseLpLiquidity, ListLpPositions, MintLpPosition,
+    Trade, TransferErc20, TransferEth, VerifySwapRouterHasAllowance,
+    WalletAddress,
 };
-use crate::common::{claude_agent_builder, PREAMBLE_COMMON};
+use crate::agent::{AgentBuilder, AgentKind};
+use crate::common::{register_common_tools, ToolRegistry};
+use crate::sonic::tools::{
+    BridgeViaGateway, ClaimGatewayTransfer, GetSonicPoints,
+};
+
+pub fn register_evm_tools<R: ToolRegistry>(registry: R) -> R {
+    register_common_tools(
+        registry
+            .tool(Trade)
+            .tool(TransferEth)
+            .tool(TransferErc20)
+            .tool(WalletAddress)
+            .tool(GetEthBalance)
+            .tool(GetErc20Balance)
+            .tool(GetEvmTokenPrice)
+            .tool(ApproveTokenForRouterSpend)
+            .tool(VerifySwapRouterHasAllowance)
+            .tool(ListLpPositions)
+            .tool(ApproveTokenForPositionManager)
+            .tool(MintLpPosition)
+            .tool(IncreaseLpLiquidity)
+            .tool(DecreaseLpLiquidity)
+            .tool(CollectLpFees)
+            .tool(BridgeViaGateway)
+            .tool(ClaimGatewayTransfer)
+            .tool(GetSonicPoints),
+    )
+}
 
+/// See `AgentBuilder` for an agent with other tools or another model
 pub async fn create_evm_agent() -> Result<Agent<AnthropicCompletionModel>> {
-    Ok(claude_agent_builder()
-        .preamble(&format!(
-            "{} {}",
-            "you are an ethereum trading agent", PREAMBLE_COMMON
-        ))
-        .max_tokens(1024)
-        .tool(Trade)
-        .tool(TransferEth)
-        .tool(TransferErc20)
-        .tool(WalletAddress)
-        .tool(GetEthBalance)
-        .tool(GetErc20Balance)
-        .tool(ApproveTokenForRouterSpend)
-        .tool(VerifySwapRouterHasAllowance)
-        .build())
+    AgentBuilder::new(AgentKind::Evm).build()
 }
@@ -0,0 +1,137 @@
+//! Local EVM node for the tests. Spawns anvil with the chain id of Sonic,
+//! or forking a live chain when ANVIL_FORK is set, and runs tool code
+//! against it with a funded dev account as the signer. Tests using it are
+//! skipped when anvil isn't installed, forks also need the network
+use std::future::Future;
+use std::sync::Arc;
+
+use alloy::node_bindings::{Anvil, AnvilInstance};
+use alloy::primitives::{Address, U256};
+use alloy::providers::Provider;
+use anyhow::Result;
+
+use super::util::{provider_for, EvmProvider};
+use crate::signer::evm::LocalEvmSigner;
+use crate::signer::SignerContext;
+
+const SONIC_CHAIN_ID: u64 = 146;
+
+pub struct LocalEvm {
+    anvil: AnvilInstance,
+}
+
+impl LocalEvm {
+    /// A fresh chain, None when anvil isn't installed
+    pub fn spawn() -> Option<Self> {
+        Self::launch(Anvil::new().chain_id(SONIC_CHAIN_ID))
+    }
+
+    /// A fork of the chain at `rpc_url` at its latest block, None unless
+    /// ANVIL_FORK is set. The dev accounts are funded on the fork too
+    pub fn fork(rpc_url: &str) -> Option<Self> {
+        if std::env::var("ANVIL_FORK").is_err() {
+            eprintln!("ANVIL_FORK not set, skipping the test");
+            return None;
+        }
+        Self::launch(Anvil::new().fork(rpc_url))
+    }
+
+    fn launch(anvil: Anvil) -> Option<Self> {
+        match anvil.try_spawn() {
+            Ok(anvil) => Some(Self { anvil }),
+            Err(e) => {
+                eprintln!("anvil not started ({}), skipping the test", e);
+                None
+            }
+        }
+    }
+
+    pub fn endpoint(&self) -> String {
+        self.anvil.endpoint()
+    }
+
+    pub fn provider(&self) -> EvmProvider {
+        provider_for(&self.endpoint()).expect("Invalid anvil endpoint")
+    }
+
+    /// Dev account `index`, anvil funds the first ten
+    pub fn account(&self, index: usize) -> Address {
+        self.anvil.addresses()[index]
+    }
+
+    /// Signer of the dev account `index`, sending to this node
+    pub fn signer(&self, index: usize) -> LocalEvmSigner {
+        let key = hex::encode(self.anvil.keys()[index].to_bytes());
+        LocalEvmSigner::new(key).with_rpc_url(&self.endpoint())
+    }
+
+    /// Sets the native balance of `address`
+    pub async fn fund(&self, address: Address, wei: U256) -> Result<()> {
+        self.provider()
+            .raw_request::<_, ()>("anvil_setBalance".into(), (address, wei))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn balance(&self, address: Address) -> Result<U256> {
+        Ok(self.provider().get_balance(address).await?)
+    }
+
+    /// Runs `future` with the dev account 0 as the signer
+    pub async fn run<T>(
+        &self,
+        future: impl Future<Output = Result<T>> + Send,
+    ) -> Result<T> {
+        self.run_as(self.signer(0), future).await
+    }
+
+    /// Runs `future` with `signer`, which should send to this node
+    pub async fn run_as<T>(
+        &self,
+        signer: LocalEvmSigner,
+        future: impl Future<Output = Result<T>> + Send,
+    ) -> Result<T> {
+        SignerContext::with_signer(Arc::new(signer), future).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::network::TransactionBuilder;
+    use alloy::rpc::types::TransactionRequest;
+
+    use super::*;
+    use crate::signer::TransactionSigner;
+
+    #[tokio::test]
+    async fn test_fund_and_sign() {
+        let Some(node) = LocalEvm::spawn() else {
+            return;
+        };
+        let sender = Address::repeat_byte(0x42);
+        node.fund(sender, U256::from(10u64.pow(18))).await.unwrap();
+        assert_eq!(
+            node.balance(sender).await.unwrap(),
+            U256::from(10u64.pow(18))
+        );
+
+        let recipient = node.account(1);
+        let before = node.balance(recipient).await.unwrap();
+        let gas_price = node.provider().get_gas_price().await.unwrap();
+        let tx = TransactionRequest::default()
+            .with_from(node.account(0))
+            .with_to(recipient)
+            .with_value(U256::from(1_000))
+            .with_gas_price(gas_price);
+        let hash = node
+            .signer(0)
+            .sign_and_send_evm_transaction(tx)
+            .await
+            .unwrap();
+        assert!(hash.starts_with("0x"));
+        assert_eq!(
+            node.balance(recipient).await.unwrap(),
+            before + U256::from(1_000)
+        );
+    }
+}
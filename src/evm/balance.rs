@@ -26,11 +26,20 @@ pub async fn token_balance(
         .await?
         ._0;
 
-    println!("IN BALANCE: {:#?}", balance);
-
     Ok(balance.to_string())
 }
 
+pub async fn token_decimals(
+    token_address: String,
+    provider: &EvmProvider,
+) -> Result<u8> {
+    Ok(IERC20::new(Address::from_str(&token_address)?, provider)
+        .decimals()
+        .call()
+        .await?
+        ._0)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::evm::util::{make_provider, make_signer};
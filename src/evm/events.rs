@@ -0,0 +1,345 @@
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+use std::time::Duration;
+
+use alloy::primitives::Address;
+use alloy::providers::{Provider, ProviderBuilder, WsConnect};
+use alloy::rpc::types::{Filter, Log};
+use alloy::sol_types::SolEvent;
+use anyhow::Result;
+use futures_util::StreamExt;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use super::abi::IERC20;
+use super::util::{make_provider, EvmProvider};
+use crate::config::config;
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const CHANNEL_CAPACITY: usize = 256;
+/// Events remembered to drop the copies the other subscription delivers
+const SEEN_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenEventKind {
+    Transfer,
+    Approval,
+}
+
+/// A decoded ERC20 Transfer/Approval log that involves the watched address.
+/// For approvals `from` is the owner and `to` is the spender.
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenEvent {
+    pub kind: TokenEventKind,
+    pub token: String,
+    pub from: String,
+    pub to: String,
+    pub amount: String,
+    pub incoming: bool,
+    pub tx_hash: Option<String>,
+    pub log_index: Option<u64>,
+    pub block_number: Option<u64>,
+}
+
+impl TokenEvent {
+    /// Identifies the log, a self-transfer matches both filters and is
+    /// delivered twice
+    fn key(&self) -> Option<(String, u64)> {
+        Some((self.tx_hash.clone()?, self.log_index?))
+    }
+}
+
+/// Keys of the last events delivered, bounded
+#[derive(Default)]
+struct SeenEvents {
+    keys: HashSet<(String, u64)>,
+    order: VecDeque<(String, u64)>,
+}
+
+impl SeenEvents {
+    /// Records the event, false when it was already delivered
+    fn insert(&mut self, event: &TokenEvent) -> bool {
+        let Some(key) = event.key() else {
+            return true;
+        };
+        if !self.keys.insert(key.clone()) {
+            return false;
+        }
+        self.order.push_back(key);
+        if self.order.len() > SEEN_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// Decodes a log into a `TokenEvent` relative to `watched`, returns None for
+/// logs that are not ERC20 Transfer/Approval or do not touch the address
+pub fn parse_token_event(log: &Log, watched: Address) -> Option<TokenEvent> {
+    let topic0 = *log.topic0()?;
+    let (kind, from, to, amount) =
+        if topic0 == IERC20::Transfer::SIGNATURE_HASH {
+            let event = log.log_decode::<IERC20::Transfer>().ok()?.inner.data;
+            (TokenEventKind::Transfer, event.from, event.to, event.value)
+        } else if topic0 == IERC20::Approval::SIGNATURE_HASH {
+            let event = log.log_decode::<IERC20::Approval>().ok()?.inner.data;
+            (
+                TokenEventKind::Approval,
+                event.owner,
+                event.spender,
+                event.value,
+            )
+        } else {
+            return None;
+        };
+
+    if from != watched && to != watched {
+        return None;
+    }
+
+    Some(TokenEvent {
+        kind,
+        token: log.address().to_string(),
+        from: from.to_string(),
+        to: to.to_string(),
+        amount: amount.to_string(),
+        incoming: kind == TokenEventKind::Transfer && to == watched,
+        tx_hash: log.transaction_hash.map(|h| h.to_string()),
+        log_index: log.log_index,
+        block_number: log.block_number,
+    })
+}
+
+/// Filters for logs involving `watched`: incoming transfers (topic2) and
+/// outgoing transfers + approvals granted by the address (topic1)
+pub fn make_filters(watched: Address) -> [Filter; 2] {
+    let topic = watched.into_word();
+    [
+        Filter::new()
+            .event_signature(IERC20::Transfer::SIGNATURE_HASH)
+            .topic2(topic),
+        Filter::new()
+            .event_signature(vec![
+                IERC20::Transfer::SIGNATURE_HASH,
+                IERC20::Approval::SIGNATURE_HASH,
+            ])
+            .topic1(topic),
+    ]
+}
+
+pub struct EvmEventWatcher {
+    provider: EvmProvider,
+    watched: Address,
+    poll_interval: Duration,
+}
+
+impl EvmEventWatcher {
+    pub fn new(provider: EvmProvider, watched: Address) -> Self {
+        Self {
+            provider,
+            watched,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Fetches the matching logs in the inclusive block range
+    pub async fn poll_range(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<TokenEvent>> {
+        let mut events = vec![];
+        let mut seen = SeenEvents::default();
+        for filter in make_filters(self.watched) {
+            let logs = self
+                .provider
+                .get_logs(&filter.from_block(from_block).to_block(to_block))
+                .await?;
+            events.extend(
+                logs.iter()
+                    .filter_map(|log| parse_token_event(log, self.watched))
+                    .filter(|event| seen.insert(event)),
+            );
+        }
+        events.sort_by_key(|e| (e.block_number, e.log_index));
+        Ok(events)
+    }
+
+    /// Starts polling from the current head, the returned receiver yields
+    /// every new event until it is dropped
+    pub fn spawn(self) -> mpsc::Receiver<TokenEvent> {
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+
+        tokio::spawn(async move {
+            let mut last_block = match self.provider.get_block_number().await
+            {
+                Ok(block) => block,
+                Err(e) => {
+                    tracing::error!(?e, "events: failed to fetch head block");
+                    return;
+                }
+            };
+
+            loop {
+                tokio::time::sleep(self.poll_interval).await;
+
+                let head = match self.provider.get_block_number().await {
+                    Ok(head) => head,
+                    Err(e) => {
+                        tracing::warn!(?e, "events: failed to fetch head");
+                        continue;
+                    }
+                };
+                if head <= last_block {
+                    continue;
+                }
+
+                match self.poll_range(last_block + 1, head).await {
+                    Ok(events) => {
+                        for event in events {
+                            if tx.send(event).await.is_err() {
+                                return; // receiver dropped
+                            }
+                        }
+                        last_block = head;
+                    }
+                    Err(e) => {
+                        tracing::warn!(?e, "events: failed to fetch logs");
+                    }
+                }
+            }
+        });
+
+        rx
+    }
+}
+
+/// Subscribes to the logs over websocket, lower latency than polling
+pub async fn subscribe_ws(
+    ws_url: &str,
+    watched: Address,
+) -> Result<mpsc::Receiver<TokenEvent>> {
+    let provider =
+        ProviderBuilder::new().on_ws(WsConnect::new(ws_url)).await?;
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+
+    let mut streams = vec![];
+    for filter in make_filters(watched) {
+        let stream = provider.subscribe_logs(&filter).await?.into_stream();
+        streams.push(Box::pin(stream));
+    }
+    let mut logs = futures_util::stream::select_all(streams);
+    tokio::spawn(async move {
+        // the task holds the provider so the ws connection stays alive
+        let _provider = provider;
+        let mut seen = SeenEvents::default();
+        while let Some(log) = logs.next().await {
+            let Some(event) = parse_token_event(&log, watched) else {
+                continue;
+            };
+            if seen.insert(&event) && tx.send(event).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+/// Watches the address over the websocket RPC when configured, otherwise
+/// falls back to polling the HTTP one
+pub async fn watch_address(
+    address: &str,
+) -> Result<mpsc::Receiver<TokenEvent>> {
+    let watched = Address::from_str(address)?;
+    match &config().rpc.ethereum_ws {
+        Some(ws_url) => subscribe_ws(ws_url, watched).await,
+        None => Ok(EvmEventWatcher::new(make_provider()?, watched).spawn()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::{address, U256};
+
+    fn make_log(event: impl SolEvent, token: Address) -> Log {
+        Log {
+            inner: alloy::primitives::Log {
+                address: token,
+                data: event.encode_log_data(),
+            },
+            block_number: Some(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_incoming_transfer() {
+        let user = address!("2fAA30d5EdDF1e4fa126aEdA79159878D58A2438");
+        let other = address!("CCC48877a33a2C14e40c82da843Cf4c607ABF770");
+        let token = address!("29219dd400f2Bf60E5a23d13Be72B486D4038894");
+        let log = make_log(
+            IERC20::Transfer {
+                from: other,
+                to: user,
+                value: U256::from(1_000_000u64),
+            },
+            token,
+        );
+
+        let event = parse_token_event(&log, user).unwrap();
+        assert_eq!(event.kind, TokenEventKind::Transfer);
+        assert!(event.incoming);
+        assert_eq!(event.amount, "1000000");
+        assert_eq!(event.token, token.to_string());
+    }
+
+    #[test]
+    fn test_parse_ignores_unrelated() {
+        let user = address!("2fAA30d5EdDF1e4fa126aEdA79159878D58A2438");
+        let log = make_log(
+            IERC20::Approval {
+                owner: address!("CCC48877a33a2C14e40c82da843Cf4c607ABF770"),
+                spender: address!("1231DEB6f5749EF6cE6943a275A1D3E7486F4EaE"),
+                value: U256::MAX,
+            },
+            Address::ZERO,
+        );
+
+        assert!(parse_token_event(&log, user).is_none());
+    }
+
+    #[test]
+    fn test_self_transfer_delivered_once() {
+        let user = address!("2fAA30d5EdDF1e4fa126aEdA79159878D58A2438");
+        let mut log = make_log(
+            IERC20::Transfer {
+                from: user,
+                to: user,
+                value: U256::from(5u64),
+            },
+            Address::ZERO,
+        );
+        log.transaction_hash = Some(Default::default());
+        log.log_index = Some(3);
+
+        // matched by the incoming and the outgoing filter
+        let event = parse_token_event(&log, user).unwrap();
+        let mut seen = SeenEvents::default();
+        assert!(seen.insert(&event));
+        assert!(!seen.insert(&event.clone()));
+
+        log.log_index = Some(4);
+        let next = parse_token_event(&log, user).unwrap();
+        assert!(seen.insert(&next));
+    }
+}
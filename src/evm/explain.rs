@@ -0,0 +1,314 @@
+//! Explanations of EVM transactions. The calldata is matched against a
+//! database of 4-byte selectors of the contracts the agent talks to
+//! (ERC20, WETH, Uniswap, the Sonic gateway), token transfers and approvals
+//! are decoded down to the amounts
+use std::collections::HashMap;
+
+use alloy::consensus::{Transaction as _, TxEnvelope};
+use alloy::eips::eip2718::Decodable2718;
+use alloy::primitives::utils::format_ether;
+use alloy::primitives::{keccak256, Address, B256, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionRequest;
+use alloy::sol;
+use alloy::sol_types::SolCall;
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+
+use super::abi::IERC20;
+use super::util::make_provider;
+use crate::common::evm_chain;
+use crate::explain::Explanation;
+use crate::tool_error::ToolError;
+
+sol! {
+    interface IExplained {
+        function transferFrom(address from, address to, uint256 amount);
+        function withdraw(uint256 amount);
+    }
+}
+
+/// Function signatures and what calling them means
+const FUNCTIONS: &[(&str, &str)] = &[
+    ("transfer(address,uint256)", "Transfer tokens"),
+    ("approve(address,uint256)", "Approve spending of tokens"),
+    ("transferFrom(address,address,uint256)", "Transfer tokens"),
+    ("increaseAllowance(address,uint256)", "Raise a token allowance"),
+    ("deposit()", "Wrap the native token"),
+    ("withdraw(uint256)", "Unwrap the native token"),
+    (
+        "approve(address,address,uint160,uint48)",
+        "Approve spending of tokens through Permit2",
+    ),
+    (
+        "exactInputSingle((address,address,uint24,address,uint256,uint256,uint256,uint160))",
+        "Swap on Uniswap V3",
+    ),
+    (
+        "exactInputSingle((address,address,uint24,address,uint256,uint256,uint160))",
+        "Swap on Uniswap V3",
+    ),
+    (
+        "exactInput((bytes,address,uint256,uint256,uint256))",
+        "Swap on Uniswap V3",
+    ),
+    (
+        "exactOutputSingle((address,address,uint24,address,uint256,uint256,uint256,uint160))",
+        "Swap on Uniswap V3",
+    ),
+    ("multicall(bytes[])", "Run several router calls"),
+    ("multicall(uint256,bytes[])", "Run several router calls"),
+    (
+        "swapExactTokensForTokens(uint256,uint256,address[],address,uint256)",
+        "Swap on a Uniswap V2 style router",
+    ),
+    (
+        "swapExactETHForTokens(uint256,address[],address,uint256)",
+        "Swap on a Uniswap V2 style router",
+    ),
+    (
+        "swapExactTokensForETH(uint256,uint256,address[],address,uint256)",
+        "Swap on a Uniswap V2 style router",
+    ),
+    (
+        "mint((address,address,uint24,int24,int24,uint256,uint256,uint256,uint256,address,uint256))",
+        "Open a Uniswap V3 liquidity position",
+    ),
+    (
+        "increaseLiquidity((uint256,uint256,uint256,uint256,uint256,uint256))",
+        "Add liquidity to a Uniswap V3 position",
+    ),
+    (
+        "decreaseLiquidity((uint256,uint128,uint256,uint256,uint256))",
+        "Remove liquidity from a Uniswap V3 position",
+    ),
+    (
+        "collect((uint256,address,uint128,uint128))",
+        "Collect the fees of a Uniswap V3 position",
+    ),
+    (
+        "deposit(uint96,address,uint256)",
+        "Bridge tokens to Sonic through the gateway",
+    ),
+    (
+        "withdraw(uint96,address,uint256)",
+        "Bridge tokens to Ethereum through the gateway",
+    ),
+    (
+        "claim(uint256,address,uint256,bytes)",
+        "Claim tokens bridged through the Sonic gateway",
+    ),
+];
+
+static SELECTORS: Lazy<HashMap<[u8; 4], (&str, &str)>> = Lazy::new(|| {
+    FUNCTIONS
+        .iter()
+        .map(|&(signature, label)| {
+            let hash = keccak256(signature);
+            ([hash[0], hash[1], hash[2], hash[3]], (signature, label))
+        })
+        .collect()
+});
+
+/// Allowances this large are as good as unlimited
+fn is_unlimited(amount: U256) -> bool {
+    amount >= U256::from(u128::MAX)
+}
+
+fn describe_call(
+    explanation: &mut Explanation,
+    to: &str,
+    data: &[u8],
+) -> String {
+    let selector = match data.get(..4) {
+        Some(selector) => {
+            [selector[0], selector[1], selector[2], selector[3]]
+        }
+        None => return format!("Call {} with malformed calldata", to),
+    };
+    let (signature, label) = match SELECTORS.get(&selector) {
+        Some(function) => *function,
+        None => {
+            explanation.warn(format!(
+                "Unknown function 0x{} on {}",
+                hex::encode(selector),
+                to
+            ));
+            return format!(
+                "Call the unknown function 0x{} on {}",
+                hex::encode(selector),
+                to
+            );
+        }
+    };
+    match signature {
+        "transfer(address,uint256)" => {
+            if let Ok(call) = IERC20::transferCall::abi_decode(data, true) {
+                return format!(
+                    "Transfer {} raw units of the token {} to {}",
+                    call.amount, to, call.to
+                );
+            }
+        }
+        "transferFrom(address,address,uint256)" => {
+            if let Ok(call) =
+                IExplained::transferFromCall::abi_decode(data, true)
+            {
+                return format!(
+                    "Transfer {} raw units of the token {} from {} to {}",
+                    call.amount, to, call.from, call.to
+                );
+            }
+        }
+        "approve(address,uint256)" => {
+            if let Ok(call) = IERC20::approveCall::abi_decode(data, true) {
+                if is_unlimited(call.amount) {
+                    explanation.warn(format!(
+                        "{} gets an unlimited allowance on the token {}",
+                        call.spender, to
+                    ));
+                    return format!(
+                        "Allow {} to spend an unlimited amount of the token {}",
+                        call.spender, to
+                    );
+                }
+                return format!(
+                    "Allow {} to spend {} raw units of the token {}",
+                    call.spender, call.amount, to
+                );
+            }
+        }
+        "withdraw(uint256)" => {
+            if let Ok(call) = IExplained::withdrawCall::abi_decode(data, true)
+            {
+                return format!(
+                    "Unwrap {} of the native token from {}",
+                    format_ether(call.amount),
+                    to
+                );
+            }
+        }
+        _ => {}
+    }
+    format!("{}: calls {} on {}", label, signature, to)
+}
+
+/// Explains a call to `to`, None being a contract deployment
+pub fn explain_call(
+    chain: &str,
+    to: Option<Address>,
+    value: U256,
+    input: &[u8],
+) -> Explanation {
+    let mut explanation = Explanation::new(chain);
+    let to = match to {
+        Some(to) => to.to_string(),
+        None => {
+            explanation.step(format!(
+                "Deploy a contract with {} bytes of code",
+                input.len()
+            ));
+            return explanation;
+        }
+    };
+    if input.is_empty() {
+        explanation.step(format!(
+            "Send {} of the native token to {}",
+            format_ether(value),
+            to
+        ));
+        return explanation;
+    }
+    let step = describe_call(&mut explanation, &to, input);
+    explanation.step(step);
+    if !value.is_zero() {
+        explanation.step(format!(
+            "Send {} of the native token along with the call",
+            format_ether(value)
+        ));
+    }
+    explanation
+}
+
+/// Explains a transaction about to be signed
+pub fn explain_request(tx: &TransactionRequest) -> Explanation {
+    let chain = tx.chain_id.map_or_else(evm_chain, |id| id.to_string());
+    let input = tx.input.input().map(|input| input.as_ref());
+    explain_call(
+        &chain,
+        tx.to.and_then(|kind| kind.to().copied()),
+        tx.value.unwrap_or_default(),
+        input.unwrap_or_default(),
+    )
+}
+
+async fn explain_hash(hash: B256) -> Result<Explanation> {
+    let provider = make_provider()?;
+    let tx = provider
+        .get_transaction_by_hash(hash)
+        .await?
+        .ok_or_else(|| anyhow!("Transaction {} not found", hash))?;
+    let mut explanation =
+        explain_call(&evm_chain(), tx.to(), tx.value(), tx.input());
+    if let Some(receipt) = provider.get_transaction_receipt(hash).await? {
+        if !receipt.status() {
+            explanation.warn("The transaction failed");
+        }
+    }
+    Ok(explanation)
+}
+
+/// Explains a 0x-prefixed transaction hash, signed raw transaction or bare
+/// calldata
+pub async fn explain(input: &str) -> Result<Explanation> {
+    let bytes =
+        hex::decode(input.trim_start_matches("0x")).map_err(|_| {
+            ToolError::invalid_input(format!("{} is not hex encoded", input))
+        })?;
+    if bytes.len() == 32 {
+        return explain_hash(B256::from_slice(&bytes)).await;
+    }
+    if let Ok(tx) = TxEnvelope::decode_2718(&mut bytes.as_slice()) {
+        return Ok(explain_call(
+            &tx.chain_id().map_or_else(evm_chain, |id| id.to_string()),
+            tx.to(),
+            tx.value(),
+            tx.input(),
+        ));
+    }
+    let mut explanation = Explanation::new(&evm_chain());
+    let step = describe_call(&mut explanation, "the contract", &bytes);
+    explanation.step(step);
+    Ok(explanation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain_approve() {
+        let token = Address::repeat_byte(1);
+        let spender = Address::repeat_byte(2);
+        let data = IERC20::approveCall {
+            spender,
+            amount: U256::MAX,
+        }
+        .abi_encode();
+
+        let explanation =
+            explain_call("sonic", Some(token), U256::ZERO, &data);
+        assert_eq!(
+            explanation.steps,
+            [format!(
+                "Allow {} to spend an unlimited amount of the token {}",
+                spender, token
+            )]
+        );
+        assert_eq!(explanation.warnings.len(), 1);
+
+        let explanation =
+            explain_call("sonic", Some(token), U256::ZERO, &[1, 2, 3, 4]);
+        assert_eq!(explanation.warnings, ["Unknown function 0x01020304 on 0x0101010101010101010101010101010101010101"]);
+    }
+}
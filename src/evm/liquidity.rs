@@ -0,0 +1,488 @@
+use std::str::FromStr;
+
+use alloy::network::TransactionBuilder;
+use alloy::primitives::aliases::{I24, U24};
+use alloy::primitives::{Address, U160, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionRequest;
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use uniswap_sdk_core::prelude::NONFUNGIBLE_POSITION_MANAGER_ADDRESSES;
+
+use super::abi::{
+    INonfungiblePositionManager, IUniswapV3Factory, IUniswapV3Pool, IERC20,
+};
+use super::util::EvmProvider;
+
+/// 20 minutes, same as the uniswap interface default
+const DEADLINE_SECS: i64 = 1200;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PositionInfo {
+    pub token_id: String,
+    pub token0: String,
+    pub token1: String,
+    pub fee: u32,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    pub liquidity: String,
+    pub tokens_owed0: String,
+    pub tokens_owed1: String,
+    pub current_tick: i32,
+    pub in_range: bool,
+}
+
+/// Sonic CL DEXes are not part of the uniswap address book, for those the
+/// manager is configured with EVM_POSITION_MANAGER_ADDRESS
+pub async fn position_manager_address(
+    provider: &EvmProvider,
+) -> Result<Address> {
+    if let Ok(address) = std::env::var("EVM_POSITION_MANAGER_ADDRESS") {
+        return Ok(Address::from_str(&address)?);
+    }
+    let chain_id = provider.get_chain_id().await?;
+    NONFUNGIBLE_POSITION_MANAGER_ADDRESSES
+        .get(&chain_id)
+        .copied()
+        .ok_or_else(|| {
+            anyhow!(
+                "Position manager not known for chain {}, set EVM_POSITION_MANAGER_ADDRESS",
+                chain_id
+            )
+        })
+}
+
+pub fn sqrt_price_at_tick(tick: i32) -> f64 {
+    1.0001f64.powf(tick as f64 / 2.0)
+}
+
+pub fn sqrt_price_from_x96(sqrt_price_x96: U160) -> f64 {
+    sqrt_price_x96.to_string().parse::<f64>().unwrap_or(0.0) / 2f64.powi(96)
+}
+
+/// Token amounts represented by `liquidity` in the [tick_lower, tick_upper)
+/// range at the given sqrt price
+pub fn amounts_for_liquidity(
+    sqrt_price: f64,
+    tick_lower: i32,
+    tick_upper: i32,
+    liquidity: f64,
+) -> (f64, f64) {
+    let sa = sqrt_price_at_tick(tick_lower);
+    let sb = sqrt_price_at_tick(tick_upper);
+    if sqrt_price <= sa {
+        (liquidity * (sb - sa) / (sa * sb), 0.0)
+    } else if sqrt_price < sb {
+        (
+            liquidity * (sb - sqrt_price) / (sqrt_price * sb),
+            liquidity * (sqrt_price - sa),
+        )
+    } else {
+        (0.0, liquidity * (sb - sa))
+    }
+}
+
+/// Max liquidity that can be minted from the desired amounts, the limiting
+/// token determines the result, mirrors LiquidityAmounts.sol
+pub fn liquidity_for_amounts(
+    sqrt_price: f64,
+    tick_lower: i32,
+    tick_upper: i32,
+    amount0: f64,
+    amount1: f64,
+) -> f64 {
+    let sa = sqrt_price_at_tick(tick_lower);
+    let sb = sqrt_price_at_tick(tick_upper);
+    if sqrt_price <= sa {
+        amount0 * sa * sb / (sb - sa)
+    } else if sqrt_price < sb {
+        let l0 = amount0 * sqrt_price * sb / (sb - sqrt_price);
+        let l1 = amount1 / (sqrt_price - sa);
+        l0.min(l1)
+    } else {
+        amount1 / (sb - sa)
+    }
+}
+
+fn min_amount(amount: f64, slippage_bps: u16) -> U256 {
+    let min = amount * (10_000 - slippage_bps.min(10_000)) as f64 / 10_000.0;
+    U256::from(min.max(0.0).floor() as u128)
+}
+
+fn deadline() -> U256 {
+    U256::from(chrono::Utc::now().timestamp() + DEADLINE_SECS)
+}
+
+fn parse_u256(amount: &str) -> Result<f64> {
+    Ok(U256::from_str(amount)?.to_string().parse::<f64>()?)
+}
+
+async fn pool_state(
+    manager: Address,
+    token0: Address,
+    token1: Address,
+    fee: U24,
+    provider: &EvmProvider,
+) -> Result<(f64, i32)> {
+    let factory = INonfungiblePositionManager::new(manager, provider)
+        .factory()
+        .call()
+        .await?
+        ._0;
+    let pool = IUniswapV3Factory::new(factory, provider)
+        .getPool(token0, token1, fee)
+        .call()
+        .await?
+        .pool;
+    if pool == Address::ZERO {
+        return Err(anyhow!("Pool does not exist for this pair and fee"));
+    }
+    let slot0 = IUniswapV3Pool::new(pool, provider).slot0().call().await?;
+    Ok((sqrt_price_from_x96(slot0.sqrtPriceX96), slot0.tick.as_i32()))
+}
+
+async fn ensure_allowance(
+    token: Address,
+    owner: Address,
+    spender: Address,
+    amount: U256,
+    provider: &EvmProvider,
+) -> Result<()> {
+    let allowance = IERC20::new(token, provider)
+        .allowance(owner, spender)
+        .call()
+        .await?
+        ._0;
+    if allowance < amount {
+        return Err(anyhow!(
+            "Allowance for token {} is not set for the position manager {}, approve it first",
+            token,
+            spender
+        ));
+    }
+    Ok(())
+}
+
+pub async fn list_positions(
+    owner: Address,
+    provider: &EvmProvider,
+) -> Result<Vec<PositionInfo>> {
+    let manager_address = position_manager_address(provider).await?;
+    let manager = INonfungiblePositionManager::new(manager_address, provider);
+
+    let count = manager.balanceOf(owner).call().await?._0;
+    let mut positions = vec![];
+    for index in 0..count.to::<u64>() {
+        let token_id = manager
+            .tokenOfOwnerByIndex(owner, U256::from(index))
+            .call()
+            .await?
+            ._0;
+        let position = manager.positions(token_id).call().await?;
+        let (_, current_tick) = pool_state(
+            manager_address,
+            position.token0,
+            position.token1,
+            position.fee,
+            provider,
+        )
+        .await?;
+        let tick_lower = position.tickLower.as_i32();
+        let tick_upper = position.tickUpper.as_i32();
+
+        positions.push(PositionInfo {
+            token_id: token_id.to_string(),
+            token0: position.token0.to_string(),
+            token1: position.token1.to_string(),
+            fee: position.fee.to::<u32>(),
+            tick_lower,
+            tick_upper,
+            liquidity: position.liquidity.to_string(),
+            tokens_owed0: position.tokensOwed0.to_string(),
+            tokens_owed1: position.tokensOwed1.to_string(),
+            current_tick,
+            in_range: tick_lower <= current_tick && current_tick < tick_upper,
+        });
+    }
+
+    Ok(positions)
+}
+
+/// Tokens of the position `token_id`, token0 then token1
+pub async fn position_tokens(
+    token_id: &str,
+    provider: &EvmProvider,
+) -> Result<(Address, Address)> {
+    let token_id = U256::from_str(token_id)?;
+    let manager = position_manager_address(provider).await?;
+    let position = INonfungiblePositionManager::new(manager, provider)
+        .positions(token_id)
+        .call()
+        .await
+        .context("Failed to fetch position")?;
+    Ok((position.token0, position.token1))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn create_mint_position_tx(
+    token_a: String,
+    token_b: String,
+    fee: u32,
+    tick_lower: i32,
+    tick_upper: i32,
+    amount_a: String,
+    amount_b: String,
+    slippage_bps: u16,
+    provider: &EvmProvider,
+    owner: Address,
+) -> Result<TransactionRequest> {
+    if tick_lower >= tick_upper {
+        return Err(anyhow!("tick_lower has to be below tick_upper"));
+    }
+    let token_a = Address::from_str(&token_a)?;
+    let token_b = Address::from_str(&token_b)?;
+    // the manager requires sorted tokens
+    let (token0, token1, amount0, amount1) = if token_a < token_b {
+        (token_a, token_b, amount_a, amount_b)
+    } else {
+        (token_b, token_a, amount_b, amount_a)
+    };
+    let amount0_desired = U256::from_str(&amount0)?;
+    let amount1_desired = U256::from_str(&amount1)?;
+    let fee = U24::from(fee);
+
+    let manager = position_manager_address(provider).await?;
+    ensure_allowance(token0, owner, manager, amount0_desired, provider)
+        .await?;
+    ensure_allowance(token1, owner, manager, amount1_desired, provider)
+        .await?;
+
+    let (sqrt_price, _) =
+        pool_state(manager, token0, token1, fee, provider).await?;
+    let liquidity = liquidity_for_amounts(
+        sqrt_price,
+        tick_lower,
+        tick_upper,
+        parse_u256(&amount0)?,
+        parse_u256(&amount1)?,
+    );
+    let (expected0, expected1) =
+        amounts_for_liquidity(sqrt_price, tick_lower, tick_upper, liquidity);
+
+    let call = INonfungiblePositionManager::mintCall {
+        params: INonfungiblePositionManager::MintParams {
+            token0,
+            token1,
+            fee,
+            tickLower: I24::try_from(tick_lower)?,
+            tickUpper: I24::try_from(tick_upper)?,
+            amount0Desired: amount0_desired,
+            amount1Desired: amount1_desired,
+            amount0Min: min_amount(expected0, slippage_bps),
+            amount1Min: min_amount(expected1, slippage_bps),
+            recipient: owner,
+            deadline: deadline(),
+        },
+    };
+
+    make_manager_tx(manager, owner, &call, provider).await
+}
+
+pub async fn create_increase_liquidity_tx(
+    token_id: String,
+    amount0: String,
+    amount1: String,
+    slippage_bps: u16,
+    provider: &EvmProvider,
+    owner: Address,
+) -> Result<TransactionRequest> {
+    let token_id = U256::from_str(&token_id)?;
+    let amount0_desired = U256::from_str(&amount0)?;
+    let amount1_desired = U256::from_str(&amount1)?;
+
+    let manager = position_manager_address(provider).await?;
+    let position = INonfungiblePositionManager::new(manager, provider)
+        .positions(token_id)
+        .call()
+        .await
+        .context("Failed to fetch position")?;
+    ensure_allowance(
+        position.token0,
+        owner,
+        manager,
+        amount0_desired,
+        provider,
+    )
+    .await?;
+    ensure_allowance(
+        position.token1,
+        owner,
+        manager,
+        amount1_desired,
+        provider,
+    )
+    .await?;
+
+    let (tick_lower, tick_upper) =
+        (position.tickLower.as_i32(), position.tickUpper.as_i32());
+    let (sqrt_price, _) = pool_state(
+        manager,
+        position.token0,
+        position.token1,
+        position.fee,
+        provider,
+    )
+    .await?;
+    let liquidity = liquidity_for_amounts(
+        sqrt_price,
+        tick_lower,
+        tick_upper,
+        parse_u256(&amount0)?,
+        parse_u256(&amount1)?,
+    );
+    let (expected0, expected1) =
+        amounts_for_liquidity(sqrt_price, tick_lower, tick_upper, liquidity);
+
+    let call = INonfungiblePositionManager::increaseLiquidityCall {
+        params: INonfungiblePositionManager::IncreaseLiquidityParams {
+            tokenId: token_id,
+            amount0Desired: amount0_desired,
+            amount1Desired: amount1_desired,
+            amount0Min: min_amount(expected0, slippage_bps),
+            amount1Min: min_amount(expected1, slippage_bps),
+            deadline: deadline(),
+        },
+    };
+
+    make_manager_tx(manager, owner, &call, provider).await
+}
+
+/// Removes `percent` (1-100) of the position liquidity, the withdrawn tokens
+/// are credited to the position and need to be collected afterwards
+pub async fn create_decrease_liquidity_tx(
+    token_id: String,
+    percent: u8,
+    slippage_bps: u16,
+    provider: &EvmProvider,
+    owner: Address,
+) -> Result<TransactionRequest> {
+    if percent == 0 || percent > 100 {
+        return Err(anyhow!("percent has to be between 1 and 100"));
+    }
+    let token_id = U256::from_str(&token_id)?;
+
+    let manager = position_manager_address(provider).await?;
+    let position = INonfungiblePositionManager::new(manager, provider)
+        .positions(token_id)
+        .call()
+        .await
+        .context("Failed to fetch position")?;
+    if position.liquidity == 0 {
+        return Err(anyhow!("Position has no liquidity"));
+    }
+    let liquidity = (U256::from(position.liquidity) * U256::from(percent)
+        / U256::from(100))
+    .to::<u128>();
+
+    let (sqrt_price, _) = pool_state(
+        manager,
+        position.token0,
+        position.token1,
+        position.fee,
+        provider,
+    )
+    .await?;
+    let (expected0, expected1) = amounts_for_liquidity(
+        sqrt_price,
+        position.tickLower.as_i32(),
+        position.tickUpper.as_i32(),
+        liquidity as f64,
+    );
+
+    let call = INonfungiblePositionManager::decreaseLiquidityCall {
+        params: INonfungiblePositionManager::DecreaseLiquidityParams {
+            tokenId: token_id,
+            liquidity,
+            amount0Min: min_amount(expected0, slippage_bps),
+            amount1Min: min_amount(expected1, slippage_bps),
+            deadline: deadline(),
+        },
+    };
+
+    make_manager_tx(manager, owner, &call, provider).await
+}
+
+pub async fn create_collect_fees_tx(
+    token_id: String,
+    provider: &EvmProvider,
+    owner: Address,
+) -> Result<TransactionRequest> {
+    let manager = position_manager_address(provider).await?;
+    let call = INonfungiblePositionManager::collectCall {
+        params: INonfungiblePositionManager::CollectParams {
+            tokenId: U256::from_str(&token_id)?,
+            recipient: owner,
+            amount0Max: u128::MAX,
+            amount1Max: u128::MAX,
+        },
+    };
+
+    make_manager_tx(manager, owner, &call, provider).await
+}
+
+async fn make_manager_tx(
+    manager: Address,
+    owner: Address,
+    call: &impl alloy::sol_types::SolCall,
+    provider: &EvmProvider,
+) -> Result<TransactionRequest> {
+    let gas_price = provider
+        .get_gas_price()
+        .await
+        .context("Failed to get gas price")?;
+
+    Ok(TransactionRequest::default()
+        .with_from(owner)
+        .with_to(manager)
+        .with_call(call)
+        .with_gas_price(gas_price))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_liquidity_roundtrip_in_range() {
+        let sqrt_price = sqrt_price_at_tick(0);
+        let liquidity =
+            liquidity_for_amounts(sqrt_price, -600, 600, 1e18, 1e18);
+        let (amount0, amount1) =
+            amounts_for_liquidity(sqrt_price, -600, 600, liquidity);
+
+        // symmetric range around the current price uses both sides fully
+        assert!((amount0 - 1e18).abs() / 1e18 < 1e-9);
+        assert!((amount1 - 1e18).abs() / 1e18 < 1e-9);
+    }
+
+    #[test]
+    fn test_out_of_range_is_single_sided() {
+        let sqrt_price = sqrt_price_at_tick(1000);
+        let (amount0, amount1) =
+            amounts_for_liquidity(sqrt_price, -600, 600, 1e18);
+        assert_eq!(amount0, 0.0);
+        assert!(amount1 > 0.0);
+
+        let sqrt_price = sqrt_price_at_tick(-1000);
+        let (amount0, amount1) =
+            amounts_for_liquidity(sqrt_price, -600, 600, 1e18);
+        assert!(amount0 > 0.0);
+        assert_eq!(amount1, 0.0);
+    }
+
+    #[test]
+    fn test_min_amount() {
+        assert_eq!(min_amount(10_000.0, 100), U256::from(9_900u64));
+        assert_eq!(min_amount(10_000.0, 0), U256::from(10_000u64));
+    }
+}
@@ -1,8 +1,14 @@
 pub mod abi;
 pub mod agent;
+#[cfg(test)]
+pub(crate) mod anvil;
 pub mod balance;
 pub mod data;
+pub mod events;
+pub mod explain;
+pub mod liquidity;
 pub mod price;
+pub mod simulate;
 pub mod tools;
 pub mod trade;
 pub mod transaction;
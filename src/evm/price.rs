@@ -1 +1,194 @@
-//
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use alloy::primitives::{address, b256, Address, B256};
+use alloy::providers::Provider;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use super::abi::{AggregatorV3Interface, IPyth};
+use super::util::EvmProvider;
+
+/// Prices older than this are rejected, Pyth on-chain prices are only as
+/// fresh as the last update someone pushed
+const MAX_PRICE_AGE_SECS: u64 = 3600;
+
+/// Pyth contract address on Sonic, override with PYTH_CONTRACT_ADDRESS
+const PYTH_SONIC: Address =
+    address!("2880aB155794e7179c9eE2e38200202908C17B43");
+
+const SONIC_CHAIN_ID: u64 = 146;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsdPrice {
+    pub symbol: String,
+    pub price: f64,
+    pub source: &'static str,
+    pub updated_at: u64,
+}
+
+/// Chainlink <SYMBOL>/USD aggregators on Ethereum mainnet
+fn chainlink_feed(chain_id: u64, symbol: &str) -> Option<Address> {
+    match (chain_id, symbol) {
+        (1, "ETH") => {
+            Some(address!("5f4eC3Df9cbd43714FE2740f5E3616155c5b8419"))
+        }
+        (1, "BTC") => {
+            Some(address!("F4030086522a5bEEa4988F8cA5B36dbC97BeE88c"))
+        }
+        (1, "USDC") => {
+            Some(address!("8fFfFfd4AfB6115b954Bd326cbe7B4BA576818f6"))
+        }
+        (1, "USDT") => {
+            Some(address!("3E7d1eAB13ad0104d2750B8863b489D65364e32D"))
+        }
+        (1, "LINK") => {
+            Some(address!("2c1d072e956AFFC0D435Cb7AC38EF18d24d9127c"))
+        }
+        _ => None,
+    }
+}
+
+/// Pyth <SYMBOL>/USD price feed ids, these are the same on every chain
+fn pyth_feed_id(symbol: &str) -> Option<B256> {
+    match symbol {
+        "S" => Some(b256!(
+            "f490b178d0c85683b7a0f2388b40af2e6f7c90cbe0f96b31f315f08d0e5a2d6d"
+        )),
+        "ETH" => Some(b256!(
+            "ff61491a931112ddf1bd8147cd1b641375f79f5825126d665480874634fd0ace"
+        )),
+        "BTC" => Some(b256!(
+            "e62df6c8b4a85fe1a67db44dc12de5db330f7ac66b72dc658afedf0f4a415b43"
+        )),
+        "USDC" => Some(b256!(
+            "eaa020c61cc479712813461ce153894a96a6c00b21ed0cfc2798d1f9a9e9c94a"
+        )),
+        "USDT" => Some(b256!(
+            "2b89b9dc8fdf9f34709a5b106b472f0f39bb6ca9ce04b0fd7f2e971688e2e53b"
+        )),
+        _ => None,
+    }
+}
+
+/// Wrapped and bridged variants are priced as the underlying asset
+fn normalize_symbol(symbol: &str) -> String {
+    match symbol.to_uppercase().as_str() {
+        "WS" | "SONIC" => "S".to_string(),
+        "WETH" => "ETH".to_string(),
+        "WBTC" => "BTC".to_string(),
+        "USDC.E" => "USDC".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn pyth_address(chain_id: u64) -> Result<Address> {
+    match std::env::var("PYTH_CONTRACT_ADDRESS") {
+        Ok(address) => Ok(Address::from_str(&address)?),
+        Err(_) if chain_id == SONIC_CHAIN_ID => Ok(PYTH_SONIC),
+        Err(_) => {
+            Err(anyhow!("Pyth contract not configured for {}", chain_id))
+        }
+    }
+}
+
+fn check_fresh(updated_at: u64) -> Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    if now.saturating_sub(updated_at) > MAX_PRICE_AGE_SECS {
+        return Err(anyhow!(
+            "Price is stale, last updated at {}",
+            updated_at
+        ));
+    }
+    Ok(())
+}
+
+pub async fn chainlink_price(
+    feed: Address,
+    provider: &EvmProvider,
+) -> Result<(f64, u64)> {
+    let aggregator = AggregatorV3Interface::new(feed, provider);
+    let decimals = aggregator.decimals().call().await?._0;
+    let round = aggregator.latestRoundData().call().await?;
+
+    let updated_at = round.updatedAt.to::<u64>();
+    check_fresh(updated_at)?;
+
+    let answer: f64 = round.answer.to_string().parse()?;
+    Ok((answer / 10f64.powi(decimals as i32), updated_at))
+}
+
+pub async fn pyth_price(
+    pyth: Address,
+    feed_id: B256,
+    provider: &EvmProvider,
+) -> Result<(f64, u64)> {
+    let price = IPyth::new(pyth, provider)
+        .getPriceUnsafe(feed_id)
+        .call()
+        .await?
+        .price;
+
+    let updated_at = price.publishTime.to::<u64>();
+    check_fresh(updated_at)?;
+
+    Ok((price.price as f64 * 10f64.powi(price.expo), updated_at))
+}
+
+/// Fetches the USD price of a major asset on the provider's chain, Chainlink
+/// is preferred where an aggregator exists, Pyth is used otherwise
+pub async fn fetch_usd_price(
+    symbol: &str,
+    provider: &EvmProvider,
+) -> Result<UsdPrice> {
+    let symbol = normalize_symbol(symbol);
+    let chain_id = provider.get_chain_id().await?;
+
+    if let Some(feed) = chainlink_feed(chain_id, &symbol) {
+        let (price, updated_at) = chainlink_price(feed, provider).await?;
+        return Ok(UsdPrice {
+            symbol,
+            price,
+            source: "chainlink",
+            updated_at,
+        });
+    }
+
+    if let Some(feed_id) = pyth_feed_id(&symbol) {
+        let (price, updated_at) =
+            pyth_price(pyth_address(chain_id)?, feed_id, provider).await?;
+        return Ok(UsdPrice {
+            symbol,
+            price,
+            source: "pyth",
+            updated_at,
+        });
+    }
+
+    Err(anyhow!(
+        "No price feed for {} on chain {}",
+        symbol,
+        chain_id
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::util::make_provider;
+
+    #[test]
+    fn test_normalize_symbol() {
+        assert_eq!(normalize_symbol("wS"), "S");
+        assert_eq!(normalize_symbol("weth"), "ETH");
+        assert_eq!(normalize_symbol("USDC"), "USDC");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_usd_price() {
+        let provider = make_provider().unwrap();
+        let price = fetch_usd_price("ETH", &provider).await.unwrap();
+        tracing::debug!(?price, "test_fetch_usd_price");
+        assert!(price.price > 0.);
+    }
+}
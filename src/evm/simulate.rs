@@ -0,0 +1,587 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use alloy::primitives::{hex, Address, Bytes, B256, I256, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::state::StateOverride;
+use alloy::rpc::types::TransactionRequest;
+use alloy::sol_types::{decode_revert_reason, SolEvent};
+use alloy::transports::TransportError;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use super::abi::IERC20;
+use super::util::EvmProvider;
+use crate::common::http_client;
+use crate::config::config;
+use crate::error::Error;
+
+const NATIVE_TOKEN: &str = "native";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BalanceChange {
+    /// token contract address or "native"
+    pub token: String,
+    pub symbol: Option<String>,
+    /// signed raw amount from the perspective of the sender
+    pub delta: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulatedEvent {
+    pub address: String,
+    pub name: Option<String>,
+    pub topics: Vec<String>,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulationResult {
+    pub backend: &'static str,
+    pub success: bool,
+    pub gas_used: Option<u64>,
+    pub revert_reason: Option<String>,
+    pub balance_changes: Vec<BalanceChange>,
+    pub events: Vec<SimulatedEvent>,
+}
+
+struct TenderlyConfig {
+    access_key: String,
+    account: String,
+    project: String,
+}
+
+impl TenderlyConfig {
+    fn from_env() -> Option<Self> {
+        Some(Self {
+            access_key: std::env::var("TENDERLY_ACCESS_KEY").ok()?,
+            account: std::env::var("TENDERLY_ACCOUNT").ok()?,
+            project: std::env::var("TENDERLY_PROJECT").ok()?,
+        })
+    }
+}
+
+/// Pre-send simulation is opt-in, enabled by features.evm_simulate
+/// (EVM_SIMULATE=true)
+pub fn simulation_enabled() -> bool {
+    config().features.evm_simulate
+}
+
+/// Simulates the transaction with Tenderly when TENDERLY_* are set,
+/// otherwise with eth_call (+ debug_traceCall for logs when supported)
+pub async fn simulate_transaction(
+    tx: &TransactionRequest,
+    provider: &EvmProvider,
+) -> Result<SimulationResult> {
+    match TenderlyConfig::from_env() {
+        Some(config) => simulate_tenderly(tx, provider, &config).await,
+        None => simulate_eth_call(tx, provider, None).await,
+    }
+}
+
+pub async fn simulate_eth_call(
+    tx: &TransactionRequest,
+    provider: &EvmProvider,
+    overrides: Option<&StateOverride>,
+) -> Result<SimulationResult> {
+    let call = provider.call(tx);
+    let call = match overrides {
+        Some(overrides) => call.overrides(overrides),
+        None => call,
+    };
+    if let Err(e) = call.await {
+        return Ok(SimulationResult {
+            backend: "eth_call",
+            success: false,
+            gas_used: None,
+            revert_reason: Some(e.to_string()),
+            balance_changes: vec![],
+            events: vec![],
+        });
+    }
+
+    let gas_used = provider.estimate_gas(tx).await.ok();
+
+    // not every RPC exposes the debug namespace, the call result alone is
+    // still useful so a missing tracer is not an error
+    let trace = provider
+        .raw_request::<_, Value>(
+            "debug_traceCall".into(),
+            (
+                tx,
+                "latest",
+                json!({
+                    "tracer": "callTracer",
+                    "tracerConfig": {"withLog": true},
+                }),
+            ),
+        )
+        .await;
+
+    let (balance_changes, events) = match (trace, tx.from) {
+        (Ok(trace), Some(from)) => parse_call_trace(&trace, from),
+        (Err(e), _) => {
+            tracing::debug!(?e, "debug_traceCall not available");
+            (vec![], vec![])
+        }
+        _ => (vec![], vec![]),
+    };
+
+    Ok(SimulationResult {
+        backend: "eth_call",
+        success: true,
+        gas_used,
+        revert_reason: None,
+        balance_changes,
+        events,
+    })
+}
+
+/// Reason of a revert from the data it returned: the message of
+/// Error(string), the kind of Panic(uint256) or the hex of a custom error
+pub fn revert_reason(data: &[u8]) -> String {
+    decode_revert_reason(data).unwrap_or_else(|| hex::encode_prefixed(data))
+}
+
+/// Data of the revert in the error of an eth_call, RPCs put it in the data
+/// of the JSON-RPC error
+fn revert_data(error: &TransportError) -> Option<Bytes> {
+    let data = error.as_error_resp()?.data.as_ref()?;
+    serde_json::from_str(data.get()).ok()
+}
+
+/// Reason of the revert an eth_call failed with, None when the node
+/// answered with another error or couldn't be reached
+fn call_revert(error: &TransportError) -> Option<String> {
+    let response = error.as_error_resp()?;
+    match revert_data(error) {
+        Some(data) => Some(revert_reason(&data)),
+        None => response
+            .message
+            .contains("revert")
+            .then(|| response.message.to_string()),
+    }
+}
+
+/// Field of a JSON transaction missing or null, left to fill
+fn unset(fields: &Map<String, Value>, name: &str) -> bool {
+    fields.get(name).map_or(true, Value::is_null)
+}
+
+/// Envelope type to sign a prepared JSON transaction as, 2 when it sets
+/// EIP-1559 fees, else the access list type 1 it asks for or legacy 0,
+/// which `prepare_json_transaction` gave a gas price
+pub fn transaction_type(fields: &Map<String, Value>) -> u64 {
+    if !unset(fields, "maxFeePerGas") || !unset(fields, "maxPriorityFeePerGas")
+    {
+        return 2;
+    }
+    match fields.get("type").and_then(quantity) {
+        Some(1) => 1,
+        _ => 0,
+    }
+}
+
+/// Quantity of a JSON transaction field, APIs send them as numbers or as
+/// hex strings
+pub fn quantity(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        },
+        _ => None,
+    }
+}
+
+/// Checks with eth_call that the JSON transaction of `from` doesn't revert,
+/// then fills the gas (eth_estimateGas), gas price and nonce it doesn't
+/// set, the gas price only for legacy transactions without EIP-1559 fees.
+/// Reverting transactions fail with the decoded reason, nothing is signed
+/// for them, errors of the node itself are RPC errors
+pub async fn prepare_json_transaction(
+    mut tx: Value,
+    from: Address,
+    provider: &EvmProvider,
+) -> Result<Value> {
+    let Value::Object(fields) = &mut tx else {
+        return Err(Error::UserInput(
+            "The EVM transaction is not a JSON object".to_string(),
+        )
+        .into());
+    };
+    // the provider is the one of the chain, the chain id and type are
+    // only for signing and not every API sends them as quantities
+    let mut call = fields.clone();
+    call.remove("chainId");
+    call.remove("type");
+    call.insert("from".to_string(), json!(from.to_string()));
+    let request: TransactionRequest = serde_json::from_value(call.into())
+        .map_err(|e| {
+            Error::UserInput(format!("Invalid EVM transaction: {}", e))
+        })?;
+
+    if let Err(e) = provider.call(&request).await {
+        return Err(match call_revert(&e) {
+            Some(reason) => Error::Simulation(format!(
+                "The transaction reverts: {}",
+                reason
+            )),
+            None => e.into(),
+        }
+        .into());
+    }
+
+    // LiFi sends the gas as gasLimit
+    if unset(fields, "gas") && unset(fields, "gasLimit") {
+        let gas = provider.estimate_gas(&request).await?;
+        fields.insert("gas".to_string(), json!(format!("{:#x}", gas)));
+    }
+    let eip1559 = !unset(fields, "maxFeePerGas")
+        || !unset(fields, "maxPriorityFeePerGas");
+    if unset(fields, "gasPrice") && !eip1559 {
+        let gas_price = provider.get_gas_price().await?;
+        fields.insert(
+            "gasPrice".to_string(),
+            json!(format!("{:#x}", gas_price)),
+        );
+    }
+    if unset(fields, "nonce") {
+        let nonce = provider.get_transaction_count(from).pending().await?;
+        fields.insert("nonce".to_string(), json!(format!("{:#x}", nonce)));
+    }
+    Ok(tx)
+}
+
+async fn simulate_tenderly(
+    tx: &TransactionRequest,
+    provider: &EvmProvider,
+    config: &TenderlyConfig,
+) -> Result<SimulationResult> {
+    let from = tx
+        .from
+        .ok_or_else(|| anyhow!("Transaction has no sender"))?;
+    let chain_id = provider.get_chain_id().await?;
+    let url = format!(
+        "https://api.tenderly.co/api/v1/account/{}/project/{}/simulate",
+        config.account, config.project
+    );
+
+    let response = http_client()
+        .post(url)
+        .header("X-Access-Key", &config.access_key)
+        .json(&json!({
+            "network_id": chain_id.to_string(),
+            "from": from,
+            "to": tx.to.and_then(|to| to.to().copied()),
+            "input": tx.input.input().cloned().unwrap_or_default(),
+            "value": tx.value.unwrap_or_default().to_string(),
+            "gas": tx.gas.unwrap_or(8_000_000),
+            "save": false,
+            "simulation_type": "full",
+        }))
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Tenderly simulation failed: {}",
+            response.text().await?
+        ));
+    }
+
+    let body: Value = response.json().await?;
+    Ok(parse_tenderly_response(&body, from))
+}
+
+fn parse_tenderly_response(body: &Value, from: Address) -> SimulationResult {
+    let info = &body["transaction"]["transaction_info"];
+    let success = body["transaction"]["status"].as_bool().unwrap_or(false);
+
+    let mut deltas: HashMap<String, (Option<String>, I256)> = HashMap::new();
+    for change in info["asset_changes"].as_array().into_iter().flatten() {
+        let token = change["token_info"]["contract_address"]
+            .as_str()
+            .unwrap_or(NATIVE_TOKEN)
+            .to_string();
+        let symbol =
+            change["token_info"]["symbol"].as_str().map(String::from);
+        let amount = change["raw_amount"]
+            .as_str()
+            .and_then(|a| I256::from_dec_str(a).ok())
+            .unwrap_or_default();
+        let involves = |key: &str| {
+            change[key]
+                .as_str()
+                .and_then(|a| Address::from_str(a).ok())
+                .map(|a| a == from)
+                .unwrap_or(false)
+        };
+        let entry = deltas.entry(token).or_insert((symbol, I256::ZERO));
+        if involves("from") {
+            entry.1 -= amount;
+        }
+        if involves("to") {
+            entry.1 += amount;
+        }
+    }
+
+    let events = info["logs"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|log| SimulatedEvent {
+            address: log["raw"]["address"]
+                .as_str()
+                .unwrap_or_default()
+                .into(),
+            name: log["name"].as_str().map(String::from),
+            topics: log["raw"]["topics"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|t| t.as_str().map(String::from))
+                .collect(),
+            data: log["raw"]["data"].as_str().unwrap_or_default().into(),
+        })
+        .collect();
+
+    SimulationResult {
+        backend: "tenderly",
+        success,
+        gas_used: info["gas_used"].as_u64(),
+        revert_reason: body["transaction"]["error_message"]
+            .as_str()
+            .map(String::from),
+        balance_changes: into_balance_changes(deltas),
+        events,
+    }
+}
+
+/// Walks a callTracer frame (with logs) collecting native value transfers
+/// and ERC20 Transfer logs that touch `from`
+pub fn parse_call_trace(
+    trace: &Value,
+    from: Address,
+) -> (Vec<BalanceChange>, Vec<SimulatedEvent>) {
+    let mut deltas: HashMap<String, (Option<String>, I256)> = HashMap::new();
+    let mut events = vec![];
+    walk_frame(trace, from, &mut deltas, &mut events);
+    (into_balance_changes(deltas), events)
+}
+
+fn walk_frame(
+    frame: &Value,
+    watched: Address,
+    deltas: &mut HashMap<String, (Option<String>, I256)>,
+    events: &mut Vec<SimulatedEvent>,
+) {
+    let parse_address = |key: &str| {
+        frame[key].as_str().and_then(|a| Address::from_str(a).ok())
+    };
+    let value = frame["value"]
+        .as_str()
+        .and_then(|v| U256::from_str(v).ok())
+        .unwrap_or_default();
+    if !value.is_zero() {
+        let value = I256::from_raw(value);
+        let entry = deltas
+            .entry(NATIVE_TOKEN.to_string())
+            .or_insert((None, I256::ZERO));
+        if parse_address("from") == Some(watched) {
+            entry.1 -= value;
+        }
+        if parse_address("to") == Some(watched) {
+            entry.1 += value;
+        }
+    }
+
+    for log in frame["logs"].as_array().into_iter().flatten() {
+        let topics: Vec<B256> = log["topics"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|t| t.as_str().and_then(|t| B256::from_str(t).ok()))
+            .collect();
+        let data = log["data"]
+            .as_str()
+            .and_then(|d| Bytes::from_str(d).ok())
+            .unwrap_or_default();
+        let address = log["address"].as_str().unwrap_or_default().to_string();
+
+        let name = match topics.first() {
+            Some(t) if *t == IERC20::Transfer::SIGNATURE_HASH => {
+                if let Ok(event) = IERC20::Transfer::decode_raw_log(
+                    topics.iter().copied(),
+                    &data,
+                    true,
+                ) {
+                    let amount = I256::from_raw(event.value);
+                    let entry = deltas
+                        .entry(address.to_lowercase())
+                        .or_insert((None, I256::ZERO));
+                    if event.from == watched {
+                        entry.1 -= amount;
+                    }
+                    if event.to == watched {
+                        entry.1 += amount;
+                    }
+                }
+                Some("Transfer".to_string())
+            }
+            Some(t) if *t == IERC20::Approval::SIGNATURE_HASH => {
+                Some("Approval".to_string())
+            }
+            _ => None,
+        };
+
+        events.push(SimulatedEvent {
+            address,
+            name,
+            topics: topics.iter().map(|t| t.to_string()).collect(),
+            data: data.to_string(),
+        });
+    }
+
+    for call in frame["calls"].as_array().into_iter().flatten() {
+        walk_frame(call, watched, deltas, events);
+    }
+}
+
+fn into_balance_changes(
+    deltas: HashMap<String, (Option<String>, I256)>,
+) -> Vec<BalanceChange> {
+    let mut changes: Vec<BalanceChange> = deltas
+        .into_iter()
+        .filter(|(_, (_, delta))| !delta.is_zero())
+        .map(|(token, (symbol, delta))| BalanceChange {
+            token,
+            symbol,
+            delta: delta.to_string(),
+        })
+        .collect();
+    changes.sort_by(|a, b| a.token.cmp(&b.token));
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::address;
+    use alloy::sol_types::{Panic, Revert, SolError};
+    use alloy::transports::TransportErrorKind;
+
+    #[test]
+    fn test_revert_reason() {
+        let data = Revert {
+            reason: "STF".to_string(),
+        }
+        .abi_encode();
+        assert!(revert_reason(&data).contains("STF"));
+        let data = Panic {
+            code: U256::from(0x11),
+        }
+        .abi_encode();
+        assert!(revert_reason(&data).contains("overflow"));
+        assert_eq!(revert_reason(&[0xde, 0xad, 0xbe, 0xef]), "0xdeadbeef");
+
+        let tx = json!({"to": "0x00", "gas": null, "gasLimit": "0x5208"});
+        let fields = tx.as_object().unwrap();
+        assert!(unset(fields, "gas"));
+        assert!(!unset(fields, "gasLimit"));
+        assert!(unset(fields, "nonce"));
+    }
+
+    #[test]
+    fn test_transaction_type() {
+        let tx = json!({
+            "chainId": "0x92",
+            "type": "0x2",
+            "maxFeePerGas": "0x3b9aca00",
+            "maxPriorityFeePerGas": null,
+        });
+        assert_eq!(transaction_type(tx.as_object().unwrap()), 2);
+        let tx = json!({"type": 1, "gasPrice": "0x3b9aca00"});
+        assert_eq!(transaction_type(tx.as_object().unwrap()), 1);
+        // a type 2 without fees got a gas price
+        let tx = json!({"type": "0x2", "gasPrice": "0x3b9aca00"});
+        assert_eq!(transaction_type(tx.as_object().unwrap()), 0);
+
+        assert_eq!(quantity(&json!("0x92")), Some(146));
+        assert_eq!(quantity(&json!("146")), Some(146));
+        assert_eq!(quantity(&json!(146)), Some(146));
+        assert_eq!(quantity(&json!(null)), None);
+    }
+
+    #[test]
+    fn test_call_revert() {
+        let response = |payload: Value| {
+            TransportError::ErrorResp(
+                serde_json::from_value(payload).unwrap(),
+            )
+        };
+        let data = Revert {
+            reason: "STF".to_string(),
+        }
+        .abi_encode();
+        let error = response(json!({
+            "code": 3,
+            "message": "execution reverted",
+            "data": hex::encode_prefixed(data),
+        }));
+        assert!(call_revert(&error).unwrap().contains("STF"));
+        let error = response(json!({
+            "code": -32000,
+            "message": "execution reverted",
+        }));
+        assert_eq!(call_revert(&error).unwrap(), "execution reverted");
+        let error = response(json!({
+            "code": -32005,
+            "message": "rate limit exceeded",
+        }));
+        assert!(call_revert(&error).is_none());
+        assert!(call_revert(&TransportErrorKind::backend_gone()).is_none());
+    }
+
+    #[test]
+    fn test_parse_call_trace_transfer_logs() {
+        let user = address!("2fAA30d5EdDF1e4fa126aEdA79159878D58A2438");
+        let pool = address!("CCC48877a33a2C14e40c82da843Cf4c607ABF770");
+        let transfer = |from: Address, to: Address, value: u64| {
+            let data = IERC20::Transfer {
+                from,
+                to,
+                value: U256::from(value),
+            }
+            .encode_log_data();
+            json!({
+                "address": "0x29219dd400f2bf60e5a23d13be72b486d4038894",
+                "topics": data.topics().iter().map(|t| t.to_string()).collect::<Vec<_>>(),
+                "data": data.data.to_string(),
+            })
+        };
+        let trace = json!({
+            "from": user.to_string(),
+            "to": pool.to_string(),
+            "value": "0x64",
+            "calls": [{
+                "from": pool.to_string(),
+                "to": pool.to_string(),
+                "logs": [transfer(user, pool, 1000), transfer(pool, user, 10)],
+            }],
+        });
+
+        let (changes, events) = parse_call_trace(&trace, user);
+        assert_eq!(events.len(), 2);
+        assert_eq!(changes.len(), 2);
+        let token = changes
+            .iter()
+            .find(|c| c.token.starts_with("0x29219"))
+            .unwrap();
+        assert_eq!(token.delta, "-990");
+        let native = changes.iter().find(|c| c.token == "native").unwrap();
+        assert_eq!(native.delta, "-100");
+    }
+}
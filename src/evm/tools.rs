@@ -1,6 +1,5 @@
 use std::str::FromStr;
 
-use alloy::primitives::utils::parse_ether;
 use alloy::primitives::Address;
 use alloy::providers::Provider;
 use anyhow::{Context, Result};
@@ -8,14 +7,30 @@ use anyhow::{Context, Result};
 use rig_tool_macro::tool;
 use uniswap_sdk_core::prelude::SWAP_ROUTER_02_ADDRESSES;
 
-use crate::common::wrap_unsafe;
+use crate::address::Address as WalletAddress;
+use crate::amount;
+use crate::chains::Chain;
+use crate::common::{evm_chain, with_explorer_link};
+use crate::confirmation::confirm_or_execute;
+use crate::risk::{assess, TradeIntent};
 use crate::signer::SignerContext;
+use crate::validation;
 
 use super::balance::{balance, token_balance};
+use super::liquidity::{
+    create_collect_fees_tx, create_decrease_liquidity_tx,
+    create_increase_liquidity_tx, create_mint_position_tx, list_positions,
+    position_manager_address, position_tokens, PositionInfo,
+};
+use super::price::{fetch_usd_price, UsdPrice};
 use super::trade::{check_allowance, create_approve_tx, create_trade_tx};
 use super::transfer::{create_transfer_erc20_tx, create_transfer_eth_tx};
 use super::util::{execute_evm_transaction, make_provider};
 
+/// Address LiFi (and most aggregators) use for the native token of a chain
+pub(crate) const NATIVE_TOKEN: &str =
+    "0x0000000000000000000000000000000000000000";
+
 // TODO it is worth to include description of the function, possibly using
 // docstring for the model to understand what is going on stuff like lamports
 // vs ether decimals vs pump decimals and so on etc models can do but need
@@ -25,20 +40,17 @@ pub async fn verify_swap_router_has_allowance(
     token_address: String,
 ) -> Result<bool> {
     let owner = SignerContext::current().await.address();
-    wrap_unsafe(move || async move {
-        let provider = make_provider()?;
-        let router_address = *SWAP_ROUTER_02_ADDRESSES
-            .get(&provider.get_chain_id().await?)
-            .context("Router address not found")?;
-
-        check_allowance(
-            Address::from_str(&token_address)?,
-            Address::from_str(&owner)?,
-            router_address,
-            &provider,
-        )
-        .await
-    })
+    let provider = make_provider()?;
+    let router_address = *SWAP_ROUTER_02_ADDRESSES
+        .get(&provider.get_chain_id().await?)
+        .context("Router address not found")?;
+
+    check_allowance(
+        Address::from_str(&token_address)?,
+        Address::from_str(&owner)?,
+        router_address,
+        &provider,
+    )
     .await
 }
 
@@ -46,94 +58,199 @@ pub async fn verify_swap_router_has_allowance(
 pub async fn approve_token_for_router_spend(
     input_token_address: String,
 ) -> Result<String> {
+    validation::address(
+        &evm_chain(),
+        "input_token_address",
+        &input_token_address,
+    )?;
     let provider = make_provider()?;
-    let router_address = wrap_unsafe(move || async move {
-        let router_address = *SWAP_ROUTER_02_ADDRESSES
-            .get(&make_provider()?.get_chain_id().await?)
-            .context("Router address not found")?;
+    let router_address = *SWAP_ROUTER_02_ADDRESSES
+        .get(&provider.get_chain_id().await?)
+        .context("Router address not found")?;
 
-        Ok(router_address)
-    })
-    .await?;
-
-    execute_evm_transaction(move |owner| async move {
-        create_approve_tx(
-            input_token_address,
-            router_address.to_string(),
-            owner.to_string(),
-            &provider,
-        )
-        .await
-    })
+    confirm_or_execute(
+        "approve_token_for_router_spend",
+        format!(
+            "Approve the swap router to spend token {}",
+            input_token_address
+        ),
+        move || async move {
+            execute_evm_transaction(move |owner| async move {
+                create_approve_tx(
+                    input_token_address,
+                    router_address.to_string(),
+                    owner.to_string(),
+                    &provider,
+                )
+                .await
+            })
+            .await
+            .map(|hash| with_explorer_link(&evm_chain(), &hash))
+        },
+    )
     .await
 }
 
-#[tool]
+#[tool(description = "
+Swaps input_token_address for output_token_address on Uniswap
+
+input_amount is the amount as the user said it, e.g. 0.5 ETH, 100 USDC, half
+or all, it is converted with the decimals and the balance of the input token
+")]
 pub async fn trade(
     input_token_address: String,
     input_amount: String,
     output_token_address: String,
 ) -> Result<String> {
-    let input_amount = if input_amount.contains('.') {
-        parse_ether(&input_amount)?.to_string()
-    } else {
-        input_amount
-    };
-    execute_evm_transaction(move |owner| async move {
-        create_trade_tx(
-            input_token_address,
-            input_amount,
-            output_token_address,
-            &make_provider()?,
-            owner,
-        )
-        .await
-    })
+    let input_amount =
+        amount::resolve(&evm_chain(), &input_token_address, &input_amount)
+            .await?;
+    let raw_amount = input_amount.raw().to_string();
+    validation::swap(
+        &evm_chain(),
+        &input_token_address,
+        &raw_amount,
+        &output_token_address,
+    )
+    .await?;
+    let assessment = assess(
+        TradeIntent::new(&evm_chain())
+            .spend(&input_token_address, &raw_amount)
+            .receive(&output_token_address),
+    )
+    .await?;
+    confirm_or_execute(
+        "trade",
+        assessment.annotate(format!(
+            "Swap {} of token {} for token {}",
+            input_amount, input_token_address, output_token_address
+        )),
+        move || async move {
+            let hash = execute_evm_transaction(move |owner| async move {
+                create_trade_tx(
+                    input_token_address,
+                    &input_amount,
+                    output_token_address,
+                    &make_provider()?,
+                    owner,
+                )
+                .await
+            })
+            .await?;
+            assessment.record().await;
+            Ok(with_explorer_link(&evm_chain(), &hash))
+        },
+    )
     .await
 }
 
-#[tool]
+#[tool(description = "
+Transfers the native token of the chain to recipient
+
+amount is the amount as the user said it, e.g. 0.5 ETH, half or all, it is
+converted to wei for you
+")]
 pub async fn transfer_eth(
     recipient: String,
     amount: String,
 ) -> Result<String> {
-    execute_evm_transaction(move |owner| async move {
-        create_transfer_eth_tx(recipient, amount, &make_provider()?, owner)
-            .await
-    })
+    let amount = amount::resolve(&evm_chain(), NATIVE_TOKEN, &amount).await?;
+    let raw_amount = amount.raw().to_string();
+    let to = validation::transfer(
+        &evm_chain(),
+        &recipient,
+        NATIVE_TOKEN,
+        &raw_amount,
+    )
+    .await?
+    .as_evm()?;
+    let assessment = assess(
+        TradeIntent::new(&evm_chain())
+            .spend(NATIVE_TOKEN, &raw_amount)
+            .destination(&to.to_string()),
+    )
+    .await?;
+    confirm_or_execute(
+        "transfer_eth",
+        assessment.annotate(format!(
+            "Transfer {} of the native token to {}",
+            amount, recipient
+        )),
+        move || async move {
+            let hash = execute_evm_transaction(move |owner| async move {
+                create_transfer_eth_tx(to, &amount, &make_provider()?, owner)
+                    .await
+            })
+            .await?;
+            assessment.record().await;
+            Ok(with_explorer_link(&evm_chain(), &hash))
+        },
+    )
     .await
 }
 
-#[tool]
+#[tool(description = "
+Transfers an ERC20 token to recipient
+
+amount is the amount as the user said it, e.g. 100 USDC, half or all, it is
+converted with the decimals and the balance of the token for you
+")]
 pub async fn transfer_erc20(
     recipient: String,
     token_address: String,
     amount: String,
 ) -> Result<String> {
-    execute_evm_transaction(move |owner| async move {
-        create_transfer_erc20_tx(
-            token_address,
-            recipient,
-            amount,
-            &make_provider()?,
-            owner,
-        )
-        .await
-    })
+    let amount =
+        amount::resolve(&evm_chain(), &token_address, &amount).await?;
+    let raw_amount = amount.raw().to_string();
+    let to = validation::transfer(
+        &evm_chain(),
+        &recipient,
+        &token_address,
+        &raw_amount,
+    )
+    .await?
+    .as_evm()?;
+    let assessment = assess(
+        TradeIntent::new(&evm_chain())
+            .spend(&token_address, &raw_amount)
+            .destination(&to.to_string()),
+    )
+    .await?;
+    confirm_or_execute(
+        "transfer_erc20",
+        assessment.annotate(format!(
+            "Transfer {} of token {} to {}",
+            amount, token_address, recipient
+        )),
+        move || async move {
+            let hash = execute_evm_transaction(move |owner| async move {
+                create_transfer_erc20_tx(
+                    token_address,
+                    to,
+                    &amount,
+                    &make_provider()?,
+                    owner,
+                )
+                .await
+            })
+            .await?;
+            assessment.record().await;
+            Ok(with_explorer_link(&evm_chain(), &hash))
+        },
+    )
     .await
 }
 
 #[tool]
 pub async fn wallet_address() -> Result<String> {
-    Ok(SignerContext::current().await.address())
+    let signer = SignerContext::current().await;
+    Ok(WalletAddress::of_signer(signer.as_ref(), Chain::Evm)?.to_string())
 }
 
 #[tool]
 pub async fn get_eth_balance(address: String) -> Result<String> {
-    wrap_unsafe(
-        move || async move { balance(&make_provider()?, address).await },
-    )
-    .await
+    balance(&make_provider()?, address).await
 }
 
 #[tool]
@@ -141,8 +258,217 @@ pub async fn get_erc20_balance(
     token_address: String,
     address: String,
 ) -> Result<String> {
-    wrap_unsafe(move || async move {
-        token_balance(address, token_address, &make_provider()?).await
+    token_balance(address, token_address, &make_provider()?).await
+}
+
+#[tool(description = "
+Returns the USD price of a major asset (S, ETH, BTC, USDC, USDT) read from
+on-chain Chainlink or Pyth feeds, wrapped symbols like wS or WETH work too
+")]
+pub async fn get_evm_token_price(symbol: String) -> Result<UsdPrice> {
+    fetch_usd_price(&symbol, &make_provider()?).await
+}
+
+#[tool(description = "
+Lists the concentrated liquidity positions (NFTs) owned by the wallet,
+including whether each position is currently in range
+")]
+pub async fn list_lp_positions() -> Result<Vec<PositionInfo>> {
+    let owner = Address::from_str(&SignerContext::current().await.address())?;
+    list_positions(owner, &make_provider()?).await
+}
+
+#[tool(description = "
+Approves the concentrated liquidity position manager to spend the token, this
+is required for both tokens before minting or increasing a position
+")]
+pub async fn approve_token_for_position_manager(
+    token_address: String,
+) -> Result<String> {
+    let provider = make_provider()?;
+    let manager = position_manager_address(&provider).await?;
+
+    confirm_or_execute(
+        "approve_token_for_position_manager",
+        format!(
+            "Approve the position manager to spend token {}",
+            token_address
+        ),
+        move || async move {
+            execute_evm_transaction(move |owner| async move {
+                create_approve_tx(
+                    token_address,
+                    manager.to_string(),
+                    owner.to_string(),
+                    &provider,
+                )
+                .await
+            })
+            .await
+            .map(|hash| with_explorer_link(&evm_chain(), &hash))
+        },
+    )
+    .await
+}
+
+#[tool(description = "
+Mints a new concentrated liquidity position.
+
+fee is the pool fee tier in hundredths of a bip, e.g. 3000 for 0.3%
+tick_lower and tick_upper define the price range, they have to be multiples of
+the pool tick spacing
+amount_a and amount_b are the desired token amounts accounting for decimals,
+the actual amounts used depend on the current price
+slippage_bps is the slippage in basis points, 50-100 is fine for most pools
+")]
+#[allow(clippy::too_many_arguments)]
+pub async fn mint_lp_position(
+    token_a: String,
+    token_b: String,
+    fee: u32,
+    tick_lower: i32,
+    tick_upper: i32,
+    amount_a: String,
+    amount_b: String,
+    slippage_bps: u16,
+) -> Result<String> {
+    let chain = evm_chain();
+    validation::address(&chain, "token_a", &token_a)?;
+    validation::address(&chain, "token_b", &token_b)?;
+    validation::raw_amount("amount_a", &amount_a)?;
+    validation::raw_amount("amount_b", &amount_b)?;
+    validation::slippage_bps(slippage_bps)?;
+    let assessment = assess(
+        TradeIntent::new(&chain)
+            .spend(&token_a, &amount_a)
+            .spend(&token_b, &amount_b)
+            .slippage_bps(slippage_bps),
+    )
+    .await?;
+    let summary = assessment.annotate(format!(
+        "Provide liquidity {} of {} and {} of {} (fee {}, ticks {} to {})",
+        amount_a, token_a, amount_b, token_b, fee, tick_lower, tick_upper
+    ));
+    confirm_or_execute("mint_lp_position", summary, move || async move {
+        let hash = execute_evm_transaction(move |owner| async move {
+            create_mint_position_tx(
+                token_a,
+                token_b,
+                fee,
+                tick_lower,
+                tick_upper,
+                amount_a,
+                amount_b,
+                slippage_bps,
+                &make_provider()?,
+                owner,
+            )
+            .await
+        })
+        .await?;
+        assessment.record().await;
+        Ok(with_explorer_link(&evm_chain(), &hash))
     })
     .await
 }
+
+#[tool(description = "
+Adds liquidity to an existing position, amounts account for decimals
+")]
+pub async fn increase_lp_liquidity(
+    token_id: String,
+    amount0: String,
+    amount1: String,
+    slippage_bps: u16,
+) -> Result<String> {
+    validation::raw_amount("amount0", &amount0)?;
+    validation::raw_amount("amount1", &amount1)?;
+    validation::slippage_bps(slippage_bps)?;
+    let (token0, token1) =
+        position_tokens(&token_id, &make_provider()?).await?;
+    let assessment = assess(
+        TradeIntent::new(&evm_chain())
+            .spend(&token0.to_string(), &amount0)
+            .spend(&token1.to_string(), &amount1)
+            .slippage_bps(slippage_bps),
+    )
+    .await?;
+    confirm_or_execute(
+        "increase_lp_liquidity",
+        assessment.annotate(format!(
+            "Add {} / {} liquidity to position {}",
+            amount0, amount1, token_id
+        )),
+        move || async move {
+            let hash = execute_evm_transaction(move |owner| async move {
+                create_increase_liquidity_tx(
+                    token_id,
+                    amount0,
+                    amount1,
+                    slippage_bps,
+                    &make_provider()?,
+                    owner,
+                )
+                .await
+            })
+            .await?;
+            assessment.record().await;
+            Ok(with_explorer_link(&evm_chain(), &hash))
+        },
+    )
+    .await
+}
+
+#[tool(description = "
+Removes percent (1-100) of the liquidity from a position. The withdrawn tokens
+stay owed to the position until collect_lp_fees is called
+")]
+pub async fn decrease_lp_liquidity(
+    token_id: String,
+    percent: u8,
+    slippage_bps: u16,
+) -> Result<String> {
+    validation::percent("percent", percent)?;
+    validation::slippage_bps(slippage_bps)?;
+    confirm_or_execute(
+        "decrease_lp_liquidity",
+        format!(
+            "Remove {}% of the liquidity of position {}",
+            percent, token_id
+        ),
+        move || async move {
+            execute_evm_transaction(move |owner| async move {
+                create_decrease_liquidity_tx(
+                    token_id,
+                    percent,
+                    slippage_bps,
+                    &make_provider()?,
+                    owner,
+                )
+                .await
+            })
+            .await
+            .map(|hash| with_explorer_link(&evm_chain(), &hash))
+        },
+    )
+    .await
+}
+
+#[tool(description = "
+Collects the earned fees and any withdrawn liquidity of a position to the wallet
+")]
+pub async fn collect_lp_fees(token_id: String) -> Result<String> {
+    confirm_or_execute(
+        "collect_lp_fees",
+        format!("Collect fees of position {}", token_id),
+        move || async move {
+            execute_evm_transaction(move |owner| async move {
+                create_collect_fees_tx(token_id, &make_provider()?, owner)
+                    .await
+            })
+            .await
+            .map(|hash| with_explorer_link(&evm_chain(), &hash))
+        },
+    )
+    .await
+}
@@ -11,6 +11,7 @@ use uniswap_v3_sdk::prelude::*;
 
 use super::abi::IERC20;
 use super::util::EvmProvider;
+use crate::amount::Amount;
 
 pub async fn check_allowance(
     token_address: Address,
@@ -39,10 +40,6 @@ pub async fn create_approve_tx(
     let input_addr = Address::from_str(&input_token_address)?;
     let spender_addr = Address::from_str(&spender)?;
     let owner_addr = Address::from_str(&owner)?;
-    let call = IERC20::approveCall {
-        spender: spender_addr,
-        amount: U256::MAX,
-    };
 
     // TODO move gas price to global cache
     let gas_price = provider
@@ -50,20 +47,32 @@ pub async fn create_approve_tx(
         .await
         .context("Failed to get gas price")?;
 
-    let tx = TransactionRequest::default()
-        .with_from(owner_addr)
-        .with_to(input_addr)
-        .with_call(&call)
-        .with_gas_price(gas_price);
-
-    Ok(tx)
+    Ok(approve_tx(input_addr, spender_addr, owner_addr, gas_price))
     // send_transaction(tx, provider, wallet).await?;
     // should probably wait for the tx here and verify approvals, but retries will handle this
 }
 
+/// Unlimited approval of `spender` at a given gas price
+pub fn approve_tx(
+    token: Address,
+    spender: Address,
+    owner: Address,
+    gas_price: u128,
+) -> TransactionRequest {
+    let call = IERC20::approveCall {
+        spender,
+        amount: U256::MAX,
+    };
+    TransactionRequest::default()
+        .with_from(owner)
+        .with_to(token)
+        .with_call(&call)
+        .with_gas_price(gas_price)
+}
+
 pub async fn create_trade_tx(
     input_token_address: String,
-    input_amount: String,
+    input_amount: &Amount,
     output_token_address: String,
     provider: &EvmProvider,
     owner: Address,
@@ -74,13 +83,13 @@ pub async fn create_trade_tx(
 
     // Create token instances
     let chain_id = provider.get_chain_id().await?;
-    let input_token = token!(chain_id, input_addr, 18);
+    let input_token = token!(chain_id, input_addr, input_amount.decimals());
     let output_token = token!(chain_id, output_addr, 18);
 
     // Parse input amount
     let amount_in = CurrencyAmount::from_raw_amount(
         input_token.clone(),
-        BigInt::from_str(&input_amount)?,
+        BigInt::from_str(&input_amount.raw().to_string())?,
     )
     .context("Failed to create CurrencyAmount")?;
 
@@ -141,61 +150,112 @@ pub async fn create_trade_tx(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::evm::util::{
-        execute_evm_transaction, make_provider, with_local_evm_signer,
-    };
+    use crate::config::config;
+    use crate::evm::anvil::LocalEvm;
+    use crate::evm::util::execute_evm_transaction;
+    use crate::signer::evm::LocalEvmSigner;
+    use crate::snapshot::{assert_snapshot, render_request};
 
-    #[tokio::test]
-    async fn test_approval() {
-        let provider = make_provider().unwrap();
+    //  WETH on arbitrum
+    const WETH: &str = "0x82aF49447D8a07e3bd95BD0d56f35241523fBab1";
+    // USDC on arbitrum
+    const USDC: &str = "0xaf88d065e77c8cC2239327C5EDb3A432268e5831";
 
-        let router_address = *SWAP_ROUTER_02_ADDRESSES
-            .get(&provider.get_chain_id().await.unwrap())
-            .expect("Router address not found");
+    /// Fork of rpc.ethereum, which has to point to Arbitrum
+    fn fork() -> Option<LocalEvm> {
+        let config = config();
+        LocalEvm::fork(config.rpc.ethereum_url().ok()?)
+    }
 
-        let input_token =
-            "0x82aF49447D8a07e3bd95BD0d56f35241523fBab1".to_string();
+    async fn router(provider: &EvmProvider) -> Address {
+        *SWAP_ROUTER_02_ADDRESSES
+            .get(&provider.get_chain_id().await.unwrap())
+            .expect("Router address not found")
+    }
 
-        with_local_evm_signer(execute_evm_transaction(
-            move |owner| async move {
+    async fn approve(
+        node: &LocalEvm,
+        signer: LocalEvmSigner,
+        token: &'static str,
+    ) {
+        let provider = node.provider();
+        let router = router(&provider).await;
+        node.run_as(
+            signer,
+            execute_evm_transaction(move |owner| async move {
                 create_approve_tx(
-                    input_token,
-                    router_address.to_string(),
+                    token.to_string(),
+                    router.to_string(),
                     owner.to_string(),
                     &provider,
                 )
                 .await
-            },
-        ))
+            }),
+        )
         .await
         .unwrap();
     }
 
+    #[test]
+    fn test_approve_snapshot() {
+        let token = Address::from_str(WETH).unwrap();
+        // SwapRouter02 on arbitrum
+        let spender =
+            Address::from_str("0x68b3465833fb72A70ecDF485E0e4C7bD8665Fc45")
+                .unwrap();
+        let owner =
+            Address::from_str("0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266")
+                .unwrap();
+        let tx = approve_tx(token, spender, owner, 10_000_000);
+        assert_snapshot("evm_approve", &render_request(&tx));
+    }
+
+    #[tokio::test]
+    async fn test_approval() {
+        let Some(node) = fork() else {
+            return;
+        };
+        let provider = node.provider();
+        approve(&node, node.signer(0), WETH).await;
+        let owner = node.account(0);
+        let router = router(&provider).await;
+        let token = Address::from_str(WETH).unwrap();
+        assert!(check_allowance(token, owner, router, &provider)
+            .await
+            .unwrap());
+    }
+
     #[tokio::test]
     async fn test_trade_evm() {
-        let provider = make_provider().unwrap();
-
-        //  WETH on arbitrum
-        let output_token =
-            "0x82aF49447D8a07e3bd95BD0d56f35241523fBab1".to_string();
-        // USDC on arbitrum
-        let input_token =
-            "0xaf88d065e77c8cC2239327C5EDb3A432268e5831".to_string();
-        // 1 usdc
-        let input_amount = "1000000".to_string();
+        // the dev accounts hold no USDC, trade from the configured wallet
+        let Ok(private_key) = std::env::var("ETHEREUM_PRIVATE_KEY") else {
+            return;
+        };
+        let Some(node) = fork() else {
+            return;
+        };
+        let signer = || {
+            LocalEvmSigner::new(private_key.clone())
+                .with_rpc_url(&node.endpoint())
+        };
+        approve(&node, signer(), USDC).await;
 
-        with_local_evm_signer(execute_evm_transaction(
-            move |owner| async move {
+        // 1 usdc
+        let input_amount = Amount::parse("1", 6).unwrap();
+        let provider = node.provider();
+        node.run_as(
+            signer(),
+            execute_evm_transaction(move |owner| async move {
                 create_trade_tx(
-                    input_token,
-                    input_amount,
-                    output_token,
+                    USDC.to_string(),
+                    &input_amount,
+                    WETH.to_string(),
                     &provider,
                     owner,
                 )
                 .await
-            },
-        ))
+            }),
+        )
         .await
         .unwrap();
     }
@@ -1,3 +1,5 @@
+use alloy::consensus::TxEnvelope;
+use alloy::eips::eip2718::Encodable2718;
 use alloy::network::{EthereumWallet, TransactionBuilder};
 use alloy::providers::Provider;
 use alloy::rpc::types::TransactionRequest;
@@ -5,44 +7,52 @@ use anyhow::{Context, Result};
 use std::time::Duration;
 use tokio::time::sleep;
 
-use super::util::EvmProvider;
+use super::util::{provider_for, EvmProvider};
+use crate::outbox::OUTBOX;
 
+/// Signs the transaction once and hands it to the outbox, which
+/// broadcasts it and waits for the receipt. Only the signing is retried,
+/// a retry never signs a second transaction after one was broadcast
 pub async fn send_transaction(
     request: TransactionRequest,
-    provider: &EvmProvider,
+    rpc_url: &str,
     wallet: &EthereumWallet,
 ) -> Result<String> {
     const MAX_RETRIES: u32 = 3;
     const RETRY_DELAY: Duration = Duration::from_secs(1);
 
-    for attempt in 0..MAX_RETRIES {
-        if attempt > 0 {
-            tracing::info!("Retry attempt {} for transaction", attempt);
-            sleep(RETRY_DELAY).await;
-        }
-
-        match try_send_transaction(request.clone(), provider, wallet).await {
-            Ok(hash) => return Ok(hash),
-            Err(e) => {
-                if attempt == MAX_RETRIES - 1 {
-                    return Err(e);
-                }
-                tracing::warn!("Transaction failed: {:?}. Retrying...", e);
+    let provider = provider_for(rpc_url)?;
+    let mut attempt = 0;
+    let (tx, nonce) = loop {
+        match sign_transaction(request.clone(), &provider, wallet).await {
+            Ok(signed) => break signed,
+            Err(e) if attempt < MAX_RETRIES - 1 => {
+                tracing::warn!("Signing failed: {:?}. Retrying...", e);
+                attempt += 1;
+                sleep(RETRY_DELAY).await;
             }
+            Err(e) => return Err(e),
         }
-    }
+    };
 
-    Err(anyhow::anyhow!(
-        "Failed to send transaction after all retries"
-    ))
+    OUTBOX
+        .send_evm(
+            &tx.encoded_2718(),
+            *tx.tx_hash(),
+            rpc_url,
+            wallet.default_signer().address(),
+            nonce,
+        )
+        .await
 }
 
-async fn try_send_transaction(
+/// Signed transaction and its nonce
+async fn sign_transaction(
     request: TransactionRequest,
     provider: &EvmProvider,
     wallet: &EthereumWallet,
-) -> Result<String> {
-    tracing::info!(?request, "Sending transaction");
+) -> Result<(TxEnvelope, u64)> {
+    tracing::info!(?request, "Signing transaction");
 
     let address = wallet.default_signer().address();
 
@@ -66,14 +76,5 @@ async fn try_send_transaction(
         .build(wallet)
         .await?;
 
-    // Send transaction and wait for receipt
-    let tx_hash = provider
-        .send_tx_envelope(tx)
-        .await
-        .context("Failed to send transaction")?
-        .watch()
-        .await
-        .context("Failed to get transaction receipt")?;
-
-    Ok(tx_hash.to_string())
+    Ok((tx, nonce))
 }
@@ -8,10 +8,11 @@ use anyhow::{Context, Result};
 
 use super::abi::IERC20;
 use super::util::EvmProvider;
+use crate::amount::Amount;
 
 pub async fn create_transfer_eth_tx(
-    to: String,
-    amount: String,
+    to: Address,
+    amount: &Amount,
     provider: &EvmProvider,
     owner: Address,
 ) -> Result<TransactionRequest> {
@@ -30,8 +31,8 @@ pub async fn create_transfer_eth_tx(
     // Create transaction request
     let request = TransactionRequest::default()
         .with_from(owner)
-        .with_to(Address::from_str(&to)?)
-        .with_value(U256::from_str(&amount)?)
+        .with_to(to)
+        .with_value(U256::from(amount.raw()))
         .with_gas_price(gas_price)
         .with_nonce(nonce)
         .with_chain_id(146)
@@ -42,15 +43,12 @@ pub async fn create_transfer_eth_tx(
 
 pub async fn create_transfer_erc20_tx(
     token_address: String,
-    to: String,
-    amount: String,
+    to: Address,
+    amount: &Amount,
     provider: &EvmProvider,
     owner: Address,
 ) -> Result<TransactionRequest> {
-    let call = IERC20::transferCall {
-        to: Address::from_str(&to)?,
-        amount: U256::from_str(&amount)?,
-    };
+    let token = Address::from_str(&token_address)?;
 
     // Get the current gas price
     let gas_price = provider
@@ -58,57 +56,122 @@ pub async fn create_transfer_erc20_tx(
         .await
         .context("Failed to get gas price")?;
 
-    let request = TransactionRequest::default()
+    Ok(transfer_erc20_tx(token, to, amount, owner, gas_price))
+}
+
+/// The transfer at a given gas price
+pub fn transfer_erc20_tx(
+    token: Address,
+    to: Address,
+    amount: &Amount,
+    owner: Address,
+    gas_price: u128,
+) -> TransactionRequest {
+    let call = IERC20::transferCall {
+        to,
+        amount: U256::from(amount.raw()),
+    };
+    TransactionRequest::default()
         .with_from(owner)
-        .with_to(Address::from_str(&token_address)?)
+        .with_to(token)
         .with_call(&call)
-        .with_gas_price(gas_price);
-
-    Ok(request)
+        .with_gas_price(gas_price)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::evm::util::{
-        execute_evm_transaction, make_provider, with_local_evm_signer,
-    };
+    use crate::config::config;
+    use crate::evm::anvil::LocalEvm;
+    use crate::evm::util::execute_evm_transaction;
+    use crate::snapshot::{assert_snapshot, render_request};
+
+    alloy::sol! {
+        #[sol(rpc)]
+        interface IWrappedNative {
+            function deposit() external payable;
+        }
+    }
+
+    // wS on Sonic
+    const WRAPPED_SONIC: &str = "0x039e2fB66102314Ce7b64Ce5Ce3E5183bc94aD38";
+
+    #[test]
+    fn test_transfer_erc20_snapshot() {
+        let token = Address::from_str(WRAPPED_SONIC).unwrap();
+        let to =
+            Address::from_str("0x70997970C51812dc3A010C7d01b50e0d17dc79C8")
+                .unwrap();
+        let owner =
+            Address::from_str("0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266")
+                .unwrap();
+        let amount = Amount::parse("0.5", 18).unwrap();
+        let tx = transfer_erc20_tx(token, to, &amount, owner, 55_000_000_000);
+        assert_snapshot("evm_transfer_erc20", &render_request(&tx));
+    }
 
     #[tokio::test]
     async fn test_transfer_eth() {
-        with_local_evm_signer(execute_evm_transaction(
-            move |owner| async move {
-                create_transfer_eth_tx(
-                    owner.to_string(),
-                    "10000000000000".to_string(),
-                    &make_provider()?,
-                    owner,
-                )
-                .await
-            },
-        ))
+        let Some(node) = LocalEvm::spawn() else {
+            return;
+        };
+        let recipient = node.account(1);
+        let before = node.balance(recipient).await.unwrap();
+        let provider = node.provider();
+        node.run(execute_evm_transaction(move |owner| async move {
+            create_transfer_eth_tx(
+                recipient,
+                &Amount::parse("0.00001", 18)?,
+                &provider,
+                owner,
+            )
+            .await
+        }))
         .await
         .unwrap();
+        assert_eq!(
+            node.balance(recipient).await.unwrap(),
+            before + U256::from(10u64.pow(13))
+        );
     }
 
     #[tokio::test]
     async fn test_transfer_erc20() {
-        with_local_evm_signer(execute_evm_transaction(
-            move |owner: Address| async move {
-                // USDC on ARB
-                let token_address =
-                    "0xaf88d065e77c8cc2239327c5edb3a432268e5831".to_string();
-                create_transfer_erc20_tx(
-                    token_address,
-                    owner.to_string(),
-                    "1000000".to_string(), // 1 USDC
-                    &make_provider()?,
-                    owner,
-                )
-                .await
-            },
-        ))
+        let Some(node) = LocalEvm::fork(&config().rpc.sonic) else {
+            return;
+        };
+        let token = Address::from_str(WRAPPED_SONIC).unwrap();
+        let provider = node.provider();
+        let gas_price = provider.get_gas_price().await.unwrap();
+        node.run(execute_evm_transaction(move |owner| async move {
+            Ok(TransactionRequest::default()
+                .with_from(owner)
+                .with_to(token)
+                .with_call(&IWrappedNative::depositCall {})
+                .with_value(U256::from(10u64.pow(18)))
+                .with_gas_price(gas_price))
+        }))
+        .await
+        .unwrap();
+
+        let recipient = node.account(1);
+        node.run(execute_evm_transaction(move |owner| async move {
+            create_transfer_erc20_tx(
+                WRAPPED_SONIC.to_string(),
+                recipient,
+                &Amount::parse("0.5", 18)?,
+                &provider,
+                owner,
+            )
+            .await
+        }))
         .await
         .unwrap();
+        let balance = IERC20::new(token, &node.provider())
+            .balanceOf(recipient)
+            .call()
+            .await
+            .unwrap();
+        assert_eq!(balance._0, U256::from(5 * 10u64.pow(17)));
     }
 }
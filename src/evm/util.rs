@@ -1,25 +1,58 @@
 use std::future::Future;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::time::Instant;
 
 use alloy::network::EthereumWallet;
 use alloy::primitives::Address;
 use alloy::providers::{ProviderBuilder, RootProvider};
+use alloy::rpc::client::RpcClient;
 use alloy::rpc::types::TransactionRequest;
 use alloy::signers::local::PrivateKeySigner;
 use alloy::transports::http::{Client, Http};
 use anyhow::{anyhow, Result};
 
-use crate::common::wrap_unsafe;
-use crate::signer::evm::LocalEvmSigner;
+use super::explain::explain_request;
+use super::simulate::{simulate_transaction, simulation_enabled};
+use crate::activity::{self, ActivityEvent};
+use crate::address::Address as WalletAddress;
+use crate::cancellation::begin_signing;
+use crate::chains::{Chain, CHAINS};
+use crate::common::{evm_chain, evm_rpc_url, http_client};
+use crate::config::config;
+use crate::gas_tank::GAS_TANK;
+use crate::metrics::record_sign;
+use crate::progress::{report, Stage};
 use crate::signer::SignerContext;
 
 pub type EvmProvider = RootProvider<Http<Client>>;
 
+/// Providers go over the shared HTTP client and its connection pool
+pub fn provider_for(rpc_url: &str) -> Result<EvmProvider> {
+    let transport = Http::with_client(http_client(), rpc_url.parse()?);
+    Ok(ProviderBuilder::new().on_client(RpcClient::new(transport, false)))
+}
+
 pub fn make_provider() -> Result<EvmProvider> {
-    let rpc_url = env("ETHEREUM_RPC_URL");
-    println!("RPC: {:#?}", rpc_url);
-    Ok(ProviderBuilder::new().on_http(rpc_url.parse()?))
+    let config = config();
+    provider_for(config.rpc.ethereum_url()?)
+}
+
+/// Provider for an explicit chain id, falls back to ETHEREUM_RPC_URL for
+/// chains without a dedicated RPC
+pub fn make_provider_for_chain(chain_id: u64) -> Result<EvmProvider> {
+    match evm_rpc_url(chain_id) {
+        Some(rpc_url) => provider_for(&rpc_url),
+        None => make_provider(),
+    }
+}
+
+/// RPC of the chain id, ETHEREUM_RPC_URL without one, see
+/// `make_provider_for_chain`
+pub fn rpc_url_for_chain(chain_id: Option<u64>) -> Result<String> {
+    match chain_id.and_then(evm_rpc_url) {
+        Some(rpc_url) => Ok(rpc_url),
+        None => Ok(config().rpc.ethereum_url()?.to_string()),
+    }
 }
 
 pub fn make_signer() -> Result<PrivateKeySigner> {
@@ -34,32 +67,57 @@ pub fn env(var: &str) -> String {
     std::env::var(var).unwrap_or_else(|_| panic!("{} env var not set", var))
 }
 
-// pub async fn with_local_evm_signer<Fut, T>(future: Fut) -> Result<T>
-// where
-//     Fut: Future<Output = Result<T>> + Send,
-// {
-//     SignerContext::with_signer(
-//         Arc::new(LocalEvmSigner::new(env("ETHEREUM_PRIVATE_KEY"))),
-//         future,
-//     )
-//     .await
-// }
-
 pub async fn execute_evm_transaction<F, Fut>(tx_creator: F) -> Result<String>
 where
     F: FnOnce(Address) -> Fut + Send + 'static,
     Fut: Future<Output = Result<TransactionRequest>> + Send + 'static,
 {
+    CHAINS.require(Chain::Evm)?;
     let signer = SignerContext::current().await;
-    let owner = Address::from_str(&signer.address())?;
+    let owner =
+        WalletAddress::of_signer(signer.as_ref(), Chain::Evm)?.as_evm()?;
 
-    let tx = wrap_unsafe(move || async move { tx_creator(owner).await })
+    let tx = tx_creator(owner).await.map_err(|e| anyhow!("{:#?}", e))?;
+    let provider = match tx.chain_id {
+        Some(chain_id) => make_provider_for_chain(chain_id)?,
+        None => make_provider()?,
+    };
+    if let Err(e) = GAS_TANK
+        .ensure_evm(signer.as_ref(), owner, &tx, &provider)
         .await
-        .map_err(|e| anyhow!("{:#?}", e))?;
+    {
+        tracing::warn!(?e, %owner, "gas tank top up failed");
+    }
+
+    if simulation_enabled() {
+        let sim_tx = tx.clone().from(owner);
+        let result = simulate_transaction(&sim_tx, &provider)
+            .await
+            .map_err(|e| anyhow!("{:#?}", e))?;
+        tracing::info!(?result, "simulated evm transaction");
+        if !result.success {
+            return Err(anyhow!(
+                "Simulation failed: {}",
+                result.revert_reason.unwrap_or_default()
+            ));
+        }
+    }
 
-    wrap_unsafe(move || async move {
-        signer.sign_and_send_evm_transaction(tx).await
-    })
-    .await
-    .map_err(|e| anyhow!("{:#?}", e))
+    let _signing = begin_signing().await?;
+    let explanation = explain_request(&tx).text();
+    tracing::info!(%explanation, "signing evm transaction");
+    report(Stage::Signing, Some(explanation.clone()));
+    let chain_id = tx.chain_id;
+    let started = Instant::now();
+    let hash = signer
+        .sign_and_send_evm_transaction(tx)
+        .await
+        .map_err(|e| anyhow!("{:#?}", e))?;
+    record_sign(&evm_chain(), started.elapsed());
+    report(Stage::Broadcast, Some(hash.clone()));
+    let chain = chain_id.map_or_else(evm_chain, |id| id.to_string());
+    let event = ActivityEvent::new(signer.as_ref(), &chain, chain_id, &hash)
+        .with_description(Some(explanation));
+    activity::record(signer.as_ref(), event).await;
+    Ok(hash)
 }
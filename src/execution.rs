@@ -0,0 +1,245 @@
+//! Per-session execution mode. In dry-run sessions value-moving tools run
+//! their full pipeline (validation, risk checks, quoting, building and
+//! simulating the transactions) against a paper signer and return the
+//! would-be result, nothing is signed nor sent. In paper sessions they are
+//! booked on a virtual portfolio instead (see `paper_trading`). Read-only
+//! tools are untouched
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::confirmation::{confirm_or_execute, preconfirmed};
+use crate::kv_store::{KVStore, KV_STORE};
+use crate::signer::paper::PaperSigner;
+use crate::signer::{SignerContext, TransactionSigner};
+
+/// Tools that sign and send transactions, the ones a dry run applies to
+const VALUE_MOVING_TOOLS: &[&str] = &[
+    "approve_token_for_router_spend",
+    "trade",
+    "transfer_eth",
+    "transfer_erc20",
+    "approve_token_for_position_manager",
+    "mint_lp_position",
+    "increase_lp_liquidity",
+    "decrease_lp_liquidity",
+    "collect_lp_fees",
+    "bridge_via_gateway",
+    "claim_gateway_transfer",
+    "perform_jupiter_swap",
+    "transfer_sol",
+    "transfer_spl_token",
+    "deploy_pump_fun_token",
+    "buy_pump_fun_token",
+    "sell_pump_fun_token",
+    "multichain_swap",
+    "approve_token",
+    "confirm_action",
+    "execute_quote",
+    "call_anchor_program",
+    "move_to_savings",
+    "withdraw_from_savings",
+];
+
+pub fn moves_value(tool: &str) -> bool {
+    VALUE_MOVING_TOOLS.contains(&tool)
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionMode {
+    #[default]
+    Live,
+    DryRun,
+    Paper,
+}
+
+impl ExecutionMode {
+    /// Mode of sessions that never set one, EXECUTION_MODE takes live,
+    /// dry_run or paper, unset means live
+    pub fn from_env() -> Result<Self> {
+        match std::env::var("EXECUTION_MODE") {
+            Ok(mode) => mode.parse(),
+            Err(_) => Ok(Self::Live),
+        }
+    }
+}
+
+impl FromStr for ExecutionMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "live" => Ok(Self::Live),
+            "dry_run" => Ok(Self::DryRun),
+            "paper" => Ok(Self::Paper),
+            s => Err(anyhow!("Invalid execution mode: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ExecutionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Live => write!(f, "live"),
+            Self::DryRun => write!(f, "dry_run"),
+            Self::Paper => write!(f, "paper"),
+        }
+    }
+}
+
+pub struct ExecutionModes {
+    store: Arc<dyn KVStore>,
+}
+
+pub static EXECUTION_MODES: Lazy<ExecutionModes> =
+    Lazy::new(|| ExecutionModes::new(KV_STORE.clone()));
+
+impl ExecutionModes {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self { store }
+    }
+
+    fn key(session: &str) -> String {
+        format!("execution:mode:{}", session)
+    }
+
+    pub async fn get(&self, session: &str) -> Result<ExecutionMode> {
+        match self.store.get(&Self::key(session)).await? {
+            Some(mode) => mode.parse(),
+            None => ExecutionMode::from_env(),
+        }
+    }
+
+    pub async fn set(
+        &self,
+        session: &str,
+        mode: ExecutionMode,
+    ) -> Result<()> {
+        self.store.set(&Self::key(session), &mode.to_string()).await
+    }
+}
+
+/// Sessions of Privy users are keyed by their id, local signers share one
+pub(crate) fn session_key(signer: &dyn TransactionSigner) -> String {
+    signer
+        .session_id()
+        .or_else(|| signer.user_id())
+        .unwrap_or_else(|| "local".to_string())
+}
+
+/// Mode of the session of `signer`, paper signers are always dry
+pub async fn mode_of(
+    signer: &dyn TransactionSigner,
+) -> Result<ExecutionMode> {
+    if signer.is_paper() {
+        return Ok(ExecutionMode::DryRun);
+    }
+    EXECUTION_MODES.get(&session_key(signer)).await
+}
+
+/// Runs `call` under a simulating paper signer wrapping `signer`, the
+/// confirmation step is skipped as nothing can be executed. Returns the
+/// result of the tool together with the simulated transactions
+pub async fn dry_run(
+    signer: Arc<dyn TransactionSigner>,
+    call: impl Future<Output = Result<String>> + Send,
+) -> Result<String> {
+    let paper = Arc::new(PaperSigner::new(signer).with_simulation());
+    let result =
+        SignerContext::with_signer(paper.clone(), preconfirmed(call)).await?;
+    // tools return serialized JSON, plain strings are passed as is
+    let result = serde_json::from_str::<Value>(&result)
+        .unwrap_or(Value::String(result));
+
+    Ok(json!({
+        "mode": "dry_run",
+        "result": result,
+        "simulations": paper.simulations(),
+        "balance_changes": paper.balance_deltas(),
+        "message": "Nothing was signed nor sent, this session is in dry-run mode",
+    })
+    .to_string())
+}
+
+#[tool(description = "
+Returns the execution mode of the session: live, dry_run in which
+transactions are only simulated and never sent, or paper in which trades are
+booked on a virtual portfolio
+")]
+pub async fn get_execution_mode() -> Result<ExecutionMode> {
+    mode_of(SignerContext::current().await.as_ref()).await
+}
+
+#[tool(description = "
+Switches the session between live, dry_run and paper execution, this
+requires the user's confirmation. In dry_run swaps, transfers and bridges are
+simulated and their would-be result is returned, nothing is signed nor sent.
+In paper swaps and transfers are booked on a virtual portfolio, see
+get_paper_portfolio and reset_paper_portfolio
+")]
+pub async fn set_execution_mode(mode: String) -> Result<String> {
+    let mode = ExecutionMode::from_str(&mode)?;
+    let session = session_key(SignerContext::current().await.as_ref());
+
+    let summary = format!("Switch the session to {} execution", mode);
+    confirm_or_execute("set_execution_mode", summary, move || async move {
+        EXECUTION_MODES.set(&session, mode).await?;
+        Ok(format!("Execution mode set to {}", mode))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    struct SessionSigner;
+
+    impl TransactionSigner for SessionSigner {
+        fn session_id(&self) -> Option<String> {
+            Some("session".to_string())
+        }
+
+        fn user_id(&self) -> Option<String> {
+            Some("alice".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execution_modes() {
+        let modes = ExecutionModes::new(Arc::new(InMemoryKVStore::new()));
+        let session = session_key(&SessionSigner);
+        assert_eq!(session, "session");
+        assert_eq!(modes.get(&session).await.unwrap(), ExecutionMode::Live);
+        modes.set(&session, ExecutionMode::DryRun).await.unwrap();
+        assert_eq!(modes.get(&session).await.unwrap(), ExecutionMode::DryRun);
+        assert_eq!(modes.get("other").await.unwrap(), ExecutionMode::Live);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_uses_paper_signer() {
+        let result = dry_run(Arc::new(SessionSigner), async {
+            let signer = SignerContext::current().await;
+            assert!(signer.is_paper());
+            assert_eq!(signer.session_id().as_deref(), Some("session"));
+            Ok(json!({ "status": "swapped" }).to_string())
+        })
+        .await
+        .unwrap();
+
+        let result: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(result["mode"], "dry_run");
+        assert_eq!(result["result"]["status"], "swapped");
+    }
+}
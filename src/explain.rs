@@ -0,0 +1,74 @@
+//! Plain-English explanations of transactions, from an encoded transaction
+//! or the hash/signature of a sent one. Also shown as the last step before
+//! signing, the explanation of the built transaction goes out with the
+//! signing progress event
+use anyhow::Result;
+use rig_tool_macro::tool;
+use serde::Serialize;
+
+use crate::tool_error::ToolError;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Explanation {
+    pub chain: String,
+    /// what the transaction does, one entry per instruction or call
+    pub steps: Vec<String>,
+    /// unlimited approvals, unknown programs and the like
+    pub warnings: Vec<String>,
+}
+
+impl Explanation {
+    pub fn new(chain: &str) -> Self {
+        Self {
+            chain: chain.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn step(&mut self, step: impl Into<String>) {
+        self.steps.push(step.into());
+    }
+
+    pub fn warn(&mut self, warning: impl Into<String>) {
+        self.warnings.push(warning.into());
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = self.steps.join("\n");
+        for warning in &self.warnings {
+            text.push_str(&format!("\nWarning: {}", warning));
+        }
+        text
+    }
+}
+
+/// Explains a 0x-prefixed EVM transaction, hash or calldata, anything else
+/// is taken as a Solana transaction (base64 or base58) or signature
+pub async fn explain(encoded_tx_or_hash: &str) -> Result<Explanation> {
+    let input = encoded_tx_or_hash.trim();
+    if input.is_empty() {
+        return Err(ToolError::invalid_input("Nothing to explain").into());
+    }
+    if input.starts_with("0x") {
+        return crate::evm::explain::explain(input).await;
+    }
+    crate::solana::explain::explain(input).await
+}
+
+#[tool(description = "
+Explains in plain English what a transaction does or did: transfers,
+approvals, swaps and the programs or contracts it calls, with warnings for
+unlimited approvals and unknown programs.
+
+encoded_tx_or_hash is one of:
+- a Solana transaction, base64 or base58 encoded, or a transaction signature
+- an EVM transaction hash, a signed raw transaction or calldata, 0x-prefixed
+
+Use it to answer questions like \"what did this transaction do\" or to walk
+the user through a transaction before they sign it
+")]
+pub async fn explain_transaction(
+    encoded_tx_or_hash: String,
+) -> Result<Explanation> {
+    explain(&encoded_tx_or_hash).await
+}
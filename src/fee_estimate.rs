@@ -0,0 +1,412 @@
+//! Fee estimates of planned actions, so the agent can warn a user whose
+//! balance won't cover the fees before anything is sent. Solana actions pay
+//! the base fee of their signatures, the priority fee of the session (see
+//! `priority_fees`) and the rent of the accounts they create. EVM actions
+//! pay gas at the current price, bridges through the Sonic gateway on both
+//! chains, bridges through LiFi only on the chain they start on (the
+//! destination is relayed). Gas limits are the typical ones of the action,
+//! not a simulation
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use alloy::providers::Provider;
+use anyhow::Result;
+use rig_tool_macro::tool;
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use spl_associated_token_account::get_associated_token_address;
+
+use crate::chains::Chain;
+use crate::common::solana_rpc;
+use crate::config::config;
+use crate::costs::LAMPORTS_PER_SIGNATURE;
+use crate::error::Error;
+use crate::evm::balance::balance;
+use crate::evm::util::{make_provider_for_chain, EvmProvider};
+use crate::pricing::{lifi_chain, to_ui_amount};
+use crate::signer::SignerContext;
+use crate::solana::congestion::SWAP_COMPUTE_UNITS;
+use crate::solana::priority_fees::{
+    priority_fee, SPL_TRANSFER_COMPUTE_UNITS, TRANSFER_COMPUTE_UNITS,
+};
+use crate::solana::rent::{self, AccountKind};
+use crate::tool_error::ToolError;
+
+const ACTIONS: &[&str] = &["swap", "transfer", "deploy", "bridge"];
+
+/// Typical gas of an EVM action
+fn gas_limit(action: &str, native: bool) -> u64 {
+    match (action, native) {
+        ("transfer", true) => 21_000,
+        ("transfer", false) => 65_000,
+        // the approval and the swap
+        ("swap", _) => 50_000 + 250_000,
+        ("deploy", _) => 1_500_000,
+        _ => 0,
+    }
+}
+
+/// Gas of the approval and deposit on the source chain and of the claim on
+/// the destination of a bridge through the Sonic gateway
+const BRIDGE_SOURCE_GAS: u64 = 50_000 + 150_000;
+const BRIDGE_CLAIM_GAS: u64 = 350_000;
+/// Gas of the approval and the LiFi bridge call on the source chain
+const LIFI_BRIDGE_GAS: u64 = 50_000 + 250_000;
+
+/// Chains paying gas for a bridge from the EVM chain `source` with their
+/// gas. eth and sonic bridge to each other through the Sonic gateway and
+/// claim on the destination, the other routes go through LiFi
+fn bridge_legs<'a>(
+    source: &'a str,
+    destination: &'a str,
+) -> Result<Vec<(&'a str, u64)>> {
+    let gateway = match source {
+        "eth" => Some("sonic"),
+        "sonic" => Some("eth"),
+        _ => None,
+    };
+    match gateway {
+        Some(gateway) if destination.is_empty() || destination == gateway => {
+            Ok(vec![
+                (source, BRIDGE_SOURCE_GAS),
+                (gateway, BRIDGE_CLAIM_GAS),
+            ])
+        }
+        _ => {
+            check_destination(source, destination)?;
+            Ok(vec![(source, LIFI_BRIDGE_GAS)])
+        }
+    }
+}
+
+fn check_destination(source: &str, destination: &str) -> Result<()> {
+    let solana = |chain| Chain::of_key(chain) == Chain::Solana;
+    if destination.is_empty()
+        || destination == source
+        || (solana(source) && solana(destination))
+    {
+        return Err(ToolError::invalid_input(format!(
+            "A bridge from {} needs the chain it ends on",
+            source
+        ))
+        .with_hint("Pass the destination chain, e.g. sol, sonic or base")
+        .into());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Fee {
+    pub chain: String,
+    /// network, priority, rent or gas
+    pub kind: &'static str,
+    /// in the native token of the chain
+    pub amount: f64,
+    pub symbol: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FeeEstimate {
+    pub action: String,
+    pub fees: Vec<Fee>,
+    /// native balance of the wallet by chain
+    pub balances: BTreeMap<String, f64>,
+    pub can_cover: bool,
+    pub warnings: Vec<String>,
+}
+
+fn native_symbol(chain: &str) -> &'static str {
+    match chain {
+        "sol" => "SOL",
+        "sonic" => "S",
+        _ => "ETH",
+    }
+}
+
+fn fee(chain: &str, kind: &'static str, amount: f64) -> Fee {
+    Fee {
+        chain: chain.to_string(),
+        kind,
+        amount,
+        symbol: native_symbol(chain),
+    }
+}
+
+/// Fees of a Solana action in lamports, `rents` are the rents of the
+/// accounts it creates
+fn solana_fees(
+    signatures: u64,
+    priority_fee: Option<u64>,
+    rents: &[u64],
+) -> Vec<(&'static str, u64)> {
+    let mut fees = vec![("network", signatures * LAMPORTS_PER_SIGNATURE)];
+    fees.extend(priority_fee.filter(|fee| *fee > 0).map(|f| ("priority", f)));
+    let rent = rents.iter().sum::<u64>();
+    if rent > 0 {
+        fees.push(("rent", rent));
+    }
+    fees
+}
+
+/// Compares the fees with the balances, warns for every chain whose
+/// balance falls short
+fn coverage(
+    fees: &[Fee],
+    balances: &BTreeMap<String, f64>,
+) -> (bool, Vec<String>) {
+    let mut totals = BTreeMap::<&str, f64>::new();
+    for fee in fees {
+        *totals.entry(&fee.chain).or_default() += fee.amount;
+    }
+    let warnings = totals
+        .into_iter()
+        .filter_map(|(chain, total)| {
+            let balance = balances.get(chain).copied().unwrap_or(0.);
+            (balance < total).then(|| {
+                format!(
+                    "The fees on {} need about {:.6} {}, the wallet holds \
+                     {:.6}",
+                    chain,
+                    total,
+                    native_symbol(chain),
+                    balance
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    (warnings.is_empty(), warnings)
+}
+
+/// Whether the token account of `mint` held by `owner` has to be created,
+/// RPC errors are errors rather than a missing account
+async fn missing_token_account(owner: &Pubkey, mint: &str) -> Result<bool> {
+    let mint = Pubkey::from_str(mint)
+        .map_err(|_| Error::UserInput(format!("Invalid mint {}", mint)))?;
+    let ata = get_associated_token_address(owner, &mint);
+    let rpc = solana_rpc();
+    let account = rpc
+        .get_account_with_commitment(&ata, rpc.commitment())
+        .await
+        .map_err(Error::from)?;
+    Ok(account.value.is_none())
+}
+
+async fn estimate_solana(
+    action: &str,
+    token: &str,
+    recipient: &str,
+    destination: &str,
+) -> Result<(Vec<Fee>, f64)> {
+    let rpc = solana_rpc();
+    let owner = Pubkey::from_str(&SignerContext::current().await.pubkey())?;
+    let account_rent =
+        rent::minimum_balance(AccountKind::TokenAccount.size()).await?;
+
+    let mut rents = vec![];
+    let signatures = match action {
+        "swap" => {
+            if !token.is_empty()
+                && missing_token_account(&owner, token).await?
+            {
+                rents.push(account_rent);
+            }
+            1
+        }
+        "transfer" => {
+            if !token.is_empty() {
+                let recipient =
+                    Pubkey::from_str(recipient).map_err(|_| {
+                        Error::UserInput("recipient is required".to_string())
+                    })?;
+                if missing_token_account(&recipient, token).await? {
+                    rents.push(account_rent);
+                }
+            }
+            1
+        }
+        // the mint signs the deploy too
+        "deploy" => {
+            let mint_rent =
+                rent::minimum_balance(AccountKind::Mint.size()).await?;
+            rents.push(mint_rent);
+            rents.push(account_rent);
+            2
+        }
+        // through LiFi, relayed on the destination
+        _ => {
+            check_destination("sol", destination)?;
+            1
+        }
+    };
+    let compute_units = match action {
+        "transfer" if token.is_empty() => TRANSFER_COMPUTE_UNITS,
+        "transfer" => SPL_TRANSFER_COMPUTE_UNITS,
+        _ => SWAP_COMPUTE_UNITS,
+    };
+    let priority_fee = priority_fee(&[owner], compute_units).await;
+
+    let fees = solana_fees(signatures, priority_fee, &rents)
+        .into_iter()
+        .map(|(kind, lamports)| {
+            Ok(fee("sol", kind, to_ui_amount(&lamports.to_string(), 9)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let balance = rpc.get_balance(&owner).await.map_err(Error::from)?;
+    Ok((fees, to_ui_amount(&balance.to_string(), 9)?))
+}
+
+fn evm_provider(chain: &str) -> Result<EvmProvider> {
+    let chain_id = lifi_chain(chain).parse::<u64>().map_err(|_| {
+        ToolError::invalid_input(format!("Unknown chain {}", chain))
+            .with_hint("Use sol, sonic, eth, arb or base")
+    })?;
+    make_provider_for_chain(chain_id)
+}
+
+/// Gas fee of `gas` units on `chain` and the native balance of `owner`
+async fn evm_gas(chain: &str, gas: u64, owner: &str) -> Result<(Fee, f64)> {
+    let provider = evm_provider(chain)?;
+    let gas_price = provider.get_gas_price().await?;
+    let cost = gas_price * gas as u128;
+    let balance = balance(&provider, owner.to_string()).await?;
+    Ok((
+        fee(chain, "gas", to_ui_amount(&cost.to_string(), 18)?),
+        to_ui_amount(&balance, 18)?,
+    ))
+}
+
+#[tool(description = "
+Estimates the fees of a planned action in the native token of the chain
+(SOL, S or ETH) before executing it: network and priority fees, the rent of
+the token accounts a Solana action creates and the gas of EVM actions, on
+both chains for bridges. It compares them with the wallet balance, warn the
+user and suggest topping up when can_cover is false.
+
+action is swap, transfer, deploy or bridge
+chain is sol, sonic, eth, arb or base, the chain a bridge starts on
+token is the output token of a swap or the token of a transfer, empty for
+the native token
+recipient is the address receiving a transfer, empty otherwise
+destination is the chain a bridge ends on, empty for the Sonic gateway from
+eth to sonic or back, other bridges go through LiFi
+")]
+pub async fn estimate_fees(
+    action: String,
+    chain: String,
+    token: String,
+    recipient: String,
+    destination: String,
+) -> Result<FeeEstimate> {
+    let action = action.trim().to_lowercase();
+    let chain = chain.trim().to_lowercase();
+    let destination = destination.trim().to_lowercase();
+    if !ACTIONS.contains(&action.as_str()) {
+        return Err(ToolError::invalid_input(format!(
+            "Can't estimate the fees of {}",
+            action
+        ))
+        .with_hint(format!("Use one of {}", ACTIONS.join(", ")))
+        .into());
+    }
+
+    let mut balances = BTreeMap::new();
+    let fees = if Chain::of_key(&chain) == Chain::Solana {
+        let (fees, balance) = estimate_solana(
+            &action,
+            token.trim(),
+            recipient.trim(),
+            &destination,
+        )
+        .await?;
+        // fees are on "sol" whatever key the chain was given with
+        balances.insert("sol".to_string(), balance);
+        fees
+    } else {
+        let owner = SignerContext::current().await.address();
+        let legs = if action == "bridge" {
+            bridge_legs(&chain, &destination)?
+        } else {
+            vec![(
+                chain.as_str(),
+                gas_limit(&action, token.trim().is_empty()),
+            )]
+        };
+        let mut fees = vec![];
+        for (leg, gas) in legs {
+            let (fee, balance) = evm_gas(leg, gas, &owner).await?;
+            fees.push(fee);
+            balances.insert(leg.to_string(), balance);
+        }
+        fees
+    };
+
+    let (can_cover, mut warnings) = coverage(&fees, &balances);
+    if !can_cover && config().gas_tank.enabled {
+        warnings.push(
+            "The gas tank of the operator tops up the shortfall when the \
+             action is sent, within its daily limit"
+                .to_string(),
+        );
+    }
+    Ok(FeeEstimate {
+        action,
+        fees,
+        balances,
+        can_cover,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fees_and_coverage() {
+        let fees = solana_fees(2, Some(60_000), &[1_461_600, 2_039_280]);
+        assert_eq!(
+            fees,
+            [
+                ("network", 10_000),
+                ("priority", 60_000),
+                ("rent", 3_500_880)
+            ]
+        );
+        assert_eq!(solana_fees(1, Some(0), &[]), [("network", 5_000)]);
+
+        let fees = vec![
+            fee("eth", "gas", 0.004),
+            fee("sonic", "gas", 0.02),
+            fee("sonic", "gas", 0.01),
+        ];
+        let balances = BTreeMap::from([
+            ("eth".to_string(), 0.01),
+            ("sonic".to_string(), 0.025),
+        ]);
+        let (can_cover, warnings) = coverage(&fees, &balances);
+        assert!(!can_cover);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("The fees on sonic"));
+    }
+
+    #[test]
+    fn test_bridge_legs() {
+        let gateway =
+            [("eth", BRIDGE_SOURCE_GAS), ("sonic", BRIDGE_CLAIM_GAS)];
+        assert_eq!(bridge_legs("eth", "").unwrap(), gateway);
+        assert_eq!(bridge_legs("eth", "sonic").unwrap(), gateway);
+        assert_eq!(
+            bridge_legs("sonic", "sol").unwrap(),
+            [("sonic", LIFI_BRIDGE_GAS)]
+        );
+        assert_eq!(
+            bridge_legs("arb", "base").unwrap(),
+            [("arb", LIFI_BRIDGE_GAS)]
+        );
+        assert!(bridge_legs("arb", "").is_err());
+        assert!(bridge_legs("base", "base").is_err());
+        assert!(check_destination("sol", "sonic").is_ok());
+        assert!(check_destination("sol", "").is_err());
+        assert!(check_destination("sol", "solana").is_err());
+    }
+}
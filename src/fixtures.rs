@@ -0,0 +1,37 @@
+//! Recorded API responses in mocks/, named by their path there without the
+//! extension, e.g. `fixture("jupiter/quote")`. Shared by the tests and the
+//! benches, which get it through the test-utils feature
+use serde_json::Value;
+
+/// Wallet the swap fixtures were recorded for
+pub const OWNER: &str = "6anbDQNCcVh2f6okexjaX1VGj6tEnizJ1kV5UTBS8Zhi";
+
+/// Body of mocks/`name`.json as it was recorded
+pub fn fixture_body(name: &str) -> String {
+    let path = format!("mocks/{}.json", name);
+    std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Failed to read fixture {}: {}", path, e))
+}
+
+pub fn fixture(name: &str) -> Value {
+    serde_json::from_str(&fixture_body(name))
+        .unwrap_or_else(|e| panic!("Invalid fixture {}: {}", name, e))
+}
+
+/// Serves the recorded getLatestBlockhash of the Solana RPC
+#[cfg(test)]
+pub async fn mock_latest_blockhash(server: &wiremock::MockServer) {
+    use wiremock::matchers::{body_partial_json, method};
+    use wiremock::{Mock, ResponseTemplate};
+
+    Mock::given(method("POST"))
+        .and(body_partial_json(
+            serde_json::json!({ "method": "getLatestBlockhash" }),
+        ))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(fixture("jupiter/latest_blockhash")),
+        )
+        .mount(server)
+        .await;
+}
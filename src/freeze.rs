@@ -0,0 +1,214 @@
+//! Kill switch of a wallet, for users who think their account (e.g. their
+//! Telegram) is compromised. Every transaction is signed past
+//! `begin_signing`, which refuses to sign for a frozen user whatever the
+//! signer backend or the caller (a tool call, a confirmed action, a
+//! scheduled task, a trigger or a job) is. Freezing also cancels the
+//! in-flight calls and rejects the pending actions of the user. Freezes are
+//! kept in the crate store and survive restarts. Users can freeze their own
+//! wallet with `freeze_my_wallet`, only operators lift a freeze (the admin
+//! endpoints), someone holding the account can't undo it
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+
+use crate::cancellation::{
+    cancel_user_calls, owner_key, CancelReport, CANCELLATIONS,
+};
+use crate::confirmation::CONFIRMATIONS;
+use crate::kv_store::{namespaced, KVStore};
+use crate::signer::{SignerContext, TransactionSigner};
+use crate::tool_error::{ErrorCode, ToolError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrozenBy {
+    Admin,
+    User,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalletFreeze {
+    pub user_id: String,
+    pub frozen: bool,
+    /// who froze or unfroze the wallet last
+    pub by: FrozenBy,
+    pub reason: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FreezeReport {
+    pub freeze: WalletFreeze,
+    pub cancelled: CancelReport,
+}
+
+pub struct WalletFreezes {
+    store: Arc<dyn KVStore>,
+}
+
+pub static FREEZES: Lazy<WalletFreezes> =
+    Lazy::new(|| WalletFreezes::new(Arc::new(namespaced("freeze"))));
+
+impl WalletFreezes {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self { store }
+    }
+
+    /// Last freeze or unfreeze of the user, None when there was none
+    pub async fn get(&self, user_id: &str) -> Result<Option<WalletFreeze>> {
+        match self.store.get(user_id).await? {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn set(
+        &self,
+        user_id: &str,
+        frozen: bool,
+        by: FrozenBy,
+        reason: Option<String>,
+    ) -> Result<WalletFreeze> {
+        let freeze = WalletFreeze {
+            user_id: user_id.to_string(),
+            frozen,
+            by,
+            reason,
+            updated_at: Utc::now(),
+        };
+        self.store
+            .set(user_id, &serde_json::to_string(&freeze)?)
+            .await?;
+        tracing::warn!(user_id, frozen, ?by, "wallet freeze changed");
+        Ok(freeze)
+    }
+
+    pub async fn freeze(
+        &self,
+        user_id: &str,
+        by: FrozenBy,
+        reason: Option<String>,
+    ) -> Result<WalletFreeze> {
+        self.set(user_id, true, by, reason).await
+    }
+
+    pub async fn unfreeze(&self, user_id: &str) -> Result<WalletFreeze> {
+        self.set(user_id, false, FrozenBy::Admin, None).await
+    }
+
+    /// Fails when the wallet of the user is frozen, and when the store
+    /// can't tell as a kill switch has to fail closed
+    pub async fn check(&self, user_id: &str) -> Result<()> {
+        match self.get(user_id).await? {
+            Some(freeze) if freeze.frozen => Err(ToolError::new(
+                ErrorCode::AccessDenied,
+                "The wallet is frozen, nothing can be signed for it",
+            )
+            .with_hint(
+                "Tell the user the wallet stays frozen until support \
+                 unfreezes it, do not retry",
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Refuses to sign for the user behind `signer` while they are frozen
+pub async fn check_signer(signer: &dyn TransactionSigner) -> Result<()> {
+    FREEZES.check(&owner_key(signer)).await
+}
+
+/// Freezes the wallet of `user_id` and stops what it has in flight, for
+/// operators
+pub async fn freeze_wallet(
+    user_id: &str,
+    reason: Option<String>,
+) -> Result<FreezeReport> {
+    let freeze = FREEZES.freeze(user_id, FrozenBy::Admin, reason).await?;
+    let owner = Some(user_id.to_string());
+    Ok(FreezeReport {
+        freeze,
+        cancelled: CancelReport {
+            cancelled_calls: CANCELLATIONS.cancel_owner(user_id, None),
+            rejected_actions: CONFIRMATIONS
+                .reject_all(&owner)
+                .into_iter()
+                .map(|action| action.summary)
+                .collect(),
+        },
+    })
+}
+
+/// Lets the wallet of `user_id` sign again, for operators
+pub async fn unfreeze_wallet(user_id: &str) -> Result<WalletFreeze> {
+    FREEZES.unfreeze(user_id).await
+}
+
+#[tool(description = "
+Emergency freeze of the user's wallet: blocks every transaction, including
+the scheduled tasks, trigger orders and TWAPs, cancels what is running and
+rejects the pending actions. Use it only when the user asks to freeze or
+lock their wallet or says their account is compromised. The user can't
+unfreeze it, only support can, tell them so. reason is what the user said
+happened, pass an empty string when they said nothing
+")]
+pub async fn freeze_my_wallet(reason: String) -> Result<FreezeReport> {
+    let signer = SignerContext::current().await;
+    let reason = (!reason.trim().is_empty()).then_some(reason);
+    let freeze = FREEZES
+        .freeze(&owner_key(signer.as_ref()), FrozenBy::User, reason)
+        .await?;
+    Ok(FreezeReport {
+        freeze,
+        cancelled: cancel_user_calls(signer.as_ref()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::cancellation::begin_signing;
+    use crate::kv_store::InMemoryKVStore;
+    use crate::signer::mock::MockSigner;
+
+    #[tokio::test]
+    async fn test_wallet_freeze() {
+        let freezes = WalletFreezes::new(Arc::new(InMemoryKVStore::new()));
+        assert!(freezes.check("alice").await.is_ok());
+        freezes
+            .freeze("alice", FrozenBy::User, Some("phone lost".into()))
+            .await
+            .unwrap();
+        let e = freezes.check("alice").await.unwrap_err();
+        let e = e.downcast_ref::<ToolError>().unwrap();
+        assert_eq!(e.code, ErrorCode::AccessDenied);
+        assert!(freezes.check("bob").await.is_ok());
+        let freeze = freezes.unfreeze("alice").await.unwrap();
+        assert_eq!(freeze.by, FrozenBy::Admin);
+        assert!(freezes.check("alice").await.is_ok());
+
+        // signing is refused for any signer of the user
+        let signer = Arc::new(
+            MockSigner::new(Duration::ZERO).with_user("frozen-test-user"),
+        );
+        freeze_wallet("frozen-test-user", None).await.unwrap();
+        let result = SignerContext::with_signer(signer.clone(), async {
+            begin_signing().await.map(|_| ())
+        })
+        .await;
+        assert!(result.is_err());
+        unfreeze_wallet("frozen-test-user").await.unwrap();
+        let result = SignerContext::with_signer(signer, async {
+            begin_signing().await.map(|_| ())
+        })
+        .await;
+        assert!(result.is_ok());
+    }
+}
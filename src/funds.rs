@@ -0,0 +1,388 @@
+//! Balance checks of built transactions, run before a transaction is sent
+//! to Privy so a wallet that can't pay gets "you need X more SOL" instead
+//! of an opaque rejection of the chain or of Privy. Solana transactions
+//! need the fees, the lamports they move and the rent of the token
+//! accounts they create, EVM ones the gas and their value. Token transfers
+//! made by the instructions (or the call) of the transaction itself are
+//! checked against the balance of the token, transfers made by the
+//! programs they call are left to the chain
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use alloy::primitives::{Address, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionRequest;
+use anyhow::Result;
+use serde_json::json;
+use solana_sdk::message::VersionedMessage;
+use solana_sdk::program_pack::Pack;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::system_instruction::SystemInstruction;
+use solana_sdk::transaction::{Transaction, VersionedTransaction};
+use spl_token::instruction::TokenInstruction;
+
+use crate::common::solana_rpc;
+use crate::costs::LAMPORTS_PER_SIGNATURE;
+use crate::error::Error;
+use crate::evm::balance::{token_balance, token_decimals};
+use crate::evm::util::{make_provider, make_provider_for_chain, EvmProvider};
+use crate::pricing::to_ui_amount;
+use crate::solana::constants::ASSOCIATED_TOKEN_PROGRAM;
+use crate::solana::rent::{self, AccountKind};
+
+/// Compute units of an instruction without an explicit limit
+const DEFAULT_COMPUTE_UNITS: u64 = 200_000;
+/// Selector of ERC-20 transfer(address,uint256)
+const ERC20_TRANSFER: [u8; 4] = [0xa9, 0x05, 0x9c, 0xbb];
+
+/// What a transaction costs its sender in the native token: the fees with
+/// the rent of the token accounts it creates, and the value it moves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Need<T> {
+    pub fees: T,
+    pub value: T,
+}
+
+impl<T: std::ops::Add<Output = T>> Need<T> {
+    pub fn total(self) -> T {
+        self.fees + self.value
+    }
+}
+
+/// Lamports `owner` needs to send `message`: the base and priority fees
+/// and the rent of the token accounts it creates, then the lamports it
+/// transfers or funds new accounts with. Accounts of v0 messages loaded
+/// from lookup tables are not resolved, the owner always is a static key
+pub(crate) fn solana_need(
+    owner: &Pubkey,
+    message: &VersionedMessage,
+    account_rent: u64,
+) -> Need<u64> {
+    let keys = message.static_account_keys();
+    let mut fees = message.header().num_required_signatures as u64
+        * LAMPORTS_PER_SIGNATURE;
+    let mut value = 0;
+    let (mut unit_price, mut unit_limit) = (0u64, None);
+    let mut instructions = 0u64;
+    for ix in message.instructions() {
+        let Some(program) = keys.get(ix.program_id_index as usize) else {
+            continue;
+        };
+        let paid_by_owner = ix
+            .accounts
+            .first()
+            .and_then(|&index| keys.get(index as usize))
+            == Some(owner);
+        if *program == solana_sdk::compute_budget::id() {
+            match ix.data.first() {
+                Some(2) => {
+                    unit_limit = ix
+                        .data
+                        .get(1..5)
+                        .and_then(|b| b.try_into().ok())
+                        .map(|b| u32::from_le_bytes(b) as u64)
+                }
+                Some(3) => {
+                    unit_price = ix
+                        .data
+                        .get(1..9)
+                        .and_then(|b| b.try_into().ok())
+                        .map_or(0, u64::from_le_bytes)
+                }
+                _ => {}
+            }
+            continue;
+        }
+        instructions += 1;
+        if !paid_by_owner {
+            continue;
+        }
+        if *program == solana_sdk::system_program::id() {
+            match bincode::deserialize::<SystemInstruction>(&ix.data) {
+                Ok(SystemInstruction::Transfer { lamports })
+                | Ok(SystemInstruction::CreateAccount { lamports, .. }) => {
+                    value += lamports
+                }
+                _ => {}
+            }
+        } else if program.to_string() == ASSOCIATED_TOKEN_PROGRAM {
+            fees += account_rent;
+        }
+    }
+    let units =
+        unit_limit.unwrap_or(instructions.max(1) * DEFAULT_COMPUTE_UNITS);
+    fees += (unit_price as u128 * units as u128 / 1_000_000) as u64;
+    Need { fees, value }
+}
+
+/// Raw amounts `owner` moves out of its token accounts with the top level
+/// token instructions of `tx`, by source account
+fn solana_token_spend(
+    owner: &Pubkey,
+    tx: &Transaction,
+) -> BTreeMap<Pubkey, u64> {
+    let keys = &tx.message.account_keys;
+    let key = |ix: &solana_sdk::instruction::CompiledInstruction,
+               at: usize| {
+        ix.accounts.get(at).and_then(|&i| keys.get(i as usize))
+    };
+    let mut spend = BTreeMap::new();
+    for ix in &tx.message.instructions {
+        if keys.get(ix.program_id_index as usize) != Some(&spl_token::id()) {
+            continue;
+        }
+        let (amount, authority) = match TokenInstruction::unpack(&ix.data) {
+            Ok(TokenInstruction::Transfer { amount }) => (amount, 2),
+            Ok(TokenInstruction::TransferChecked { amount, .. }) => {
+                (amount, 3)
+            }
+            _ => continue,
+        };
+        if let (Some(source), Some(authority)) =
+            (key(ix, 0), key(ix, authority))
+        {
+            if authority == owner {
+                *spend.entry(*source).or_default() += amount;
+            }
+        }
+    }
+    spend
+}
+
+fn insufficient(
+    shortfall: String,
+    symbol: &str,
+    needed: String,
+    balance: String,
+) -> anyhow::Error {
+    Error::InsufficientBalance(format!(
+        "You need {} more {} to send this transaction, it needs {} and the \
+         wallet holds {}",
+        shortfall, symbol, needed, balance
+    ))
+    .detailed(|error| {
+        error
+            .with_details(json!({
+                "shortfall": shortfall,
+                "needed": needed,
+                "balance": balance,
+                "symbol": symbol,
+            }))
+            .with_hint(
+                "Ask the user to top up the wallet or send a smaller amount",
+            )
+    })
+}
+
+/// `check_solana` of a versioned transaction holding a legacy message, v0
+/// messages loading accounts from lookup tables are left to the chain
+pub async fn check_versioned_solana(
+    owner: &Pubkey,
+    tx: &VersionedTransaction,
+) -> Result<()> {
+    match &tx.message {
+        VersionedMessage::Legacy(message) => {
+            check_solana(owner, &Transaction::new_unsigned(message.clone()))
+                .await
+        }
+        VersionedMessage::V0(_) => Ok(()),
+    }
+}
+
+/// Fails when `owner` can't pay for `tx` or doesn't hold the tokens it
+/// transfers
+pub async fn check_solana(owner: &Pubkey, tx: &Transaction) -> Result<()> {
+    let rpc = solana_rpc();
+    let account_rent =
+        rent::minimum_balance(AccountKind::TokenAccount.size()).await?;
+    let message = VersionedMessage::Legacy(tx.message.clone());
+    let need = solana_need(owner, &message, account_rent).total();
+    let balance = rpc.get_balance(owner).await.map_err(Error::from)?;
+    if balance < need {
+        let sol = |lamports: u64| to_ui_amount(&lamports.to_string(), 9);
+        return Err(insufficient(
+            sol(need - balance)?.to_string(),
+            "SOL",
+            sol(need)?.to_string(),
+            sol(balance)?.to_string(),
+        ));
+    }
+
+    for (source, amount) in solana_token_spend(owner, tx) {
+        let held = rpc
+            .get_token_account_balance(&source)
+            .await
+            .map_err(Error::from)?;
+        let balance = held.amount.parse::<u64>()?;
+        if balance < amount {
+            let ui = |raw: u64| to_ui_amount(&raw.to_string(), held.decimals);
+            let account =
+                rpc.get_account_data(&source).await.map_err(Error::from)?;
+            let mint = spl_token::state::Account::unpack(&account)?.mint;
+            return Err(insufficient(
+                ui(amount - balance)?.to_string(),
+                &format!("of token {}", mint),
+                ui(amount)?.to_string(),
+                ui(balance)?.to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Wei `owner` needs to send `tx`, its gas at the price it pays and its
+/// value
+pub(crate) async fn evm_need(
+    owner: Address,
+    tx: &TransactionRequest,
+    provider: &EvmProvider,
+) -> Result<Need<U256>> {
+    let gas = match tx.gas {
+        Some(gas) => gas,
+        None => provider.estimate_gas(&tx.clone().from(owner)).await?,
+    };
+    let gas_price = match tx.gas_price.or(tx.max_fee_per_gas) {
+        Some(price) => price,
+        None => provider.get_gas_price().await?,
+    };
+    Ok(Need {
+        fees: U256::from(gas) * U256::from(gas_price),
+        value: tx.value.unwrap_or_default(),
+    })
+}
+
+/// Token and amount of an ERC-20 transfer call
+fn erc20_transfer(tx: &TransactionRequest) -> Option<(Address, U256)> {
+    let token = tx.to.as_ref()?.to()?;
+    let input = tx.input.input()?;
+    if input.len() < 68 || input[..4] != ERC20_TRANSFER {
+        return None;
+    }
+    Some((*token, U256::from_be_slice(&input[36..68])))
+}
+
+/// Fails when `owner` can't pay the gas and value of `tx` or doesn't hold
+/// the tokens it transfers
+pub async fn check_evm(
+    owner: Address,
+    tx: &TransactionRequest,
+) -> Result<()> {
+    let provider = match tx.chain_id {
+        Some(chain_id) => make_provider_for_chain(chain_id)?,
+        None => make_provider()?,
+    };
+    if let Some((token, amount)) = erc20_transfer(tx) {
+        let balance = U256::from_str(
+            &token_balance(owner.to_string(), token.to_string(), &provider)
+                .await?,
+        )?;
+        if balance < amount {
+            let decimals =
+                token_decimals(token.to_string(), &provider).await?;
+            let ui = |raw: U256| to_ui_amount(&raw.to_string(), decimals);
+            return Err(insufficient(
+                ui(amount - balance)?.to_string(),
+                &format!("of token {}", token),
+                ui(amount)?.to_string(),
+                ui(balance)?.to_string(),
+            ));
+        }
+    }
+    // estimating the gas of a call that reverts fails, the chain explains
+    // the revert better
+    let need = match evm_need(owner, tx, &provider).await {
+        Ok(need) => need.total(),
+        Err(e) => {
+            tracing::debug!(?e, "gas not estimated, balance not checked");
+            return Ok(());
+        }
+    };
+    let balance = provider.get_balance(owner).await?;
+    if balance < need {
+        let native = |wei: U256| to_ui_amount(&wei.to_string(), 18);
+        return Err(insufficient(
+            native(need - balance)?.to_string(),
+            "of the native token",
+            native(need)?.to_string(),
+            native(balance)?.to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::compute_budget::ComputeBudgetInstruction;
+    use solana_sdk::system_instruction;
+    use spl_associated_token_account::instruction::create_associated_token_account;
+
+    #[test]
+    fn test_solana_need() {
+        let owner = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let tx = Transaction::new_with_payer(
+            &[
+                ComputeBudgetInstruction::set_compute_unit_limit(100_000),
+                ComputeBudgetInstruction::set_compute_unit_price(50_000),
+                create_associated_token_account(
+                    &owner,
+                    &owner,
+                    &mint,
+                    &spl_token::id(),
+                ),
+                system_instruction::transfer(
+                    &owner,
+                    &Pubkey::new_unique(),
+                    1_000_000,
+                ),
+            ],
+            Some(&owner),
+        );
+        // fee, priority fee and rent, then the transfer
+        assert_eq!(
+            solana_need(
+                &owner,
+                &VersionedMessage::Legacy(tx.message),
+                2_039_280
+            ),
+            Need {
+                fees: 5_000 + 5_000 + 2_039_280,
+                value: 1_000_000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_token_spend() {
+        let owner = Pubkey::new_unique();
+        let source = Pubkey::new_unique();
+        let transfer = |amount| {
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &source,
+                &Pubkey::new_unique(),
+                &owner,
+                &[],
+                amount,
+            )
+            .unwrap()
+        };
+        let tx = Transaction::new_with_payer(
+            &[transfer(10), transfer(5)],
+            Some(&owner),
+        );
+        assert_eq!(
+            solana_token_spend(&owner, &tx),
+            BTreeMap::from([(source, 15)])
+        );
+        assert!(solana_token_spend(&Pubkey::new_unique(), &tx).is_empty());
+
+        let mut input = ERC20_TRANSFER.to_vec();
+        input.extend([0u8; 32]);
+        input.extend(U256::from(42).to_be_bytes::<32>());
+        let token = Address::repeat_byte(1);
+        let tx = TransactionRequest::default().to(token).input(input.into());
+        assert_eq!(erc20_transfer(&tx), Some((token, U256::from(42))));
+    }
+}
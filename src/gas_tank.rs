@@ -0,0 +1,269 @@
+//! Gas tank, an operator treasury topping up the native balance of users
+//! whose wallet can't pay the fees of the transaction they are about to
+//! send: network and priority fees, the rent of the token accounts a Solana
+//! transaction creates and the gas of an EVM one. The native value the
+//! transaction moves is never funded, a wallet that doesn't hold it gets no
+//! top up. Top ups cover the shortfall only and are capped per user, chain
+//! and day (UTC) by gas_tank.max_lamports_per_day and max_wei_per_day. Off
+//! unless gas_tank.enabled (GAS_TANK) is set with a treasury key of the
+//! chain
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use alloy::network::{EthereumWallet, TransactionBuilder};
+use alloy::primitives::{Address, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionRequest;
+use alloy::signers::local::PrivateKeySigner;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Keypair;
+use solana_sdk::signer::Signer;
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::{Transaction, VersionedTransaction};
+
+use crate::audit::audit_user;
+use crate::common::{evm_chain, solana_rpc};
+use crate::config::config;
+use crate::error::Error;
+use crate::evm::transaction::send_transaction;
+use crate::evm::util::{rpc_url_for_chain, EvmProvider};
+use crate::funds::{evm_need, solana_need};
+use crate::kv_store::{update, KVStore, KV_STORE};
+use crate::signer::TransactionSigner;
+use crate::solana::blockhash::BLOCKHASH_CACHE;
+use crate::solana::rent::{self, AccountKind};
+use crate::sonic::gateway::wait_for_receipt;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopUp {
+    pub chain: String,
+    pub address: String,
+    /// base units of the native token
+    pub amount: u128,
+    pub hash: String,
+}
+
+/// What the tank may still send, None when the shortfall exceeds the
+/// daily cap
+fn allowance(shortfall: u128, used_today: u128, max: u128) -> Option<u128> {
+    (used_today.saturating_add(shortfall) <= max).then_some(shortfall)
+}
+
+/// The counters are per day, kept a bit longer than the day they count
+const USED_TTL: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+pub struct GasTank {
+    store: Arc<dyn KVStore>,
+}
+
+pub static GAS_TANK: Lazy<GasTank> =
+    Lazy::new(|| GasTank::new(KV_STORE.clone()));
+
+impl GasTank {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self { store }
+    }
+
+    fn used_key(user: &str, chain: &str) -> String {
+        format!(
+            "gas_tank:{}:{}:{}",
+            user,
+            chain,
+            Utc::now().format("%Y-%m-%d")
+        )
+    }
+
+    /// Base units sent to the user on `chain` today (UTC)
+    pub async fn used_today(&self, user: &str, chain: &str) -> Result<u128> {
+        Ok(self
+            .store
+            .get(&Self::used_key(user, chain))
+            .await?
+            .and_then(|used| used.parse().ok())
+            .unwrap_or(0))
+    }
+
+    /// Reserves `shortfall` of the daily cap of the user, fails when the
+    /// cap doesn't allow it. Concurrent reservations can't both pass the
+    /// cap, the counter is compared and set
+    async fn reserve(
+        &self,
+        user: &str,
+        chain: &str,
+        shortfall: u128,
+        max: u128,
+    ) -> Result<()> {
+        update(
+            self.store.as_ref(),
+            &Self::used_key(user, chain),
+            Some(USED_TTL),
+            |used| {
+                let used = used.and_then(|used| used.parse().ok());
+                let used = used.unwrap_or(0);
+                let amount =
+                    allowance(shortfall, used, max).ok_or_else(|| {
+                        Error::InsufficientBalance(format!(
+                            "The wallet can't pay the fees on {} and the gas \
+                             tank reached its daily limit",
+                            chain
+                        ))
+                        .detailed(|error| {
+                            error.with_hint(
+                                "Ask the user to top up the native balance",
+                            )
+                        })
+                    })?;
+                Ok((used + amount).to_string())
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Tops up `owner` when its balance holds the lamports `tx` moves but
+    /// can't pay its fees and rent on top
+    pub async fn ensure_solana(
+        &self,
+        signer: &dyn TransactionSigner,
+        owner: &Pubkey,
+        tx: &VersionedTransaction,
+    ) -> Result<Option<TopUp>> {
+        let settings = config().gas_tank.clone();
+        let Some(key) = settings.solana_key.filter(|_| settings.enabled)
+        else {
+            return Ok(None);
+        };
+        if signer.is_paper() {
+            return Ok(None);
+        }
+        let rpc = solana_rpc();
+        let account_rent =
+            rent::minimum_balance(AccountKind::TokenAccount.size()).await?;
+        let need = solana_need(owner, &tx.message, account_rent);
+        let balance = rpc.get_balance(owner).await.map_err(Error::from)?;
+        // the lamports the user moves are theirs to hold
+        if balance >= need.total() || balance < need.value {
+            return Ok(None);
+        }
+        let shortfall = need.total() - balance;
+        self.reserve(
+            &audit_user(signer),
+            "sol",
+            shortfall as u128,
+            settings.max_lamports_per_day as u128,
+        )
+        .await?;
+
+        let treasury = bs58::decode(&key)
+            .into_vec()
+            .ok()
+            .and_then(|bytes| Keypair::from_bytes(&bytes).ok())
+            .ok_or_else(|| anyhow!("gas_tank.solana_key is not a keypair"))?;
+        let mut top_up = Transaction::new_with_payer(
+            &[system_instruction::transfer(
+                &treasury.pubkey(),
+                owner,
+                shortfall,
+            )],
+            Some(&treasury.pubkey()),
+        );
+        top_up.sign(&[&treasury], BLOCKHASH_CACHE.get_blockhash().await?);
+        let signature = rpc
+            .send_and_confirm_transaction(&top_up)
+            .await
+            .map_err(Error::from)?;
+        tracing::info!(%owner, shortfall, %signature, "gas tank top up");
+        Ok(Some(TopUp {
+            chain: "sol".to_string(),
+            address: owner.to_string(),
+            amount: shortfall as u128,
+            hash: signature.to_string(),
+        }))
+    }
+
+    /// Tops up `owner` when its balance holds the value of `tx` but can't
+    /// pay its gas on top
+    pub async fn ensure_evm(
+        &self,
+        signer: &dyn TransactionSigner,
+        owner: Address,
+        tx: &TransactionRequest,
+        provider: &EvmProvider,
+    ) -> Result<Option<TopUp>> {
+        let settings = config().gas_tank.clone();
+        let Some(key) = settings.evm_key.filter(|_| settings.enabled) else {
+            return Ok(None);
+        };
+        if signer.is_paper() {
+            return Ok(None);
+        }
+        let need = evm_need(owner, tx, provider).await?;
+        let balance = provider.get_balance(owner).await?;
+        // the value the user moves is theirs to hold
+        if balance >= need.total() || balance < need.value {
+            return Ok(None);
+        }
+        let shortfall = need.total() - balance;
+        let shortfall = u128::try_from(shortfall)
+            .map_err(|_| anyhow!("Shortfall of {} wei", shortfall))?;
+        let chain = tx
+            .chain_id
+            .map_or_else(evm_chain, |chain_id| chain_id.to_string());
+        self.reserve(
+            &audit_user(signer),
+            &chain,
+            shortfall,
+            settings.max_wei_per_day as u128,
+        )
+        .await?;
+
+        let treasury = PrivateKeySigner::from_str(&key)
+            .map_err(|_| anyhow!("gas_tank.evm_key is not a private key"))?;
+        let top_up = TransactionRequest::default()
+            .from(treasury.address())
+            .to(owner)
+            .value(U256::from(shortfall))
+            .with_gas_price(provider.get_gas_price().await?);
+        let hash = send_transaction(
+            top_up,
+            &rpc_url_for_chain(tx.chain_id)?,
+            &EthereumWallet::from(treasury),
+        )
+        .await?;
+        wait_for_receipt(&hash, provider).await?;
+        tracing::info!(%owner, shortfall, %hash, "gas tank top up");
+        Ok(Some(TopUp {
+            chain,
+            address: owner.to_string(),
+            amount: shortfall,
+            hash,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    #[test]
+    fn test_allowance() {
+        assert_eq!(allowance(5, 10, 20), Some(5));
+        assert_eq!(allowance(15, 10, 20), None);
+    }
+
+    #[tokio::test]
+    async fn test_reserve() {
+        let tank = GasTank::new(Arc::new(InMemoryKVStore::new()));
+        let reserves = (0..10).map(|_| tank.reserve("alice", "sol", 3, 20));
+        let reserved = futures::future::join_all(reserves).await;
+        // 6 reservations of 3 fit in 20, never more
+        assert_eq!(reserved.iter().filter(|r| r.is_ok()).count(), 6);
+        assert_eq!(tank.used_today("alice", "sol").await.unwrap(), 18);
+    }
+}
@@ -0,0 +1,240 @@
+//! Connectivity of the services the agent depends on: the RPCs of the
+//! enabled chains, Privy, Jupiter, LiFi, the database and the kv_store.
+//! The server exposes it as `/readyz`, `/healthz` only tells the instance is
+//! up. Jupiter and LiFi are not required, an outage of one of them degrades
+//! the tools using it without taking every instance out of rotation. Of the
+//! EVM RPCs only the one of rpc.evm_chain and `rpc.ethereum` are required,
+//! Ethereum mainnet only serves the bridges otherwise
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use alloy::providers::Provider;
+use anyhow::{anyhow, Result};
+use futures::future::{join_all, BoxFuture};
+use futures::FutureExt;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::chains::{Chain, CHAINS};
+use crate::common::{http_client, solana_rpc};
+use crate::config::config;
+use crate::cross_chain::lifi::LiFi;
+use crate::db::{db_pool, Backend};
+use crate::evm::util::provider_for;
+use crate::kv_store::KV_STORE;
+use crate::solana::jup::JUPITER_API;
+
+/// Time a check gets before it is reported down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+const PROBE_KEY: &str = "health:probe";
+const PROBE_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    /// an optional service is down
+    Degraded,
+    /// a required service is down
+    Down,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    /// readiness fails when a required check does
+    pub required: bool,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HealthReport {
+    pub status: Status,
+    pub checks: Vec<Check>,
+    pub timestamp: String,
+}
+
+impl HealthReport {
+    pub fn new(checks: Vec<Check>) -> Self {
+        let status = if checks.iter().any(|check| check.required && !check.ok)
+        {
+            Status::Down
+        } else if checks.iter().any(|check| !check.ok) {
+            Status::Degraded
+        } else {
+            Status::Ok
+        };
+        Self {
+            status,
+            checks,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+
+    /// Whether the instance can take traffic
+    pub fn is_ready(&self) -> bool {
+        self.status != Status::Down
+    }
+
+    /// The report without the errors and latencies, ok or fail by service
+    pub fn public(&self) -> Value {
+        let checks = self
+            .checks
+            .iter()
+            .map(|check| {
+                let status = if check.ok { "ok" } else { "fail" };
+                (check.name.clone(), Value::from(status))
+            })
+            .collect::<Map<_, _>>();
+        json!({
+            "status": self.status,
+            "checks": checks,
+            "timestamp": self.timestamp,
+        })
+    }
+}
+
+async fn check(
+    name: &str,
+    required: bool,
+    probe: impl Future<Output = Result<()>>,
+) -> Check {
+    let start = Instant::now();
+    let result = match tokio::time::timeout(CHECK_TIMEOUT, probe).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow!("Timed out after {:?}", CHECK_TIMEOUT)),
+    };
+    Check {
+        name: name.to_string(),
+        ok: result.is_ok(),
+        required,
+        latency_ms: start.elapsed().as_millis() as u64,
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+async fn solana() -> Result<()> {
+    solana_rpc().get_slot().await?;
+    Ok(())
+}
+
+async fn evm(rpc_url: String) -> Result<()> {
+    provider_for(&rpc_url)?.get_block_number().await?;
+    Ok(())
+}
+
+/// Reachable when the API answers, whatever the route returns short of a
+/// server error
+async fn api(url: &str) -> Result<()> {
+    let response = http_client().get(url).send().await?;
+    if response.status().is_server_error() {
+        return Err(anyhow!("{} answered {}", url, response.status()));
+    }
+    Ok(())
+}
+
+async fn lifi() -> Result<()> {
+    LiFi::new(None).get_chains().await?;
+    Ok(())
+}
+
+#[cfg(feature = "http")]
+async fn privy() -> Result<()> {
+    let privy = crate::wallet_manager::config::PrivyConfig::from_env()?;
+    api(&format!("{}/api/v1/apps/{}", privy.auth_url, privy.app_id)).await
+}
+
+async fn database() -> Result<()> {
+    sqlx::query("SELECT 1").execute(&db_pool()?).await?;
+    Ok(())
+}
+
+async fn kv_store() -> Result<()> {
+    KV_STORE.set_with_ttl(PROBE_KEY, "ok", PROBE_TTL).await?;
+    match KV_STORE.get(PROBE_KEY).await? {
+        Some(_) => Ok(()),
+        None => Err(anyhow!("The probe key was not read back")),
+    }
+}
+
+/// The database backs the wallets of the server and the Postgres kv_store,
+/// only Postgres is checked
+fn uses_database() -> bool {
+    Backend::of(&config().database.url) == Some(Backend::Postgres)
+        && (cfg!(feature = "http")
+            || std::env::var("KV_STORE").as_deref() == Ok("postgres"))
+}
+
+/// Runs every check concurrently, each within `CHECK_TIMEOUT`
+pub async fn check_all() -> HealthReport {
+    let config = config();
+    let mut checks: Vec<BoxFuture<Check>> =
+        vec![check("kv_store", true, kv_store()).boxed()];
+    if CHAINS.is_enabled(Chain::Solana) {
+        checks.push(check("solana_rpc", true, solana()).boxed());
+        checks.push(check("jupiter", false, api(JUPITER_API)).boxed());
+    }
+    if CHAINS.is_enabled(Chain::Evm) {
+        let rpc = &config.rpc;
+        let on = |chain: &[&str]| chain.contains(&rpc.evm_chain.as_str());
+        checks.push(
+            check("sonic_rpc", on(&["sonic"]), evm(rpc.sonic.clone()))
+                .boxed(),
+        );
+        checks.push(
+            check(
+                "ethereum_mainnet_rpc",
+                on(&["eth", "ethereum"]),
+                evm(rpc.ethereum_mainnet.clone()),
+            )
+            .boxed(),
+        );
+        if let Some(ethereum) = rpc.ethereum.clone() {
+            checks.push(check("ethereum_rpc", true, evm(ethereum)).boxed());
+        }
+        checks.push(check("lifi", false, lifi()).boxed());
+    }
+    #[cfg(feature = "http")]
+    checks.push(check("privy", true, privy()).boxed());
+    if uses_database() {
+        checks.push(check("database", true, database()).boxed());
+    }
+    HealthReport::new(join_all(checks).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_report_status() {
+        let up = check("kv_store", true, async { Ok(()) }).await;
+        let down =
+            check("lifi", false, async { Err(anyhow!("unreachable")) }).await;
+        assert!(up.ok && up.error.is_none());
+        assert_eq!(down.error.as_deref(), Some("unreachable"));
+
+        let report = HealthReport::new(vec![up.clone()]);
+        assert_eq!(report.status, Status::Ok);
+        let report = HealthReport::new(vec![up.clone(), down.clone()]);
+        assert_eq!(report.status, Status::Degraded);
+        assert!(report.is_ready());
+
+        let required = Check {
+            required: true,
+            ..down
+        };
+        let report = HealthReport::new(vec![up, required]);
+        assert_eq!(report.status, Status::Down);
+        assert!(!report.is_ready());
+
+        // the errors stay behind the admin token
+        let public = report.public();
+        assert_eq!(public["status"], "down");
+        assert_eq!(public["checks"]["lifi"], "fail");
+        assert!(!public.to_string().contains("unreachable"));
+    }
+}
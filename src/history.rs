@@ -0,0 +1,667 @@
+//! Export of the transaction history of the user for tax tools, one row per
+//! token moved: date, action, token, amount, USD value at execution, fee
+//! and transaction hash. Solana history is read from the chain (every
+//! transaction of the wallet, deposits included), EVM history from the
+//! transactions the tools sent according to the audit log, decoded from
+//! their receipts. The audit log keeps the last 500 tool calls, EVM
+//! transactions sent before the oldest one are missing and the export says
+//! since when (`truncated_before`). Actions come from the audit log when a tool sent the
+//! transaction. USD values are the daily close of the token on the day of
+//! the transaction, left empty for tokens without price history
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use alloy::consensus::Transaction as _;
+use alloy::primitives::{Address as EvmAddress, B256};
+use alloy::providers::Provider;
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Duration, TimeZone, Utc};
+use futures::stream::{self, StreamExt};
+use rig_tool_macro::tool;
+use serde::Serialize;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+
+use crate::address::Address;
+use crate::audit::{audit_user, AuditQuery, AUDIT};
+use crate::chains::{Chain, CHAINS};
+use crate::common::{evm_chain, solana_rpc};
+use crate::error::Error;
+use crate::evm::events::{parse_token_event, TokenEventKind};
+use crate::evm::tools::NATIVE_TOKEN;
+use crate::evm::util::make_provider;
+use crate::pricing::{candles, token_price, Candle};
+use crate::signer::{SignerContext, TransactionSigner};
+use crate::solana::balance::transaction_balances;
+use crate::solana::constants::WSOL;
+use crate::tool_error::ToolError;
+
+/// Transactions read per chain, older ones are left out of the export
+const MAX_TRANSACTIONS: usize = 1000;
+const CONCURRENT_FETCHES: usize = 8;
+/// Longest price history GeckoTerminal returns
+const MAX_DAYS: i64 = 1000;
+
+/// Time range of an export: 7d, 30d (any number of days), ytd, a year
+/// like 2024 or all
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Period {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl Period {
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        self.since.map_or(true, |since| at >= since)
+            && self.until.map_or(true, |until| at < until)
+    }
+
+    /// Start of what a log complete since `complete_since` covers of the
+    /// period, None when it covers all of it
+    pub fn truncated_by(
+        &self,
+        complete_since: Option<DateTime<Utc>>,
+    ) -> Option<DateTime<Utc>> {
+        let complete_since = complete_since?;
+        (self.since.map_or(true, |since| since < complete_since)
+            && self.until.map_or(true, |until| until > complete_since))
+        .then_some(complete_since)
+    }
+}
+
+impl FromStr for Period {
+    type Err = ToolError;
+
+    fn from_str(s: &str) -> Result<Self, ToolError> {
+        let s = s.trim().to_lowercase();
+        let invalid = || {
+            ToolError::invalid_input(format!("Invalid period {}", s))
+                .with_hint("Use 7d, 30d, ytd, a year like 2024 or all")
+        };
+        let now = Utc::now();
+        let year_start =
+            |year: i32| Utc.with_ymd_and_hms(year, 1, 1, 0, 0, 0).single();
+        Ok(match s.as_str() {
+            "all" => Self {
+                since: None,
+                until: None,
+            },
+            "ytd" => Self {
+                since: year_start(now.year()),
+                until: None,
+            },
+            _ => {
+                if let Some(days) = s.strip_suffix('d') {
+                    let days = days.parse::<u32>().map_err(|_| invalid())?;
+                    Self {
+                        since: Some(now - Duration::days(days.into())),
+                        until: None,
+                    }
+                } else {
+                    let year = s.parse::<i32>().map_err(|_| invalid())?;
+                    Self {
+                        since: Some(year_start(year).ok_or_else(invalid)?),
+                        until: year_start(year + 1),
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = ToolError;
+
+    fn from_str(s: &str) -> Result<Self, ToolError> {
+        match s.trim().to_lowercase().as_str() {
+            "csv" | "" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            other => Err(ToolError::invalid_input(format!(
+                "Invalid format {}, use csv or json",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Csv => write!(f, "csv"),
+            Self::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// A token moved by a transaction, amounts are decimal and negative when
+/// the token left the wallet
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    pub date: DateTime<Utc>,
+    pub chain: String,
+    /// tool that sent the transaction, or send, receive or swap
+    pub action: String,
+    pub token: String,
+    pub symbol: Option<String>,
+    pub amount: f64,
+    pub usd_value: Option<f64>,
+    /// on the first entry of the transaction only, in the native token
+    pub fee: Option<f64>,
+    pub fee_token: Option<String>,
+    pub tx_hash: String,
+}
+
+const CSV_HEADER: &str =
+    "date,chain,action,token,symbol,amount,usd_value,fee,fee_token,tx_hash";
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn to_csv(entries: &[HistoryEntry]) -> String {
+    let optional = |value: Option<f64>| value.map(|v| v.to_string());
+    let mut csv = format!("{}\n", CSV_HEADER);
+    for entry in entries {
+        let row = [
+            entry.date.to_rfc3339(),
+            entry.chain.clone(),
+            entry.action.clone(),
+            entry.token.clone(),
+            entry.symbol.clone().unwrap_or_default(),
+            entry.amount.to_string(),
+            optional(entry.usd_value).unwrap_or_default(),
+            optional(entry.fee).unwrap_or_default(),
+            entry.fee_token.clone().unwrap_or_default(),
+            entry.tx_hash.clone(),
+        ];
+        let row = row.iter().map(|v| csv_field(v)).collect::<Vec<_>>();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Export ready to be downloaded, hosts serve `content` as a file
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryExport {
+    pub filename: String,
+    pub content_type: String,
+    pub rows: usize,
+    pub content: String,
+    /// EVM transactions sent before are missing, the audit log they are
+    /// read from only keeps the last tool calls
+    pub truncated_before: Option<DateTime<Utc>>,
+}
+
+impl HistoryExport {
+    pub fn new(
+        entries: &[HistoryEntry],
+        format: ExportFormat,
+    ) -> Result<Self> {
+        let (content_type, content) = match format {
+            ExportFormat::Csv => ("text/csv", to_csv(entries)),
+            ExportFormat::Json => {
+                ("application/json", serde_json::to_string_pretty(entries)?)
+            }
+        };
+        Ok(Self {
+            filename: format!(
+                "history-{}.{}",
+                Utc::now().format("%Y%m%d"),
+                format
+            ),
+            content_type: content_type.to_string(),
+            rows: entries.len(),
+            content,
+            truncated_before: None,
+        })
+    }
+}
+
+/// Transaction hashes and signatures mentioned in a tool result
+pub(crate) fn tx_hashes(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| {
+            (word.len() == 66
+                && word.starts_with("0x")
+                && word[2..].chars().all(|c| c.is_ascii_hexdigit()))
+                || (word.len() >= 86 && Signature::from_str(word).is_ok())
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// A token moved by a transaction before it is priced, in base units
+struct Movement {
+    date: DateTime<Utc>,
+    chain: String,
+    action: String,
+    token: String,
+    amount: i128,
+    decimals: Option<u8>,
+    fee: Option<f64>,
+    fee_token: Option<String>,
+    tx_hash: String,
+}
+
+/// swap when tokens went both ways
+fn classify(amounts: &[i128]) -> &'static str {
+    let sent = amounts.iter().any(|amount| *amount < 0);
+    let received = amounts.iter().any(|amount| *amount > 0);
+    match (sent, received) {
+        (true, true) => "swap",
+        (true, false) => "send",
+        _ => "receive",
+    }
+}
+
+async fn solana_movements(
+    wallet: &str,
+    period: &Period,
+    actions: &HashMap<String, String>,
+) -> Result<Vec<Movement>> {
+    let address = Pubkey::from_str(wallet)?;
+    let rpc = solana_rpc();
+    let mut signatures = vec![];
+    let mut before = None;
+    'pages: while signatures.len() < MAX_TRANSACTIONS {
+        let page = rpc
+            .get_signatures_for_address_with_config(
+                &address,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    until: None,
+                    limit: None,
+                    commitment: Some(CommitmentConfig::confirmed()),
+                },
+            )
+            .await
+            .map_err(Error::from)?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        before = Some(Signature::from_str(&last.signature)?);
+        for status in page {
+            let Some(at) = status
+                .block_time
+                .and_then(|time| DateTime::from_timestamp(time, 0))
+            else {
+                continue;
+            };
+            // newest first
+            if period.since.is_some_and(|since| at < since) {
+                break 'pages;
+            }
+            if period.contains(at) {
+                signatures.push((status.signature, at));
+            }
+        }
+    }
+    signatures.truncate(MAX_TRANSACTIONS);
+
+    let transactions = stream::iter(signatures)
+        .map(|(signature, at)| async move {
+            let balances =
+                transaction_balances(&Signature::from_str(&signature)?)
+                    .await?;
+            Ok::<_, anyhow::Error>((signature, at, balances))
+        })
+        .buffered(CONCURRENT_FETCHES)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut movements = vec![];
+    for transaction in transactions {
+        let (signature, at, balances) = match transaction {
+            Ok(transaction) => transaction,
+            Err(e) => {
+                tracing::warn!(?e, "transaction left out of the history");
+                continue;
+            }
+        };
+        let changes = balances.changes(wallet);
+        let fee =
+            balances.paid_fee(wallet).then(|| balances.fee as f64 / 1e9);
+        let amounts = changes.iter().map(|c| c.amount).collect::<Vec<_>>();
+        let action = actions
+            .get(&signature)
+            .cloned()
+            .unwrap_or_else(|| classify(&amounts).to_string());
+        if changes.is_empty() {
+            // failed or only paid for, the fee is still a cost
+            if let Some(fee) = fee {
+                movements.push(Movement {
+                    date: at,
+                    chain: "sol".to_string(),
+                    action,
+                    token: WSOL.to_string(),
+                    amount: 0,
+                    decimals: Some(9),
+                    fee: Some(fee),
+                    fee_token: Some("SOL".to_string()),
+                    tx_hash: signature,
+                });
+            }
+            continue;
+        }
+        for (i, change) in changes.into_iter().enumerate() {
+            movements.push(Movement {
+                date: at,
+                chain: "sol".to_string(),
+                action: action.clone(),
+                token: change.mint,
+                amount: change.amount,
+                decimals: Some(change.decimals),
+                fee: fee.filter(|_| i == 0),
+                fee_token: fee.filter(|_| i == 0).map(|_| "SOL".into()),
+                tx_hash: signature.clone(),
+            });
+        }
+    }
+    Ok(movements)
+}
+
+async fn evm_movements(
+    wallet: &str,
+    sent: &[(String, String, DateTime<Utc>)],
+) -> Result<Vec<Movement>> {
+    let wallet = EvmAddress::from_str(wallet)?;
+    let provider = make_provider()?;
+    let chain = evm_chain();
+    let native = if chain == "sonic" { "S" } else { "ETH" };
+    let mut movements = vec![];
+    for (hash, action, at) in sent {
+        let tx_hash = B256::from_str(hash)?;
+        let (tx, receipt) = match tokio::try_join!(
+            provider.get_transaction_by_hash(tx_hash),
+            provider.get_transaction_receipt(tx_hash),
+        ) {
+            Ok((Some(tx), Some(receipt))) => (tx, receipt),
+            // sent on another chain (bridges) or dropped
+            Ok(_) => continue,
+            Err(e) => {
+                tracing::warn!(?e, %hash, "left out of the history");
+                continue;
+            }
+        };
+        let mut amounts = vec![];
+        if receipt.from == wallet && !tx.value().is_zero() {
+            amounts.push((
+                NATIVE_TOKEN.to_string(),
+                -(i128::try_from(tx.value()).unwrap_or(i128::MAX)),
+                Some(18),
+            ));
+        }
+        for log in receipt.inner.logs() {
+            let Some(event) = parse_token_event(log, wallet) else {
+                continue;
+            };
+            if event.kind != TokenEventKind::Transfer {
+                continue;
+            }
+            let amount = event.amount.parse::<i128>().unwrap_or(i128::MAX);
+            let amount = if event.incoming { amount } else { -amount };
+            amounts.push((event.token, amount, None));
+        }
+        let fee = (receipt.from == wallet).then(|| {
+            receipt.gas_used as f64 * receipt.effective_gas_price as f64
+                / 1e18
+        });
+        for (i, (token, amount, decimals)) in amounts.into_iter().enumerate()
+        {
+            movements.push(Movement {
+                date: *at,
+                chain: chain.clone(),
+                action: action.clone(),
+                token,
+                amount,
+                decimals,
+                fee: fee.filter(|_| i == 0),
+                fee_token: fee.filter(|_| i == 0).map(|_| native.into()),
+                tx_hash: hash.clone(),
+            });
+        }
+    }
+    Ok(movements)
+}
+
+/// Symbol, decimals and daily closes of a token, whatever could be fetched
+#[derive(Default)]
+pub(crate) struct TokenInfo {
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+    closes: Vec<Candle>,
+}
+
+impl TokenInfo {
+    async fn fetch(chain: &str, token: &str, days: usize) -> Self {
+        let price = token_price(chain, token).await.ok();
+        let closes =
+            candles(chain, token, "1d", days).await.unwrap_or_default();
+        Self {
+            symbol: price.as_ref().map(|price| price.symbol.clone()),
+            decimals: price.map(|price| price.decimals),
+            closes,
+        }
+    }
+
+    /// Close of the day of `at`
+    pub fn price_at(&self, at: DateTime<Utc>) -> Option<f64> {
+        self.closes
+            .iter()
+            .rev()
+            .find(|candle| candle.time <= at)
+            .map(|candle| candle.close)
+    }
+}
+
+/// Info of the (chain, token) pairs, with closes back to `oldest`
+pub(crate) async fn token_infos(
+    keys: impl IntoIterator<Item = (String, String)>,
+    oldest: Option<DateTime<Utc>>,
+) -> HashMap<(String, String), TokenInfo> {
+    let days = oldest.map_or(1, |oldest| {
+        ((Utc::now() - oldest).num_days() + 2).clamp(1, MAX_DAYS)
+    }) as usize;
+    let mut tokens = HashMap::new();
+    for key in keys {
+        if !tokens.contains_key(&key) {
+            let info = TokenInfo::fetch(&key.0, &key.1, days).await;
+            tokens.insert(key, info);
+        }
+    }
+    tokens
+}
+
+async fn price(movements: Vec<Movement>) -> Vec<HistoryEntry> {
+    let tokens = token_infos(
+        movements.iter().map(|m| (m.chain.clone(), m.token.clone())),
+        movements.iter().map(|m| m.date).min(),
+    )
+    .await;
+
+    movements
+        .into_iter()
+        .map(|movement| {
+            let info =
+                &tokens[&(movement.chain.clone(), movement.token.clone())];
+            let decimals = movement.decimals.or(info.decimals).unwrap_or(0);
+            let amount = movement.amount as f64 / 10f64.powi(decimals as i32);
+            HistoryEntry {
+                date: movement.date,
+                usd_value: info
+                    .price_at(movement.date)
+                    .map(|price| (amount * price).abs()),
+                symbol: info.symbol.clone(),
+                amount,
+                chain: movement.chain,
+                action: movement.action,
+                token: movement.token,
+                fee: movement.fee,
+                fee_token: movement.fee_token,
+                tx_hash: movement.tx_hash,
+            }
+        })
+        .collect()
+}
+
+/// History of the current signer over `period`, oldest first
+pub async fn history(period: &Period) -> Result<Vec<HistoryEntry>> {
+    let signer = SignerContext::current().await;
+    let records = AUDIT
+        .query(
+            &audit_user(signer.as_ref()),
+            &AuditQuery {
+                since: period.since,
+                ..Default::default()
+            },
+        )
+        .await?;
+    // tool of every transaction sent for real
+    let mut sent = vec![];
+    for record in records.iter().filter(|r| !r.paper && period.contains(r.at))
+    {
+        for hash in tx_hashes(record.result.as_deref().unwrap_or_default()) {
+            sent.push((hash, record.tool.clone(), record.at));
+        }
+    }
+    let actions = sent
+        .iter()
+        .map(|(hash, tool, _)| (hash.clone(), tool.clone()))
+        .collect::<HashMap<_, _>>();
+
+    let mut movements = vec![];
+    if CHAINS.is_enabled(Chain::Solana) {
+        let owner = Address::of_signer(signer.as_ref(), Chain::Solana)?;
+        movements.extend(
+            solana_movements(&owner.to_string(), period, &actions).await?,
+        );
+    }
+    if CHAINS.is_enabled(Chain::Evm) {
+        sent.retain(|(hash, _, _)| hash.starts_with("0x"));
+        let owner = Address::of_signer(signer.as_ref(), Chain::Evm)?;
+        movements.extend(evm_movements(&owner.to_string(), &sent).await?);
+    }
+    let mut entries = price(movements).await;
+    entries.sort_by_key(|entry| entry.date);
+    Ok(entries)
+}
+
+/// Date before which the EVM transactions of `signer` over `period` are
+/// missing because the audit log dropped their tool calls
+pub(crate) async fn evm_truncated(
+    signer: &dyn TransactionSigner,
+    period: &Period,
+) -> Result<Option<DateTime<Utc>>> {
+    if !CHAINS.is_enabled(Chain::Evm) {
+        return Ok(None);
+    }
+    let complete_since = AUDIT.complete_since(&audit_user(signer)).await?;
+    Ok(period.truncated_by(complete_since))
+}
+
+/// History of the current signer in the format, ready to be downloaded
+pub async fn export(period: &str, format: &str) -> Result<HistoryExport> {
+    let period = Period::from_str(period)?;
+    let format = ExportFormat::from_str(format)?;
+    let mut export = HistoryExport::new(&history(&period).await?, format)?;
+    let signer = SignerContext::current().await;
+    export.truncated_before = evm_truncated(signer.as_ref(), &period).await?;
+    Ok(export)
+}
+
+#[tool(description = "
+Exports the transaction history of the user for tax tools, one row per token
+moved with the date, action, token, amount, USD value at execution, fee and
+transaction hash. The host offers the content as a file download.
+EVM transactions sent before truncated_before are missing, tell the user
+when it is set.
+
+period is 7d, 30d (any number of days), ytd, a year like 2024 or all
+format is csv or json
+")]
+pub async fn export_history(
+    period: String,
+    format: String,
+) -> Result<HistoryExport> {
+    export(&period, &format).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_period() {
+        let year = Period::from_str("2024").unwrap();
+        assert!(year.contains("2024-06-01T00:00:00Z".parse().unwrap()));
+        assert!(!year.contains("2025-01-01T00:00:00Z".parse().unwrap()));
+        let week = Period::from_str("7d").unwrap();
+        assert!(week.contains(Utc::now()));
+        assert!(!week.contains(Utc::now() - Duration::days(8)));
+        assert_eq!(Period::from_str("all").unwrap().since, None);
+        assert!(Period::from_str("last week").is_err());
+
+        let kept = Utc::now() - Duration::days(3);
+        assert_eq!(week.truncated_by(Some(kept)), Some(kept));
+        assert_eq!(week.truncated_by(None), None);
+        assert_eq!(year.truncated_by(Some(kept)), None);
+    }
+
+    #[test]
+    fn test_csv() {
+        let entry = HistoryEntry {
+            date: "2024-06-01T12:00:00Z".parse().unwrap(),
+            chain: "sol".to_string(),
+            action: "swap".to_string(),
+            token: WSOL.to_string(),
+            symbol: Some("SOL".to_string()),
+            amount: -1.5,
+            usd_value: Some(240.),
+            fee: Some(0.000005),
+            fee_token: Some("SOL".to_string()),
+            tx_hash: "5h6x".to_string(),
+        };
+        let memo = HistoryEntry {
+            symbol: Some("A,\"B\"".to_string()),
+            usd_value: None,
+            fee: None,
+            fee_token: None,
+            ..entry.clone()
+        };
+        let csv = to_csv(&[entry, memo]);
+        let rows = csv.lines().collect::<Vec<_>>();
+        assert_eq!(rows[0], CSV_HEADER);
+        assert_eq!(
+            rows[1],
+            format!(
+                "2024-06-01T12:00:00+00:00,sol,swap,{},SOL,-1.5,240,{}",
+                WSOL, "0.000005,SOL,5h6x"
+            )
+        );
+        assert!(rows[2].contains(",\"A,\"\"B\"\"\",-1.5,,,,5h6x"));
+    }
+
+    #[test]
+    fn test_tx_hashes() {
+        let evm = format!("0x{}", "ab".repeat(32));
+        let result = format!("{} (https://sonicscan.org/tx/{})", evm, evm);
+        assert_eq!(tx_hashes(&result), [evm.clone(), evm]);
+        assert!(tx_hashes("Bought 1.5 SOL of 0x1234").is_empty());
+        assert_eq!(classify(&[-5, 10]), "swap");
+        assert_eq!(classify(&[10]), "receive");
+    }
+}
@@ -0,0 +1,180 @@
+//! Outbound HTTP calls to the external APIs (Privy, Jupiter, LiFi) with
+//! retries and per-host rate limits. Transient failures, 429, 5xx and
+//! network errors, are retried with exponential backoff and jitter, every
+//! request waits for a slot of its host. A request that creates, signs or
+//! sends something is only retried when it surely did not execute, see
+//! `Retry`. Settings come from the `[http]` config
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode};
+
+use crate::config::{config, HttpSettings};
+use crate::error::Result;
+use crate::metrics::{HTTP_RETRIES, METRICS};
+
+/// Whether a request may run twice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retry {
+    /// reads, quotes and other requests without side effects, retried on
+    /// 429, 5xx and network errors
+    Idempotent,
+    /// requests creating, signing or sending something, retried on 429 and
+    /// failures to connect only as a 5xx or a timeout may come after they
+    /// executed
+    Unsafe,
+}
+
+fn retryable_status(status: StatusCode, retry: Retry) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || (retry == Retry::Idempotent && status.is_server_error())
+}
+
+fn retryable_error(error: &reqwest::Error, retry: Retry) -> bool {
+    match retry {
+        Retry::Idempotent => {
+            error.is_connect() || error.is_timeout() || error.is_request()
+        }
+        Retry::Unsafe => error.is_connect(),
+    }
+}
+
+/// Delay before the retry `attempt` (from 0): the base delay doubled on
+/// every attempt up to the max, jittered down to half of it
+fn backoff(attempt: u32, settings: &HttpSettings) -> Duration {
+    let delay = settings
+        .base_delay_ms
+        .saturating_mul(1 << attempt.min(20))
+        .min(settings.max_delay_ms);
+    Duration::from_millis(rand::thread_rng().gen_range(delay / 2..=delay))
+}
+
+/// Delay asked by a 429 or 503 in seconds, capped by the max delay
+fn retry_after(
+    response: &Response,
+    settings: &HttpSettings,
+) -> Option<Duration> {
+    let secs: u64 = response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(
+        Duration::from_secs(secs)
+            .min(Duration::from_millis(settings.max_delay_ms)),
+    )
+}
+
+/// Spaces the requests to every host by one over its requests per second
+#[derive(Default)]
+pub struct HostLimiter {
+    next_slot: Mutex<HashMap<String, Instant>>,
+}
+
+static LIMITER: Lazy<HostLimiter> = Lazy::new(HostLimiter::default);
+
+impl HostLimiter {
+    /// Takes the next slot of `host`, returns how long to wait for it
+    fn reserve(
+        &self,
+        host: &str,
+        requests_per_sec: u32,
+        now: Instant,
+    ) -> Duration {
+        if requests_per_sec == 0 {
+            return Duration::ZERO;
+        }
+        let interval = Duration::from_secs(1) / requests_per_sec;
+        let mut next_slot = self.next_slot.lock().unwrap();
+        let slot = next_slot.get(host).map_or(now, |slot| (*slot).max(now));
+        next_slot.insert(host.to_string(), slot + interval);
+        slot - now
+    }
+
+    pub async fn acquire(&self, host: &str, requests_per_sec: u32) {
+        let wait = self.reserve(host, requests_per_sec, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Sends `request`, retrying it as `retry` allows. The last response is
+/// returned whatever its status, callers check it as for a plain `send`.
+/// Requests with a streamed body can't be replayed and are sent once
+pub async fn send(request: RequestBuilder, retry: Retry) -> Result<Response> {
+    let settings = config().http.clone();
+    let (client, request) = request.build_split();
+    let request = request?;
+    let host = request.url().host_str().unwrap_or_default().to_string();
+    let mut attempt = 0;
+    loop {
+        LIMITER.acquire(&host, settings.requests_per_sec).await;
+        let current = match request.try_clone() {
+            Some(current) if attempt < settings.max_retries => current,
+            _ => return Ok(client.execute(request).await?),
+        };
+        let delay = match client.execute(current).await {
+            Ok(response) if retryable_status(response.status(), retry) => {
+                retry_after(&response, &settings)
+            }
+            Ok(response) => return Ok(response),
+            Err(e) if retryable_error(&e, retry) => None,
+            Err(e) => return Err(e.into()),
+        };
+        let delay = delay.unwrap_or_else(|| backoff(attempt, &settings));
+        tracing::debug!(host, attempt, ?delay, "retrying http request");
+        METRICS.inc(HTTP_RETRIES, &[("host", &host)]);
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_policy() {
+        let idempotent = Retry::Idempotent;
+        assert!(retryable_status(StatusCode::TOO_MANY_REQUESTS, idempotent));
+        assert!(retryable_status(StatusCode::BAD_GATEWAY, idempotent));
+        assert!(!retryable_status(StatusCode::BAD_REQUEST, idempotent));
+        // a 5xx may come after the transaction was sent
+        assert!(retryable_status(
+            StatusCode::TOO_MANY_REQUESTS,
+            Retry::Unsafe
+        ));
+        assert!(!retryable_status(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Retry::Unsafe
+        ));
+
+        let settings = HttpSettings::default();
+        for attempt in 0..10 {
+            let delay = backoff(attempt, &settings).as_millis() as u64;
+            let full: u64 = (250 << attempt).min(5000);
+            assert!(delay >= full / 2 && delay <= full, "{}", delay);
+        }
+
+        let limiter = HostLimiter::default();
+        let now = Instant::now();
+        let interval = Duration::from_millis(100);
+        assert_eq!(limiter.reserve("a", 10, now), Duration::ZERO);
+        assert_eq!(limiter.reserve("a", 10, now), interval);
+        assert_eq!(limiter.reserve("a", 10, now), interval * 2);
+        assert_eq!(limiter.reserve("b", 10, now), Duration::ZERO);
+        assert_eq!(
+            limiter.reserve("a", 10, now + interval * 5),
+            Duration::ZERO
+        );
+        assert_eq!(limiter.reserve("a", 0, now), Duration::ZERO);
+    }
+}
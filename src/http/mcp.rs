@@ -0,0 +1,119 @@
+//! SSE transport of the MCP server. A client opens `/v1/mcp/sse` with its
+//! bearer token, receives the endpoint to post JSON-RPC messages to and gets
+//! the responses back as `message` events on the stream. The session belongs
+//! to the authenticated user, messages are posted with a token of the same
+//! user. Tool calls run under the Privy signer of the user
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use actix_web_lab::sse;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::Sender;
+
+use super::middleware::verify_auth;
+use super::state::AppState;
+use crate::common::spawn_with_signer;
+use crate::signer::privy::PrivySigner;
+use crate::signer::TransactionSigner;
+
+struct McpSession {
+    user_id: String,
+    tx: Sender<sse::Event>,
+    signer: Arc<dyn TransactionSigner>,
+}
+
+static SESSIONS: Lazy<Mutex<HashMap<String, McpSession>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Deserialize)]
+pub struct MessageQuery {
+    session_id: String,
+}
+
+#[get("/mcp/sse")]
+async fn mcp_sse(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let user_session = match verify_auth(&req).await {
+        Ok(session) => session,
+        Err(e) => {
+            return HttpResponse::Unauthorized()
+                .json(json!({ "error": e.to_string() }))
+        }
+    };
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<sse::Event>(32);
+    let session_id = format!("{:032x}", rand::random::<u128>());
+    let endpoint = format!("/v1/mcp/messages?session_id={}", session_id);
+    let _ = tx
+        .send(sse::Event::Data(sse::Data::new(endpoint).event("endpoint")))
+        .await;
+
+    let user_id = user_session.user_id.clone();
+    let signer: Arc<dyn TransactionSigner> = Arc::new(PrivySigner::new(
+        state.wallet_manager.clone(),
+        user_session,
+    ));
+    SESSIONS.lock().unwrap().insert(
+        session_id,
+        McpSession {
+            user_id,
+            tx,
+            signer,
+        },
+    );
+
+    sse::Sse::from_infallible_receiver(rx)
+        .with_keep_alive(Duration::from_secs(15))
+        .respond_to(&req)
+        .map_into_boxed_body()
+}
+
+#[post("/mcp/messages")]
+async fn mcp_message(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    query: web::Query<MessageQuery>,
+    message: web::Json<Value>,
+) -> HttpResponse {
+    let user_session = match verify_auth(&req).await {
+        Ok(session) => session,
+        Err(e) => {
+            return HttpResponse::Unauthorized()
+                .json(json!({ "error": e.to_string() }))
+        }
+    };
+    let (tx, signer) = {
+        let mut sessions = SESSIONS.lock().unwrap();
+        // drop the sessions of clients that went away
+        sessions.retain(|_, session| !session.tx.is_closed());
+        match sessions.get(&query.session_id) {
+            // sessions of other users look the same as unknown ones
+            Some(session) if session.user_id == user_session.user_id => {
+                (session.tx.clone(), session.signer.clone())
+            }
+            _ => {
+                return HttpResponse::NotFound()
+                    .json(json!({ "error": "Unknown session" }))
+            }
+        }
+    };
+
+    let mcp = state.mcp.clone();
+    let message = message.into_inner();
+    spawn_with_signer(signer, || async move {
+        if let Some(response) = mcp.handle(message).await {
+            let event = sse::Data::new(response.to_string()).event("message");
+            let _ = tx.send(sse::Event::Data(event)).await;
+        }
+        Ok(())
+    })
+    .await;
+
+    HttpResponse::Accepted().finish()
+}
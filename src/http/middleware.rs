@@ -1,3 +1,5 @@
+use crate::config::config;
+use crate::portfolio::{TrackedUser, SNAPSHOTS};
 use crate::wallet_manager::UserSession;
 use actix_web::{web, HttpRequest};
 use anyhow::Result;
@@ -16,9 +18,16 @@ pub async fn verify_auth(req: &HttpRequest) -> Result<UserSession> {
         .app_data::<web::Data<AppState>>()
         .ok_or_else(|| anyhow::anyhow!("App state not found"))?;
 
-    state
+    let session = state
         .wallet_manager
         .authenticate_user(token)
         .await
-        .map_err(|e| anyhow::anyhow!("Invalid token: {}", e))
+        .map_err(|e| anyhow::anyhow!("Invalid token: {}", e))?;
+
+    if config().snapshots.enabled {
+        if let Err(e) = SNAPSHOTS.track(TrackedUser::from(&session)).await {
+            tracing::warn!(?e, "user not tracked for portfolio snapshots");
+        }
+    }
+    Ok(session)
 }
@@ -1,6 +1,14 @@
+pub mod client;
+#[cfg(feature = "http")]
+pub mod mcp;
+#[cfg(feature = "http")]
 pub mod middleware;
+#[cfg(feature = "http")]
 pub mod routes;
+#[cfg(feature = "http")]
 pub mod server;
+#[cfg(feature = "http")]
 pub mod state;
 
+#[cfg(feature = "http")]
 pub use server::run_server;
@@ -1,10 +1,28 @@
 use super::middleware::verify_auth;
 use super::state::AppState;
+use crate::alerts::ALERTS;
+use crate::audit::{audit_user, replay, AuditQuery, AUDIT};
+use crate::automation::{self, AutomationCall};
+use crate::cancellation::cancel_user_calls;
 use crate::common::spawn_with_signer;
+use crate::config::config;
+use crate::dedup::with_request_id;
+use crate::dispatch::dispatch_tool_call;
+use crate::freeze::{freeze_wallet, unfreeze_wallet};
+use crate::health;
+use crate::history;
+use crate::metrics::METRICS;
+use crate::preferences;
+use crate::progress::ProgressEvent;
 use crate::reasoning_loop::LoopResponse;
 use crate::reasoning_loop::ReasoningLoop;
-use crate::signer::privy::PrivySigner;
+use crate::render::render;
+use crate::revenue::REVENUE;
+use crate::shutdown::SHUTDOWN;
+use crate::signer::privy::{PrivySigner, PrivySignerResolver};
+use crate::signer::registry::SessionResolver;
 use crate::signer::TransactionSigner;
+use crate::tool_error::{ErrorCode, ToolError};
 use actix_web::{
     get, post, web, Error, HttpRequest, HttpResponse, Responder,
 };
@@ -13,8 +31,11 @@ use anyhow::Result;
 use rig::completion::Message;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
 
 #[derive(Deserialize)]
 pub struct ChatRequest {
@@ -28,7 +49,14 @@ pub struct ChatRequest {
 #[serde(tag = "type", content = "content")]
 pub enum StreamResponse {
     Message(String),
-    ToolCall { name: String, result: String },
+    /// `rendered` is the result in the language of the session, for the
+    /// user
+    ToolCall {
+        name: String,
+        result: String,
+        rendered: String,
+    },
+    Progress { name: String, event: ProgressEvent },
     Error(String),
 }
 
@@ -64,11 +92,9 @@ async fn stream(
 
     // Select the appropriate agent based on the chain parameter
     let agent = match request.chain.as_deref() {
-        #[cfg(feature = "solana")]
-        Some("solana") => state.solana_agent.clone(),
-        #[cfg(feature = "evm")]
-        Some("evm") => state.evm_agent.clone(),
-        Some("omni") => state.omni_agent.clone(),
+        Some(chain) if state.agent(chain).is_some() => {
+            state.agent(chain).unwrap()
+        }
         Some(chain) => {
             let error_event = sse::Event::Data(sse::Data::new(
                 serde_json::to_string(&StreamResponse::Error(format!(
@@ -94,6 +120,7 @@ async fn stream(
 
     let prompt = request.prompt.clone();
     let messages = request.chat_history.clone();
+    let request_id = request_id(&req);
 
     let signer: Arc<dyn TransactionSigner> = Arc::new(PrivySigner::new(
         state.wallet_manager.clone(),
@@ -112,6 +139,7 @@ async fn stream(
         // Create a channel for the reasoning loop to send responses
         let (internal_tx, mut internal_rx) = tokio::sync::mpsc::channel(32);
 
+        let locale = preferences::locale().await;
         // Create a separate task to handle sending responses
         let tx_clone = tx.clone();
         let send_task = tokio::spawn(async move {
@@ -121,7 +149,15 @@ async fn stream(
                         StreamResponse::Message(text)
                     }
                     LoopResponse::ToolCall { name, result } => {
-                        StreamResponse::ToolCall { name, result }
+                        let rendered = render(&name, &result, locale);
+                        StreamResponse::ToolCall {
+                            name,
+                            result,
+                            rendered,
+                        }
+                    }
+                    LoopResponse::Progress { name, event } => {
+                        StreamResponse::Progress { name, event }
                     }
                 };
 
@@ -138,9 +174,11 @@ async fn stream(
         });
 
         // Run the reasoning loop in the current task (with signer context)
-        let loop_result = reasoning_loop
-            .stream(initial_messages, Some(internal_tx))
-            .await;
+        let loop_result = with_request_id(
+            request_id,
+            reasoning_loop.stream(initial_messages, Some(internal_tx)),
+        )
+        .await;
 
         // Wait for the send task to complete
         let _ = send_task.await;
@@ -166,14 +204,570 @@ async fn stream(
         .with_retry_duration(Duration::from_secs(10))
 }
 
+#[derive(Serialize)]
+pub struct ChatResponse {
+    response: String,
+    tool_calls: Vec<ToolCallResult>,
+}
+
+#[derive(Serialize)]
+pub struct ToolCallResult {
+    name: String,
+    result: String,
+    /// in the language of the session
+    rendered: String,
+}
+
+/// Non-streaming counterpart of `/stream`, returns the final answer of the
+/// agent together with the tool calls it made
+#[post("/chat")]
+async fn chat(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    request: web::Json<ChatRequest>,
+) -> Result<HttpResponse, Error> {
+    let user_session = match verify_auth(&req).await {
+        Ok(session) => session,
+        Err(e) => {
+            return Ok(HttpResponse::Unauthorized()
+                .json(json!({ "error": e.to_string() })))
+        }
+    };
+
+    let Some(agent) = request.chain.as_deref().and_then(|c| state.agent(c))
+    else {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "error": format!("Unsupported chain: {:?}", request.chain)
+        })));
+    };
+
+    let request = request.into_inner();
+    let request_id = request_id(&req);
+    let signer: Arc<dyn TransactionSigner> = Arc::new(PrivySigner::new(
+        state.wallet_manager.clone(),
+        user_session,
+    ));
+
+    let result = spawn_with_signer(signer, || async move {
+        let reasoning_loop = ReasoningLoop::new(agent).with_stdout(false);
+
+        let mut messages = request.chat_history;
+        messages.push(Message {
+            role: "user".to_string(),
+            content: request.prompt,
+        });
+
+        let locale = preferences::locale().await;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        let collect_task = tokio::spawn(async move {
+            let mut response = String::new();
+            let mut tool_calls = vec![];
+            while let Some(event) = rx.recv().await {
+                match event {
+                    LoopResponse::Message(text) => response.push_str(&text),
+                    LoopResponse::ToolCall { name, result } => {
+                        let rendered = render(&name, &result, locale);
+                        tool_calls.push(ToolCallResult {
+                            name,
+                            result,
+                            rendered,
+                        })
+                    }
+                    LoopResponse::Progress { .. } => {}
+                }
+            }
+            ChatResponse {
+                response,
+                tool_calls,
+            }
+        });
+
+        with_request_id(request_id, reasoning_loop.stream(messages, Some(tx)))
+            .await?;
+        Ok(collect_task.await?)
+    })
+    .await
+    .await;
+
+    match result {
+        Ok(Ok(response)) => Ok(HttpResponse::Ok().json(response)),
+        Ok(Err(e)) => Ok(HttpResponse::InternalServerError()
+            .json(json!({ "error": e.to_string() }))),
+        Err(e) => Ok(HttpResponse::InternalServerError()
+            .json(json!({ "error": e.to_string() }))),
+    }
+}
+
+/// Calls a single tool of any agent with the JSON body as its arguments,
+/// the same session checks apply as for tool calls made by the agent
+#[post("/tools/{name}")]
+async fn call_tool(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    name: web::Path<String>,
+    args: web::Json<serde_json::Value>,
+) -> Result<HttpResponse, Error> {
+    let user_session = match verify_auth(&req).await {
+        Ok(session) => session,
+        Err(e) => {
+            return Ok(HttpResponse::Unauthorized()
+                .json(json!({ "error": e.to_string() })))
+        }
+    };
+
+    let name = name.into_inner();
+    let Some(agent) = state
+        .agents()
+        .into_iter()
+        .find(|agent| agent.tools.contains(&name))
+    else {
+        return Ok(HttpResponse::NotFound()
+            .json(json!({ "error": format!("Tool {} not found", name) })));
+    };
+
+    let signer: Arc<dyn TransactionSigner> = Arc::new(PrivySigner::new(
+        state.wallet_manager.clone(),
+        user_session,
+    ));
+    let args = args.into_inner().to_string();
+    let request_id = request_id(&req);
+
+    let result = spawn_with_signer(signer, || async move {
+        with_request_id(
+            request_id,
+            dispatch_tool_call(&agent.tools, &name, args),
+        )
+        .await
+    })
+    .await
+    .await;
+
+    match result {
+        Ok(Ok(result)) => {
+            // tools return serialized JSON, plain strings are passed as is
+            let result = serde_json::from_str::<serde_json::Value>(&result)
+                .unwrap_or(serde_json::Value::String(result));
+            Ok(HttpResponse::Ok().json(json!({ "result": result })))
+        }
+        Ok(Err(e)) => Ok(HttpResponse::BadRequest()
+            .json(json!({ "error": e.to_string() }))),
+        Err(e) => Ok(HttpResponse::InternalServerError()
+            .json(json!({ "error": e.to_string() }))),
+    }
+}
+
+/// Runs a tool through an automation hook for external systems, they
+/// authenticate with the token of the hook instead of a user session
+#[post("/automation/{id}")]
+async fn automation_call(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+    call: web::Json<AutomationCall>,
+) -> Result<HttpResponse, Error> {
+    let bearer = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .map(str::to_string);
+    let resolver = SessionResolver::new(PrivySignerResolver::new(
+        state.wallet_manager.clone(),
+    ));
+
+    match automation::trigger(
+        &id,
+        call.into_inner(),
+        bearer,
+        &state.agents(),
+        &resolver,
+    )
+    .await
+    {
+        Ok(result) => {
+            let result = serde_json::from_str::<serde_json::Value>(&result)
+                .unwrap_or(serde_json::Value::String(result));
+            Ok(HttpResponse::Ok().json(json!({ "result": result })))
+        }
+        Err(e) => {
+            let error = ToolError::from_anyhow(&e);
+            let mut response = match error.code {
+                ErrorCode::NotFound => HttpResponse::NotFound(),
+                ErrorCode::Unauthorized => HttpResponse::Unauthorized(),
+                ErrorCode::AccessDenied => HttpResponse::Forbidden(),
+                ErrorCode::RateLimited => HttpResponse::TooManyRequests(),
+                _ => HttpResponse::BadRequest(),
+            };
+            Ok(response.json(json!({ "error": error })))
+        }
+    }
+}
+
+/// Tool calls of the authenticated user, newest first
+#[get("/audit")]
+async fn audit_log(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, Error> {
+    let user_session = match verify_auth(&req).await {
+        Ok(session) => session,
+        Err(e) => {
+            return Ok(HttpResponse::Unauthorized()
+                .json(json!({ "error": e.to_string() })))
+        }
+    };
+    let signer = PrivySigner::new(state.wallet_manager.clone(), user_session);
+
+    match AUDIT.query(&audit_user(&signer), &query).await {
+        Ok(records) => Ok(HttpResponse::Ok().json(records)),
+        Err(e) => Ok(HttpResponse::InternalServerError()
+            .json(json!({ "error": e.to_string() }))),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    #[serde(default = "default_period")]
+    period: String,
+    #[serde(default)]
+    format: String,
+}
+
+fn default_period() -> String {
+    "ytd".to_string()
+}
+
+/// Transaction history of the authenticated user as a file, see `history`
+#[get("/history/export")]
+async fn export_history(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, Error> {
+    let user_session = match verify_auth(&req).await {
+        Ok(session) => session,
+        Err(e) => {
+            return Ok(HttpResponse::Unauthorized()
+                .json(json!({ "error": e.to_string() })))
+        }
+    };
+    let signer: Arc<dyn TransactionSigner> = Arc::new(PrivySigner::new(
+        state.wallet_manager.clone(),
+        user_session,
+    ));
+    let ExportQuery { period, format } = query.into_inner();
+
+    let result = spawn_with_signer(signer, || async move {
+        history::export(&period, &format).await
+    })
+    .await
+    .await;
+
+    match result {
+        Ok(Ok(export)) => Ok(HttpResponse::Ok()
+            .content_type(export.content_type)
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", export.filename),
+            ))
+            .body(export.content)),
+        Ok(Err(e)) => Ok(HttpResponse::BadRequest()
+            .json(json!({ "error": e.to_string() }))),
+        Err(e) => Ok(HttpResponse::InternalServerError()
+            .json(json!({ "error": e.to_string() }))),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RevenueQuery {
+    #[serde(default = "default_period")]
+    period: String,
+}
+
+/// Fees collected by the operator and the referrer shares, authenticated
+/// with revenue.api_token. Not found while no token is configured
+#[get("/revenue")]
+async fn revenue_report(
+    req: HttpRequest,
+    query: web::Query<RevenueQuery>,
+) -> Result<HttpResponse, Error> {
+    let Some(api_token) = config().revenue.api_token.clone() else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    if !require_bearer(&req, &api_token) {
+        return Ok(HttpResponse::Unauthorized()
+            .json(json!({ "error": "Invalid revenue token" })));
+    }
+    let period = match history::Period::from_str(&query.period) {
+        Ok(period) => period,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(json!({ "error": e })))
+        }
+    };
+    match REVENUE.report(&period).await {
+        Ok(report) => Ok(HttpResponse::Ok().json(report)),
+        Err(e) => Ok(HttpResponse::InternalServerError()
+            .json(json!({ "error": e.to_string() }))),
+    }
+}
+
+/// Fails unless the request carries admin.api_token, not found while no
+/// token is configured
+fn check_admin(req: &HttpRequest) -> Result<(), HttpResponse> {
+    let Some(api_token) = config().admin.api_token.clone() else {
+        return Err(HttpResponse::NotFound().finish());
+    };
+    if !require_bearer(req, &api_token) {
+        return Err(HttpResponse::Unauthorized()
+            .json(json!({ "error": "Invalid admin token" })));
+    }
+    Ok(())
+}
+
+/// X-Request-Id of the client, kept when it resends a request so the
+/// trades of the first one are not executed again
+fn request_id(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get("X-Request-Id")
+        .and_then(|h| h.to_str().ok())
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+}
+
+/// Whether the request carries `token` as its bearer token, compared in
+/// constant time so the response time doesn't leak how much of it matched
+fn require_bearer(req: &HttpRequest, token: &str) -> bool {
+    let Some(bearer) = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    // digests have the same length whatever the lengths of the tokens
+    let bearer = Sha256::digest(bearer.as_bytes());
+    let token = Sha256::digest(token.as_bytes());
+    bearer.iter().zip(token.iter()).fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+#[derive(Deserialize)]
+pub struct FreezeRequest {
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Freezes the wallet of the user, see `freeze`
+#[post("/admin/wallets/{user_id}/freeze")]
+async fn admin_freeze_wallet(
+    req: HttpRequest,
+    user_id: web::Path<String>,
+    body: Option<web::Json<FreezeRequest>>,
+) -> Result<HttpResponse, Error> {
+    if let Err(response) = check_admin(&req) {
+        return Ok(response);
+    }
+    let reason = body.and_then(|body| body.into_inner().reason);
+    match freeze_wallet(&user_id, reason).await {
+        Ok(report) => Ok(HttpResponse::Ok().json(report)),
+        Err(e) => Ok(HttpResponse::InternalServerError()
+            .json(json!({ "error": e.to_string() }))),
+    }
+}
+
+#[post("/admin/wallets/{user_id}/unfreeze")]
+async fn admin_unfreeze_wallet(
+    req: HttpRequest,
+    user_id: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    if let Err(response) = check_admin(&req) {
+        return Ok(response);
+    }
+    match unfreeze_wallet(&user_id).await {
+        Ok(freeze) => Ok(HttpResponse::Ok().json(freeze)),
+        Err(e) => Ok(HttpResponse::InternalServerError()
+            .json(json!({ "error": e.to_string() }))),
+    }
+}
+
+/// Dry-runs a logged tool call again against the paper signer
+#[post("/audit/{id}/replay")]
+async fn replay_tool_call(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let user_session = match verify_auth(&req).await {
+        Ok(session) => session,
+        Err(e) => {
+            return Ok(HttpResponse::Unauthorized()
+                .json(json!({ "error": e.to_string() })))
+        }
+    };
+    let signer: Arc<dyn TransactionSigner> = Arc::new(PrivySigner::new(
+        state.wallet_manager.clone(),
+        user_session,
+    ));
+
+    let record = match AUDIT.get(&audit_user(signer.as_ref()), &id).await {
+        Ok(record) => record,
+        Err(e) => {
+            return Ok(HttpResponse::NotFound()
+                .json(json!({ "error": e.to_string() })))
+        }
+    };
+    let Some(agent) = state
+        .agents()
+        .into_iter()
+        .find(|agent| agent.tools.contains(&record.tool))
+    else {
+        return Ok(HttpResponse::NotFound().json(
+            json!({ "error": format!("Tool {} not found", record.tool) }),
+        ));
+    };
+
+    let result = spawn_with_signer(signer, || async move {
+        replay(&agent.tools, &record).await
+    })
+    .await
+    .await;
+
+    match result {
+        Ok(Ok(result)) => Ok(HttpResponse::Ok().json(json!({
+            "result": result,
+            "paper": true,
+        }))),
+        Ok(Err(e)) => Ok(HttpResponse::BadRequest()
+            .json(json!({ "error": e.to_string() }))),
+        Err(e) => Ok(HttpResponse::InternalServerError()
+            .json(json!({ "error": e.to_string() }))),
+    }
+}
+
+/// Price alerts of the user as they fire, as server-sent events
+#[get("/alerts/stream")]
+async fn alert_stream(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let (tx, rx) = tokio::sync::mpsc::channel::<sse::Event>(16);
+    let user_session = match verify_auth(&req).await {
+        Ok(session) => session,
+        Err(_) => {
+            let error_event = sse::Event::Data(sse::Data::new(
+                serde_json::to_string(&StreamResponse::Error(
+                    "Error: unauthorized".to_string(),
+                ))
+                .unwrap(),
+            ));
+            let _ = tx.send(error_event).await;
+            return sse::Sse::from_infallible_receiver(rx);
+        }
+    };
+    let signer = PrivySigner::new(state.wallet_manager.clone(), user_session);
+    let user_id = signer.user_id();
+    let mut alerts = ALERTS.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let alert = tokio::select! {
+                alert = alerts.recv() => alert,
+                _ = tx.closed() => break,
+            };
+            match alert {
+                Ok(alert) if alert.owner.user_id == user_id => {
+                    let data = match serde_json::to_string(&alert) {
+                        Ok(data) => sse::Data::new(data),
+                        Err(e) => {
+                            tracing::warn!(?e, "alert not serialized");
+                            continue;
+                        }
+                    };
+                    if tx.send(sse::Event::Data(data)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                // the alerts stay listed as triggered, list_alerts has them
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(skipped, "alert stream lagged")
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    sse::Sse::from_infallible_receiver(rx)
+        .with_keep_alive(Duration::from_secs(15))
+}
+
+/// Cancels the in-flight tool calls and pending actions of the user, for
+/// a cancel button next to a running tool
+#[post("/cancel")]
+async fn cancel(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let user_session = match verify_auth(&req).await {
+        Ok(session) => session,
+        Err(e) => {
+            return Ok(HttpResponse::Unauthorized()
+                .json(json!({ "error": e.to_string() })))
+        }
+    };
+
+    let signer = PrivySigner::new(state.wallet_manager.clone(), user_session);
+    Ok(HttpResponse::Ok().json(cancel_user_calls(&signer)))
+}
+
+/// Liveness of the instance, see `readyz` for its dependencies
 #[get("/healthz")]
 async fn healthz() -> Result<HttpResponse, Error> {
+    // takes the instance out of the load balancer while it drains
+    if SHUTDOWN.is_draining() {
+        return Ok(HttpResponse::ServiceUnavailable().json(json!({
+            "status": "draining",
+            "timestamp": chrono::Utc::now().to_rfc3339()
+        })));
+    }
     Ok(HttpResponse::Ok().json(json!({
         "status": "ok",
         "timestamp": chrono::Utc::now().to_rfc3339()
     })))
 }
 
+/// Readiness of the instance, 503 while it drains or when a required
+/// service is unreachable. Only ok or fail per service unless the request
+/// carries admin.api_token, the errors can name hosts and credentials
+#[get("/readyz")]
+async fn readyz(req: HttpRequest) -> Result<HttpResponse, Error> {
+    if SHUTDOWN.is_draining() {
+        return Ok(HttpResponse::ServiceUnavailable().json(json!({
+            "status": "draining",
+            "timestamp": chrono::Utc::now().to_rfc3339()
+        })));
+    }
+    let report = health::check_all().await;
+    let body = match check_admin(&req) {
+        Ok(()) => json!(report),
+        Err(_) => report.public(),
+    };
+    if !report.is_ready() {
+        return Ok(HttpResponse::ServiceUnavailable().json(body));
+    }
+    Ok(HttpResponse::Ok().json(body))
+}
+
+/// Prometheus scrape endpoint
+#[get("/metrics")]
+async fn metrics() -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(METRICS.render()))
+}
+
 #[get("/auth")]
 async fn auth(req: HttpRequest) -> Result<HttpResponse, Error> {
     let user_session = match verify_auth(&req).await {
@@ -1,16 +1,44 @@
+use crate::activity::{run_activity_job, ACTIVITY_JOB};
+use crate::alerts::ALERTS;
+use crate::config::config;
+use crate::deposits::DEPOSITS;
+use crate::jobs::JOBS;
+use crate::outbox::OUTBOX;
+use crate::portfolio::SNAPSHOTS;
+use crate::scheduler::SCHEDULER;
+use crate::shutdown::{self, SHUTDOWN};
+use crate::signer::privy::PrivySignerResolver;
+use crate::signer::registry::SessionResolver;
+use crate::signer::SignerResolver;
+use crate::solana::blockhash::BLOCKHASH_CACHE;
+use crate::solana::congestion::CONGESTION;
+use crate::solana::launches::LAUNCHES;
+use crate::solana::prefetch::PREFETCHER;
+use crate::sonic::tools::{run_claim_job, GATEWAY_CLAIM_JOB};
+use crate::triggers::TRIGGERS;
+use crate::twap::{run_slice, TWAP_SLICE_JOB};
 use crate::wallet_manager::WalletManager;
 use actix_cors::Cors;
 use actix_web::middleware::{Compress, Logger};
 use actix_web::{web, App, HttpServer};
+use once_cell::sync::Lazy;
 use rig::agent::Agent;
 use rig::providers::anthropic::completion::CompletionModel;
+use std::sync::Arc;
+use std::time::Duration;
 
-use super::routes::{auth, healthz, stream};
+use super::mcp::{mcp_message, mcp_sse};
+use super::routes::{
+    admin_freeze_wallet, admin_unfreeze_wallet, alert_stream, audit_log,
+    auth, automation_call, call_tool, cancel, chat, export_history, healthz,
+    metrics, readyz, replay_tool_call, revenue_report, stream,
+};
 use super::state::AppState;
 
+/// The agents of the chains that are not enabled are None
 pub async fn run_server(
-    #[cfg(feature = "solana")] solana_agent: Agent<CompletionModel>,
-    #[cfg(feature = "evm")] evm_agent: Agent<CompletionModel>,
+    solana_agent: Option<Agent<CompletionModel>>,
+    evm_agent: Option<Agent<CompletionModel>>,
     wallet_manager: WalletManager,
     omni_agent: Agent<CompletionModel>,
 ) -> std::io::Result<()> {
@@ -18,29 +46,98 @@ pub async fn run_server(
 
     builder = builder.with_omni_agent(omni_agent);
 
-    #[cfg(feature = "solana")]
-    {
+    if let Some(solana_agent) = solana_agent {
         builder = builder.with_solana_agent(solana_agent);
     }
 
-    #[cfg(feature = "evm")]
-    {
+    if let Some(evm_agent) = evm_agent {
         builder = builder.with_evm_agent(evm_agent);
     }
 
     let state =
         web::Data::new(builder.build().expect("Failed to build AppState"));
 
-    HttpServer::new(move || {
+    ALERTS.spawn();
+    PREFETCHER.spawn();
+    CONGESTION.spawn();
+    DEPOSITS.spawn();
+    if let Err(e) = OUTBOX.schedule().await {
+        tracing::error!(?e, "outbox job not queued");
+    }
+    if let Err(e) = SNAPSHOTS.schedule().await {
+        tracing::error!(?e, "portfolio snapshots job not queued");
+    }
+    JOBS.register(GATEWAY_CLAIM_JOB, run_claim_job);
+    JOBS.register(TWAP_SLICE_JOB, run_slice);
+    JOBS.register(ACTIVITY_JOB, run_activity_job);
+    // starts refreshing, the first swap doesn't wait for a blockhash
+    Lazy::force(&BLOCKHASH_CACHE);
+
+    let agents = state.agents();
+    let resolver: Arc<dyn SignerResolver> = Arc::new(SessionResolver::new(
+        PrivySignerResolver::new(state.wallet_manager.clone()),
+    ));
+    SCHEDULER.spawn(agents.clone());
+    JOBS.spawn(resolver.clone());
+    TRIGGERS.spawn(agents.clone(), resolver.clone());
+    LAUNCHES.spawn(agents, resolver);
+
+    match SHUTDOWN.interrupted_calls().await {
+        Ok(calls) if !calls.is_empty() => tracing::warn!(
+            count = calls.len(),
+            "tool calls were interrupted by earlier shutdowns"
+        ),
+        Ok(_) => {}
+        Err(e) => tracing::error!(?e, "interrupted calls not loaded"),
+    }
+
+    let server = HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
             .wrap(Compress::default())
             .wrap(Cors::permissive())
             .app_data(state.clone())
             .service(healthz)
-            .service(web::scope("/v1").service(stream).service(auth))
+            .service(readyz)
+            .service(metrics)
+            .service(
+                web::scope("/v1")
+                    .service(stream)
+                    .service(chat)
+                    .service(call_tool)
+                    .service(mcp_sse)
+                    .service(mcp_message)
+                    .service(audit_log)
+                    .service(export_history)
+                    .service(automation_call)
+                    .service(revenue_report)
+                    .service(replay_tool_call)
+                    .service(cancel)
+                    .service(alert_stream)
+                    .service(admin_freeze_wallet)
+                    .service(admin_unfreeze_wallet)
+                    .service(auth),
+            )
     })
     .bind("0.0.0.0:6969")?
-    .run()
-    .await
+    .disable_signals()
+    .run();
+
+    // tool calls are drained before the server stops taking requests
+    let handle = server.handle();
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        let timeout =
+            Duration::from_secs(config().shutdown.drain_timeout_secs);
+        tracing::info!(?timeout, "shutting down, draining tool calls");
+        let report = SHUTDOWN.drain(timeout).await;
+        tracing::info!(
+            drained = report.drained,
+            interrupted = report.interrupted.len(),
+            "tool calls drained"
+        );
+        handle.stop(true).await;
+    });
+
+    server.await
 }
@@ -1,21 +1,21 @@
+use crate::chains::{Chain, CHAINS};
+use crate::mcp::McpServer;
 use crate::wallet_manager::WalletManager;
 use rig::agent::Agent;
 use rig::providers::anthropic::completion::CompletionModel;
 use std::sync::Arc;
 
 pub struct AppState {
-    #[cfg(feature = "solana")]
-    pub(crate) solana_agent: Arc<Agent<CompletionModel>>,
-    #[cfg(feature = "evm")]
-    pub(crate) evm_agent: Arc<Agent<CompletionModel>>,
+    /// None when the chain is not enabled in the deployment
+    pub(crate) solana_agent: Option<Arc<Agent<CompletionModel>>>,
+    pub(crate) evm_agent: Option<Arc<Agent<CompletionModel>>>,
     pub(crate) wallet_manager: Arc<WalletManager>,
     pub(crate) omni_agent: Arc<Agent<CompletionModel>>,
+    pub(crate) mcp: Arc<McpServer>,
 }
 
 pub struct AppStateBuilder {
-    #[cfg(feature = "solana")]
     solana_agent: Option<Agent<CompletionModel>>,
-    #[cfg(feature = "evm")]
     evm_agent: Option<Agent<CompletionModel>>,
     wallet_manager: Option<WalletManager>,
     omni_agent: Option<Agent<CompletionModel>>,
@@ -30,16 +30,13 @@ impl Default for AppStateBuilder {
 impl AppStateBuilder {
     pub fn new() -> Self {
         Self {
-            #[cfg(feature = "solana")]
             solana_agent: None,
-            #[cfg(feature = "evm")]
             evm_agent: None,
             wallet_manager: None,
             omni_agent: None,
         }
     }
 
-    #[cfg(feature = "solana")]
     pub fn with_solana_agent(
         mut self,
         agent: Agent<CompletionModel>,
@@ -48,7 +45,6 @@ impl AppStateBuilder {
         self
     }
 
-    #[cfg(feature = "evm")]
     pub fn with_evm_agent(mut self, agent: Agent<CompletionModel>) -> Self {
         self.evm_agent = Some(agent);
         self
@@ -68,15 +64,15 @@ impl AppStateBuilder {
     }
 
     pub fn build(self) -> Result<AppState, &'static str> {
+        if CHAINS.is_enabled(Chain::Solana) && self.solana_agent.is_none() {
+            return Err("Solana agent is required when solana is enabled");
+        }
+        if CHAINS.is_enabled(Chain::Evm) && self.evm_agent.is_none() {
+            return Err("EVM agent is required when evm is enabled");
+        }
         Ok(AppState {
-            #[cfg(feature = "solana")]
-            solana_agent: Arc::new(self.solana_agent.ok_or(
-                "Solana agent is required when solana feature is enabled",
-            )?),
-            #[cfg(feature = "evm")]
-            evm_agent: Arc::new(self.evm_agent.ok_or(
-                "EVM agent is required when evm feature is enabled",
-            )?),
+            solana_agent: self.solana_agent.map(Arc::new),
+            evm_agent: self.evm_agent.map(Arc::new),
             wallet_manager: Arc::new(
                 self.wallet_manager.ok_or("Wallet manager is required")?,
             ),
@@ -84,6 +80,7 @@ impl AppStateBuilder {
                 self.omni_agent
                     .expect("omni agent is required with http feature"),
             ),
+            mcp: Arc::new(McpServer::with_all_tools()),
         })
     }
 }
@@ -92,4 +89,24 @@ impl AppState {
     pub fn builder() -> AppStateBuilder {
         AppStateBuilder::default()
     }
+
+    /// Agent serving `chain`, one of solana, evm or omni
+    pub(crate) fn agent(
+        &self,
+        chain: &str,
+    ) -> Option<Arc<Agent<CompletionModel>>> {
+        match chain {
+            "solana" => self.solana_agent.clone(),
+            "evm" => self.evm_agent.clone(),
+            "omni" => Some(self.omni_agent.clone()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn agents(&self) -> Vec<Arc<Agent<CompletionModel>>> {
+        let mut agents = vec![self.omni_agent.clone()];
+        agents.extend(self.solana_agent.clone());
+        agents.extend(self.evm_agent.clone());
+        agents
+    }
 }
@@ -0,0 +1,623 @@
+//! Persisted jobs for work that outlives a request: gateway claims waiting
+//! for the bridge to sync, the runs of scheduled tasks, the outbox keeper
+//! and the portfolio snapshots. A job is stored in the job store before it
+//! runs and its status is updated as workers pick it up, so a restart
+//! resumes the queue instead of losing the futures that were in flight.
+//! Failed jobs are retried with exponential backoff until their retry
+//! policy gives up, recurring jobs are queued again after every run. Jobs
+//! of a user run under their signer. Workers are started with
+//! `JOBS.spawn`, JOB_WORKERS (4 by default) of them. Workers find the due
+//! jobs in an index of the queued ones and claim a job by compare-and-set
+//! on the store, so processes sharing the store never run a job twice
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::kv_store::{namespaced, KVStore};
+use crate::signer::{SignerContext, SignerRef, SignerResolver};
+use crate::tool_error::ToolError;
+
+const DEFAULT_WORKERS: usize = 4;
+/// How often an idle worker looks for due jobs
+const TICK: Duration = Duration::from_secs(5);
+/// Finished jobs are kept this long for get_job
+const FINISHED_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    /// failed, runs again at run_at
+    Retrying,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// runs before the job fails, the first one included
+    pub max_attempts: u32,
+    /// delay before the first retry, doubled after every failure
+    pub backoff_secs: u64,
+    pub max_backoff_secs: u64,
+}
+
+impl RetryPolicy {
+    /// A single run, for jobs that must not be repeated blindly
+    pub const NONE: Self = Self {
+        max_attempts: 1,
+        backoff_secs: 0,
+        max_backoff_secs: 0,
+    };
+
+    /// Delay before the run following the `attempts`th failure
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+        Duration::from_secs(
+            self.backoff_secs
+                .saturating_mul(factor)
+                .min(self.max_backoff_secs),
+        )
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            backoff_secs: 30,
+            max_backoff_secs: 30 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    pub kind: String,
+    /// the user the job runs for, None for the jobs of the operator
+    pub owner: Option<SignerRef>,
+    pub payload: Value,
+    pub status: JobStatus,
+    pub retry: RetryPolicy,
+    /// seconds between the runs of a recurring job
+    pub every_secs: Option<u64>,
+    pub attempts: u32,
+    pub run_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub result: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Job {
+    pub fn new(kind: &str, payload: Value) -> Self {
+        let now = Utc::now();
+        Self {
+            id: format!("{:016x}", rand::random::<u64>()),
+            kind: kind.to_string(),
+            owner: None,
+            payload,
+            status: JobStatus::Queued,
+            retry: RetryPolicy::default(),
+            every_secs: None,
+            attempts: 0,
+            run_at: now,
+            last_error: None,
+            result: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn with_owner(mut self, owner: SignerRef) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.run_at = Utc::now() + delay;
+        self
+    }
+
+    /// Runs the job every `interval`, under `id` so it is queued once
+    pub fn recurring(mut self, id: &str, interval: Duration) -> Self {
+        self.id = id.to_string();
+        self.every_secs = Some(interval.as_secs());
+        self
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status, JobStatus::Succeeded | JobStatus::Failed)
+    }
+
+    fn is_due(&self, now: DateTime<Utc>) -> bool {
+        matches!(self.status, JobStatus::Queued | JobStatus::Retrying)
+            && self.run_at <= now
+    }
+
+    /// Moves the job on after a run, `now` is when the run ended
+    fn complete(&mut self, outcome: Result<String>, now: DateTime<Utc>) {
+        self.attempts += 1;
+        self.updated_at = now;
+        match outcome {
+            Ok(result) => {
+                self.status = JobStatus::Succeeded;
+                self.result = Some(result);
+                self.last_error = None;
+            }
+            Err(e) if self.attempts < self.retry.max_attempts => {
+                self.status = JobStatus::Retrying;
+                self.last_error = Some(e.to_string());
+                self.run_at = now + self.retry.delay(self.attempts);
+                return;
+            }
+            Err(e) => {
+                self.status = JobStatus::Failed;
+                self.last_error = Some(e.to_string());
+            }
+        }
+        if let Some(every) = self.every_secs {
+            self.status = JobStatus::Queued;
+            self.attempts = 0;
+            self.run_at = now + Duration::from_secs(every);
+        }
+    }
+}
+
+/// Entry of the index of the queued and retrying jobs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedJob {
+    pub id: String,
+    pub kind: String,
+    pub run_at: DateTime<Utc>,
+}
+
+#[async_trait]
+pub trait JobStore: Send + Sync {
+    async fn get(&self, id: &str) -> Result<Option<Job>>;
+    async fn put(&self, job: &Job) -> Result<()>;
+    /// Every stored job, in no particular order
+    async fn list(&self) -> Result<Vec<Job>>;
+    /// The queued and retrying jobs, in no particular order
+    async fn queued(&self) -> Result<Vec<QueuedJob>>;
+    /// Marks the job as running when it is due, None when it isn't or
+    /// another worker, of any process, claimed it first
+    async fn claim(
+        &self,
+        id: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Job>>;
+}
+
+/// Jobs stored in a kv store under their id, finished ones expire after
+/// FINISHED_TTL. The queued ones are indexed in a second store
+pub struct KvJobStore {
+    store: Arc<dyn KVStore>,
+    queued: Arc<dyn KVStore>,
+}
+
+impl KvJobStore {
+    pub fn new(store: Arc<dyn KVStore>, queued: Arc<dyn KVStore>) -> Self {
+        Self { store, queued }
+    }
+
+    async fn unindex(&self, id: &str) -> Result<()> {
+        self.queued.expire(id, Duration::ZERO).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl JobStore for KvJobStore {
+    async fn get(&self, id: &str) -> Result<Option<Job>> {
+        match self.store.get(id).await? {
+            Some(job) => Ok(Some(serde_json::from_str(&job)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn put(&self, job: &Job) -> Result<()> {
+        let value = serde_json::to_string(job)?;
+        if job.is_finished() {
+            self.store
+                .set_with_ttl(&job.id, &value, FINISHED_TTL)
+                .await?;
+        } else {
+            self.store.set(&job.id, &value).await?;
+        }
+        if !matches!(job.status, JobStatus::Queued | JobStatus::Retrying) {
+            return self.unindex(&job.id).await;
+        }
+        let entry = QueuedJob {
+            id: job.id.clone(),
+            kind: job.kind.clone(),
+            run_at: job.run_at,
+        };
+        self.queued
+            .set(&job.id, &serde_json::to_string(&entry)?)
+            .await
+    }
+
+    async fn queued(&self) -> Result<Vec<QueuedJob>> {
+        self.queued
+            .scan_prefix("")
+            .await?
+            .into_iter()
+            .map(|(_, entry)| Ok(serde_json::from_str(&entry)?))
+            .collect()
+    }
+
+    async fn claim(
+        &self,
+        id: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Job>> {
+        let Some(stored) = self.store.get(id).await? else {
+            self.unindex(id).await?;
+            return Ok(None);
+        };
+        let mut job: Job = serde_json::from_str(&stored)?;
+        if !job.is_due(now) {
+            return Ok(None);
+        }
+        job.status = JobStatus::Running;
+        job.updated_at = now;
+        let running = serde_json::to_string(&job)?;
+        if !self
+            .store
+            .compare_and_set(id, Some(&stored), &running, None)
+            .await?
+        {
+            return Ok(None);
+        }
+        self.unindex(id).await?;
+        Ok(Some(job))
+    }
+
+    async fn list(&self) -> Result<Vec<Job>> {
+        self.store
+            .scan_prefix("")
+            .await?
+            .into_iter()
+            .map(|(_, job)| Ok(serde_json::from_str(&job)?))
+            .collect()
+    }
+}
+
+#[async_trait]
+pub trait JobHandler: Send + Sync {
+    /// Runs the job, the result is stored with it
+    async fn run(&self, job: Job) -> Result<String>;
+}
+
+#[async_trait]
+impl<F, Fut> JobHandler for F
+where
+    F: Fn(Job) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<String>> + Send,
+{
+    async fn run(&self, job: Job) -> Result<String> {
+        self(job).await
+    }
+}
+
+pub struct JobQueue {
+    store: Arc<dyn JobStore>,
+    handlers: RwLock<HashMap<String, Arc<dyn JobHandler>>>,
+    /// `ensure` and `recover` read and write the jobs, they take turns
+    maintenance: tokio::sync::Mutex<()>,
+}
+
+pub static JOBS: Lazy<JobQueue> = Lazy::new(|| {
+    JobQueue::new(Arc::new(KvJobStore::new(
+        Arc::new(namespaced("jobs")),
+        Arc::new(namespaced("jobs_queued")),
+    )))
+});
+
+impl JobQueue {
+    pub fn new(store: Arc<dyn JobStore>) -> Self {
+        Self {
+            store,
+            handlers: RwLock::new(HashMap::new()),
+            maintenance: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Runs the jobs of `kind` with `handler`, jobs of a kind without a
+    /// handler stay queued
+    pub fn register(&self, kind: &str, handler: impl JobHandler + 'static) {
+        self.handlers
+            .write()
+            .unwrap()
+            .insert(kind.to_string(), Arc::new(handler));
+    }
+
+    fn handler(&self, kind: &str) -> Option<Arc<dyn JobHandler>> {
+        self.handlers.read().unwrap().get(kind).cloned()
+    }
+
+    /// Persists the job, it runs once it is due
+    pub async fn enqueue(&self, job: Job) -> Result<String> {
+        self.store.put(&job).await?;
+        Ok(job.id)
+    }
+
+    /// Queues a recurring job unless it already is, a changed interval
+    /// applies from its next run
+    pub async fn ensure(&self, job: Job) -> Result<()> {
+        let _guard = self.maintenance.lock().await;
+        match self.store.get(&job.id).await? {
+            Some(mut stored) if stored.every_secs != job.every_secs => {
+                stored.every_secs = job.every_secs;
+                self.store.put(&stored).await
+            }
+            Some(_) => Ok(()),
+            None => self.store.put(&job).await,
+        }
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<Job>> {
+        self.store.get(id).await
+    }
+
+    /// Marks the earliest due job with a handler as running and returns it
+    async fn claim_next(&self, now: DateTime<Utc>) -> Result<Option<Job>> {
+        let mut due = self
+            .store
+            .queued()
+            .await?
+            .into_iter()
+            .filter(|job| {
+                job.run_at <= now && self.handler(&job.kind).is_some()
+            })
+            .collect::<Vec<_>>();
+        due.sort_by_key(|job| job.run_at);
+        for queued in due {
+            if let Some(job) = self.store.claim(&queued.id, now).await? {
+                return Ok(Some(job));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Queues the jobs a previous process left running, they were
+    /// interrupted. Jobs that must not run twice (`RetryPolicy::NONE`, a
+    /// scheduled swap, a TWAP slice) may have done their work already,
+    /// they fail instead. Also indexes the queued jobs again
+    async fn recover(&self) -> Result<usize> {
+        let _guard = self.maintenance.lock().await;
+        let mut recovered = 0;
+        for mut job in self.store.list().await? {
+            match job.status {
+                JobStatus::Running if job.retry.max_attempts <= 1 => {
+                    job.status = JobStatus::Failed;
+                    job.last_error = Some(
+                        "Interrupted by a restart, it may have run and is \
+                         not run again"
+                            .to_string(),
+                    );
+                    job.updated_at = Utc::now();
+                }
+                JobStatus::Running => {
+                    job.status = JobStatus::Queued;
+                    recovered += 1;
+                }
+                JobStatus::Queued | JobStatus::Retrying => {}
+                JobStatus::Succeeded | JobStatus::Failed => continue,
+            }
+            self.store.put(&job).await?;
+        }
+        Ok(recovered)
+    }
+
+    async fn run(
+        &self,
+        job: Job,
+        resolver: &dyn SignerResolver,
+    ) -> Result<()> {
+        let handler = self
+            .handler(&job.kind)
+            .ok_or_else(|| anyhow!("No handler of {} jobs", job.kind))?;
+        let outcome = match &job.owner {
+            Some(owner) => match resolver.resolve(owner).await {
+                Ok(signer) => {
+                    SignerContext::with_signer(
+                        signer,
+                        handler.run(job.clone()),
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            },
+            None => handler.run(job.clone()).await,
+        };
+        if let Err(e) = &outcome {
+            tracing::warn!(?e, id = %job.id, kind = %job.kind, "job failed");
+        }
+        let mut job = job;
+        job.complete(outcome, Utc::now());
+        self.store.put(&job).await
+    }
+
+    /// Runs one due job, false when there was none
+    async fn work(&self, resolver: &dyn SignerResolver) -> Result<bool> {
+        match self.claim_next(Utc::now()).await? {
+            Some(job) => self.run(job, resolver).await.map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Starts the workers, after queueing again the jobs that were
+    /// interrupted by the last shutdown
+    pub fn spawn(
+        &'static self,
+        resolver: Arc<dyn SignerResolver>,
+    ) -> tokio::task::JoinHandle<()> {
+        let workers = std::env::var("JOB_WORKERS")
+            .ok()
+            .and_then(|workers| workers.parse().ok())
+            .unwrap_or(DEFAULT_WORKERS);
+        tokio::spawn(async move {
+            match self.recover().await {
+                Ok(0) => {}
+                Ok(recovered) => tracing::info!(recovered, "jobs recovered"),
+                Err(e) => tracing::error!(?e, "jobs not recovered"),
+            }
+            let handles = (0..workers)
+                .map(|_| {
+                    let resolver = resolver.clone();
+                    tokio::spawn(async move {
+                        loop {
+                            match self.work(resolver.as_ref()).await {
+                                Ok(true) => continue,
+                                Ok(false) => {}
+                                Err(e) => tracing::error!(?e, "job worker"),
+                            }
+                            tokio::time::sleep(TICK).await;
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            futures::future::join_all(handles).await;
+        })
+    }
+}
+
+#[tool(description = "
+Returns the status of a background job of the user, e.g. the automatic claim
+of a gateway transfer: queued, running, retrying, succeeded or failed, the
+number of attempts, when it runs next, its result or last error
+")]
+pub async fn get_job(id: String) -> Result<Job> {
+    let owner = SignerRef::of(SignerContext::current().await.as_ref());
+    JOBS.get(&id)
+        .await?
+        .filter(|job| job.owner.as_ref().is_some_and(|o| o.same_user(&owner)))
+        .ok_or_else(|| {
+            ToolError::not_found(format!("Job {} not found", id)).into()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    #[test]
+    fn test_retry_policy() {
+        let retry = RetryPolicy {
+            max_attempts: 3,
+            backoff_secs: 10,
+            max_backoff_secs: 25,
+        };
+        assert_eq!(retry.delay(1), Duration::from_secs(10));
+        assert_eq!(retry.delay(2), Duration::from_secs(20));
+        assert_eq!(retry.delay(3), Duration::from_secs(25));
+
+        let now = Utc::now();
+        let mut job = Job::new("test", Value::Null).with_retry(retry);
+        job.complete(Err(anyhow!("not yet")), now);
+        assert_eq!(job.status, JobStatus::Retrying);
+        assert_eq!(job.run_at, now + Duration::from_secs(10));
+        job.complete(Err(anyhow!("not yet")), now);
+        job.complete(Err(anyhow!("gave up")), now);
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.last_error.as_deref(), Some("gave up"));
+
+        let mut job = Job::new("test", Value::Null)
+            .recurring("keeper", Duration::from_secs(60));
+        job.complete(Ok("done".to_string()), now);
+        assert_eq!(job.status, JobStatus::Queued);
+        assert_eq!(job.attempts, 0);
+        assert_eq!(job.run_at, now + Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_claim_due_jobs() {
+        let store = KvJobStore::new(
+            Arc::new(InMemoryKVStore::new()),
+            Arc::new(InMemoryKVStore::new()),
+        );
+        let queue = JobQueue::new(Arc::new(store));
+        queue.register("test", |job: Job| async move {
+            Ok(job.payload.to_string())
+        });
+
+        let later = Job::new("test", Value::Null)
+            .with_delay(Duration::from_secs(3600));
+        queue.enqueue(later).await.unwrap();
+        queue
+            .enqueue(Job::new("unknown", Value::Null))
+            .await
+            .unwrap();
+        let id = queue.enqueue(Job::new("test", 1.into())).await.unwrap();
+
+        let job = queue.claim_next(Utc::now()).await.unwrap().unwrap();
+        assert_eq!(job.id, id);
+        assert_eq!(
+            queue.get(&id).await.unwrap().unwrap().status,
+            JobStatus::Running
+        );
+        // not due, without a handler or already running
+        assert!(queue.claim_next(Utc::now()).await.unwrap().is_none());
+
+        assert_eq!(queue.recover().await.unwrap(), 1);
+        let job = queue.claim_next(Utc::now()).await.unwrap().unwrap();
+        let outcome = queue.handler("test").unwrap().run(job.clone()).await;
+        let mut job = job;
+        job.complete(outcome, Utc::now());
+        assert_eq!(job.status, JobStatus::Succeeded);
+        assert_eq!(job.result.as_deref(), Some("1"));
+        queue.store.put(&job).await.unwrap();
+
+        // a swap interrupted mid-run isn't sent again
+        let id = queue
+            .enqueue(Job::new("test", 2.into()).with_retry(RetryPolicy::NONE))
+            .await
+            .unwrap();
+        let claimed = queue.claim_next(Utc::now()).await.unwrap().unwrap();
+        assert_eq!(claimed.id, id);
+        assert_eq!(queue.recover().await.unwrap(), 0);
+        let job = queue.get(&id).await.unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Failed);
+        assert!(queue.claim_next(Utc::now()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_claims() {
+        let store = Arc::new(KvJobStore::new(
+            Arc::new(InMemoryKVStore::new()),
+            Arc::new(InMemoryKVStore::new()),
+        ));
+        // two processes sharing the store
+        let first = JobQueue::new(store.clone());
+        let second = JobQueue::new(store);
+        for queue in [&first, &second] {
+            queue.register("test", |_: Job| async { Ok(String::new()) });
+        }
+        first.enqueue(Job::new("test", Value::Null)).await.unwrap();
+        let (a, b) = tokio::join!(
+            first.claim_next(Utc::now()),
+            second.claim_next(Utc::now())
+        );
+        let claimed = [a.unwrap(), b.unwrap()];
+        assert_eq!(claimed.iter().flatten().count(), 1);
+    }
+}
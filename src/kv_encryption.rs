@@ -0,0 +1,358 @@
+//! Encryption at rest for the kv_store. Every value is sealed with its own
+//! AES-256-GCM data key, the data key is wrapped by a `MasterKey` (a key
+//! from the config, or a KMS behind the same trait) and stored next to the
+//! ciphertext, so no backend ever sees sessions, tokens or wallet metadata
+//! in plaintext. The kv key is authenticated with the value, a value copied
+//! under another key fails to decrypt. Values written before encryption was
+//! enabled are refused, unless KV_ENCRYPTION_MIGRATE=true reads them as they
+//! are while they get rewritten encrypted
+use std::sync::Arc;
+use std::time::Duration;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+
+use crate::kv_store::{InMemoryKVStore, KVStore};
+
+const PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+
+/// Wraps and unwraps the data keys, implemented by the config key and by
+/// KMS clients
+#[async_trait]
+pub trait MasterKey: Send + Sync {
+    /// Stored with every value to find the key it was wrapped with
+    fn id(&self) -> &str;
+    async fn wrap(&self, data_key: &[u8]) -> Result<Vec<u8>>;
+    async fn unwrap(&self, wrapped: &[u8]) -> Result<Vec<u8>>;
+}
+
+pub(crate) fn seal(
+    key: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = rand::random::<[u8; NONCE_LEN]>();
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| anyhow!("Encryption failed"))?;
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+pub(crate) fn open(key: &[u8], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN || key.len() != 32 {
+        return Err(anyhow!("Malformed encrypted value"));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| {
+            anyhow!("Decryption failed, wrong key or tampered value")
+        })
+}
+
+/// 32-byte key from the config
+pub struct LocalMasterKey {
+    id: String,
+    key: [u8; 32],
+}
+
+impl LocalMasterKey {
+    pub fn new(id: &str, key: [u8; 32]) -> Result<Self> {
+        if id.is_empty() || id.contains(':') {
+            return Err(anyhow!("Invalid master key id {}", id));
+        }
+        Ok(Self {
+            id: id.to_string(),
+            key,
+        })
+    }
+
+    /// KV_ENCRYPTION_KEY (base64, 32 bytes) and KV_ENCRYPTION_KEY_ID,
+    /// None when no key is configured
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(key) = std::env::var("KV_ENCRYPTION_KEY") else {
+            return Ok(None);
+        };
+        let key: [u8; 32] = BASE64_STANDARD
+            .decode(key.trim())?
+            .try_into()
+            .map_err(|_| anyhow!("KV_ENCRYPTION_KEY has to be 32 bytes"))?;
+        let id = std::env::var("KV_ENCRYPTION_KEY_ID")
+            .unwrap_or_else(|_| "local".to_string());
+        Self::new(&id, key).map(Some)
+    }
+}
+
+#[async_trait]
+impl MasterKey for LocalMasterKey {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    async fn wrap(&self, data_key: &[u8]) -> Result<Vec<u8>> {
+        seal(&self.key, data_key, self.id.as_bytes())
+    }
+
+    async fn unwrap(&self, wrapped: &[u8]) -> Result<Vec<u8>> {
+        open(&self.key, wrapped, self.id.as_bytes())
+    }
+}
+
+/// Stands in for a KV_ENCRYPTION_KEY that is missing or invalid, every
+/// value fails to encrypt and decrypt with its error
+struct InvalidMasterKey(String);
+
+#[async_trait]
+impl MasterKey for InvalidMasterKey {
+    fn id(&self) -> &str {
+        "invalid"
+    }
+
+    async fn wrap(&self, _data_key: &[u8]) -> Result<Vec<u8>> {
+        Err(anyhow!("{}", self.0))
+    }
+
+    async fn unwrap(&self, _wrapped: &[u8]) -> Result<Vec<u8>> {
+        Err(anyhow!("{}", self.0))
+    }
+}
+
+/// Encrypts the values of any other store
+pub struct EncryptedKVStore {
+    inner: Arc<dyn KVStore>,
+    master_key: Arc<dyn MasterKey>,
+    /// values stored before encryption was enabled are read as they are
+    read_plaintext: bool,
+}
+
+impl EncryptedKVStore {
+    pub fn with_master_key(
+        inner: Arc<dyn KVStore>,
+        master_key: Arc<dyn MasterKey>,
+    ) -> Self {
+        Self {
+            inner,
+            master_key,
+            read_plaintext: false,
+        }
+    }
+
+    /// `inner` encrypted with KV_ENCRYPTION_KEY, reading plaintext values
+    /// when KV_ENCRYPTION_MIGRATE=true. An invalid key is reported by the
+    /// config validation, the store then fails rather than store plaintext
+    pub fn from_env(inner: Arc<dyn KVStore>) -> Self {
+        let master_key: Arc<dyn MasterKey> = match LocalMasterKey::from_env()
+        {
+            Ok(Some(master_key)) => Arc::new(master_key),
+            Ok(None) => Arc::new(InvalidMasterKey(
+                "KV_ENCRYPTION_KEY is not set".to_string(),
+            )),
+            Err(e) => Arc::new(InvalidMasterKey(format!(
+                "Invalid KV_ENCRYPTION_KEY: {}",
+                e
+            ))),
+        };
+        let migrate = std::env::var("KV_ENCRYPTION_MIGRATE");
+        Self::with_master_key(inner, master_key)
+            .read_plaintext(matches!(migrate.as_deref(), Ok("true" | "1")))
+    }
+
+    /// Reads the values stored in plaintext instead of failing on them,
+    /// while a store is migrated to encryption
+    pub fn read_plaintext(mut self, read_plaintext: bool) -> Self {
+        self.read_plaintext = read_plaintext;
+        self
+    }
+
+    async fn encrypt(&self, key: &str, value: &str) -> Result<String> {
+        let data_key = rand::random::<[u8; 32]>();
+        let sealed = seal(&data_key, value.as_bytes(), key.as_bytes())?;
+        let wrapped = self.master_key.wrap(&data_key).await?;
+        Ok(format!(
+            "{}{}:{}:{}",
+            PREFIX,
+            self.master_key.id(),
+            BASE64_STANDARD.encode(wrapped),
+            BASE64_STANDARD.encode(sealed)
+        ))
+    }
+
+    async fn decrypt(&self, key: &str, stored: String) -> Result<String> {
+        let Some(envelope) = stored.strip_prefix(PREFIX) else {
+            if self.read_plaintext {
+                return Ok(stored);
+            }
+            return Err(anyhow!(
+                "{} is stored in plaintext, set KV_ENCRYPTION_MIGRATE=true to \
+                 read the values stored before encryption was enabled",
+                key
+            ));
+        };
+        let mut parts = envelope.splitn(3, ':');
+        let (Some(key_id), Some(wrapped), Some(sealed)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(anyhow!("Malformed encrypted value of {}", key));
+        };
+        if key_id != self.master_key.id() {
+            return Err(anyhow!(
+                "{} was encrypted with the master key {}",
+                key,
+                key_id
+            ));
+        }
+        let data_key = self
+            .master_key
+            .unwrap(&BASE64_STANDARD.decode(wrapped)?)
+            .await?;
+        let value = open(
+            &data_key,
+            &BASE64_STANDARD.decode(sealed)?,
+            key.as_bytes(),
+        )?;
+        Ok(String::from_utf8(value)?)
+    }
+}
+
+#[async_trait]
+impl KVStore for EncryptedKVStore {
+    /// In-memory store encrypted with the key from the environment
+    fn new() -> Self {
+        Self::from_env(Arc::new(InMemoryKVStore::new()))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        match self.inner.get(key).await? {
+            Some(stored) => Ok(Some(self.decrypt(key, stored).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn set(&self, key: &str, value: &str) -> Result<()> {
+        let encrypted = self.encrypt(key, value).await?;
+        self.inner.set(key, &encrypted).await
+    }
+
+    async fn set_with_ttl(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<()> {
+        let encrypted = self.encrypt(key, value).await?;
+        self.inner.set_with_ttl(key, &encrypted, ttl).await
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<bool> {
+        self.inner.expire(key, ttl).await
+    }
+
+    async fn scan_prefix(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let mut entries = Vec::new();
+        for (key, stored) in self.inner.scan_prefix(prefix).await? {
+            let value = self.decrypt(&key, stored).await?;
+            entries.push((key, value));
+        }
+        Ok(entries)
+    }
+
+    /// The ciphertexts differ for the same value, the stored one is
+    /// compared once decrypted and swapped as it is
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<&str>,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<bool> {
+        let stored = self.inner.get(key).await?;
+        let current = match stored.clone() {
+            Some(stored) => Some(self.decrypt(key, stored).await?),
+            None => None,
+        };
+        if current.as_deref() != expected {
+            return Ok(false);
+        }
+        let encrypted = self.encrypt(key, value).await?;
+        self.inner
+            .compare_and_set(key, stored.as_deref(), &encrypted, ttl)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(inner: Arc<dyn KVStore>) -> EncryptedKVStore {
+        EncryptedKVStore::with_master_key(
+            inner,
+            Arc::new(LocalMasterKey::new("test", [7; 32]).unwrap()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_values_are_encrypted() {
+        let inner: Arc<dyn KVStore> = Arc::new(InMemoryKVStore::new());
+        let store = store(inner.clone());
+        store.set("session:1", "secret token").await.unwrap();
+
+        let stored = inner.get("session:1").await.unwrap().unwrap();
+        assert!(stored.starts_with("enc:v1:test:"));
+        assert!(!stored.contains("secret"));
+        assert_eq!(
+            store.get("session:1").await.unwrap().as_deref(),
+            Some("secret token")
+        );
+
+        // a value moved to another key is rejected
+        inner.set("session:2", &stored).await.unwrap();
+        assert!(store.get("session:2").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_values_are_refused() {
+        let inner: Arc<dyn KVStore> = Arc::new(InMemoryKVStore::new());
+        inner.set("legacy", "{\"a\":1}").await.unwrap();
+        assert!(store(inner.clone()).get("legacy").await.is_err());
+        assert_eq!(
+            store(inner)
+                .read_plaintext(true)
+                .get("legacy")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("{\"a\":1}")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_key_fails() {
+        let store = EncryptedKVStore::with_master_key(
+            Arc::new(InMemoryKVStore::new()),
+            Arc::new(InvalidMasterKey("KV_ENCRYPTION_KEY is not set".into())),
+        );
+        assert!(store.set("session:1", "secret").await.is_err());
+    }
+}
@@ -0,0 +1,839 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+#[cfg(feature = "http")]
+use redis::AsyncCommands;
+use sqlx::migrate::Migrator;
+use sqlx::postgres::PgPool;
+use sqlx::Row;
+use tokio::sync::OnceCell;
+
+use crate::db::db_pool;
+use crate::kv_encryption::EncryptedKVStore;
+
+#[async_trait::async_trait]
+pub trait KVStore: Send + Sync {
+    fn new() -> Self
+    where
+        Self: Sized;
+    async fn get(&self, key: &str) -> Result<Option<String>>;
+    async fn set(&self, key: &str, value: &str) -> Result<()>;
+    /// Sets `key` to expire after `ttl`, a later `set` keeps it forever
+    async fn set_with_ttl(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<()>;
+    /// Sets a new `ttl` on an existing key, false when there is none
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<bool>;
+    /// Keys starting with `prefix` and their values, in no particular order
+    async fn scan_prefix(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<(String, String)>>;
+    /// Sets `key` to `value` only while it holds `expected` (while it is
+    /// absent for None), expiring after `ttl` when given. False when
+    /// another writer changed it first, see `update`
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<&str>,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<bool>;
+    /// Adds `delta` to the number at `key` (0 when absent), expiring after
+    /// `ttl` when given, and returns the sum. Backends add in one step and
+    /// keep the expiry without `ttl`, the default compares and sets until no
+    /// other writer got in between
+    async fn increment(
+        &self,
+        key: &str,
+        delta: f64,
+        ttl: Option<Duration>,
+    ) -> Result<f64> {
+        let sum = update(self, key, ttl, |current| {
+            let current = current.map(str::parse::<f64>).transpose()?;
+            Ok((current.unwrap_or_default() + delta).to_string())
+        })
+        .await?;
+        Ok(sum.parse()?)
+    }
+
+    async fn get_wallet(&self, user_id: &str) -> Result<Option<Wallet>> {
+        match self.get(&make_wallet_key(user_id)).await? {
+            Some(json_str) => {
+                let wallet: serde_json::Value =
+                    serde_json::from_str(&json_str)?;
+                Ok(Some(Wallet {
+                    wallet_address: wallet["wallet_address"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    wallet_id: wallet["wallet_id"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn set_wallet(&self, user_id: &str, wallet: Wallet) -> Result<()> {
+        let wallet_json = serde_json::to_string(&serde_json::json!({
+            "wallet_address": wallet.wallet_address,
+            "wallet_id": wallet.wallet_id,
+        }))?;
+        self.set(&make_wallet_key(user_id), &wallet_json).await
+    }
+}
+
+pub struct Wallet {
+    pub(crate) wallet_address: String,
+    pub(crate) wallet_id: String,
+}
+
+fn make_wallet_key(user_id: &str) -> String {
+    format!("wallet:solana:{}", user_id)
+}
+
+/// Writers racing on one key give up after that many lost rounds
+const UPDATE_ATTEMPTS: usize = 64;
+
+/// Read-modify-write of `key` that never loses a concurrent write: `f`
+/// gets the current value and returns the new one, it runs again when
+/// another writer got in between. An error of `f` aborts the update
+pub async fn update<S, F>(
+    store: &S,
+    key: &str,
+    ttl: Option<Duration>,
+    mut f: F,
+) -> Result<String>
+where
+    S: KVStore + ?Sized,
+    F: FnMut(Option<&str>) -> Result<String> + Send,
+{
+    for _ in 0..UPDATE_ATTEMPTS {
+        let current = store.get(key).await?;
+        let value = f(current.as_deref())?;
+        if store
+            .compare_and_set(key, current.as_deref(), &value, ttl)
+            .await?
+        {
+            return Ok(value);
+        }
+    }
+    Err(anyhow!("Too many concurrent writes to {}", key))
+}
+
+/// Sets `key` unless it is set, false when another writer set it first
+pub async fn set_if_absent(
+    store: &dyn KVStore,
+    key: &str,
+    value: &str,
+    ttl: Option<Duration>,
+) -> Result<bool> {
+    store.compare_and_set(key, None, value, ttl).await
+}
+
+/// Store shared by the crate (risk limits, volumes..), Postgres when
+/// KV_STORE=postgres, Redis when REDIS_URL is set, otherwise an in-memory
+/// store that is lost on restart. Values are encrypted when
+/// KV_ENCRYPTION_KEY is set
+pub static KV_STORE: Lazy<Arc<dyn KVStore>> = Lazy::new(|| {
+    let store = backend();
+    match std::env::var("KV_ENCRYPTION_KEY") {
+        Ok(_) => Arc::new(EncryptedKVStore::from_env(store)),
+        Err(_) => store,
+    }
+});
+
+fn backend() -> Arc<dyn KVStore> {
+    if std::env::var("KV_STORE").as_deref() == Ok("postgres") {
+        return Arc::new(PostgresKVStore::new());
+    }
+    #[cfg(feature = "http")]
+    if std::env::var("REDIS_URL").is_ok() {
+        return Arc::new(RedisKVStore::new());
+    }
+    Arc::new(InMemoryKVStore::new())
+}
+
+/// Keys of one subsystem (sessions, quotes, alerts..) in a shared store,
+/// prefixed with "namespace:" so they can't collide and can be scanned
+pub struct NamespacedKVStore {
+    inner: Arc<dyn KVStore>,
+    namespace: String,
+}
+
+impl NamespacedKVStore {
+    pub fn with_store(inner: Arc<dyn KVStore>, namespace: &str) -> Self {
+        Self {
+            inner,
+            namespace: format!("{}:", namespace),
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.namespace, key)
+    }
+}
+
+/// Namespace of the crate store
+pub fn namespaced(namespace: &str) -> NamespacedKVStore {
+    NamespacedKVStore::with_store(KV_STORE.clone(), namespace)
+}
+
+#[async_trait::async_trait]
+impl KVStore for NamespacedKVStore {
+    fn new() -> Self {
+        Self::with_store(Arc::new(InMemoryKVStore::new()), "default")
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        self.inner.get(&self.key(key)).await
+    }
+
+    async fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.inner.set(&self.key(key), value).await
+    }
+
+    async fn set_with_ttl(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<()> {
+        self.inner.set_with_ttl(&self.key(key), value, ttl).await
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<bool> {
+        self.inner.expire(&self.key(key), ttl).await
+    }
+
+    async fn scan_prefix(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let entries = self.inner.scan_prefix(&self.key(prefix)).await?;
+        Ok(entries
+            .into_iter()
+            .filter_map(|(key, value)| {
+                Some((key.strip_prefix(&self.namespace)?.to_string(), value))
+            })
+            .collect())
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<&str>,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<bool> {
+        self.inner
+            .compare_and_set(&self.key(key), expected, value, ttl)
+            .await
+    }
+
+    async fn increment(
+        &self,
+        key: &str,
+        delta: f64,
+        ttl: Option<Duration>,
+    ) -> Result<f64> {
+        self.inner.increment(&self.key(key), delta, ttl).await
+    }
+}
+
+#[cfg(feature = "http")]
+pub struct RedisKVStore {
+    client: redis::Client,
+}
+
+#[cfg(feature = "http")]
+#[async_trait::async_trait]
+impl KVStore for RedisKVStore {
+    fn new() -> Self {
+        let url = std::env::var("REDIS_URL")
+            .unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+        let client =
+            redis::Client::open(url).expect("Failed to connect to Redis");
+        Self { client }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        Ok(conn.get(key).await?)
+    }
+
+    async fn set(&self, key: &str, value: &str) -> Result<()> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let _: () = conn.set(key, value).await?;
+        Ok(())
+    }
+
+    async fn set_with_ttl(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<()> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let _: () = conn
+            .pset_ex(key, value, ttl.as_millis().max(1) as u64)
+            .await?;
+        Ok(())
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<bool> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        Ok(conn.pexpire(key, ttl.as_millis().max(1) as i64).await?)
+    }
+
+    async fn scan_prefix(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let pattern = format!("{}*", escape_glob(prefix));
+        let mut keys = Vec::new();
+        let mut cursor = 0u64;
+        loop {
+            let (next, batch): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(500)
+                .query_async(&mut conn)
+                .await?;
+            keys.extend(batch);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        // keys expiring between SCAN and MGET come back as nil
+        let values: Vec<Option<String>> =
+            redis::cmd("MGET").arg(&keys).query_async(&mut conn).await?;
+        Ok(keys
+            .into_iter()
+            .zip(values)
+            .filter_map(|(key, value)| Some((key, value?)))
+            .collect())
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<&str>,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<bool> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let set: i32 = COMPARE_AND_SET
+            .key(key)
+            .arg(if expected.is_some() { "1" } else { "0" })
+            .arg(expected.unwrap_or_default())
+            .arg(value)
+            .arg(ttl.map_or(0, |ttl| ttl.as_millis().max(1) as u64))
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(set == 1)
+    }
+
+    async fn increment(
+        &self,
+        key: &str,
+        delta: f64,
+        ttl: Option<Duration>,
+    ) -> Result<f64> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let sum: String = INCREMENT
+            .key(key)
+            .arg(delta)
+            .arg(ttl.map_or(0, |ttl| ttl.as_millis().max(1) as u64))
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(sum.parse()?)
+    }
+}
+
+/// INCRBYFLOAT and PEXPIRE in one step, ARGV is the delta and the TTL in
+/// ms (0 for none)
+#[cfg(feature = "http")]
+static INCREMENT: Lazy<redis::Script> = Lazy::new(|| {
+    redis::Script::new(
+        r"
+        local sum = redis.call('INCRBYFLOAT', KEYS[1], ARGV[1])
+        if ARGV[2] ~= '0' then
+            redis.call('PEXPIRE', KEYS[1], ARGV[2])
+        end
+        return sum
+        ",
+    )
+});
+
+/// GET, compare and SET in one step, ARGV is whether a value is expected,
+/// the expected value, the new one and its TTL in ms (0 for none)
+#[cfg(feature = "http")]
+static COMPARE_AND_SET: Lazy<redis::Script> = Lazy::new(|| {
+    redis::Script::new(
+        r"
+        local current = redis.call('GET', KEYS[1])
+        if ARGV[1] == '1' then
+            if current ~= ARGV[2] then return 0 end
+        elseif current then
+            return 0
+        end
+        if ARGV[4] == '0' then
+            redis.call('SET', KEYS[1], ARGV[3])
+        else
+            redis.call('SET', KEYS[1], ARGV[3], 'PX', ARGV[4])
+        end
+        return 1
+        ",
+    )
+});
+
+#[cfg(feature = "http")]
+fn escape_glob(prefix: &str) -> String {
+    prefix
+        .chars()
+        .flat_map(|c| match c {
+            '*' | '?' | '[' | ']' | '\\' => vec!['\\', c],
+            c => vec![c],
+        })
+        .collect()
+}
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Durable store for deployments without Redis, shares the pool of the
+/// wallet store and creates its table on first use
+pub struct PostgresKVStore {
+    /// the error of an invalid database.url, returned by every call
+    pool: Result<PgPool, String>,
+    migrated: OnceCell<()>,
+}
+
+impl PostgresKVStore {
+    pub fn with_pool(pool: PgPool) -> Self {
+        Self {
+            pool: Ok(pool),
+            migrated: OnceCell::new(),
+        }
+    }
+
+    async fn pool(&self) -> Result<&PgPool> {
+        let pool = self.pool.as_ref().map_err(|e| anyhow!("{}", e))?;
+        self.migrated
+            .get_or_try_init(|| async { MIGRATOR.run(pool).await })
+            .await?;
+        Ok(pool)
+    }
+}
+
+#[async_trait::async_trait]
+impl KVStore for PostgresKVStore {
+    fn new() -> Self {
+        Self {
+            pool: db_pool().map_err(|e| e.to_string()),
+            migrated: OnceCell::new(),
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(sqlx::query(&format!(
+            "SELECT value FROM kv_store WHERE key = $1 AND {}",
+            NOT_EXPIRED
+        ))
+        .bind(key)
+        .fetch_optional(self.pool().await?)
+        .await?
+        .map(|row| row.get("value")))
+    }
+
+    async fn set(&self, key: &str, value: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO kv_store (key, value) VALUES ($1, $2)
+             ON CONFLICT (key) DO UPDATE
+             SET value = EXCLUDED.value, expires_at = NULL, updated_at = now()",
+        )
+        .bind(key)
+        .bind(value)
+        .execute(self.pool().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn set_with_ttl(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO kv_store (key, value, expires_at)
+             VALUES ($1, $2, now() + make_interval(secs => $3))
+             ON CONFLICT (key) DO UPDATE
+             SET value = EXCLUDED.value, expires_at = EXCLUDED.expires_at,
+                 updated_at = now()",
+        )
+        .bind(key)
+        .bind(value)
+        .bind(ttl.as_secs_f64())
+        .execute(self.pool().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<bool> {
+        let updated = sqlx::query(&format!(
+            "UPDATE kv_store
+             SET expires_at = now() + make_interval(secs => $2)
+             WHERE key = $1 AND {}",
+            NOT_EXPIRED
+        ))
+        .bind(key)
+        .bind(ttl.as_secs_f64())
+        .execute(self.pool().await?)
+        .await?;
+        Ok(updated.rows_affected() > 0)
+    }
+
+    async fn scan_prefix(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let pool = self.pool().await?;
+        // expired rows are never read, clean them up while scanning anyway
+        sqlx::query("DELETE FROM kv_store WHERE expires_at <= now()")
+            .execute(pool)
+            .await?;
+        let pattern = format!(
+            "{}%",
+            prefix
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        Ok(sqlx::query(&format!(
+            "SELECT key, value FROM kv_store
+             WHERE key LIKE $1 ESCAPE '\\' AND {}",
+            NOT_EXPIRED
+        ))
+        .bind(pattern)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| (row.get("key"), row.get("value")))
+        .collect())
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<&str>,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<bool> {
+        let pool = self.pool().await?;
+        let ttl = ttl.map(|ttl| ttl.as_secs_f64());
+        let updated = match expected {
+            // an expired row counts as absent
+            None => {
+                sqlx::query(
+                    "INSERT INTO kv_store (key, value, expires_at)
+                     VALUES ($1, $2, now() + make_interval(secs => $3))
+                     ON CONFLICT (key) DO UPDATE
+                     SET value = EXCLUDED.value,
+                         expires_at = EXCLUDED.expires_at,
+                         updated_at = now()
+                     WHERE kv_store.expires_at <= now()",
+                )
+                .bind(key)
+                .bind(value)
+                .bind(ttl)
+                .execute(pool)
+                .await?
+            }
+            Some(expected) => {
+                sqlx::query(&format!(
+                    "UPDATE kv_store
+                     SET value = $2, updated_at = now(),
+                         expires_at = now() + make_interval(secs => $3)
+                     WHERE key = $1 AND value = $4 AND {}",
+                    NOT_EXPIRED
+                ))
+                .bind(key)
+                .bind(value)
+                .bind(ttl)
+                .bind(expected)
+                .execute(pool)
+                .await?
+            }
+        };
+        Ok(updated.rows_affected() > 0)
+    }
+
+    async fn increment(
+        &self,
+        key: &str,
+        delta: f64,
+        ttl: Option<Duration>,
+    ) -> Result<f64> {
+        // an expired row counts as 0
+        let sum: String = sqlx::query(
+            "INSERT INTO kv_store (key, value, expires_at)
+             VALUES ($1, $2::float8::text, now() + make_interval(secs => $3))
+             ON CONFLICT (key) DO UPDATE
+             SET value = ((CASE WHEN kv_store.expires_at <= now() THEN 0
+                                ELSE kv_store.value::float8 END) + $2)::text,
+                 expires_at = CASE WHEN kv_store.expires_at <= now()
+                                   THEN EXCLUDED.expires_at
+                                   ELSE COALESCE(EXCLUDED.expires_at,
+                                                 kv_store.expires_at) END,
+                 updated_at = now()
+             RETURNING value",
+        )
+        .bind(key)
+        .bind(delta)
+        .bind(ttl.map(|ttl| ttl.as_secs_f64()))
+        .fetch_one(self.pool().await?)
+        .await?
+        .get("value");
+        Ok(sum.parse()?)
+    }
+}
+
+const NOT_EXPIRED: &str = "(expires_at IS NULL OR expires_at > now())";
+
+/// Values and when they expire
+type Entries = HashMap<String, (String, Option<Instant>)>;
+
+#[derive(Default)]
+pub struct InMemoryKVStore {
+    values: Mutex<Entries>,
+}
+
+impl InMemoryKVStore {
+    /// Entries without the expired ones
+    fn live(&self) -> MutexGuard<'_, Entries> {
+        let mut values = self.values.lock().unwrap();
+        let now = Instant::now();
+        values.retain(|_, (_, expires)| expires.is_none_or(|at| at > now));
+        values
+    }
+}
+
+#[async_trait::async_trait]
+impl KVStore for InMemoryKVStore {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.live().get(key).map(|(value, _)| value.clone()))
+    }
+
+    async fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.live()
+            .insert(key.to_string(), (value.to_string(), None));
+        Ok(())
+    }
+
+    async fn set_with_ttl(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<()> {
+        self.live().insert(
+            key.to_string(),
+            (value.to_string(), Some(Instant::now() + ttl)),
+        );
+        Ok(())
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<bool> {
+        Ok(match self.live().get_mut(key) {
+            Some((_, expires)) => {
+                *expires = Some(Instant::now() + ttl);
+                true
+            }
+            None => false,
+        })
+    }
+
+    async fn scan_prefix(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<(String, String)>> {
+        Ok(self
+            .live()
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, (value, _))| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<&str>,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<bool> {
+        let mut values = self.live();
+        if values.get(key).map(|(value, _)| value.as_str()) != expected {
+            return Ok(false);
+        }
+        let expires = ttl.map(|ttl| Instant::now() + ttl);
+        values.insert(key.to_string(), (value.to_string(), expires));
+        Ok(true)
+    }
+
+    async fn increment(
+        &self,
+        key: &str,
+        delta: f64,
+        ttl: Option<Duration>,
+    ) -> Result<f64> {
+        let mut values = self.live();
+        let (current, expires) = match values.get(key) {
+            Some((value, expires)) => (value.parse::<f64>()?, *expires),
+            None => (0., None),
+        };
+        let expires = ttl.map(|ttl| Instant::now() + ttl).or(expires);
+        let sum = current + delta;
+        values.insert(key.to_string(), (sum.to_string(), expires));
+        Ok(sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_encryption::LocalMasterKey;
+
+    #[tokio::test]
+    async fn test_ttl() {
+        let store = InMemoryKVStore::new();
+        store
+            .set_with_ttl("quote:1", "a", Duration::from_millis(20))
+            .await
+            .unwrap();
+        store.set("quote:2", "b").await.unwrap();
+        assert!(store.expire("quote:2", Duration::ZERO).await.unwrap());
+        assert!(!store.expire("quote:3", Duration::ZERO).await.unwrap());
+        assert_eq!(store.get("quote:1").await.unwrap().as_deref(), Some("a"));
+        assert_eq!(store.get("quote:2").await.unwrap(), None);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(store.get("quote:1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_namespaces() {
+        let inner: Arc<dyn KVStore> = Arc::new(InMemoryKVStore::new());
+        let alerts = NamespacedKVStore::with_store(inner.clone(), "alerts");
+        let sessions =
+            NamespacedKVStore::with_store(inner.clone(), "sessions");
+        alerts.set("user:1", "a").await.unwrap();
+        alerts.set("user:2", "b").await.unwrap();
+        sessions.set("user:1", "c").await.unwrap();
+
+        assert_eq!(
+            inner.get("alerts:user:1").await.unwrap().as_deref(),
+            Some("a")
+        );
+        let mut scanned = alerts.scan_prefix("user:").await.unwrap();
+        scanned.sort();
+        assert_eq!(
+            scanned,
+            [
+                ("user:1".to_string(), "a".to_string()),
+                ("user:2".to_string(), "b".to_string())
+            ]
+        );
+        assert_eq!(sessions.scan_prefix("").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_compare_and_set() {
+        let store: Arc<dyn KVStore> = Arc::new(InMemoryKVStore::new());
+        assert!(set_if_absent(store.as_ref(), "k", "a", None).await.unwrap());
+        assert!(!set_if_absent(store.as_ref(), "k", "b", None)
+            .await
+            .unwrap());
+        assert!(!store
+            .compare_and_set("k", Some("b"), "c", None)
+            .await
+            .unwrap());
+        assert!(store
+            .compare_and_set("k", Some("a"), "c", None)
+            .await
+            .unwrap());
+
+        // concurrent increments all land, natively and compared and set
+        let namespaced: Arc<dyn KVStore> =
+            Arc::new(NamespacedKVStore::with_store(store.clone(), "ns"));
+        let encrypted: Arc<dyn KVStore> =
+            Arc::new(EncryptedKVStore::with_master_key(
+                store.clone(),
+                Arc::new(LocalMasterKey::new("test", [7; 32]).unwrap()),
+            ));
+        let adds = (0..20).flat_map(|_| {
+            [namespaced.clone(), encrypted.clone()].map(|store| {
+                tokio::spawn(async move {
+                    store.increment("n", 1.5, None).await.unwrap()
+                })
+            })
+        });
+        futures::future::join_all(adds).await;
+        assert_eq!(namespaced.get("n").await.unwrap().as_deref(), Some("30"));
+        assert_eq!(encrypted.get("n").await.unwrap().as_deref(), Some("30"));
+        let capped = update(store.as_ref(), "n", None, |_| {
+            Err(anyhow!("over the cap"))
+        })
+        .await;
+        assert!(capped.is_err());
+    }
+
+    /// An increment without a TTL keeps the one the key has
+    async fn check_increment_keeps_ttl(store: &dyn KVStore) {
+        let key = "test:increment_ttl";
+        store.expire(key, Duration::ZERO).await.unwrap();
+        let ttl = Some(Duration::from_millis(500));
+        assert_eq!(store.increment(key, 1., ttl).await.unwrap(), 1.);
+        assert_eq!(store.increment(key, 2., None).await.unwrap(), 3.);
+        assert_eq!(store.get(key).await.unwrap().as_deref(), Some("3"));
+
+        tokio::time::sleep(Duration::from_millis(700)).await;
+        assert_eq!(store.get(key).await.unwrap(), None);
+        // an expired sum starts over, without the old TTL
+        assert_eq!(store.increment(key, 1., None).await.unwrap(), 1.);
+        assert!(store.expire(key, Duration::ZERO).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_increment_keeps_ttl() {
+        check_increment_keeps_ttl(&InMemoryKVStore::new()).await;
+    }
+
+    /// Needs a Postgres database.url
+    #[tokio::test]
+    #[ignore]
+    async fn test_postgres_increment_keeps_ttl() {
+        check_increment_keeps_ttl(&PostgresKVStore::new()).await;
+    }
+}
@@ -1,33 +1,95 @@
-#[cfg(feature = "http")]
 pub mod http;
 
-// #[cfg(feature = "solana")]
-pub mod solana;
-
-#[cfg(feature = "evm")]
-pub mod evm;
-
 #[cfg(feature = "http")]
 pub mod wallet_manager;
 
+pub mod access;
+pub mod activity;
+pub mod address;
+pub mod agent;
+pub mod alerts;
+pub mod amount;
+pub mod audit;
+pub mod automation;
+pub mod backtest;
+pub mod cancellation;
+pub mod chains;
+pub mod circuit_breaker;
 pub mod common;
+pub mod config;
+pub mod confirmation;
+pub mod costs;
 pub mod cross_chain;
+pub mod db;
+pub mod dedup;
+pub mod deposits;
 pub mod dexscreener;
+pub mod dispatch;
+pub mod error;
+pub mod evm;
+pub mod execution;
+pub mod explain;
+pub mod fee_estimate;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod fixtures;
+pub mod freeze;
+pub mod funds;
+pub mod gas_tank;
+pub mod health;
+pub mod history;
+pub mod jobs;
+pub mod kv_encryption;
+pub mod kv_store;
+pub mod mcp;
+pub mod memory;
+pub mod metrics;
+pub mod notify;
+pub mod outbox;
+pub mod paper_trading;
+pub mod portfolio;
+pub mod position_sizing;
+pub mod preferences;
+pub mod preview;
+pub mod pricing;
+pub mod progress;
+pub mod quotes;
+pub mod rate_limit;
 pub mod reasoning_loop;
+pub mod render;
+pub mod revenue;
+pub mod risk;
+pub mod scheduler;
+pub mod screening;
+pub mod shutdown;
 pub mod signer;
+#[cfg(test)]
+mod snapshot;
+pub mod solana;
+pub mod sonic;
+pub mod spenders;
+pub mod token_search;
+pub mod tool_error;
+pub mod trade_plan;
+pub mod trending;
+pub mod triggers;
+pub mod twap;
+pub mod validation;
 
 #[ctor::ctor]
 fn init() {
     dotenv::dotenv().ok();
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| {
-                    tracing_subscriber::EnvFilter::new("info")
-                        .add_directive("listen_kit=info".parse().unwrap())
-                }),
-        )
-        .with_test_writer()
-        .try_init()
-        .ok();
+    let subscriber = tracing_subscriber::fmt().with_env_filter(
+        tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(
+            |_| {
+                tracing_subscriber::EnvFilter::new("info")
+                    .add_directive("listen_kit=info".parse().unwrap())
+            },
+        ),
+    );
+    // stdout carries the protocol of `mcp --stdio`, logs never go there
+    #[cfg(test)]
+    let subscriber = subscriber.with_test_writer();
+    #[cfg(not(test))]
+    let subscriber = subscriber.with_writer(std::io::stderr);
+    subscriber.try_init().ok();
 }
@@ -0,0 +1,259 @@
+//! Model Context Protocol server for the tool set, lets MCP clients (Claude
+//! Desktop etc.) call the tools directly without the rig agent loop. The
+//! JSON schemas of the tools are the ones `#[tool]` generates from the
+//! function signatures. The JSON-RPC handling and the stdio transport
+//! (`mcp --stdio`) live here, the SSE transport is served by the http module
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use rig::tool::{Tool, ToolDyn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::agent::{AgentBuilder, AgentKind};
+use crate::chains::CHAINS;
+use crate::common::ToolRegistry;
+use crate::dispatch::dispatch;
+
+pub const PROTOCOL_VERSION: &str = "2024-11-05";
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+#[derive(Debug, Deserialize)]
+pub struct JsonRpcRequest {
+    /// None for notifications, those get no response
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct CallToolParams {
+    name: String,
+    #[serde(default)]
+    arguments: Option<Value>,
+}
+
+#[derive(Default)]
+pub struct McpServer {
+    tools: BTreeMap<String, Box<dyn ToolDyn>>,
+}
+
+impl ToolRegistry for McpServer {
+    fn tool<T: Tool + 'static>(mut self, tool: T) -> Self {
+        self.tools.insert(T::NAME.to_string(), Box::new(tool));
+        self
+    }
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+impl McpServer {
+    /// Server with the tools of every chain enabled in the deployment,
+    /// selected by agents.tools and agents.disabled_tools like the agents
+    pub fn with_all_tools() -> Self {
+        AgentKind::ALL
+            .iter()
+            .filter(|kind| {
+                kind.chain().map_or(true, |c| CHAINS.is_enabled(c))
+            })
+            .fold(Self::default(), |server, &kind| {
+                AgentBuilder::new(kind).register(server)
+            })
+    }
+
+    pub async fn list_tools(&self) -> Vec<Value> {
+        let mut tools = vec![];
+        for tool in self.tools.values() {
+            let definition = tool.definition(String::new()).await;
+            tools.push(json!({
+                "name": definition.name,
+                "description": definition.description,
+                "inputSchema": definition.parameters,
+            }));
+        }
+        tools
+    }
+
+    /// Calls the tool through the regular dispatch, tool failures are
+    /// results with isError set as the spec asks, not protocol errors
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Value {
+        let result = match self.tools.get(name) {
+            Some(tool) => {
+                dispatch(name, arguments.to_string(), |args| tool.call(args))
+                    .await
+            }
+            None => Err(anyhow!("Tool {} not found", name)),
+        };
+        let (text, is_error) = match result {
+            Ok(result) => (result, false),
+            Err(e) => (e.to_string(), true),
+        };
+        json!({
+            "content": [{ "type": "text", "text": text }],
+            "isError": is_error,
+        })
+    }
+
+    /// Handles a single JSON-RPC message, returns the response to send back
+    /// if any
+    pub async fn handle(&self, message: Value) -> Option<Value> {
+        let request: JsonRpcRequest = match serde_json::from_value(message) {
+            Ok(request) => request,
+            Err(e) => {
+                return Some(error_response(
+                    Value::Null,
+                    PARSE_ERROR,
+                    e.to_string(),
+                ))
+            }
+        };
+        let id = request.id?;
+
+        let result = match request.method.as_str() {
+            "initialize" => json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": { "tools": {} },
+                "serverInfo": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+            }),
+            "ping" => json!({}),
+            "tools/list" => json!({ "tools": self.list_tools().await }),
+            "tools/call" => {
+                let params: CallToolParams =
+                    match serde_json::from_value(request.params) {
+                        Ok(params) => params,
+                        Err(e) => {
+                            return Some(error_response(
+                                id,
+                                INVALID_PARAMS,
+                                e.to_string(),
+                            ))
+                        }
+                    };
+                let arguments = params.arguments.unwrap_or_else(|| json!({}));
+                self.call_tool(&params.name, arguments).await
+            }
+            method => {
+                return Some(error_response(
+                    id,
+                    METHOD_NOT_FOUND,
+                    format!("Method {} not found", method),
+                ))
+            }
+        };
+
+        Some(json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+    }
+
+    /// stdio transport: one JSON-RPC message per line of `input`, handled
+    /// in order, the responses one per line of `output`. Returns once the
+    /// client closes `input`
+    pub async fn serve_lines<R, W>(
+        &self,
+        input: R,
+        mut output: W,
+    ) -> Result<()>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut lines = input.lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str(&line) {
+                Ok(message) => self.handle(message).await,
+                Err(e) => Some(error_response(
+                    Value::Null,
+                    PARSE_ERROR,
+                    e.to_string(),
+                )),
+            };
+            if let Some(response) = response {
+                output
+                    .write_all(format!("{}\n", response).as_bytes())
+                    .await?;
+                output.flush().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_handle() {
+        let server = McpServer::default();
+
+        let request =
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize" });
+        let response = server.handle(request).await.unwrap();
+        assert_eq!(response["result"]["protocolVersion"], PROTOCOL_VERSION);
+
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/initialized",
+        });
+        assert!(server.handle(notification).await.is_none());
+
+        let request =
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "foo/list" });
+        let response = server.handle(request).await.unwrap();
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+
+        let response = server
+            .handle(json!({
+                "jsonrpc": "2.0",
+                "id": 3,
+                "method": "tools/call",
+                "params": { "name": "missing" },
+            }))
+            .await
+            .unwrap();
+        assert_eq!(response["result"]["isError"], true);
+    }
+
+    #[tokio::test]
+    async fn test_serve_lines() {
+        let input = concat!(
+            r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#,
+            "\n\n",
+            r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+            "\nnot json\n",
+            r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#,
+            "\n",
+        );
+        let mut output = vec![];
+        McpServer::default()
+            .serve_lines(input.as_bytes(), &mut output)
+            .await
+            .unwrap();
+
+        let responses = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect::<Vec<Value>>();
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[1]["error"]["code"], PARSE_ERROR);
+        assert_eq!(responses[2]["result"]["tools"], json!([]));
+    }
+}
@@ -0,0 +1,225 @@
+//! Per-session memory of recent tool results, persisted in the kv store.
+//! Every dispatched tool call is recorded so later calls can refer to an
+//! earlier result ("execute that quote", "sell the token we just bought")
+//! without the LLM re-supplying ids or addresses
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::kv_store::{KVStore, KV_STORE};
+use crate::signer::{SignerContext, TransactionSigner};
+
+const MAX_ENTRIES: usize = 20;
+/// Results are truncated so a portfolio dump does not bloat the store
+const MAX_RESULT_LEN: usize = 4000;
+
+/// Id used by tools to refer to the most recent matching result
+pub const LAST: &str = "last";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryEntry {
+    pub tool: String,
+    pub args: Value,
+    pub result: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl MemoryEntry {
+    /// The result as JSON, tools returning a JSON string get unwrapped
+    pub fn result_json(&self) -> Option<Value> {
+        match serde_json::from_str(&self.result).ok()? {
+            Value::String(inner) => serde_json::from_str(&inner).ok(),
+            value => Some(value),
+        }
+    }
+}
+
+pub struct SessionMemory {
+    store: Arc<dyn KVStore>,
+}
+
+pub static MEMORY: Lazy<SessionMemory> =
+    Lazy::new(|| SessionMemory::new(KV_STORE.clone()));
+
+impl SessionMemory {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self { store }
+    }
+
+    fn key(session: &str) -> String {
+        format!("memory:{}", session)
+    }
+
+    /// Entries of the session, newest first
+    pub async fn entries(&self, session: &str) -> Result<Vec<MemoryEntry>> {
+        match self.store.get(&Self::key(session)).await? {
+            Some(entries) => Ok(serde_json::from_str(&entries)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    pub async fn record(
+        &self,
+        session: &str,
+        mut entry: MemoryEntry,
+    ) -> Result<()> {
+        if entry.result.len() > MAX_RESULT_LEN {
+            let mut end = MAX_RESULT_LEN;
+            while !entry.result.is_char_boundary(end) {
+                end -= 1;
+            }
+            entry.result.truncate(end);
+        }
+
+        let mut entries = self.entries(session).await?;
+        entries.insert(0, entry);
+        entries.truncate(MAX_ENTRIES);
+        self.store
+            .set(&Self::key(session), &serde_json::to_string(&entries)?)
+            .await
+    }
+
+    /// Walks the entries newest first and returns the first value `find`
+    /// extracts
+    pub async fn find_last<T>(
+        &self,
+        session: &str,
+        find: impl Fn(&MemoryEntry) -> Option<T>,
+    ) -> Result<Option<T>> {
+        Ok(self.entries(session).await?.iter().find_map(find))
+    }
+}
+
+/// Session of the signer, falls back to the user and then to a single
+/// local session for signers without either
+pub fn session_key(signer: &dyn TransactionSigner) -> String {
+    signer
+        .session_id()
+        .or_else(|| signer.user_id())
+        .unwrap_or_else(|| "local".to_string())
+}
+
+async fn current_session() -> String {
+    session_key(SignerContext::current().await.as_ref())
+}
+
+/// Records a tool call of the current session, failures are only logged as
+/// memory is best effort
+pub async fn record_tool_result(tool: &str, args: &str, result: &str) {
+    let Some(signer) = SignerContext::try_current() else {
+        return;
+    };
+    let entry = MemoryEntry {
+        tool: tool.to_string(),
+        args: serde_json::from_str(args).unwrap_or(Value::Null),
+        result: result.to_string(),
+        created_at: Utc::now(),
+    };
+    if let Err(e) = MEMORY.record(&session_key(signer.as_ref()), entry).await
+    {
+        tracing::warn!(?e, tool, "failed to record tool result");
+    }
+}
+
+/// Id of the most recent quote returned by a quote_* tool
+pub async fn last_quote_id() -> Result<String> {
+    MEMORY
+        .find_last(&current_session().await, |entry| {
+            if !entry.tool.starts_with("quote_") {
+                return None;
+            }
+            entry.result_json()?["id"].as_str().map(str::to_string)
+        })
+        .await?
+        .ok_or_else(|| anyhow!("No recent quote found"))
+}
+
+/// Id of the most recent action that was parked for confirmation
+pub async fn last_pending_action_id() -> Result<String> {
+    MEMORY
+        .find_last(&current_session().await, |entry| {
+            let result = entry.result_json()?;
+            if result["status"] != "confirmation_required" {
+                return None;
+            }
+            result["action"]["id"].as_str().map(str::to_string)
+        })
+        .await?
+        .ok_or_else(|| anyhow!("No recent pending action found"))
+}
+
+#[tool(description = "
+Returns the most recent tool calls of this conversation with their arguments
+and results, newest first. Use it to look up addresses, mints, amounts or ids
+from earlier steps (e.g. the token that was just bought) instead of asking the
+user again.
+
+tool filters by the tool name, pass an empty string for all tools
+")]
+pub async fn recall_tool_results(tool: String) -> Result<Vec<MemoryEntry>> {
+    let entries = MEMORY.entries(&current_session().await).await?;
+    Ok(entries
+        .into_iter()
+        .filter(|entry| tool.is_empty() || entry.tool == tool)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    fn entry(tool: &str, result: Value) -> MemoryEntry {
+        MemoryEntry {
+            tool: tool.to_string(),
+            args: Value::Null,
+            result: serde_json::to_string(&result).unwrap(),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_last_quote() {
+        let memory = SessionMemory::new(Arc::new(InMemoryKVStore::new()));
+        for id in ["first", "second"] {
+            memory
+                .record(
+                    "session",
+                    entry(
+                        "quote_jupiter_swap",
+                        serde_json::json!({"id": id}),
+                    ),
+                )
+                .await
+                .unwrap();
+        }
+        memory
+            .record("session", entry("get_portfolio", Value::Array(vec![])))
+            .await
+            .unwrap();
+
+        let last = memory
+            .find_last("session", |entry| {
+                if !entry.tool.starts_with("quote_") {
+                    return None;
+                }
+                entry.result_json()?["id"].as_str().map(str::to_string)
+            })
+            .await
+            .unwrap();
+        assert_eq!(last.as_deref(), Some("second"));
+        assert!(memory.entries("other").await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_result_json_unwraps_strings() {
+        let pending = serde_json::json!({"status": "confirmation_required"});
+        let entry = entry("trade", Value::String(pending.to_string()));
+        assert_eq!(entry.result_json(), Some(pending));
+    }
+}
@@ -0,0 +1,258 @@
+//! Prometheus metrics of the agent: tool invocations by outcome, tool, sign
+//! and external API latencies and API error rates. Kept in memory and
+//! rendered in the text exposition format by the `/metrics` endpoint
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+
+use crate::circuit_breaker::{is_outage, BREAKERS};
+use crate::config::config;
+
+pub const TOOL_CALLS: &str = "listen_tool_calls_total";
+pub const TOOL_DURATION: &str = "listen_tool_call_duration_seconds";
+pub const SIGN_DURATION: &str = "listen_sign_duration_seconds";
+pub const API_REQUESTS: &str = "listen_api_requests_total";
+pub const API_DURATION: &str = "listen_api_request_duration_seconds";
+pub const QUOTE_PREFETCH: &str = "listen_quote_prefetch_total";
+pub const OUTBOX: &str = "listen_outbox_transactions_total";
+pub const HTTP_RETRIES: &str = "listen_http_retries_total";
+
+const HELP: &[(&str, &str)] = &[
+    (
+        TOOL_CALLS,
+        "Tool invocations by tool and outcome (ok or error code)",
+    ),
+    (TOOL_DURATION, "Tool invocation latency"),
+    (SIGN_DURATION, "Latency of signing and sending transactions"),
+    (
+        API_REQUESTS,
+        "External API requests by api and outcome (ok, error or rejected by \
+         the circuit breaker)",
+    ),
+    (API_DURATION, "External API request latency"),
+    (
+        QUOTE_PREFETCH,
+        "Swap quotes served prefetched (hit) or fetched inline (miss)",
+    ),
+    (
+        OUTBOX,
+        "Outbox transactions by chain and outcome (landed, failed, expired \
+         or retried)",
+    ),
+    (HTTP_RETRIES, "Retries of outbound HTTP requests by host"),
+];
+
+const BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10., 30., 60.];
+
+type Labels = Vec<(&'static str, String)>;
+
+struct Histogram {
+    /// non-cumulative counts per bucket, the last one is +Inf
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets: vec![0; BUCKETS.len() + 1],
+            sum: 0.,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<BTreeMap<(&'static str, Labels), u64>>,
+    histograms: Mutex<BTreeMap<(&'static str, Labels), Histogram>>,
+}
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+fn labels(labels: &[(&'static str, &str)]) -> Labels {
+    labels.iter().map(|(k, v)| (*k, v.to_string())).collect()
+}
+
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let escape = |v: &str| {
+        v.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    };
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str) {
+    let help = HELP
+        .iter()
+        .find(|(metric, _)| *metric == name)
+        .map_or("", |(_, help)| help);
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+impl Metrics {
+    pub fn inc(&self, name: &'static str, l: &[(&'static str, &str)]) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry((name, labels(l)))
+            .or_default() += 1;
+    }
+
+    pub fn observe(
+        &self,
+        name: &'static str,
+        l: &[(&'static str, &str)],
+        duration: Duration,
+    ) {
+        self.histograms
+            .lock()
+            .unwrap()
+            .entry((name, labels(l)))
+            .or_insert_with(Histogram::new)
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Text exposition format, series of a metric are grouped under its
+    /// header as the keys are sorted by name first
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let mut last = None;
+        for ((name, labels), value) in self.counters.lock().unwrap().iter() {
+            if last != Some(*name) {
+                write_header(&mut out, name, "counter");
+                last = Some(*name);
+            }
+            let _ = writeln!(
+                out,
+                "{}{} {}",
+                name,
+                format_labels(labels, None),
+                value
+            );
+        }
+
+        let mut last = None;
+        for ((name, labels), histogram) in
+            self.histograms.lock().unwrap().iter()
+        {
+            if last != Some(*name) {
+                write_header(&mut out, name, "histogram");
+                last = Some(*name);
+            }
+            let mut cumulative = 0;
+            for (i, count) in histogram.buckets.iter().enumerate() {
+                cumulative += count;
+                let le = BUCKETS
+                    .get(i)
+                    .map_or("+Inf".to_string(), |bound| bound.to_string());
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{} {}",
+                    name,
+                    format_labels(labels, Some(&le)),
+                    cumulative
+                );
+            }
+            let labels = format_labels(labels, None);
+            let _ = writeln!(out, "{}_sum{} {}", name, labels, histogram.sum);
+            let _ =
+                writeln!(out, "{}_count{} {}", name, labels, histogram.count);
+        }
+
+        out
+    }
+}
+
+pub fn record_tool_call(tool: &str, outcome: &str, duration: Duration) {
+    METRICS.inc(TOOL_CALLS, &[("tool", tool), ("outcome", outcome)]);
+    METRICS.observe(TOOL_DURATION, &[("tool", tool)], duration);
+}
+
+pub fn record_sign(chain: &str, duration: Duration) {
+    METRICS.observe(SIGN_DURATION, &[("chain", chain)], duration);
+}
+
+/// Runs a request to an external API (privy, jupiter, lifi..) recording its
+/// latency and outcome, behind the circuit breaker of the API
+pub async fn observe_api<T>(
+    api: &'static str,
+    request: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let config = config();
+    if let Err(e) = BREAKERS.check(api, &config.breakers) {
+        METRICS.inc(API_REQUESTS, &[("api", api), ("outcome", "rejected")]);
+        return Err(e.into());
+    }
+    let started = Instant::now();
+    let result = request.await;
+    let outage = matches!(&result, Err(e) if is_outage(e));
+    BREAKERS.record(api, outage, &config.breakers);
+    let outcome = if result.is_ok() { "ok" } else { "error" };
+    METRICS.inc(API_REQUESTS, &[("api", api), ("outcome", outcome)]);
+    METRICS.observe(API_DURATION, &[("api", api)], started.elapsed());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.inc(TOOL_CALLS, &[("tool", "trade"), ("outcome", "ok")]);
+        metrics.inc(TOOL_CALLS, &[("tool", "trade"), ("outcome", "ok")]);
+        metrics.observe(
+            API_DURATION,
+            &[("api", "lifi")],
+            Duration::from_millis(200),
+        );
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE listen_tool_calls_total counter"));
+        assert!(rendered.contains(
+            "listen_tool_calls_total{tool=\"trade\",outcome=\"ok\"} 2"
+        ));
+        assert!(rendered.contains(
+            "listen_api_request_duration_seconds_bucket{api=\"lifi\",le=\"0.1\"} 0"
+        ));
+        assert!(rendered.contains(
+            "listen_api_request_duration_seconds_bucket{api=\"lifi\",le=\"0.25\"} 1"
+        ));
+        assert!(rendered.contains(
+            "listen_api_request_duration_seconds_count{api=\"lifi\"} 1"
+        ));
+    }
+}
@@ -0,0 +1,412 @@
+//! Delivery of notifications out of the agent: fired price alerts, bridge
+//! transfers changing status, actions waiting for a confirmation and
+//! deposits to the watched wallets. The
+//! subsystems hand them to `NOTIFIER` which fans them out to the
+//! `NotificationSink`s of the user they are about. Host applications
+//! register the sinks of each user, the Telegram, webhook and email ones
+//! configured from the environment serve the local signer
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use crate::common::http_client;
+
+const SENDGRID_URL: &str = "https://api.sendgrid.com/v3/mail/send";
+pub const SIGNATURE_HEADER: &str = "x-listen-signature";
+pub const TIMESTAMP_HEADER: &str = "x-listen-timestamp";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    PriceAlert,
+    BridgeStatus,
+    ConfirmationRequired,
+    Deposit,
+    /// a transaction of the agent settled, sent to the activity webhook
+    Transaction,
+    /// a pump.fun launch matched a watch of the user
+    Launch,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    /// user the notification is about, None for local signers
+    pub user_id: Option<String>,
+    pub title: String,
+    pub body: String,
+    pub data: Value,
+}
+
+impl Notification {
+    pub fn new(
+        kind: NotificationKind,
+        user_id: Option<String>,
+        title: impl Into<String>,
+        body: impl Into<String>,
+    ) -> Self {
+        Self {
+            kind,
+            user_id,
+            title: title.into(),
+            body: body.into(),
+            data: Value::Null,
+        }
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = data;
+        self
+    }
+
+    fn text(&self) -> String {
+        format!("{}\n\n{}", self.title, self.body)
+    }
+}
+
+#[async_trait]
+pub trait NotificationSink: Send + Sync {
+    fn name(&self) -> &str;
+    async fn send(&self, notification: &Notification) -> Result<()>;
+}
+
+async fn check(response: reqwest::Response) -> Result<()> {
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Request failed with status code {}, {}",
+            response.status(),
+            response.text().await?
+        ));
+    }
+    Ok(())
+}
+
+/// Messages a chat through a Telegram bot
+pub struct TelegramSink {
+    client: Client,
+    bot_token: String,
+    chat_id: String,
+}
+
+impl TelegramSink {
+    pub fn new(bot_token: String, chat_id: String) -> Self {
+        Self {
+            client: http_client(),
+            bot_token,
+            chat_id,
+        }
+    }
+}
+
+#[async_trait]
+impl NotificationSink for TelegramSink {
+    fn name(&self) -> &str {
+        "telegram"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        let url = format!(
+            "https://api.telegram.org/bot{}/sendMessage",
+            self.bot_token
+        );
+        let body = json!({
+            "chat_id": self.chat_id,
+            "text": notification.text(),
+        });
+        check(self.client.post(url).json(&body).send().await?).await
+    }
+}
+
+/// Signature of a webhook body sent at `timestamp` (unix seconds): hex of
+/// the HMAC-SHA256 of "{timestamp}.{body}" keyed with the secret. Receivers
+/// recompute it to check the sender and reject old timestamps
+pub fn sign_webhook(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC takes keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// POSTs the notification as JSON, signed in the SIGNATURE_HEADER and
+/// TIMESTAMP_HEADER headers when it has a secret
+pub struct WebhookSink {
+    client: Client,
+    url: String,
+    secret: Option<String>,
+}
+
+impl WebhookSink {
+    pub fn new(url: String) -> Self {
+        Self {
+            client: http_client(),
+            url,
+            secret: None,
+        }
+    }
+
+    pub fn with_secret(mut self, secret: String) -> Self {
+        self.secret = Some(secret);
+        self
+    }
+}
+
+#[async_trait]
+impl NotificationSink for WebhookSink {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        let body = serde_json::to_vec(notification)?;
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("content-type", "application/json");
+        if let Some(secret) = &self.secret {
+            let timestamp = chrono::Utc::now().timestamp();
+            request = request
+                .header(
+                    SIGNATURE_HEADER,
+                    sign_webhook(secret, timestamp, &body),
+                )
+                .header(TIMESTAMP_HEADER, timestamp);
+        }
+        check(request.body(body).send().await?).await
+    }
+}
+
+/// Emails through the SendGrid mail API, or any API taking the same payload
+pub struct EmailSink {
+    client: Client,
+    api_url: String,
+    api_key: String,
+    from: String,
+    to: String,
+}
+
+impl EmailSink {
+    pub fn new(api_key: String, from: String, to: String) -> Self {
+        Self {
+            client: http_client(),
+            api_url: SENDGRID_URL.to_string(),
+            api_key,
+            from,
+            to,
+        }
+    }
+
+    pub fn with_api_url(mut self, api_url: String) -> Self {
+        self.api_url = api_url;
+        self
+    }
+}
+
+#[async_trait]
+impl NotificationSink for EmailSink {
+    fn name(&self) -> &str {
+        "email"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        let body = json!({
+            "personalizations": [{ "to": [{ "email": self.to }] }],
+            "from": { "email": self.from },
+            "subject": notification.title,
+            "content": [{ "type": "text/plain", "value": notification.body }],
+        });
+        let request = self
+            .client
+            .post(&self.api_url)
+            .bearer_auth(&self.api_key)
+            .json(&body);
+        check(request.send().await?).await
+    }
+}
+
+type Sinks = HashMap<Option<String>, Vec<Arc<dyn NotificationSink>>>;
+
+#[derive(Default)]
+pub struct Notifier {
+    /// sinks per user id, None for the local signer
+    sinks: RwLock<Sinks>,
+}
+
+pub static NOTIFIER: Lazy<Notifier> = Lazy::new(Notifier::from_env);
+
+impl Notifier {
+    /// Sinks of the local signer configured in the environment:
+    /// NOTIFY_TELEGRAM_BOT_TOKEN and NOTIFY_TELEGRAM_CHAT_ID,
+    /// NOTIFY_WEBHOOK_URL (NOTIFY_WEBHOOK_SECRET optional),
+    /// NOTIFY_EMAIL_API_KEY with NOTIFY_EMAIL_FROM and NOTIFY_EMAIL_TO
+    /// (NOTIFY_EMAIL_API_URL optional). They never get the notifications
+    /// of other users
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok();
+        let notifier = Self::default();
+        if let (Some(bot_token), Some(chat_id)) = (
+            var("NOTIFY_TELEGRAM_BOT_TOKEN"),
+            var("NOTIFY_TELEGRAM_CHAT_ID"),
+        ) {
+            notifier.register(
+                None,
+                Arc::new(TelegramSink::new(bot_token, chat_id)),
+            );
+        }
+        if let Some(url) = var("NOTIFY_WEBHOOK_URL") {
+            let mut sink = WebhookSink::new(url);
+            if let Some(secret) = var("NOTIFY_WEBHOOK_SECRET") {
+                sink = sink.with_secret(secret);
+            }
+            notifier.register(None, Arc::new(sink));
+        }
+        if let (Some(api_key), Some(from), Some(to)) = (
+            var("NOTIFY_EMAIL_API_KEY"),
+            var("NOTIFY_EMAIL_FROM"),
+            var("NOTIFY_EMAIL_TO"),
+        ) {
+            let mut sink = EmailSink::new(api_key, from, to);
+            if let Some(api_url) = var("NOTIFY_EMAIL_API_URL") {
+                sink = sink.with_api_url(api_url);
+            }
+            notifier.register(None, Arc::new(sink));
+        }
+        notifier
+    }
+
+    /// Adds a sink of the user `user_id`, None for the local signer
+    pub fn register(
+        &self,
+        user_id: Option<&str>,
+        sink: Arc<dyn NotificationSink>,
+    ) {
+        self.sinks
+            .write()
+            .unwrap()
+            .entry(user_id.map(str::to_string))
+            .or_default()
+            .push(sink);
+    }
+
+    /// Removes the sinks of the user named `name`, false when there was
+    /// none
+    pub fn unregister(&self, user_id: Option<&str>, name: &str) -> bool {
+        let mut sinks = self.sinks.write().unwrap();
+        let Some(user_sinks) = sinks.get_mut(&user_id.map(str::to_string))
+        else {
+            return false;
+        };
+        let before = user_sinks.len();
+        user_sinks.retain(|sink| sink.name() != name);
+        before != user_sinks.len()
+    }
+
+    fn sinks_of(
+        &self,
+        user_id: &Option<String>,
+    ) -> Vec<Arc<dyn NotificationSink>> {
+        let sinks = self.sinks.read().unwrap();
+        sinks.get(user_id).cloned().unwrap_or_default()
+    }
+
+    /// Sends `notification` to the sinks of its user, failed deliveries
+    /// are logged and never fail the caller
+    pub async fn notify(&self, notification: &Notification) {
+        for sink in self.sinks_of(&notification.user_id) {
+            if let Err(e) = sink.send(notification).await {
+                tracing::warn!(?e, sink = sink.name(), "notification failed");
+            }
+        }
+    }
+
+    /// `notify` without waiting for the deliveries
+    pub fn spawn_notify(&'static self, notification: Notification) {
+        if self.sinks_of(&notification.user_id).is_empty() {
+            return;
+        }
+        tokio::spawn(async move { self.notify(&notification).await });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingSink {
+        sent: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl NotificationSink for RecordingSink {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        async fn send(&self, notification: &Notification) -> Result<()> {
+            self.sent.lock().unwrap().push(notification.title.clone());
+            Ok(())
+        }
+    }
+
+    struct FailingSink;
+
+    #[async_trait]
+    impl NotificationSink for FailingSink {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        async fn send(&self, _: &Notification) -> Result<()> {
+            Err(anyhow!("unreachable"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_notify_fans_out() {
+        let notifier = Notifier::default();
+        let sink = Arc::new(RecordingSink::default());
+        let other = Arc::new(RecordingSink::default());
+        notifier.register(Some("alice"), Arc::new(FailingSink));
+        notifier.register(Some("alice"), sink.clone());
+        notifier.register(Some("bob"), other.clone());
+        notifier.register(None, other.clone());
+
+        let notification = Notification::new(
+            NotificationKind::PriceAlert,
+            Some("alice".to_string()),
+            "SOL is above 300",
+            "SOL is at 301.5 USD",
+        );
+        notifier.notify(&notification).await;
+        assert_eq!(*sink.sent.lock().unwrap(), ["SOL is above 300"]);
+        assert!(other.sent.lock().unwrap().is_empty());
+
+        assert!(notifier.unregister(Some("alice"), "recording"));
+        notifier.notify(&notification).await;
+        assert_eq!(sink.sent.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_sign_webhook() {
+        let body = br#"{"kind":"deposit"}"#;
+        assert_eq!(
+            sign_webhook("whsec", 1_700_000_000, body),
+            "5869fa2dafc6fd3e93675c125989e570ab835fe749464f83352edb1835711b74"
+        );
+        assert_ne!(
+            sign_webhook("whsec", 1_700_000_001, body),
+            sign_webhook("whsec", 1_700_000_000, body)
+        );
+    }
+}
@@ -0,0 +1,585 @@
+//! Outbox of the transactions signed by the local signers. A signed
+//! transaction is persisted in the kv store before it is broadcast and
+//! stays there until it landed, failed or can't land anymore (expired
+//! blockhash, nonce used by another transaction). A recurring job
+//! rebroadcasts the pending ones every outbox.retry_secs, also right after
+//! a restart, so a crash between signing and sending doesn't lose the
+//! transaction. Entries are keyed by signature / hash: the same signed
+//! transaction is admitted once and a rebroadcast can't send it twice. A
+//! worker leases an entry by compare-and-set before checking it, one worker
+//! of one process checks and rebroadcasts it at a time
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use alloy::primitives::{Address, TxHash};
+use alloy::providers::Provider;
+use anyhow::{anyhow, Result};
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use solana_client::rpc_client::SerializableTransaction;
+use solana_sdk::commitment_config::{CommitmentConfig, CommitmentLevel};
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::VersionedTransaction;
+
+use crate::common::solana_rpc;
+use crate::config::{config, OutboxSettings};
+use crate::confirmation::status::{evm_status, solana_status};
+use crate::confirmation::{await_evm_confirmation, TxStatus};
+use crate::dedup::is_ambiguous;
+use crate::evm::util::provider_for;
+use crate::jobs::{Job, JOBS};
+use crate::kv_store::{namespaced, set_if_absent, KVStore};
+use crate::metrics::{METRICS, OUTBOX as OUTBOX_METRIC};
+use crate::solana::transaction::{send_tx, send_tx_fallback};
+use crate::tool_error::ToolError;
+
+const OUTBOX_JOB: &str = "outbox";
+/// Resolved entries are kept this long for inspection
+const RESOLVED_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long the EVM signer waits for the receipt before leaving the
+/// transaction to the worker
+const RECEIPT_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "chain", rename_all = "snake_case")]
+pub enum Payload {
+    /// base64 of the bincode of the signed transaction
+    Solana { transaction: String },
+    /// hex of the EIP-2718 encoding of the signed transaction
+    Evm {
+        raw: String,
+        rpc_url: String,
+        from: String,
+        nonce: u64,
+    },
+}
+
+impl Payload {
+    fn chain(&self) -> &'static str {
+        match self {
+            Self::Solana { .. } => "solana",
+            Self::Evm { .. } => "evm",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutboxStatus {
+    Pending,
+    Landed,
+    /// landed and reverted, or refused for good
+    Failed,
+    /// can't land anymore, it may be signed and sent again
+    Expired,
+}
+
+/// Outcome of a transaction seen on chain, None while it isn't
+fn landed(status: TxStatus) -> Option<(OutboxStatus, Option<String>)> {
+    match status {
+        TxStatus::Confirmed { .. } => Some((OutboxStatus::Landed, None)),
+        TxStatus::Failed { error, .. } => {
+            Some((OutboxStatus::Failed, Some(error)))
+        }
+        TxStatus::Pending | TxStatus::TimedOut => None,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    /// signature or transaction hash
+    pub id: String,
+    pub payload: Payload,
+    pub status: OutboxStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub next_attempt_at: DateTime<Utc>,
+}
+
+impl OutboxEntry {
+    fn new(id: String, payload: Payload) -> Self {
+        Self {
+            id,
+            payload,
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            created_at: Utc::now(),
+            next_attempt_at: Utc::now(),
+        }
+    }
+
+    /// Legacy and v0 transactions have the same entries, both decode as
+    /// `VersionedTransaction`
+    fn solana(tx: &impl SerializableTransaction) -> Result<Self> {
+        let transaction = BASE64_STANDARD.encode(bincode::serialize(tx)?);
+        Ok(Self::new(
+            tx.get_signature().to_string(),
+            Payload::Solana { transaction },
+        ))
+    }
+
+    /// Counts a broadcast, the next one is due after `retry`
+    fn attempted(&mut self, retry: Duration, error: Option<String>) {
+        self.attempts += 1;
+        self.last_error = error;
+        self.next_attempt_at = Utc::now()
+            + chrono::Duration::from_std(retry).unwrap_or_default();
+    }
+}
+
+pub struct Outbox {
+    store: Arc<dyn KVStore>,
+}
+
+pub static OUTBOX: Lazy<Outbox> =
+    Lazy::new(|| Outbox::new(Arc::new(namespaced("outbox"))));
+
+impl Outbox {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self { store }
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<OutboxEntry>> {
+        match self.store.get(id).await? {
+            Some(entry) => Ok(Some(serde_json::from_str(&entry)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(&self, entry: &OutboxEntry) -> Result<()> {
+        let value = serde_json::to_string(entry)?;
+        match entry.status {
+            OutboxStatus::Pending => self.store.set(&entry.id, &value).await,
+            _ => {
+                self.store
+                    .set_with_ttl(&entry.id, &value, RESOLVED_TTL)
+                    .await
+            }
+        }
+    }
+
+    async fn resolve(
+        &self,
+        mut entry: OutboxEntry,
+        status: OutboxStatus,
+        error: Option<String>,
+    ) -> Result<()> {
+        let outcome = match status {
+            OutboxStatus::Landed => "landed",
+            OutboxStatus::Failed => "failed",
+            _ => "expired",
+        };
+        tracing::info!(id = %entry.id, outcome, ?error, "outbox resolved");
+        METRICS.inc(
+            OUTBOX_METRIC,
+            &[("chain", entry.payload.chain()), ("outcome", outcome)],
+        );
+        entry.status = status;
+        entry.last_error = error.or(entry.last_error);
+        self.save(&entry).await
+    }
+
+    /// Persists the entry unless it is in the outbox already, in which case
+    /// the existing one is returned and nothing must be broadcast. The write
+    /// is atomic in the store, so this holds across processes too
+    async fn admit(
+        &self,
+        entry: &OutboxEntry,
+    ) -> Result<Option<OutboxEntry>> {
+        let value = serde_json::to_string(entry)?;
+        if set_if_absent(self.store.as_ref(), &entry.id, &value, None).await?
+        {
+            return Ok(None);
+        }
+        match self.get(&entry.id).await? {
+            Some(existing) => Ok(Some(existing)),
+            None => Err(anyhow!(
+                "Outbox entry {} vanished on admission",
+                entry.id
+            )),
+        }
+    }
+
+    /// Transactions waiting to land, oldest first
+    pub async fn pending(&self) -> Result<Vec<OutboxEntry>> {
+        let pending = self.stored_pending().await?;
+        Ok(pending.into_iter().map(|(_, entry)| entry).collect())
+    }
+
+    /// Pending entries with their stored value, oldest first
+    async fn stored_pending(&self) -> Result<Vec<(String, OutboxEntry)>> {
+        let mut pending = self
+            .store
+            .scan_prefix("")
+            .await?
+            .into_iter()
+            .filter_map(|(_, value)| {
+                let entry =
+                    serde_json::from_str::<OutboxEntry>(&value).ok()?;
+                Some((value, entry))
+            })
+            .filter(|(_, entry)| entry.status == OutboxStatus::Pending)
+            .collect::<Vec<_>>();
+        pending.sort_by_key(|(_, entry)| entry.created_at);
+        Ok(pending)
+    }
+
+    /// Leases the entry stored as `stored` for `lease`, None when another
+    /// worker changed it first
+    async fn lease(
+        &self,
+        stored: &str,
+        mut entry: OutboxEntry,
+        lease: Duration,
+    ) -> Result<Option<OutboxEntry>> {
+        entry.next_attempt_at = Utc::now()
+            + chrono::Duration::from_std(lease).unwrap_or_default();
+        let leased = serde_json::to_string(&entry)?;
+        let won = self
+            .store
+            .compare_and_set(&entry.id, Some(stored), &leased, None)
+            .await?;
+        Ok(won.then_some(entry))
+    }
+
+    /// Result of a transaction sent again, the id when it was already
+    /// sent or is being sent
+    fn admitted(existing: OutboxEntry) -> Result<String> {
+        tracing::info!(id = %existing.id, ?existing.status, "already in outbox");
+        match existing.status {
+            OutboxStatus::Pending | OutboxStatus::Landed => Ok(existing.id),
+            _ => Err(anyhow!(
+                "Transaction {} {}",
+                existing.id,
+                existing.last_error.as_deref().unwrap_or("didn't land")
+            )),
+        }
+    }
+
+    /// Persists and broadcasts a signed Solana transaction. A broadcast
+    /// that may have gone through is left to the worker and the signature
+    /// returned
+    pub async fn send_solana(
+        &self,
+        tx: &(impl SerializableTransaction + Sync),
+    ) -> Result<String> {
+        let mut entry = OutboxEntry::solana(tx)?;
+        if let Some(existing) = self.admit(&entry).await? {
+            return Self::admitted(existing);
+        }
+        let retry = Duration::from_secs(config().outbox.retry_secs);
+        match send_tx(tx).await {
+            Ok(signature) => {
+                entry.attempted(retry, None);
+                self.save(&entry).await?;
+                Ok(signature)
+            }
+            Err(e) if is_ambiguous(&ToolError::from_anyhow(&e)) => {
+                tracing::warn!(?e, id = %entry.id, "broadcast left to outbox");
+                entry.attempted(retry, Some(e.to_string()));
+                self.save(&entry).await?;
+                Ok(entry.id)
+            }
+            Err(e) => {
+                let error = Some(e.to_string());
+                self.resolve(entry, OutboxStatus::Failed, error).await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Persists and broadcasts a signed EVM transaction of `from` with
+    /// `nonce` and waits for its receipt, see `send_solana`. A reverted
+    /// transaction is an error, the worker leaves the entry alone while
+    /// the receipt is awaited
+    pub async fn send_evm(
+        &self,
+        raw: &[u8],
+        hash: TxHash,
+        rpc_url: &str,
+        from: Address,
+        nonce: u64,
+    ) -> Result<String> {
+        let mut entry = OutboxEntry::new(
+            hash.to_string(),
+            Payload::Evm {
+                raw: hex::encode(raw),
+                rpc_url: rpc_url.to_string(),
+                from: from.to_string(),
+                nonce,
+            },
+        );
+        if let Some(existing) = self.admit(&entry).await? {
+            return Self::admitted(existing);
+        }
+        // the worker checks the entry once the receipt isn't awaited anymore
+        let retry = Duration::from_secs(config().outbox.retry_secs)
+            .max(RECEIPT_TIMEOUT);
+        let provider = provider_for(rpc_url)?;
+        if let Err(e) = provider.send_raw_transaction(raw).await {
+            let e = anyhow!("Failed to send transaction: {}", e);
+            if !is_ambiguous(&ToolError::from_anyhow(&e)) {
+                let error = Some(e.to_string());
+                self.resolve(entry, OutboxStatus::Failed, error).await?;
+                return Err(e);
+            }
+            tracing::warn!(?e, id = %entry.id, "broadcast left to outbox");
+            entry.attempted(retry, Some(e.to_string()));
+        } else {
+            entry.attempted(retry, None);
+        }
+        self.save(&entry).await?;
+
+        let status = await_evm_confirmation(
+            provider,
+            hash,
+            CommitmentLevel::Processed,
+            RECEIPT_TIMEOUT,
+        )
+        .await;
+        match status {
+            TxStatus::Confirmed { .. } => {
+                self.resolve(entry, OutboxStatus::Landed, None).await?
+            }
+            TxStatus::Failed { error, .. } => {
+                self.resolve(entry, OutboxStatus::Failed, Some(error))
+                    .await?;
+                return Err(anyhow!("Transaction {} reverted", hash));
+            }
+            _ => tracing::warn!(id = %entry.id, "no receipt yet"),
+        }
+        Ok(hash.to_string())
+    }
+
+    /// Where a pending transaction stands on chain, None while it can
+    /// still land
+    async fn check(
+        &self,
+        entry: &OutboxEntry,
+    ) -> Result<Option<(OutboxStatus, Option<String>)>> {
+        match &entry.payload {
+            Payload::Solana { transaction } => {
+                let tx: VersionedTransaction = bincode::deserialize(
+                    &BASE64_STANDARD.decode(transaction)?,
+                )?;
+                let signature = Signature::from_str(&entry.id)?;
+                let status =
+                    solana_status(&signature, CommitmentLevel::Processed)
+                        .await?;
+                if let Some(landed) = landed(status) {
+                    return Ok(Some(landed));
+                }
+                let valid = solana_rpc()
+                    .is_blockhash_valid(
+                        tx.message.recent_blockhash(),
+                        CommitmentConfig::processed(),
+                    )
+                    .await?;
+                Ok((!valid).then(|| {
+                    let error = "blockhash expired".to_string();
+                    (OutboxStatus::Expired, Some(error))
+                }))
+            }
+            Payload::Evm {
+                rpc_url,
+                from,
+                nonce,
+                ..
+            } => {
+                let provider = provider_for(rpc_url)?;
+                let hash = TxHash::from_str(&entry.id)?;
+                let status =
+                    evm_status(&provider, hash, CommitmentLevel::Processed)
+                        .await?;
+                if let Some(landed) = landed(status) {
+                    return Ok(Some(landed));
+                }
+                let next_nonce = provider
+                    .get_transaction_count(Address::from_str(from)?)
+                    .await?;
+                Ok((next_nonce > *nonce).then(|| {
+                    let error =
+                        "nonce used by another transaction".to_string();
+                    (OutboxStatus::Expired, Some(error))
+                }))
+            }
+        }
+    }
+
+    async fn rebroadcast(&self, entry: &OutboxEntry) -> Result<()> {
+        match &entry.payload {
+            Payload::Solana { transaction } => {
+                let tx: VersionedTransaction = bincode::deserialize(
+                    &BASE64_STANDARD.decode(transaction)?,
+                )?;
+                send_tx_fallback(&tx).await?;
+            }
+            Payload::Evm { raw, rpc_url, .. } => {
+                provider_for(rpc_url)?
+                    .send_raw_transaction(&hex::decode(raw)?)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Settles the pending transactions that landed or can't land anymore
+    /// and rebroadcasts the due ones, returns how many were rebroadcast
+    pub async fn drain(&self, settings: &OutboxSettings) -> Result<usize> {
+        let retry = Duration::from_secs(settings.retry_secs);
+        let mut rebroadcast = 0;
+        for (stored, entry) in self.stored_pending().await? {
+            if entry.next_attempt_at > Utc::now() {
+                continue;
+            }
+            let Some(mut entry) = self.lease(&stored, entry, retry).await?
+            else {
+                continue;
+            };
+            match self.check(&entry).await {
+                Ok(Some((status, error))) => {
+                    self.resolve(entry, status, error).await?;
+                    continue;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::warn!(?e, id = %entry.id, "outbox check failed");
+                    continue;
+                }
+            }
+            if entry.attempts >= settings.max_attempts {
+                let error = Some("too many attempts".to_string());
+                self.resolve(entry, OutboxStatus::Expired, error).await?;
+                continue;
+            }
+            let error = self.rebroadcast(&entry).await.err();
+            if let Some(e) = &error {
+                tracing::warn!(?e, id = %entry.id, "rebroadcast failed");
+            }
+            METRICS.inc(
+                OUTBOX_METRIC,
+                &[("chain", entry.payload.chain()), ("outcome", "retried")],
+            );
+            entry.attempted(retry, error.map(|e| e.to_string()));
+            self.save(&entry).await?;
+            rebroadcast += 1;
+        }
+        Ok(rebroadcast)
+    }
+
+    /// Drains the outbox every outbox.retry_secs through a recurring job,
+    /// the first run picks up what a previous process left behind
+    pub async fn schedule(&'static self) -> Result<()> {
+        JOBS.register(OUTBOX_JOB, move |_: Job| async move {
+            let rebroadcast = self.drain(&config().outbox).await?;
+            if rebroadcast > 0 {
+                tracing::info!(rebroadcast, "outbox drained");
+            }
+            Ok(format!("{} rebroadcast", rebroadcast))
+        });
+        let interval = Duration::from_secs(config().outbox.retry_secs);
+        JOBS.ensure(
+            Job::new(OUTBOX_JOB, Value::Null).recurring(OUTBOX_JOB, interval),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::hash::Hash;
+    use solana_sdk::signature::Keypair;
+    use solana_sdk::signer::Signer;
+    use solana_sdk::system_instruction::transfer;
+    use solana_sdk::transaction::Transaction;
+
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    fn signed_transfer() -> Transaction {
+        let keypair = Keypair::new();
+        let ix = transfer(&keypair.pubkey(), &keypair.pubkey(), 1);
+        Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&keypair.pubkey()),
+            &[&keypair],
+            Hash::new_unique(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_admit_once() {
+        let outbox = Outbox::new(Arc::new(InMemoryKVStore::new()));
+        let tx = signed_transfer();
+        let entry = OutboxEntry::solana(&tx).unwrap();
+        assert_eq!(entry.id, tx.signatures[0].to_string());
+
+        assert!(outbox.admit(&entry).await.unwrap().is_none());
+        // persisted before anything was sent
+        let pending = outbox.pending().await.unwrap();
+        assert_eq!(pending.len(), 1);
+        let Payload::Solana { transaction } = &pending[0].payload else {
+            panic!("not a solana entry");
+        };
+        let decoded: Transaction = bincode::deserialize(
+            &BASE64_STANDARD.decode(transaction).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(decoded, tx);
+
+        // the same signed transaction isn't sent twice
+        let existing = outbox.admit(&entry).await.unwrap().unwrap();
+        assert_eq!(Outbox::admitted(existing).unwrap(), entry.id);
+    }
+
+    #[tokio::test]
+    async fn test_resolved_leave_pending() {
+        let outbox = Outbox::new(Arc::new(InMemoryKVStore::new()));
+        let landed = OutboxEntry::solana(&signed_transfer()).unwrap();
+        let expired = OutboxEntry::solana(&signed_transfer()).unwrap();
+        outbox.admit(&landed).await.unwrap();
+        outbox.admit(&expired).await.unwrap();
+
+        outbox
+            .resolve(landed.clone(), OutboxStatus::Landed, None)
+            .await
+            .unwrap();
+        let error = Some("blockhash expired".to_string());
+        outbox
+            .resolve(expired.clone(), OutboxStatus::Expired, error)
+            .await
+            .unwrap();
+        assert!(outbox.pending().await.unwrap().is_empty());
+
+        let existing = outbox.get(&expired.id).await.unwrap().unwrap();
+        let error = Outbox::admitted(existing).unwrap_err();
+        assert!(error.to_string().contains("blockhash expired"));
+    }
+
+    #[tokio::test]
+    async fn test_lease_once() {
+        let outbox = Outbox::new(Arc::new(InMemoryKVStore::new()));
+        let entry = OutboxEntry::solana(&signed_transfer()).unwrap();
+        outbox.admit(&entry).await.unwrap();
+        let (stored, pending) =
+            outbox.stored_pending().await.unwrap().remove(0);
+
+        let lease = Duration::from_secs(60);
+        let (first, second) = tokio::join!(
+            outbox.lease(&stored, pending.clone(), lease),
+            outbox.lease(&stored, pending, lease)
+        );
+        let leased = [first.unwrap(), second.unwrap()];
+        assert_eq!(leased.iter().flatten().count(), 1);
+        // not due while leased
+        let entry = outbox.get(&entry.id).await.unwrap().unwrap();
+        assert!(entry.next_attempt_at > Utc::now());
+    }
+}
@@ -0,0 +1,453 @@
+//! Paper trading: sessions in paper mode trade against a virtual portfolio
+//! kept in the kv store instead of their wallet, so strategies can be tried
+//! before connecting real funds. Swaps are booked at the current USD prices
+//! of both tokens and transfers only debit the portfolio, nothing is built,
+//! signed nor sent
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::amount::{Quantity, UserAmount};
+use crate::common::evm_chain;
+use crate::error::Error;
+use crate::execution::session_key;
+use crate::kv_store::{KVStore, KV_STORE};
+use crate::preferences::PREFERENCES;
+use crate::pricing::{to_ui_amount, token_price, TokenPrice};
+use crate::signer::{SignerContext, TransactionSigner};
+use crate::solana::constants::WSOL;
+use crate::tool_error::ToolError;
+
+/// Funding of sessions entering paper mode without a portfolio
+const DEFAULT_FUNDING: (&str, &str, f64) = ("sol", WSOL, 10.);
+/// LiFi gives native SOL this address, Jupiter swaps it as wrapped SOL
+const NATIVE_SOL: &str = "11111111111111111111111111111111";
+const EVM_NATIVE: &str = "0x0000000000000000000000000000000000000000";
+/// Balances below this are dust left by rounding and are dropped
+const DUST: f64 = 1e-9;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaperBalance {
+    pub chain: String,
+    pub token: String,
+    pub symbol: String,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaperTrade {
+    pub tool: String,
+    pub sold: String,
+    pub bought: Option<String>,
+    pub value_usd: f64,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PaperPortfolio {
+    pub balances: BTreeMap<String, PaperBalance>,
+    /// USD value of the funding, the baseline of the PnL
+    pub funded_usd: f64,
+    pub trades: Vec<PaperTrade>,
+}
+
+/// Tokens are keyed by chain and address, native SOL is wrapped SOL and EVM
+/// addresses are case insensitive
+fn balance_key(chain: &str, token: &str) -> String {
+    match chain {
+        "sol" if token == NATIVE_SOL => format!("sol:{}", WSOL),
+        "sol" => format!("sol:{}", token),
+        _ => format!("{}:{}", chain, token.to_lowercase()),
+    }
+}
+
+impl PaperPortfolio {
+    pub fn balance(&self, chain: &str, token: &str) -> f64 {
+        self.balances
+            .get(&balance_key(chain, token))
+            .map_or(0., |balance| balance.amount)
+    }
+
+    pub fn credit(&mut self, chain: &str, token: &TokenPrice, amount: f64) {
+        self.balances
+            .entry(balance_key(chain, &token.address))
+            .or_insert_with(|| PaperBalance {
+                chain: chain.to_string(),
+                token: token.address.clone(),
+                symbol: token.symbol.clone(),
+                amount: 0.,
+            })
+            .amount += amount;
+    }
+
+    pub fn debit(
+        &mut self,
+        chain: &str,
+        token: &TokenPrice,
+        amount: f64,
+    ) -> Result<()> {
+        let key = balance_key(chain, &token.address);
+        let balance = self.balances.get(&key).map_or(0., |b| b.amount);
+        if amount > balance + DUST {
+            return Err(Error::InsufficientBalance(format!(
+                "The paper portfolio only holds {} {}",
+                balance, token.symbol
+            ))
+            .detailed(|error| {
+                error.with_details(json!({ "balance": balance }))
+            }));
+        }
+        if balance - amount < DUST {
+            self.balances.remove(&key);
+        } else if let Some(balance) = self.balances.get_mut(&key) {
+            balance.amount -= amount;
+        }
+        Ok(())
+    }
+}
+
+/// What a value-moving tool call spends and, for swaps, what it buys
+#[derive(Debug, PartialEq)]
+struct Order {
+    chain: String,
+    token: String,
+    amount: String,
+    /// the tool takes a raw integer amount rather than a spoken one
+    raw: bool,
+    output: Option<(String, String)>,
+}
+
+impl Order {
+    fn from_call(tool: &str, args: &Value) -> Result<Self> {
+        let arg = |name: &str| -> Result<String> {
+            match &args[name] {
+                Value::String(s) => Ok(s.clone()),
+                Value::Number(n) => Ok(n.to_string()),
+                _ => Err(ToolError::invalid_input(format!(
+                    "Missing argument {}",
+                    name
+                ))
+                .into()),
+            }
+        };
+        let order = |chain: String, token, amount, output| Self {
+            chain,
+            token,
+            amount,
+            raw: false,
+            output,
+        };
+        Ok(match tool {
+            "perform_jupiter_swap" => order(
+                "sol".to_string(),
+                arg("input_mint")?,
+                arg("input_amount")?,
+                Some(("sol".to_string(), arg("output_mint")?)),
+            ),
+            "trade" => order(
+                evm_chain(),
+                arg("input_token_address")?,
+                arg("input_amount")?,
+                Some((evm_chain(), arg("output_token_address")?)),
+            ),
+            "multichain_swap" => Self {
+                raw: true,
+                ..order(
+                    arg("from_chain")?,
+                    arg("from_token_symbol")?,
+                    arg("amount")?,
+                    Some((arg("to_chain")?, arg("to_token_symbol")?)),
+                )
+            },
+            "transfer_sol" => order(
+                "sol".to_string(),
+                WSOL.to_string(),
+                arg("amount")?,
+                None,
+            ),
+            "transfer_spl_token" => {
+                order("sol".to_string(), arg("mint")?, arg("amount")?, None)
+            }
+            "transfer_eth" => order(
+                evm_chain(),
+                EVM_NATIVE.to_string(),
+                arg("amount")?,
+                None,
+            ),
+            "transfer_erc20" => order(
+                evm_chain(),
+                arg("token_address")?,
+                arg("amount")?,
+                None,
+            ),
+            _ => {
+                return Err(ToolError::invalid_input(format!(
+                    "{} is not supported in paper trading",
+                    tool
+                ))
+                .with_hint("Switch the session to live to use it")
+                .into())
+            }
+        })
+    }
+
+    /// Decimal amount spent, shares are of the paper balance
+    fn ui_amount(&self, balance: f64, decimals: u8) -> Result<f64> {
+        if self.raw {
+            return to_ui_amount(&self.amount, decimals);
+        }
+        Ok(match UserAmount::from_str(&self.amount)?.quantity {
+            Quantity::Tokens(tokens) => tokens.parse()?,
+            Quantity::Raw(raw) => to_ui_amount(&raw.to_string(), decimals)?,
+            Quantity::Share(share) => balance * share as f64 / 10_000.,
+        })
+    }
+}
+
+pub struct PaperLedger {
+    store: Arc<dyn KVStore>,
+}
+
+pub static PAPER_LEDGER: Lazy<PaperLedger> =
+    Lazy::new(|| PaperLedger::new(KV_STORE.clone()));
+
+impl PaperLedger {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self { store }
+    }
+
+    fn key(session: &str) -> String {
+        format!("paper:portfolio:{}", session)
+    }
+
+    pub async fn get(&self, session: &str) -> Result<Option<PaperPortfolio>> {
+        match self.store.get(&Self::key(session)).await? {
+            Some(portfolio) => Ok(Some(serde_json::from_str(&portfolio)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set(
+        &self,
+        session: &str,
+        portfolio: &PaperPortfolio,
+    ) -> Result<()> {
+        self.store
+            .set(&Self::key(session), &serde_json::to_string(portfolio)?)
+            .await
+    }
+
+    /// Replaces the portfolio of `session` with `amount` of `token`
+    pub async fn reset(
+        &self,
+        session: &str,
+        chain: &str,
+        token: &str,
+        amount: f64,
+    ) -> Result<PaperPortfolio> {
+        let price = token_price(chain, token).await?;
+        let mut portfolio = PaperPortfolio {
+            funded_usd: amount * price.price_usd,
+            ..Default::default()
+        };
+        portfolio.credit(chain, &price, amount);
+        self.set(session, &portfolio).await?;
+        Ok(portfolio)
+    }
+
+    /// Portfolio of `session`, funded with the default one on first use
+    pub async fn load(&self, session: &str) -> Result<PaperPortfolio> {
+        match self.get(session).await? {
+            Some(portfolio) => Ok(portfolio),
+            None => {
+                let (chain, token, amount) = DEFAULT_FUNDING;
+                self.reset(session, chain, token, amount).await
+            }
+        }
+    }
+}
+
+/// Books the value-moving `tool` call on the paper portfolio of the session
+/// of `signer` instead of running it
+pub async fn execute(
+    signer: &dyn TransactionSigner,
+    tool: &str,
+    args: &str,
+) -> Result<String> {
+    let mut order = Order::from_call(tool, &serde_json::from_str(args)?)?;
+    let session = session_key(signer);
+    let preferences = PREFERENCES.get(&session).await?;
+    order.chain = preferences.chain_or_default(&order.chain)?;
+    if let Some((chain, _)) = &mut order.output {
+        *chain = preferences.chain_or_default(chain)?;
+    }
+    let mut portfolio = PAPER_LEDGER.load(&session).await?;
+
+    let sold = token_price(&order.chain, &order.token).await?;
+    let balance = portfolio.balance(&order.chain, &sold.address);
+    let amount = order.ui_amount(balance, sold.decimals)?;
+    portfolio.debit(&order.chain, &sold, amount)?;
+    let value_usd = amount * sold.price_usd;
+
+    let bought = match &order.output {
+        Some((chain, token)) => {
+            let price = token_price(chain, token).await?;
+            let bought = value_usd / price.price_usd;
+            portfolio.credit(chain, &price, bought);
+            Some(format!("{} {}", bought, price.symbol))
+        }
+        None => None,
+    };
+    let trade = PaperTrade {
+        tool: tool.to_string(),
+        sold: format!("{} {}", amount, sold.symbol),
+        bought,
+        value_usd,
+        timestamp: chrono::Utc::now().timestamp(),
+    };
+    portfolio.trades.push(trade.clone());
+    PAPER_LEDGER.set(&session, &portfolio).await?;
+
+    Ok(json!({
+        "mode": "paper",
+        "trade": trade,
+        "message": "Booked on the paper portfolio, nothing was signed nor sent",
+    })
+    .to_string())
+}
+
+#[derive(Debug, Serialize)]
+pub struct PaperHolding {
+    #[serde(flatten)]
+    pub balance: PaperBalance,
+    pub value_usd: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PaperReport {
+    pub holdings: Vec<PaperHolding>,
+    pub value_usd: f64,
+    pub funded_usd: f64,
+    pub pnl_usd: f64,
+    pub pnl_percent: f64,
+    pub trades: Vec<PaperTrade>,
+}
+
+#[tool(description = "
+Returns the paper trading portfolio of the session: the virtual holdings
+valued at current prices, the PnL against the funding and the paper trades
+")]
+pub async fn get_paper_portfolio() -> Result<PaperReport> {
+    let session = session_key(SignerContext::current().await.as_ref());
+    let portfolio = PAPER_LEDGER.load(&session).await?;
+
+    let mut holdings = vec![];
+    for balance in portfolio.balances.into_values() {
+        let value_usd = token_price(&balance.chain, &balance.token)
+            .await
+            .ok()
+            .map(|price| balance.amount * price.price_usd);
+        holdings.push(PaperHolding { balance, value_usd });
+    }
+    let value_usd = holdings.iter().filter_map(|h| h.value_usd).sum::<f64>();
+    let pnl_usd = value_usd - portfolio.funded_usd;
+    let pnl_percent = if portfolio.funded_usd > 0. {
+        pnl_usd / portfolio.funded_usd * 100.
+    } else {
+        0.
+    };
+
+    Ok(PaperReport {
+        holdings,
+        value_usd,
+        funded_usd: portfolio.funded_usd,
+        pnl_usd,
+        pnl_percent,
+        trades: portfolio.trades,
+    })
+}
+
+#[tool(description = "
+Resets the paper trading portfolio of the session to a single virtual
+balance, e.g. chain sol, token SOL and amount 10 for 10 SOL. token is a
+symbol or an address, amount is in whole tokens. The trade history is
+cleared and the PnL starts over
+")]
+pub async fn reset_paper_portfolio(
+    chain: String,
+    token: String,
+    amount: f64,
+) -> Result<PaperPortfolio> {
+    if amount <= 0. {
+        return Err(
+            ToolError::invalid_input("amount has to be positive").into()
+        );
+    }
+    let session = session_key(SignerContext::current().await.as_ref());
+    PAPER_LEDGER.reset(&session, &chain, &token, amount).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tool_error::ErrorCode;
+
+    fn token(address: &str, symbol: &str) -> TokenPrice {
+        TokenPrice {
+            address: address.to_string(),
+            symbol: symbol.to_string(),
+            decimals: 9,
+            price_usd: 1.,
+        }
+    }
+
+    #[test]
+    fn test_portfolio_balances() {
+        let sol = token(WSOL, "SOL");
+        let mut portfolio = PaperPortfolio::default();
+        portfolio.credit("sol", &sol, 10.);
+        assert_eq!(portfolio.balance("sol", NATIVE_SOL), 10.);
+
+        portfolio.debit("sol", &sol, 4.).unwrap();
+        assert_eq!(portfolio.balance("sol", WSOL), 6.);
+        let error = portfolio.debit("sol", &sol, 7.).unwrap_err();
+        assert_eq!(
+            ToolError::from_anyhow(&error).code,
+            ErrorCode::InsufficientFunds
+        );
+        portfolio.debit("sol", &sol, 6.).unwrap();
+        assert!(portfolio.balances.is_empty());
+    }
+
+    #[test]
+    fn test_orders() {
+        let args = json!({
+            "input_mint": WSOL,
+            "input_amount": "half",
+            "output_mint": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+            "slippage_bps": 50,
+        });
+        let order = Order::from_call("perform_jupiter_swap", &args).unwrap();
+        assert_eq!(order.ui_amount(10., 9).unwrap(), 5.);
+
+        let args = json!({
+            "from_token_symbol": "USDC",
+            "to_token_symbol": "SOL",
+            "amount": "1500000",
+            "from_chain": "arb",
+            "to_chain": "sol",
+        });
+        let order = Order::from_call("multichain_swap", &args).unwrap();
+        assert_eq!(order.ui_amount(0., 6).unwrap(), 1.5);
+
+        assert!(
+            Order::from_call("deploy_pump_fun_token", &json!({})).is_err()
+        );
+    }
+}
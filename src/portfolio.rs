@@ -0,0 +1,495 @@
+//! Periodic snapshots of the portfolios of the users, so questions like "am
+//! I up this week?" are answered from stored values instead of being derived
+//! again from the transactions. Users are tracked from the first time they
+//! authenticate, while snapshots.enabled a recurring job values their
+//! wallets every snapshots.interval_mins: SOL and the SPL tokens on Solana,
+//! the native token and snapshots.evm_tokens on EVM chains. Tokens without
+//! a price are left out. Snapshots are kept snapshots.retention_days
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::address::Address;
+use crate::chains::CHAINS;
+use crate::common::{evm_chain, solana_rpc};
+use crate::config::{config, SnapshotSettings};
+use crate::evm::balance::{balance, token_balance};
+use crate::evm::tools::NATIVE_TOKEN;
+use crate::evm::util::make_provider;
+use crate::history::Period;
+use crate::jobs::{Job, JOBS};
+use crate::kv_store::{namespaced, KVStore};
+use crate::pricing::{to_ui_amount, token_price};
+use crate::signer::SignerContext;
+use crate::solana::balance::get_holdings;
+use crate::solana::constants::WSOL;
+use crate::tool_error::{ErrorCode, ToolError};
+use crate::wallet_manager::UserSession;
+
+const USERS_KEY: &str = "users";
+const SNAPSHOTS_JOB: &str = "portfolio_snapshots";
+const CONCURRENT_USERS: usize = 4;
+/// Points returned by `get_portfolio_history`, longer histories are thinned
+const MAX_POINTS: usize = 200;
+
+/// Wallets of a user, the ones the user doesn't have are None
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TrackedUser {
+    pub user_id: String,
+    pub solana: Option<String>,
+    pub evm: Option<String>,
+}
+
+impl From<&UserSession> for TrackedUser {
+    fn from(session: &UserSession) -> Self {
+        let wallet = |address: &str| {
+            Some(address.to_string()).filter(|a| !a.is_empty())
+        };
+        Self {
+            user_id: session.user_id.clone(),
+            solana: wallet(&session.pubkey),
+            evm: wallet(&session.wallet_address),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotHolding {
+    /// sol or the chain of the EVM RPC (sonic, eth..)
+    pub chain: String,
+    pub token: String,
+    pub symbol: String,
+    pub amount: f64,
+    pub usd_value: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PortfolioSnapshot {
+    pub at: DateTime<Utc>,
+    pub total_usd: f64,
+    pub holdings: Vec<SnapshotHolding>,
+}
+
+/// Valuation of any wallet, largest holdings first
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WalletReport {
+    pub address: String,
+    /// sol or the chain of the EVM RPC (sonic, eth..)
+    pub chain: String,
+    pub total_usd: f64,
+    pub holdings: Vec<SnapshotHolding>,
+}
+
+impl WalletReport {
+    fn new(address: &Address, mut holdings: Vec<SnapshotHolding>) -> Self {
+        holdings.sort_by(|a, b| b.usd_value.total_cmp(&a.usd_value));
+        Self {
+            address: address.to_string(),
+            chain: match address {
+                Address::Solana(_) => "sol".to_string(),
+                Address::Evm(_) => evm_chain(),
+            },
+            total_usd: holdings.iter().map(|holding| holding.usd_value).sum(),
+            holdings,
+        }
+    }
+}
+
+/// Value of the portfolio at a snapshot
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PortfolioPoint {
+    pub at: DateTime<Utc>,
+    pub total_usd: f64,
+}
+
+/// How the portfolio changed over a period, deposits and withdrawals count
+/// as changes too
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PortfolioHistory {
+    pub start_usd: f64,
+    pub end_usd: f64,
+    pub change_usd: f64,
+    /// None when the portfolio was empty at the start
+    pub change_pct: Option<f64>,
+    pub points: Vec<PortfolioPoint>,
+    /// holdings at the last snapshot
+    pub latest: PortfolioSnapshot,
+}
+
+impl PortfolioHistory {
+    /// History of the snapshots in order, None without any
+    pub fn new(snapshots: &[PortfolioSnapshot]) -> Option<Self> {
+        let (first, latest) = (snapshots.first()?, snapshots.last()?);
+        let change_usd = latest.total_usd - first.total_usd;
+        let step = snapshots.len().div_ceil(MAX_POINTS);
+        let mut points = snapshots
+            .iter()
+            .step_by(step)
+            .map(|snapshot| PortfolioPoint {
+                at: snapshot.at,
+                total_usd: snapshot.total_usd,
+            })
+            .collect::<Vec<_>>();
+        if points.last().map(|point| point.at) != Some(latest.at) {
+            points.push(PortfolioPoint {
+                at: latest.at,
+                total_usd: latest.total_usd,
+            });
+        }
+        Some(Self {
+            start_usd: first.total_usd,
+            end_usd: latest.total_usd,
+            change_usd,
+            change_pct: (first.total_usd > 0.)
+                .then(|| change_usd / first.total_usd * 100.),
+            points,
+            latest: latest.clone(),
+        })
+    }
+}
+
+/// Values `amount` base units of a token, None for tokens without a price
+async fn holding(
+    chain: &str,
+    token: &str,
+    amount: u128,
+) -> Option<SnapshotHolding> {
+    if amount == 0 {
+        return None;
+    }
+    let price = match token_price(chain, token).await {
+        Ok(price) => price,
+        Err(e) => {
+            tracing::debug!(?e, token, "holding not priced");
+            return None;
+        }
+    };
+    let amount = to_ui_amount(&amount.to_string(), price.decimals).ok()?;
+    Some(SnapshotHolding {
+        chain: chain.to_string(),
+        token: token.to_string(),
+        symbol: price.symbol,
+        amount,
+        usd_value: amount * price.price_usd,
+    })
+}
+
+async fn solana_holdings(wallet: &str) -> Result<Vec<SnapshotHolding>> {
+    let owner = Pubkey::from_str(wallet)?;
+    let rpc = solana_rpc();
+    let mut amounts =
+        vec![(WSOL.to_string(), rpc.get_balance(&owner).await?)];
+    amounts.extend(
+        get_holdings(&rpc, &owner)
+            .await?
+            .into_iter()
+            .map(|holding| (holding.mint, holding.amount)),
+    );
+    let mut holdings = vec![];
+    for (mint, amount) in amounts {
+        holdings.extend(holding("sol", &mint, amount.into()).await);
+    }
+    Ok(holdings)
+}
+
+async fn evm_holdings(
+    wallet: &str,
+    tokens: &[String],
+) -> Result<Vec<SnapshotHolding>> {
+    let provider = make_provider()?;
+    let chain = evm_chain();
+    let mut amounts = vec![(
+        NATIVE_TOKEN.to_string(),
+        balance(&provider, wallet.to_string()).await?,
+    )];
+    for token in tokens {
+        let amount =
+            token_balance(wallet.to_string(), token.clone(), &provider)
+                .await?;
+        amounts.push((token.clone(), amount));
+    }
+    let mut holdings = vec![];
+    for (token, amount) in amounts {
+        let amount = amount.parse::<u128>().unwrap_or(u128::MAX);
+        holdings.extend(holding(&chain, &token, amount).await);
+    }
+    Ok(holdings)
+}
+
+pub struct PortfolioSnapshots {
+    store: Arc<dyn KVStore>,
+    /// users are stored as a single value, writes have to be serialized
+    lock: tokio::sync::Mutex<()>,
+    /// users already stored as they are, tracking them again is a no-op
+    tracked: Mutex<HashSet<TrackedUser>>,
+}
+
+pub static SNAPSHOTS: Lazy<PortfolioSnapshots> =
+    Lazy::new(|| PortfolioSnapshots::new(Arc::new(namespaced("portfolio"))));
+
+impl PortfolioSnapshots {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self {
+            store,
+            lock: tokio::sync::Mutex::new(()),
+            tracked: Mutex::new(HashSet::new()),
+        }
+    }
+
+    async fn users(&self) -> Result<Vec<TrackedUser>> {
+        match self.store.get(USERS_KEY).await? {
+            Some(users) => Ok(serde_json::from_str(&users)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Snapshots the wallets of the user from now on, users changing
+    /// wallets are followed
+    pub async fn track(&self, user: TrackedUser) -> Result<()> {
+        if self.tracked.lock().unwrap().contains(&user) {
+            return Ok(());
+        }
+        let _guard = self.lock.lock().await;
+        let mut users = self.users().await?;
+        users.retain(|tracked| tracked.user_id != user.user_id);
+        users.push(user.clone());
+        self.store
+            .set(USERS_KEY, &serde_json::to_string(&users)?)
+            .await?;
+        self.tracked.lock().unwrap().insert(user);
+        Ok(())
+    }
+
+    fn key_prefix(user_id: &str) -> String {
+        format!("snapshot:{}:", user_id)
+    }
+
+    pub async fn record(
+        &self,
+        user_id: &str,
+        snapshot: &PortfolioSnapshot,
+        retention: Duration,
+    ) -> Result<()> {
+        let key = format!(
+            "{}{:012}",
+            Self::key_prefix(user_id),
+            snapshot.at.timestamp()
+        );
+        self.store
+            .set_with_ttl(&key, &serde_json::to_string(snapshot)?, retention)
+            .await
+    }
+
+    /// Snapshots of the user over `period`, oldest first
+    pub async fn history(
+        &self,
+        user_id: &str,
+        period: &Period,
+    ) -> Result<Vec<PortfolioSnapshot>> {
+        let mut snapshots = self
+            .store
+            .scan_prefix(&Self::key_prefix(user_id))
+            .await?
+            .into_iter()
+            .filter_map(|(_, snapshot)| {
+                serde_json::from_str::<PortfolioSnapshot>(&snapshot).ok()
+            })
+            .filter(|snapshot| period.contains(snapshot.at))
+            .collect::<Vec<_>>();
+        snapshots.sort_by_key(|snapshot| snapshot.at);
+        Ok(snapshots)
+    }
+
+    /// Values the wallets of the user, a chain that can't be read fails the
+    /// whole snapshot rather than recording a drop in value
+    pub async fn value(
+        user: &TrackedUser,
+        settings: &SnapshotSettings,
+    ) -> Result<PortfolioSnapshot> {
+        let mut holdings = vec![];
+        if let Some(wallet) = &user.solana {
+            holdings.extend(solana_holdings(wallet).await?);
+        }
+        if let Some(wallet) = &user.evm {
+            holdings
+                .extend(evm_holdings(wallet, &settings.evm_tokens).await?);
+        }
+        Ok(PortfolioSnapshot {
+            at: Utc::now(),
+            total_usd: holdings.iter().map(|holding| holding.usd_value).sum(),
+            holdings,
+        })
+    }
+
+    async fn snapshot_all(&self, settings: &SnapshotSettings) -> Result<()> {
+        let retention =
+            Duration::from_secs(settings.retention_days * 24 * 3600);
+        stream::iter(self.users().await?)
+            .for_each_concurrent(CONCURRENT_USERS, |user| async move {
+                let recorded = match Self::value(&user, settings).await {
+                    Ok(snapshot) => {
+                        self.record(&user.user_id, &snapshot, retention).await
+                    }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = recorded {
+                    tracing::warn!(?e, user = %user.user_id, "no snapshot");
+                }
+            })
+            .await;
+        Ok(())
+    }
+
+    /// Snapshots every tracked user every snapshots.interval_mins through a
+    /// recurring job, runs are skipped while snapshots are disabled
+    pub async fn schedule(&'static self) -> Result<()> {
+        JOBS.register(SNAPSHOTS_JOB, move |_: Job| async move {
+            let settings = config().snapshots.clone();
+            if !settings.enabled {
+                return Ok("disabled".to_string());
+            }
+            self.snapshot_all(&settings).await?;
+            Ok("snapshots taken".to_string())
+        });
+        let interval =
+            Duration::from_secs(config().snapshots.interval_mins * 60);
+        JOBS.ensure(
+            Job::new(SNAPSHOTS_JOB, Value::Null)
+                .recurring(SNAPSHOTS_JOB, interval),
+        )
+        .await
+    }
+}
+
+#[tool(description = "
+Returns how the value of the user's portfolio changed over a period, from
+the periodic snapshots: the USD value at the start and the end, the change
+in USD and percent, the values over time and the holdings at the latest
+snapshot. Use it to answer questions like \"am I up this week?\".
+
+period is 7d, 30d (any number of days), ytd, a year like 2024 or all
+")]
+pub async fn get_portfolio_history(
+    period: String,
+) -> Result<PortfolioHistory> {
+    let period = Period::from_str(&period)?;
+    let user_id =
+        SignerContext::current().await.user_id().ok_or_else(|| {
+            ToolError::new(
+                ErrorCode::Unauthorized,
+                "Portfolio history is only kept for signed in users",
+            )
+        })?;
+    let snapshots = SNAPSHOTS.history(&user_id, &period).await?;
+    PortfolioHistory::new(&snapshots).ok_or_else(|| {
+        ToolError::not_found("No portfolio snapshots over the period")
+            .with_hint("Snapshots are taken periodically, try again later")
+            .into()
+    })
+}
+
+#[tool(description = "
+Values the holdings of any wallet, e.g. a whale the user wants analyzed:
+every priced token with its amount and USD value, largest first, and the
+total. Read-only, nothing is signed. Solana addresses list all their SPL
+tokens, EVM addresses (0x..) the native token and the tracked tokens on the
+configured EVM chain
+
+address is the wallet to inspect
+")]
+pub async fn inspect_wallet(address: String) -> Result<WalletReport> {
+    let address = Address::parse(&address)?;
+    CHAINS.require(address.chain())?;
+    let holdings = match &address {
+        Address::Solana(pubkey) => {
+            solana_holdings(&pubkey.to_string()).await?
+        }
+        Address::Evm(wallet) => {
+            evm_holdings(&wallet.to_string(), &config().snapshots.evm_tokens)
+                .await?
+        }
+    };
+    Ok(WalletReport::new(&address, holdings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    fn snapshot(hours_ago: i64, total_usd: f64) -> PortfolioSnapshot {
+        PortfolioSnapshot {
+            at: Utc::now() - chrono::Duration::hours(hours_ago),
+            total_usd,
+            holdings: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_history() {
+        let snapshots =
+            PortfolioSnapshots::new(Arc::new(InMemoryKVStore::new()));
+        let retention = Duration::from_secs(3600);
+        for (hours_ago, total_usd) in [(24 * 10, 50.), (48, 100.), (1, 125.)]
+        {
+            snapshots
+                .record("alice", &snapshot(hours_ago, total_usd), retention)
+                .await
+                .unwrap();
+        }
+        snapshots
+            .record("bob", &snapshot(2, 1.), retention)
+            .await
+            .unwrap();
+
+        let week = Period::from_str("7d").unwrap();
+        let history = snapshots.history("alice", &week).await.unwrap();
+        let history = PortfolioHistory::new(&history).unwrap();
+        assert_eq!(history.start_usd, 100.);
+        assert_eq!(history.change_usd, 25.);
+        assert_eq!(history.change_pct, Some(25.));
+        assert_eq!(history.points.len(), 2);
+        assert!(PortfolioHistory::new(&[]).is_none());
+    }
+
+    #[test]
+    fn test_wallet_report() {
+        let holding = |token: &str, usd_value: f64| SnapshotHolding {
+            chain: "sol".to_string(),
+            token: token.to_string(),
+            symbol: token.to_uppercase(),
+            amount: 1.,
+            usd_value,
+        };
+        let address = Address::Solana(Pubkey::new_unique());
+        let report = WalletReport::new(
+            &address,
+            vec![holding("bonk", 5.), holding("sol", 150.)],
+        );
+        assert_eq!(report.chain, "sol");
+        assert_eq!(report.total_usd, 155.);
+        assert_eq!(report.holdings[0].token, "sol");
+    }
+
+    #[test]
+    fn test_points_are_thinned() {
+        let snapshots = (0..1000)
+            .rev()
+            .map(|hours_ago| snapshot(hours_ago, hours_ago as f64))
+            .collect::<Vec<_>>();
+        let history = PortfolioHistory::new(&snapshots).unwrap();
+        assert!(history.points.len() <= MAX_POINTS + 1);
+        assert_eq!(history.points.last().unwrap().total_usd, 0.);
+        assert_eq!(history.change_pct, Some(-100.));
+    }
+}
@@ -0,0 +1,206 @@
+//! Position sizing, so "how much should I buy" gets a number backed by the
+//! portfolio and the token instead of a guess. The size risks risk_percent
+//! of the portfolio on a stop two daily standard deviations away, capped by
+//! the risk limits of the user and what they can still trade today
+use anyhow::Result;
+use rig_tool_macro::tool;
+use serde::Serialize;
+
+use crate::audit::audit_user;
+use crate::config::config;
+use crate::portfolio::{PortfolioSnapshots, TrackedUser};
+use crate::pricing::{candles, Candle};
+use crate::risk::{RiskLimits, RISK};
+use crate::signer::SignerContext;
+use crate::tool_error::ToolError;
+
+/// Daily candles the volatility is measured over
+const VOLATILITY_DAYS: usize = 30;
+/// Stop distance in daily standard deviations
+const STOP_DEVIATIONS: f64 = 2.;
+const MAX_RISK_PERCENT: f64 = 10.;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PositionSize {
+    pub portfolio_usd: f64,
+    pub risk_percent: f64,
+    /// loss if the stop is hit
+    pub risk_usd: f64,
+    /// standard deviation of the daily returns
+    pub daily_volatility_pct: f64,
+    pub stop_loss_pct: f64,
+    pub price_usd: f64,
+    pub size_usd: f64,
+    /// size in whole tokens at the current price
+    pub size_tokens: f64,
+    /// the limit that shrank the size, if any
+    pub capped_by: Option<&'static str>,
+}
+
+/// Standard deviation of the close to close returns, None with less than
+/// two returns
+fn daily_volatility(candles: &[Candle]) -> Option<f64> {
+    let returns = candles
+        .windows(2)
+        .filter(|pair| pair[0].close > 0.)
+        .map(|pair| pair[1].close / pair[0].close - 1.)
+        .collect::<Vec<_>>();
+    if returns.len() < 2 {
+        return None;
+    }
+    let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>()
+        / (returns.len() - 1) as f64;
+    Some(variance.sqrt())
+}
+
+fn size(
+    portfolio_usd: f64,
+    risk_percent: f64,
+    volatility: f64,
+    price_usd: f64,
+    limits: &RiskLimits,
+    traded_today_usd: f64,
+) -> PositionSize {
+    let risk_usd = portfolio_usd * risk_percent / 100.;
+    let stop_loss = (volatility * STOP_DEVIATIONS).min(1.);
+    let mut size_usd = if stop_loss > 0. {
+        risk_usd / stop_loss
+    } else {
+        portfolio_usd
+    };
+
+    let caps = [
+        (Some(portfolio_usd), "portfolio_value"),
+        (limits.max_trade_usd, "max_trade_usd"),
+        (
+            limits
+                .max_daily_volume_usd
+                .map(|max| (max - traded_today_usd).max(0.)),
+            "max_daily_volume_usd",
+        ),
+    ];
+    let mut capped_by = None;
+    for (cap, name) in caps {
+        if let Some(cap) = cap.filter(|cap| *cap < size_usd) {
+            size_usd = cap;
+            capped_by = Some(name);
+        }
+    }
+
+    PositionSize {
+        portfolio_usd,
+        risk_percent,
+        risk_usd,
+        daily_volatility_pct: volatility * 100.,
+        stop_loss_pct: stop_loss * 100.,
+        price_usd,
+        size_usd,
+        size_tokens: if price_usd > 0. {
+            size_usd / price_usd
+        } else {
+            0.
+        },
+        capped_by,
+    }
+}
+
+#[tool(description = "
+Suggests how much of a token to buy: the size risks risk_percent of the
+portfolio if the price falls two daily standard deviations (the suggested
+stop loss), capped by the risk limits of the user and their remaining daily
+volume. Use it when the user asks how much to buy, then pass size_usd or
+size_tokens to the swap.
+
+chain is sol, sonic, eth, arb or base and token the mint or contract address
+risk_percent is the share of the portfolio to risk, 1 to 2 is common, at
+most 10
+")]
+pub async fn suggest_position_size(
+    chain: String,
+    token: String,
+    risk_percent: f64,
+) -> Result<PositionSize> {
+    if risk_percent <= 0. || risk_percent > MAX_RISK_PERCENT {
+        return Err(ToolError::invalid_input(format!(
+            "Can't risk {}% of the portfolio",
+            risk_percent
+        ))
+        .with_hint("Pass a risk_percent between 0 and 10, 1 to 2 is common")
+        .into());
+    }
+    let signer = SignerContext::current().await;
+    let user_id = audit_user(signer.as_ref());
+    let wallet = |address: String| Some(address).filter(|a| !a.is_empty());
+    let user = TrackedUser {
+        user_id: user_id.clone(),
+        solana: wallet(signer.pubkey()),
+        evm: wallet(signer.address()),
+    };
+
+    let portfolio =
+        PortfolioSnapshots::value(&user, &config().snapshots).await?;
+    let candles = candles(&chain, &token, "1d", VOLATILITY_DAYS + 1).await?;
+    let volatility = daily_volatility(&candles).ok_or_else(|| {
+        ToolError::not_found(format!("Not enough price history of {}", token))
+            .with_hint("The token is too new to size a position from")
+    })?;
+    let price_usd = candles.last().map_or(0., |candle| candle.close);
+
+    Ok(size(
+        portfolio.total_usd,
+        risk_percent,
+        volatility,
+        price_usd,
+        &RISK.limits(&user_id).await?,
+        RISK.daily_volume(&user_id).await?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Duration};
+
+    #[test]
+    fn test_size() {
+        let start = DateTime::from_timestamp(1_735_689_600, 0).unwrap();
+        let candles = [100., 105., 100., 105., 100.]
+            .iter()
+            .enumerate()
+            .map(|(i, close)| Candle {
+                time: start + Duration::days(i as i64),
+                open: *close,
+                high: *close,
+                low: *close,
+                close: *close,
+            })
+            .collect::<Vec<_>>();
+        let volatility = daily_volatility(&candles).unwrap();
+        assert!((volatility - 0.0564).abs() < 0.001);
+        assert!(daily_volatility(&candles[..2]).is_none());
+
+        let limits = RiskLimits {
+            max_trade_usd: None,
+            max_daily_volume_usd: None,
+            max_slippage_bps: 100,
+            allowlist: vec![],
+            denylist: vec![],
+            new_token_hours: 24.,
+        };
+        // 1% of 10k on a 10% stop
+        let position = size(10_000., 1., 0.05, 2., &limits, 0.);
+        assert!((position.size_usd - 1_000.).abs() < 1e-9);
+        assert!((position.size_tokens - 500.).abs() < 1e-9);
+        assert_eq!(position.capped_by, None);
+
+        let limits = RiskLimits {
+            max_trade_usd: Some(800.),
+            max_daily_volume_usd: Some(1_000.),
+            ..limits
+        };
+        let position = size(10_000., 1., 0.05, 2., &limits, 500.);
+        assert_eq!(position.size_usd, 500.);
+        assert_eq!(position.capped_by, Some("max_daily_volume_usd"));
+    }
+}
@@ -0,0 +1,285 @@
+//! Per-session defaults the user states once instead of in every message:
+//! the slippage of swaps, the chain of multichain swaps and bridges, the
+//! priority fee of Solana transactions and its strategy and the language
+//! tool results are rendered in. Tools fall back to them when the LLM
+//! leaves the matching argument empty
+use std::sync::Arc;
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+
+use crate::config::config;
+use crate::execution::session_key;
+use crate::kv_store::{KVStore, KV_STORE};
+use crate::render::Locale;
+use crate::signer::SignerContext;
+use crate::solana::priority_fees::PriorityFeeStrategy;
+use crate::tool_error::ToolError;
+use crate::validation;
+
+const CHAINS: &[&str] = &["sol", "sonic", "eth", "arb", "base"];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Preferences {
+    pub slippage_bps: Option<u16>,
+    pub chain: Option<String>,
+    /// paid on top of the base fee of Solana transactions
+    pub priority_fee_lamports: Option<u64>,
+    /// compute unit price of Solana transactions, see
+    /// `solana::priority_fees`
+    pub priority_fee_strategy: Option<PriorityFeeStrategy>,
+    /// of the rendered tool results, see `render`
+    pub language: Option<Locale>,
+}
+
+impl Preferences {
+    /// Sets `key` from a value as the user said it, "default" or an empty
+    /// value clears it
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let value = value.trim().to_lowercase();
+        let clear = value.is_empty() || value == "default";
+        match key.trim() {
+            "slippage" | "slippage_bps" => {
+                self.slippage_bps = if clear {
+                    None
+                } else {
+                    Some(parse_slippage(&value)?)
+                }
+            }
+            "chain" => {
+                if !clear && !CHAINS.contains(&value.as_str()) {
+                    return Err(ToolError::invalid_input(format!(
+                        "Unsupported chain {}",
+                        value
+                    ))
+                    .with_hint(format!("Use one of {}", CHAINS.join(", ")))
+                    .into());
+                }
+                self.chain = (!clear).then_some(value);
+            }
+            "priority_fee" | "priority_fee_lamports" => {
+                self.priority_fee_lamports = if clear {
+                    None
+                } else {
+                    Some(value.parse().map_err(|_| {
+                        ToolError::invalid_input(format!(
+                            "Invalid priority fee {}",
+                            value
+                        ))
+                        .with_hint("The priority fee is in lamports")
+                    })?)
+                }
+            }
+            "priority_fee_strategy" | "fee_strategy" => {
+                self.priority_fee_strategy =
+                    if clear { None } else { Some(value.parse()?) }
+            }
+            "language" | "locale" => {
+                self.language =
+                    if clear { None } else { Some(value.parse()?) }
+            }
+            key => {
+                return Err(ToolError::invalid_input(format!(
+                    "Unknown preference {}",
+                    key
+                ))
+                .with_hint(
+                    "Use slippage, chain, priority_fee, \
+                     priority_fee_strategy or language",
+                )
+                .into())
+            }
+        }
+        Ok(())
+    }
+
+    /// `chain` unless it is empty, then the default one
+    pub fn chain_or_default(&self, chain: &str) -> Result<String> {
+        if !chain.trim().is_empty() {
+            return Ok(chain.to_string());
+        }
+        self.chain.clone().ok_or_else(|| {
+            ToolError::invalid_input(
+                "No chain given and no default chain set",
+            )
+            .with_hint("Ask the user for the chain")
+            .into()
+        })
+    }
+}
+
+/// "1%" or "0.5 percent" are percents, plain numbers and "50 bps" are
+/// basis points
+fn parse_slippage(value: &str) -> Result<u16> {
+    let invalid = || {
+        ToolError::invalid_input(format!("Invalid slippage {}", value))
+            .with_hint("Pass e.g. 1% or 100 bps")
+    };
+    let (number, scale) = match value
+        .strip_suffix('%')
+        .or_else(|| value.strip_suffix("percent"))
+    {
+        Some(percent) => (percent, 100.),
+        None => (value.trim_end_matches("bps"), 1.),
+    };
+    let number: f64 = number.trim().parse().map_err(|_| invalid())?;
+    let bps = (number * scale).round();
+    if !(0. ..=u16::MAX as f64).contains(&bps) {
+        return Err(invalid().into());
+    }
+    validation::slippage_bps(bps as u16)?;
+    Ok(bps as u16)
+}
+
+pub struct SessionPreferences {
+    store: Arc<dyn KVStore>,
+}
+
+pub static PREFERENCES: Lazy<SessionPreferences> =
+    Lazy::new(|| SessionPreferences::new(KV_STORE.clone()));
+
+impl SessionPreferences {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self { store }
+    }
+
+    fn key(session: &str) -> String {
+        format!("preferences:{}", session)
+    }
+
+    pub async fn get(&self, session: &str) -> Result<Preferences> {
+        match self.store.get(&Self::key(session)).await? {
+            Some(preferences) => Ok(serde_json::from_str(&preferences)?),
+            None => Ok(Preferences::default()),
+        }
+    }
+
+    pub async fn set(
+        &self,
+        session: &str,
+        preferences: &Preferences,
+    ) -> Result<()> {
+        self.store
+            .set(&Self::key(session), &serde_json::to_string(preferences)?)
+            .await
+    }
+}
+
+/// Preferences of the session of the current signer
+pub async fn current() -> Result<Preferences> {
+    let signer = SignerContext::current().await;
+    PREFERENCES.get(&session_key(signer.as_ref())).await
+}
+
+/// Language of the session, English when it isn't set or can't be read
+pub async fn locale() -> Locale {
+    match current().await {
+        Ok(preferences) => preferences.language.unwrap_or_default(),
+        Err(e) => {
+            tracing::warn!(?e, "preferences not read");
+            Locale::default()
+        }
+    }
+}
+
+/// `slippage_bps` unless it is 0, then the session default
+pub async fn slippage_bps(slippage_bps: u16) -> Result<u16> {
+    if slippage_bps != 0 {
+        return Ok(slippage_bps);
+    }
+    Ok(current()
+        .await?
+        .slippage_bps
+        .unwrap_or(config().trading.default_slippage_bps))
+}
+
+/// `chain` unless it is empty, then the session default
+pub async fn chain(chain: &str) -> Result<String> {
+    if !chain.trim().is_empty() {
+        return Ok(chain.to_string());
+    }
+    current().await?.chain_or_default(chain)
+}
+
+#[tool(description = "
+Sets a default of the session so the user doesn't have to repeat it in every
+message. key is one of:
+- slippage: the slippage of swaps, e.g. 1% or 50 bps
+- chain: the chain of multichain swaps and bridges, sol, sonic, eth, arb or
+  base
+- priority_fee: the priority fee of Solana transactions in lamports
+- priority_fee_strategy: off, low, medium, high or max, see
+  set_priority_fee_strategy
+- language: the language tool results are shown to the user in, en or ru
+value \"default\" clears the preference
+")]
+pub async fn set_preference(
+    key: String,
+    value: String,
+) -> Result<Preferences> {
+    let signer = SignerContext::current().await;
+    let session = session_key(signer.as_ref());
+    let mut preferences = PREFERENCES.get(&session).await?;
+    preferences.set(&key, &value)?;
+    PREFERENCES.set(&session, &preferences).await?;
+    Ok(preferences)
+}
+
+#[tool(description = "
+Returns the defaults of the session: slippage_bps, chain,
+priority_fee_lamports, priority_fee_strategy and language, null ones are not
+set
+")]
+pub async fn get_preferences() -> Result<Preferences> {
+    current().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    #[tokio::test]
+    async fn test_preferences() {
+        let store = SessionPreferences::new(Arc::new(InMemoryKVStore::new()));
+        let mut preferences = store.get("session").await.unwrap();
+        assert_eq!(preferences, Preferences::default());
+
+        preferences.set("slippage", "1%").unwrap();
+        preferences.set("chain", "Arb").unwrap();
+        preferences.set("priority_fee", "10000").unwrap();
+        store.set("session", &preferences).await.unwrap();
+
+        let preferences = store.get("session").await.unwrap();
+        assert_eq!(preferences.slippage_bps, Some(100));
+        assert_eq!(preferences.chain.as_deref(), Some("arb"));
+        assert_eq!(preferences.priority_fee_lamports, Some(10_000));
+        assert_eq!(store.get("other").await.unwrap(), Preferences::default());
+    }
+
+    #[test]
+    fn test_set_preference() {
+        let mut preferences = Preferences::default();
+        preferences.set("slippage", "75 bps").unwrap();
+        assert_eq!(preferences.slippage_bps, Some(75));
+        preferences.set("slippage", "default").unwrap();
+        assert_eq!(preferences.slippage_bps, None);
+
+        assert!(preferences.set("slippage", "90%").is_err());
+        assert!(preferences.set("chain", "doge").is_err());
+        assert!(preferences.set("gas", "1").is_err());
+
+        preferences.set("priority_fee_strategy", "high").unwrap();
+        assert_eq!(
+            preferences.priority_fee_strategy,
+            Some(PriorityFeeStrategy::High)
+        );
+        assert!(preferences.set("priority_fee_strategy", "fast").is_err());
+
+        preferences.set("language", "RU").unwrap();
+        assert_eq!(preferences.language, Some(Locale::Ru));
+        assert!(preferences.set("language", "klingon").is_err());
+    }
+}
@@ -0,0 +1,281 @@
+//! Balance-change previews of pending actions. Before a value-moving action
+//! is parked for confirmation it runs once under a simulating paper signer,
+//! the balances of the wallet before and after each simulated transaction
+//! are diffed, so the user confirms what the action does to their wallet and
+//! not only what the tool says it does. Network fees are left out
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use alloy::primitives::{Address, I256, U256};
+use anyhow::Result;
+use serde::Serialize;
+use solana_sdk::account::Account;
+use solana_sdk::program_pack::Pack;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::common::{evm_chain, solana_rpc};
+use crate::confirmation::preconfirmed;
+use crate::evm::balance::{token_balance, token_decimals};
+use crate::evm::simulate::BalanceChange;
+use crate::evm::util::EvmProvider;
+use crate::pricing::to_ui_amount;
+use crate::signer::paper::PaperSigner;
+use crate::signer::{SignerContext, TransactionSigner};
+
+/// Token of the native balance, lamports on Solana and wei on EVM chains
+pub(crate) const NATIVE: &str = "native";
+
+/// Change of a balance of the wallet, in human units
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BalanceDelta {
+    pub chain: String,
+    /// mint or contract address, "native" for SOL and the gas tokens
+    pub token: String,
+    pub symbol: Option<String>,
+    pub before: f64,
+    pub after: f64,
+    pub delta: f64,
+}
+
+impl BalanceDelta {
+    fn new(
+        chain: &str,
+        token: &str,
+        symbol: Option<&str>,
+        (before, after): (&str, &str),
+        decimals: u8,
+    ) -> Result<Self> {
+        let before = to_ui_amount(before, decimals)?;
+        let after = to_ui_amount(after, decimals)?;
+        Ok(Self {
+            chain: chain.to_string(),
+            token: token.to_string(),
+            symbol: symbol.map(str::to_string),
+            before,
+            after,
+            delta: after - before,
+        })
+    }
+}
+
+/// Runs `call` under a simulating paper signer wrapping `signer` and
+/// returns the balance changes of the transactions it would send. Fails
+/// when the action or one of its simulations fails
+pub async fn balance_preview(
+    signer: Arc<dyn TransactionSigner>,
+    call: impl Future<Output = Result<String>> + Send,
+) -> Result<Vec<BalanceDelta>> {
+    let paper = Arc::new(PaperSigner::new(signer).with_simulation());
+    SignerContext::with_signer(paper.clone(), preconfirmed(call)).await?;
+    Ok(paper.balance_deltas())
+}
+
+/// Mint and amount of an SPL token account held by `owner`
+fn token_account(owner: &Pubkey, account: &Account) -> Option<(String, u64)> {
+    if account.owner != spl_token::id()
+        && account.owner != spl_token_2022::id()
+    {
+        return None;
+    }
+    // token-2022 extensions follow the base layout
+    let data = account.data.get(..spl_token::state::Account::LEN)?;
+    let token = spl_token::state::Account::unpack_from_slice(data).ok()?;
+    (token.owner == *owner).then(|| (token.mint.to_string(), token.amount))
+}
+
+/// Raw balances of `owner` before and after by token, from the accounts of
+/// the transaction and their states. Unchanged balances are left out
+pub(crate) fn solana_changes(
+    owner: &Pubkey,
+    accounts: &[(Pubkey, Option<Account>, Option<Account>)],
+) -> Vec<(String, u64, u64)> {
+    let mut balances = BTreeMap::<String, (u64, u64)>::new();
+    for (address, before, after) in accounts {
+        if address == owner {
+            let lamports =
+                |a: &Option<Account>| a.as_ref().map_or(0, |a| a.lamports);
+            let entry = balances.entry(NATIVE.to_string()).or_default();
+            entry.0 += lamports(before);
+            entry.1 += lamports(after);
+            continue;
+        }
+        let before = before.as_ref().and_then(|a| token_account(owner, a));
+        let after = after.as_ref().and_then(|a| token_account(owner, a));
+        let Some((mint, _)) = before.as_ref().or(after.as_ref()) else {
+            continue;
+        };
+        let entry = balances.entry(mint.clone()).or_default();
+        entry.0 += before.map_or(0, |(_, amount)| amount);
+        entry.1 += after.map_or(0, |(_, amount)| amount);
+    }
+    balances
+        .into_iter()
+        .filter(|(_, (before, after))| before != after)
+        .map(|(token, (before, after))| (token, before, after))
+        .collect()
+}
+
+/// Balance changes of `owner` from the states of the writable accounts of a
+/// simulated transaction
+pub(crate) async fn solana_deltas(
+    owner: &Pubkey,
+    accounts: &[(Pubkey, Option<Account>, Option<Account>)],
+) -> Result<Vec<BalanceDelta>> {
+    let mut deltas = vec![];
+    for (token, before, after) in solana_changes(owner, accounts) {
+        let (symbol, decimals) = match token.as_str() {
+            NATIVE => (Some("SOL"), 9),
+            mint => {
+                let supply = solana_rpc()
+                    .get_token_supply(&Pubkey::from_str(mint)?)
+                    .await?;
+                (None, supply.decimals)
+            }
+        };
+        deltas.push(BalanceDelta::new(
+            "sol",
+            &token,
+            symbol,
+            (&before.to_string(), &after.to_string()),
+            decimals,
+        )?);
+    }
+    Ok(deltas)
+}
+
+fn chain_name(chain_id: Option<u64>) -> String {
+    match chain_id {
+        None => evm_chain(),
+        Some(1) => "eth".to_string(),
+        Some(146) => "sonic".to_string(),
+        Some(8453) => "base".to_string(),
+        Some(42161) => "arb".to_string(),
+        Some(chain_id) => chain_id.to_string(),
+    }
+}
+
+/// Balance changes of `owner` in human units from the raw deltas of an EVM
+/// simulation
+pub(crate) async fn evm_deltas(
+    chain_id: Option<u64>,
+    owner: Address,
+    changes: &[BalanceChange],
+    provider: &EvmProvider,
+) -> Result<Vec<BalanceDelta>> {
+    use alloy::providers::Provider;
+
+    let chain = chain_name(chain_id);
+    let mut deltas = vec![];
+    for change in changes {
+        let delta = I256::from_str(&change.delta)?;
+        if delta.is_zero() {
+            continue;
+        }
+        let (before, decimals) = match change.token.as_str() {
+            NATIVE => (provider.get_balance(owner).await?, 18),
+            token => (
+                U256::from_str(
+                    &token_balance(
+                        owner.to_string(),
+                        token.to_string(),
+                        provider,
+                    )
+                    .await?,
+                )?,
+                token_decimals(token.to_string(), provider).await?,
+            ),
+        };
+        let after = I256::from_raw(before) + delta;
+        deltas.push(BalanceDelta::new(
+            &chain,
+            &change.token,
+            change.symbol.as_deref(),
+            (&before.to_string(), &after.to_string()),
+            decimals,
+        )?);
+    }
+    Ok(deltas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token_account_of(
+        owner: &Pubkey,
+        mint: &Pubkey,
+        amount: u64,
+    ) -> Account {
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        spl_token::state::Account {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            state: spl_token::state::AccountState::Initialized,
+            ..Default::default()
+        }
+        .pack_into_slice(&mut data);
+        Account {
+            lamports: 2_039_280,
+            data,
+            owner: spl_token::id(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_solana_changes() {
+        let owner = Pubkey::new_unique();
+        let usdc = Pubkey::new_unique();
+        let bonk = Pubkey::new_unique();
+        let wallet = |lamports| Account {
+            lamports,
+            ..Default::default()
+        };
+        let accounts = vec![
+            (
+                owner,
+                Some(wallet(2_000_000_000)),
+                Some(wallet(1_500_000_000)),
+            ),
+            (
+                Pubkey::new_unique(),
+                Some(token_account_of(&owner, &usdc, 5_000_000)),
+                Some(token_account_of(&owner, &usdc, 80_000_000)),
+            ),
+            // the swap creates the account of the output token
+            (
+                Pubkey::new_unique(),
+                None,
+                Some(token_account_of(&owner, &bonk, 42)),
+            ),
+            // the pool's vault is not the wallet's
+            (
+                Pubkey::new_unique(),
+                Some(token_account_of(&Pubkey::new_unique(), &usdc, 10)),
+                Some(token_account_of(&Pubkey::new_unique(), &usdc, 1)),
+            ),
+        ];
+
+        let changes = solana_changes(&owner, &accounts);
+        let mut expected = vec![
+            (NATIVE.to_string(), 2_000_000_000, 1_500_000_000),
+            (usdc.to_string(), 5_000_000, 80_000_000),
+            (bonk.to_string(), 0, 42),
+        ];
+        expected.sort();
+        assert_eq!(changes, expected);
+
+        let delta = BalanceDelta::new(
+            "sol",
+            NATIVE,
+            Some("SOL"),
+            ("2000000000", "1500000000"),
+            9,
+        )
+        .unwrap();
+        assert_eq!((delta.before, delta.after, delta.delta), (2., 1.5, -0.5));
+    }
+}
@@ -0,0 +1,209 @@
+//! Chain-agnostic token pricing, used to value amounts passed to tools in
+//! USD. Backed by the LiFi token endpoint which returns both the decimals and
+//! the price of any token it can route. Price history comes from the
+//! GeckoTerminal candles of the most liquid DexScreener pool of the token
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::common::http_client;
+use crate::cross_chain::lifi::LiFi;
+use crate::dexscreener::search_ticker;
+use crate::metrics::observe_api;
+
+const MAX_CANDLES: usize = 1000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenPrice {
+    pub address: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub price_usd: f64,
+}
+
+/// LiFi takes chain ids for EVM chains, the names used across the crate
+/// are mapped to those
+pub fn lifi_chain(chain: &str) -> &str {
+    match chain {
+        "sonic" | "s" => "146",
+        "eth" => "1",
+        "arb" => "42161",
+        "base" => "8453",
+        other => other,
+    }
+}
+
+/// Converts a raw integer amount into a decimal amount, e.g. 1500000 with
+/// 6 decimals is 1.5
+pub fn to_ui_amount(amount: &str, decimals: u8) -> Result<f64> {
+    let amount: f64 = amount
+        .parse()
+        .map_err(|_| anyhow!("Invalid amount {}", amount))?;
+    Ok(amount / 10f64.powi(decimals as i32))
+}
+
+pub async fn token_price(chain: &str, token: &str) -> Result<TokenPrice> {
+    let token = LiFi::new(None).get_token(lifi_chain(chain), token).await?;
+    let price_usd = token
+        .price_usd
+        .as_deref()
+        .and_then(|price| price.parse().ok())
+        .ok_or_else(|| anyhow!("No USD price for {}", token.symbol))?;
+    let decimals = token
+        .decimals
+        .as_u64()
+        .ok_or_else(|| anyhow!("Invalid decimals for {}", token.symbol))?;
+
+    Ok(TokenPrice {
+        address: token.address,
+        symbol: token.symbol,
+        decimals: decimals as u8,
+        price_usd,
+    })
+}
+
+/// USD value of a raw `amount` of `token` on `chain`
+pub async fn usd_value(
+    chain: &str,
+    token: &str,
+    amount: &str,
+) -> Result<f64> {
+    let price = token_price(chain, token).await?;
+    Ok(to_ui_amount(amount, price.decimals)? * price.price_usd)
+}
+
+/// USD candle of a token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Candle {
+    pub time: DateTime<Utc>,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+}
+
+/// GeckoTerminal timeframe and aggregate of 1d, 4h, 1h, 15m, 5m and 1m
+fn timeframe(interval: &str) -> Result<(&'static str, u32)> {
+    Ok(match interval {
+        "1d" => ("day", 1),
+        "4h" => ("hour", 4),
+        "1h" => ("hour", 1),
+        "15m" => ("minute", 15),
+        "5m" => ("minute", 5),
+        "1m" => ("minute", 1),
+        _ => {
+            return Err(anyhow!(
+                "Invalid interval {}, use 1d, 4h, 1h, 15m, 5m or 1m",
+                interval
+            ))
+        }
+    })
+}
+
+/// DexScreener and GeckoTerminal names of the chains of the crate
+pub(crate) fn networks(chain: &str) -> Result<(&'static str, &'static str)> {
+    Ok(match chain {
+        "sol" => ("solana", "solana"),
+        "sonic" | "s" => ("sonic", "sonic"),
+        "eth" => ("ethereum", "eth"),
+        "arb" => ("arbitrum", "arbitrum"),
+        "base" => ("base", "base"),
+        _ => return Err(anyhow!("No price history for chain {}", chain)),
+    })
+}
+
+fn parse_candles(response: &Value) -> Result<Vec<Candle>> {
+    let list = response["data"]["attributes"]["ohlcv_list"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Invalid OHLCV response"))?;
+    let mut candles = list
+        .iter()
+        .map(|row| {
+            let field = |i: usize| {
+                row[i].as_f64().ok_or_else(|| anyhow!("Invalid candle"))
+            };
+            Ok(Candle {
+                time: DateTime::from_timestamp(field(0)? as i64, 0)
+                    .ok_or_else(|| anyhow!("Invalid candle time"))?,
+                open: field(1)?,
+                high: field(2)?,
+                low: field(3)?,
+                close: field(4)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // newest first in the response
+    candles.sort_by_key(|candle| candle.time);
+    Ok(candles)
+}
+
+/// Last `limit` USD candles of `token` on `chain`, oldest first
+pub async fn candles(
+    chain: &str,
+    token: &str,
+    interval: &str,
+    limit: usize,
+) -> Result<Vec<Candle>> {
+    let (timeframe, aggregate) = timeframe(interval)?;
+    let (dexscreener_chain, network) = networks(chain)?;
+
+    let pool = search_ticker(token.to_string())
+        .await?
+        .pairs
+        .into_iter()
+        .filter(|pair| pair.chain_id == dexscreener_chain)
+        .filter(|pair| {
+            pair.base_token.address.eq_ignore_ascii_case(token)
+                || pair.quote_token.address.eq_ignore_ascii_case(token)
+        })
+        .max_by(|a, b| a.liquidity.usd.total_cmp(&b.liquidity.usd))
+        .ok_or_else(|| anyhow!("No pool of {} on {}", token, chain))?;
+
+    let url = format!(
+        "https://api.geckoterminal.com/api/v2/networks/{}/pools/{}/ohlcv/{}?aggregate={}&limit={}&currency=usd&token={}",
+        network,
+        pool.pair_address,
+        timeframe,
+        aggregate,
+        limit.min(MAX_CANDLES),
+        token
+    );
+    let response = observe_api("geckoterminal", async {
+        Ok(http_client()
+            .get(&url)
+            .send()
+            .await?
+            .json::<Value>()
+            .await?)
+    })
+    .await?;
+    parse_candles(&response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_ui_amount() {
+        assert_eq!(to_ui_amount("1500000", 6).unwrap(), 1.5);
+        assert_eq!(to_ui_amount("1000000000000000000", 18).unwrap(), 1.);
+        assert!(to_ui_amount("1.5 SOL", 9).is_err());
+    }
+
+    #[test]
+    fn test_parse_candles() {
+        let response = serde_json::json!({
+            "data": { "attributes": { "ohlcv_list": [
+                [1712620800, 2., 3., 1.5, 2.5, 1000.],
+                [1712534400, 1., 2.2, 0.9, 2., 500.],
+            ]}}
+        });
+        let candles = parse_candles(&response).unwrap();
+        assert_eq!(candles.len(), 2);
+        assert_eq!(candles[0].open, 1.);
+        assert_eq!(candles[1].close, 2.5);
+        assert!(timeframe("2h").is_err());
+    }
+}
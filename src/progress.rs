@@ -0,0 +1,62 @@
+//! Progress events of long running tools (bridges, multi-step swaps). Tools
+//! report the stage they reached, the host forwards the events to the UI
+//! while the tool is still running instead of waiting for the final result
+use serde::Serialize;
+use std::future::Future;
+use tokio::sync::mpsc::UnboundedSender;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    QuoteFetched,
+    Signing,
+    Broadcast,
+    Confirmed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressEvent {
+    pub stage: Stage,
+    pub detail: Option<String>,
+}
+
+tokio::task_local! {
+    static PROGRESS: UnboundedSender<ProgressEvent>;
+}
+
+/// Runs `f` with the progress reported inside of it sent to `tx`
+pub async fn with_progress<T>(
+    tx: UnboundedSender<ProgressEvent>,
+    f: impl Future<Output = T>,
+) -> T {
+    PROGRESS.scope(tx, f).await
+}
+
+/// Reports a stage of the current tool call, a no-op when nobody listens.
+/// Has to be called from the task of the tool call as task locals do not
+/// carry over to spawned tasks
+pub fn report(stage: Stage, detail: Option<String>) {
+    let _ = PROGRESS.try_with(|tx| tx.send(ProgressEvent { stage, detail }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_report() {
+        // outside of a scope reporting is a no-op
+        report(Stage::Signing, None);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        with_progress(tx, async {
+            report(Stage::Broadcast, Some("sig".to_string()));
+        })
+        .await;
+
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.stage, Stage::Broadcast);
+        assert_eq!(event.detail.as_deref(), Some("sig"));
+        assert!(rx.recv().await.is_none());
+    }
+}
@@ -0,0 +1,200 @@
+//! Two-phase quote-then-execute flow for swaps. A `quote_*` tool fetches the
+//! route, caches everything needed to execute it and returns a `Quote` with a
+//! human readable summary (amounts, fees, slippage); nothing is spent until
+//! `execute_quote` is called with the id after the user agreed to the terms
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::confirmation::{ActionFuture, BoxedAction};
+use crate::memory::{last_quote_id, LAST};
+use crate::signer::SignerContext;
+use crate::tool_error::{ErrorCode, ToolError};
+
+/// Quotes go stale quickly, executing an old route mostly ends up failing on
+/// slippage so they are kept much shorter than pending confirmations
+const DEFAULT_TTL_SECS: i64 = 60;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Quote {
+    pub id: String,
+    pub venue: String,
+    pub summary: Value,
+    pub expires_at: DateTime<Utc>,
+}
+
+struct StoredQuote {
+    quote: Quote,
+    owner: Option<String>,
+    execute: BoxedAction,
+}
+
+pub struct QuoteCache {
+    quotes: Mutex<HashMap<String, StoredQuote>>,
+    ttl: Duration,
+}
+
+pub static QUOTES: Lazy<QuoteCache> = Lazy::new(|| {
+    let ttl = std::env::var("QUOTE_TTL_SECS")
+        .ok()
+        .and_then(|ttl| ttl.parse().ok())
+        .unwrap_or(DEFAULT_TTL_SECS);
+    QuoteCache::new(Duration::seconds(ttl))
+});
+
+impl QuoteCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            quotes: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    fn insert(
+        &self,
+        venue: &str,
+        summary: Value,
+        owner: Option<String>,
+        execute: BoxedAction,
+    ) -> Quote {
+        let quote = Quote {
+            id: format!("{:016x}", rand::random::<u64>()),
+            venue: venue.to_string(),
+            summary,
+            expires_at: Utc::now() + self.ttl,
+        };
+
+        let mut quotes = self.quotes.lock().unwrap();
+        quotes.retain(|_, stored| stored.quote.expires_at > Utc::now());
+        quotes.insert(
+            quote.id.clone(),
+            StoredQuote {
+                quote: quote.clone(),
+                owner,
+                execute,
+            },
+        );
+        quote
+    }
+
+    /// Removes the quote if it exists and belongs to `owner`, a quote can
+    /// only ever be executed once
+    fn take(&self, id: &str, owner: &Option<String>) -> Result<StoredQuote> {
+        let mut quotes = self.quotes.lock().unwrap();
+        match quotes.get(id) {
+            Some(stored) if stored.owner != *owner => {
+                return Err(not_found(id));
+            }
+            None => return Err(not_found(id)),
+            _ => {}
+        }
+
+        let stored = quotes.remove(id).unwrap();
+        if stored.quote.expires_at <= Utc::now() {
+            return Err(ToolError::new(
+                ErrorCode::Expired,
+                format!("Quote {} expired", id),
+            )
+            .with_hint("Fetch a new quote and show it to the user")
+            .into());
+        }
+        Ok(stored)
+    }
+
+    pub async fn execute(
+        &self,
+        id: &str,
+        owner: &Option<String>,
+    ) -> Result<String> {
+        let stored = self.take(id, owner)?;
+        (stored.execute)().await
+    }
+}
+
+fn not_found(id: &str) -> anyhow::Error {
+    ToolError::not_found(format!("Quote {} not found", id))
+        .with_hint("Quotes can only be executed once, fetch a new one")
+        .into()
+}
+
+/// Caches the quote for the current user, `execute` is what `execute_quote`
+/// runs, it is called under the signer of whoever executes the quote
+pub async fn cache_quote<F, Fut>(
+    venue: &str,
+    summary: Value,
+    execute: F,
+) -> Result<Quote>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<String>> + Send + 'static,
+{
+    let owner = SignerContext::current().await.user_id();
+    Ok(QUOTES.insert(
+        venue,
+        summary,
+        owner,
+        Box::new(move || Box::pin(execute()) as ActionFuture),
+    ))
+}
+
+#[tool(description = "
+Executes a swap quote previously returned by one of the quote_* tools.
+
+Always show the quote summary (amounts, minimum received, fees, price impact
+and slippage) to the user first and only call this once they agreed to it.
+Quotes expire after a minute, fetch a new one if this fails with expired.
+
+quote_id can be \"last\" to execute the most recent quote
+")]
+pub async fn execute_quote(quote_id: String) -> Result<String> {
+    let quote_id = match quote_id.as_str() {
+        LAST => last_quote_id().await?,
+        _ => quote_id,
+    };
+    let owner = SignerContext::current().await.user_id();
+    QUOTES.execute(&quote_id, &owner).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store_quote(cache: &QuoteCache, owner: &str) -> Quote {
+        cache.insert(
+            "jupiter",
+            serde_json::json!({ "in_amount": "1000000" }),
+            Some(owner.to_string()),
+            Box::new(|| Box::pin(async { Ok("sig".to_string()) })),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_quote_executes_once() {
+        let cache = QuoteCache::new(Duration::seconds(60));
+        let quote = store_quote(&cache, "alice");
+        let alice = Some("alice".to_string());
+
+        assert!(cache.execute(&quote.id, &None).await.is_err());
+        assert_eq!(cache.execute(&quote.id, &alice).await.unwrap(), "sig");
+        assert!(cache.execute(&quote.id, &alice).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_expired_quote_is_not_executed() {
+        let cache = QuoteCache::new(Duration::seconds(-1));
+        let quote = store_quote(&cache, "alice");
+
+        let err = cache
+            .execute(&quote.id, &Some("alice".to_string()))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("expired"));
+    }
+}
@@ -0,0 +1,319 @@
+//! Per-user rate limits of value-moving tool calls, enforced when the call
+//! is dispatched so a runaway LLM loop can't drain a wallet through rapid
+//! fire transactions. Windows are sliding and kept in memory, the limits
+//! follow the reloads of the config. A call reserves its slot when it is
+//! checked, so concurrent calls can't all pass the same check, and gives it
+//! back when it failed without sending a transaction
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use serde_json::json;
+use tokio::sync::watch;
+
+use crate::cancellation::CancellationToken;
+use crate::config::{subscribe, Config};
+use crate::execution::moves_value;
+use crate::signer::TransactionSigner;
+use crate::tool_error::{ErrorCode, ToolError};
+
+const MINUTE: Duration = Duration::from_secs(60);
+const HOUR: Duration = Duration::from_secs(3600);
+
+const SWAP_TOOLS: &[&str] = &[
+    "perform_jupiter_swap",
+    "trade",
+    "multichain_swap",
+    "buy_pump_fun_token",
+    "sell_pump_fun_token",
+    "execute_quote",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimits {
+    pub swaps_per_minute: usize,
+    pub transactions_per_hour: usize,
+}
+
+impl RateLimits {
+    /// rate_limits of the config (RATE_LIMIT_SWAPS_PER_MINUTE and
+    /// RATE_LIMIT_TRANSACTIONS_PER_HOUR), 0 disables the limit
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            swaps_per_minute: config.rate_limits.swaps_per_minute,
+            transactions_per_hour: config.rate_limits.transactions_per_hour,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Window {
+    swaps: VecDeque<Instant>,
+    transactions: VecDeque<Instant>,
+}
+
+pub struct RateLimiter {
+    limits: Mutex<RateLimits>,
+    updates: Option<Mutex<watch::Receiver<Arc<Config>>>>,
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+pub static RATE_LIMITER: Lazy<RateLimiter> = Lazy::new(|| {
+    let updates = subscribe();
+    let limits = RateLimits::from_config(&updates.borrow());
+    RateLimiter {
+        updates: Some(Mutex::new(updates)),
+        ..RateLimiter::new(limits)
+    }
+});
+
+/// Drops the calls older than `period` and returns how long until the
+/// oldest remaining one leaves the window if `limit` is reached
+fn retry_after(
+    calls: &mut VecDeque<Instant>,
+    limit: usize,
+    period: Duration,
+    now: Instant,
+) -> Option<Duration> {
+    while calls
+        .front()
+        .is_some_and(|call| now.duration_since(*call) >= period)
+    {
+        calls.pop_front();
+    }
+    if limit == 0 || calls.len() < limit {
+        return None;
+    }
+    calls
+        .front()
+        .map(|oldest| period - now.duration_since(*oldest))
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits: Mutex::new(limits),
+            updates: None,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The limits, updated when the config was reloaded
+    fn limits(&self) -> RateLimits {
+        let mut limits = self.limits.lock().unwrap();
+        if let Some(updates) = &self.updates {
+            let mut updates = updates.lock().unwrap();
+            if updates.has_changed().unwrap_or(false) {
+                *limits =
+                    RateLimits::from_config(&updates.borrow_and_update());
+                tracing::info!(?limits, "rate limits updated");
+            }
+        }
+        limits.clone()
+    }
+
+    /// Reserves a call of `tool` by `user` unless it would break a limit,
+    /// read-only tools are never limited. The check and the reservation
+    /// are made under one lock
+    pub fn reserve(
+        &self,
+        user: &str,
+        tool: &str,
+        now: Instant,
+    ) -> Result<Reservation<'_>> {
+        let mut reservation = Reservation {
+            limiter: self,
+            user: user.to_string(),
+            swap: false,
+            at: now,
+            kept: true,
+            signed: None,
+        };
+        if !moves_value(tool) {
+            return Ok(reservation);
+        }
+        let swap = SWAP_TOOLS.contains(&tool);
+        let limits = self.limits();
+
+        let mut windows = self.windows.lock().unwrap();
+        let window = windows.entry(user.to_string()).or_default();
+        let swaps = retry_after(
+            &mut window.swaps,
+            limits.swaps_per_minute,
+            MINUTE,
+            now,
+        );
+        let transactions = retry_after(
+            &mut window.transactions,
+            limits.transactions_per_hour,
+            HOUR,
+            now,
+        );
+
+        let exceeded = match (swaps.filter(|_| swap), transactions) {
+            (_, Some(wait)) => Some((
+                wait,
+                format!(
+                    "at most {} transactions per hour",
+                    limits.transactions_per_hour
+                ),
+            )),
+            (Some(wait), None) => Some((
+                wait,
+                format!(
+                    "at most {} swaps per minute",
+                    limits.swaps_per_minute
+                ),
+            )),
+            (None, None) => None,
+        };
+        if let Some((wait, limit)) = exceeded {
+            let retry_after_secs = wait.as_secs().max(1);
+            return Err(ToolError::new(
+                ErrorCode::RateLimited,
+                format!("Rate limit reached, {}", limit),
+            )
+            .with_hint(format!(
+                "Do not retry in a loop, tell the user it can be retried in {} seconds",
+                retry_after_secs
+            ))
+            .with_details(json!({ "retry_after_secs": retry_after_secs }))
+            .into());
+        }
+
+        if swap {
+            window.swaps.push_back(now);
+        }
+        window.transactions.push_back(now);
+        reservation.swap = swap;
+        reservation.kept = false;
+        Ok(reservation)
+    }
+
+    fn release(&self, reservation: &Reservation<'_>) {
+        let remove = |calls: &mut VecDeque<Instant>| {
+            if let Some(i) = calls.iter().position(|c| *c == reservation.at) {
+                calls.remove(i);
+            }
+        };
+        let mut windows = self.windows.lock().unwrap();
+        if let Some(window) = windows.get_mut(&reservation.user) {
+            if reservation.swap {
+                remove(&mut window.swaps);
+            }
+            remove(&mut window.transactions);
+        }
+    }
+}
+
+/// Slot of a call in the windows, given back when dropped unless kept
+pub struct Reservation<'a> {
+    limiter: &'a RateLimiter,
+    user: String,
+    swap: bool,
+    at: Instant,
+    kept: bool,
+    /// token of the call, a call that signed a transaction keeps its slot
+    signed: Option<CancellationToken>,
+}
+
+impl Reservation<'_> {
+    /// Keeps the slot if the call is dropped after signing, e.g. timed out
+    pub fn keep_once_signed(mut self, token: &CancellationToken) -> Self {
+        self.signed = Some(token.clone());
+        self
+    }
+
+    /// Counts the call, it succeeded
+    pub fn keep(mut self) {
+        self.kept = true;
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let signed = self.signed.as_ref().is_some_and(|t| t.has_signed());
+        if !self.kept && !signed {
+            self.limiter.release(self);
+        }
+    }
+}
+
+/// Rate limits are per user, sessions of a user share them
+pub fn rate_limit_key(signer: &dyn TransactionSigner) -> String {
+    signer
+        .user_id()
+        .or_else(|| signer.session_id())
+        .unwrap_or_else(|| "local".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancellation::begin_signing;
+    use crate::signer::SignerContext;
+
+    #[test]
+    fn test_rate_limits() {
+        let limiter = RateLimiter::new(RateLimits {
+            swaps_per_minute: 2,
+            transactions_per_hour: 3,
+        });
+        let start = Instant::now();
+        let check = |user: &str, tool: &str, now: Instant| {
+            limiter.reserve(user, tool, now).map(Reservation::keep)
+        };
+
+        assert!(check("alice", "trade", start).is_ok());
+        assert!(check("alice", "trade", start).is_ok());
+        let error = check("alice", "trade", start).unwrap_err();
+        let error = ToolError::from_anyhow(&error);
+        assert_eq!(error.code, ErrorCode::RateLimited);
+        assert_eq!(error.details.unwrap()["retry_after_secs"], 60);
+
+        // other users and read-only tools are not affected
+        assert!(check("bob", "trade", start).is_ok());
+        assert!(check("alice", "get_portfolio", start).is_ok());
+
+        let later = start + MINUTE;
+        assert!(check("alice", "transfer_sol", later).is_ok());
+        // the hourly transaction limit is reached with the swaps
+        assert!(check("alice", "transfer_sol", later).is_err());
+        assert!(check("alice", "trade", start + HOUR).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_failed_calls_are_not_counted() {
+        let limiter = RateLimiter::new(RateLimits {
+            swaps_per_minute: 1,
+            transactions_per_hour: 0,
+        });
+        let now = Instant::now();
+
+        // in flight calls hold their slot
+        let first = limiter.reserve("alice", "trade", now).unwrap();
+        assert!(limiter.reserve("alice", "trade", now).is_err());
+        // failed, given back
+        drop(first);
+        let second = limiter.reserve("alice", "trade", now).unwrap();
+        second.keep();
+        assert!(limiter.reserve("alice", "trade", now).is_err());
+
+        // a call dropped after signing keeps it
+        let token = CancellationToken::new();
+        let later = now + MINUTE;
+        let signed = limiter
+            .reserve("bob", "trade", later)
+            .unwrap()
+            .keep_once_signed(&token);
+        SignerContext::with_cancellation(token.clone(), async {
+            begin_signing().await.map(|_| ())
+        })
+        .await
+        .unwrap();
+        drop(signed);
+        assert!(limiter.reserve("bob", "trade", later).is_err());
+    }
+}
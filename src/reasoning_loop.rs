@@ -9,9 +9,20 @@ use std::io::Write;
 use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
 
+use crate::dispatch::dispatch_tool_call;
+use crate::progress::{with_progress, ProgressEvent};
+
 pub enum LoopResponse {
     Message(String),
-    ToolCall { name: String, result: String },
+    ToolCall {
+        name: String,
+        result: String,
+    },
+    /// emitted while the tool `name` is still running
+    Progress {
+        name: String,
+        event: ProgressEvent,
+    },
 }
 
 pub struct ReasoningLoop {
@@ -39,7 +50,7 @@ impl ReasoningLoop {
         let mut current_messages = messages;
 
         'outer: loop {
-            println!("current_messages: {:?}", current_messages);
+            tracing::debug!(messages = current_messages.len(), "chat turn");
             let mut stream =
                 self.agent.stream_chat("", current_messages.clone()).await?;
             let mut current_response = String::new();
@@ -57,11 +68,36 @@ impl ReasoningLoop {
                         current_response.push_str(&text);
                     }
                     StreamingChoice::ToolCall(name, tool_id, params) => {
-                        let result = self
-                            .agent
-                            .tools
-                            .call(&name, params.to_string())
-                            .await;
+                        let (progress_tx, mut progress_rx) =
+                            tokio::sync::mpsc::unbounded_channel();
+                        let forward_task = tx.clone().map(|tx| {
+                            let name = name.clone();
+                            tokio::spawn(async move {
+                                while let Some(event) =
+                                    progress_rx.recv().await
+                                {
+                                    let progress = LoopResponse::Progress {
+                                        name: name.clone(),
+                                        event,
+                                    };
+                                    if tx.send(progress).await.is_err() {
+                                        break;
+                                    }
+                                }
+                            })
+                        });
+                        let result = with_progress(
+                            progress_tx,
+                            dispatch_tool_call(
+                                &self.agent.tools,
+                                &name,
+                                params.to_string(),
+                            ),
+                        )
+                        .await;
+                        if let Some(forward_task) = forward_task {
+                            let _ = forward_task.await;
+                        }
 
                         if self.stdout {
                             println!("Tool result: {:?}", result);
@@ -84,9 +120,10 @@ impl ReasoningLoop {
                                     "{{\"type\": \"tool_result\", \"tool_use_id\": \"{}\", \"content\": \"{}\"}}",
                                     tool_id, content
                                 ),
+                                // the error is a serialized ToolError
                                 Err(err) => format!(
-                                    "{{\"type\": \"tool_result\", \"tool_use_id\": \"{}\", \"content\": \"{}\", \"is_error\": true}}",
-                                    tool_id, err.to_string()
+                                    "{{\"type\": \"tool_result\", \"tool_use_id\": \"{}\", \"content\": {}, \"is_error\": true}}",
+                                    tool_id, err
                                 ),
                             },
                         });
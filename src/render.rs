@@ -0,0 +1,259 @@
+//! User-facing text of tool results in the language of the session (the
+//! `language` preference, English by default). The raw results stay what
+//! the LLM consumes, the rendered text travels next to them for clients to
+//! show: errors by their code, actions waiting for a confirmation, sent
+//! transactions and the fields of JSON results under translated labels.
+//! Messages coming from the chains and APIs are not translated
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::execution::moves_value;
+use crate::tool_error::{ErrorCode, ToolError};
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Locale {
+    #[default]
+    En,
+    Ru,
+}
+
+impl FromStr for Locale {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "en" | "english" | "английский" => Ok(Self::En),
+            "ru" | "russian" | "русский" => Ok(Self::Ru),
+            _ => Err(ToolError::invalid_input(format!(
+                "Unsupported language {}",
+                s
+            ))
+            .with_hint("Use en or ru")
+            .into()),
+        }
+    }
+}
+
+/// Texts of a locale
+struct Catalog {
+    error: &'static str,
+    confirmation_required: &'static str,
+    approval_required: &'static str,
+    transaction_sent: &'static str,
+    hint: &'static str,
+}
+
+const EN: Catalog = Catalog {
+    error: "Error",
+    confirmation_required: "Waiting for your confirmation",
+    approval_required: "The token has to be approved first",
+    transaction_sent: "Transaction sent",
+    hint: "Hint",
+};
+
+const RU: Catalog = Catalog {
+    error: "Ошибка",
+    confirmation_required: "Ожидает вашего подтверждения",
+    approval_required: "Сначала нужно одобрить токен",
+    transaction_sent: "Транзакция отправлена",
+    hint: "Подсказка",
+};
+
+impl Locale {
+    fn catalog(&self) -> &'static Catalog {
+        match self {
+            Self::En => &EN,
+            Self::Ru => &RU,
+        }
+    }
+
+    fn error_code(&self, code: ErrorCode) -> &'static str {
+        use ErrorCode::*;
+        match (self, code) {
+            (Self::En, InvalidInput) => "invalid input",
+            (Self::En, NotFound) => "not found",
+            (Self::En, Expired) => "expired",
+            (Self::En, InsufficientFunds) => "insufficient funds",
+            (Self::En, SlippageExceeded) => "the price moved too much",
+            (Self::En, RiskRejected) => "rejected by the risk limits",
+            (Self::En, AccessDenied) => "not allowed in this session",
+            (Self::En, Unauthorized) => "unauthorized",
+            (Self::En, RateLimited) => "too many requests",
+            (Self::En, Timeout) => "timed out",
+            (Self::En, Cancelled) => "cancelled",
+            (Self::En, Duplicate) => "already done",
+            (Self::En, Unavailable) => "temporarily unavailable",
+            (Self::En, Network) => "network error",
+            (Self::En, Internal) => "internal error",
+            (Self::Ru, InvalidInput) => "неверные данные",
+            (Self::Ru, NotFound) => "не найдено",
+            (Self::Ru, Expired) => "срок истёк",
+            (Self::Ru, InsufficientFunds) => "недостаточно средств",
+            (Self::Ru, SlippageExceeded) => "цена изменилась слишком сильно",
+            (Self::Ru, RiskRejected) => "отклонено лимитами риска",
+            (Self::Ru, AccessDenied) => "недоступно в этой сессии",
+            (Self::Ru, Unauthorized) => "нет авторизации",
+            (Self::Ru, RateLimited) => "слишком много запросов",
+            (Self::Ru, Timeout) => "превышено время ожидания",
+            (Self::Ru, Cancelled) => "отменено",
+            (Self::Ru, Duplicate) => "уже выполнено",
+            (Self::Ru, Unavailable) => "временно недоступно",
+            (Self::Ru, Network) => "ошибка сети",
+            (Self::Ru, Internal) => "внутренняя ошибка",
+        }
+    }
+
+    /// Label of a field of a JSON result, the key itself when unknown
+    fn label<'a>(&self, key: &'a str) -> &'a str {
+        let labels: &[(&str, &str, &str)] = &[
+            ("address", "Address", "Адрес"),
+            ("amount", "Amount", "Сумма"),
+            ("balance", "Balance", "Баланс"),
+            ("chain", "Chain", "Сеть"),
+            ("fee", "Fee", "Комиссия"),
+            ("fees", "Fees", "Комиссии"),
+            ("id", "Id", "Id"),
+            ("price", "Price", "Цена"),
+            ("price_usd", "Price, USD", "Цена, USD"),
+            ("slippage_bps", "Slippage, bps", "Проскальзывание, bps"),
+            ("status", "Status", "Статус"),
+            ("symbol", "Token", "Токен"),
+            ("token", "Token", "Токен"),
+            ("total_usd", "Total, USD", "Итого, USD"),
+            ("usd_value", "Value, USD", "Стоимость, USD"),
+        ];
+        match labels.iter().find(|(k, _, _)| *k == key) {
+            Some((_, en, ru)) => match self {
+                Self::En => en,
+                Self::Ru => ru,
+            },
+            None => key,
+        }
+    }
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn render_error(error: &ToolError, locale: Locale) -> String {
+    let catalog = locale.catalog();
+    let mut text = format!(
+        "{}: {}. {}",
+        catalog.error,
+        locale.error_code(error.code),
+        error.message
+    );
+    if let Some(hint) = &error.hint {
+        text.push_str(&format!("\n{}: {}", catalog.hint, hint));
+    }
+    text
+}
+
+/// "label: value" lines of the scalar fields of an object, nested values
+/// are left to the raw result
+fn render_fields(
+    object: &serde_json::Map<String, Value>,
+    locale: Locale,
+) -> String {
+    object
+        .iter()
+        .filter_map(|(key, value)| {
+            scalar(value)
+                .map(|value| format!("{}: {}", locale.label(key), value))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Text of the result `tool` returned, for the user
+pub fn render(tool: &str, result: &str, locale: Locale) -> String {
+    let catalog = locale.catalog();
+    let Ok(json) = serde_json::from_str::<Value>(result) else {
+        if moves_value(tool) {
+            return format!("{}: {}", catalog.transaction_sent, result);
+        }
+        return result.to_string();
+    };
+    if let Ok(error) = serde_json::from_value::<ToolError>(json.clone()) {
+        return render_error(&error, locale);
+    }
+    match json["status"].as_str() {
+        Some("confirmation_required") => {
+            let summary = json["action"]["summary"].as_str().unwrap_or("");
+            return format!("{}: {}", catalog.confirmation_required, summary);
+        }
+        Some("approval_required") => {
+            let summary = json["approval"]["summary"].as_str().unwrap_or("");
+            return format!("{}: {}", catalog.approval_required, summary);
+        }
+        _ => {}
+    }
+    match &json {
+        Value::Object(object) => render_fields(object, locale),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                Value::Object(object) => render_fields(object, locale),
+                item => scalar(item).unwrap_or_else(|| item.to_string()),
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        json => scalar(json).unwrap_or_else(|| result.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render() {
+        let error = ToolError::new(
+            ErrorCode::InsufficientFunds,
+            "You need 0.1 more SOL",
+        )
+        .with_hint("Top up");
+        assert_eq!(
+            render("transfer_sol", &error.to_string(), Locale::Ru),
+            "Ошибка: недостаточно средств. You need 0.1 more SOL\nПодсказка: Top up"
+        );
+
+        let pending = json!({
+            "status": "confirmation_required",
+            "action": { "id": "1", "summary": "Send 1 SOL" },
+        });
+        assert_eq!(
+            render("transfer_sol", &pending.to_string(), Locale::En),
+            "Waiting for your confirmation: Send 1 SOL"
+        );
+        assert_eq!(
+            render(
+                "transfer_sol",
+                "5xyz (https://solscan.io/tx/5xyz)",
+                Locale::Ru
+            ),
+            "Транзакция отправлена: 5xyz (https://solscan.io/tx/5xyz)"
+        );
+
+        let balance = json!({ "balance": "1.5", "symbol": "SOL", "raw": {} });
+        assert_eq!(
+            render("get_balance", &balance.to_string(), Locale::Ru),
+            "Баланс: 1.5\nТокен: SOL"
+        );
+        assert_eq!(render("get_balance", "1.5", Locale::En), "1.5");
+        assert_eq!("Русский".parse::<Locale>().unwrap(), Locale::Ru);
+        assert!("de".parse::<Locale>().is_err());
+    }
+}
@@ -0,0 +1,435 @@
+//! Operator revenue: the Jupiter platform fees and LiFi integrator fees
+//! collected on swaps (see the revenue section of the config) are recorded
+//! per user once the swap is sent, together with the referrer of the user
+//! and their share of the fee. Users get a referral code with
+//! `get_referral_code` and new users enter it with `set_referrer`.
+//! Referrers follow their earnings with `get_referral_earnings`, operators
+//! query the whole ledger with `GET /v1/revenue`
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::audit_user;
+use crate::config::config;
+use crate::cross_chain::lifi::quote::QuoteResponse as LiFiQuote;
+use crate::history::Period;
+use crate::kv_store::{namespaced, KVStore};
+use crate::pricing::{to_ui_amount, token_price};
+use crate::signer::SignerContext;
+use crate::solana::jup::QuoteResponse as JupiterQuote;
+use crate::tool_error::ToolError;
+
+/// Fee the operator collects on a swap, as quoted by the venue
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectedFee {
+    pub venue: String,
+    pub chain: String,
+    pub token: String,
+    pub symbol: Option<String>,
+    /// base units of `token`
+    pub amount: String,
+    pub usd_value: Option<f64>,
+}
+
+impl CollectedFee {
+    /// Platform fee of a Jupiter quote, taken from the output token
+    pub fn jupiter(quote: &JupiterQuote) -> Option<Self> {
+        let fee =
+            quote.platform_fee.as_ref().filter(|fee| fee.fee_bps > 0)?;
+        Some(Self {
+            venue: "jupiter".to_string(),
+            chain: "sol".to_string(),
+            token: quote.output_mint.clone(),
+            symbol: None,
+            amount: fee.amount.clone(),
+            usd_value: None,
+        })
+    }
+
+    /// Integrator fee of a LiFi quote, taken from the input token
+    pub fn lifi(quote: &LiFiQuote, chain: &str) -> Option<Self> {
+        let fee = quote
+            .estimate
+            .fee_costs
+            .as_ref()?
+            .iter()
+            .find(|fee| fee.name.to_lowercase().contains("integrator"))?;
+        Some(Self {
+            venue: "lifi".to_string(),
+            chain: chain.to_string(),
+            token: fee.token.address.clone(),
+            symbol: Some(fee.token.symbol.clone()),
+            amount: fee.amount.clone()?,
+            usd_value: fee
+                .amount_usd
+                .as_deref()
+                .and_then(|usd| usd.parse().ok()),
+        })
+    }
+
+    /// Fills in the symbol and the USD value from the current price
+    async fn priced(mut self) -> Self {
+        if self.usd_value.is_some() && self.symbol.is_some() {
+            return self;
+        }
+        match token_price(&self.chain, &self.token).await {
+            Ok(price) => {
+                if self.usd_value.is_none() {
+                    self.usd_value =
+                        to_ui_amount(&self.amount, price.decimals)
+                            .ok()
+                            .map(|amount| amount * price.price_usd);
+                }
+                self.symbol.get_or_insert(price.symbol);
+            }
+            Err(e) => tracing::warn!(token = %self.token, ?e, "no fee price"),
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeRecord {
+    pub tx: String,
+    pub user_id: String,
+    pub referrer: Option<String>,
+    pub venue: String,
+    pub chain: String,
+    pub token: String,
+    pub symbol: Option<String>,
+    pub amount: String,
+    pub usd_value: Option<f64>,
+    pub referrer_share_usd: Option<f64>,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RevenueReport {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub swaps: usize,
+    pub fees_usd: f64,
+    pub referrer_share_usd: f64,
+    /// fees minus the referrer shares
+    pub net_usd: f64,
+    pub by_venue: BTreeMap<String, f64>,
+    pub by_user: BTreeMap<String, f64>,
+    /// shares owed to each referrer
+    pub by_referrer: BTreeMap<String, f64>,
+}
+
+impl RevenueReport {
+    pub fn new(records: &[FeeRecord], period: &Period) -> Self {
+        let mut report = Self {
+            since: period.since,
+            until: period.until,
+            ..Default::default()
+        };
+        for record in
+            records.iter().filter(|record| period.contains(record.at))
+        {
+            let usd_value = record.usd_value.unwrap_or(0.0);
+            let share = record.referrer_share_usd.unwrap_or(0.0);
+            report.swaps += 1;
+            report.fees_usd += usd_value;
+            report.referrer_share_usd += share;
+            *report.by_venue.entry(record.venue.clone()).or_default() +=
+                usd_value;
+            *report.by_user.entry(record.user_id.clone()).or_default() +=
+                usd_value;
+            if let Some(referrer) = &record.referrer {
+                *report.by_referrer.entry(referrer.clone()).or_default() +=
+                    share;
+            }
+        }
+        report.net_usd = report.fees_usd - report.referrer_share_usd;
+        report
+    }
+}
+
+/// What a referrer earned from the users they referred
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReferralEarnings {
+    pub code: String,
+    pub referred_users: usize,
+    pub swaps: usize,
+    pub fees_usd: f64,
+    pub earnings_usd: f64,
+    pub share_pct: f64,
+}
+
+pub struct Revenue {
+    store: Arc<dyn KVStore>,
+    /// codes are created and referrers set after a lookup, writes have to
+    /// be serialized
+    lock: tokio::sync::Mutex<()>,
+}
+
+pub static REVENUE: Lazy<Revenue> =
+    Lazy::new(|| Revenue::new(Arc::new(namespaced("revenue"))));
+
+impl Revenue {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self {
+            store,
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Referral code of the user, created on first use
+    pub async fn referral_code(&self, user_id: &str) -> Result<String> {
+        let _guard = self.lock.lock().await;
+        let key = format!("user:{}:code", user_id);
+        if let Some(code) = self.store.get(&key).await? {
+            return Ok(code);
+        }
+        let code = loop {
+            let code = hex::encode_upper(rand::random::<[u8; 4]>());
+            if self.store.get(&format!("code:{}", code)).await?.is_none() {
+                break code;
+            }
+        };
+        self.store.set(&format!("code:{}", code), user_id).await?;
+        self.store.set(&key, &code).await?;
+        Ok(code)
+    }
+
+    pub async fn referrer(&self, user_id: &str) -> Result<Option<String>> {
+        self.store.get(&format!("user:{}:referrer", user_id)).await
+    }
+
+    /// Makes the owner of `code` the referrer of the user, once. Returns
+    /// the referrer
+    pub async fn set_referrer(
+        &self,
+        user_id: &str,
+        code: &str,
+    ) -> Result<String> {
+        let _guard = self.lock.lock().await;
+        let code = code.trim().to_uppercase();
+        let referrer = self
+            .store
+            .get(&format!("code:{}", code))
+            .await?
+            .ok_or_else(|| {
+                ToolError::not_found(format!(
+                    "Unknown referral code {}",
+                    code
+                ))
+            })?;
+        if referrer == user_id {
+            return Err(ToolError::invalid_input(
+                "Users can't refer themselves",
+            )
+            .into());
+        }
+        let key = format!("user:{}:referrer", user_id);
+        if self.store.get(&key).await?.is_some() {
+            return Err(ToolError::invalid_input(
+                "The referrer of the user is already set",
+            )
+            .into());
+        }
+        self.store.set(&key, &referrer).await?;
+        self.store
+            .set(&format!("referred:{}:{}", referrer, user_id), "")
+            .await?;
+        Ok(referrer)
+    }
+
+    /// Records the fee collected on the swap `tx` of the user, credits
+    /// `share_pct` of it to their referrer
+    pub async fn record(
+        &self,
+        user_id: &str,
+        tx: &str,
+        fee: CollectedFee,
+        share_pct: f64,
+    ) -> Result<FeeRecord> {
+        let referrer = self.referrer(user_id).await?;
+        let record = FeeRecord {
+            tx: tx.to_string(),
+            user_id: user_id.to_string(),
+            referrer_share_usd: referrer
+                .as_ref()
+                .and(fee.usd_value)
+                .map(|usd_value| usd_value * share_pct / 100.),
+            referrer,
+            venue: fee.venue,
+            chain: fee.chain,
+            token: fee.token,
+            symbol: fee.symbol,
+            amount: fee.amount,
+            usd_value: fee.usd_value,
+            at: Utc::now(),
+        };
+        let key = format!("fee:{:012}:{}", record.at.timestamp(), tx);
+        self.store
+            .set(&key, &serde_json::to_string(&record)?)
+            .await?;
+        Ok(record)
+    }
+
+    /// Fees collected over `period`, oldest first
+    pub async fn fees(&self, period: &Period) -> Result<Vec<FeeRecord>> {
+        let mut records = self
+            .store
+            .scan_prefix("fee:")
+            .await?
+            .into_iter()
+            .filter_map(|(_, record)| {
+                serde_json::from_str::<FeeRecord>(&record).ok()
+            })
+            .filter(|record| period.contains(record.at))
+            .collect::<Vec<_>>();
+        records.sort_by_key(|record| record.at);
+        Ok(records)
+    }
+
+    pub async fn report(&self, period: &Period) -> Result<RevenueReport> {
+        Ok(RevenueReport::new(&self.fees(period).await?, period))
+    }
+
+    pub async fn earnings(
+        &self,
+        referrer: &str,
+        period: &Period,
+    ) -> Result<ReferralEarnings> {
+        let referred_users = self
+            .store
+            .scan_prefix(&format!("referred:{}:", referrer))
+            .await?
+            .len();
+        let records = self
+            .fees(period)
+            .await?
+            .into_iter()
+            .filter(|record| record.referrer.as_deref() == Some(referrer))
+            .collect::<Vec<_>>();
+        Ok(ReferralEarnings {
+            code: self.referral_code(referrer).await?,
+            referred_users,
+            swaps: records.len(),
+            fees_usd: records.iter().filter_map(|r| r.usd_value).sum(),
+            earnings_usd: records
+                .iter()
+                .filter_map(|r| r.referrer_share_usd)
+                .sum(),
+            share_pct: config().revenue.referrer_share_pct,
+        })
+    }
+}
+
+/// Records the fee of a swap sent by the current signer, failures are only
+/// logged so accounting never fails a swap that went through
+pub async fn record_fee(fee: Option<CollectedFee>, tx: &str) {
+    let Some(fee) = fee else {
+        return;
+    };
+    let signer = SignerContext::current().await;
+    // dry runs and previews collect nothing
+    if signer.is_paper() {
+        return;
+    }
+    let user_id = audit_user(signer.as_ref());
+    let share_pct = config().revenue.referrer_share_pct;
+    if let Err(e) = REVENUE
+        .record(&user_id, tx, fee.priced().await, share_pct)
+        .await
+    {
+        tracing::warn!(tx, ?e, "failed to record the swap fee");
+    }
+}
+
+#[tool(description = "
+Returns the referral code of the user, other users enter it with
+set_referrer and the user earns a share of the fees of their swaps
+")]
+pub async fn get_referral_code() -> Result<String> {
+    let user_id = audit_user(SignerContext::current().await.as_ref());
+    REVENUE.referral_code(&user_id).await
+}
+
+#[tool(description = "
+Sets the referrer of the user from the referral code they were given. The
+referrer can only be set once
+")]
+pub async fn set_referrer(code: String) -> Result<String> {
+    let user_id = audit_user(SignerContext::current().await.as_ref());
+    REVENUE.set_referrer(&user_id, &code).await?;
+    Ok(format!(
+        "Referral code {} applied",
+        code.trim().to_uppercase()
+    ))
+}
+
+#[tool(description = "
+What the user earned from the swaps of the users they referred over a
+period: 7d, 30d (any number of days), ytd, a year like 2024 or all
+")]
+pub async fn get_referral_earnings(
+    period: String,
+) -> Result<ReferralEarnings> {
+    let user_id = audit_user(SignerContext::current().await.as_ref());
+    REVENUE
+        .earnings(&user_id, &Period::from_str(&period)?)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    fn fee(usd_value: f64) -> CollectedFee {
+        CollectedFee {
+            venue: "jupiter".to_string(),
+            chain: "sol".to_string(),
+            token: "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v".to_string(),
+            symbol: Some("USDC".to_string()),
+            amount: "250000".to_string(),
+            usd_value: Some(usd_value),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_referral_revenue() {
+        let revenue = Revenue::new(Arc::new(InMemoryKVStore::new()));
+        let code = revenue.referral_code("alice").await.unwrap();
+        assert_eq!(revenue.referral_code("alice").await.unwrap(), code);
+        assert!(revenue.set_referrer("alice", &code).await.is_err());
+        assert!(revenue.set_referrer("bob", "NOPE").await.is_err());
+        revenue
+            .set_referrer("bob", &code.to_lowercase())
+            .await
+            .unwrap();
+        assert!(revenue.set_referrer("bob", &code).await.is_err());
+
+        let record =
+            revenue.record("bob", "sig1", fee(2.), 25.).await.unwrap();
+        assert_eq!(record.referrer.as_deref(), Some("alice"));
+        assert_eq!(record.referrer_share_usd, Some(0.5));
+        let record =
+            revenue.record("carol", "sig2", fee(1.), 25.).await.unwrap();
+        assert_eq!(record.referrer_share_usd, None);
+
+        let all = Period::from_str("all").unwrap();
+        let report = revenue.report(&all).await.unwrap();
+        assert_eq!(report.swaps, 2);
+        assert_eq!(report.fees_usd, 3.);
+        assert_eq!(report.net_usd, 2.5);
+        assert_eq!(report.by_user["carol"], 1.);
+        assert_eq!(report.by_referrer["alice"], 0.5);
+
+        let earnings = revenue.earnings("alice", &all).await.unwrap();
+        assert_eq!(earnings.referred_users, 1);
+        assert_eq!(earnings.swaps, 1);
+        assert_eq!(earnings.earnings_usd, 0.5);
+    }
+}
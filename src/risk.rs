@@ -0,0 +1,909 @@
+//! Risk engine consulted by every value-moving tool before anything is
+//! executed or parked for confirmation. Limits are configured per user and
+//! persisted in the kv store, trades breaking them are rejected with typed
+//! `RiskViolation`s so the LLM can explain what to change. Users can also
+//! block single tokens (e.g. known scams) so no prompt gets them traded
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::address::Address;
+use crate::config::{config, RiskSettings, ScreeningMode};
+use crate::confirmation::confirm_or_execute;
+use crate::dexscreener::token_age_hours;
+use crate::kv_store::{update, KVStore, KV_STORE};
+use crate::pricing::usd_value;
+use crate::screening::{ScreeningHit, SCREENING};
+use crate::signer::SignerContext;
+use crate::tool_error::{ErrorCode, ToolError};
+
+/// Owner used for local signers that are not tied to a user
+const LOCAL_USER: &str = "local";
+/// Daily volumes are kept a little longer than the day they count
+const VOLUME_TTL: Duration = Duration::from_secs(2 * 24 * 3600);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RiskLimits {
+    /// None disables the cap
+    pub max_trade_usd: Option<f64>,
+    /// None disables the cap
+    pub max_daily_volume_usd: Option<f64>,
+    pub max_slippage_bps: u16,
+    /// when not empty only these tokens can be traded
+    pub allowlist: Vec<String>,
+    pub denylist: Vec<String>,
+    /// tokens whose first pool is younger than this raise a warning
+    pub new_token_hours: f64,
+}
+
+impl RiskLimits {
+    /// The limits capped by the ones of the operator, users can only
+    /// tighten them
+    pub fn within(mut self, ceiling: &RiskSettings) -> Self {
+        self.max_trade_usd =
+            capped(self.max_trade_usd, ceiling.max_trade_usd);
+        self.max_daily_volume_usd =
+            capped(self.max_daily_volume_usd, ceiling.max_daily_volume_usd);
+        self.max_slippage_bps =
+            self.max_slippage_bps.min(ceiling.max_slippage_bps);
+        self
+    }
+}
+
+/// USD cap no higher than the one of the operator, 0 disables a cap of
+/// the operator and a missing cap is the one of the operator
+fn capped(limit_usd: Option<f64>, ceiling_usd: f64) -> Option<f64> {
+    let limit_usd = limit_usd.filter(|limit| *limit > 0.);
+    if ceiling_usd <= 0. {
+        return limit_usd;
+    }
+    Some(limit_usd.map_or(ceiling_usd, |limit| limit.min(ceiling_usd)))
+}
+
+/// The limits of the config, follow its reloads until the user sets their own
+impl Default for RiskLimits {
+    fn default() -> Self {
+        let risk = config().risk.clone();
+        Self {
+            max_trade_usd: (risk.max_trade_usd > 0.)
+                .then_some(risk.max_trade_usd),
+            max_daily_volume_usd: (risk.max_daily_volume_usd > 0.)
+                .then_some(risk.max_daily_volume_usd),
+            max_slippage_bps: risk.max_slippage_bps,
+            allowlist: vec![],
+            denylist: vec![],
+            new_token_hours: risk.new_token_hours,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RiskViolation {
+    TradeLimit {
+        value_usd: f64,
+        limit_usd: f64,
+    },
+    DailyVolumeLimit {
+        value_usd: f64,
+        used_usd: f64,
+        limit_usd: f64,
+    },
+    SlippageLimit {
+        slippage_bps: u16,
+        limit_bps: u16,
+    },
+    DeniedToken {
+        token: String,
+    },
+    TokenNotAllowed {
+        token: String,
+    },
+    ScreenedAddress {
+        address: String,
+        reasons: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RiskWarning {
+    NewToken {
+        token: String,
+        age_hours: f64,
+    },
+    /// the value could not be priced, the USD caps were not applied to it
+    UnknownValue {
+        token: String,
+    },
+    /// screening flagged the destination, sent anyway in warn mode
+    ScreenedAddress {
+        address: String,
+        reasons: Vec<String>,
+    },
+    /// the route approves or calls a contract missing from the registry of
+    /// known routers
+    UnknownSpender {
+        chain_id: u64,
+        address: String,
+    },
+}
+
+/// Error returned to the LLM when a trade breaks the user's limits, it
+/// displays as JSON so the violations stay machine readable
+#[derive(Debug)]
+pub struct RiskRejection(pub Vec<RiskViolation>);
+
+impl fmt::Display for RiskRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rejection = ToolError::new(
+            ErrorCode::RiskRejected,
+            "Rejected by the risk limits, nothing was executed",
+        )
+        .with_hint("Explain the violations to the user, the limits can be changed with set_risk_limits")
+        .with_details(json!({ "violations": self.0 }));
+        write!(f, "{}", rejection)
+    }
+}
+
+impl std::error::Error for RiskRejection {}
+
+/// What a tool is about to do, amounts are raw (accounting for decimals)
+#[derive(Debug, Clone, Default)]
+pub struct TradeIntent {
+    pub chain: String,
+    pub spend: Vec<(String, String)>,
+    pub receive: Vec<String>,
+    pub slippage_bps: Option<u16>,
+    /// address receiving the funds of a transfer or bridge
+    pub destination: Option<String>,
+}
+
+impl TradeIntent {
+    pub fn new(chain: &str) -> Self {
+        Self {
+            chain: chain.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn spend(mut self, token: &str, amount: &str) -> Self {
+        self.spend.push((token.to_string(), amount.to_string()));
+        self
+    }
+
+    pub fn receive(mut self, token: &str) -> Self {
+        self.receive.push(token.to_string());
+        self
+    }
+
+    pub fn slippage_bps(mut self, slippage_bps: u16) -> Self {
+        self.slippage_bps = Some(slippage_bps);
+        self
+    }
+
+    pub fn destination(mut self, address: &str) -> Self {
+        self.destination = Some(address.to_string());
+        self
+    }
+
+    fn tokens(&self) -> impl Iterator<Item = &String> {
+        self.spend
+            .iter()
+            .map(|(token, _)| token)
+            .chain(&self.receive)
+    }
+}
+
+/// Market facts about an intent, fetched before evaluating it
+#[derive(Debug, Clone, Default)]
+pub struct MarketContext {
+    pub value_usd: Option<f64>,
+    pub unpriced: Vec<String>,
+    pub token_ages: Vec<(String, f64)>,
+    /// of the destination
+    pub screening: Vec<ScreeningHit>,
+}
+
+impl MarketContext {
+    pub async fn fetch(intent: &TradeIntent) -> Self {
+        let mut context = Self::default();
+        for (token, amount) in &intent.spend {
+            match usd_value(&intent.chain, token, amount).await {
+                Ok(value) => {
+                    *context.value_usd.get_or_insert(0.) += value;
+                }
+                Err(e) => {
+                    tracing::warn!(?e, %token, "failed to price token");
+                    context.unpriced.push(token.clone());
+                }
+            }
+        }
+        for token in &intent.receive {
+            if let Ok(Some(age_hours)) = token_age_hours(token).await {
+                context.token_ages.push((token.clone(), age_hours));
+            }
+        }
+        context
+    }
+}
+
+/// Value a passed check counted towards the daily volume of the user. It
+/// is given back once every copy of the assessment is dropped without the
+/// action going through (failed, rejected or never confirmed)
+struct VolumeReservation {
+    store: Arc<dyn KVStore>,
+    key: String,
+    value_usd: f64,
+    kept: AtomicBool,
+}
+
+impl fmt::Debug for VolumeReservation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VolumeReservation")
+            .field("key", &self.key)
+            .field("value_usd", &self.value_usd)
+            .finish()
+    }
+}
+
+impl Drop for VolumeReservation {
+    fn drop(&mut self) {
+        if self.kept.load(Ordering::SeqCst) {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            tracing::warn!(key = %self.key, "volume reservation leaked");
+            return;
+        };
+        let store = self.store.clone();
+        let key = std::mem::take(&mut self.key);
+        let value_usd = self.value_usd;
+        runtime.spawn(async move {
+            if let Err(e) =
+                store.increment(&key, -value_usd, Some(VOLUME_TTL)).await
+            {
+                tracing::warn!(?e, %key, "failed to release volume");
+            }
+        });
+    }
+}
+
+/// Outcome of a passed check, the warnings should be shown to the user
+/// together with the summary of the action
+#[derive(Debug, Clone, Serialize)]
+pub struct RiskAssessment {
+    pub user: String,
+    pub value_usd: Option<f64>,
+    pub warnings: Vec<RiskWarning>,
+    #[serde(skip)]
+    reservation: Option<Arc<VolumeReservation>>,
+}
+
+impl RiskAssessment {
+    pub fn annotate(&self, summary: String) -> String {
+        if self.warnings.is_empty() {
+            return summary;
+        }
+        format!("{} (risk warnings: {})", summary, json!(self.warnings))
+    }
+
+    /// Keeps the value the check reserved in the daily volume, to be
+    /// called once the action went through
+    pub async fn record(&self) {
+        if let Some(reservation) = &self.reservation {
+            reservation.kept.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Solana mints are case sensitive, EVM addresses are compared without
+/// their checksum casing
+fn same_token(a: &str, b: &str) -> bool {
+    if a.starts_with("0x") && b.starts_with("0x") {
+        return a.eq_ignore_ascii_case(b);
+    }
+    a == b
+}
+
+fn listed(list: &[String], token: &str) -> bool {
+    list.iter().any(|t| same_token(t, token))
+}
+
+/// Tokens of the intent that are denied or not allowed by the lists
+fn token_violations(
+    allowlist: &[String],
+    denylist: &[String],
+    intent: &TradeIntent,
+) -> Vec<RiskViolation> {
+    let mut violations = vec![];
+    for token in intent.tokens() {
+        if listed(denylist, token) {
+            violations.push(RiskViolation::DeniedToken {
+                token: token.clone(),
+            });
+        } else if !allowlist.is_empty() && !listed(allowlist, token) {
+            violations.push(RiskViolation::TokenNotAllowed {
+                token: token.clone(),
+            });
+        }
+    }
+    violations
+}
+
+pub fn evaluate(
+    limits: &RiskLimits,
+    intent: &TradeIntent,
+    context: &MarketContext,
+    used_today_usd: f64,
+) -> (Vec<RiskViolation>, Vec<RiskWarning>) {
+    let mut violations =
+        token_violations(&limits.allowlist, &limits.denylist, intent);
+    let mut warnings = vec![];
+
+    if let Some(slippage_bps) = intent.slippage_bps {
+        if slippage_bps > limits.max_slippage_bps {
+            violations.push(RiskViolation::SlippageLimit {
+                slippage_bps,
+                limit_bps: limits.max_slippage_bps,
+            });
+        }
+    }
+
+    if let Some(value_usd) = context.value_usd {
+        if let Some(limit_usd) = limits.max_trade_usd {
+            if value_usd > limit_usd {
+                violations.push(RiskViolation::TradeLimit {
+                    value_usd,
+                    limit_usd,
+                });
+            }
+        }
+        if let Some(limit_usd) = limits.max_daily_volume_usd {
+            if used_today_usd + value_usd > limit_usd {
+                violations.push(RiskViolation::DailyVolumeLimit {
+                    value_usd,
+                    used_usd: used_today_usd,
+                    limit_usd,
+                });
+            }
+        }
+    }
+
+    for token in &context.unpriced {
+        warnings.push(RiskWarning::UnknownValue {
+            token: token.clone(),
+        });
+    }
+    for (token, age_hours) in &context.token_ages {
+        if *age_hours < limits.new_token_hours {
+            warnings.push(RiskWarning::NewToken {
+                token: token.clone(),
+                age_hours: *age_hours,
+            });
+        }
+    }
+
+    (violations, warnings)
+}
+
+pub struct RiskEngine {
+    store: Arc<dyn KVStore>,
+}
+
+pub static RISK: Lazy<RiskEngine> =
+    Lazy::new(|| RiskEngine::new(KV_STORE.clone()));
+
+impl RiskEngine {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self { store }
+    }
+
+    fn limits_key(user: &str) -> String {
+        format!("risk:limits:{}", user)
+    }
+
+    fn volume_key(user: &str) -> String {
+        format!("risk:volume:{}:{}", user, Utc::now().format("%Y-%m-%d"))
+    }
+
+    /// Limits of the user, capped by the ones of the config in case the
+    /// operator lowered them since the user set theirs
+    pub async fn limits(&self, user: &str) -> Result<RiskLimits> {
+        match self.store.get(&Self::limits_key(user)).await? {
+            Some(limits) => Ok(serde_json::from_str::<RiskLimits>(&limits)?
+                .within(&config().risk)),
+            None => Ok(RiskLimits::default()),
+        }
+    }
+
+    pub async fn set_limits(
+        &self,
+        user: &str,
+        limits: &RiskLimits,
+    ) -> Result<()> {
+        self.store
+            .set(&Self::limits_key(user), &serde_json::to_string(limits)?)
+            .await
+    }
+
+    /// Changes the limits of the user with `f`, which tells whether it
+    /// changed them. Compared and set so concurrent changes all apply
+    async fn update_limits<F>(&self, user: &str, mut f: F) -> Result<bool>
+    where
+        F: FnMut(&mut RiskLimits) -> bool + Send,
+    {
+        let mut changed = false;
+        update(
+            self.store.as_ref(),
+            &Self::limits_key(user),
+            None,
+            |limits| {
+                let mut limits = match limits {
+                    Some(limits) => serde_json::from_str(limits)?,
+                    None => RiskLimits::default(),
+                };
+                changed = f(&mut limits);
+                Ok(serde_json::to_string(&limits)?)
+            },
+        )
+        .await?;
+        Ok(changed)
+    }
+
+    /// Adds `token` to the denylist of the user, false when it already was
+    /// on it
+    pub async fn block_token(&self, user: &str, token: &str) -> Result<bool> {
+        self.update_limits(user, |limits| {
+            if listed(&limits.denylist, token) {
+                return false;
+            }
+            limits.denylist.push(token.to_string());
+            true
+        })
+        .await
+    }
+
+    /// Removes `token` from the denylist of the user and adds it to their
+    /// allowlist when they keep one, false when it was already allowed
+    pub async fn allow_token(&self, user: &str, token: &str) -> Result<bool> {
+        self.update_limits(user, |limits| {
+            let denied = listed(&limits.denylist, token);
+            limits.denylist.retain(|t| !same_token(t, token));
+            let missing = !limits.allowlist.is_empty()
+                && !listed(&limits.allowlist, token);
+            if missing {
+                limits.allowlist.push(token.to_string());
+            }
+            denied || missing
+        })
+        .await
+    }
+
+    /// USD volume traded by the user today (UTC)
+    pub async fn daily_volume(&self, user: &str) -> Result<f64> {
+        Ok(self
+            .store
+            .get(&Self::volume_key(user))
+            .await?
+            .and_then(|volume| volume.parse().ok())
+            .unwrap_or(0.))
+    }
+
+    /// Counts `value_usd` towards today's volume and returns the volume
+    /// before it, concurrent trades all count
+    async fn reserve_volume(
+        &self,
+        user: &str,
+        value_usd: f64,
+    ) -> Result<(f64, VolumeReservation)> {
+        let key = Self::volume_key(user);
+        let used = self
+            .store
+            .increment(&key, value_usd, Some(VOLUME_TTL))
+            .await?;
+        let reservation = VolumeReservation {
+            store: self.store.clone(),
+            key,
+            value_usd,
+            kept: AtomicBool::new(false),
+        };
+        Ok((used - value_usd, reservation))
+    }
+
+    /// Checks the intent against the limits of the user. The value of a
+    /// passed check is reserved in the daily volume right away, so
+    /// concurrent trades can't all pass the cap, see `RiskAssessment::record`
+    pub async fn check(
+        &self,
+        user: &str,
+        intent: &TradeIntent,
+        context: MarketContext,
+    ) -> Result<RiskAssessment> {
+        let limits = self.limits(user).await?;
+        // paper trades don't count towards the volume
+        let paper =
+            SignerContext::try_current().is_some_and(|s| s.is_paper());
+        let (used_today_usd, reservation) = match context.value_usd {
+            Some(value_usd) if !paper => {
+                let (used, reservation) =
+                    self.reserve_volume(user, value_usd).await?;
+                (used, Some(reservation))
+            }
+            _ => (self.daily_volume(user).await?, None),
+        };
+        let (mut violations, mut warnings) =
+            evaluate(&limits, intent, &context, used_today_usd);
+        // the token lists of the config apply to every user
+        let config = config();
+        let tokens = &config.tokens;
+        for violation in
+            token_violations(&tokens.allowlist, &tokens.denylist, intent)
+        {
+            if !violations.contains(&violation) {
+                violations.push(violation);
+            }
+        }
+        if let Some(hit) = context.screening.first() {
+            let address = hit.address.clone();
+            let reasons = context
+                .screening
+                .iter()
+                .map(|hit| format!("{}: {}", hit.source, hit.reason))
+                .collect();
+            match config.screening.mode {
+                ScreeningMode::Block => {
+                    violations.push(RiskViolation::ScreenedAddress {
+                        address,
+                        reasons,
+                    })
+                }
+                ScreeningMode::Warn => warnings
+                    .push(RiskWarning::ScreenedAddress { address, reasons }),
+            }
+        }
+        if !violations.is_empty() {
+            // dropping the reservation gives the volume back
+            return Err(RiskRejection(violations).into());
+        }
+
+        Ok(RiskAssessment {
+            user: user.to_string(),
+            value_usd: context.value_usd,
+            warnings,
+            reservation: reservation.map(Arc::new),
+        })
+    }
+}
+
+async fn current_user() -> String {
+    SignerContext::current()
+        .await
+        .user_id()
+        .unwrap_or_else(|| LOCAL_USER.to_string())
+}
+
+/// Checks the intent of the current user against their limits, fails with a
+/// `RiskRejection` when any of them is broken
+pub async fn assess(intent: TradeIntent) -> Result<RiskAssessment> {
+    let user = current_user().await;
+    let mut context = MarketContext::fetch(&intent).await;
+    if let Some(destination) = &intent.destination {
+        context.screening = SCREENING
+            .screen(&user, destination, &config().screening)
+            .await?;
+    }
+    RISK.check(&user, &intent, context).await
+}
+
+fn parse_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+#[tool(description = "
+Returns the risk limits of the user: the per-trade and daily USD caps, the max
+slippage, the token allowlist/denylist and the age under which tokens are
+considered new
+")]
+pub async fn get_risk_limits() -> Result<RiskLimits> {
+    RISK.limits(&current_user().await).await
+}
+
+#[tool(description = "
+Changes the risk limits of the user, this requires the user's confirmation.
+
+max_trade_usd and max_daily_volume_usd are USD caps, 0 disables the cap
+max_slippage_bps is the max slippage in basis points
+the limits can't be looser than the ones of the operator, higher values and
+disabled caps are lowered to them
+allowlist and denylist are comma separated token addresses/mints, an empty
+allowlist allows every token that is not on the denylist
+")]
+pub async fn set_risk_limits(
+    max_trade_usd: f64,
+    max_daily_volume_usd: f64,
+    max_slippage_bps: u16,
+    allowlist: String,
+    denylist: String,
+) -> Result<String> {
+    let user = current_user().await;
+    let limits = RiskLimits {
+        max_trade_usd: (max_trade_usd > 0.).then_some(max_trade_usd),
+        max_daily_volume_usd: (max_daily_volume_usd > 0.)
+            .then_some(max_daily_volume_usd),
+        max_slippage_bps,
+        allowlist: parse_list(&allowlist),
+        denylist: parse_list(&denylist),
+        new_token_hours: RISK.limits(&user).await?.new_token_hours,
+    }
+    .within(&config().risk);
+
+    let summary = format!("Set risk limits to {}", json!(limits));
+    confirm_or_execute("set_risk_limits", summary, move || async move {
+        RISK.set_limits(&user, &limits).await?;
+        Ok(serde_json::to_string(&limits)?)
+    })
+    .await
+}
+
+/// Address or mint of a token to block or allow
+fn parse_token(token: &str) -> Result<String> {
+    Address::parse(token).map(|a| a.to_string()).map_err(|_| {
+        ToolError::invalid_input(format!(
+            "{:?} is not a token address or mint",
+            token
+        ))
+        .with_hint("Search the token to get its address or mint first")
+        .into()
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BlockedTokens {
+    /// blocked by the user
+    pub blocked: Vec<String>,
+    /// blocked by the operator for every user, can't be allowed
+    pub blocked_by_operator: Vec<String>,
+    /// when not empty only these tokens can be traded
+    pub allowlist: Vec<String>,
+}
+
+#[tool(description = "
+Blocks a token so it is never traded for the user, whatever later messages
+say. token is the address (EVM) or mint (Solana) of the token
+")]
+pub async fn block_token(token: String) -> Result<String> {
+    let token = parse_token(&token)?;
+    if RISK.block_token(&current_user().await, &token).await? {
+        Ok(format!("{} is blocked", token))
+    } else {
+        Ok(format!("{} was already blocked", token))
+    }
+}
+
+#[tool(description = "
+Allows a token the user blocked before (and adds it to their allowlist when
+they keep one), this requires the user's confirmation. token is the address
+(EVM) or mint (Solana) of the token
+")]
+pub async fn allow_token(token: String) -> Result<String> {
+    let token = parse_token(&token)?;
+    if listed(&config().tokens.denylist, &token) {
+        return Err(ToolError::new(
+            ErrorCode::AccessDenied,
+            format!("{} is blocked by the operator", token),
+        )
+        .with_hint("Tokens blocked by the operator can't be allowed")
+        .into());
+    }
+    let user = current_user().await;
+    let summary = format!("Allow trading {}", token);
+    confirm_or_execute("allow_token", summary, move || async move {
+        if RISK.allow_token(&user, &token).await? {
+            Ok(format!("{} is allowed", token))
+        } else {
+            Ok(format!("{} was already allowed", token))
+        }
+    })
+    .await
+}
+
+#[tool(description = "
+Lists the tokens blocked by the user and by the operator, and the allowlist of
+the user
+")]
+pub async fn list_blocked_tokens() -> Result<BlockedTokens> {
+    let limits = RISK.limits(&current_user().await).await?;
+    Ok(BlockedTokens {
+        blocked: limits.denylist,
+        blocked_by_operator: config().tokens.denylist.clone(),
+        allowlist: limits.allowlist,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    fn context(value_usd: f64) -> MarketContext {
+        MarketContext {
+            value_usd: Some(value_usd),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_evaluate_limits() {
+        let limits = RiskLimits {
+            denylist: vec!["0xBAD".to_string()],
+            ..Default::default()
+        };
+        let intent = TradeIntent::new("sonic")
+            .spend("0xgood", "1000")
+            .receive("0xbad")
+            .slippage_bps(1000);
+
+        let (violations, _) = evaluate(&limits, &intent, &context(6000.), 0.);
+        assert_eq!(
+            violations,
+            vec![
+                RiskViolation::DeniedToken {
+                    token: "0xbad".to_string()
+                },
+                RiskViolation::SlippageLimit {
+                    slippage_bps: 1000,
+                    limit_bps: limits.max_slippage_bps
+                },
+                RiskViolation::TradeLimit {
+                    value_usd: 6000.,
+                    limit_usd: limits.max_trade_usd.unwrap()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_limits_only_tighten() {
+        let ceiling = RiskSettings::default();
+        let limits = RiskLimits {
+            max_trade_usd: Some(1e9),
+            max_daily_volume_usd: None,
+            max_slippage_bps: u16::MAX,
+            ..Default::default()
+        }
+        .within(&ceiling);
+        assert_eq!(limits.max_trade_usd, Some(ceiling.max_trade_usd));
+        assert_eq!(
+            limits.max_daily_volume_usd,
+            Some(ceiling.max_daily_volume_usd)
+        );
+        assert_eq!(limits.max_slippage_bps, ceiling.max_slippage_bps);
+
+        let limits = RiskLimits {
+            max_trade_usd: Some(100.),
+            max_slippage_bps: 50,
+            ..Default::default()
+        }
+        .within(&ceiling);
+        assert_eq!(limits.max_trade_usd, Some(100.));
+        assert_eq!(limits.max_slippage_bps, 50);
+
+        let uncapped = RiskSettings {
+            max_trade_usd: 0.,
+            ..Default::default()
+        };
+        assert_eq!(capped(None, uncapped.max_trade_usd), None);
+    }
+
+    #[test]
+    fn test_mints_are_case_sensitive() {
+        let mint = "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263";
+        let denylist = vec![mint.to_string()];
+        assert!(listed(&denylist, mint));
+        assert!(!listed(&denylist, &mint.to_lowercase()));
+        assert!(listed(&["0xBAD".to_string()], "0xbad"));
+    }
+
+    #[test]
+    fn test_new_and_unpriced_tokens_warn() {
+        let intent =
+            TradeIntent::new("sol").spend("mint", "1").receive("new");
+        let context = MarketContext {
+            value_usd: None,
+            unpriced: vec!["mint".to_string()],
+            token_ages: vec![("new".to_string(), 2.)],
+            ..Default::default()
+        };
+
+        let (violations, warnings) =
+            evaluate(&RiskLimits::default(), &intent, &context, 0.);
+        assert!(violations.is_empty());
+        assert_eq!(warnings.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_daily_volume_is_enforced() {
+        let engine = RiskEngine::new(Arc::new(InMemoryKVStore::new()));
+        let intent = TradeIntent::new("sonic").spend("0xtoken", "1");
+
+        // only four of five concurrent trades fit into the 20k cap
+        let checks =
+            (0..5).map(|_| engine.check("alice", &intent, context(4_500.)));
+        let (passed, rejected): (Vec<_>, Vec<_>) =
+            futures::future::join_all(checks)
+                .await
+                .into_iter()
+                .partition(Result::is_ok);
+        assert_eq!(passed.len(), 4);
+        let err = rejected[0].as_ref().unwrap_err();
+        assert!(err.to_string().contains("daily_volume_limit"));
+        for assessment in passed.iter().flatten() {
+            assessment.record().await;
+        }
+        assert!(engine.check("bob", &intent, context(2000.)).await.is_ok());
+
+        // the rejected check and the one that never went through give
+        // their volume back
+        drop(
+            engine
+                .check("alice", &intent, context(1_000.))
+                .await
+                .unwrap(),
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(engine.daily_volume("alice").await.unwrap(), 18_000.);
+    }
+
+    #[tokio::test]
+    async fn test_block_and_allow_token() {
+        let engine = RiskEngine::new(Arc::new(InMemoryKVStore::new()));
+        let intent = TradeIntent::new("sonic").spend("0xbad", "1");
+
+        assert!(engine.block_token("alice", "0xBAD").await.unwrap());
+        assert!(!engine.block_token("alice", "0xbad").await.unwrap());
+        let err = engine
+            .check("alice", &intent, context(100.))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("denied_token"));
+        assert!(engine.check("bob", &intent, context(100.)).await.is_ok());
+
+        assert!(engine.allow_token("alice", "0xbad").await.unwrap());
+        assert!(!engine.allow_token("alice", "0xbad").await.unwrap());
+        assert!(engine.check("alice", &intent, context(100.)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_screened_destination_is_rejected() {
+        let engine = RiskEngine::new(Arc::new(InMemoryKVStore::new()));
+        let destination = "0x8589427373D6D84E98730D7795D8f6f8731FDA16";
+        let intent = TradeIntent::new("sonic")
+            .spend("0xtoken", "1")
+            .destination(destination);
+        let flagged = MarketContext {
+            screening: vec![ScreeningHit {
+                address: destination.to_string(),
+                source: "chainalysis".to_string(),
+                reason: "sanctions: OFAC SDN".to_string(),
+            }],
+            ..context(100.)
+        };
+
+        // blocking is the default mode
+        let err = engine.check("alice", &intent, flagged).await.unwrap_err();
+        assert!(err.to_string().contains("screened_address"));
+        assert!(engine.check("alice", &intent, context(100.)).await.is_ok());
+    }
+}
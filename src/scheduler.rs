@@ -0,0 +1,514 @@
+//! Recurring tool invocations (DCA buys, weekly reports, rebalances). Jobs
+//! are persisted in the kv store together with a reference to the signer of
+//! the user that scheduled them, the runner resolves the signer and calls the
+//! tool through the regular dispatch pipeline every time a job is due. Due
+//! runs are queued as scheduled_task jobs, they are not retried, a swap that
+//! failed halfway must not be sent twice
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Utc, Weekday};
+use once_cell::sync::Lazy;
+use rig::agent::Agent;
+use rig::providers::anthropic::completion::CompletionModel;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::confirmation::{
+    confirm_or_execute, preconfirmed, ALWAYS_CONFIRMED_TOOLS,
+};
+use crate::dispatch::dispatch_tool_call;
+use crate::jobs::{Job, RetryPolicy, JOBS};
+use crate::kv_store::{KVStore, KV_STORE};
+use crate::signer::{SignerContext, SignerRef, SignerResolver};
+
+const JOBS_KEY: &str = "scheduler:jobs";
+const DEFAULT_TICK_SECS: u64 = 30;
+const MIN_INTERVAL_SECS: i64 = 60;
+const SCHEDULED_TASK_JOB: &str = "scheduled_task";
+/// The only tools a job can run: trades, transfers and reports. The runs
+/// aren't confirmed, tools that change settings, create hooks, schedules
+/// or orders or confirm actions would run behind the user's back
+const SCHEDULABLE_TOOLS: &[&str] = &[
+    "perform_jupiter_swap",
+    "trade",
+    "multichain_swap",
+    "buy_pump_fun_token",
+    "sell_pump_fun_token",
+    "twap_swap",
+    "transfer_sol",
+    "transfer_spl_token",
+    "transfer_eth",
+    "transfer_erc20",
+    "get_portfolio",
+    "get_portfolio_history",
+    "get_paper_portfolio",
+    "get_cost_report",
+    "get_evm_token_price",
+    "get_trending_tokens",
+    "search_token",
+];
+
+/// When a job runs, all times are UTC
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schedule {
+    Every(Duration),
+    Daily(NaiveTime),
+    Weekly(Weekday, NaiveTime),
+}
+
+impl Schedule {
+    /// First run strictly after `after`
+    pub fn next_after(&self, after: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Self::Every(interval) => after + *interval,
+            Self::Daily(time) => {
+                let today = after.date_naive().and_time(*time).and_utc();
+                if today > after {
+                    today
+                } else {
+                    today + Duration::days(1)
+                }
+            }
+            Self::Weekly(weekday, time) => {
+                let days_ahead = (weekday.num_days_from_monday() + 7
+                    - after.weekday().num_days_from_monday())
+                    % 7;
+                let next = (after.date_naive()
+                    + Duration::days(days_ahead as i64))
+                .and_time(*time)
+                .and_utc();
+                if next > after {
+                    next
+                } else {
+                    next + Duration::weeks(1)
+                }
+            }
+        }
+    }
+}
+
+/// Parses `every <n><m|h|d|w>`, `daily HH:MM` and `weekly <weekday> HH:MM`
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts = s.split_whitespace().collect::<Vec<_>>();
+        let time = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M")
+                .map_err(|_| anyhow!("Invalid time {}, expected HH:MM", time))
+        };
+        match parts.as_slice() {
+            ["every", interval] => {
+                let unit_at = interval.len()
+                    - interval.chars().last().map_or(0, char::len_utf8);
+                let (value, unit) = interval.split_at(unit_at);
+                let value: i64 = value
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid interval {}", interval))?;
+                let interval = match unit {
+                    "s" => Duration::seconds(value),
+                    "m" => Duration::minutes(value),
+                    "h" => Duration::hours(value),
+                    "d" => Duration::days(value),
+                    "w" => Duration::weeks(value),
+                    _ => {
+                        return Err(anyhow!("Invalid interval {}", interval))
+                    }
+                };
+                if interval.num_seconds() < MIN_INTERVAL_SECS {
+                    return Err(anyhow!("Interval has to be at least 1m"));
+                }
+                Ok(Self::Every(interval))
+            }
+            ["daily", at] => Ok(Self::Daily(time(at)?)),
+            ["weekly", weekday, at] => Ok(Self::Weekly(
+                weekday
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid weekday {}", weekday))?,
+                time(at)?,
+            )),
+            _ => Err(anyhow!("Invalid schedule {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Every(interval) => {
+                let secs = interval.num_seconds();
+                match secs {
+                    _ if secs % 604_800 == 0 => {
+                        write!(f, "every {}w", secs / 604_800)
+                    }
+                    _ if secs % 86_400 == 0 => {
+                        write!(f, "every {}d", secs / 86_400)
+                    }
+                    _ if secs % 3600 == 0 => {
+                        write!(f, "every {}h", secs / 3600)
+                    }
+                    _ if secs % 60 == 0 => write!(f, "every {}m", secs / 60),
+                    _ => write!(f, "every {}s", secs),
+                }
+            }
+            Self::Daily(time) => write!(f, "daily {}", time.format("%H:%M")),
+            Self::Weekly(weekday, time) => {
+                write!(f, "weekly {} {}", weekday, time.format("%H:%M"))
+            }
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledJob {
+    pub id: String,
+    pub owner: SignerRef,
+    pub tool: String,
+    pub args: Value,
+    #[serde_as(as = "DisplayFromStr")]
+    pub schedule: Schedule,
+    pub next_run: DateTime<Utc>,
+    pub last_run: Option<DateTime<Utc>>,
+    pub last_result: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct Scheduler {
+    store: Arc<dyn KVStore>,
+    /// jobs are stored as a single value, writes have to be serialized
+    lock: tokio::sync::Mutex<()>,
+}
+
+pub static SCHEDULER: Lazy<Scheduler> =
+    Lazy::new(|| Scheduler::new(KV_STORE.clone()));
+
+impl Scheduler {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self {
+            store,
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    async fn load(&self) -> Result<Vec<ScheduledJob>> {
+        match self.store.get(JOBS_KEY).await? {
+            Some(jobs) => Ok(serde_json::from_str(&jobs)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    async fn save(&self, jobs: &[ScheduledJob]) -> Result<()> {
+        self.store
+            .set(JOBS_KEY, &serde_json::to_string(jobs)?)
+            .await
+    }
+
+    pub async fn add(&self, job: ScheduledJob) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut jobs = self.load().await?;
+        jobs.push(job);
+        self.save(&jobs).await
+    }
+
+    pub async fn list(&self, owner: &SignerRef) -> Result<Vec<ScheduledJob>> {
+        Ok(self
+            .load()
+            .await?
+            .into_iter()
+            .filter(|job| job.owner.same_user(owner))
+            .collect())
+    }
+
+    pub async fn cancel(
+        &self,
+        id: &str,
+        owner: &SignerRef,
+    ) -> Result<ScheduledJob> {
+        let _guard = self.lock.lock().await;
+        let mut jobs = self.load().await?;
+        let index = jobs
+            .iter()
+            .position(|job| job.id == id && job.owner.same_user(owner))
+            .ok_or_else(|| anyhow!("Scheduled task {} not found", id))?;
+        let job = jobs.remove(index);
+        self.save(&jobs).await?;
+        Ok(job)
+    }
+
+    /// Returns the jobs due at `now` and moves their next run forward right
+    /// away, so a slow run is never picked up twice
+    pub async fn take_due(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<ScheduledJob>> {
+        let _guard = self.lock.lock().await;
+        let mut jobs = self.load().await?;
+        let mut due = vec![];
+        for job in jobs.iter_mut().filter(|job| job.next_run <= now) {
+            due.push(job.clone());
+            job.next_run = job.schedule.next_after(now);
+        }
+        if !due.is_empty() {
+            self.save(&jobs).await?;
+        }
+        Ok(due)
+    }
+
+    async fn record_run(&self, id: &str, result: String) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut jobs = self.load().await?;
+        if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+            job.last_run = Some(Utc::now());
+            job.last_result = Some(result);
+            self.save(&jobs).await?;
+        }
+        Ok(())
+    }
+
+    /// Queues a run of every due job
+    pub async fn run_due(&self) -> Result<()> {
+        for job in self.take_due(Utc::now()).await? {
+            let run = Job::new(
+                SCHEDULED_TASK_JOB,
+                json!({ "id": job.id, "tool": job.tool, "args": job.args }),
+            )
+            .with_owner(job.owner)
+            .with_retry(RetryPolicy::NONE);
+            JOBS.enqueue(run).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs a queued scheduled_task job under the signer of its owner
+    async fn run_job(
+        &self,
+        job: Job,
+        agents: &[Arc<Agent<CompletionModel>>],
+    ) -> Result<String> {
+        let id = job.payload["id"].as_str().unwrap_or_default();
+        let tool = job.payload["tool"].as_str().unwrap_or_default();
+        // jobs scheduled before a tool was dropped from the list
+        let outcome = match check_schedulable(tool) {
+            Ok(()) => run_tool(tool, &job.payload["args"], agents).await,
+            Err(e) => Err(e),
+        };
+        let result = match &outcome {
+            Ok(result) => result.clone(),
+            Err(e) => {
+                tracing::warn!(?e, id, "scheduled task failed");
+                format!("Error: {}", e)
+            }
+        };
+        self.record_run(id, result).await?;
+        outcome
+    }
+
+    /// Queues due jobs every SCHEDULER_TICK_SECS (30 by default), the job
+    /// workers look the tools up in the given agents
+    pub fn spawn(
+        &'static self,
+        agents: Vec<Arc<Agent<CompletionModel>>>,
+    ) -> tokio::task::JoinHandle<()> {
+        let agents = Arc::new(agents);
+        JOBS.register(SCHEDULED_TASK_JOB, move |job: Job| {
+            let agents = agents.clone();
+            async move { self.run_job(job, &agents).await }
+        });
+        let tick = std::env::var("SCHEDULER_TICK_SECS")
+            .ok()
+            .and_then(|tick| tick.parse().ok())
+            .unwrap_or(DEFAULT_TICK_SECS);
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(std::time::Duration::from_secs(tick));
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.run_due().await {
+                    tracing::error!(?e, "scheduler tick failed");
+                }
+            }
+        })
+    }
+}
+
+/// Calls `tool` under the signer of `owner` without asking for
+/// confirmation, the owner agreed to the run when it was set up
+pub(crate) async fn run_tool_as(
+    owner: &SignerRef,
+    tool: &str,
+    args: &Value,
+    agents: &[Arc<Agent<CompletionModel>>],
+    resolver: &dyn SignerResolver,
+) -> Result<String> {
+    let signer = resolver.resolve(owner).await?;
+    SignerContext::with_signer(signer, run_tool(tool, args, agents)).await
+}
+
+/// Calls `tool` under the current signer without asking for confirmation,
+/// tools that always have to be confirmed are refused
+async fn run_tool(
+    tool: &str,
+    args: &Value,
+    agents: &[Arc<Agent<CompletionModel>>],
+) -> Result<String> {
+    if ALWAYS_CONFIRMED_TOOLS.contains(&tool) {
+        return Err(anyhow!("{} has to be confirmed by the user", tool));
+    }
+    let agent = agents
+        .iter()
+        .find(|agent| agent.tools.contains(tool))
+        .ok_or_else(|| anyhow!("Tool {} not found", tool))?;
+    preconfirmed(dispatch_tool_call(&agent.tools, tool, args.to_string()))
+        .await
+}
+
+fn check_schedulable(tool: &str) -> Result<()> {
+    if SCHEDULABLE_TOOLS.contains(&tool) {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "{} cannot be scheduled, only {}",
+        tool,
+        SCHEDULABLE_TOOLS.join(", ")
+    ))
+}
+
+async fn current_owner() -> SignerRef {
+    SignerRef::of(SignerContext::current().await.as_ref())
+}
+
+#[tool(description = "
+Schedules a tool to run repeatedly on behalf of the user, e.g. a DCA buy every
+day or a weekly portfolio report. Requires the user's confirmation, the runs
+themselves are not confirmed again. Only swaps, transfers and portfolio or
+price reports can be scheduled.
+
+tool is the name of the tool to run, e.g. perform_jupiter_swap
+args is a JSON object with the arguments of the tool
+schedule is one of:
+- every <n><m|h|d|w>, e.g. every 12h
+- daily HH:MM, e.g. daily 09:00
+- weekly <weekday> HH:MM, e.g. weekly mon 09:00
+all times are UTC
+")]
+pub async fn schedule_task(
+    tool: String,
+    args: String,
+    schedule: String,
+) -> Result<String> {
+    let schedule = Schedule::from_str(&schedule)?;
+    let args: Value = serde_json::from_str(&args)
+        .map_err(|e| anyhow!("args has to be a JSON object: {}", e))?;
+    check_schedulable(&tool)?;
+    let signer = SignerContext::current().await;
+    signer.tool_access().check(&tool)?;
+
+    let now = Utc::now();
+    let job = ScheduledJob {
+        id: format!("{:016x}", rand::random::<u64>()),
+        owner: SignerRef::of(signer.as_ref()),
+        tool,
+        args,
+        next_run: schedule.next_after(now),
+        schedule,
+        last_run: None,
+        last_result: None,
+        created_at: now,
+    };
+
+    let summary = format!(
+        "Run {} with {} {}, first run at {}",
+        job.tool, job.args, job.schedule, job.next_run
+    );
+    confirm_or_execute("schedule_task", summary, move || async move {
+        SCHEDULER.add(job.clone()).await?;
+        Ok(serde_json::to_string(&job)?)
+    })
+    .await
+}
+
+#[tool(description = "
+Lists the scheduled tasks of the user with their next run and the result of
+the last run
+")]
+pub async fn list_scheduled_tasks() -> Result<Vec<ScheduledJob>> {
+    SCHEDULER.list(&current_owner().await).await
+}
+
+#[tool(description = "
+Cancels a scheduled task
+")]
+pub async fn cancel_scheduled_task(id: String) -> Result<String> {
+    let job = SCHEDULER.cancel(&id, &current_owner().await).await?;
+    Ok(format!("Cancelled {} ({})", job.tool, job.schedule))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_schedulable_tools() {
+        assert!(check_schedulable("perform_jupiter_swap").is_ok());
+        for tool in [
+            "schedule_task",
+            "confirm_action",
+            "set_risk_limits",
+            "create_automation_hook",
+        ] {
+            assert!(check_schedulable(tool).is_err());
+        }
+        for tool in ALWAYS_CONFIRMED_TOOLS {
+            assert!(check_schedulable(tool).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_schedule() {
+        for schedule in ["every 12h", "every 90m", "daily 09:00"] {
+            assert_eq!(
+                Schedule::from_str(schedule).unwrap().to_string(),
+                schedule
+            );
+        }
+        assert_eq!(
+            Schedule::from_str("weekly monday 09:30")
+                .unwrap()
+                .to_string(),
+            "weekly Mon 09:30"
+        );
+        assert!(Schedule::from_str("every 10s").is_err());
+        assert!(Schedule::from_str("hourly").is_err());
+    }
+
+    #[test]
+    fn test_next_after() {
+        // a wednesday
+        let now = Utc.with_ymd_and_hms(2025, 1, 15, 10, 0, 0).unwrap();
+
+        let daily = Schedule::from_str("daily 09:00").unwrap();
+        assert_eq!(
+            daily.next_after(now),
+            Utc.with_ymd_and_hms(2025, 1, 16, 9, 0, 0).unwrap()
+        );
+
+        let weekly = Schedule::from_str("weekly wed 11:00").unwrap();
+        assert_eq!(
+            weekly.next_after(now),
+            Utc.with_ymd_and_hms(2025, 1, 15, 11, 0, 0).unwrap()
+        );
+
+        let weekly = Schedule::from_str("weekly mon 11:00").unwrap();
+        assert_eq!(
+            weekly.next_after(now),
+            Utc.with_ymd_and_hms(2025, 1, 20, 11, 0, 0).unwrap()
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! Compliance screening of the destinations of transfers and bridges, for
+//! operators that must not send funds to sanctioned addresses. The local
+//! denylist (screening.denylist) is checked on every transfer, the
+//! `AddressScreener`s (the Chainalysis sanctions API when
+//! screening.api_key is set, hosts register their own) only the first time
+//! a user sends to an address and again every screening.recheck_hours.
+//! The risk engine turns hits into violations or warnings following
+//! screening.mode
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::common::http_client;
+use crate::config::{config, ScreeningMode, ScreeningSettings};
+use crate::error::Error;
+use crate::kv_store::{namespaced, KVStore};
+use crate::metrics::observe_api;
+
+/// Why an address was flagged
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScreeningHit {
+    pub address: String,
+    /// denylist or the name of the screener
+    pub source: String,
+    pub reason: String,
+}
+
+/// Sanctions or risk API consulted for new destinations
+#[async_trait]
+pub trait AddressScreener: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Reasons the address is flagged for, empty when it is clear
+    async fn screen(&self, address: &str) -> Result<Vec<String>>;
+}
+
+/// Sanctions screening of Chainalysis, flags the addresses it identifies
+pub struct ChainalysisScreener {
+    client: Client,
+    api_url: String,
+    api_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identification {
+    category: Option<String>,
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChainalysisResponse {
+    identifications: Vec<Identification>,
+}
+
+impl ChainalysisScreener {
+    pub fn new(api_url: String, api_key: String) -> Self {
+        Self {
+            client: http_client(),
+            api_url,
+            api_key,
+        }
+    }
+}
+
+#[async_trait]
+impl AddressScreener for ChainalysisScreener {
+    fn name(&self) -> &str {
+        "chainalysis"
+    }
+
+    async fn screen(&self, address: &str) -> Result<Vec<String>> {
+        let response = observe_api("chainalysis", async {
+            Ok(self
+                .client
+                .get(format!(
+                    "{}/{}",
+                    self.api_url.trim_end_matches('/'),
+                    address
+                ))
+                .header("X-API-Key", &self.api_key)
+                .header("Accept", "application/json")
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<ChainalysisResponse>()
+                .await?)
+        })
+        .await?;
+        Ok(response
+            .identifications
+            .into_iter()
+            .map(|identification| {
+                [identification.category, identification.name]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>()
+                    .join(": ")
+            })
+            .collect())
+    }
+}
+
+/// EVM addresses are compared case-insensitively, base58 is case-sensitive
+fn same_address(a: &str, b: &str) -> bool {
+    if a.starts_with("0x") {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
+
+pub struct AddressScreening {
+    /// destinations screened clear, by user
+    store: Arc<dyn KVStore>,
+    screeners: RwLock<Vec<Arc<dyn AddressScreener>>>,
+}
+
+pub static SCREENING: Lazy<AddressScreening> = Lazy::new(|| {
+    let screening = AddressScreening::new(Arc::new(namespaced("screening")));
+    let config = config();
+    if let Some(api_key) = &config.screening.api_key {
+        screening.register(Arc::new(ChainalysisScreener::new(
+            config.screening.api_url.clone(),
+            api_key.clone(),
+        )));
+    }
+    screening
+});
+
+impl AddressScreening {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self {
+            store,
+            screeners: RwLock::new(vec![]),
+        }
+    }
+
+    pub fn register(&self, screener: Arc<dyn AddressScreener>) {
+        self.screeners.write().unwrap().push(screener);
+    }
+
+    fn known_key(user: &str, address: &str) -> String {
+        if address.starts_with("0x") {
+            format!("{}:{}", user, address.to_lowercase())
+        } else {
+            format!("{}:{}", user, address)
+        }
+    }
+
+    /// Screens a destination of `user`, no hits when screening is off.
+    /// Screeners that fail block the transfer in block mode, in warn mode
+    /// the failure is reported as a hit
+    pub async fn screen(
+        &self,
+        user: &str,
+        address: &str,
+        settings: &ScreeningSettings,
+    ) -> Result<Vec<ScreeningHit>> {
+        if !settings.enabled {
+            return Ok(vec![]);
+        }
+        let hit = |source: &str, reason: String| ScreeningHit {
+            address: address.to_string(),
+            source: source.to_string(),
+            reason,
+        };
+        if settings
+            .denylist
+            .iter()
+            .any(|denied| same_address(address, denied))
+        {
+            return Ok(vec![hit(
+                "denylist",
+                "denied by the operator".into(),
+            )]);
+        }
+
+        let known = Self::known_key(user, address);
+        if self.store.get(&known).await?.is_some() {
+            return Ok(vec![]);
+        }
+        let screeners = self.screeners.read().unwrap().clone();
+        let mut hits = vec![];
+        for screener in screeners {
+            match screener.screen(address).await {
+                Ok(reasons) => hits.extend(
+                    reasons
+                        .into_iter()
+                        .map(|reason| hit(screener.name(), reason)),
+                ),
+                Err(e) if settings.mode == ScreeningMode::Block => {
+                    return Err(Error::Unavailable(format!(
+                        "{} couldn't be screened by {}: {}",
+                        address,
+                        screener.name(),
+                        e
+                    ))
+                    .into());
+                }
+                Err(e) => hits.push(hit(
+                    screener.name(),
+                    format!("not screened: {}", e),
+                )),
+            }
+        }
+        if hits.is_empty() {
+            let recheck = Duration::from_secs(settings.recheck_hours * 3600);
+            self.store.set_with_ttl(&known, "clear", recheck).await?;
+        } else {
+            tracing::warn!(user, address, ?hits, "destination flagged");
+        }
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    const SANCTIONED: &str = "0x8589427373D6D84E98730D7795D8f6f8731FDA16";
+
+    #[derive(Default)]
+    struct CountingScreener {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl AddressScreener for CountingScreener {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        async fn screen(&self, address: &str) -> Result<Vec<String>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(if same_address(address, SANCTIONED) {
+                vec!["sanctions: OFAC SDN Tornado Cash".to_string()]
+            } else {
+                vec![]
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_screen_new_destinations() {
+        let settings = ScreeningSettings {
+            enabled: true,
+            denylist: vec![
+                "0xdead000000000000000000000000000000000000".into()
+            ],
+            ..Default::default()
+        };
+        let screening =
+            AddressScreening::new(Arc::new(InMemoryKVStore::new()));
+        let screener = Arc::new(CountingScreener::default());
+        screening.register(screener.clone());
+
+        let clean = "0x1f9840a85d5aF5bf1D1762F925BDADdC4201F984";
+        let screen = |address: String| {
+            let screening = &screening;
+            let settings = &settings;
+            async move {
+                screening.screen("alice", &address, settings).await.unwrap()
+            }
+        };
+        assert!(screen(clean.to_string()).await.is_empty());
+        // known from then on, the API isn't asked again
+        assert!(screen(clean.to_string()).await.is_empty());
+        assert_eq!(screener.calls.load(Ordering::SeqCst), 1);
+
+        let hits = screen(SANCTIONED.to_lowercase()).await;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].source, "counting");
+        // flagged destinations are screened every time
+        assert_eq!(screen(SANCTIONED.to_string()).await.len(), 1);
+        assert_eq!(screener.calls.load(Ordering::SeqCst), 3);
+
+        let hits =
+            screen("0xDEAD000000000000000000000000000000000000".into()).await;
+        assert_eq!(hits[0].source, "denylist");
+        assert_eq!(screener.calls.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,294 @@
+//! Graceful shutdown, so deployments can roll without orphaning the
+//! transactions of users. On SIGTERM or Ctrl-C no new tool calls are taken,
+//! in-flight calls that haven't signed yet are cancelled and the signing
+//! ones are waited for until they are sent and audited, up to
+//! shutdown.drain_timeout_secs. Calls still running then are persisted as
+//! interrupted so they can be reconciled after the restart
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Notify;
+
+use crate::cancellation::CancellationToken;
+use crate::kv_store::{KVStore, KV_STORE};
+use crate::signer::TransactionSigner;
+use crate::tool_error::{ErrorCode, ToolError};
+
+const INTERRUPTED_PREFIX: &str = "shutdown:interrupted:";
+
+/// A tool call that was still running when the process exited
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterruptedCall {
+    pub tool: String,
+    pub args: Value,
+    pub user: Option<String>,
+    /// the transaction may have been sent, check the wallet before retrying
+    pub signing: bool,
+    pub started_at: DateTime<Utc>,
+    pub interrupted_at: DateTime<Utc>,
+}
+
+struct InFlight {
+    tool: String,
+    args: String,
+    user: Option<String>,
+    token: CancellationToken,
+    started_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DrainReport {
+    /// calls that finished while draining
+    pub drained: usize,
+    pub interrupted: Vec<InterruptedCall>,
+}
+
+pub struct ShutdownCoordinator {
+    store: Arc<dyn KVStore>,
+    draining: AtomicBool,
+    next_id: AtomicU64,
+    in_flight: Mutex<HashMap<u64, InFlight>>,
+    notify: Notify,
+}
+
+pub static SHUTDOWN: Lazy<ShutdownCoordinator> =
+    Lazy::new(|| ShutdownCoordinator::new(KV_STORE.clone()));
+
+/// Registration of an in-flight call, removed on drop
+pub struct InFlightGuard<'a> {
+    coordinator: &'a ShutdownCoordinator,
+    id: u64,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.coordinator.in_flight.lock().unwrap().remove(&self.id);
+        self.coordinator.notify.notify_waiters();
+    }
+}
+
+impl ShutdownCoordinator {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self {
+            store,
+            draining: AtomicBool::new(false),
+            next_id: AtomicU64::new(0),
+            in_flight: Mutex::new(HashMap::new()),
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Registers a tool call, fails once the shutdown began
+    pub fn begin(
+        &self,
+        tool: &str,
+        args: &str,
+        signer: Option<&dyn TransactionSigner>,
+        token: &CancellationToken,
+    ) -> Result<InFlightGuard<'_>> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.in_flight.lock().unwrap().insert(
+            id,
+            InFlight {
+                tool: tool.to_string(),
+                args: args.to_string(),
+                user: signer.and_then(|signer| signer.user_id()),
+                token: token.clone(),
+                started_at: Utc::now(),
+            },
+        );
+        let guard = InFlightGuard {
+            coordinator: self,
+            id,
+        };
+        // checked after registering so `drain` can't miss the call
+        if self.is_draining() {
+            return Err(ToolError::new(
+                ErrorCode::Network,
+                "The agent is restarting and doesn't take new calls",
+            )
+            .with_hint("Retry the call in a moment")
+            .into());
+        }
+        Ok(guard)
+    }
+
+    fn in_flight_count(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+
+    async fn idle(&self) {
+        loop {
+            let notified = self.notify.notified();
+            if self.in_flight_count() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Stops taking calls, cancels the ones that can still stop and waits
+    /// up to `timeout` for the signing ones, the rest is persisted
+    pub async fn drain(&self, timeout: Duration) -> DrainReport {
+        self.draining.store(true, Ordering::SeqCst);
+        let running = {
+            let in_flight = self.in_flight.lock().unwrap();
+            in_flight.values().for_each(|call| call.token.cancel());
+            in_flight.len()
+        };
+        if tokio::time::timeout(timeout, self.idle()).await.is_ok() {
+            return DrainReport {
+                drained: running,
+                interrupted: vec![],
+            };
+        }
+
+        let interrupted: Vec<_> = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&id, call)| {
+                let interrupted = InterruptedCall {
+                    tool: call.tool.clone(),
+                    args: serde_json::from_str(&call.args)
+                        .unwrap_or_else(|_| Value::String(call.args.clone())),
+                    user: call.user.clone(),
+                    signing: call.token.is_signing(),
+                    started_at: call.started_at,
+                    interrupted_at: Utc::now(),
+                };
+                (id, interrupted)
+            })
+            .collect();
+        for (id, call) in &interrupted {
+            if let Err(e) = self.persist(*id, call).await {
+                tracing::error!(?e, tool = %call.tool, "interrupted call lost");
+            }
+        }
+        DrainReport {
+            drained: running.saturating_sub(interrupted.len()),
+            interrupted: interrupted
+                .into_iter()
+                .map(|(_, call)| call)
+                .collect(),
+        }
+    }
+
+    async fn persist(&self, id: u64, call: &InterruptedCall) -> Result<()> {
+        let key = format!(
+            "{}{}-{}",
+            INTERRUPTED_PREFIX,
+            call.interrupted_at.timestamp_millis(),
+            id
+        );
+        self.store.set(&key, &serde_json::to_string(call)?).await
+    }
+
+    /// Calls interrupted by earlier shutdowns
+    pub async fn interrupted_calls(&self) -> Result<Vec<InterruptedCall>> {
+        let mut calls = self
+            .store
+            .scan_prefix(INTERRUPTED_PREFIX)
+            .await?
+            .into_iter()
+            .filter_map(|(_, call)| serde_json::from_str(&call).ok())
+            .collect::<Vec<InterruptedCall>>();
+        calls.sort_by_key(|call| call.interrupted_at);
+        Ok(calls)
+    }
+}
+
+/// Resolves on SIGTERM or Ctrl-C
+pub async fn signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(
+            tokio::signal::unix::SignalKind::terminate(),
+        ) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::error!(?e, "SIGTERM handler not installed");
+                std::future::pending::<()>().await
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancellation::begin_signing;
+    use crate::kv_store::InMemoryKVStore;
+    use crate::signer::SignerContext;
+
+    #[tokio::test]
+    async fn test_drain() {
+        let shutdown =
+            ShutdownCoordinator::new(Arc::new(InMemoryKVStore::new()));
+        let quoting = CancellationToken::new();
+        let signing = CancellationToken::new();
+        let quote = shutdown.begin("trade", "{}", None, &quoting).unwrap();
+        let transfer = shutdown
+            .begin("transfer_sol", "{}", None, &signing)
+            .unwrap();
+        let guard =
+            SignerContext::with_cancellation(signing.clone(), async {
+                begin_signing().await
+            })
+            .await
+            .unwrap();
+
+        // the quoting call stops once cancelled, the signing one finishes
+        let finish = async {
+            quoting.interrupted().await;
+            drop(quote);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            drop(guard);
+            drop(transfer);
+        };
+        let (report, _) =
+            tokio::join!(shutdown.drain(Duration::from_secs(1)), finish);
+        assert_eq!(report.drained, 2);
+        assert!(report.interrupted.is_empty());
+
+        let token = CancellationToken::new();
+        assert!(shutdown.begin("trade", "{}", None, &token).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout() {
+        let shutdown =
+            ShutdownCoordinator::new(Arc::new(InMemoryKVStore::new()));
+        let token = CancellationToken::new();
+        let _call = shutdown
+            .begin("multichain_swap", r#"{"amount":"1"}"#, None, &token)
+            .unwrap();
+        let report = shutdown.drain(Duration::from_millis(10)).await;
+        assert_eq!(report.drained, 0);
+        assert_eq!(report.interrupted[0].tool, "multichain_swap");
+
+        let calls = shutdown.interrupted_calls().await.unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].args["amount"], "1");
+    }
+}
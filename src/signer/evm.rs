@@ -8,12 +8,14 @@ use alloy::signers::k256;
 use crate::signer::evm::k256::ecdsa::SigningKey;
 
 use crate::evm::transaction::send_transaction;
-use crate::evm::util::make_provider;
+use crate::evm::util::rpc_url_for_chain;
 
 use super::TransactionSigner;
 
 pub struct LocalEvmSigner {
     wallet: EthereumWallet,
+    /// sends every transaction to this node instead of the chain's RPC
+    rpc_url: Option<String>,
 }
 
 impl LocalEvmSigner {
@@ -22,7 +24,15 @@ impl LocalEvmSigner {
             PrivateKeySigner::from_str(&private_key)
                 .expect("make evm PrivateKeySigner"),
         );
-        Self { wallet }
+        Self {
+            wallet,
+            rpc_url: None,
+        }
+    }
+
+    pub fn with_rpc_url(mut self, rpc_url: &str) -> Self {
+        self.rpc_url = Some(rpc_url.to_string());
+        self
     }
 }
 
@@ -43,10 +53,18 @@ impl TransactionSigner for LocalEvmSigner {
         self.wallet.default_signer().address().to_string()
     }
 
+    fn wallet_id(&self) -> Option<String> {
+        Some(self.address())
+    }
+
     async fn sign_and_send_evm_transaction(
         &self,
         tx: alloy::rpc::types::TransactionRequest,
     ) -> Result<String> {
-        send_transaction(tx, &make_provider()?, &self.wallet).await
+        let rpc_url = match &self.rpc_url {
+            Some(rpc_url) => rpc_url.clone(),
+            None => rpc_url_for_chain(tx.chain_id)?,
+        };
+        send_transaction(tx, &rpc_url, &self.wallet).await
     }
 }
@@ -0,0 +1,190 @@
+//! Encrypted keypair files of the local Solana signer, so self-hosters
+//! don't keep raw secret keys on disk. The secret key is sealed with
+//! AES-256-GCM under a key derived from a passphrase with
+//! PBKDF2-HMAC-SHA256, the public key stays readable and is authenticated
+//! with the ciphertext. `migrate` turns a plaintext keypair into such a
+//! file, `cargo run --bin keystore -- migrate <keypair> <keystore>` runs it
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use solana_sdk::signature::Keypair;
+use solana_sdk::signer::Signer;
+
+use crate::kv_encryption;
+
+const VERSION: u32 = 1;
+const KDF: &str = "pbkdf2-hmac-sha256";
+/// OWASP recommendation for PBKDF2-HMAC-SHA256
+const ITERATIONS: u32 = 600_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keystore {
+    pub version: u32,
+    pub pubkey: String,
+    pub kdf: String,
+    pub iterations: u32,
+    /// base64
+    pub salt: String,
+    /// base64 nonce and ciphertext of the 64-byte keypair
+    pub sealed: String,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(
+        passphrase.as_bytes(),
+        salt,
+        iterations,
+        &mut key,
+    );
+    key
+}
+
+impl Keystore {
+    pub fn seal(keypair: &Keypair, passphrase: &str) -> Result<Self> {
+        Self::seal_with(keypair, passphrase, ITERATIONS)
+    }
+
+    fn seal_with(
+        keypair: &Keypair,
+        passphrase: &str,
+        iterations: u32,
+    ) -> Result<Self> {
+        if passphrase.is_empty() {
+            return Err(anyhow!("The keystore passphrase is empty"));
+        }
+        let pubkey = keypair.pubkey().to_string();
+        let salt = rand::random::<[u8; 16]>();
+        let key = derive_key(passphrase, &salt, iterations);
+        let sealed = kv_encryption::seal(
+            &key,
+            &keypair.to_bytes(),
+            pubkey.as_bytes(),
+        )?;
+        Ok(Self {
+            version: VERSION,
+            pubkey,
+            kdf: KDF.to_string(),
+            iterations,
+            salt: BASE64_STANDARD.encode(salt),
+            sealed: BASE64_STANDARD.encode(sealed),
+        })
+    }
+
+    pub fn open(&self, passphrase: &str) -> Result<Keypair> {
+        if self.version != VERSION || self.kdf != KDF {
+            return Err(anyhow!(
+                "Unsupported keystore version {} ({})",
+                self.version,
+                self.kdf
+            ));
+        }
+        let key = derive_key(
+            passphrase,
+            &BASE64_STANDARD.decode(&self.salt)?,
+            self.iterations,
+        );
+        let bytes = kv_encryption::open(
+            &key,
+            &BASE64_STANDARD.decode(&self.sealed)?,
+            self.pubkey.as_bytes(),
+        )
+        .map_err(|_| anyhow!("Wrong passphrase or tampered keystore"))?;
+        let keypair = Keypair::from_bytes(&bytes)
+            .map_err(|_| anyhow!("The keystore holds no keypair"))?;
+        if keypair.pubkey().to_string() != self.pubkey {
+            return Err(anyhow!("The keystore holds another keypair"));
+        }
+        Ok(keypair)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read_to_string(path).map_err(|e| {
+            anyhow!("Failed to read the keystore {}: {}", path.display(), e)
+        })?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    /// Writes the keystore readable by its owner only
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path.as_ref())?;
+        std::io::Write::write_all(
+            &mut file,
+            serde_json::to_string_pretty(self)?.as_bytes(),
+        )?;
+        Ok(())
+    }
+}
+
+/// Keypair of a solana-keygen JSON file or of a base58 secret key
+fn parse_plaintext(plaintext: &str) -> Result<Keypair> {
+    let plaintext = plaintext.trim();
+    if plaintext.starts_with('[') {
+        let bytes: Vec<u8> = serde_json::from_str(plaintext)?;
+        return Keypair::from_bytes(&bytes)
+            .map_err(|_| anyhow!("Invalid keypair file"));
+    }
+    let bytes = bs58::decode(plaintext)
+        .into_vec()
+        .map_err(|_| anyhow!("Invalid base58 secret key"))?;
+    Keypair::from_bytes(&bytes).map_err(|_| anyhow!("Invalid secret key"))
+}
+
+/// Encrypts the plaintext keypair at `source` (a solana-keygen JSON file or
+/// a base58 secret key) into a keystore at `destination`, the source is
+/// left for the caller to delete once the keystore was checked. Returns the
+/// public key
+pub fn migrate(
+    source: impl AsRef<Path>,
+    destination: impl AsRef<Path>,
+    passphrase: &str,
+) -> Result<String> {
+    let keypair = parse_plaintext(&std::fs::read_to_string(source)?)?;
+    let keystore = Keystore::seal(&keypair, passphrase)?;
+    keystore.save(destination)?;
+    Ok(keystore.pubkey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let keypair = Keypair::new();
+        let keystore = Keystore::seal_with(&keypair, "hunter2", 10).unwrap();
+        assert_eq!(keystore.pubkey, keypair.pubkey().to_string());
+        assert!(!keystore.sealed.contains(&keypair.to_base58_string()));
+        assert_eq!(
+            keystore.open("hunter2").unwrap().to_bytes(),
+            keypair.to_bytes()
+        );
+        assert!(keystore.open("hunter3").is_err());
+
+        let mut swapped = keystore.clone();
+        swapped.pubkey = Keypair::new().pubkey().to_string();
+        assert!(swapped.open("hunter2").is_err());
+
+        let json =
+            serde_json::to_string(&keypair.to_bytes().to_vec()).unwrap();
+        assert_eq!(
+            parse_plaintext(&json).unwrap().to_bytes(),
+            keypair.to_bytes()
+        );
+        assert_eq!(
+            parse_plaintext(&keypair.to_base58_string())
+                .unwrap()
+                .to_bytes(),
+            keypair.to_bytes()
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! Solana signer of a keypair held by the process, for bots, scripts and
+//! tests running without Privy. The keypair comes from a solana-keygen
+//! file, a base58 key or an encrypted keystore, see `from_env`
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use solana_sdk::signature::{Keypair, Signature};
+use solana_sdk::signer::Signer;
+use solana_sdk::transaction::VersionedTransaction;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::outbox::OUTBOX;
+use crate::solana::blockhash::BLOCKHASH_CACHE;
+
+use super::keystore::Keystore;
+use super::TransactionSigner;
+
+pub struct LocalSolanaSigner {
+    keypair: Arc<Keypair>,
+}
+
+impl LocalSolanaSigner {
+    /// Panics on an invalid key, see `from_base58`
+    pub fn new(private_key: String) -> Self {
+        Self::from_base58(&private_key).expect("Invalid Solana private key")
+    }
+
+    pub fn from_keypair(keypair: Keypair) -> Self {
+        Self {
+            keypair: Arc::new(keypair),
+        }
+    }
+
+    /// Signer of the 64 bytes of a keypair in base58, as Phantom exports it
+    pub fn from_base58(private_key: &str) -> Result<Self> {
+        let bytes =
+            bs58::decode(private_key.trim()).into_vec().map_err(|_| {
+                Error::Signer("The key is not base58".to_string())
+            })?;
+        let keypair = Keypair::from_bytes(&bytes).map_err(|_| {
+            Error::Signer("The key is not a Solana keypair".to_string())
+        })?;
+        Ok(Self::from_keypair(keypair))
+    }
+
+    /// Signer of a keypair file of solana-keygen, a JSON array of the 64
+    /// bytes, or of a file holding the base58 key
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            anyhow!("Failed to read the keypair {}: {}", path.display(), e)
+        })?;
+        if !content.trim_start().starts_with('[') {
+            return Self::from_base58(&content);
+        }
+        let bytes: Vec<u8> =
+            serde_json::from_str(&content).map_err(|_| {
+                Error::Signer(format!(
+                    "{} is not a keypair file",
+                    path.display()
+                ))
+            })?;
+        let keypair = Keypair::from_bytes(&bytes).map_err(|_| {
+            Error::Signer(format!("{} is not a keypair file", path.display()))
+        })?;
+        Ok(Self::from_keypair(keypair))
+    }
+
+    /// Signer of an encrypted keypair file, see `keystore::migrate` to
+    /// create one from a plaintext key
+    pub fn from_keystore(
+        path: impl AsRef<Path>,
+        passphrase: &str,
+    ) -> Result<Self> {
+        Keystore::load(path)?
+            .open(passphrase)
+            .map(Self::from_keypair)
+    }
+
+    /// SOLANA_KEYSTORE with SOLANA_KEYSTORE_PASSPHRASE when set, then the
+    /// keypair file SOLANA_KEYPAIR_PATH, the plaintext SOLANA_PRIVATE_KEY
+    /// otherwise
+    pub fn from_env() -> Result<Self> {
+        if let Ok(path) = std::env::var("SOLANA_KEYSTORE") {
+            let passphrase = std::env::var("SOLANA_KEYSTORE_PASSPHRASE")
+                .map_err(|_| {
+                    anyhow!("SOLANA_KEYSTORE_PASSPHRASE env var not set")
+                })?;
+            return Self::from_keystore(path, &passphrase);
+        }
+        if let Ok(path) = std::env::var("SOLANA_KEYPAIR_PATH") {
+            return Self::from_file(path);
+        }
+        let private_key =
+            std::env::var("SOLANA_PRIVATE_KEY").map_err(|_| {
+                anyhow!(
+                    "None of SOLANA_KEYSTORE, SOLANA_KEYPAIR_PATH and \
+                     SOLANA_PRIVATE_KEY is set"
+                )
+            })?;
+        Self::from_base58(&private_key)
+    }
+}
+
+/// Signs `tx` as it was built, keeping its blockhash and the signatures of
+/// its other signers
+fn sign_encoded(
+    tx: &mut VersionedTransaction,
+    keypair: &Keypair,
+) -> Result<()> {
+    let signers = tx.message.header().num_required_signatures as usize;
+    let index = tx
+        .message
+        .static_account_keys()
+        .iter()
+        .take(signers)
+        .position(|key| *key == keypair.pubkey())
+        .ok_or_else(|| {
+            Error::Signer(
+                "The transaction is not to be signed by this wallet"
+                    .to_string(),
+            )
+        })?;
+    tx.signatures.resize(signers, Signature::default());
+    tx.signatures[index] = keypair.sign_message(&tx.message.serialize());
+    Ok(())
+}
+
+#[async_trait]
+impl TransactionSigner for LocalSolanaSigner {
+    fn pubkey(&self) -> String {
+        self.keypair.pubkey().to_string()
+    }
+
+    fn wallet_id(&self) -> Option<String> {
+        Some(self.keypair.pubkey().to_string())
+    }
+
+    async fn sign_and_send_solana_transaction(
+        &self,
+        mut tx: solana_sdk::transaction::Transaction,
+    ) -> Result<String> {
+        let recent_blockhash = BLOCKHASH_CACHE.get_blockhash().await?;
+        tx.try_sign(&[&*self.keypair], recent_blockhash)?;
+        OUTBOX.send_solana(&tx).await
+    }
+
+    async fn sign_and_send_versioned_solana_transaction(
+        &self,
+        tx: VersionedTransaction,
+    ) -> Result<String> {
+        let mut message = tx.message;
+        message.set_recent_blockhash(BLOCKHASH_CACHE.get_blockhash().await?);
+        let tx = VersionedTransaction::try_new(message, &[&*self.keypair])?;
+        OUTBOX.send_solana(&tx).await
+    }
+
+    /// Base64 transactions built by an API (LiFi), signed as they are like
+    /// Privy does
+    async fn sign_and_send_encoded_solana_transaction(
+        &self,
+        tx: String,
+    ) -> Result<String> {
+        let bytes = BASE64_STANDARD.decode(tx.trim()).map_err(|_| {
+            Error::Signer("The transaction is not base64".to_string())
+        })?;
+        let mut tx: VersionedTransaction = bincode::deserialize(&bytes)
+            .map_err(|_| {
+                Error::Signer("The transaction can't be decoded".to_string())
+            })?;
+        sign_encoded(&mut tx, &self.keypair)?;
+        OUTBOX.send_solana(&tx).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::hash::Hash;
+    use solana_sdk::system_instruction::transfer;
+    use solana_sdk::transaction::Transaction;
+
+    use super::*;
+
+    #[test]
+    fn test_local_solana_signer() {
+        let keypair = Keypair::new();
+        let base58 = keypair.to_base58_string();
+        let signer = LocalSolanaSigner::from_base58(&base58).unwrap();
+        assert_eq!(signer.pubkey(), keypair.pubkey().to_string());
+        assert!(LocalSolanaSigner::from_base58("not a key").is_err());
+        // no EVM wallet, EVM tools are refused rather than given the pubkey
+        assert!(crate::address::Address::of_signer(
+            &signer,
+            crate::chains::Chain::Evm
+        )
+        .is_err());
+
+        let path = std::env::temp_dir()
+            .join(format!("keypair-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            serde_json::to_string(&keypair.to_bytes().to_vec()).unwrap(),
+        )
+        .unwrap();
+        let signer = LocalSolanaSigner::from_file(&path).unwrap();
+        assert_eq!(signer.pubkey(), keypair.pubkey().to_string());
+        std::fs::write(&path, &base58).unwrap();
+        assert!(LocalSolanaSigner::from_file(&path).is_ok());
+        std::fs::write(&path, "[1, 2, 3]").unwrap();
+        assert!(LocalSolanaSigner::from_file(&path).is_err());
+        std::fs::remove_file(path).ok();
+
+        // signed as built, the signature of the other signer is kept
+        let other = Keypair::new();
+        let mut tx = Transaction::new_with_payer(
+            &[transfer(&other.pubkey(), &keypair.pubkey(), 1)],
+            Some(&keypair.pubkey()),
+        );
+        tx.partial_sign(&[&other], Hash::new_unique());
+        let mut tx = VersionedTransaction::from(tx);
+        sign_encoded(&mut tx, &keypair).unwrap();
+        assert!(tx.verify_with_results().into_iter().all(|ok| ok));
+        assert!(sign_encoded(&mut tx, &Keypair::new()).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use solana_sdk::pubkey::Pubkey;
+
+use super::TransactionSigner;
+
+/// Signer for benchmarks and load tests. Holds no keys, waits `latency` in
+/// place of signing and broadcasting and returns a made up signature, so
+/// the numbers measure the code around the signer and not the network
+pub struct MockSigner {
+    pubkey: Pubkey,
+    address: alloy::primitives::Address,
+    user_id: Option<String>,
+    session_id: Option<String>,
+    latency: Duration,
+    sent: AtomicU64,
+}
+
+impl MockSigner {
+    pub fn new(latency: Duration) -> Self {
+        Self {
+            pubkey: Pubkey::new_unique(),
+            address: alloy::primitives::Address::random(),
+            user_id: None,
+            session_id: None,
+            latency,
+            sent: AtomicU64::new(0),
+        }
+    }
+
+    /// Separate users aren't rate limited against each other
+    pub fn with_user(mut self, user_id: &str) -> Self {
+        self.user_id = Some(user_id.to_string());
+        self
+    }
+
+    pub fn with_session(mut self, session_id: &str) -> Self {
+        self.session_id = Some(session_id.to_string());
+        self
+    }
+
+    /// Transactions "sent" so far
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    async fn send(&self) -> Result<String> {
+        if !self.latency.is_zero() {
+            tokio::time::sleep(self.latency).await;
+        }
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        Ok(format!("mock-{:016x}", rand::random::<u64>()))
+    }
+}
+
+#[async_trait]
+impl TransactionSigner for MockSigner {
+    fn address(&self) -> String {
+        self.address.to_string()
+    }
+
+    fn pubkey(&self) -> String {
+        self.pubkey.to_string()
+    }
+
+    fn user_id(&self) -> Option<String> {
+        self.user_id.clone()
+    }
+
+    fn session_id(&self) -> Option<String> {
+        self.session_id.clone()
+    }
+
+    async fn sign_and_send_solana_transaction(
+        &self,
+        _tx: solana_sdk::transaction::Transaction,
+    ) -> Result<String> {
+        self.send().await
+    }
+
+    async fn sign_and_send_versioned_solana_transaction(
+        &self,
+        _tx: solana_sdk::transaction::VersionedTransaction,
+    ) -> Result<String> {
+        self.send().await
+    }
+
+    async fn sign_and_send_evm_transaction(
+        &self,
+        _tx: alloy::rpc::types::TransactionRequest,
+    ) -> Result<String> {
+        self.send().await
+    }
+
+    async fn sign_and_send_encoded_solana_transaction(
+        &self,
+        _tx: String,
+    ) -> Result<String> {
+        self.send().await
+    }
+
+    async fn sign_and_send_json_evm_transaction(
+        &self,
+        _tx: serde_json::Value,
+    ) -> Result<String> {
+        self.send().await
+    }
+}
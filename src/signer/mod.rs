@@ -1,93 +1,188 @@
-#[cfg(feature = "evm")]
 pub mod evm;
-#[cfg(feature = "solana")]
+pub mod keystore;
+pub mod local_solana;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock;
+pub mod paper;
+#[cfg(feature = "http")]
 pub mod privy;
-#[cfg(feature = "http")] // NOTE: changed from solana
-pub mod solana;
+pub mod registry;
+
+/// Former path of `local_solana`, kept for the crates using it
+pub use self::local_solana as solana;
 
 use std::future::Future;
 use std::sync::Arc;
 
 use anyhow::Result;
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::access::ToolAccess;
+use crate::cancellation::CancellationToken;
+use crate::error::Error;
 
-#[cfg(feature = "evm")]
 use self::evm::LocalEvmSigner;
-#[cfg(feature = "solana")]
+use self::local_solana::LocalSolanaSigner;
+#[cfg(feature = "http")]
 use self::privy::PrivySigner;
-#[cfg(feature = "http")] // NOTE: changed from solana
-use self::solana::LocalSolanaSigner;
 
 pub enum Transaction {
-    #[cfg(feature = "solana")]
     Solana(solana_sdk::transaction::Transaction),
-    #[cfg(feature = "evm")]
     Evm(),
 }
 
 pub enum SignerType {
-    #[cfg(feature = "http")] // NOTE: changed from solana
     LocalSolana(LocalSolanaSigner),
-    #[cfg(feature = "evm")]
     LocalEvm(LocalEvmSigner),
-    #[cfg(any(
-        feature = "solana",
-        not(any(feature = "evm", feature = "http"))
-    ))]
+    #[cfg(feature = "http")]
     Privy(PrivySigner),
 }
 
+/// Signs and broadcasts transactions of the tools. The futures run on the
+/// runtime of the caller, implementations have to stay `Send` and must not
+/// block, use the nonblocking RPC clients
 #[async_trait]
 pub trait TransactionSigner: Send + Sync {
+    /// EVM address of the wallet, empty when the signer has none, see
+    /// `Address::of_signer`
     fn address(&self) -> String {
-        unimplemented!()
+        String::new()
     }
 
+    /// Solana public key of the wallet, empty when the signer has none
     fn pubkey(&self) -> String {
-        unimplemented!()
+        String::new()
+    }
+
+    /// Identifies the user behind the signer, used to scope per-user state
+    /// like pending confirmations. Local signers are single-user
+    fn user_id(&self) -> Option<String> {
+        None
+    }
+
+    /// Conversation session of the user, None for local signers
+    fn session_id(&self) -> Option<String> {
+        None
+    }
+
+    /// Public key or address of the wallet, for logs
+    fn wallet_id(&self) -> Option<String> {
+        None
+    }
+
+    /// Tools the session behind the signer is allowed to call
+    fn tool_access(&self) -> ToolAccess {
+        ToolAccess::Full
+    }
+
+    /// Paper signers never broadcast, what they do does not count towards
+    /// limits or volumes
+    fn is_paper(&self) -> bool {
+        false
+    }
+
+    /// Signer of the savings wallet of the user, created on first use
+    async fn savings_signer(&self) -> Result<Arc<dyn TransactionSigner>> {
+        Err(Error::Signer(
+            "Savings wallets are not supported by this signer".to_string(),
+        )
+        .into())
+    }
+
+    /// Address of the savings wallet of the user, None until it is created
+    async fn savings_wallet(&self) -> Result<Option<String>> {
+        Ok(None)
     }
 
-    #[cfg(feature = "solana")]
     async fn sign_and_send_solana_transaction(
         &self,
-        _tx: &mut solana_sdk::transaction::Transaction,
+        _tx: solana_sdk::transaction::Transaction,
+    ) -> Result<String> {
+        Err(Error::Signer(
+            "Solana transactions not supported by this signer".to_string(),
+        )
+        .into())
+    }
+
+    /// v0 transactions loading accounts from address lookup tables, like
+    /// the multi-hop Jupiter routes. The blockhash is refreshed before
+    /// signing, as for legacy transactions
+    async fn sign_and_send_versioned_solana_transaction(
+        &self,
+        _tx: solana_sdk::transaction::VersionedTransaction,
     ) -> Result<String> {
-        Err(anyhow::anyhow!(
-            "Solana transactions not supported by this signer"
-        ))
+        Err(Error::Signer(
+            "Versioned Solana transactions not supported by this signer"
+                .to_string(),
+        )
+        .into())
     }
 
-    #[cfg(feature = "evm")]
     async fn sign_and_send_evm_transaction(
         &self,
         _tx: alloy::rpc::types::TransactionRequest,
     ) -> Result<String> {
-        Err(anyhow::anyhow!(
-            "EVM transactions not supported by this signer"
-        ))
+        Err(Error::Signer(
+            "EVM transactions not supported by this signer".to_string(),
+        )
+        .into())
     }
 
     async fn sign_and_send_encoded_solana_transaction(
         &self,
         _tx: String,
     ) -> Result<String> {
-        Err(anyhow::anyhow!(
-            "Solana transactions not supported by this signer"
-        ))
+        Err(Error::Signer(
+            "Solana transactions not supported by this signer".to_string(),
+        )
+        .into())
     }
 
     async fn sign_and_send_json_evm_transaction(
         &self,
         _tx: serde_json::Value,
     ) -> Result<String> {
-        Err(anyhow::anyhow!(
-            "EVM transactions not supported by this signer"
-        ))
+        Err(Error::Signer(
+            "EVM transactions not supported by this signer".to_string(),
+        )
+        .into())
     }
 }
 
+/// Serializable reference to the signer of a user, background jobs store it
+/// and get the signer back through a `SignerResolver` when they run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignerRef {
+    pub user_id: Option<String>,
+    pub session_id: Option<String>,
+}
+
+impl SignerRef {
+    pub fn of(signer: &dyn TransactionSigner) -> Self {
+        Self {
+            user_id: signer.user_id(),
+            session_id: signer.session_id(),
+        }
+    }
+
+    /// References are owned by users, sessions come and go
+    pub fn same_user(&self, other: &SignerRef) -> bool {
+        self.user_id == other.user_id
+    }
+}
+
+#[async_trait]
+pub trait SignerResolver: Send + Sync {
+    async fn resolve(
+        &self,
+        signer_ref: &SignerRef,
+    ) -> Result<Arc<dyn TransactionSigner>>;
+}
+
 tokio::task_local! {
     static CURRENT_SIGNER: Arc<dyn TransactionSigner>;
+    static CURRENT_CANCELLATION: CancellationToken;
 }
 
 pub struct SignerContext;
@@ -100,8 +195,26 @@ impl SignerContext {
         CURRENT_SIGNER.scope(signer, f).await
     }
 
+    /// Like `current` but returns None outside of a signer context
+    pub fn try_current() -> Option<Arc<dyn TransactionSigner>> {
+        CURRENT_SIGNER.try_with(|signer| signer.clone()).ok()
+    }
+
     pub async fn current() -> Arc<dyn TransactionSigner> {
-        println!("IN SIGNER");
         CURRENT_SIGNER.get().clone()
     }
+
+    /// Runs `f` with `token` cancelling the tool call made inside of it
+    pub async fn with_cancellation<T>(
+        token: CancellationToken,
+        f: impl Future<Output = Result<T>> + Send,
+    ) -> Result<T> {
+        CURRENT_CANCELLATION.scope(token, f).await
+    }
+
+    /// Cancellation token of the current tool call, None outside of a
+    /// dispatched call
+    pub fn cancellation() -> Option<CancellationToken> {
+        CURRENT_CANCELLATION.try_with(|token| token.clone()).ok()
+    }
 }
@@ -0,0 +1,246 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::Value;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::VersionedTransaction;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use crate::access::ToolAccess;
+use crate::error::Error;
+use crate::preview::BalanceDelta;
+
+use super::TransactionSigner;
+
+/// Signer that builds everything against the wallet of `inner` but never
+/// signs nor broadcasts, the returned signatures are made up. Used to dry-run
+/// tool calls
+pub struct PaperSigner {
+    inner: Arc<dyn TransactionSigner>,
+    simulate: bool,
+    simulations: Mutex<Vec<Value>>,
+    balance_deltas: Mutex<Vec<BalanceDelta>>,
+}
+
+impl PaperSigner {
+    pub fn new(inner: Arc<dyn TransactionSigner>) -> Self {
+        Self {
+            inner,
+            simulate: false,
+            simulations: Mutex::new(vec![]),
+            balance_deltas: Mutex::new(vec![]),
+        }
+    }
+
+    /// Simulates every transaction before making up its signature, failed
+    /// simulations fail the send like the real one would
+    pub fn with_simulation(mut self) -> Self {
+        self.simulate = true;
+        self
+    }
+
+    /// Results of the simulations so far, in order
+    pub fn simulations(&self) -> Vec<Value> {
+        self.simulations.lock().unwrap().clone()
+    }
+
+    /// Balance changes of the wallet over the successful simulations so far
+    pub fn balance_deltas(&self) -> Vec<BalanceDelta> {
+        self.balance_deltas.lock().unwrap().clone()
+    }
+
+    fn add_balance_deltas(&self, deltas: Result<Vec<BalanceDelta>>) {
+        match deltas {
+            Ok(deltas) => self.balance_deltas.lock().unwrap().extend(deltas),
+            Err(e) => tracing::warn!(?e, "balance changes not previewed"),
+        }
+    }
+
+    fn signature(&self) -> String {
+        format!("paper-{:016x}", rand::random::<u64>())
+    }
+
+    async fn simulate_solana(&self, tx: &VersionedTransaction) -> Result<()> {
+        let owner = Pubkey::from_str(&self.pubkey())?;
+        let result = crate::solana::simulate::simulate(tx, &owner).await?;
+        self.balance_deltas
+            .lock()
+            .unwrap()
+            .extend(result.balance_changes);
+        self.simulations.lock().unwrap().push(serde_json::json!({
+            "chain": "sol",
+            "success": result.success,
+            "error": result.error,
+            "units_consumed": result.units_consumed,
+            "logs": result.logs,
+        }));
+        match result.error {
+            Some(error) => Err(Error::Simulation(error).into()),
+            None => Ok(()),
+        }
+    }
+
+    async fn simulate_evm(
+        &self,
+        tx: alloy::rpc::types::TransactionRequest,
+    ) -> Result<()> {
+        use crate::evm::simulate::simulate_transaction;
+        use crate::evm::util::{make_provider, make_provider_for_chain};
+
+        let tx = tx.from(self.address().parse()?);
+        let provider = match tx.chain_id {
+            Some(chain_id) => make_provider_for_chain(chain_id)?,
+            None => make_provider()?,
+        };
+        let result = simulate_transaction(&tx, &provider).await?;
+        if result.success {
+            self.add_balance_deltas(
+                crate::preview::evm_deltas(
+                    tx.chain_id,
+                    self.address().parse()?,
+                    &result.balance_changes,
+                    &provider,
+                )
+                .await,
+            );
+        }
+        self.simulations
+            .lock()
+            .unwrap()
+            .push(serde_json::to_value(&result)?);
+        if !result.success {
+            return Err(Error::Simulation(
+                result.revert_reason.unwrap_or_default(),
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TransactionSigner for PaperSigner {
+    fn address(&self) -> String {
+        self.inner.address()
+    }
+
+    fn pubkey(&self) -> String {
+        self.inner.pubkey()
+    }
+
+    fn user_id(&self) -> Option<String> {
+        self.inner.user_id()
+    }
+
+    fn session_id(&self) -> Option<String> {
+        self.inner.session_id()
+    }
+
+    fn wallet_id(&self) -> Option<String> {
+        self.inner.wallet_id()
+    }
+
+    fn tool_access(&self) -> ToolAccess {
+        self.inner.tool_access()
+    }
+
+    fn is_paper(&self) -> bool {
+        true
+    }
+
+    async fn savings_wallet(&self) -> Result<Option<String>> {
+        self.inner.savings_wallet().await
+    }
+
+    async fn sign_and_send_solana_transaction(
+        &self,
+        tx: solana_sdk::transaction::Transaction,
+    ) -> Result<String> {
+        tracing::info!(
+            instructions = tx.message.instructions.len(),
+            "paper solana transaction"
+        );
+        if self.simulate {
+            self.simulate_solana(&VersionedTransaction::from(tx))
+                .await?;
+        }
+        Ok(self.signature())
+    }
+
+    async fn sign_and_send_versioned_solana_transaction(
+        &self,
+        tx: VersionedTransaction,
+    ) -> Result<String> {
+        tracing::info!(
+            instructions = tx.message.instructions().len(),
+            "paper versioned solana transaction"
+        );
+        if self.simulate {
+            self.simulate_solana(&tx).await?;
+        }
+        Ok(self.signature())
+    }
+
+    async fn sign_and_send_evm_transaction(
+        &self,
+        tx: alloy::rpc::types::TransactionRequest,
+    ) -> Result<String> {
+        tracing::info!(to = ?tx.to, value = ?tx.value, "paper evm transaction");
+        if self.simulate {
+            self.simulate_evm(tx).await?;
+        }
+        Ok(self.signature())
+    }
+
+    async fn sign_and_send_encoded_solana_transaction(
+        &self,
+        tx: String,
+    ) -> Result<String> {
+        tracing::info!(len = tx.len(), "paper encoded solana transaction");
+        if self.simulate {
+            use base64::Engine;
+            let bytes = base64::prelude::BASE64_STANDARD.decode(&tx)?;
+            let tx: solana_sdk::transaction::VersionedTransaction =
+                bincode::deserialize(&bytes)?;
+            self.simulate_solana(&tx).await?;
+        }
+        Ok(self.signature())
+    }
+
+    async fn sign_and_send_json_evm_transaction(
+        &self,
+        tx: serde_json::Value,
+    ) -> Result<String> {
+        tracing::info!(%tx, "paper evm transaction");
+        if self.simulate {
+            self.simulate_evm(serde_json::from_value(tx)?).await?;
+        }
+        Ok(self.signature())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct UserSigner;
+
+    impl TransactionSigner for UserSigner {
+        fn user_id(&self) -> Option<String> {
+            Some("alice".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_paper_signer_never_sends() {
+        let signer = PaperSigner::new(Arc::new(UserSigner));
+        assert!(signer.is_paper());
+        assert_eq!(signer.user_id().as_deref(), Some("alice"));
+
+        let signature = signer
+            .sign_and_send_json_evm_transaction(serde_json::json!({}))
+            .await
+            .unwrap();
+        assert!(signature.starts_with("paper-"));
+    }
+}
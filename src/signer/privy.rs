@@ -1,12 +1,20 @@
 use anyhow::Result;
 use async_trait::async_trait;
 
-#[cfg(feature = "solana")]
+use crate::access::ToolAccess;
+use crate::error::Error;
+use crate::funds;
+use crate::metrics::observe_api;
 use crate::solana::blockhash::BLOCKHASH_CACHE;
+use crate::solana::savings::{SavingsWallet, SAVINGS};
+use crate::wallet_manager::util::transaction_to_base64;
 use crate::wallet_manager::{UserSession, WalletManager};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::VersionedTransaction;
+use std::str::FromStr;
 use std::sync::Arc;
 
-use super::TransactionSigner;
+use super::{SignerRef, SignerResolver, TransactionSigner};
 
 pub struct PrivySigner {
     wallet_manager: Arc<WalletManager>,
@@ -25,6 +33,117 @@ impl PrivySigner {
     }
 }
 
+/// Signs with the savings wallet of the user behind `session`, a Privy
+/// wallet of the app rather than one linked to the user
+pub struct PrivySavingsSigner {
+    wallet_manager: Arc<WalletManager>,
+    session: UserSession,
+    wallet: SavingsWallet,
+}
+
+#[async_trait]
+impl TransactionSigner for PrivySavingsSigner {
+    /// The savings wallet is a Solana one, EVM transactions fail with the
+    /// error of the default `sign_and_send_evm_transaction`
+    fn address(&self) -> String {
+        String::new()
+    }
+
+    fn pubkey(&self) -> String {
+        self.wallet.address.clone()
+    }
+
+    fn session_id(&self) -> Option<String> {
+        Some(self.session.session_id.clone())
+    }
+
+    fn wallet_id(&self) -> Option<String> {
+        Some(self.wallet.address.clone())
+    }
+
+    fn tool_access(&self) -> ToolAccess {
+        self.session.tool_access.clone()
+    }
+
+    fn user_id(&self) -> Option<String> {
+        Some(self.session.user_id.clone())
+    }
+
+    async fn sign_and_send_solana_transaction(
+        &self,
+        mut tx: solana_sdk::transaction::Transaction,
+    ) -> Result<String> {
+        funds::check_solana(&Pubkey::from_str(&self.wallet.address)?, &tx)
+            .await?;
+        tx.message.recent_blockhash = BLOCKHASH_CACHE.get_blockhash().await?;
+        self.sign_and_send_encoded_solana_transaction(transaction_to_base64(
+            &tx,
+        )?)
+        .await
+    }
+
+    async fn sign_and_send_versioned_solana_transaction(
+        &self,
+        mut tx: VersionedTransaction,
+    ) -> Result<String> {
+        let owner = Pubkey::from_str(&self.wallet.address)?;
+        funds::check_versioned_solana(&owner, &tx).await?;
+        tx.message
+            .set_recent_blockhash(BLOCKHASH_CACHE.get_blockhash().await?);
+        self.sign_and_send_encoded_solana_transaction(transaction_to_base64(
+            &tx,
+        )?)
+        .await
+    }
+
+    async fn sign_and_send_encoded_solana_transaction(
+        &self,
+        encoded_transaction: String,
+    ) -> Result<String> {
+        observe_api(
+            "privy",
+            self.wallet_manager.sign_and_send_solana_with_wallet(
+                &self.wallet.wallet_id,
+                self.wallet.address.clone(),
+                encoded_transaction,
+            ),
+        )
+        .await
+    }
+}
+
+/// Resolves stored signer references of Privy users
+pub struct PrivySignerResolver {
+    wallet_manager: Arc<WalletManager>,
+}
+
+impl PrivySignerResolver {
+    pub fn new(wallet_manager: Arc<WalletManager>) -> Self {
+        Self { wallet_manager }
+    }
+}
+
+#[async_trait]
+impl SignerResolver for PrivySignerResolver {
+    async fn resolve(
+        &self,
+        signer_ref: &SignerRef,
+    ) -> Result<Arc<dyn TransactionSigner>> {
+        let user_id = signer_ref.user_id.as_deref().ok_or_else(|| {
+            Error::Unauthorized("Signer reference without a user".into())
+        })?;
+        let session_id = signer_ref.session_id.as_deref().unwrap_or_default();
+        let session = self
+            .wallet_manager
+            .session_for_user(user_id, session_id)
+            .await?;
+        Ok(Arc::new(PrivySigner::new(
+            self.wallet_manager.clone(),
+            session,
+        )))
+    }
+}
+
 #[async_trait]
 impl TransactionSigner for PrivySigner {
     fn address(&self) -> String {
@@ -32,53 +151,136 @@ impl TransactionSigner for PrivySigner {
     }
 
     fn pubkey(&self) -> String {
-        println!("IN PRIVY");
         self.session.pubkey.clone()
     }
 
-    #[cfg(feature = "solana")]
+    fn session_id(&self) -> Option<String> {
+        Some(self.session.session_id.clone())
+    }
+
+    fn wallet_id(&self) -> Option<String> {
+        [&self.session.pubkey, &self.session.wallet_address]
+            .into_iter()
+            .find(|wallet| !wallet.is_empty())
+            .cloned()
+    }
+
+    fn tool_access(&self) -> ToolAccess {
+        self.session.tool_access.clone()
+    }
+
+    fn user_id(&self) -> Option<String> {
+        Some(self.session.user_id.clone())
+    }
+
+    async fn savings_wallet(&self) -> Result<Option<String>> {
+        let wallet = SAVINGS.get(&self.session.user_id).await?;
+        Ok(wallet.map(|wallet| wallet.address))
+    }
+
+    async fn savings_signer(&self) -> Result<Arc<dyn TransactionSigner>> {
+        let user_id = &self.session.user_id;
+        let wallet = match SAVINGS.get(user_id).await? {
+            Some(wallet) => wallet,
+            None => {
+                let created = observe_api("privy", async {
+                    Ok(self.wallet_manager.create_wallet().await?)
+                })
+                .await?;
+                let wallet = SavingsWallet {
+                    wallet_id: created.id,
+                    address: created.address,
+                };
+                // a concurrent first move may have created one too, the
+                // wallet stored first is kept and the other stays empty
+                let kept = SAVINGS.insert(user_id, wallet.clone()).await?;
+                if kept != wallet {
+                    tracing::warn!(
+                        %user_id,
+                        unused = %wallet.address,
+                        "savings wallet created twice"
+                    );
+                }
+                kept
+            }
+        };
+        Ok(Arc::new(PrivySavingsSigner {
+            wallet_manager: self.wallet_manager.clone(),
+            session: self.session.clone(),
+            wallet,
+        }))
+    }
+
     async fn sign_and_send_solana_transaction(
         &self,
-        tx: &mut solana_sdk::transaction::Transaction,
+        mut tx: solana_sdk::transaction::Transaction,
     ) -> Result<String> {
+        funds::check_solana(&Pubkey::from_str(&self.pubkey())?, &tx).await?;
         tx.message.recent_blockhash = BLOCKHASH_CACHE.get_blockhash().await?;
-        let tx_hash = self
-            .wallet_manager
-            .sign_and_send_solana_transaction(self.pubkey(), tx)
-            .await?;
-        Ok(tx_hash)
+        observe_api(
+            "privy",
+            self.wallet_manager
+                .sign_and_send_solana_transaction(self.pubkey(), &tx),
+        )
+        .await
+    }
+
+    /// The funds of v0 transactions aren't checked up front, the accounts
+    /// loaded from the lookup tables aren't known without resolving them
+    async fn sign_and_send_versioned_solana_transaction(
+        &self,
+        mut tx: VersionedTransaction,
+    ) -> Result<String> {
+        funds::check_versioned_solana(
+            &Pubkey::from_str(&self.pubkey())?,
+            &tx,
+        )
+        .await?;
+        tx.message
+            .set_recent_blockhash(BLOCKHASH_CACHE.get_blockhash().await?);
+        self.sign_and_send_encoded_solana_transaction(transaction_to_base64(
+            &tx,
+        )?)
+        .await
     }
 
-    #[cfg(feature = "evm")]
     async fn sign_and_send_evm_transaction(
         &self,
         tx: alloy::rpc::types::TransactionRequest,
     ) -> Result<String> {
-        let tx_hash = self
-            .wallet_manager
-            .sign_and_send_evm_transaction(self.address(), tx)
-            .await?;
-        Ok(tx_hash)
+        funds::check_evm(self.address().parse()?, &tx).await?;
+        observe_api(
+            "privy",
+            self.wallet_manager
+                .sign_and_send_evm_transaction(self.address(), tx),
+        )
+        .await
     }
 
     async fn sign_and_send_encoded_solana_transaction(
         &self,
         encoded_transaction: String,
     ) -> Result<String> {
-        self.wallet_manager
-            .sign_and_send_encoded_solana_transaction(
-                self.pubkey(),
-                encoded_transaction,
-            )
-            .await
+        observe_api(
+            "privy",
+            self.wallet_manager
+                .sign_and_send_encoded_solana_transaction(
+                    self.pubkey(),
+                    encoded_transaction,
+                ),
+        )
+        .await
     }
 
     async fn sign_and_send_json_evm_transaction(
         &self,
         tx: serde_json::Value,
     ) -> Result<String> {
-        self.wallet_manager
-            .sign_and_send_json_evm_transaction(self.address(), tx)
-            .await
+        observe_api(
+            "privy",
+            self.wallet_manager
+                .sign_and_send_json_evm_transaction(self.address(), tx),
+        )
+        .await
     }
 }
@@ -0,0 +1,238 @@
+//! Signers of the live sessions keyed by session id, for frontends driving
+//! the tools of many users from one process like the HTTP server or a
+//! Telegram bot. Every turn runs in its own signer scope and keeps its
+//! signer registered while it runs, the scope is task-local so concurrent
+//! sessions never see each other's signer. Background jobs of a session
+//! resolve to its live signer through `SessionResolver`
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+
+use super::{SignerContext, SignerRef, SignerResolver, TransactionSigner};
+use crate::error::Error;
+
+#[derive(Default)]
+pub struct SignerRegistry {
+    signers: RwLock<HashMap<String, Arc<dyn TransactionSigner>>>,
+}
+
+pub static SIGNERS: Lazy<SignerRegistry> = Lazy::new(SignerRegistry::new);
+
+impl SignerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the signer of the session, returns the one it replaces
+    pub fn register(
+        &self,
+        session_id: &str,
+        signer: Arc<dyn TransactionSigner>,
+    ) -> Option<Arc<dyn TransactionSigner>> {
+        self.signers
+            .write()
+            .unwrap()
+            .insert(session_id.to_string(), signer)
+    }
+
+    pub fn get(
+        &self,
+        session_id: &str,
+    ) -> Option<Arc<dyn TransactionSigner>> {
+        self.signers.read().unwrap().get(session_id).cloned()
+    }
+
+    /// Forgets the session, turns already running keep their signer
+    pub fn remove(
+        &self,
+        session_id: &str,
+    ) -> Option<Arc<dyn TransactionSigner>> {
+        self.signers.write().unwrap().remove(session_id)
+    }
+
+    /// Forgets the session unless a later turn registered another signer
+    fn release(&self, session_id: &str, signer: &Arc<dyn TransactionSigner>) {
+        let mut signers = self.signers.write().unwrap();
+        if signers
+            .get(session_id)
+            .is_some_and(|current| Arc::ptr_eq(current, signer))
+        {
+            signers.remove(session_id);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.signers.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn require(
+        &self,
+        session_id: &str,
+    ) -> Result<Arc<dyn TransactionSigner>> {
+        self.get(session_id).ok_or_else(|| {
+            Error::Signer(format!("No signer for session {}", session_id))
+                .into()
+        })
+    }
+
+    /// Runs the turn `f` under `signer`, which is registered for its
+    /// session until the turn is over
+    pub async fn run<T>(
+        &self,
+        signer: Arc<dyn TransactionSigner>,
+        f: impl Future<Output = Result<T>> + Send,
+    ) -> Result<T> {
+        let Some(session_id) = signer.session_id() else {
+            return SignerContext::with_signer(signer, f).await;
+        };
+        self.register(&session_id, signer.clone());
+        let result = SignerContext::with_signer(signer.clone(), f).await;
+        self.release(&session_id, &signer);
+        result
+    }
+}
+
+/// Background jobs of a session resolve to its registered signer
+#[async_trait]
+impl SignerResolver for SignerRegistry {
+    async fn resolve(
+        &self,
+        signer_ref: &SignerRef,
+    ) -> Result<Arc<dyn TransactionSigner>> {
+        let session_id =
+            signer_ref.session_id.as_deref().ok_or_else(|| {
+                Error::Signer("The signer has no session".to_string())
+            })?;
+        let signer = self.require(session_id)?;
+        if signer.user_id() != signer_ref.user_id {
+            return Err(Error::Signer(format!(
+                "Session {} belongs to another user",
+                session_id
+            ))
+            .into());
+        }
+        Ok(signer)
+    }
+}
+
+/// Resolves to the live signer of the session while one of its turns runs
+/// and through `fallback` once the session is gone
+pub struct SessionResolver<R> {
+    registry: &'static SignerRegistry,
+    fallback: R,
+}
+
+impl<R: SignerResolver> SessionResolver<R> {
+    pub fn new(fallback: R) -> Self {
+        Self {
+            registry: &SIGNERS,
+            fallback,
+        }
+    }
+}
+
+#[async_trait]
+impl<R: SignerResolver> SignerResolver for SessionResolver<R> {
+    async fn resolve(
+        &self,
+        signer_ref: &SignerRef,
+    ) -> Result<Arc<dyn TransactionSigner>> {
+        match self.registry.resolve(signer_ref).await {
+            Ok(signer) => Ok(signer),
+            Err(_) => self.fallback.resolve(signer_ref).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::signer::mock::MockSigner;
+
+    fn signer(user: &str) -> Arc<dyn TransactionSigner> {
+        Arc::new(
+            MockSigner::new(Duration::ZERO)
+                .with_user(user)
+                .with_session(&format!("tg:{}", user)),
+        )
+    }
+
+    /// Stands in for the Privy resolver of sessions that are gone
+    struct Fallback;
+
+    #[async_trait]
+    impl SignerResolver for Fallback {
+        async fn resolve(
+            &self,
+            signer_ref: &SignerRef,
+        ) -> Result<Arc<dyn TransactionSigner>> {
+            Ok(signer(signer_ref.user_id.as_deref().unwrap_or_default()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_sessions() {
+        let registry: &'static SignerRegistry =
+            Box::leak(Box::new(SignerRegistry::new()));
+        let resolver = Arc::new(SessionResolver {
+            registry,
+            fallback: Fallback,
+        });
+
+        let mut turns = vec![];
+        for i in 0..30 {
+            let user = ["alice", "bob", "carol"][i % 3];
+            let resolver = resolver.clone();
+            turns.push(tokio::spawn(registry.run(
+                signer(user),
+                async move {
+                    for _ in 0..5 {
+                        tokio::time::sleep(Duration::from_millis(1)).await;
+                        let current = SignerContext::current().await;
+                        assert_eq!(current.user_id().as_deref(), Some(user));
+                    }
+                    // jobs of the session get the signer of a running turn
+                    let current = SignerContext::current().await;
+                    let resolved = resolver
+                        .resolve(&SignerRef::of(current.as_ref()))
+                        .await?;
+                    Ok((user, resolved.user_id()))
+                },
+            )));
+        }
+        for turn in turns {
+            let (user, resolved) = turn.await.unwrap().unwrap();
+            assert_eq!(resolved.as_deref(), Some(user));
+        }
+        // sessions are forgotten once their turns are over
+        assert!(registry.is_empty());
+
+        let alice = signer("alice");
+        let signer_ref = SignerRef::of(alice.as_ref());
+        registry.register("tg:alice", alice.clone());
+        let resolved = resolver.resolve(&signer_ref).await.unwrap();
+        assert!(Arc::ptr_eq(&resolved, &alice));
+        let stolen = SignerRef {
+            user_id: Some("bob".to_string()),
+            ..signer_ref.clone()
+        };
+        assert!(registry.resolve(&stolen).await.is_err());
+        // another user's reference to the session goes to the fallback
+        let resolved = resolver.resolve(&stolen).await.unwrap();
+        assert_eq!(resolved.user_id().as_deref(), Some("bob"));
+
+        registry.remove("tg:alice");
+        let resolved = resolver.resolve(&signer_ref).await.unwrap();
+        assert!(!Arc::ptr_eq(&resolved, &alice));
+    }
+}
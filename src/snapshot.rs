@@ -0,0 +1,85 @@
+//! Snapshots of the transactions the builders make from fixed inputs, kept
+//! in mocks/snapshots so a change to what gets signed fails the tests with
+//! a readable diff. UPDATE_SNAPSHOTS=1 rewrites them after an intended
+//! change
+use alloy::rpc::types::TransactionRequest;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use solana_sdk::message::Message;
+
+/// Header, keys and instructions of the message, then its serialized bytes
+pub fn render_message(message: &Message) -> String {
+    let header = &message.header;
+    let mut lines = vec![format!(
+        "header {} {} {}",
+        header.num_required_signatures,
+        header.num_readonly_signed_accounts,
+        header.num_readonly_unsigned_accounts
+    )];
+    for (index, key) in message.account_keys.iter().enumerate() {
+        lines.push(format!("key {} {}", index, key));
+    }
+    lines.push(format!("blockhash {}", message.recent_blockhash));
+    for instruction in &message.instructions {
+        lines.push(format!(
+            "instruction {} {:?} {}",
+            instruction.program_id_index,
+            instruction.accounts,
+            hex::encode(&instruction.data)
+        ));
+    }
+    lines.push(format!(
+        "message {}",
+        BASE64_STANDARD.encode(message.serialize())
+    ));
+    lines.join("\n") + "\n"
+}
+
+fn field<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| value.to_string())
+}
+
+/// Fields of the request the signer fills the transaction from
+pub fn render_request(request: &TransactionRequest) -> String {
+    let address = |address: &alloy::primitives::Address| {
+        format!("0x{}", hex::encode(address.as_slice()))
+    };
+    let lines = [
+        format!("from {}", field(request.from.as_ref().map(address))),
+        format!(
+            "to {}",
+            field(request.to.as_ref().and_then(|to| to.to()).map(address))
+        ),
+        format!("value {}", field(request.value)),
+        format!("gas_price {}", field(request.gas_price)),
+        format!("nonce {}", field(request.nonce)),
+        format!("chain_id {}", field(request.chain_id)),
+        format!(
+            "input {}",
+            field(
+                request
+                    .input
+                    .input()
+                    .map(|input| format!("0x{}", hex::encode(input)))
+            )
+        ),
+    ];
+    lines.join("\n") + "\n"
+}
+
+/// Compares `actual` with mocks/snapshots/`name`.snap
+pub fn assert_snapshot(name: &str, actual: &str) {
+    let path = format!("mocks/snapshots/{}.snap", name);
+    if std::env::var("UPDATE_SNAPSHOTS").is_ok() {
+        std::fs::write(&path, actual).expect("Failed to write snapshot");
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!("No snapshot {}, run with UPDATE_SNAPSHOTS=1", path)
+    });
+    assert_eq!(
+        expected, actual,
+        "{} changed, run with UPDATE_SNAPSHOTS=1 if it is intended",
+        path
+    );
+}
@@ -2,34 +2,57 @@ use anyhow::Result;
 use rig::agent::Agent;
 use rig::providers::anthropic::completion::CompletionModel as AnthropicCompletionModel;
 
+use super::anchor::CallAnchorProgram;
+use super::congestion::GetNetworkCongestion;
+use super::faucet::{MintTestToken, RequestAirdrop};
+use super::launches::{
+    ListPumpLaunchWatches, StopWatchingPumpLaunches, WatchPumpLaunches,
+};
+use super::priority_fees::SetPriorityFeeStrategy;
+use super::rent::GetRentExemptMinimum;
+use super::savings::{MoveToSavings, WithdrawFromSavings};
 use super::tools::{
     BuyPumpFunToken, DeployPumpFunToken, FetchTokenPrice, GetPortfolio,
-    GetPublicKey, GetSolBalance, GetSplTokenBalance, PerformJupiterSwap,
+    GetPublicKey, GetSolBalance, GetSplTokenBalance, QuoteJupiterSwap,
     SellPumpFunToken, TransferSol, TransferSplToken,
 };
-use crate::common::{claude_agent_builder, PREAMBLE_COMMON};
+use crate::agent::{AgentBuilder, AgentKind};
+use crate::common::{register_common_tools, ToolRegistry};
 use crate::dexscreener::tools::SearchOnDexScreener;
+use crate::quotes::ExecuteQuote;
+
+pub fn register_solana_tools<R: ToolRegistry>(registry: R) -> R {
+    register_common_tools(
+        registry
+            .tool(QuoteJupiterSwap)
+            .tool(ExecuteQuote)
+            .tool(TransferSol)
+            .tool(TransferSplToken)
+            .tool(GetPublicKey)
+            .tool(GetSolBalance)
+            .tool(GetSplTokenBalance)
+            .tool(FetchTokenPrice)
+            .tool(GetNetworkCongestion)
+            .tool(SetPriorityFeeStrategy)
+            .tool(GetRentExemptMinimum)
+            .tool(GetPortfolio)
+            .tool(SearchOnDexScreener)
+            .tool(DeployPumpFunToken)
+            .tool(BuyPumpFunToken)
+            .tool(SellPumpFunToken)
+            .tool(WatchPumpLaunches)
+            .tool(ListPumpLaunchWatches)
+            .tool(StopWatchingPumpLaunches)
+            .tool(RequestAirdrop)
+            .tool(MintTestToken)
+            .tool(CallAnchorProgram)
+            .tool(MoveToSavings)
+            .tool(WithdrawFromSavings),
+    )
+}
 
+/// See `AgentBuilder` for an agent with other tools or another model
 pub async fn create_solana_agent() -> Result<Agent<AnthropicCompletionModel>>
 {
-    Ok(claude_agent_builder()
-        .preamble(&format!(
-            "{} {}",
-            "you are a solana trading agent that can also interact with pump.fun;", 
-            PREAMBLE_COMMON
-        ))
-        .max_tokens(1024)
-        .tool(PerformJupiterSwap)
-        .tool(TransferSol)
-        .tool(TransferSplToken)
-        .tool(GetPublicKey)
-        .tool(GetSolBalance)
-        .tool(GetSplTokenBalance)
-        .tool(FetchTokenPrice)
-        .tool(GetPortfolio)
-        .tool(SearchOnDexScreener)
-        .tool(DeployPumpFunToken)
-        .tool(BuyPumpFunToken)
-        .tool(SellPumpFunToken)
-        .build())
+    AgentBuilder::new(AgentKind::Solana).build()
 }
@@ -0,0 +1,754 @@
+//! Calls to any Anchor program from its IDL, so new Solana protocols can be
+//! used before a dedicated integration exists. The IDL is read from the
+//! chain (the account Anchor publishes it to), an URL of IDL_HOSTS or given
+//! inline, both the legacy and the 0.30 formats work. Arguments are borsh
+//! encoded from JSON following the IDL types, accounts are passed by name
+//! with the wallet, fixed addresses and the usual programs filled in. What
+//! the simulated call takes out of the wallet is held to the risk caps
+use std::io::Read;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use flate2::read::ZlibDecoder;
+use rig_tool_macro::tool;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::{Transaction, VersionedTransaction};
+
+use super::blockhash::BLOCKHASH_CACHE;
+use super::simulate::spent;
+use super::util::execute_solana_transaction;
+use crate::common::{http_client, solana_rpc, with_explorer_link};
+use crate::confirmation::confirm_or_execute;
+use crate::error::Error;
+use crate::risk::{assess, TradeIntent};
+use crate::signer::SignerContext;
+use crate::tool_error::ToolError;
+
+const IDL_SEED: &str = "anchor:idl";
+/// discriminator, authority and length of the IDL account
+const IDL_HEADER_LEN: usize = 8 + 32 + 4;
+/// Hosts IDLs are downloaded from, the server never fetches other URLs
+const IDL_HOSTS: &[&str] =
+    &["raw.githubusercontent.com", "gist.githubusercontent.com"];
+/// Largest IDL downloaded
+const MAX_IDL_BYTES: usize = 1 << 20;
+
+/// Anchor IDL, kept as JSON to read both formats
+#[derive(Debug, Clone)]
+pub struct Idl(Value);
+
+/// Names compare without underscores and case, legacy IDLs are camelCase
+/// and 0.30 ones snake_case
+fn normalize(name: &str) -> String {
+    name.replace('_', "").to_lowercase()
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for c in name.chars() {
+        if c.is_uppercase() {
+            if !snake.is_empty() {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+/// Value of `object` under `name`, compared like the IDL names
+fn lookup<'a>(object: &'a Value, name: &str) -> Option<&'a Value> {
+    object
+        .as_object()?
+        .iter()
+        .find(|(key, _)| normalize(key) == normalize(name))
+        .map(|(_, value)| value)
+}
+
+fn invalid_arg(name: &str, expected: &str, value: &Value) -> anyhow::Error {
+    ToolError::invalid_input(format!(
+        "{} has to be {}, got {}",
+        name, expected, value
+    ))
+    .into()
+}
+
+fn integer<T>(name: &str, value: &Value) -> Result<T>
+where
+    T: TryFrom<i128> + FromStr,
+{
+    let integer = match value {
+        // amounts above 2^53 are passed as strings
+        Value::String(s) => s.trim().parse().ok(),
+        Value::Number(n) => n
+            .as_i64()
+            .map(i128::from)
+            .or_else(|| n.as_u64().map(i128::from))
+            .and_then(|n| T::try_from(n).ok()),
+        _ => None,
+    };
+    integer.ok_or_else(|| invalid_arg(name, "an integer in range", value))
+}
+
+impl Idl {
+    pub fn parse(json: &str) -> Result<Self> {
+        let idl: Value = serde_json::from_str(json)
+            .map_err(|e| anyhow!("Invalid IDL: {}", e))?;
+        if !idl["instructions"].is_array() {
+            return Err(anyhow!("Invalid IDL: no instructions"));
+        }
+        Ok(Self(idl))
+    }
+
+    pub fn instruction(&self, name: &str) -> Result<&Value> {
+        let instructions = self.0["instructions"].as_array();
+        instructions
+            .into_iter()
+            .flatten()
+            .find(|ix| {
+                ix["name"].as_str().map(normalize) == Some(normalize(name))
+            })
+            .ok_or_else(|| {
+                let names = instructions
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|ix| ix["name"].as_str())
+                    .collect::<Vec<_>>();
+                ToolError::not_found(format!("No instruction {}", name))
+                    .with_hint(format!("The IDL has {}", names.join(", ")))
+                    .into()
+            })
+    }
+
+    fn type_def(&self, name: &str) -> Result<&Value> {
+        // legacy IDLs keep the account types apart
+        ["types", "accounts"]
+            .iter()
+            .flat_map(|key| self.0[key].as_array().into_iter().flatten())
+            .find(|def| def["name"] == name && def["type"].is_object())
+            .ok_or_else(|| anyhow!("IDL type {} not found", name))
+    }
+
+    /// Borsh encodes `value` as `ty`
+    fn encode(
+        &self,
+        name: &str,
+        ty: &Value,
+        value: &Value,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        if let Some(primitive) = ty.as_str() {
+            return encode_primitive(name, primitive, value, out);
+        }
+        if let Some(inner) = ty.get("vec") {
+            let items = value
+                .as_array()
+                .ok_or_else(|| invalid_arg(name, "an array", value))?;
+            out.extend((items.len() as u32).to_le_bytes());
+            for item in items {
+                self.encode(name, inner, item, out)?;
+            }
+            return Ok(());
+        }
+        if let Some(inner) = ty.get("option") {
+            if value.is_null() {
+                out.push(0);
+            } else {
+                out.push(1);
+                self.encode(name, inner, value, out)?;
+            }
+            return Ok(());
+        }
+        if let Some([inner, len]) =
+            ty.get("array").and_then(Value::as_array).map(Vec::as_slice)
+        {
+            let items = value
+                .as_array()
+                .filter(|items| Some(items.len() as u64) == len.as_u64())
+                .ok_or_else(|| {
+                    invalid_arg(name, &format!("an array of {}", len), value)
+                })?;
+            for item in items {
+                self.encode(name, inner, item, out)?;
+            }
+            return Ok(());
+        }
+        if let Some(defined) = ty.get("defined") {
+            let type_name = defined
+                .as_str()
+                .or_else(|| defined["name"].as_str())
+                .ok_or_else(|| anyhow!("Invalid IDL type {}", ty))?;
+            return self.encode_defined(name, type_name, value, out);
+        }
+        Err(anyhow!("Unsupported IDL type {}", ty))
+    }
+
+    fn encode_defined(
+        &self,
+        name: &str,
+        type_name: &str,
+        value: &Value,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        let ty = &self.type_def(type_name)?["type"];
+        match ty["kind"].as_str() {
+            Some("struct") => {
+                self.encode_fields(name, &ty["fields"], value, out)
+            }
+            Some("enum") => {
+                // unit variants by name, the others as {"Variant": fields}
+                let (variant, fields) = match value {
+                    Value::String(variant) => {
+                        (variant.as_str(), &Value::Null)
+                    }
+                    Value::Object(map) if map.len() == 1 => {
+                        let (variant, fields) = map.iter().next().unwrap();
+                        (variant.as_str(), fields)
+                    }
+                    _ => return Err(invalid_arg(name, type_name, value)),
+                };
+                let variants = ty["variants"].as_array();
+                let index = variants
+                    .into_iter()
+                    .flatten()
+                    .position(|v| {
+                        v["name"].as_str().map(normalize)
+                            == Some(normalize(variant))
+                    })
+                    .ok_or_else(|| {
+                        invalid_arg(name, &format!("a {}", type_name), value)
+                    })?;
+                out.push(index as u8);
+                match variants.and_then(|v| v[index].get("fields")) {
+                    Some(def) => self.encode_fields(name, def, fields, out),
+                    None => Ok(()),
+                }
+            }
+            _ => Err(anyhow!("Unsupported IDL type {}", type_name)),
+        }
+    }
+
+    fn encode_fields(
+        &self,
+        name: &str,
+        fields: &Value,
+        value: &Value,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        for (i, field) in fields.as_array().into_iter().flatten().enumerate()
+        {
+            match field.get("name").and_then(Value::as_str) {
+                Some(field_name) => {
+                    let path = format!("{}.{}", name, field_name);
+                    let value =
+                        lookup(value, field_name).ok_or_else(|| {
+                            ToolError::invalid_input(format!(
+                                "{} is missing",
+                                path
+                            ))
+                        })?;
+                    self.encode(&path, &field["type"], value, out)?;
+                }
+                // tuple fields are bare types
+                None => {
+                    let path = format!("{}.{}", name, i);
+                    self.encode(&path, field, &value[i], out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Discriminator and borsh encoded arguments of the instruction
+    pub fn encode_instruction(
+        &self,
+        instruction: &str,
+        args: &Value,
+    ) -> Result<Vec<u8>> {
+        let ix = self.instruction(instruction)?;
+        let mut data = match ix["discriminator"].as_array() {
+            Some(bytes) => bytes
+                .iter()
+                .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(|| anyhow!("Invalid IDL discriminator"))?,
+            None => {
+                let name =
+                    snake_case(ix["name"].as_str().unwrap_or_default());
+                let hash = Sha256::digest(format!("global:{}", name));
+                hash[..8].to_vec()
+            }
+        };
+        for arg in ix["args"].as_array().into_iter().flatten() {
+            let name = arg["name"].as_str().unwrap_or_default();
+            let value = lookup(args, name).ok_or_else(|| {
+                ToolError::invalid_input(format!(
+                    "Argument {} is missing",
+                    name
+                ))
+            })?;
+            self.encode(name, &arg["type"], value, &mut data)?;
+        }
+        Ok(data)
+    }
+
+    /// Metas of the accounts of the instruction, the ones not in `accounts`
+    /// are the fixed addresses of the IDL, the wallet for signers and the
+    /// well known programs
+    pub fn account_metas(
+        &self,
+        instruction: &str,
+        accounts: &Value,
+        program_id: &Pubkey,
+        wallet: &Pubkey,
+    ) -> Result<Vec<AccountMeta>> {
+        let mut leaves = vec![];
+        flatten_accounts(
+            &self.instruction(instruction)?["accounts"],
+            &mut leaves,
+        );
+
+        let mut metas = vec![];
+        let mut missing = vec![];
+        for account in leaves {
+            let name = account["name"].as_str().unwrap_or_default();
+            let flag = |new: &str, legacy: &str| {
+                account[new]
+                    .as_bool()
+                    .or_else(|| account[legacy].as_bool())
+                    .unwrap_or(false)
+            };
+            let (writable, signer) =
+                (flag("writable", "isMut"), flag("signer", "isSigner"));
+            let given = match lookup(accounts, name) {
+                Some(value) => Some(
+                    value
+                        .as_str()
+                        .and_then(|a| Pubkey::from_str(a).ok())
+                        .ok_or_else(|| {
+                            invalid_arg(name, "a public key", value)
+                        })?,
+                ),
+                None => account["address"]
+                    .as_str()
+                    .and_then(|a| Pubkey::from_str(a).ok()),
+            };
+            let pubkey = match given {
+                Some(pubkey) => pubkey,
+                None if signer => *wallet,
+                None => match well_known_account(name) {
+                    Some(pubkey) => pubkey,
+                    // Anchor reads the program id as a missing optional
+                    None if flag("optional", "isOptional") => {
+                        metas.push(AccountMeta::new_readonly(
+                            *program_id,
+                            false,
+                        ));
+                        continue;
+                    }
+                    None => {
+                        missing.push(name.to_string());
+                        continue;
+                    }
+                },
+            };
+            metas.push(match writable {
+                true => AccountMeta::new(pubkey, signer),
+                false => AccountMeta::new_readonly(pubkey, signer),
+            });
+        }
+        if !missing.is_empty() {
+            return Err(ToolError::invalid_input(format!(
+                "Missing accounts {}",
+                missing.join(", ")
+            ))
+            .with_hint("Pass them by name in accounts, e.g. {\"pool\": \"<address>\"}")
+            .into());
+        }
+        Ok(metas)
+    }
+}
+
+fn encode_primitive(
+    name: &str,
+    primitive: &str,
+    value: &Value,
+    out: &mut Vec<u8>,
+) -> Result<()> {
+    match primitive {
+        "bool" => out.push(
+            value
+                .as_bool()
+                .ok_or_else(|| invalid_arg(name, "a bool", value))?
+                as u8,
+        ),
+        "u8" => out.extend(integer::<u8>(name, value)?.to_le_bytes()),
+        "i8" => out.extend(integer::<i8>(name, value)?.to_le_bytes()),
+        "u16" => out.extend(integer::<u16>(name, value)?.to_le_bytes()),
+        "i16" => out.extend(integer::<i16>(name, value)?.to_le_bytes()),
+        "u32" => out.extend(integer::<u32>(name, value)?.to_le_bytes()),
+        "i32" => out.extend(integer::<i32>(name, value)?.to_le_bytes()),
+        "u64" => out.extend(integer::<u64>(name, value)?.to_le_bytes()),
+        "i64" => out.extend(integer::<i64>(name, value)?.to_le_bytes()),
+        "u128" => out.extend(integer::<u128>(name, value)?.to_le_bytes()),
+        "i128" => out.extend(integer::<i128>(name, value)?.to_le_bytes()),
+        "f32" | "f64" => {
+            let float = value
+                .as_f64()
+                .ok_or_else(|| invalid_arg(name, "a number", value))?;
+            match primitive {
+                "f32" => out.extend((float as f32).to_le_bytes()),
+                _ => out.extend(float.to_le_bytes()),
+            }
+        }
+        "string" => {
+            let s = value
+                .as_str()
+                .ok_or_else(|| invalid_arg(name, "a string", value))?;
+            out.extend((s.len() as u32).to_le_bytes());
+            out.extend(s.as_bytes());
+        }
+        "publicKey" | "pubkey" => {
+            let pubkey = value
+                .as_str()
+                .and_then(|s| Pubkey::from_str(s).ok())
+                .ok_or_else(|| invalid_arg(name, "a public key", value))?;
+            out.extend(pubkey.to_bytes());
+        }
+        "bytes" => {
+            let bytes = value
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+                .collect::<Option<Vec<u8>>>()
+                .filter(|_| value.is_array())
+                .ok_or_else(|| {
+                    invalid_arg(name, "an array of bytes", value)
+                })?;
+            out.extend((bytes.len() as u32).to_le_bytes());
+            out.extend(bytes);
+        }
+        other => return Err(anyhow!("Unsupported IDL type {}", other)),
+    }
+    Ok(())
+}
+
+/// Accounts of an instruction with the composite ones expanded, in order
+fn flatten_accounts<'a>(accounts: &'a Value, out: &mut Vec<&'a Value>) {
+    for account in accounts.as_array().into_iter().flatten() {
+        match account.get("accounts") {
+            Some(nested) => flatten_accounts(nested, out),
+            None => out.push(account),
+        }
+    }
+}
+
+fn well_known_account(name: &str) -> Option<Pubkey> {
+    match normalize(name).as_str() {
+        "systemprogram" => Some(solana_sdk::system_program::id()),
+        "tokenprogram" => Some(spl_token::id()),
+        "associatedtokenprogram" => Some(spl_associated_token_account::id()),
+        "rent" => Some(solana_sdk::sysvar::rent::id()),
+        "clock" => Some(solana_sdk::sysvar::clock::id()),
+        _ => None,
+    }
+}
+
+/// IDL JSON of the account Anchor publishes it to, zlib compressed after
+/// the header
+fn decode_idl_account(data: &[u8]) -> Result<String> {
+    let len = data
+        .get(IDL_HEADER_LEN - 4..IDL_HEADER_LEN)
+        .map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize)
+        .ok_or_else(|| anyhow!("Invalid IDL account"))?;
+    let compressed = data
+        .get(IDL_HEADER_LEN..IDL_HEADER_LEN + len)
+        .ok_or_else(|| anyhow!("Invalid IDL account"))?;
+    let mut json = String::new();
+    ZlibDecoder::new(compressed).read_to_string(&mut json)?;
+    Ok(json)
+}
+
+/// IDL JSON at `url`, an https URL of IDL_HOSTS of up to MAX_IDL_BYTES
+async fn download_idl(url: &str) -> Result<String> {
+    let parsed = reqwest::Url::parse(url).map_err(|_| {
+        ToolError::invalid_input(format!("Invalid IDL URL {}", url))
+    })?;
+    let host = parsed.host_str().unwrap_or_default();
+    if parsed.scheme() != "https" || !IDL_HOSTS.contains(&host) {
+        return Err(ToolError::invalid_input(format!(
+            "IDLs are only downloaded from {}",
+            IDL_HOSTS.join(", ")
+        ))
+        .with_hint("Use the IDL on chain or pass the IDL JSON itself")
+        .into());
+    }
+    let too_large = || {
+        ToolError::invalid_input(format!(
+            "The IDL is larger than {} bytes",
+            MAX_IDL_BYTES
+        ))
+    };
+    let mut response =
+        http_client().get(parsed).send().await?.error_for_status()?;
+    if response
+        .content_length()
+        .is_some_and(|len| len > MAX_IDL_BYTES as u64)
+    {
+        return Err(too_large().into());
+    }
+    let mut body = vec![];
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > MAX_IDL_BYTES {
+            return Err(too_large().into());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(String::from_utf8(body)?)
+}
+
+/// IDL of the program from `source`: "onchain" (or empty) for the account
+/// Anchor publishes it to, an URL of IDL_HOSTS, or the IDL JSON itself
+pub async fn fetch_idl(program_id: &Pubkey, source: &str) -> Result<Idl> {
+    let source = source.trim();
+    let json = match source {
+        "" | "onchain" => {
+            let (base, _) = Pubkey::find_program_address(&[], program_id);
+            let address =
+                Pubkey::create_with_seed(&base, IDL_SEED, program_id)?;
+            let data = solana_rpc()
+                .get_account_data(&address)
+                .await
+                .map_err(|_| {
+                    ToolError::not_found(format!(
+                        "Program {} has no IDL on chain",
+                        program_id
+                    ))
+                    .with_hint("Pass the IDL JSON or its URL as idl_source")
+                })?;
+            decode_idl_account(&data)?
+        }
+        url if url.starts_with("https://") || url.starts_with("http://") => {
+            download_idl(url).await?
+        }
+        json => json.to_string(),
+    };
+    Idl::parse(&json)
+}
+
+fn json_object(name: &str, json: &str) -> Result<Value> {
+    if json.trim().is_empty() {
+        return Ok(Value::Object(Map::new()));
+    }
+    serde_json::from_str::<Value>(json)
+        .ok()
+        .filter(Value::is_object)
+        .ok_or_else(|| {
+            ToolError::invalid_input(format!(
+                "{} has to be a JSON object",
+                name
+            ))
+            .into()
+        })
+}
+
+#[tool(description = "
+Calls an instruction of any Anchor program on Solana from its IDL, for
+protocols without a dedicated tool. This requires the user's confirmation.
+
+program_id is the address of the program
+idl_source is \"onchain\" (or empty) to read the IDL the program published,
+an https URL of the IDL JSON on raw.githubusercontent.com or
+gist.githubusercontent.com, or the IDL JSON itself
+instruction is the name of the instruction in the IDL
+args is a JSON object of the instruction arguments by name, structs are
+objects, enums are \"Variant\" or {\"Variant\": {..}}, options are null or the
+value, integers above 2^53 are strings
+accounts is a JSON object of the account addresses by name, signers default
+to the wallet, the system, token and associated token programs, rent and
+clock default to theirs
+")]
+pub async fn call_anchor_program(
+    program_id: String,
+    idl_source: String,
+    instruction: String,
+    args: String,
+    accounts: String,
+) -> Result<String> {
+    let program = Pubkey::from_str(program_id.trim()).map_err(|_| {
+        Error::UserInput(format!("Invalid program id {}", program_id))
+    })?;
+    let args = json_object("args", &args)?;
+    let accounts = json_object("accounts", &accounts)?;
+    let wallet = Pubkey::from_str(&SignerContext::current().await.pubkey())?;
+
+    let idl = fetch_idl(&program, &idl_source).await?;
+    let ix = Instruction {
+        program_id: program,
+        accounts: idl.account_metas(
+            &instruction,
+            &accounts,
+            &program,
+            &wallet,
+        )?,
+        data: idl.encode_instruction(&instruction, &args)?,
+    };
+    let writable = ix
+        .accounts
+        .iter()
+        .filter(|meta| meta.is_writable)
+        .map(|meta| meta.pubkey.to_string())
+        .collect::<Vec<_>>();
+    // the call may move anything, what its simulation takes out of the
+    // wallet counts towards the caps like a trade would
+    let mut tx = Transaction::new_with_payer(&[ix.clone()], Some(&wallet));
+    tx.message.recent_blockhash = BLOCKHASH_CACHE.get_blockhash().await?;
+    let intent = spent(&VersionedTransaction::from(tx), &wallet)
+        .await?
+        .into_iter()
+        .fold(TradeIntent::new("sol"), |intent, (token, amount)| {
+            intent.spend(&token, &amount.to_string())
+        });
+    let assessment = assess(intent).await?;
+    let summary = assessment.annotate(format!(
+        "Call {} of program {} with {} (writes {})",
+        instruction,
+        program,
+        args,
+        writable.join(", ")
+    ));
+    confirm_or_execute("call_anchor_program", summary, move || async move {
+        let signature = execute_solana_transaction(move |owner| async move {
+            let mut tx = Transaction::new_with_payer(&[ix], Some(&owner));
+            tx.message.recent_blockhash =
+                BLOCKHASH_CACHE.get_blockhash().await?;
+            Ok(tx)
+        })
+        .await?;
+        assessment.record().await;
+        Ok(with_explorer_link("sol", &signature))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_idl_hosts() {
+        for url in [
+            "http://raw.githubusercontent.com/org/repo/main/idl.json",
+            "https://169.254.169.254/latest/meta-data",
+            "https://raw.githubusercontent.com.example.com/idl.json",
+        ] {
+            let error = download_idl(url).await.unwrap_err();
+            assert!(error.to_string().contains("only downloaded"), "{}", url);
+        }
+    }
+
+    fn idl() -> Idl {
+        Idl(json!({
+            "version": "0.1.0",
+            "name": "vault",
+            "instructions": [{
+                "name": "depositFor",
+                "accounts": [
+                    {"name": "vault", "isMut": true, "isSigner": false},
+                    {"name": "user", "isMut": true, "isSigner": true},
+                    {"name": "common", "accounts": [
+                        {"name": "systemProgram", "isMut": false, "isSigner": false},
+                        {"name": "referrer", "isMut": false, "isSigner": false, "isOptional": true},
+                    ]},
+                ],
+                "args": [
+                    {"name": "amount", "type": "u64"},
+                    {"name": "beneficiary", "type": {"option": "publicKey"}},
+                    {"name": "params", "type": {"defined": "Params"}},
+                    {"name": "mode", "type": {"defined": "Mode"}},
+                ],
+            }],
+            "types": [
+                {"name": "Params", "type": {"kind": "struct", "fields": [
+                    {"name": "memo", "type": "string"},
+                    {"name": "splits", "type": {"vec": "u16"}},
+                ]}},
+                {"name": "Mode", "type": {"kind": "enum", "variants": [
+                    {"name": "Instant"},
+                    {"name": "Locked", "fields": [{"name": "days", "type": "u8"}]},
+                ]}},
+            ],
+        }))
+    }
+
+    #[test]
+    fn test_encode_instruction() {
+        let idl = idl();
+        let data = idl
+            .encode_instruction(
+                "deposit_for",
+                &json!({
+                    "amount": "18446744073709551615",
+                    "beneficiary": null,
+                    "params": {"memo": "hi", "splits": [1, 2]},
+                    "mode": {"Locked": {"days": 7}},
+                }),
+            )
+            .unwrap();
+        let discriminator = Sha256::digest("global:deposit_for");
+        assert_eq!(data[..8], discriminator[..8]);
+        let mut expected = u64::MAX.to_le_bytes().to_vec();
+        expected.push(0);
+        expected.extend([2, 0, 0, 0, b'h', b'i']);
+        expected.extend([2, 0, 0, 0, 1, 0, 2, 0]);
+        expected.extend([1, 7]);
+        assert_eq!(data[8..], expected);
+
+        let err = idl
+            .encode_instruction("depositFor", &json!({"amount": -1}))
+            .unwrap_err();
+        assert!(err.to_string().contains("amount"));
+        assert!(idl.encode_instruction("withdraw", &json!({})).is_err());
+    }
+
+    #[test]
+    fn test_account_metas() {
+        let idl = idl();
+        let (program, wallet, vault) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let metas = idl
+            .account_metas(
+                "depositFor",
+                &json!({"vault": vault.to_string()}),
+                &program,
+                &wallet,
+            )
+            .unwrap();
+        assert_eq!(
+            metas,
+            vec![
+                AccountMeta::new(vault, false),
+                AccountMeta::new(wallet, true),
+                AccountMeta::new_readonly(
+                    solana_sdk::system_program::id(),
+                    false
+                ),
+                AccountMeta::new_readonly(program, false),
+            ]
+        );
+        let err = idl
+            .account_metas("depositFor", &json!({}), &program, &wallet)
+            .unwrap_err();
+        assert!(err.to_string().contains("vault"));
+    }
+}
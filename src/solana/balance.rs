@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
 use anyhow::{anyhow, Result};
@@ -5,9 +6,20 @@ use serde::Serialize;
 use solana_account_decoder::parse_account_data::ParsedAccount;
 use solana_account_decoder::UiAccountData;
 use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcTransactionConfig;
 use solana_client::rpc_request::TokenAccountsFilter;
 use solana_client::rpc_response::RpcKeyedAccount;
+use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::{
+    UiLoadedAddresses, UiTransactionEncoding, UiTransactionTokenBalance,
+};
+
+use super::constants::WSOL;
+use crate::common::solana_rpc;
+use crate::error::Error;
 
 #[derive(Debug, Default, Clone, Serialize)]
 pub struct Holding {
@@ -58,3 +70,256 @@ pub async fn get_holdings(
 
     Ok(holdings)
 }
+
+/// Balance of `mint` held by `owner` together with the decimals of the mint,
+/// the native balance for wrapped SOL. Owners without a token account hold 0
+pub async fn token_balance(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    mint: &Pubkey,
+) -> Result<(u64, u8)> {
+    if mint.to_string() == WSOL {
+        return Ok((rpc_client.get_balance(owner).await?, 9));
+    }
+    let ata = spl_associated_token_account::get_associated_token_address(
+        owner, mint,
+    );
+    match rpc_client.get_token_account_balance(&ata).await {
+        Ok(balance) => Ok((balance.amount.parse()?, balance.decimals)),
+        Err(_) => {
+            let supply = rpc_client.get_token_supply(mint).await?;
+            Ok((0, supply.decimals))
+        }
+    }
+}
+
+/// SPL token balance of an account of a transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenBalance {
+    pub owner: String,
+    pub mint: String,
+    pub amount: u64,
+    pub decimals: u8,
+}
+
+impl TokenBalance {
+    fn from_ui(
+        balances: OptionSerializer<Vec<UiTransactionTokenBalance>>,
+    ) -> Vec<Self> {
+        Option::<Vec<_>>::from(balances)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|balance| {
+                Some(Self {
+                    owner: Option::<String>::from(balance.owner)?,
+                    mint: balance.mint,
+                    amount: balance.ui_token_amount.amount.parse().ok()?,
+                    decimals: balance.ui_token_amount.decimals,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Net change of the balance of a mint held by an address, in base units
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalanceChange {
+    /// wrapped SOL for SOL
+    pub mint: String,
+    pub amount: i128,
+    pub decimals: u8,
+}
+
+/// Balances of the accounts of a Solana transaction before and after it
+#[derive(Debug, Clone, Default)]
+pub struct TransactionBalances {
+    pub keys: Vec<String>,
+    /// the first `signers` keys signed, the first one paid `fee`
+    pub signers: usize,
+    pub fee: u64,
+    pub failed: bool,
+    pub block_time: Option<i64>,
+    pub pre_lamports: Vec<u64>,
+    pub post_lamports: Vec<u64>,
+    pub pre_tokens: Vec<TokenBalance>,
+    pub post_tokens: Vec<TokenBalance>,
+}
+
+impl TransactionBalances {
+    pub fn signed_by(&self, address: &str) -> bool {
+        self.keys[..self.signers.min(self.keys.len())]
+            .iter()
+            .any(|key| key == address)
+    }
+
+    pub fn paid_fee(&self, address: &str) -> bool {
+        self.keys.first().is_some_and(|payer| payer == address)
+    }
+
+    /// How the balances of `address` changed, SOL first and without the
+    /// fee when it paid it
+    pub fn changes(&self, address: &str) -> Vec<BalanceChange> {
+        let mut changes = vec![];
+        if let Some(index) = self.keys.iter().position(|key| key == address) {
+            let pre = self.pre_lamports.get(index).copied().unwrap_or(0);
+            let post = self.post_lamports.get(index).copied().unwrap_or(0);
+            let mut amount = post as i128 - pre as i128;
+            if index == 0 {
+                amount += self.fee as i128;
+            }
+            if amount != 0 {
+                changes.push(BalanceChange {
+                    mint: WSOL.to_string(),
+                    amount,
+                    decimals: 9,
+                });
+            }
+        }
+        // the address can hold several accounts of a mint
+        let mut deltas = BTreeMap::<&str, (i128, u8)>::new();
+        for (balances, sign) in
+            [(&self.post_tokens, 1), (&self.pre_tokens, -1)]
+        {
+            for balance in balances.iter().filter(|b| b.owner == address) {
+                let delta = deltas
+                    .entry(&balance.mint)
+                    .or_insert((0, balance.decimals));
+                delta.0 += sign * balance.amount as i128;
+            }
+        }
+        changes.extend(
+            deltas
+                .into_iter()
+                .filter(|(_, (amount, _))| *amount != 0)
+                .map(|(mint, (amount, decimals))| BalanceChange {
+                    mint: mint.to_string(),
+                    amount,
+                    decimals,
+                }),
+        );
+        changes
+    }
+
+    /// Raw amounts `address` received by mint. Empty when it signed,
+    /// whatever it got back was its own
+    pub fn received(&self, address: &str) -> Vec<(String, u64)> {
+        if self.signed_by(address) {
+            return vec![];
+        }
+        self.changes(address)
+            .into_iter()
+            .filter(|change| change.amount > 0)
+            .map(|change| (change.mint, change.amount as u64))
+            .collect()
+    }
+}
+
+/// Balances of a confirmed transaction, failed ones included
+pub async fn transaction_balances(
+    signature: &Signature,
+) -> Result<TransactionBalances> {
+    let config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: Some(CommitmentConfig::confirmed()),
+        max_supported_transaction_version: Some(0),
+    };
+    let confirmed = solana_rpc()
+        .get_transaction_with_config(signature, config)
+        .await
+        .map_err(Error::from)?;
+    let tx = confirmed.transaction.transaction.decode().ok_or_else(|| {
+        anyhow!("Failed to decode transaction {}", signature)
+    })?;
+    let meta = confirmed
+        .transaction
+        .meta
+        .ok_or_else(|| anyhow!("No status of transaction {}", signature))?;
+
+    let mut keys = tx
+        .message
+        .static_account_keys()
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    if let Some(loaded) =
+        Option::<UiLoadedAddresses>::from(meta.loaded_addresses)
+    {
+        keys.extend(loaded.writable);
+        keys.extend(loaded.readonly);
+    }
+    Ok(TransactionBalances {
+        keys,
+        signers: tx.message.header().num_required_signatures as usize,
+        fee: meta.fee,
+        failed: meta.err.is_some(),
+        block_time: confirmed.block_time,
+        pre_lamports: meta.pre_balances,
+        post_lamports: meta.post_balances,
+        pre_tokens: TokenBalance::from_ui(meta.pre_token_balances),
+        post_tokens: TokenBalance::from_ui(meta.post_token_balances),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER: &str = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+    const SENDER: &str = "7xKXtg2CW87d97TXJSDpbD5jBkheTqA83TZRuJosgAsU";
+    const USDC: &str = "EPjFWJd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+    fn usdc(owner: &str, amount: u64) -> TokenBalance {
+        TokenBalance {
+            owner: owner.to_string(),
+            mint: USDC.to_string(),
+            amount,
+            decimals: 6,
+        }
+    }
+
+    fn transfer() -> TransactionBalances {
+        TransactionBalances {
+            keys: vec![SENDER.to_string(), USER.to_string()],
+            signers: 1,
+            fee: 5_000,
+            failed: false,
+            block_time: None,
+            pre_lamports: vec![5_000_000_000, 1_000_000],
+            post_lamports: vec![3_999_995_000, 1_001_000_000],
+            pre_tokens: vec![usdc(SENDER, 50_000_000), usdc(USER, 1_000_000)],
+            post_tokens: vec![
+                usdc(SENDER, 25_000_000),
+                usdc(USER, 26_000_000),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_received() {
+        let transfer = transfer();
+        assert_eq!(
+            transfer.received(USER),
+            [
+                (WSOL.to_string(), 1_000_000_000),
+                (USDC.to_string(), 25_000_000)
+            ]
+        );
+        assert!(transfer.received(SENDER).is_empty());
+
+        // the user swapping is no deposit
+        let swap = TransactionBalances {
+            keys: vec![USER.to_string()],
+            ..transfer
+        };
+        assert!(swap.received(USER).is_empty());
+    }
+
+    #[test]
+    fn test_changes_leave_the_fee_out() {
+        let changes = transfer().changes(SENDER);
+        assert_eq!(changes[0].amount, -1_000_000_000);
+        assert_eq!(changes[1].amount, -25_000_000);
+        assert_eq!(changes[1].decimals, 6);
+        assert!(transfer().paid_fee(SENDER));
+    }
+}
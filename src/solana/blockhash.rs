@@ -1,94 +1,187 @@
-use crate::solana::util::env;
-use anyhow::{anyhow, Result};
+//! Recent blockhash shared by all Solana transaction builders and signers,
+//! refreshed in the background every rpc.blockhash_refresh_ms so building
+//! a transaction never waits for a `getLatestBlockhash` round trip. The
+//! blockhash is a confirmed one, a finalized one is ~13s older and expires
+//! that much sooner
+use crate::common::solana_rpc;
+use crate::config::config;
+use crate::error::Error;
+use anyhow::Result;
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::hash::Hash;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
 use once_cell::sync::Lazy;
 
-pub static BLOCKHASH_CACHE: Lazy<BlockhashCache> =
-    Lazy::new(|| BlockhashCache::new(&env("SOLANA_RPC_URL")));
+/// A blockhash this old is fetched again instead of served, the refresh
+/// stopped keeping up and it is halfway to expiring
+const MAX_AGE: Duration = Duration::from_secs(30);
+
+/// Follows the shared Solana RPC client, shared by the clients holding a
+/// cache
+pub static BLOCKHASH_CACHE: Lazy<Arc<BlockhashCache>> =
+    Lazy::new(|| Arc::new(BlockhashCache::start(Rpc::Shared)));
+
+#[derive(Clone, Copy)]
+struct Cached {
+    blockhash: Hash,
+    fetched_at: Instant,
+}
+
+/// Where the blockhashes come from
+#[derive(Clone)]
+enum Rpc {
+    /// `solana_rpc`, which follows rpc.solana across config reloads
+    Shared,
+    Fixed(Arc<RpcClient>),
+}
+
+impl Rpc {
+    fn client(&self) -> Arc<RpcClient> {
+        match self {
+            Self::Shared => solana_rpc(),
+            Self::Fixed(client) => client.clone(),
+        }
+    }
+}
 
 pub struct BlockhashCache {
-    blockhash: Arc<RwLock<Hash>>,
-    client: Arc<RpcClient>,
+    blockhash: Arc<RwLock<Option<Cached>>>,
+    rpc: Rpc,
+}
+
+async fn fetch(client: &RpcClient) -> Result<Cached> {
+    let (blockhash, _) = client
+        .get_latest_blockhash_with_commitment(CommitmentConfig::confirmed())
+        .await
+        .map_err(|e| {
+            Error::Rpc(format!("Failed to fetch blockhash: {}", e))
+        })?;
+    Ok(Cached {
+        blockhash,
+        fetched_at: Instant::now(),
+    })
 }
 
 impl BlockhashCache {
+    /// Cache of the RPC at `rpc_url` rather than the shared one
     pub fn new(rpc_url: &str) -> Self {
-        let client = Arc::new(RpcClient::new(rpc_url.to_string()));
-        let blockhash = Arc::new(RwLock::new(Hash::default())); // TODO: Надо исправлять
+        Self::start(Rpc::Fixed(Arc::new(RpcClient::new(rpc_url.to_string()))))
+    }
 
-        let cache = Self { blockhash, client };
+    fn start(rpc: Rpc) -> Self {
+        let cache = Self {
+            blockhash: Arc::new(RwLock::new(None)),
+            rpc,
+        };
         cache.start_update_task();
         cache
     }
 
+    /// Refreshes the blockhash until the cache is dropped
     fn start_update_task(&self) {
-        let blockhash = self.blockhash.clone();
-        let client = self.client.clone();
+        let weak_blockhash = Arc::downgrade(&self.blockhash);
+        let rpc = self.rpc.clone();
 
         tokio::spawn(async move {
+            let mut rpc_url = None;
             loop {
-                match client
-                    .get_latest_blockhash_with_commitment(
-                        CommitmentConfig::finalized(),
-                    )
-                    .await
+                let Some(blockhash) = weak_blockhash.upgrade() else {
+                    return;
+                };
+                let client = rpc.client();
+                let url = client.url();
+                if rpc_url.replace(url.clone()).is_some_and(|last| last != url)
                 {
-                    Ok(res) => {
-                        let new_blockhash = res.0;
-                        let mut hash_writer = blockhash.write().await;
-                        *hash_writer = new_blockhash;
-                        drop(hash_writer);
-                    }
-                    Err(err) => {
-                        tracing::error!("Failed to fetch blockhash: {}", err);
-                    }
+                    tracing::info!(%url, "blockhash RPC changed");
+                    // of the previous cluster
+                    *blockhash.write().await = None;
+                }
+                match fetch(&client).await {
+                    Ok(cached) => *blockhash.write().await = Some(cached),
+                    Err(e) => tracing::error!(?e, "blockhash not refreshed"),
                 }
 
-                tokio::time::sleep(Duration::from_secs(2)).await;
+                let interval = config().rpc.blockhash_refresh_ms;
+                tokio::time::sleep(Duration::from_millis(interval)).await;
             }
         });
     }
 
-    pub async fn get_blockhash(&self) -> Result<Hash> {
-        let current_hash = *self.blockhash.read().await;
-
-        // If we have a valid blockhash (not default), return it
-        if current_hash != Hash::default() {
-            return Ok(current_hash);
-        }
+    /// Client of the RPC the blockhashes come from
+    pub async fn client(&self) -> Arc<RpcClient> {
+        self.rpc.client()
+    }
 
-        // If we don't have a valid blockhash yet, fetch it immediately
-        match self
-            .client
-            .get_latest_blockhash_with_commitment(
-                CommitmentConfig::finalized(),
-            )
-            .await
-        {
-            Ok(res) => {
-                let new_blockhash = res.0;
-                let mut hash_writer = self.blockhash.write().await;
-                *hash_writer = new_blockhash;
-                Ok(new_blockhash)
-            }
-            Err(err) => {
-                Err(anyhow!("Failed to fetch initial blockhash: {}", err))
+    /// The cached blockhash, fetched inline only before the first refresh
+    /// or when the refresh is failing
+    pub async fn get_blockhash(&self) -> Result<Hash> {
+        if let Some(cached) = *self.blockhash.read().await {
+            if cached.fetched_at.elapsed() < MAX_AGE {
+                return Ok(cached.blockhash);
             }
         }
+
+        let cached = fetch(&self.rpc.client()).await?;
+        *self.blockhash.write().await = Some(cached);
+        Ok(cached.blockhash)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use wiremock::MockServer;
+
+    use super::*;
+    use crate::fixtures::mock_latest_blockhash;
+
     #[tokio::test]
     async fn test_blockhash_cache() {
         let blockhash = super::BLOCKHASH_CACHE.get_blockhash().await.unwrap();
         assert_ne!(blockhash, Default::default());
     }
+
+    async fn mock_rpc() -> MockServer {
+        let server = MockServer::start().await;
+        mock_latest_blockhash(&server).await;
+        server
+    }
+
+    #[tokio::test]
+    async fn test_refresh() {
+        let server = mock_rpc().await;
+        let cache = BlockhashCache::new(&server.uri());
+        let blockhash = cache.get_blockhash().await.unwrap();
+        assert_eq!(
+            blockhash.to_string(),
+            "8xzrcjogDrqqjV6RNYjo77eERX1n6EtGu4FXgUMUZoyV"
+        );
+        // served from the cache from then on, refreshed in the background
+        tokio::time::sleep(Duration::from_millis(1_000)).await;
+        let fetched = server.received_requests().await.unwrap().len();
+        for _ in 0..10 {
+            cache.get_blockhash().await.unwrap();
+        }
+        assert!(
+            server.received_requests().await.unwrap().len() <= fetched + 1
+        );
+        assert!(fetched >= 2);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_cache_stops_refreshing() {
+        let server = mock_rpc().await;
+        let cache = Arc::new(BlockhashCache::new(&server.uri()));
+        cache.get_blockhash().await.unwrap();
+        drop(cache);
+
+        // the refresh task sees it at its next wake up
+        tokio::time::sleep(Duration::from_millis(1_000)).await;
+        let fetched = server.received_requests().await.unwrap().len();
+        tokio::time::sleep(Duration::from_millis(1_000)).await;
+        assert_eq!(server.received_requests().await.unwrap().len(), fetched);
+    }
 }
@@ -0,0 +1,251 @@
+//! Congestion of the Solana network, sampled in the background from the
+//! recent prioritization fees and slot times. When fees.auto is on it
+//! raises the floor of the priority fees (see `priority_fees`): nothing is
+//! added while the network is calm, the fee goes up with the fees that
+//! landed in the recent slots once it gets busy, capped by
+//! fees.max_priority_fee_lamports. The fee set by the session is kept as
+//! the minimum
+use std::sync::RwLock;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::Serialize;
+
+use crate::chains::{Chain, CHAINS};
+use crate::common::solana_rpc;
+use crate::config::config;
+use crate::error::Error;
+
+/// Compute units of a typical swap, converts compute unit prices to fees
+pub const SWAP_COMPUTE_UNITS: u64 = 300_000;
+/// Slots slower than this mean the leaders can't keep up
+const SLOW_SLOT_MS: u64 = 600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Congestion {
+    Low,
+    Normal,
+    High,
+    Extreme,
+}
+
+impl Congestion {
+    fn raised(self) -> Self {
+        match self {
+            Self::Low => Self::Normal,
+            Self::Normal => Self::High,
+            Self::High | Self::Extreme => Self::Extreme,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkConditions {
+    pub congestion: Congestion,
+    /// percentiles of the lowest compute unit price of the recent slots, in
+    /// micro-lamports
+    pub fee_p50: u64,
+    pub fee_p75: u64,
+    pub fee_p90: u64,
+    pub slot_time_ms: u64,
+    /// priority fee of a swap in lamports, None while the network is calm
+    pub suggested_fee_lamports: Option<u64>,
+    /// Jito bundles land more reliably than the RPC while congested
+    pub jito_suggested: bool,
+    pub sampled_at: DateTime<Utc>,
+}
+
+pub(crate) fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+impl NetworkConditions {
+    /// `fees` are the prioritization fees of the recent slots
+    pub fn from_samples(mut fees: Vec<u64>, slot_time_ms: u64) -> Self {
+        fees.sort_unstable();
+        let (fee_p50, fee_p75, fee_p90) = (
+            percentile(&fees, 0.5),
+            percentile(&fees, 0.75),
+            percentile(&fees, 0.9),
+        );
+        let mut congestion = match fee_p75 {
+            0 => Congestion::Low,
+            1..=9_999 => Congestion::Normal,
+            10_000..=99_999 => Congestion::High,
+            _ => Congestion::Extreme,
+        };
+        if slot_time_ms > SLOW_SLOT_MS {
+            congestion = congestion.raised();
+        }
+        let compute_unit_price = match congestion {
+            Congestion::Low => None,
+            Congestion::Normal => Some(fee_p75.max(50_000)),
+            Congestion::High => Some(fee_p90.max(200_000)),
+            Congestion::Extreme => Some((fee_p90 * 2).max(1_000_000)),
+        };
+        Self {
+            congestion,
+            fee_p50,
+            fee_p75,
+            fee_p90,
+            slot_time_ms,
+            suggested_fee_lamports: compute_unit_price
+                .map(|price| price * SWAP_COMPUTE_UNITS / 1_000_000),
+            jito_suggested: congestion >= Congestion::High,
+            sampled_at: Utc::now(),
+        }
+    }
+
+    /// Priority fee of a transaction whose session asked for `preference`
+    pub fn priority_fee(
+        &self,
+        preference: Option<u64>,
+        max_lamports: u64,
+    ) -> Option<u64> {
+        match self.suggested_fee_lamports {
+            Some(suggested) => {
+                Some(preference.unwrap_or(0).max(suggested.min(max_lamports)))
+            }
+            None => preference,
+        }
+    }
+}
+
+pub struct CongestionMonitor {
+    latest: RwLock<Option<NetworkConditions>>,
+}
+
+pub static CONGESTION: Lazy<CongestionMonitor> =
+    Lazy::new(|| CongestionMonitor {
+        latest: RwLock::new(None),
+    });
+
+impl CongestionMonitor {
+    /// The last sample, unless the sampler stopped keeping up
+    pub fn latest(&self) -> Option<NetworkConditions> {
+        let max_age = config().fees.sample_secs as i64 * 3;
+        self.latest.read().unwrap().clone().filter(|conditions| {
+            (Utc::now() - conditions.sampled_at).num_seconds() <= max_age
+        })
+    }
+
+    pub async fn sample(&self) -> Result<NetworkConditions> {
+        let rpc = solana_rpc();
+        let (fees, samples) = tokio::try_join!(
+            rpc.get_recent_prioritization_fees(&[]),
+            rpc.get_recent_performance_samples(Some(5)),
+        )
+        .map_err(Error::from)?;
+        let (slots, millis) =
+            samples.iter().fold((0, 0), |(slots, millis), sample| {
+                (
+                    slots + sample.num_slots,
+                    millis + sample.sample_period_secs as u64 * 1_000,
+                )
+            });
+        let conditions = NetworkConditions::from_samples(
+            fees.iter().map(|fee| fee.prioritization_fee).collect(),
+            millis.checked_div(slots).unwrap_or_default(),
+        );
+        *self.latest.write().unwrap() = Some(conditions.clone());
+        Ok(conditions)
+    }
+
+    /// Samples every fees.sample_secs while fees.auto is on
+    pub fn spawn(&'static self) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let config = config();
+                if config.fees.auto && CHAINS.is_enabled(Chain::Solana) {
+                    match self.sample().await {
+                        Ok(conditions) => tracing::debug!(
+                            ?conditions.congestion,
+                            "network congestion sampled"
+                        ),
+                        Err(e) => {
+                            tracing::warn!(?e, "congestion not sampled")
+                        }
+                    }
+                }
+                let interval = Duration::from_secs(config.fees.sample_secs);
+                tokio::time::sleep(interval).await;
+            }
+        })
+    }
+}
+
+/// Least priority fee of a swap, the one of the session raised to what the
+/// congestion calls for. Transactions take their price from
+/// `priority_fees::compute_unit_price`
+pub(crate) fn priority_fee(preference: Option<u64>) -> Option<u64> {
+    let config = config();
+    if !config.fees.auto {
+        return preference;
+    }
+    match CONGESTION.latest() {
+        Some(conditions) => conditions
+            .priority_fee(preference, config.fees.max_priority_fee_lamports),
+        None => preference,
+    }
+}
+
+#[tool(description = "
+Returns the congestion of the Solana network (low, normal, high or extreme),
+the recent priority fees and slot time, the priority fee swaps pay right now
+and whether sending through Jito is advisable.
+
+Use it when transactions are slow to land or fail to confirm, and suggest
+raising the priority_fee preference or using Jito when congestion is high
+")]
+pub async fn get_network_congestion() -> Result<NetworkConditions> {
+    match CONGESTION.latest() {
+        Some(conditions) => Ok(conditions),
+        None => CONGESTION.sample().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let calm = NetworkConditions::from_samples(vec![0; 150], 400);
+        assert_eq!(calm.congestion, Congestion::Low);
+        assert_eq!(calm.suggested_fee_lamports, None);
+        assert_eq!(calm.priority_fee(Some(10_000), 5_000_000), Some(10_000));
+
+        // a third of the slots took only transactions paying 20k and more
+        let mut fees = vec![0; 100];
+        fees.extend([20_000; 50]);
+        let busy = NetworkConditions::from_samples(fees.clone(), 400);
+        assert_eq!(busy.congestion, Congestion::High);
+        assert!(busy.jito_suggested);
+        assert_eq!(busy.suggested_fee_lamports, Some(60_000));
+
+        let slow = NetworkConditions::from_samples(fees, 800);
+        assert_eq!(slow.congestion, Congestion::Extreme);
+    }
+
+    #[test]
+    fn test_priority_fee() {
+        let conditions =
+            NetworkConditions::from_samples(vec![5_000; 150], 400);
+        assert_eq!(conditions.congestion, Congestion::Normal);
+        assert_eq!(conditions.suggested_fee_lamports, Some(15_000));
+        // raised to the suggestion, capped, the session's fee is the floor
+        assert_eq!(conditions.priority_fee(None, 5_000_000), Some(15_000));
+        assert_eq!(conditions.priority_fee(None, 10_000), Some(10_000));
+        assert_eq!(
+            conditions.priority_fee(Some(100_000), 10_000),
+            Some(100_000)
+        );
+    }
+}
@@ -1,9 +1,10 @@
 use anyhow::Result;
 use futures::future::join_all;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
+use crate::common::http_client;
 use crate::dexscreener::{search_ticker, PairInfo};
+use crate::error::Error;
 use crate::solana::balance::Holding;
 
 pub async fn fetch_pair_info(mint_or_symbol: String) -> Result<PairInfo> {
@@ -27,7 +28,11 @@ pub async fn fetch_pair_info(mint_or_symbol: String) -> Result<PairInfo> {
         .first()
         .map(|pair| (*pair).clone()) // Dereference and clone the PairInfo
         .ok_or_else(|| {
-            anyhow::anyhow!("No matching pairs found for {}", mint_or_symbol)
+            Error::NotFound(format!(
+                "No matching pairs found for {}",
+                mint_or_symbol
+            ))
+            .into()
         })
 }
 
@@ -72,7 +77,7 @@ pub struct PortfolioItem {
 pub async fn holdings_to_portfolio(
     holdings: Vec<Holding>,
 ) -> Result<Vec<PortfolioItem>> {
-    let client = Client::new();
+    let client = http_client();
 
     // Fetch metadata for all tokens
     let metadata_futures: Vec<_> = holdings
@@ -115,7 +120,6 @@ pub async fn holdings_to_portfolio(
                     None => 0.0,
                 })
                 .unwrap_or(0.0);
-            println!("ЕДРЖУ: {:#?}", holding);
             let amount = holding.amount as f64
                 / (10f64.powi(metadata.decimals as i32));
 
@@ -132,7 +136,7 @@ pub async fn holdings_to_portfolio(
         })
         .collect();
 
-    println!("portfolio: {:#?}", portfolio);
+    tracing::debug!(items = portfolio.len(), "fetched portfolio");
     Ok(portfolio)
 }
 
@@ -141,17 +145,16 @@ mod tests {
     use solana_sdk::signer::Signer;
 
     use super::*;
+    use crate::common::solana_rpc;
     use crate::solana::balance::get_holdings;
-    use crate::solana::util::{load_keypair_for_tests, make_rpc_client};
+    use crate::solana::util::load_keypair_for_tests;
 
     #[tokio::test]
     async fn test_holdings_to_portfolio() {
-        let holdings = get_holdings(
-            &make_rpc_client(),
-            &load_keypair_for_tests().pubkey(),
-        )
-        .await
-        .unwrap();
+        let holdings =
+            get_holdings(&solana_rpc(), &load_keypair_for_tests().pubkey())
+                .await
+                .unwrap();
 
         holdings_to_portfolio(holdings).await.unwrap();
     }
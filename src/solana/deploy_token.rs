@@ -15,6 +15,7 @@ use solana_sdk::{
 };
 use std::str::FromStr;
 
+use crate::common::http_client;
 use crate::solana::{
     blockhash::BLOCKHASH_CACHE,
     constants::{
@@ -264,8 +265,7 @@ pub async fn fetch_pool_state(
 
 pub async fn load_image(image_path: &str) -> Result<Vec<u8>> {
     if image_path.starts_with("http") {
-        let client = Client::new();
-        let res = client.get(image_path).send().await?.bytes().await?;
+        let res = http_client().get(image_path).send().await?.bytes().await?;
         Ok(res.to_vec())
     } else {
         Ok(std::fs::read(image_path)?)
@@ -438,17 +438,7 @@ fn get_ipfs_client() -> Client {
     // let auth = format!("{}:{}", project_id, project_secret);
     // let encoded_auth = BASE64.encode(auth);
 
-    Client::builder()
-        // .default_headers({
-        // let mut headers = reqwest::header::HeaderMap::new();
-        // headers.insert(
-        //     reqwest::header::AUTHORIZATION,
-        //     format!("Basic {}", encoded_auth).parse().unwrap(),
-        // );
-        // headers
-        // })
-        .build()
-        .expect("Failed to create IPFS client")
+    http_client()
 }
 
 pub fn derive_metadata_account(mint: &Pubkey) -> Pubkey {
@@ -0,0 +1,613 @@
+//! Explanations of Solana transactions, instruction by instruction. The
+//! programs the agent builds transactions for are decoded, anything else
+//! is named by its program id and flagged. Accounts of v0 transactions
+//! loaded from address lookup tables are resolved through the RPC, the
+//! wallet of the user and its token accounts are pointed out
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_sdk::address_lookup_table::state::AddressLookupTable;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::system_instruction::SystemInstruction;
+use solana_sdk::transaction::VersionedTransaction;
+use solana_transaction_status::{UiLoadedAddresses, UiTransactionEncoding};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token::instruction::TokenInstruction;
+
+use super::constants::{
+    ASSOCIATED_TOKEN_PROGRAM, PUMP_BUY_METHOD, PUMP_CREATE_METHOD,
+    PUMP_FUN_PROGRAM, PUMP_SELL_METHOD, SYSTEM_PROGRAM_ID, TOKEN_PROGRAM,
+};
+use super::rent::{default_minimum_balance, AccountKind};
+use crate::amount::Amount;
+use crate::chains::{Chain, CHAINS};
+use crate::common::solana_rpc;
+use crate::error::Error;
+use crate::explain::Explanation;
+use crate::signer::SignerContext;
+use crate::spenders::JUPITER_PROGRAM;
+use crate::tool_error::ToolError;
+
+const TOKEN_2022_PROGRAM: &str =
+    "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
+const MEMO_PROGRAM: &str = "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr";
+
+/// Anchor discriminators of the Jupiter v6 routes
+const JUPITER_ROUTE: [u8; 8] = [229, 23, 203, 151, 122, 227, 173, 42];
+const JUPITER_SHARED_ACCOUNTS_ROUTE: [u8; 8] =
+    [193, 32, 155, 51, 65, 214, 156, 129];
+const JUPITER_EXACT_OUT_ROUTE: [u8; 8] =
+    [208, 51, 239, 151, 123, 43, 237, 92];
+const JUPITER_SHARED_ACCOUNTS_EXACT_OUT_ROUTE: [u8; 8] =
+    [176, 209, 105, 168, 154, 125, 69, 62];
+/// The routes end with two u64 amounts, the slippage (u16) and the
+/// platform fee (u8) after the variable length route plan
+const JUPITER_ROUTE_TAIL: usize = 8 + 8 + 2 + 1;
+
+/// Accounts of a transaction, the static keys followed by the ones loaded
+/// from lookup tables, and the accounts of the user among them
+struct Accounts {
+    keys: Vec<Pubkey>,
+    user: Option<Pubkey>,
+    user_token_accounts: HashSet<Pubkey>,
+}
+
+impl Accounts {
+    fn new(keys: Vec<Pubkey>, user: Option<&Pubkey>) -> Self {
+        // any key may be a mint the user holds
+        let user_token_accounts = user.map_or_else(HashSet::new, |user| {
+            keys.iter()
+                .flat_map(|mint| {
+                    [spl_token::id(), spl_token_2022::id()].map(|program| {
+                        get_associated_token_address_with_program_id(
+                            user, mint, &program,
+                        )
+                    })
+                })
+                .collect()
+        });
+        Self {
+            keys,
+            user: user.copied(),
+            user_token_accounts,
+        }
+    }
+
+    fn label(&self, key: &Pubkey) -> String {
+        if Some(*key) == self.user {
+            format!("{} (your wallet)", key)
+        } else if self.user_token_accounts.contains(key) {
+            format!("{} (your token account)", key)
+        } else {
+            key.to_string()
+        }
+    }
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn sol(lamports: u64) -> Amount {
+    Amount::new(lamports as u128, 9).with_symbol("SOL")
+}
+
+fn describe_system(data: &[u8], account: &dyn Fn(usize) -> String) -> String {
+    match bincode::deserialize::<SystemInstruction>(data) {
+        Ok(SystemInstruction::Transfer { lamports }) => format!(
+            "Transfer {} from {} to {}",
+            sol(lamports),
+            account(0),
+            account(1)
+        ),
+        Ok(SystemInstruction::CreateAccount {
+            lamports, owner, ..
+        }) => format!(
+            "Create the account {} with {}, owned by the program {}",
+            account(1),
+            sol(lamports),
+            owner
+        ),
+        Ok(instruction) => format!("System program: {:?}", instruction),
+        Err(_) => "Unrecognized system program instruction".to_string(),
+    }
+}
+
+fn describe_token(
+    explanation: &mut Explanation,
+    data: &[u8],
+    account: &dyn Fn(usize) -> String,
+) -> String {
+    let instruction = match TokenInstruction::unpack(data) {
+        Ok(instruction) => instruction,
+        Err(_) => {
+            return "Unrecognized token program instruction".to_string()
+        }
+    };
+    match instruction {
+        TokenInstruction::Transfer { amount } => format!(
+            "Transfer {} raw units of tokens from the token account {} to {}",
+            amount,
+            account(0),
+            account(1)
+        ),
+        TokenInstruction::TransferChecked { amount, decimals } => format!(
+            "Transfer {} of the token {} from the token account {} to {}",
+            Amount::new(amount as u128, decimals),
+            account(1),
+            account(0),
+            account(2)
+        ),
+        TokenInstruction::Approve { amount }
+        | TokenInstruction::ApproveChecked { amount, .. } => {
+            if amount == u64::MAX {
+                explanation.warn(format!(
+                    "{} gets an unlimited allowance on the token account {}",
+                    account(1),
+                    account(0)
+                ));
+            }
+            format!(
+                "Allow {} to spend {} raw units from the token account {}",
+                account(1),
+                amount,
+                account(0)
+            )
+        }
+        TokenInstruction::SetAuthority { .. } => {
+            explanation
+                .warn(format!("The authority of {} is changed", account(0)));
+            format!("Change the authority of {}", account(0))
+        }
+        TokenInstruction::CloseAccount => format!(
+            "Close the token account {}, its rent goes to {}",
+            account(0),
+            account(1)
+        ),
+        TokenInstruction::SyncNative => {
+            format!("Sync the wrapped SOL balance of {}", account(0))
+        }
+        TokenInstruction::Burn { amount }
+        | TokenInstruction::BurnChecked { amount, .. } => format!(
+            "Burn {} raw units of the token {} from {}",
+            amount,
+            account(1),
+            account(0)
+        ),
+        TokenInstruction::InitializeAccount
+        | TokenInstruction::InitializeAccount2 { .. }
+        | TokenInstruction::InitializeAccount3 { .. } => format!(
+            "Initialize the token account {} for the token {}",
+            account(0),
+            account(1)
+        ),
+        instruction => format!("Token program: {:?}", instruction),
+    }
+}
+
+fn describe_pump_fun(
+    data: &[u8],
+    account: &dyn Fn(usize) -> String,
+) -> String {
+    let method = data.get(..8).unwrap_or_default();
+    let (amount, lamports) = (u64_at(data, 8), u64_at(data, 16));
+    match (amount, lamports) {
+        (Some(amount), Some(lamports)) if method == PUMP_BUY_METHOD => {
+            format!(
+                "Buy {} raw units of the pump.fun token {} for at most {}",
+                amount,
+                account(2),
+                sol(lamports)
+            )
+        }
+        (Some(amount), Some(lamports)) if method == PUMP_SELL_METHOD => {
+            format!(
+                "Sell {} raw units of the pump.fun token {} for at least {}",
+                amount,
+                account(2),
+                sol(lamports)
+            )
+        }
+        _ if method == PUMP_CREATE_METHOD => {
+            format!("Create the pump.fun token {}", account(0))
+        }
+        _ => "Call the pump.fun program".to_string(),
+    }
+}
+
+fn describe_jupiter(
+    data: &[u8],
+    account: &dyn Fn(usize) -> String,
+) -> String {
+    let method = data.get(..8).unwrap_or_default();
+    let tail = data
+        .len()
+        .checked_sub(JUPITER_ROUTE_TAIL)
+        .and_then(|start| data.get(start..));
+    let (Some(tail), Some((source, destination))) = (
+        tail,
+        match method {
+            m if m == JUPITER_ROUTE || m == JUPITER_EXACT_OUT_ROUTE => {
+                Some((2, 5))
+            }
+            m if m == JUPITER_SHARED_ACCOUNTS_ROUTE
+                || m == JUPITER_SHARED_ACCOUNTS_EXACT_OUT_ROUTE =>
+            {
+                Some((3, 8))
+            }
+            _ => None,
+        },
+    ) else {
+        return "Swap tokens through Jupiter".to_string();
+    };
+    let (first, second) =
+        (u64_at(tail, 0).unwrap_or(0), u64_at(tail, 8).unwrap_or(0));
+    let slippage_bps = u16::from_le_bytes([tail[16], tail[17]]);
+    let with_slippage = |amount: u64, up: bool| {
+        let bps = if up {
+            10_000 + slippage_bps as u128
+        } else {
+            10_000u128.saturating_sub(slippage_bps as u128)
+        };
+        amount as u128 * bps / 10_000
+    };
+    if method == JUPITER_ROUTE || method == JUPITER_SHARED_ACCOUNTS_ROUTE {
+        format!(
+            "Swap {} raw units from the token account {} through Jupiter for \
+             at least {} raw units of the token {} ({} bps slippage)",
+            first,
+            account(source),
+            with_slippage(second, false),
+            account(destination),
+            slippage_bps
+        )
+    } else {
+        format!(
+            "Swap at most {} raw units from the token account {} through \
+             Jupiter for exactly {} raw units of the token {} ({} bps \
+             slippage)",
+            with_slippage(second, true),
+            account(source),
+            first,
+            account(destination),
+            slippage_bps
+        )
+    }
+}
+
+fn describe_compute_budget(data: &[u8]) -> String {
+    match data.first() {
+        Some(2) => format!(
+            "Limit the transaction to {} compute units",
+            data.get(1..5)
+                .and_then(|b| b.try_into().ok())
+                .map_or(0, u32::from_le_bytes)
+        ),
+        Some(3) => format!(
+            "Pay a priority fee of {} micro-lamports per compute unit",
+            u64_at(data, 1).unwrap_or_default()
+        ),
+        _ => "Set the compute budget".to_string(),
+    }
+}
+
+/// Explains every instruction of `tx`. `loaded` are the accounts loaded
+/// from its address lookup tables, writable then readonly, those left out
+/// are shown as unknown. Accounts of `user` are pointed out
+pub fn explain_transaction(
+    tx: &VersionedTransaction,
+    loaded: &[Pubkey],
+    user: Option<&Pubkey>,
+) -> Explanation {
+    let mut explanation = Explanation::new("sol");
+    let lookups = tx.message.address_table_lookups().unwrap_or_default();
+    let looked_up = lookups
+        .iter()
+        .map(|lookup| {
+            lookup.writable_indexes.len() + lookup.readonly_indexes.len()
+        })
+        .sum::<usize>();
+    if loaded.len() < looked_up {
+        explanation
+            .warn("Some accounts come from address lookup tables and are shown as unknown");
+    }
+    let mut keys = tx.message.static_account_keys().to_vec();
+    keys.extend(loaded.iter().take(looked_up));
+    let accounts = Accounts::new(keys, user);
+
+    for ix in tx.message.instructions() {
+        let account = |i: usize| {
+            ix.accounts
+                .get(i)
+                .and_then(|&index| accounts.keys.get(index as usize))
+                .map_or_else(
+                    || "an unknown account".to_string(),
+                    |key| accounts.label(key),
+                )
+        };
+        let program = match accounts.keys.get(ix.program_id_index as usize) {
+            Some(program) => *program,
+            None => {
+                explanation.warn("An instruction calls an unknown program");
+                continue;
+            }
+        };
+        let step = match program.to_string().as_str() {
+            SYSTEM_PROGRAM_ID => describe_system(&ix.data, &account),
+            TOKEN_PROGRAM | TOKEN_2022_PROGRAM => {
+                describe_token(&mut explanation, &ix.data, &account)
+            }
+            ASSOCIATED_TOKEN_PROGRAM => format!(
+                "Create the token account {} of {} for the token {}, {} \
+                 deposits about {} of rent",
+                account(1),
+                account(2),
+                account(3),
+                account(0),
+                sol(default_minimum_balance(
+                    AccountKind::TokenAccount.size()
+                ))
+            ),
+            JUPITER_PROGRAM => describe_jupiter(&ix.data, &account),
+            PUMP_FUN_PROGRAM => describe_pump_fun(&ix.data, &account),
+            MEMO_PROGRAM => format!(
+                "Attach the memo \"{}\"",
+                String::from_utf8_lossy(&ix.data)
+            ),
+            _ if program == solana_sdk::compute_budget::id() => {
+                describe_compute_budget(&ix.data)
+            }
+            program => {
+                explanation.warn(format!("Unknown program {}", program));
+                format!(
+                    "Call the program {} with {} accounts",
+                    program,
+                    ix.accounts.len()
+                )
+            }
+        };
+        explanation.step(step);
+    }
+    explanation
+}
+
+/// Accounts loaded from the address lookup tables of `tx`, writable then
+/// readonly as the runtime orders them
+async fn load_addresses(tx: &VersionedTransaction) -> Result<Vec<Pubkey>> {
+    let lookups = tx.message.address_table_lookups().unwrap_or_default();
+    let mut writable = vec![];
+    let mut readonly = vec![];
+    for lookup in lookups {
+        let account = solana_rpc()
+            .get_account(&lookup.account_key)
+            .await
+            .map_err(Error::from)?;
+        let table =
+            AddressLookupTable::deserialize(&account.data).map_err(|e| {
+                anyhow!("Invalid lookup table {}: {}", lookup.account_key, e)
+            })?;
+        let address =
+            |index: &u8| {
+                table.addresses.get(*index as usize).copied().ok_or_else(
+                    || {
+                        anyhow!(
+                            "Lookup table {} has no index {}",
+                            lookup.account_key,
+                            index
+                        )
+                    },
+                )
+            };
+        for index in &lookup.writable_indexes {
+            writable.push(address(index)?);
+        }
+        for index in &lookup.readonly_indexes {
+            readonly.push(address(index)?);
+        }
+    }
+    writable.extend(readonly);
+    Ok(writable)
+}
+
+/// Resolves the lookup tables of `tx` and explains it, the tables may have
+/// been closed since
+async fn explain_loaded(
+    tx: &VersionedTransaction,
+    user: Option<&Pubkey>,
+) -> Explanation {
+    let loaded = match load_addresses(tx).await {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            tracing::warn!(?e, "lookup tables not resolved");
+            vec![]
+        }
+    };
+    explain_transaction(tx, &loaded, user)
+}
+
+/// Wallet of the current signer when Solana is enabled
+fn current_user() -> Option<Pubkey> {
+    CHAINS.require(Chain::Solana).ok()?;
+    Pubkey::from_str(&SignerContext::try_current()?.pubkey()).ok()
+}
+
+async fn explain_signature(signature: &Signature) -> Result<Explanation> {
+    let config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: None,
+        max_supported_transaction_version: Some(0),
+    };
+    let confirmed = solana_rpc()
+        .get_transaction_with_config(signature, config)
+        .await
+        .map_err(|e| {
+            Error::NotFound(format!(
+                "Transaction {} not found: {}",
+                signature, e
+            ))
+        })?;
+    let tx = confirmed.transaction.transaction.decode().ok_or_else(|| {
+        anyhow!("Failed to decode transaction {}", signature)
+    })?;
+
+    let user = current_user();
+    let meta = confirmed.transaction.meta;
+    // the addresses the transaction loaded when it landed
+    let loaded = meta
+        .as_ref()
+        .and_then(|meta| Option::from(meta.loaded_addresses.clone()))
+        .map(|loaded: UiLoadedAddresses| {
+            loaded
+                .writable
+                .iter()
+                .chain(&loaded.readonly)
+                .map(|key| key.parse::<Pubkey>())
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+    let mut explanation = match loaded {
+        Some(loaded) => explain_transaction(&tx, &loaded, user.as_ref()),
+        None => explain_loaded(&tx, user.as_ref()).await,
+    };
+    if let Some(error) = meta.and_then(|meta| meta.err) {
+        explanation.warn(format!("The transaction failed: {}", error));
+    }
+    Ok(explanation)
+}
+
+/// Explains a transaction signature or a base64/base58 encoded transaction
+pub async fn explain(input: &str) -> Result<Explanation> {
+    if let Ok(signature) = Signature::from_str(input) {
+        return explain_signature(&signature).await;
+    }
+    let tx = BASE64_STANDARD
+        .decode(input)
+        .or_else(|_| bs58::decode(input).into_vec())
+        .ok()
+        .and_then(|bytes| {
+            bincode::deserialize::<VersionedTransaction>(&bytes).ok()
+        })
+        .ok_or_else(|| {
+            ToolError::invalid_input("Not a Solana transaction nor signature")
+                .with_hint("Pass a base64 encoded transaction or a signature")
+        })?;
+    Ok(explain_loaded(&tx, current_user().as_ref()).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::compute_budget::ComputeBudgetInstruction;
+    use solana_sdk::native_token::sol_to_lamports;
+    use solana_sdk::system_instruction::transfer;
+    use solana_sdk::transaction::Transaction;
+
+    #[test]
+    fn test_explain_transaction() {
+        let from = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let unknown = Pubkey::new_unique();
+        let tx = Transaction::new_with_payer(
+            &[
+                ComputeBudgetInstruction::set_compute_unit_price(1000),
+                transfer(&from, &to, sol_to_lamports(1.5)),
+                solana_sdk::instruction::Instruction::new_with_bytes(
+                    unknown,
+                    &[],
+                    vec![],
+                ),
+            ],
+            Some(&from),
+        );
+        let explanation = explain_transaction(&tx.into(), &[], None);
+        assert_eq!(
+            explanation.steps,
+            [
+                "Pay a priority fee of 1000 micro-lamports per compute unit"
+                    .to_string(),
+                format!("Transfer 1.5 SOL from {} to {}", from, to),
+                format!("Call the program {} with 0 accounts", unknown),
+            ]
+        );
+        assert_eq!(
+            explanation.warnings,
+            [format!("Unknown program {}", unknown)]
+        );
+    }
+
+    #[test]
+    fn test_explain_v0_transaction() {
+        use solana_sdk::address_lookup_table::AddressLookupTableAccount;
+        use solana_sdk::hash::Hash;
+        use solana_sdk::instruction::{AccountMeta, Instruction};
+        use solana_sdk::message::{v0, VersionedMessage};
+
+        let user = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let output_mint = Pubkey::new_unique();
+        let source = get_associated_token_address_with_program_id(
+            &user,
+            &mint,
+            &spl_token::id(),
+        );
+        let pool = Pubkey::new_unique();
+        let table = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: vec![pool, output_mint],
+        };
+
+        let mut data = JUPITER_ROUTE.to_vec();
+        // an empty route plan
+        data.extend(0u32.to_le_bytes());
+        data.extend(1_000u64.to_le_bytes());
+        data.extend(2_000u64.to_le_bytes());
+        data.extend(50u16.to_le_bytes());
+        data.push(0);
+        // the mint of the source is among the remaining accounts
+        let accounts =
+            [spl_token::id(), user, source, pool, pool, output_mint, mint]
+                .iter()
+                .map(|key| AccountMeta::new_readonly(*key, *key == user))
+                .collect();
+        let route = Instruction::new_with_bytes(
+            Pubkey::from_str(JUPITER_PROGRAM).unwrap(),
+            &data,
+            accounts,
+        );
+        let message = v0::Message::try_compile(
+            &user,
+            &[route],
+            &[table],
+            Hash::default(),
+        )
+        .unwrap();
+        let tx = VersionedTransaction {
+            signatures: vec![Signature::default()],
+            message: VersionedMessage::V0(message),
+        };
+
+        let explanation = explain_transaction(&tx, &[], Some(&user));
+        assert!(explanation.steps[0].contains("of the token an unknown"));
+        assert_eq!(explanation.warnings.len(), 1);
+
+        let explanation =
+            explain_transaction(&tx, &[pool, output_mint], Some(&user));
+        assert_eq!(
+            explanation.steps,
+            [format!(
+                "Swap 1000 raw units from the token account {} (your token \
+                 account) through Jupiter for at least 1990 raw units of the \
+                 token {} (50 bps slippage)",
+                source, output_mint
+            )]
+        );
+        assert!(explanation.warnings.is_empty());
+    }
+}
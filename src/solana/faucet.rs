@@ -0,0 +1,195 @@
+//! Faucet tools for devnet and testnet, so the whole flow (funding, swaps,
+//! transfers, deploys) can be demoed end to end without real funds. They
+//! refuse to run unless rpc.solana_cluster (SOLANA_CLUSTER) is devnet or
+//! testnet
+use std::time::Duration;
+
+use anyhow::Result;
+use rig_tool_macro::tool;
+use serde::Serialize;
+use solana_sdk::program_pack::Pack;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::Transaction;
+use spl_associated_token_account::get_associated_token_address;
+use spl_associated_token_account::instruction::create_associated_token_account;
+
+use super::blockhash::BLOCKHASH_CACHE;
+use super::rent::{self, AccountKind};
+use super::util::execute_solana_transaction;
+use crate::amount::Amount;
+use crate::common::{solana_rpc, with_explorer_link};
+use crate::config::{config, SolanaCluster};
+use crate::error::Error;
+use crate::signer::SignerContext;
+use crate::tool_error::{ErrorCode, ToolError};
+
+/// The public faucets reject larger airdrops
+const MAX_AIRDROP_LAMPORTS: u64 = 2_000_000_000;
+const AIRDROP_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_TEST_TOKEN_DECIMALS: u8 = 9;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TestToken {
+    pub mint: String,
+    pub decimals: u8,
+    pub amount: String,
+    pub signature: String,
+}
+
+fn require_test_cluster() -> Result<SolanaCluster> {
+    let cluster = config().rpc.solana_cluster;
+    if cluster == SolanaCluster::Mainnet {
+        return Err(ToolError::new(
+            ErrorCode::Unavailable,
+            "The faucet only works on devnet and testnet",
+        )
+        .with_hint("Set rpc.solana_cluster (SOLANA_CLUSTER) to devnet")
+        .into());
+    }
+    Ok(cluster)
+}
+
+/// Creates a mint with `owner` as the mint authority and mints `amount` of
+/// it to the owner, the transaction is signed by the mint already
+pub async fn create_test_token_tx(
+    owner: &Pubkey,
+    mint: &Keypair,
+    amount: &Amount,
+) -> Result<Transaction> {
+    let rent = rent::minimum_balance(AccountKind::Mint.size()).await?;
+    let ata = get_associated_token_address(owner, &mint.pubkey());
+    let instructions = vec![
+        system_instruction::create_account(
+            owner,
+            &mint.pubkey(),
+            rent,
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint2(
+            &spl_token::id(),
+            &mint.pubkey(),
+            owner,
+            None,
+            amount.decimals(),
+        )?,
+        create_associated_token_account(
+            owner,
+            owner,
+            &mint.pubkey(),
+            &spl_token::id(),
+        ),
+        spl_token::instruction::mint_to(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &ata,
+            owner,
+            &[],
+            amount.to_u64()?,
+        )?,
+    ];
+    let mut tx = Transaction::new_with_payer(&instructions, Some(owner));
+    tx.partial_sign(&[mint], BLOCKHASH_CACHE.get_blockhash().await?);
+    Ok(tx)
+}
+
+#[tool(description = "
+Airdrops SOL to the wallet from the faucet of devnet or testnet, it fails on
+mainnet. Use it to fund the wallet before demoing swaps and transfers.
+
+amount is in SOL, e.g. 1.5, at most 2 per airdrop
+")]
+pub async fn request_airdrop(amount: String) -> Result<String> {
+    let cluster = require_test_cluster()?;
+    let lamports = Amount::parse(&amount, 9)?.to_u64()?;
+    if lamports == 0 || lamports > MAX_AIRDROP_LAMPORTS {
+        return Err(ToolError::invalid_input(format!(
+            "Can't airdrop {} SOL",
+            amount
+        ))
+        .with_hint("Airdrop between 0 and 2 SOL at a time")
+        .into());
+    }
+    let owner: Pubkey = SignerContext::current().await.pubkey().parse()?;
+
+    let rpc = solana_rpc();
+    let signature =
+        rpc.request_airdrop(&owner, lamports).await.map_err(|e| {
+            ToolError::new(
+                ErrorCode::RateLimited,
+                format!("The {} faucet refused: {}", cluster.as_str(), e),
+            )
+            .with_hint(
+                "The faucet is rate limited, try a smaller amount later",
+            )
+        })?;
+    tokio::time::timeout(AIRDROP_TIMEOUT, async {
+        while !rpc.confirm_transaction(&signature).await.unwrap_or(false) {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    })
+    .await
+    .map_err(|_| {
+        ToolError::new(
+            ErrorCode::Timeout,
+            format!("Airdrop {} not confirmed yet", signature),
+        )
+    })?;
+    Ok(with_explorer_link("sol", &signature.to_string()))
+}
+
+#[tool(description = "
+Creates a new SPL token on devnet or testnet with the wallet as the mint
+authority and mints amount of it to the wallet, it fails on mainnet. The
+token can then be transferred or swapped to demo the agent without real
+funds.
+
+decimals is the number of decimals of the token, at most 9
+amount is in whole tokens, e.g. 1000000
+")]
+pub async fn mint_test_token(
+    decimals: u8,
+    amount: String,
+) -> Result<TestToken> {
+    require_test_cluster()?;
+    if decimals > MAX_TEST_TOKEN_DECIMALS {
+        return Err(ToolError::invalid_input(format!(
+            "Tokens can't have {} decimals",
+            decimals
+        ))
+        .with_hint("Use 0 to 9 decimals")
+        .into());
+    }
+    let amount = Amount::parse(&amount, decimals)?;
+    // SPL amounts are u64, fail before paying for the mint
+    amount.to_u64()?;
+
+    let mint = Keypair::new();
+    let mint_address = mint.pubkey().to_string();
+    let minted = amount.to_ui_string();
+    let signature = execute_solana_transaction(move |owner| async move {
+        create_test_token_tx(&owner, &mint, &amount).await
+    })
+    .await?;
+    Ok(TestToken {
+        mint: mint_address,
+        decimals,
+        amount: minted,
+        signature: with_explorer_link("sol", &signature),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_faucet_refuses_mainnet() {
+        assert_eq!(config().rpc.solana_cluster, SolanaCluster::Mainnet);
+        let err = request_airdrop("1".to_string()).await.unwrap_err();
+        assert_eq!(ToolError::from_anyhow(&err).code, ErrorCode::Unavailable);
+        assert!(mint_test_token(6, "1000".to_string()).await.is_err());
+    }
+}
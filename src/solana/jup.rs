@@ -1,20 +1,37 @@
 use std::str::FromStr;
+use std::sync::Arc;
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
 use serde::{Deserialize, Serialize};
+use solana_sdk::address_lookup_table::state::AddressLookupTable;
+use solana_sdk::address_lookup_table::AddressLookupTableAccount;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::message::{v0, VersionedMessage};
 use solana_sdk::pubkey::Pubkey;
-use solana_sdk::transaction::Transaction;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{Transaction, VersionedTransaction};
+use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
 
-#[derive(Serialize, Deserialize, Debug)]
+use super::blockhash::{BlockhashCache, BLOCKHASH_CACHE};
+use crate::common::{http_client, solana_rpc};
+use crate::config::config;
+use crate::error::Error;
+use crate::http::client::{send, Retry};
+use crate::metrics::observe_api;
+
+pub(crate) const JUPITER_API: &str = "https://quote-api.jup.ag/v6";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PlatformFee {
     pub amount: String,
     #[serde(rename = "feeBps")]
     pub fee_bps: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DynamicSlippage {
     #[serde(rename = "minBps")]
     pub min_bps: i32,
@@ -22,14 +39,14 @@ pub struct DynamicSlippage {
     pub max_bps: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RoutePlan {
     #[serde(rename = "swapInfo")]
     pub swap_info: SwapInfo,
     pub percent: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct QuoteResponse {
     #[serde(rename = "inputMint")]
     pub input_mint: String,
@@ -57,7 +74,41 @@ pub struct QuoteResponse {
     pub time_taken: f64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl QuoteResponse {
+    pub fn summary(&self) -> serde_json::Value {
+        let route = self
+            .route_plan
+            .iter()
+            .map(|plan| {
+                format!(
+                    "{} ({}%)",
+                    plan.swap_info.label.as_deref().unwrap_or("unknown"),
+                    plan.percent
+                )
+            })
+            .collect::<Vec<_>>();
+
+        serde_json::json!({
+            "from": {
+                "mint": self.input_mint,
+                "amount": self.in_amount,
+            },
+            "to": {
+                "mint": self.output_mint,
+                "amount": self.out_amount,
+                "amount_min": self.other_amount_threshold,
+            },
+            "costs": {
+                "platform_fee": self.platform_fee,
+                "price_impact_pct": self.price_impact_pct,
+            },
+            "slippage_bps": self.slippage_bps,
+            "route": route,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SwapInfo {
     #[serde(rename = "ammKey")]
     pub amm_key: String,
@@ -141,50 +192,228 @@ pub struct AccountMeta {
     pub is_writable: bool,
 }
 
-pub struct Jupiter;
+/// Platform fee of the operator and the wallet collecting it, None when
+/// no fee is configured
+fn platform_fee() -> Option<(u16, Pubkey)> {
+    let revenue = &config().revenue;
+    if revenue.jupiter_fee_bps == 0 {
+        return None;
+    }
+    let owner = Pubkey::from_str(revenue.jupiter_fee_owner.as_deref()?).ok()?;
+    Some((revenue.jupiter_fee_bps, owner))
+}
+
+/// Token account of the fee owner for the output token, the fee is taken
+/// from the output of the swap. None when the quote carries no fee
+fn fee_account(quote: &QuoteResponse) -> Option<String> {
+    quote.platform_fee.as_ref().filter(|fee| fee.fee_bps > 0)?;
+    let (_, owner) = platform_fee()?;
+    let output_mint = Pubkey::from_str(&quote.output_mint).ok()?;
+    let ata = spl_associated_token_account::get_associated_token_address(
+        &owner,
+        &output_mint,
+    );
+    Some(ata.to_string())
+}
+
+/// Accounts of the lookup tables at `addresses`
+async fn resolve_lookup_tables(
+    addresses: &[String],
+) -> Result<Vec<AddressLookupTableAccount>> {
+    if addresses.is_empty() {
+        return Ok(vec![]);
+    }
+    let keys = addresses
+        .iter()
+        .map(|address| Pubkey::from_str(address))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::Quote(format!("Invalid lookup table: {}", e)))?;
+    let accounts = solana_rpc()
+        .get_multiple_accounts(&keys)
+        .await
+        .map_err(Error::from)?;
+    keys.into_iter()
+        .zip(accounts)
+        .map(|(key, account)| -> Result<AddressLookupTableAccount> {
+            let account = account.ok_or_else(|| {
+                Error::Rpc(format!("Lookup table {} not found", key))
+            })?;
+            let table = AddressLookupTable::deserialize(&account.data)
+                .map_err(|e| {
+                    Error::Rpc(format!("Invalid lookup table {}: {}", key, e))
+                })?;
+            Ok(AddressLookupTableAccount {
+                key,
+                addresses: table.addresses.to_vec(),
+            })
+        })
+        .collect()
+}
+
+/// Whether `ix` creates the associated token account of `owner` for `mint`
+fn creates_ata(ix: &Instruction, owner: &Pubkey, mint: &Pubkey) -> bool {
+    let account =
+        |index: usize| ix.accounts.get(index).map(|meta| meta.pubkey);
+    ix.program_id == spl_associated_token_account::id()
+        && account(2) == Some(*owner)
+        && account(3) == Some(*mint)
+}
+
+/// Idempotent creation of the token account of `owner` for `mint` under
+/// `token_program`, None when a setup instruction of Jupiter creates it
+fn output_account_ix(
+    setup: &[Instruction],
+    owner: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Option<Instruction> {
+    (!setup.iter().any(|ix| creates_ata(ix, owner, mint))).then(|| {
+        create_associated_token_account_idempotent(
+            owner,
+            owner,
+            mint,
+            token_program,
+        )
+    })
+}
+
+/// Unsigned v0 transaction of `instructions` paid by `owner`, the accounts
+/// found in `lookup_tables` are loaded from them
+pub fn versioned_swap_tx(
+    owner: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+    blockhash: Hash,
+) -> Result<VersionedTransaction> {
+    let message = v0::Message::try_compile(
+        owner,
+        instructions,
+        lookup_tables,
+        blockhash,
+    )?;
+    let signatures = vec![
+        Signature::default();
+        message.header.num_required_signatures as usize
+    ];
+    Ok(VersionedTransaction {
+        signatures,
+        message: VersionedMessage::V0(message),
+    })
+}
+
+pub struct Jupiter {
+    api_url: String,
+    blockhashes: Arc<BlockhashCache>,
+}
+
+impl Default for Jupiter {
+    fn default() -> Self {
+        Self::new(JUPITER_API, BLOCKHASH_CACHE.clone())
+    }
+}
 
 impl Jupiter {
+    /// Client of the Jupiter API at `api_url`, the blockhashes of the swap
+    /// transactions come from `blockhashes`
+    pub fn new(api_url: &str, blockhashes: Arc<BlockhashCache>) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            blockhashes,
+        }
+    }
+
     pub async fn fetch_quote(
+        &self,
         input_mint: &str,
         output_mint: &str,
         amount: u64,
         slippage: u16,
     ) -> Result<QuoteResponse> {
-        let url = format!(
-            "https://quote-api.jup.ag/v6/quote?inputMint={}&outputMint={}&amount={}&slippageBps={}&asLegacyTransaction=true",
-            input_mint, output_mint, amount, slippage
+        let mut url = format!(
+            "{}/quote?inputMint={}&outputMint={}&amount={}&slippageBps={}&asLegacyTransaction=true",
+            self.api_url, input_mint, output_mint, amount, slippage
         );
+        if let Some((fee_bps, _)) = platform_fee() {
+            url.push_str(&format!("&platformFeeBps={}", fee_bps));
+        }
 
-        let response =
-            reqwest::get(&url).await?.json::<QuoteResponse>().await?;
-        Ok(response)
+        observe_api("jupiter", async {
+            let response =
+                send(http_client().get(&url), Retry::Idempotent).await?;
+            if !response.status().is_success() {
+                let error = response.text().await?;
+                return Err(Error::quote(format!("Jupiter Quote Error: {}", error)).into());
+            }
+            Ok(response.json::<QuoteResponse>().await?)
+        })
+        .await
     }
 
+    /// `priority_fee_lamports` is left to Jupiter when None
     pub async fn swap(
+        &self,
         quote_response: QuoteResponse,
         owner: &Pubkey,
+        priority_fee_lamports: Option<u64>,
     ) -> Result<Transaction> {
-        use solana_client::rpc_client::RpcClient;
-        use spl_associated_token_account::{
-            get_associated_token_address,
-            instruction::create_associated_token_account,
-        };
-        use spl_token::ID as TOKEN_PROGRAM_ID;
-        use std::env;
+        let (instructions, _) = self
+            .swap_instructions(quote_response, owner, priority_fee_lamports)
+            .await?;
+
+        // 🔥 10️⃣ Получаем свежий `blockhash`
+        let blockhash = self.blockhashes.get_blockhash().await?;
+
+        // ✅ 11️⃣ Создаём транзакцию и применяем blockhash
+        let mut tx = Transaction::new_with_payer(&instructions, Some(owner));
+        tx.message.recent_blockhash = blockhash;
+
+        Ok(tx)
+    }
+
+    /// Like `swap` but a v0 transaction using the address lookup tables of
+    /// the route, multi-hop routes are too large for a legacy transaction
+    pub async fn swap_versioned(
+        &self,
+        quote_response: QuoteResponse,
+        owner: &Pubkey,
+        priority_fee_lamports: Option<u64>,
+    ) -> Result<VersionedTransaction> {
+        let (instructions, lookup_tables) = self
+            .swap_instructions(quote_response, owner, priority_fee_lamports)
+            .await?;
+        let lookup_tables = resolve_lookup_tables(&lookup_tables).await?;
+        let blockhash = self.blockhashes.get_blockhash().await?;
+        versioned_swap_tx(owner, &instructions, &lookup_tables, blockhash)
+    }
+
+    /// Token program owning `mint`, spl-token or Token-2022
+    async fn token_program(&self, mint: &Pubkey) -> Result<Pubkey> {
+        let account = self
+            .blockhashes
+            .client()
+            .await
+            .get_account(mint)
+            .await
+            .map_err(Error::from)?;
+        Ok(account.owner)
+    }
+
+    /// Instructions of the swap and the lookup tables of the route
+    async fn swap_instructions(
+        &self,
+        quote_response: QuoteResponse,
+        owner: &Pubkey,
+        priority_fee_lamports: Option<u64>,
+    ) -> Result<(Vec<Instruction>, Vec<String>)> {
+        use spl_associated_token_account::get_associated_token_address_with_program_id;
         use solana_program::system_program;
         use std::str::FromStr;
     
-        let rpc_url = env::var("SOLANA_RPC_URL").unwrap_or_else(|_| {
-            "https://api.mainnet-beta.solana.com".to_string()
-        });
-    
-        let rpc_client = RpcClient::new(rpc_url);
-    
         // 🔥 1️⃣ Определяем mint входного и выходного токенов
         // let input_mint = Pubkey::from_str(&quote_response.input_mint)
         //     .map_err(|_| anyhow!("Invalid input mint"))?;
         let output_mint = Pubkey::from_str(&quote_response.output_mint)
-            .map_err(|_| anyhow!("Invalid output mint"))?;
+            .map_err(|_| Error::UserInput("Invalid output mint".to_string()))?;
     
         // 🔥 2️⃣ Проверяем, является ли входной или выходной токен SOL
         // let is_input_sol = input_mint == system_program::ID;
@@ -199,21 +428,25 @@ impl Jupiter {
         //     Some(get_associated_token_address(owner, &input_mint))
         // };
     
-        let output_ata = if is_output_sol {
+        // Token-2022 mints have their accounts under their own program
+        let output_program = if is_output_sol {
             None // SOL не использует ATA
         } else {
-            Some(get_associated_token_address(owner, &output_mint))
+            Some(self.token_program(&output_mint).await?)
         };
+        let output_ata = output_program.map(|program| {
+            get_associated_token_address_with_program_id(owner, &output_mint, &program)
+        });
     
         // 🔥 5️⃣ Запрашиваем swap-инструкции у Jupiter
         let swap_request = SwapRequest {
             user_public_key: owner.to_string(),
             wrap_and_unwrap_sol: is_output_sol, // Jupiter сам оборачивает SOL
             use_shared_accounts: false,
-            fee_account: None,
+            fee_account: fee_account(&quote_response),
             tracking_account: None,
             compute_unit_price_micro_lamports: None,
-            prioritization_fee_lamports: None,
+            prioritization_fee_lamports: priority_fee_lamports,
             as_legacy_transaction: false,
             use_token_ledger: false,
             destination_token_account: output_ata.map(|ata| ata.to_string()), // None, если SOL
@@ -223,27 +456,55 @@ impl Jupiter {
             quote_response,
         };
     
-        let client = reqwest::Client::new();
-        let raw_res = client
-            .post("https://quote-api.jup.ag/v6/swap-instructions")
-            .json(&swap_request)
-            .send()
+        let client = http_client();
+        let url = format!("{}/swap-instructions", self.api_url);
+        let response = observe_api("jupiter", async {
+            // only builds the instructions, nothing is sent
+            let raw_res = send(
+                client.post(&url).json(&swap_request),
+                Retry::Idempotent,
+            )
             .await?;
-    
-        if !raw_res.status().is_success() {
-            let error = raw_res.text().await.map_err(|e| anyhow!(e))?;
-            return Err(anyhow!("Jupiter Swap Error: {}", error));
+
+            if !raw_res.status().is_success() {
+                let error = raw_res.text().await?;
+                return Err(Error::quote(format!("Jupiter Swap Error: {}", error)).into());
+            }
+
+            raw_res
+                .json::<SwapInstructionsResponse>()
+                .await
+                .map_err(|e| {
+                    Error::Quote(format!("Failed to parse swap response: {}", e))
+                        .into()
+                })
+        })
+        .await?;
+
+        // the priority fee is paid through the compute budget instructions
+        if priority_fee_lamports.is_some() {
+            for compute_ix in response.compute_budget_instructions.unwrap_or_default() {
+                instructions.push(Self::convert_instruction_data(compute_ix)?);
+            }
         }
     
-        let response = raw_res
-            .json::<SwapInstructionsResponse>()
-            .await
-            .map_err(|e| anyhow!("Failed to parse swap response: {}", e))?;
-    
+        let setup_ixs = response
+            .setup_instructions
+            .into_iter()
+            .map(Self::convert_instruction_data)
+            .collect::<Result<Vec<_>>>()?;
+
+        // the output token account is created in the swap transaction, a
+        // separate one would cost a fee and could land without the swap.
+        // The instruction is idempotent, Jupiter usually sets it up already
+        if let Some(program) = output_program {
+            instructions.extend(output_account_ix(&setup_ixs, owner, &output_mint, &program));
+        }
+
         // 🔥 6️⃣ Выполняем setupInstrductions (создание необходимых аккаунтов)
-        for setup_ix in response.setup_instructions {
-            println!("✅ Добавляем setup-инструкцию: {:?}", setup_ix);
-            instructions.push(Self::convert_instruction_data(setup_ix)?);
+        for setup_ix in setup_ixs {
+            tracing::debug!(?setup_ix, "adding setup instruction");
+            instructions.push(setup_ix);
         }
     
         // 🔥 7️⃣ Добавляем основную swap-инструкцию
@@ -261,14 +522,7 @@ impl Jupiter {
         //     instructions.push(Self::convert_instruction_data(cleanup_ix)?);
         // }
     
-        // 🔥 10️⃣ Получаем свежий `blockhash`
-        let blockhash = rpc_client.get_latest_blockhash()?;
-    
-        // ✅ 11️⃣ Создаём транзакцию и применяем blockhash
-        let mut tx = Transaction::new_with_payer(&instructions, Some(owner));
-        tx.message.recent_blockhash = blockhash;
-    
-        Ok(tx)
+        Ok((instructions, response.address_lookup_table_addresses))
     }    
 
     // pub async fn swap(
@@ -487,7 +741,7 @@ impl Jupiter {
     //     Ok(tx)
     // }
 
-    fn convert_instruction_data(
+    pub(crate) fn convert_instruction_data(
         ix_data: InstructionData,
     ) -> Result<solana_sdk::instruction::Instruction> {
         let program_id = Pubkey::from_str(&ix_data.program_id)?;
@@ -512,4 +766,198 @@ impl Jupiter {
             data,
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::fixtures::{fixture, mock_latest_blockhash, OWNER};
+    use crate::snapshot::{assert_snapshot, render_message};
+    use crate::tool_error::{ErrorCode, ToolError};
+
+    const WSOL: &str = "So11111111111111111111111111111111111111112";
+    const USDC: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+    /// Jupiter and the Solana RPC served by the same mock server
+    fn jupiter(server: &MockServer) -> Jupiter {
+        let blockhashes = BlockhashCache::new(&server.uri());
+        Jupiter::new(&server.uri(), Arc::new(blockhashes))
+    }
+
+    /// The USDC mint, an spl-token mint
+    async fn mock_mint(server: &MockServer) {
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": "getAccountInfo" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "result": {
+                    "context": { "slot": 1 },
+                    "value": {
+                        "data": ["", "base64"],
+                        "executable": false,
+                        "lamports": 1461600,
+                        "owner": spl_token::ID.to_string(),
+                        "rentEpoch": 0,
+                        "space": 82,
+                    },
+                },
+            })))
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_quote_and_swap() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/quote"))
+            .and(query_param("inputMint", WSOL))
+            .and(query_param("outputMint", USDC))
+            .and(query_param("amount", "1000000"))
+            .and(query_param("slippageBps", "50"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(fixture("jupiter/quote")))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/swap-instructions"))
+            .and(body_partial_json(json!({
+                "userPublicKey": OWNER,
+                "prioritizationFeeLamports": 5000,
+                "quoteResponse": { "inputMint": WSOL, "outAmount": "236512" },
+            })))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(fixture("jupiter/swap_instructions")),
+            )
+            .mount(&server)
+            .await;
+        mock_latest_blockhash(&server).await;
+        mock_mint(&server).await;
+
+        let jupiter = jupiter(&server);
+        let quote = jupiter.fetch_quote(WSOL, USDC, 1_000_000, 50).await.unwrap();
+        assert_eq!(quote.out_amount, "236512");
+        assert_eq!(quote.summary()["route"][0], "Whirlpool (100%)");
+
+        let owner = Pubkey::from_str(OWNER).unwrap();
+        let tx = jupiter.swap(quote, &owner, Some(5000)).await.unwrap();
+        // two compute budget, the ATA setup and the swap instructions
+        assert_eq!(tx.message.instructions.len(), 4);
+        assert_eq!(tx.message.account_keys[0], owner);
+        assert_eq!(
+            tx.message.recent_blockhash.to_string(),
+            "8xzrcjogDrqqjV6RNYjo77eERX1n6EtGu4FXgUMUZoyV"
+        );
+        assert_snapshot("jupiter_swap", &render_message(&tx.message));
+
+        // the route has no lookup tables, the v0 message has the same keys
+        let quote = serde_json::from_value(fixture("jupiter/quote")).unwrap();
+        let versioned = jupiter.swap_versioned(quote, &owner, Some(5000)).await.unwrap();
+        let VersionedMessage::V0(message) = &versioned.message else {
+            panic!("not a v0 message");
+        };
+        assert_eq!(message.account_keys, tx.message.account_keys);
+        assert_eq!(message.instructions, tx.message.instructions);
+        assert!(message.address_table_lookups.is_empty());
+        assert_eq!(versioned.signatures.len(), 1);
+    }
+
+    #[test]
+    fn test_versioned_swap_tx() {
+        let owner = Pubkey::from_str(OWNER).unwrap();
+        let pool = Pubkey::new_unique();
+        let vault = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let ix = Instruction::new_with_bytes(
+            program,
+            &[1, 2, 3],
+            vec![
+                solana_sdk::instruction::AccountMeta::new(owner, true),
+                solana_sdk::instruction::AccountMeta::new(pool, false),
+                solana_sdk::instruction::AccountMeta::new_readonly(vault, false),
+            ],
+        );
+        let table = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: vec![vault, pool],
+        };
+        let tx = versioned_swap_tx(&owner, &[ix], &[table.clone()], Hash::default())
+            .unwrap();
+        let VersionedMessage::V0(message) = &tx.message else {
+            panic!("not a v0 message");
+        };
+        // the pool and the vault are loaded from the table
+        assert_eq!(message.account_keys, vec![owner, program]);
+        assert_eq!(message.address_table_lookups.len(), 1);
+        let lookup = &message.address_table_lookups[0];
+        assert_eq!(lookup.account_key, table.key);
+        assert_eq!(lookup.writable_indexes, vec![1]);
+        assert_eq!(lookup.readonly_indexes, vec![0]);
+        assert_eq!(tx.signatures, vec![Signature::default()]);
+    }
+
+    #[test]
+    fn test_creates_ata() {
+        let owner = Pubkey::from_str(OWNER).unwrap();
+        let usdc = Pubkey::from_str(USDC).unwrap();
+        let wsol = Pubkey::from_str(WSOL).unwrap();
+        let ix = create_associated_token_account_idempotent(&owner, &owner, &usdc, &spl_token::ID);
+        assert!(creates_ata(&ix, &owner, &usdc));
+        assert!(!creates_ata(&ix, &owner, &wsol));
+        // the setup of the fixture creates the USDC account already
+        let setup: InstructionData =
+            serde_json::from_value(fixture("jupiter/swap_instructions")["setupInstructions"][0].clone())
+                .unwrap();
+        let setup = Jupiter::convert_instruction_data(setup).unwrap();
+        assert!(creates_ata(&setup, &owner, &usdc));
+        assert!(output_account_ix(&[setup], &owner, &usdc, &spl_token::ID).is_none());
+
+        // Jupiter left the account out, it is created under the program of
+        // the mint
+        let ix = output_account_ix(&[], &owner, &usdc, &spl_token_2022::ID).unwrap();
+        assert!(creates_ata(&ix, &owner, &usdc));
+        assert_eq!(ix.accounts[5].pubkey, spl_token_2022::ID);
+        assert_eq!(
+            ix.accounts[1].pubkey,
+            spl_associated_token_account::get_associated_token_address_with_program_id(
+                &owner,
+                &usdc,
+                &spl_token_2022::ID,
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/quote"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "error": "The token is not tradable, it was not found",
+                "errorCode": "TOKEN_NOT_TRADABLE",
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/swap-instructions"))
+            .respond_with(ResponseTemplate::new(422).set_body_json(json!({
+                "error": "Slippage tolerance exceeded",
+            })))
+            .mount(&server)
+            .await;
+        mock_mint(&server).await;
+
+        let jupiter = jupiter(&server);
+        let error = jupiter.fetch_quote(WSOL, OWNER, 1_000_000, 50).await.unwrap_err();
+        assert_eq!(ToolError::from_anyhow(&error).code, ErrorCode::NotFound);
+
+        let quote = serde_json::from_value(fixture("jupiter/quote")).unwrap();
+        let owner = Pubkey::from_str(OWNER).unwrap();
+        let error = jupiter.swap(quote, &owner, None).await.unwrap_err();
+        assert_eq!(ToolError::from_anyhow(&error).code, ErrorCode::SlippageExceeded);
+    }
+}
@@ -0,0 +1,708 @@
+//! Feed of new pump.fun launches. Users watch launches with
+//! `watch_pump_launches` and a filter (the creator, a pattern of the name or
+//! symbol, the SOL the creator bought at launch), a background task follows
+//! the logs of the pump.fun program over the websocket of the Solana RPC
+//! (deposits.solana_ws) while launches.enabled and sends every launch
+//! matching a watch to the notification sinks. A watch can also snipe: buy
+//! the launch right away with buy_pump_fun_token under the signer of its
+//! owner, capped by launches.max_snipe_sol and launches.max_snipes_per_day
+//! on top of the risk limits of the user
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use borsh::BorshDeserialize;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use regex::{Regex, RegexBuilder};
+use rig::agent::Agent;
+use rig::providers::anthropic::completion::CompletionModel;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_client::rpc_config::{
+    RpcTransactionLogsConfig, RpcTransactionLogsFilter,
+};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::native_token::lamports_to_sol;
+use solana_sdk::pubkey::Pubkey;
+
+use super::constants::PUMP_FUN_PROGRAM;
+use crate::config::config;
+use crate::confirmation::confirm_or_execute;
+use crate::error::Error;
+use crate::kv_store::{namespaced, update, KVStore};
+use crate::notify::{Notification, NotificationKind, NOTIFIER};
+use crate::scheduler::run_tool_as;
+use crate::signer::{SignerContext, SignerRef, SignerResolver};
+use crate::tool_error::ToolError;
+use crate::validation;
+
+const WATCHES_KEY: &str = "watches";
+const MAX_WATCHES_PER_USER: usize = 10;
+/// Daily snipe counts are kept a little longer than the day they count
+const SNIPES_TTL: Duration = Duration::from_secs(2 * 24 * 3600);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const CREATE_LOG: &str = "Program log: Instruction: Create";
+const EVENT_LOG: &str = "Program data: ";
+
+/// Anchor event of the create instruction, newer program versions append
+/// fields that are not read
+#[derive(BorshDeserialize)]
+struct CreateEvent {
+    name: String,
+    symbol: String,
+    uri: String,
+    mint: [u8; 32],
+    bonding_curve: [u8; 32],
+    user: [u8; 32],
+}
+
+/// Anchor event of the buy and sell instructions, leading fields only
+#[derive(BorshDeserialize)]
+struct TradeEvent {
+    mint: [u8; 32],
+    sol_amount: u64,
+    _token_amount: u64,
+    is_buy: bool,
+    user: [u8; 32],
+}
+
+fn event_discriminator(name: &str) -> [u8; 8] {
+    let hash = Sha256::digest(format!("event:{}", name));
+    hash[..8].try_into().expect("sha256 has 32 bytes")
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Launch {
+    pub signature: String,
+    pub mint: String,
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub creator: String,
+    pub bonding_curve: String,
+    /// SOL the creator bought the token with in the launch transaction
+    pub initial_buy_sol: f64,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// Launch of the logs of a pump.fun transaction, None for the other
+/// instructions
+pub fn parse_launch(signature: &str, logs: &[String]) -> Option<Launch> {
+    if !logs.iter().any(|log| log == CREATE_LOG) {
+        return None;
+    }
+    let events = logs
+        .iter()
+        .filter_map(|log| log.strip_prefix(EVENT_LOG))
+        .filter_map(|data| BASE64_STANDARD.decode(data).ok())
+        .filter(|data| data.len() > 8)
+        .collect::<Vec<_>>();
+    let (create_event, trade_event) = (
+        event_discriminator("CreateEvent"),
+        event_discriminator("TradeEvent"),
+    );
+    let create = events
+        .iter()
+        .filter(|data| data[..8] == create_event)
+        .find_map(|data| CreateEvent::deserialize(&mut &data[8..]).ok())?;
+    let initial_buy = events
+        .iter()
+        .filter(|data| data[..8] == trade_event)
+        .filter_map(|data| TradeEvent::deserialize(&mut &data[8..]).ok())
+        .filter(|trade| {
+            trade.is_buy
+                && trade.mint == create.mint
+                && trade.user == create.user
+        })
+        .map(|trade| trade.sol_amount)
+        .sum();
+    Some(Launch {
+        signature: signature.to_string(),
+        mint: Pubkey::new_from_array(create.mint).to_string(),
+        name: create.name,
+        symbol: create.symbol,
+        uri: create.uri,
+        creator: Pubkey::new_from_array(create.user).to_string(),
+        bonding_curve: Pubkey::new_from_array(create.bonding_curve)
+            .to_string(),
+        initial_buy_sol: lamports_to_sol(initial_buy),
+        detected_at: Utc::now(),
+    })
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LaunchFilter {
+    /// wallet of the creator
+    pub creator: Option<String>,
+    /// case-insensitive regex matched against the name and the symbol
+    pub name_pattern: Option<String>,
+    /// bounds of the SOL the creator bought at launch, included
+    pub min_initial_buy_sol: Option<f64>,
+    pub max_initial_buy_sol: Option<f64>,
+}
+
+impl LaunchFilter {
+    fn validate(&self) -> Result<()> {
+        if let Some(creator) = &self.creator {
+            validation::address("sol", "creator", creator)?;
+        }
+        self.pattern()?;
+        Ok(())
+    }
+
+    /// Compiled name_pattern, case-insensitive
+    fn pattern(&self) -> Result<Option<Regex>> {
+        let Some(pattern) = &self.name_pattern else {
+            return Ok(None);
+        };
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| {
+                ToolError::invalid_input(format!(
+                    "Invalid name_pattern: {}",
+                    e
+                ))
+            })?;
+        Ok(Some(regex))
+    }
+
+    /// Filters set by the user, a snipe needs one of them
+    fn is_narrow(&self) -> bool {
+        self.creator.is_some() || self.name_pattern.is_some()
+    }
+
+    /// Whether the launch passes the filter, `pattern` is the compiled
+    /// name_pattern (None when it doesn't compile, nothing matches then)
+    pub fn matches(&self, launch: &Launch, pattern: Option<&Regex>) -> bool {
+        if let Some(creator) = &self.creator {
+            if *creator != launch.creator {
+                return false;
+            }
+        }
+        if self.name_pattern.is_some() {
+            let matched = pattern.is_some_and(|re| {
+                re.is_match(&launch.name) || re.is_match(&launch.symbol)
+            });
+            if !matched {
+                return false;
+            }
+        }
+        self.min_initial_buy_sol
+            .map_or(true, |min| launch.initial_buy_sol >= min)
+            && self
+                .max_initial_buy_sol
+                .map_or(true, |max| launch.initial_buy_sol <= max)
+    }
+}
+
+/// Buy of the launches matching a watch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snipe {
+    pub sol_amount: f64,
+    pub slippage_bps: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LaunchWatch {
+    pub id: String,
+    pub owner: SignerRef,
+    pub filter: LaunchFilter,
+    pub snipe: Option<Snipe>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct Launches {
+    store: Arc<dyn KVStore>,
+    /// watches are stored as a single value, writes have to be serialized
+    lock: tokio::sync::Mutex<()>,
+    /// name patterns of the watches compiled once, not for every launch
+    patterns: Mutex<HashMap<String, Option<Regex>>>,
+}
+
+pub static LAUNCHES: Lazy<Launches> =
+    Lazy::new(|| Launches::new(Arc::new(namespaced("launches"))));
+
+impl Launches {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self {
+            store,
+            lock: tokio::sync::Mutex::new(()),
+            patterns: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn load(&self) -> Result<Vec<LaunchWatch>> {
+        match self.store.get(WATCHES_KEY).await? {
+            Some(watches) => Ok(serde_json::from_str(&watches)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    async fn save(&self, watches: &[LaunchWatch]) -> Result<()> {
+        self.store
+            .set(WATCHES_KEY, &serde_json::to_string(watches)?)
+            .await
+    }
+
+    pub async fn add(&self, watch: LaunchWatch) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut watches = self.load().await?;
+        let owned = watches
+            .iter()
+            .filter(|w| w.owner.same_user(&watch.owner))
+            .count();
+        if owned >= MAX_WATCHES_PER_USER {
+            return Err(ToolError::invalid_input(format!(
+                "A user can have {} launch watches",
+                MAX_WATCHES_PER_USER
+            ))
+            .with_hint("Stop watching launches the user no longer needs")
+            .into());
+        }
+        watches.push(watch);
+        self.save(&watches).await
+    }
+
+    pub async fn list(&self, owner: &SignerRef) -> Result<Vec<LaunchWatch>> {
+        Ok(self
+            .load()
+            .await?
+            .into_iter()
+            .filter(|watch| watch.owner.same_user(owner))
+            .collect())
+    }
+
+    pub async fn remove(
+        &self,
+        id: &str,
+        owner: &SignerRef,
+    ) -> Result<LaunchWatch> {
+        let _guard = self.lock.lock().await;
+        let mut watches = self.load().await?;
+        let index = watches
+            .iter()
+            .position(|w| w.id == id && w.owner.same_user(owner))
+            .ok_or_else(|| anyhow!("Launch watch {} not found", id))?;
+        let watch = watches.remove(index);
+        self.save(&watches).await?;
+        Ok(watch)
+    }
+
+    /// Counts a snipe of the owner, fails once they made `max_per_day`
+    /// today. The counter is compared and set, concurrent snipes can't
+    /// both take the last one
+    async fn take_snipe(
+        &self,
+        owner: &SignerRef,
+        max_per_day: u32,
+    ) -> Result<()> {
+        let key = format!(
+            "snipes:{}:{}",
+            owner.user_id.as_deref().unwrap_or("local"),
+            Utc::now().format("%Y-%m-%d")
+        );
+        update(self.store.as_ref(), &key, Some(SNIPES_TTL), |snipes| {
+            let snipes = snipes.and_then(|s| s.parse::<u32>().ok());
+            let snipes = snipes.unwrap_or(0);
+            if snipes >= max_per_day {
+                return Err(anyhow!(
+                    "The {} snipes of the day were made",
+                    max_per_day
+                ));
+            }
+            Ok((snipes + 1).to_string())
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Buys the launch for the owner of the watch within the limits of the
+    /// config, which may have been lowered since the watch was set
+    async fn snipe(
+        &self,
+        watch: &LaunchWatch,
+        snipe: &Snipe,
+        launch: &Launch,
+        agents: &[Arc<Agent<CompletionModel>>],
+        resolver: &dyn SignerResolver,
+    ) -> Result<String> {
+        let settings = config().launches.clone();
+        if snipe.sol_amount > settings.max_snipe_sol {
+            return Err(anyhow!(
+                "The snipe of {} SOL is over the cap of {} SOL",
+                snipe.sol_amount,
+                settings.max_snipe_sol
+            ));
+        }
+        self.take_snipe(&watch.owner, settings.max_snipes_per_day)
+            .await?;
+        let args = json!({
+            "mint": launch.mint,
+            "sol_amount": snipe.sol_amount,
+            "slippage_bps": snipe.slippage_bps,
+        });
+        run_tool_as(
+            &watch.owner,
+            "buy_pump_fun_token",
+            &args,
+            agents,
+            resolver,
+        )
+        .await
+    }
+
+    /// Compiled name pattern of the watches, the patterns of the watches
+    /// that are gone are dropped
+    fn patterns(&self, watches: &[LaunchWatch]) -> Vec<Option<Regex>> {
+        let mut patterns = self.patterns.lock().unwrap();
+        patterns.retain(|pattern, _| {
+            watches
+                .iter()
+                .any(|w| w.filter.name_pattern.as_ref() == Some(pattern))
+        });
+        watches
+            .iter()
+            .map(|watch| {
+                let pattern = watch.filter.name_pattern.as_ref()?;
+                patterns
+                    .entry(pattern.clone())
+                    .or_insert_with(|| watch.filter.pattern().ok().flatten())
+                    .clone()
+            })
+            .collect()
+    }
+
+    /// Notifies the owners of the watches matching the launch. The watches
+    /// that snipe buy it first, each in a task of its own so a slow buy
+    /// holds up neither the feed nor the other watches
+    async fn deliver(
+        &'static self,
+        launch: &Launch,
+        agents: &Arc<Vec<Arc<Agent<CompletionModel>>>>,
+        resolver: &Arc<dyn SignerResolver>,
+    ) -> Result<()> {
+        let watches = self.load().await?;
+        let patterns = self.patterns(&watches);
+        for (watch, pattern) in watches.into_iter().zip(patterns) {
+            if !watch.filter.matches(launch, pattern.as_ref()) {
+                continue;
+            }
+            let Some(snipe) = watch.snipe.clone() else {
+                notify_launch(&watch, launch, None).await;
+                continue;
+            };
+            let (launch, agents, resolver) =
+                (launch.clone(), agents.clone(), resolver.clone());
+            tokio::spawn(async move {
+                let result = self
+                    .snipe(
+                        &watch,
+                        &snipe,
+                        &launch,
+                        &agents,
+                        resolver.as_ref(),
+                    )
+                    .await;
+                tracing::info!(
+                    watch = %watch.id,
+                    mint = %launch.mint,
+                    ok = result.is_ok(),
+                    "launch sniped"
+                );
+                notify_launch(&watch, &launch, Some((&snipe, result))).await;
+            });
+        }
+        Ok(())
+    }
+
+    /// Follows the logs of the pump.fun program until the subscription
+    /// drops or launches.enabled is turned off
+    async fn follow(
+        &'static self,
+        agents: &Arc<Vec<Arc<Agent<CompletionModel>>>>,
+        resolver: &Arc<dyn SignerResolver>,
+    ) -> Result<()> {
+        let ws_url = {
+            let config = config();
+            config.deposits.solana_ws_url(&config.rpc)
+        };
+        let client = PubsubClient::new(&ws_url)
+            .await
+            .map_err(|e| Error::Rpc(e.to_string()))?;
+        let (mut logs, _unsubscribe) = client
+            .logs_subscribe(
+                RpcTransactionLogsFilter::Mentions(vec![
+                    PUMP_FUN_PROGRAM.to_string()
+                ]),
+                RpcTransactionLogsConfig {
+                    commitment: Some(CommitmentConfig::confirmed()),
+                },
+            )
+            .await
+            .map_err(|e| Error::Rpc(e.to_string()))?;
+        while let Some(log) = logs.next().await {
+            if !config().launches.enabled {
+                return Ok(());
+            }
+            if log.value.err.is_some() {
+                continue;
+            }
+            let Some(launch) =
+                parse_launch(&log.value.signature, &log.value.logs)
+            else {
+                continue;
+            };
+            if let Err(e) = self.deliver(&launch, agents, resolver).await {
+                let mint = &launch.mint;
+                tracing::warn!(?e, %mint, "launch not delivered");
+            }
+        }
+        Err(anyhow!("pump.fun log subscription closed"))
+    }
+
+    /// Follows the launches while launches.enabled, reconnecting when the
+    /// subscription drops. Snipes run the buy tool looked up in the given
+    /// agents
+    pub fn spawn(
+        &'static self,
+        agents: Vec<Arc<Agent<CompletionModel>>>,
+        resolver: Arc<dyn SignerResolver>,
+    ) -> tokio::task::JoinHandle<()> {
+        let agents = Arc::new(agents);
+        tokio::spawn(async move {
+            loop {
+                if config().launches.enabled {
+                    if let Err(e) = self.follow(&agents, &resolver).await {
+                        tracing::warn!(?e, "launch feed reconnecting");
+                    }
+                }
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        })
+    }
+}
+
+/// Notifies the owner of the watch of the launch, with the result of the
+/// snipe of watches that snipe
+async fn notify_launch(
+    watch: &LaunchWatch,
+    launch: &Launch,
+    sniped: Option<(&Snipe, Result<String>)>,
+) {
+    let mut data = json!({ "watch": watch.id, "launch": launch });
+    let mut body = format!(
+        "{} launched {} with an initial buy of {} SOL",
+        launch.creator, launch.mint, launch.initial_buy_sol
+    );
+    if let Some((snipe, result)) = sniped {
+        body = match &result {
+            Ok(result) => format!(
+                "{}, bought for {} SOL: {}",
+                body, snipe.sol_amount, result
+            ),
+            Err(e) => format!("{}, not bought: {}", body, e),
+        };
+        data["snipe"] = match result {
+            Ok(result) => json!({ "result": result }),
+            Err(e) => json!({ "error": e.to_string() }),
+        };
+    }
+    let notification = Notification::new(
+        NotificationKind::Launch,
+        watch.owner.user_id.clone(),
+        format!("New pump.fun token {} ({})", launch.name, launch.symbol),
+        body,
+    )
+    .with_data(data);
+    NOTIFIER.notify(&notification).await;
+}
+
+async fn current_owner() -> SignerRef {
+    SignerRef::of(SignerContext::current().await.as_ref())
+}
+
+#[tool(description = "
+Watches new pump.fun launches for the user, every launch matching the filter
+notifies them. Empty filters match every launch.
+
+creator is the wallet of the creator, empty for any
+name_pattern is a case-insensitive regex matched against the name and the
+symbol, e.g. ^pepe, empty for any
+min_initial_buy_sol and max_initial_buy_sol bound the SOL the creator bought
+at launch, 0 for no bound
+
+snipe_sol_amount > 0 also buys every matching launch right away for that much
+SOL (with slippage_bps, 0 uses the default slippage of the session), without
+asking again. Sniping needs a creator or a name_pattern, is capped by the
+operator and requires the user's confirmation. Meme coins at launch are
+extremely risky, make sure the user understands it
+")]
+pub async fn watch_pump_launches(
+    creator: String,
+    name_pattern: String,
+    min_initial_buy_sol: f64,
+    max_initial_buy_sol: f64,
+    snipe_sol_amount: f64,
+    slippage_bps: u16,
+) -> Result<String> {
+    let settings = config().launches.clone();
+    if !settings.enabled {
+        return Err(anyhow!("The pump.fun launch feed is disabled"));
+    }
+    let bound = |sol: f64| (sol > 0.).then_some(sol);
+    let filter = LaunchFilter {
+        creator: Some(creator.trim().to_string()).filter(|c| !c.is_empty()),
+        name_pattern: Some(name_pattern.trim().to_string())
+            .filter(|p| !p.is_empty()),
+        min_initial_buy_sol: bound(min_initial_buy_sol),
+        max_initial_buy_sol: bound(max_initial_buy_sol),
+    };
+    filter.validate()?;
+    let signer = SignerContext::current().await;
+    let mut watch = LaunchWatch {
+        id: format!("{:016x}", rand::random::<u64>()),
+        owner: SignerRef::of(signer.as_ref()),
+        filter,
+        snipe: None,
+        created_at: Utc::now(),
+    };
+    if snipe_sol_amount <= 0. {
+        LAUNCHES.add(watch.clone()).await?;
+        return Ok(serde_json::to_string(&watch)?);
+    }
+
+    if snipe_sol_amount > settings.max_snipe_sol {
+        return Err(ToolError::invalid_input(format!(
+            "Snipes are capped at {} SOL",
+            settings.max_snipe_sol
+        ))
+        .with_hint("Use a smaller snipe_sol_amount or watch without sniping")
+        .into());
+    }
+    if !watch.filter.is_narrow() {
+        return Err(ToolError::invalid_input(
+            "Sniping every launch is not allowed",
+        )
+        .with_hint("Ask the user for a creator or a name_pattern")
+        .into());
+    }
+    signer.tool_access().check("buy_pump_fun_token")?;
+    let slippage_bps = crate::preferences::slippage_bps(slippage_bps).await?;
+    validation::slippage_bps(slippage_bps)?;
+    watch.snipe = Some(Snipe {
+        sol_amount: snipe_sol_amount,
+        slippage_bps,
+    });
+    let summary = format!(
+        "Buy every pump.fun launch matching {} for {} SOL (slippage {} bps), \
+         at most {} a day",
+        json!(watch.filter),
+        snipe_sol_amount,
+        slippage_bps,
+        settings.max_snipes_per_day
+    );
+    confirm_or_execute("watch_pump_launches", summary, move || async move {
+        LAUNCHES.add(watch.clone()).await?;
+        Ok(serde_json::to_string(&watch)?)
+    })
+    .await
+}
+
+#[tool(description = "
+Lists the pump.fun launch watches of the user with their filters and snipes
+")]
+pub async fn list_pump_launch_watches() -> Result<Vec<LaunchWatch>> {
+    LAUNCHES.list(&current_owner().await).await
+}
+
+#[tool(description = "
+Stops a pump.fun launch watch (and its snipes)
+")]
+pub async fn stop_watching_pump_launches(id: String) -> Result<String> {
+    let watch = LAUNCHES.remove(&id, &current_owner().await).await?;
+    Ok(format!("Stopped launch watch {}", watch.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+    use borsh::BorshSerialize;
+
+    fn event(name: &str, fields: impl BorshSerialize) -> String {
+        let mut data = event_discriminator(name).to_vec();
+        fields.serialize(&mut data).unwrap();
+        format!("{}{}", EVENT_LOG, BASE64_STANDARD.encode(data))
+    }
+
+    #[test]
+    fn test_parse_launch() {
+        let (mint, creator) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let logs = vec![
+            CREATE_LOG.to_string(),
+            event(
+                "CreateEvent",
+                (
+                    "Pepe Moon".to_string(),
+                    "PMOON".to_string(),
+                    "https://ipfs.io/1".to_string(),
+                    mint.to_bytes(),
+                    Pubkey::new_unique().to_bytes(),
+                    creator.to_bytes(),
+                ),
+            ),
+            event(
+                "TradeEvent",
+                (
+                    mint.to_bytes(),
+                    1_500_000_000u64,
+                    1u64,
+                    true,
+                    creator.to_bytes(),
+                ),
+            ),
+        ];
+        let launch = parse_launch("sig", &logs).unwrap();
+        assert_eq!(launch.mint, mint.to_string());
+        assert_eq!(launch.creator, creator.to_string());
+        assert_eq!(launch.initial_buy_sol, 1.5);
+        assert!(parse_launch("sig", &logs[1..]).is_none());
+
+        let filter = LaunchFilter {
+            name_pattern: Some("^pepe".to_string()),
+            min_initial_buy_sol: Some(1.),
+            ..Default::default()
+        };
+        let pattern = filter.pattern().unwrap();
+        assert!(filter.matches(&launch, pattern.as_ref()));
+        assert!(!filter.matches(&launch, None));
+        let filter = LaunchFilter {
+            creator: Some(mint.to_string()),
+            ..filter
+        };
+        assert!(!filter.matches(&launch, pattern.as_ref()));
+    }
+
+    #[tokio::test]
+    async fn test_take_snipe() {
+        let launches = Launches::new(Arc::new(InMemoryKVStore::new()));
+        let owner = SignerRef {
+            user_id: Some("alice".to_string()),
+            session_id: None,
+        };
+        let taken = futures::future::join_all(
+            (0..5).map(|_| launches.take_snipe(&owner, 3)),
+        )
+        .await;
+        assert_eq!(taken.iter().filter(|taken| taken.is_ok()).count(), 3);
+    }
+}
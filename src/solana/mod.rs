@@ -1,16 +1,29 @@
 pub mod agent;
+pub mod anchor;
 pub mod balance;
 pub mod blockhash;
+pub mod congestion;
 pub mod constants;
 pub mod data;
 pub mod deploy_token;
+pub mod explain;
+pub mod faucet;
 pub mod jup;
+pub mod launches;
+pub mod okx;
+pub mod prefetch;
 pub mod price;
+pub mod priority_fees;
 pub mod pump;
+pub mod raydium;
+pub mod rent;
+pub mod savings;
 pub mod scan;
+pub mod simulate;
 pub mod tools;
 pub mod trade;
 pub mod trade_pump;
 pub mod transaction;
 pub mod transfer;
 pub mod util;
+pub mod venues;
@@ -0,0 +1,432 @@
+//! Client of the OKX DEX aggregator API on Solana, quoted next to Jupiter
+//! and Raydium, see `venues`. Requests are signed with the API credentials
+//! of venues.okx_*. The swap is built from the instructions OKX returns, as
+//! a legacy transaction like the Jupiter swaps
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::Result;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use chrono::{SecondsFormat, Utc};
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use solana_sdk::compute_budget::{self, ComputeBudgetInstruction};
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::Transaction;
+
+use super::blockhash::{BlockhashCache, BLOCKHASH_CACHE};
+use super::jup::{InstructionData, Jupiter};
+use crate::common::http_client;
+use crate::config::config;
+use crate::error::Error;
+use crate::metrics::observe_api;
+
+const OKX_API: &str = "https://web3.okx.com";
+const SOLANA_CHAIN_ID: &str = "501";
+/// Compute unit limit of the swaps, the priority fee of the session is
+/// spread over it
+const SWAP_COMPUTE_UNITS: u32 = 600_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OkxToken {
+    pub token_contract_address: String,
+    pub token_symbol: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OkxQuote {
+    pub from_token: OkxToken,
+    pub to_token: OkxToken,
+    pub from_token_amount: String,
+    pub to_token_amount: String,
+    pub price_impact_percentage: Option<String>,
+    /// routes split over the DEXes, only read for the summary
+    #[serde(default)]
+    pub dex_router_list: Vec<Value>,
+    /// the slippage the quote was asked with, the swap is built with it
+    #[serde(skip)]
+    pub slippage_bps: u16,
+}
+
+impl OkxQuote {
+    /// Least output the swap accepts, the quoted output less the slippage
+    /// rounded down. A slippage of 100% or more accepts nothing
+    pub fn min_out_amount(&self) -> u64 {
+        let out = self.to_token_amount.parse::<u64>().unwrap_or_default();
+        let kept = 10_000u128.saturating_sub(self.slippage_bps as u128);
+        // at most `out`, it fits
+        (out as u128 * kept / 10_000) as u64
+    }
+
+    pub fn summary(&self) -> Value {
+        let mut route = vec![];
+        for router in &self.dex_router_list {
+            collect_dex_names(router, &mut route);
+        }
+        route.dedup();
+        json!({
+            "from": {
+                "mint": self.from_token.token_contract_address,
+                "amount": self.from_token_amount,
+            },
+            "to": {
+                "mint": self.to_token.token_contract_address,
+                "amount": self.to_token_amount,
+                "amount_min": self.min_out_amount().to_string(),
+            },
+            "costs": {
+                "platform_fee": null,
+                "price_impact_pct": self.price_impact_percentage,
+            },
+            "slippage_bps": self.slippage_bps,
+            "route": route,
+        })
+    }
+}
+
+/// Names of the DEXes anywhere in a route of the quote
+fn collect_dex_names(value: &Value, names: &mut Vec<String>) {
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::String(name)) = object.get("dexName") {
+                names.push(format!("OKX {}", name));
+            }
+            object.values().for_each(|v| collect_dex_names(v, names));
+        }
+        Value::Array(values) => {
+            values.iter().for_each(|v| collect_dex_names(v, names));
+        }
+        _ => {}
+    }
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    code: String,
+    #[serde(default)]
+    msg: String,
+    data: Option<T>,
+}
+
+impl<T> Response<T> {
+    fn into_data(self) -> Result<T> {
+        match self.data {
+            Some(data) if self.code == "0" => Ok(data),
+            _ => Err(Error::Quote(format!(
+                "OKX error {}: {}",
+                self.code, self.msg
+            ))
+            .into()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SwapInstructions {
+    instruction_lists: Vec<InstructionData>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Credentials {
+    api_key: String,
+    secret_key: String,
+    passphrase: String,
+}
+
+pub struct Okx {
+    api_url: String,
+    credentials: Option<Credentials>,
+    blockhashes: Arc<BlockhashCache>,
+}
+
+impl Default for Okx {
+    fn default() -> Self {
+        let venues = &config().venues;
+        let mut okx = Self::new(OKX_API, BLOCKHASH_CACHE.clone());
+        if let (Some(api_key), Some(secret_key), Some(passphrase)) = (
+            &venues.okx_api_key,
+            &venues.okx_secret_key,
+            &venues.okx_passphrase,
+        ) {
+            okx = okx.with_credentials(api_key, secret_key, passphrase);
+        }
+        okx
+    }
+}
+
+impl Okx {
+    pub fn new(api_url: &str, blockhashes: Arc<BlockhashCache>) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            credentials: None,
+            blockhashes,
+        }
+    }
+
+    pub fn with_credentials(
+        mut self,
+        api_key: &str,
+        secret_key: &str,
+        passphrase: &str,
+    ) -> Self {
+        self.credentials = Some(Credentials {
+            api_key: api_key.to_string(),
+            secret_key: secret_key.to_string(),
+            passphrase: passphrase.to_string(),
+        });
+        self
+    }
+
+    /// Signed GET of `path` with the query string `query`
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &str,
+    ) -> Result<T> {
+        let credentials = self.credentials.as_ref().ok_or_else(|| {
+            Error::Quote("OKX API credentials are not configured".to_string())
+        })?;
+        let request_path = format!("{}?{}", path, query);
+        let timestamp =
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        let signature =
+            sign(&credentials.secret_key, &timestamp, "GET", &request_path);
+        observe_api("okx", async {
+            let response = http_client()
+                .get(format!("{}{}", self.api_url, request_path))
+                .header("OK-ACCESS-KEY", &credentials.api_key)
+                .header("OK-ACCESS-SIGN", signature)
+                .header("OK-ACCESS-TIMESTAMP", timestamp)
+                .header("OK-ACCESS-PASSPHRASE", &credentials.passphrase)
+                .send()
+                .await?;
+            if !response.status().is_success() {
+                let error = response.text().await?;
+                return Err(
+                    Error::Quote(format!("OKX Error: {}", error)).into()
+                );
+            }
+            response.json::<Response<T>>().await?.into_data()
+        })
+        .await
+    }
+
+    fn swap_query(
+        input_mint: &str,
+        output_mint: &str,
+        amount: &str,
+        slippage_bps: u16,
+    ) -> String {
+        // OKX takes the slippage as a fraction, 0.005 is 50 bps
+        format!(
+            "chainId={}&amount={}&fromTokenAddress={}&toTokenAddress={}&slippage={}",
+            SOLANA_CHAIN_ID,
+            amount,
+            input_mint,
+            output_mint,
+            slippage_bps as f64 / 10_000.
+        )
+    }
+
+    pub async fn fetch_quote(
+        &self,
+        input_mint: &str,
+        output_mint: &str,
+        amount: u64,
+        slippage_bps: u16,
+    ) -> Result<OkxQuote> {
+        let query = Self::swap_query(
+            input_mint,
+            output_mint,
+            &amount.to_string(),
+            slippage_bps,
+        );
+        let quotes = self
+            .get::<Vec<OkxQuote>>("/api/v5/dex/aggregator/quote", &query)
+            .await?;
+        let mut quote = quotes.into_iter().next().ok_or_else(|| {
+            Error::Quote("OKX returned no quote".to_string())
+        })?;
+        quote.slippage_bps = slippage_bps;
+        Ok(quote)
+    }
+
+    /// Unsigned swap transaction of `owner`, recent blockhash included.
+    /// The compute budget of OKX is replaced by the one of the session
+    pub async fn swap(
+        &self,
+        quote: OkxQuote,
+        owner: &Pubkey,
+        priority_fee_lamports: Option<u64>,
+    ) -> Result<Transaction> {
+        let query = format!(
+            "{}&userWalletAddress={}",
+            Self::swap_query(
+                &quote.from_token.token_contract_address,
+                &quote.to_token.token_contract_address,
+                &quote.from_token_amount,
+                quote.slippage_bps,
+            ),
+            owner
+        );
+        let response = self
+            .get::<SwapInstructions>(
+                "/api/v5/dex/aggregator/swap-instruction",
+                &query,
+            )
+            .await?;
+
+        let mut instructions = vec![];
+        if let Some(lamports) = priority_fee_lamports {
+            let micro_lamports =
+                lamports * 1_000_000 / SWAP_COMPUTE_UNITS as u64;
+            instructions.push(
+                ComputeBudgetInstruction::set_compute_unit_limit(
+                    SWAP_COMPUTE_UNITS,
+                ),
+            );
+            instructions.push(
+                ComputeBudgetInstruction::set_compute_unit_price(
+                    micro_lamports,
+                ),
+            );
+        }
+        for instruction in response.instruction_lists {
+            if Pubkey::from_str(&instruction.program_id)?
+                == compute_budget::id()
+                && priority_fee_lamports.is_some()
+            {
+                continue;
+            }
+            instructions
+                .push(Jupiter::convert_instruction_data(instruction)?);
+        }
+
+        let mut tx = Transaction::new_with_payer(&instructions, Some(owner));
+        tx.message.recent_blockhash =
+            self.blockhashes.get_blockhash().await?;
+        if bincode::serialized_size(&tx)? as usize > PACKET_DATA_SIZE {
+            return Err(Error::Quote(
+                "The OKX route doesn't fit in a legacy transaction"
+                    .to_string(),
+            )
+            .into());
+        }
+        Ok(tx)
+    }
+}
+
+/// Signature of a request to the OKX API
+fn sign(
+    secret_key: &str,
+    timestamp: &str,
+    method: &str,
+    path: &str,
+) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret_key.as_bytes())
+        .expect("HMAC takes keys of any size");
+    mac.update(format!("{}{}{}", timestamp, method, path).as_bytes());
+    BASE64_STANDARD.encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{
+        header, header_exists, method, path, query_param,
+    };
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::fixtures::{fixture, mock_latest_blockhash, OWNER};
+    use crate::solana::constants::{USDC, WSOL};
+
+    /// OKX and the Solana RPC served by the same mock server
+    fn okx(server: &MockServer) -> Okx {
+        let blockhashes = BlockhashCache::new(&server.uri());
+        Okx::new(&server.uri(), Arc::new(blockhashes)).with_credentials(
+            "key",
+            "secret",
+            "passphrase",
+        )
+    }
+
+    #[tokio::test]
+    async fn test_quote_and_swap() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v5/dex/aggregator/quote"))
+            .and(query_param("chainId", "501"))
+            .and(query_param("fromTokenAddress", WSOL))
+            .and(query_param("slippage", "0.005"))
+            .and(header("OK-ACCESS-KEY", "key"))
+            .and(header_exists("OK-ACCESS-SIGN"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(fixture("okx/quote")),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v5/dex/aggregator/swap-instruction"))
+            .and(query_param("userWalletAddress", OWNER))
+            .and(query_param("amount", "1000000"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(fixture("okx/swap_instruction")),
+            )
+            .mount(&server)
+            .await;
+        mock_latest_blockhash(&server).await;
+
+        let okx = okx(&server);
+        let quote = okx.fetch_quote(WSOL, USDC, 1_000_000, 50).await.unwrap();
+        assert_eq!(quote.to_token_amount, "236604");
+        let summary = quote.summary();
+        assert_eq!(summary["to"]["amount_min"], "235420");
+        let mut unbounded = quote.clone();
+        unbounded.slippage_bps = 20_000;
+        assert_eq!(unbounded.min_out_amount(), 0);
+        assert_eq!(summary["route"][0], "OKX Raydium");
+
+        let owner = Pubkey::from_str(OWNER).unwrap();
+        let tx = okx.swap(quote, &owner, Some(30_000)).await.unwrap();
+        // the compute budget of OKX is replaced by the two of the session
+        assert_eq!(tx.message.instructions.len(), 3);
+        assert_eq!(tx.message.account_keys[0], owner);
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v5/dex/aggregator/quote"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "code": "82000",
+                "msg": "Insufficient liquidity",
+                "data": [],
+            })))
+            .mount(&server)
+            .await;
+
+        let error = okx(&server)
+            .fetch_quote(WSOL, USDC, 1_000_000, 50)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Insufficient liquidity"));
+
+        let blockhashes = Arc::new(BlockhashCache::new(&server.uri()));
+        let error = Okx::new(&server.uri(), blockhashes)
+            .fetch_quote(WSOL, USDC, 1_000_000, 50)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("credentials"));
+    }
+}
@@ -0,0 +1,224 @@
+//! Background prefetching of Jupiter quotes for the hot pairs of
+//! prefetch.pairs, SOL/USDC both ways by default. Swaps of exactly a
+//! prefetched amount at the default slippage run against the warm quote
+//! instead of waiting 1-2s for Jupiter inline, quotes older than
+//! prefetch.max_age_secs are never used. Off unless prefetch.enabled
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use futures::future::join_all;
+use once_cell::sync::Lazy;
+
+use super::jup::{Jupiter, QuoteResponse};
+use crate::chains::{Chain, CHAINS};
+use crate::config::{config, PrefetchSettings};
+use crate::metrics::{METRICS, QUOTE_PREFETCH};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct QuoteKey {
+    input_mint: String,
+    output_mint: String,
+    amount: u64,
+    slippage_bps: u16,
+}
+
+struct WarmQuote {
+    quote: QuoteResponse,
+    fetched_at: Instant,
+}
+
+pub struct QuotePrefetcher {
+    jupiter: Jupiter,
+    quotes: Mutex<HashMap<QuoteKey, WarmQuote>>,
+}
+
+pub static PREFETCHER: Lazy<QuotePrefetcher> =
+    Lazy::new(|| QuotePrefetcher::new(Jupiter::default()));
+
+impl QuotePrefetcher {
+    pub fn new(jupiter: Jupiter) -> Self {
+        Self {
+            jupiter,
+            quotes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Prefetched quote of exactly this swap, if not older than `max_age`
+    pub fn warm(
+        &self,
+        input_mint: &str,
+        output_mint: &str,
+        amount: u64,
+        slippage_bps: u16,
+        max_age: Duration,
+    ) -> Option<QuoteResponse> {
+        let key = QuoteKey {
+            input_mint: input_mint.to_string(),
+            output_mint: output_mint.to_string(),
+            amount,
+            slippage_bps,
+        };
+        self.quotes
+            .lock()
+            .unwrap()
+            .get(&key)
+            .filter(|warm| warm.fetched_at.elapsed() <= max_age)
+            .map(|warm| warm.quote.clone())
+    }
+
+    /// Fetches the quotes of all hot pairs at once, returns how many were
+    /// refreshed. Quotes that failed or whose pair was removed expire
+    pub async fn refresh(
+        &self,
+        settings: &PrefetchSettings,
+        slippage_bps: u16,
+    ) -> usize {
+        let keys = settings.pairs.iter().flat_map(|pair| {
+            pair.amounts.iter().map(|&amount| QuoteKey {
+                input_mint: pair.input_mint.clone(),
+                output_mint: pair.output_mint.clone(),
+                amount,
+                slippage_bps,
+            })
+        });
+        let fetched = join_all(keys.map(|key| async move {
+            let quote = self
+                .jupiter
+                .fetch_quote(
+                    &key.input_mint,
+                    &key.output_mint,
+                    key.amount,
+                    key.slippage_bps,
+                )
+                .await;
+            (key, quote)
+        }))
+        .await;
+
+        let max_age = Duration::from_secs(settings.max_age_secs);
+        let mut quotes = self.quotes.lock().unwrap();
+        quotes.retain(|_, warm| warm.fetched_at.elapsed() <= max_age);
+        let mut refreshed = 0;
+        for (key, quote) in fetched {
+            match quote {
+                Ok(quote) => {
+                    let fetched_at = Instant::now();
+                    quotes.insert(key, WarmQuote { quote, fetched_at });
+                    refreshed += 1;
+                }
+                Err(e) => tracing::warn!(?e, ?key, "quote not prefetched"),
+            }
+        }
+        refreshed
+    }
+
+    /// The warm quote of the swap when there is one, otherwise a fresh one
+    /// from Jupiter
+    pub async fn fetch_quote(
+        &self,
+        input_mint: &str,
+        output_mint: &str,
+        amount: u64,
+        slippage_bps: u16,
+    ) -> Result<QuoteResponse> {
+        let config = config();
+        if config.prefetch.enabled {
+            let max_age = Duration::from_secs(config.prefetch.max_age_secs);
+            let warm = self.warm(
+                input_mint,
+                output_mint,
+                amount,
+                slippage_bps,
+                max_age,
+            );
+            let outcome = if warm.is_some() { "hit" } else { "miss" };
+            METRICS.inc(QUOTE_PREFETCH, &[("outcome", outcome)]);
+            if let Some(quote) = warm {
+                return Ok(quote);
+            }
+        }
+        self.jupiter
+            .fetch_quote(input_mint, output_mint, amount, slippage_bps)
+            .await
+    }
+
+    /// Refreshes the quotes every prefetch.interval_secs while enabled,
+    /// follows the changes of the config
+    pub fn spawn(&'static self) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let config = config();
+                if config.prefetch.enabled && CHAINS.is_enabled(Chain::Solana)
+                {
+                    let refreshed = self
+                        .refresh(
+                            &config.prefetch,
+                            config.trading.default_slippage_bps,
+                        )
+                        .await;
+                    tracing::debug!(refreshed, "hot pair quotes prefetched");
+                }
+                let interval = config.prefetch.interval_secs;
+                tokio::time::sleep(Duration::from_secs(interval)).await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::config::HotPair;
+    use crate::fixtures::fixture;
+    use crate::solana::blockhash::BlockhashCache;
+    use crate::solana::constants::{USDC, WSOL};
+
+    #[tokio::test]
+    async fn test_refresh() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/quote"))
+            .and(query_param("amount", "1000000"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(fixture("jupiter/quote")),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let blockhashes = BlockhashCache::new(&server.uri());
+        let prefetcher = QuotePrefetcher::new(Jupiter::new(
+            &server.uri(),
+            std::sync::Arc::new(blockhashes),
+        ));
+        let settings = PrefetchSettings {
+            enabled: true,
+            pairs: vec![HotPair {
+                input_mint: WSOL.to_string(),
+                output_mint: USDC.to_string(),
+                amounts: vec![1_000_000],
+            }],
+            ..Default::default()
+        };
+
+        assert_eq!(prefetcher.refresh(&settings, 50).await, 1);
+        let max_age = Duration::from_secs(15);
+        let warm = prefetcher.warm(WSOL, USDC, 1_000_000, 50, max_age);
+        assert_eq!(warm.unwrap().out_amount, "236512");
+        // other amounts, slippages and directions aren't prefetched
+        assert!(prefetcher
+            .warm(WSOL, USDC, 2_000_000, 50, max_age)
+            .is_none());
+        assert!(prefetcher
+            .warm(WSOL, USDC, 1_000_000, 100, max_age)
+            .is_none());
+        assert!(prefetcher
+            .warm(USDC, WSOL, 1_000_000, 50, max_age)
+            .is_none());
+    }
+}
@@ -4,6 +4,9 @@ use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
 use std::collections::HashMap;
 
+use crate::http::client::{send, Retry};
+use crate::metrics::observe_api;
+
 #[serde_with::skip_serializing_none]
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -23,12 +26,15 @@ pub struct PriceData {
 
 pub async fn fetch_token_price(mint: String, client: &Client) -> Result<f64> {
     let url = format!("https://api.jup.ag/price/v2?ids={}", mint);
-    let res = client
-        .get(url)
-        .header("accept", "application/json")
-        .send()
+    let data = observe_api("jupiter_price", async {
+        let res = send(
+            client.get(url).header("accept", "application/json"),
+            Retry::Idempotent,
+        )
         .await?;
-    let data = res.json::<PriceResponse>().await?;
+        Ok(res.json::<PriceResponse>().await?)
+    })
+    .await?;
     tracing::debug!(?data, "fetch_token_price");
     Ok(data.data.get(&mint).unwrap().price)
 }
@@ -41,7 +47,7 @@ mod tests {
     pub async fn test_fetch_token_price() {
         let res = fetch_token_price(
             "Cn5Ne1vmR9ctMGY9z5NC71A3NYFvopjXNyxYtfVYpump".to_string(),
-            &reqwest::Client::new(),
+            &crate::common::http_client(),
         )
         .await;
         tracing::debug!(?res, "test_fetch_token_price");
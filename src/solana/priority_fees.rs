@@ -0,0 +1,272 @@
+//! Priority fees of Solana transactions from the recent prioritization fees
+//! of the accounts they write. The congestion monitor samples the network
+//! as a whole, a busy pool can be contended while the rest of it is calm.
+//! The strategy of the session picks the percentile of the recent fees the
+//! transactions pay, off sends them without a compute unit price. The fee
+//! the session set (priority_fee_lamports), raised to what the congestion
+//! calls for, is the floor: every Solana transaction takes its price from
+//! `compute_unit_price`
+use std::str::FromStr;
+
+use anyhow::Result;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use super::congestion::{self, percentile};
+use crate::common::solana_rpc;
+use crate::config::config;
+use crate::error::Error;
+use crate::execution::session_key;
+use crate::preferences::{self, Preferences, PREFERENCES};
+use crate::signer::SignerContext;
+use crate::tool_error::ToolError;
+
+/// Compute units of a SOL transfer with its compute budget instructions
+pub const TRANSFER_COMPUTE_UNITS: u64 = 1_000;
+/// Compute units of an SPL transfer creating the token account of the
+/// recipient
+pub const SPL_TRANSFER_COMPUTE_UNITS: u64 = 40_000;
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PriorityFeeStrategy {
+    Off,
+    /// 25th percentile of the recent fees
+    Low,
+    #[default]
+    Medium,
+    /// 75th percentile
+    High,
+    /// 95th percentile
+    Max,
+}
+
+impl FromStr for PriorityFeeStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "off" | "none" => Ok(Self::Off),
+            "low" => Ok(Self::Low),
+            "medium" | "default" => Ok(Self::Medium),
+            "high" => Ok(Self::High),
+            "max" | "turbo" => Ok(Self::Max),
+            _ => Err(ToolError::invalid_input(format!(
+                "Unknown priority fee strategy {}",
+                s
+            ))
+            .with_hint("Use off, low, medium, high or max")
+            .into()),
+        }
+    }
+}
+
+/// Percentiles of the prioritization fees paid to write the accounts in the
+/// recent slots, in micro-lamports per compute unit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PriorityFeeEstimate {
+    pub low: u64,
+    pub medium: u64,
+    pub high: u64,
+    pub max: u64,
+    pub slots: usize,
+}
+
+impl PriorityFeeEstimate {
+    pub fn from_fees(mut fees: Vec<u64>) -> Self {
+        fees.sort_unstable();
+        Self {
+            low: percentile(&fees, 0.25),
+            medium: percentile(&fees, 0.5),
+            high: percentile(&fees, 0.75),
+            max: percentile(&fees, 0.95),
+            slots: fees.len(),
+        }
+    }
+
+    /// Compute unit price of `strategy` for a transaction of up to
+    /// `compute_units`, capped by `max_lamports`. None when the strategy is
+    /// off or the recent transactions paid nothing
+    pub fn compute_unit_price(
+        &self,
+        strategy: PriorityFeeStrategy,
+        compute_units: u64,
+        max_lamports: u64,
+    ) -> Option<u64> {
+        let price = match strategy {
+            PriorityFeeStrategy::Off => return None,
+            PriorityFeeStrategy::Low => self.low,
+            PriorityFeeStrategy::Medium => self.medium,
+            PriorityFeeStrategy::High => self.high,
+            PriorityFeeStrategy::Max => self.max,
+        };
+        let cap = max_lamports
+            .saturating_mul(1_000_000)
+            .checked_div(compute_units)
+            .unwrap_or(u64::MAX);
+        Some(price.min(cap)).filter(|price| *price > 0)
+    }
+}
+
+/// Fee in lamports of `compute_units` at `price` micro-lamports each
+pub fn fee_lamports(price: u64, compute_units: u64) -> u64 {
+    price.saturating_mul(compute_units) / 1_000_000
+}
+
+/// Recent prioritization fees of transactions writing all of `accounts`
+pub async fn estimate_priority_fee(
+    accounts: &[Pubkey],
+) -> Result<PriorityFeeEstimate> {
+    let fees = solana_rpc()
+        .get_recent_prioritization_fees(accounts)
+        .await
+        .map_err(Error::from)?;
+    Ok(PriorityFeeEstimate::from_fees(
+        fees.iter().map(|fee| fee.prioritization_fee).collect(),
+    ))
+}
+
+/// Strategy of the session of the current signer, the default one outside
+/// of a session
+pub async fn current_strategy() -> Result<PriorityFeeStrategy> {
+    let Some(signer) = SignerContext::try_current() else {
+        return Ok(PriorityFeeStrategy::default());
+    };
+    Ok(PREFERENCES
+        .get(&session_key(signer.as_ref()))
+        .await?
+        .priority_fee_strategy
+        .unwrap_or_default())
+}
+
+/// Price in micro-lamports paying `lamports` for `compute_units`
+fn price_of(lamports: u64, compute_units: u64) -> u64 {
+    lamports
+        .saturating_mul(1_000_000)
+        .checked_div(compute_units)
+        .unwrap_or_default()
+}
+
+/// Fee of the session raised to what the congestion calls for, in lamports
+async fn floor_lamports() -> Result<Option<u64>> {
+    let preference = match SignerContext::try_current() {
+        Some(_) => preferences::current().await?.priority_fee_lamports,
+        None => None,
+    };
+    Ok(congestion::priority_fee(preference))
+}
+
+/// Compute unit price of a transaction writing `accounts` with the strategy
+/// of the session, at least the floor of the session and the congestion.
+/// None when there is nothing to pay or the fees can't be read, the
+/// transaction is sent without a price then
+pub async fn compute_unit_price(
+    accounts: &[Pubkey],
+    compute_units: u64,
+) -> Option<u64> {
+    let price = async {
+        let floor = floor_lamports()
+            .await?
+            .map(|lamports| price_of(lamports, compute_units));
+        let strategy = current_strategy().await?;
+        if strategy == PriorityFeeStrategy::Off {
+            return Ok(floor);
+        }
+        let estimate = estimate_priority_fee(accounts).await?;
+        let estimated = estimate.compute_unit_price(
+            strategy,
+            compute_units,
+            config().fees.max_priority_fee_lamports,
+        );
+        Ok::<_, anyhow::Error>(estimated.max(floor))
+    };
+    price
+        .await
+        .unwrap_or_else(|e| {
+            tracing::warn!(?e, "priority fee not estimated");
+            None
+        })
+        .filter(|price| *price > 0)
+}
+
+/// Priority fee in lamports of a transaction of up to `compute_units`
+/// writing `accounts`, for the venues taking the fee rather than a price
+pub async fn priority_fee(
+    accounts: &[Pubkey],
+    compute_units: u64,
+) -> Option<u64> {
+    compute_unit_price(accounts, compute_units)
+        .await
+        .map(|price| fee_lamports(price, compute_units))
+}
+
+#[tool(description = "
+Sets the priority fee Solana transfers and swaps of the session pay, from
+the fees recently paid by the transactions using the same accounts. strategy
+is off (no estimated fee), low, medium (the default), high or max, which pay
+the 25th, 50th, 75th or 95th percentile of the recent fees. The
+priority_fee preference stays the minimum fee. Suggest high or max when
+transactions fail to land because the network is congested
+")]
+pub async fn set_priority_fee_strategy(
+    strategy: String,
+) -> Result<Preferences> {
+    let signer = SignerContext::current().await;
+    let session = session_key(signer.as_ref());
+    let mut preferences = PREFERENCES.get(&session).await?;
+    preferences.set("priority_fee_strategy", &strategy)?;
+    PREFERENCES.set(&session, &preferences).await?;
+    Ok(preferences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_unit_price() {
+        let mut fees = vec![0; 50];
+        fees.extend((1..=50).map(|fee| fee * 1_000));
+        let estimate = PriorityFeeEstimate::from_fees(fees);
+        assert_eq!(estimate.slots, 100);
+        assert_eq!(estimate.low, 0);
+        assert_eq!(estimate.high, 25_000);
+
+        let max = 5_000_000;
+        // half of the recent transactions paid nothing
+        assert_eq!(
+            estimate.compute_unit_price(PriorityFeeStrategy::Low, 1_000, max),
+            None
+        );
+        assert_eq!(
+            estimate.compute_unit_price(
+                PriorityFeeStrategy::High,
+                1_000,
+                max
+            ),
+            Some(25_000)
+        );
+        assert_eq!(
+            estimate.compute_unit_price(PriorityFeeStrategy::Off, 1_000, max),
+            None
+        );
+        // capped at 10 lamports for 1000 units
+        assert_eq!(
+            estimate.compute_unit_price(PriorityFeeStrategy::Max, 1_000, 10),
+            Some(10_000)
+        );
+        assert_eq!(fee_lamports(25_000, 300_000), 7_500);
+        // the floor of the session converts back to the same fee
+        assert_eq!(price_of(7_500, 300_000), 25_000);
+        assert_eq!(price_of(7_500, 0), 0);
+
+        assert_eq!(
+            "HIGH".parse::<PriorityFeeStrategy>().unwrap(),
+            PriorityFeeStrategy::High
+        );
+        assert!("fast".parse::<PriorityFeeStrategy>().is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::common::http_client;
 use crate::solana::{
     blockhash::BLOCKHASH_CACHE,
     constants::{
@@ -695,7 +696,7 @@ pub async fn fetch_metadata(mint: &Pubkey) -> Result<PumpTokenInfo> {
 
 async fn fetch_metadata_inner(mint: &Pubkey) -> Result<PumpTokenInfo> {
     let url = format!("https://frontend-api.pump.fun/coins/{}", mint);
-    let res = reqwest::get(&url).await?;
+    let res = http_client().get(&url).send().await?;
     let data = res.json::<serde_json::Value>().await?;
     Ok(serde_json::from_value(data)?)
 }
@@ -704,6 +705,7 @@ async fn fetch_metadata_inner(mint: &Pubkey) -> Result<PumpTokenInfo> {
 mod tests {
     use solana_sdk::signer::EncodableKey;
 
+    use crate::fixtures::fixture;
     use crate::solana::util::env;
 
     use super::*;
@@ -740,10 +742,8 @@ mod tests {
 
     #[test]
     fn test_parse_pump_accounts() {
-        let sample_tx = std::fs::read_to_string("mocks/pump_fun_tx.json")
-            .expect("read tx");
         let tx: EncodedConfirmedTransactionWithStatusMeta =
-            serde_json::from_str(&sample_tx).expect("parse tx");
+            serde_json::from_value(fixture("pump_fun_tx")).expect("parse tx");
         let accounts = parse_pump_accounts(tx).expect("parse accounts");
         tracing::debug!(?accounts, "parsed_accounts");
         assert!(
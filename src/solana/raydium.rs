@@ -0,0 +1,301 @@
+//! Client of the Raydium trade API, routing over the Raydium pools only.
+//! Quoted next to Jupiter, see `venues`
+use std::str::FromStr;
+
+use anyhow::Result;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::Transaction;
+use spl_associated_token_account::get_associated_token_address;
+
+use super::constants::WSOL;
+use crate::common::http_client;
+use crate::error::Error;
+use crate::metrics::observe_api;
+
+const RAYDIUM_API: &str = "https://transaction-v1.raydium.io";
+/// Compute unit limit Raydium sets on swaps, the priority fee of the
+/// session is spread over it
+const SWAP_COMPUTE_UNITS: u64 = 600_000;
+/// Compute unit price when the session has no priority fee
+const DEFAULT_COMPUTE_UNIT_PRICE: u64 = 100_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RaydiumRoute {
+    pub pool_id: String,
+    pub input_mint: String,
+    pub output_mint: String,
+    pub fee_mint: String,
+    pub fee_rate: u64,
+    pub fee_amount: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RaydiumSwap {
+    pub input_mint: String,
+    pub input_amount: String,
+    pub output_mint: String,
+    pub output_amount: String,
+    pub other_amount_threshold: String,
+    pub slippage_bps: u16,
+    pub price_impact_pct: f64,
+    pub route_plan: Vec<RaydiumRoute>,
+}
+
+/// A quote of the compute endpoint, the swap endpoint takes the response
+/// back as it came
+#[derive(Debug, Clone)]
+pub struct RaydiumQuote {
+    pub swap: RaydiumSwap,
+    response: Value,
+}
+
+impl RaydiumQuote {
+    pub(crate) fn from_response(response: Value) -> Result<Self> {
+        let swap = serde_json::from_value::<Response<RaydiumSwap>>(
+            response.clone(),
+        )?
+        .into_data()?;
+        Ok(Self { swap, response })
+    }
+
+    pub fn summary(&self) -> Value {
+        let route = self
+            .swap
+            .route_plan
+            .iter()
+            .map(|route| format!("Raydium {}", route.pool_id))
+            .collect::<Vec<_>>();
+        json!({
+            "from": {
+                "mint": self.swap.input_mint,
+                "amount": self.swap.input_amount,
+            },
+            "to": {
+                "mint": self.swap.output_mint,
+                "amount": self.swap.output_amount,
+                "amount_min": self.swap.other_amount_threshold,
+            },
+            "costs": {
+                "platform_fee": null,
+                "price_impact_pct": self.swap.price_impact_pct.to_string(),
+            },
+            "slippage_bps": self.swap.slippage_bps,
+            "route": route,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    success: bool,
+    msg: Option<String>,
+    data: Option<T>,
+}
+
+impl<T> Response<T> {
+    fn into_data(self) -> Result<T> {
+        match self.data {
+            Some(data) if self.success => Ok(data),
+            _ => Err(Error::Quote(format!(
+                "Raydium error: {}",
+                self.msg.unwrap_or_else(|| "no data".to_string())
+            ))
+            .into()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EncodedTransaction {
+    transaction: String,
+}
+
+pub struct Raydium {
+    api_url: String,
+}
+
+impl Default for Raydium {
+    fn default() -> Self {
+        Self::new(RAYDIUM_API)
+    }
+}
+
+impl Raydium {
+    pub fn new(api_url: &str) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub async fn fetch_quote(
+        &self,
+        input_mint: &str,
+        output_mint: &str,
+        amount: u64,
+        slippage_bps: u16,
+    ) -> Result<RaydiumQuote> {
+        let url = format!(
+            "{}/compute/swap-base-in?inputMint={}&outputMint={}&amount={}&slippageBps={}&txVersion=LEGACY",
+            self.api_url, input_mint, output_mint, amount, slippage_bps
+        );
+        observe_api("raydium", async {
+            let response = http_client().get(&url).send().await?;
+            if !response.status().is_success() {
+                let error = response.text().await?;
+                return Err(Error::Quote(format!(
+                    "Raydium Quote Error: {}",
+                    error
+                ))
+                .into());
+            }
+            RaydiumQuote::from_response(response.json().await?)
+        })
+        .await
+    }
+
+    /// Unsigned swap transaction of `owner`, recent blockhash included.
+    /// The token accounts of both mints have to exist unless they are SOL
+    pub async fn swap(
+        &self,
+        quote: RaydiumQuote,
+        owner: &Pubkey,
+        priority_fee_lamports: Option<u64>,
+    ) -> Result<Transaction> {
+        let account = |mint: &str| -> Result<Option<String>> {
+            if mint == WSOL {
+                return Ok(None);
+            }
+            let mint = Pubkey::from_str(mint).map_err(|_| {
+                Error::UserInput(format!("Invalid mint {}", mint))
+            })?;
+            Ok(Some(get_associated_token_address(owner, &mint).to_string()))
+        };
+        let compute_unit_price = priority_fee_lamports
+            .map_or(DEFAULT_COMPUTE_UNIT_PRICE, |lamports| {
+                lamports * 1_000_000 / SWAP_COMPUTE_UNITS
+            });
+        let request = json!({
+            "computeUnitPriceMicroLamports": compute_unit_price.to_string(),
+            "swapResponse": quote.response,
+            "txVersion": "LEGACY",
+            "wallet": owner.to_string(),
+            "wrapSol": quote.swap.input_mint == WSOL,
+            "unwrapSol": quote.swap.output_mint == WSOL,
+            "inputAccount": account(&quote.swap.input_mint)?,
+            "outputAccount": account(&quote.swap.output_mint)?,
+        });
+
+        let url = format!("{}/transaction/swap-base-in", self.api_url);
+        let transactions = observe_api("raydium", async {
+            let response =
+                http_client().post(&url).json(&request).send().await?;
+            if !response.status().is_success() {
+                let error = response.text().await?;
+                return Err(Error::Quote(format!(
+                    "Raydium Swap Error: {}",
+                    error
+                ))
+                .into());
+            }
+            response
+                .json::<Response<Vec<EncodedTransaction>>>()
+                .await?
+                .into_data()
+        })
+        .await?;
+
+        let [encoded] = transactions.as_slice() else {
+            return Err(Error::Quote(format!(
+                "Raydium returned {} transactions for the swap",
+                transactions.len()
+            ))
+            .into());
+        };
+        let bytes = BASE64_STANDARD.decode(&encoded.transaction)?;
+        Ok(bincode::deserialize::<Transaction>(&bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{body_partial_json, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::fixtures::{fixture, OWNER};
+    use crate::solana::constants::USDC;
+
+    #[tokio::test]
+    async fn test_quote_and_swap() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/compute/swap-base-in"))
+            .and(query_param("inputMint", WSOL))
+            .and(query_param("amount", "1000000"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(fixture("raydium/quote")),
+            )
+            .mount(&server)
+            .await;
+        let owner = Pubkey::from_str(OWNER).unwrap();
+        let usdc_account = get_associated_token_address(
+            &owner,
+            &Pubkey::from_str(USDC).unwrap(),
+        );
+        Mock::given(method("POST"))
+            .and(path("/transaction/swap-base-in"))
+            .and(body_partial_json(json!({
+                "wallet": OWNER,
+                "wrapSol": true,
+                "unwrapSol": false,
+                "outputAccount": usdc_account.to_string(),
+                "computeUnitPriceMicroLamports": "50000",
+                "swapResponse": { "id": "3c4b7cf8-b7b6-4d7c-a0a0-b4a14e1c2b2c" },
+            })))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(fixture("raydium/swap")),
+            )
+            .mount(&server)
+            .await;
+
+        let raydium = Raydium::new(&server.uri());
+        let quote = raydium
+            .fetch_quote(WSOL, USDC, 1_000_000, 50)
+            .await
+            .unwrap();
+        assert_eq!(quote.swap.output_amount, "236581");
+        assert_eq!(quote.summary()["to"]["amount_min"], "235398");
+
+        let tx = raydium.swap(quote, &owner, Some(30_000)).await.unwrap();
+        assert_eq!(tx.message.account_keys[0], owner);
+    }
+
+    #[tokio::test]
+    async fn test_no_route() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/compute/swap-base-in"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "a4e2b0c1-0d1c-4a53-9f44-4d0c5a1e7f10",
+                "success": false,
+                "version": "V1",
+                "msg": "ROUTE_NOT_FOUND",
+            })))
+            .mount(&server)
+            .await;
+
+        let error = Raydium::new(&server.uri())
+            .fetch_quote(WSOL, USDC, 1_000_000, 50)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("ROUTE_NOT_FOUND"));
+    }
+}
@@ -0,0 +1,150 @@
+//! Rent-exempt minimums of the accounts the agent creates. New accounts
+//! have to hold the minimum for their size, which the creator pays and gets
+//! back when the account is closed, so balance checks, fee estimates and
+//! explanations count it next to the fees. Minimums are asked from the RPC
+//! once per size, explanations use the rent of the cluster defaults
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::Serialize;
+use solana_sdk::native_token::lamports_to_sol;
+use solana_sdk::program_pack::Pack;
+use solana_sdk::rent::Rent;
+
+use crate::common::solana_rpc;
+use crate::error::Error;
+use crate::tool_error::ToolError;
+
+/// Size of a stake account (`StakeStateV2`)
+const STAKE_ACCOUNT_LEN: usize = 200;
+/// Size of a durable nonce account (versioned nonce state)
+const NONCE_ACCOUNT_LEN: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountKind {
+    /// SPL token account, associated or not
+    TokenAccount,
+    Mint,
+    Stake,
+    Nonce,
+}
+
+impl AccountKind {
+    pub fn size(&self) -> usize {
+        match self {
+            Self::TokenAccount => spl_token::state::Account::LEN,
+            Self::Mint => spl_token::state::Mint::LEN,
+            Self::Stake => STAKE_ACCOUNT_LEN,
+            Self::Nonce => NONCE_ACCOUNT_LEN,
+        }
+    }
+}
+
+impl FromStr for AccountKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().replace([' ', '-'], "_").as_str() {
+            "token_account" | "ata" | "token" => Ok(Self::TokenAccount),
+            "mint" => Ok(Self::Mint),
+            "stake" | "stake_account" => Ok(Self::Stake),
+            "nonce" | "nonce_account" => Ok(Self::Nonce),
+            _ => Err(ToolError::invalid_input(format!(
+                "Unknown account kind {}",
+                s
+            ))
+            .with_hint(
+                "Use token_account, mint, stake, nonce or a size in bytes",
+            )
+            .into()),
+        }
+    }
+}
+
+static MINIMUMS: Lazy<RwLock<HashMap<usize, u64>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Rent-exempt minimum of an account of `size` bytes, in lamports
+pub async fn minimum_balance(size: usize) -> Result<u64> {
+    if let Some(lamports) = MINIMUMS.read().unwrap().get(&size) {
+        return Ok(*lamports);
+    }
+    let lamports = solana_rpc()
+        .get_minimum_balance_for_rent_exemption(size)
+        .await
+        .map_err(Error::from)?;
+    MINIMUMS.write().unwrap().insert(size, lamports);
+    Ok(lamports)
+}
+
+/// Rent-exempt minimum of `size` bytes under the default rent, the one of
+/// mainnet and devnet, without a call to the RPC
+pub fn default_minimum_balance(size: usize) -> u64 {
+    Rent::default().minimum_balance(size)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RentExemption {
+    /// kind of the account, None for a size given in bytes
+    pub account: Option<AccountKind>,
+    pub size: usize,
+    pub lamports: u64,
+    pub sol: f64,
+}
+
+#[tool(description = "
+Returns the rent-exempt minimum of a Solana account: the SOL the creator of
+the account has to deposit in it, refunded when the account is closed.
+
+account is token_account (e.g. the associated token account created when
+receiving a token for the first time), mint, stake, nonce or the size of the
+account in bytes
+")]
+pub async fn get_rent_exempt_minimum(
+    account: String,
+) -> Result<RentExemption> {
+    let (kind, size) = match account.trim().parse::<usize>() {
+        Ok(size) => (None, size),
+        Err(_) => {
+            let kind = AccountKind::from_str(&account)?;
+            (Some(kind), kind.size())
+        }
+    };
+    let lamports = minimum_balance(size).await?;
+    Ok(RentExemption {
+        account: kind,
+        size,
+        lamports,
+        sol: lamports_to_sol(lamports),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_minimum_balance() {
+        let token_account = "ata".parse::<AccountKind>().unwrap();
+        assert_eq!(token_account, AccountKind::TokenAccount);
+        assert_eq!(default_minimum_balance(token_account.size()), 2_039_280);
+        assert_eq!(
+            default_minimum_balance(AccountKind::Mint.size()),
+            1_461_600
+        );
+        assert_eq!(
+            default_minimum_balance(AccountKind::Stake.size()),
+            2_282_880
+        );
+        assert_eq!(
+            default_minimum_balance(AccountKind::Nonce.size()),
+            1_447_680
+        );
+        assert!("vault".parse::<AccountKind>().is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! Savings wallets, a second Solana wallet per user holding what they don't
+//! trade. It is created through the wallet manager the first time funds are
+//! moved to it, once the move is confirmed, and only the savings tools
+//! touch it: trades, transfers and scheduled jobs keep using the trading
+//! wallet. Moves in and out always wait for the confirmation of the user,
+//! whatever the confirmation policy
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+
+use super::constants::WSOL;
+use super::transfer::create_transfer_sol_tx;
+use super::util::execute_solana_transaction;
+use crate::amount;
+use crate::common::with_explorer_link;
+use crate::confirmation::confirm_or_execute;
+use crate::kv_store::{set_if_absent, KVStore, KV_STORE};
+use crate::signer::SignerContext;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavingsWallet {
+    /// id of the wallet at the wallet provider
+    pub wallet_id: String,
+    pub address: String,
+}
+
+pub struct SavingsWallets {
+    store: Arc<dyn KVStore>,
+}
+
+pub static SAVINGS: Lazy<SavingsWallets> =
+    Lazy::new(|| SavingsWallets::new(KV_STORE.clone()));
+
+impl SavingsWallets {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self { store }
+    }
+
+    fn key(user_id: &str) -> String {
+        format!("savings:{}", user_id)
+    }
+
+    pub async fn get(&self, user_id: &str) -> Result<Option<SavingsWallet>> {
+        match self.store.get(&Self::key(user_id)).await? {
+            Some(wallet) => Ok(Some(serde_json::from_str(&wallet)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set(
+        &self,
+        user_id: &str,
+        wallet: &SavingsWallet,
+    ) -> Result<()> {
+        self.store
+            .set(&Self::key(user_id), &serde_json::to_string(wallet)?)
+            .await
+    }
+
+    /// Stores `wallet` unless the user has one already and returns the one
+    /// kept, concurrent first moves all end up with the same wallet
+    pub async fn insert(
+        &self,
+        user_id: &str,
+        wallet: SavingsWallet,
+    ) -> Result<SavingsWallet> {
+        let key = Self::key(user_id);
+        let value = serde_json::to_string(&wallet)?;
+        if set_if_absent(self.store.as_ref(), &key, &value, None).await? {
+            return Ok(wallet);
+        }
+        match self.get(user_id).await? {
+            Some(stored) => Ok(stored),
+            None => {
+                Err(anyhow::anyhow!("Savings wallet of {} lost", user_id))
+            }
+        }
+    }
+}
+
+#[tool(description = "
+Moves SOL from the trading wallet to the savings wallet of the user, creating
+the savings wallet the first time. Savings are kept apart from the funds the
+agent trades with. The move always needs the confirmation of the user.
+
+amount is the amount as the user said it, e.g. 2 SOL, half or all of the
+trading wallet
+")]
+pub async fn move_to_savings(amount: String) -> Result<String> {
+    let existing = SignerContext::current().await.savings_wallet().await?;
+    let amount = amount::resolve("sol", WSOL, &amount)
+        .await?
+        .with_symbol("SOL");
+    let summary = match &existing {
+        Some(to) => format!("Move {} to savings ({})", amount, to),
+        None => format!("Move {} to a new savings wallet", amount),
+    };
+    confirm_or_execute("move_to_savings", summary, move || async move {
+        let signer = SignerContext::current().await;
+        let to = match existing {
+            Some(to) => Pubkey::from_str(&to)?,
+            // dry runs and previews move to a made-up wallet, the real one
+            // is only created once the move is confirmed
+            None if signer.is_paper() => Keypair::new().pubkey(),
+            None => {
+                Pubkey::from_str(&signer.savings_signer().await?.pubkey())?
+            }
+        };
+        let hash = execute_solana_transaction(move |owner| async move {
+            create_transfer_sol_tx(&to, &amount, &owner).await
+        })
+        .await?;
+        Ok(with_explorer_link("sol", &hash))
+    })
+    .await
+}
+
+#[tool(description = "
+Withdraws SOL from the savings wallet of the user back to the trading wallet.
+The savings wallet pays the network fee. The withdrawal always needs the
+confirmation of the user.
+
+amount is the amount as the user said it, e.g. 2 SOL, half or all of the
+savings
+")]
+pub async fn withdraw_from_savings(amount: String) -> Result<String> {
+    let signer = SignerContext::current().await;
+    let savings = signer.savings_signer().await?;
+    let to = Pubkey::from_str(&signer.pubkey())?;
+    // half or all are shares of the savings
+    let amount = SignerContext::with_signer(
+        savings,
+        amount::resolve("sol", WSOL, &amount),
+    )
+    .await?
+    .with_symbol("SOL");
+    let summary = format!("Withdraw {} from savings to {}", amount, to);
+    confirm_or_execute("withdraw_from_savings", summary, move || async move {
+        // from the signer running the action, previews run under a paper
+        // signer and must not reach the savings wallet
+        let savings = SignerContext::current().await.savings_signer().await?;
+        let hash = SignerContext::with_signer(
+            savings,
+            execute_solana_transaction(move |owner| async move {
+                create_transfer_sol_tx(&to, &amount, &owner).await
+            }),
+        )
+        .await?;
+        Ok(with_explorer_link("sol", &hash))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    #[tokio::test]
+    async fn test_insert_keeps_the_first_wallet() {
+        let savings = SavingsWallets::new(Arc::new(InMemoryKVStore::new()));
+        let wallet = |id: &str| SavingsWallet {
+            wallet_id: id.to_string(),
+            address: format!("{}-address", id),
+        };
+        let (a, b) = tokio::join!(
+            savings.insert("user", wallet("a")),
+            savings.insert("user", wallet("b"))
+        );
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert_eq!(a, b);
+        assert_eq!(savings.get("user").await.unwrap(), Some(a));
+    }
+}
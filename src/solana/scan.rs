@@ -3,6 +3,7 @@ use std::str::FromStr;
 use anyhow::{anyhow, Result};
 use solana_sdk::pubkey::Pubkey;
 
+use crate::common::http_client;
 use crate::solana::pump::fetch_metadata;
 
 pub async fn scan(mint: String) -> Result<String> {
@@ -14,7 +15,7 @@ pub async fn scan(mint: String) -> Result<String> {
     // could check the deploy history of creator here too
     // let _raw_response = vec![];
     if let Some(twitter) = metadata.twitter {
-        let res = reqwest::get(twitter).await?.text().await?;
+        let res = http_client().get(twitter).send().await?.text().await?;
         tracing::debug!(?res, "scan:twitter");
     }
     unimplemented!();
@@ -0,0 +1,214 @@
+//! Simulation of the Solana transactions before they are signed, so a
+//! transaction bound to fail (insufficient funds, slippage exceeded) is
+//! refused before it costs a fee and the LLM is told why. The unsigned
+//! transaction is simulated with the latest blockhash, the error the
+//! failing program logged is kept with the one of the runtime and the
+//! writable accounts give the balance changes of the wallet
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::json;
+use solana_client::rpc_response::RpcSimulateTransactionResult;
+use solana_sdk::account::Account;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::VersionedTransaction;
+
+use super::constants::WSOL;
+use super::transaction::simulate_unsigned_tx;
+use crate::common::solana_rpc;
+use crate::config::config;
+use crate::preview::{solana_changes, solana_deltas, BalanceDelta, NATIVE};
+use crate::tool_error::{classify, ToolError};
+
+/// Logs of a failed simulation passed on to the LLM
+const FAILURE_LOGS: usize = 10;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SolanaSimulation {
+    pub success: bool,
+    /// error of the runtime with the last error a program logged
+    pub error: Option<String>,
+    pub units_consumed: Option<u64>,
+    pub logs: Vec<String>,
+    /// changes of the balances of the wallet, empty when it failed
+    pub balance_changes: Vec<BalanceDelta>,
+}
+
+impl SolanaSimulation {
+    /// Error for the LLM when the simulation failed, coded from what failed
+    /// (insufficient funds, slippage exceeded) with the last logs
+    pub fn failure(&self) -> Option<ToolError> {
+        let error = self.error.as_ref()?;
+        let logs = &self.logs[self.logs.len().saturating_sub(FAILURE_LOGS)..];
+        Some(
+            ToolError::new(
+                classify(error),
+                format!(
+                    "Simulation failed, the transaction was not sent: {}",
+                    error
+                ),
+            )
+            .with_details(json!({
+                "units_consumed": self.units_consumed,
+                "logs": logs,
+            })),
+        )
+    }
+}
+
+/// Pre-send simulation is opt-in, enabled by features.solana_simulate
+/// (SOLANA_SIMULATE=true)
+pub fn simulation_enabled() -> bool {
+    config().features.solana_simulate
+}
+
+/// Error of the runtime with the last error a program logged, the runtime
+/// only gives the instruction and a custom error code
+fn failure_reason(error: &str, logs: &[String]) -> String {
+    let logged = logs.iter().rev().find_map(|log| {
+        let log = log.strip_prefix("Program log: ").unwrap_or(log);
+        let lower = log.to_lowercase();
+        ((lower.contains("error") && !lower.starts_with("program "))
+            || lower.contains("insufficient"))
+        .then(|| log.trim_start_matches("Error: ").to_string())
+    });
+    match logged {
+        Some(logged) => format!("{}: {}", error, logged),
+        None => error.to_string(),
+    }
+}
+
+/// Writable accounts of `tx` with their states before and after it
+type AccountStates = Vec<(Pubkey, Option<Account>, Option<Account>)>;
+
+/// Simulates `tx` with the states of its writable accounts, empty when it
+/// failed
+async fn simulate_accounts(
+    tx: &VersionedTransaction,
+) -> Result<(RpcSimulateTransactionResult, AccountStates)> {
+    let message = &tx.message;
+    let writable = message
+        .static_account_keys()
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| message.is_maybe_writable(*i, None))
+        .map(|(_, key)| *key)
+        .collect::<Vec<_>>();
+    let before = solana_rpc().get_multiple_accounts(&writable).await?;
+    let mut result = simulate_unsigned_tx(tx, &writable).await?;
+    if result.err.is_some() {
+        return Ok((result, vec![]));
+    }
+    let after = result.accounts.take().unwrap_or_default();
+    let accounts = writable
+        .into_iter()
+        .zip(before)
+        .zip(after)
+        .map(|((key, before), after)| {
+            (key, before, after.and_then(|a| a.decode::<Account>()))
+        })
+        .collect();
+    Ok((result, accounts))
+}
+
+/// Raw amounts of the tokens `tx` takes out of the wallet `owner`, SOL as
+/// WSOL, from a simulation. Fails with the error of a failed simulation
+pub async fn spent(
+    tx: &VersionedTransaction,
+    owner: &Pubkey,
+) -> Result<Vec<(String, u64)>> {
+    let (result, accounts) = simulate_accounts(tx).await?;
+    let logs = result.logs.unwrap_or_default();
+    let simulation = SolanaSimulation {
+        success: result.err.is_none(),
+        error: result.err.map(|e| failure_reason(&e.to_string(), &logs)),
+        units_consumed: result.units_consumed,
+        logs,
+        balance_changes: vec![],
+    };
+    if let Some(failure) = simulation.failure() {
+        return Err(failure.into());
+    }
+    Ok(solana_changes(owner, &accounts)
+        .into_iter()
+        .filter(|(_, before, after)| after < before)
+        .map(|(token, before, after)| {
+            let token = match token.as_str() {
+                NATIVE => WSOL.to_string(),
+                _ => token,
+            };
+            (token, before - after)
+        })
+        .collect())
+}
+
+/// Simulates `tx` for the wallet `owner`, the balance changes are those of
+/// its writable accounts (the wallet and its token accounts)
+pub async fn simulate(
+    tx: &VersionedTransaction,
+    owner: &Pubkey,
+) -> Result<SolanaSimulation> {
+    let (result, accounts) = simulate_accounts(tx).await?;
+    let logs = result.logs.unwrap_or_default();
+    let error = result.err.map(|e| failure_reason(&e.to_string(), &logs));
+    let mut balance_changes = vec![];
+    if error.is_none() {
+        match solana_deltas(owner, &accounts).await {
+            Ok(deltas) => balance_changes = deltas,
+            Err(e) => tracing::warn!(?e, "balance changes not previewed"),
+        }
+    }
+    Ok(SolanaSimulation {
+        success: error.is_none(),
+        error,
+        units_consumed: result.units_consumed,
+        logs,
+        balance_changes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tool_error::ErrorCode;
+
+    #[test]
+    fn test_failure_reason() {
+        let error =
+            "Error processing Instruction 2: custom program error: 0x1";
+        let logs = |lines: &[&str]| {
+            lines.iter().map(|l| l.to_string()).collect::<Vec<_>>()
+        };
+        let token = logs(&[
+            "Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA invoke [1]",
+            "Program log: Instruction: Transfer",
+            "Program log: Error: insufficient funds",
+            "Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA failed: \
+             custom program error: 0x1",
+        ]);
+        let reason = failure_reason(error, &token);
+        assert_eq!(reason, format!("{}: insufficient funds", error));
+        assert_eq!(classify(&reason), ErrorCode::InsufficientFunds);
+
+        let system = logs(&["Transfer: insufficient lamports 10, need 20"]);
+        assert!(failure_reason(error, &system).ends_with("need 20"));
+
+        let jupiter =
+            logs(&["Program log: AnchorError occurred. Error Code: \
+             SlippageToleranceExceeded. Error Number: 6001. Error Message: \
+             Slippage tolerance exceeded."]);
+        let reason = failure_reason(error, &jupiter);
+        assert_eq!(classify(&reason), ErrorCode::SlippageExceeded);
+        assert_eq!(failure_reason(error, &[]), error);
+
+        let simulation = SolanaSimulation {
+            success: false,
+            error: Some(reason),
+            units_consumed: Some(50_000),
+            logs: jupiter,
+            balance_changes: vec![],
+        };
+        let failure = simulation.failure().unwrap();
+        assert_eq!(failure.code, ErrorCode::SlippageExceeded);
+        assert!(failure.message.starts_with("Simulation failed"));
+    }
+}
@@ -3,155 +3,233 @@
 //! as function calls
 #![allow(non_upper_case_globals)]
 
-use anyhow::{anyhow, Result};
-use once_cell::sync::Lazy;
-use reqwest::Client;
+use anyhow::Result;
 use rig_tool_macro::tool;
-use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::native_token::sol_to_lamports;
 use solana_sdk::pubkey::Pubkey;
 use std::str::FromStr;
-use std::sync::Arc;
 
-use crate::common::wrap_unsafe;
+use crate::amount::{self, Amount};
+use crate::common::{http_client, solana_rpc, with_explorer_link};
+use crate::confirmation::confirm_or_execute;
+use crate::error::Error;
+use crate::preferences;
+use crate::progress::{report, Stage};
+use crate::quotes::{cache_quote, Quote};
+use crate::revenue::record_fee;
+use crate::risk::{assess, TradeIntent};
 use crate::solana::data::PortfolioItem;
+use crate::validation;
 
+use super::congestion::SWAP_COMPUTE_UNITS;
+use super::constants::WSOL;
 use super::data::holdings_to_portfolio;
 use super::deploy_token::create_deploy_token_tx;
-use super::trade::{create_trade_transaction, create_ata_if_needed};
+use super::priority_fees::priority_fee;
+use super::trade::create_ata_if_needed;
 use super::trade_pump::{create_buy_pump_fun_tx, create_sell_pump_fun_tx};
 use super::transfer::{create_transfer_sol_tx, create_transfer_spl_tx};
-use super::util::execute_solana_transaction;
+use super::util::{execute_solana_transaction, execute_versioned_solana_transaction};
+use super::venues::{best_quote, SwapQuote};
 use crate::signer::SignerContext;
 
-static SOLANA_RPC_URL: Lazy<String> = Lazy::new(|| {
-    std::env::var("SOLANA_RPC_URL")
-        .unwrap_or_else(|_| "https://api.mainnet-beta.solana.com".to_string())
-});
-
-fn create_rpc() -> RpcClient {
-    RpcClient::new(SOLANA_RPC_URL.to_string())
-}
-
 #[tool(description = "
-Performs a swap from input_mint to output_mint on Jupiter. 
+Performs a swap from input_mint to output_mint on the venue paying the most,
+the venues of the deployment are quoted at the same time (Jupiter and
+Raydium, OKX when it is enabled).
 
-The input_amount has to be account for decimals
-e.g. 1 token with 6 decimals => 1000000
+input_amount is the amount as the user said it, e.g. 0.5 SOL, 100 USDC,
+half, 25% or all, it is converted with the decimals and the balance of the
+input token. Don't scale it by the decimals yourself
 
 Both the input_mint and output_mint have to be valid Solana public keys of 
 tokens, the so called token mints
 
-slippage_bps is slippage in basis points, for majority of stuff it is fine to use 50-100bps
+slippage_bps is slippage in basis points, for majority of stuff it is fine to use 50-100bps,
+0 uses the default slippage of the session
 ")]
 pub async fn perform_jupiter_swap(
     input_mint: String,
-    input_amount: u64,
+    input_amount: String,
     output_mint: String,
     slippage_bps: u16,
 ) -> Result<String> {
-    let owner = SignerContext::current().await;
-    let owner_pubkey = Pubkey::from_str(&owner.pubkey())?;
-    let owner_clone = Arc::clone(&owner);
-
-    let output_mint_pubkey = Pubkey::from_str(&output_mint)
-            .map_err(|_| anyhow!("Invalid output mint"))?;
-    let mut tx_ata = create_ata_if_needed(&owner_pubkey, &output_mint_pubkey).await?;
-    let result = tokio::task::spawn_blocking(move || {
-        tokio::runtime::Runtime::new()
-            .unwrap()
-            .block_on(owner.sign_and_send_solana_transaction(&mut tx_ata))
+    let input_amount = amount::resolve("sol", &input_mint, &input_amount).await?;
+    validation::swap("sol", &input_mint, &input_amount.raw().to_string(), &output_mint).await?;
+    let slippage_bps = preferences::slippage_bps(slippage_bps).await?;
+    validation::slippage_bps(slippage_bps)?;
+    let assessment = assess(
+        TradeIntent::new("sol")
+            .spend(&input_mint, &input_amount.raw().to_string())
+            .receive(&output_mint)
+            .slippage_bps(slippage_bps),
+    )
+    .await?;
+    let summary = assessment.annotate(format!(
+        "Swap {} of {} for {} on the best venue (slippage {} bps)",
+        input_amount, input_mint, output_mint, slippage_bps
+    ));
+    confirm_or_execute("perform_jupiter_swap", summary, move || async move {
+        let quote = best_quote(
+            &input_mint,
+            &output_mint,
+            input_amount.to_u64()?,
+            slippage_bps,
+        )
+        .await
+        .map_err(|e| Error::Quote(format!("Failed to fetch quote: {}", e)))?;
+        report(Stage::QuoteFetched, Some(quote.venue().to_string()));
+
+        let signature = swap_with_quote(quote).await?;
+        assessment.record().await;
+        Ok(signature)
     })
     .await
-    .map_err(|e| anyhow::anyhow!("Join error: {:?}", e))??; 
+}
 
-    println!("I'AM IN TRANSFER");
-    let mut tx = create_trade_transaction(
-        input_mint,
-        input_amount,
-        output_mint,
-        slippage_bps,
-        &owner_pubkey,
+#[tool(description = "
+Quotes a swap from input_mint to output_mint on the venues of the deployment
+(Jupiter and Raydium, OKX when it is enabled) at the same time and keeps the
+best quote, this is the first step of every swap on Solana.
+
+input_amount is the amount as the user said it, e.g. 0.5 SOL, 100 USDC,
+half, 25% or all, it is converted with the decimals and the balance of the
+input token. Don't scale it by the decimals yourself
+
+Both the input_mint and output_mint have to be valid Solana public keys of 
+tokens, the so called token mints
+
+slippage_bps is slippage in basis points, for majority of stuff it is fine to use 50-100bps,
+0 uses the default slippage of the session
+
+Returns a quote id together with a summary of the amounts, minimum received,
+price impact, fees and route. Nothing is executed, show the summary to the
+user and call execute_quote with the id once they agree to it.
+")]
+pub async fn quote_jupiter_swap(
+    input_mint: String,
+    input_amount: String,
+    output_mint: String,
+    slippage_bps: u16,
+) -> Result<Quote> {
+    let input_amount = amount::resolve("sol", &input_mint, &input_amount).await?;
+    validation::swap("sol", &input_mint, &input_amount.raw().to_string(), &output_mint).await?;
+    let slippage_bps = preferences::slippage_bps(slippage_bps).await?;
+    validation::slippage_bps(slippage_bps)?;
+    let assessment = assess(
+        TradeIntent::new("sol")
+            .spend(&input_mint, &input_amount.raw().to_string())
+            .receive(&output_mint)
+            .slippage_bps(slippage_bps),
     )
     .await?;
-
-    // let res = execute_solana_transaction(move |owner| async move {
-    //     create_trade_transaction(
-    //         input_mint,
-    //         input_amount,
-    //         output_mint,
-    //         slippage_bps,
-    //         &owner,
-    //     )
-    //     .await
-    // })
-    // .await;
-
-    let result = tokio::task::spawn_blocking(move || {
-        tokio::runtime::Runtime::new()
-            .unwrap()
-            .block_on(owner_clone.sign_and_send_solana_transaction(&mut tx))
+    let quote = best_quote(
+        &input_mint,
+        &output_mint,
+        input_amount.to_u64()?,
+        slippage_bps,
+    )
+    .await
+    .map_err(|e| Error::Quote(format!("Failed to fetch quote: {}", e)))?;
+
+    let mut summary = quote.summary();
+    summary["risk_warnings"] = serde_json::json!(assessment.warnings);
+    cache_quote(quote.venue(), summary, move || async move {
+        let signature = swap_with_quote(quote).await?;
+        assessment.record().await;
+        Ok(signature)
     })
     .await
-    .map_err(|e| anyhow::anyhow!("Join error: {:?}", e))??;
-
-    Ok(result)
 }
 
-// #[tool]
-// pub async fn transfer_sol(to: String, amount: u64) -> Result<String> {
-//     // execute_solana_transaction(move |owner| async move {
-//     //     create_transfer_sol_tx(&Pubkey::from_str(&to)?, amount, &owner).await
-//     // })
-//     // .await
-//     let owner = SignerContext::current().await;
-//     let owner_pubkey = Pubkey::from_str(&owner.pubkey())?;
+/// Creates the output token account if needed and executes the quote with
+/// the current signer
+async fn swap_with_quote(quote: SwapQuote) -> Result<String> {
+    let owner = Pubkey::from_str(&SignerContext::current().await.pubkey())?;
+    let output_mint = Pubkey::from_str(quote.output_mint())
+        .map_err(|_| Error::UserInput("Invalid output mint".to_string()))?;
+    // Jupiter swaps create the output account in the swap transaction
+    if !matches!(quote, SwapQuote::Jupiter(_)) {
+        if let Some(ata_tx) = create_ata_if_needed(&owner, &output_mint).await? {
+            execute_solana_transaction(move |_| async move { Ok(ata_tx) }).await?;
+        }
+    }
 
-//     let mut tx = create_transfer_sol_tx(&Pubkey::from_str(&to)?, amount, &owner_pubkey).await?;
+    let mut accounts = vec![owner];
+    accounts.extend(quote.pools());
+    let priority_fee = priority_fee(&accounts, SWAP_COMPUTE_UNITS).await;
+    let fee = quote.collected_fee();
+    let hash = execute_versioned_solana_transaction(move |owner| async move {
+        quote.transaction(&owner, priority_fee).await
+    })
+    .await?;
+    record_fee(fee, &hash).await;
 
-//     Box::pin(async move {
-//         owner.sign_and_send_solana_transaction(&mut tx).await
-//     })
-//     .await
-// }
+    Ok(with_explorer_link("sol", &hash))
+}
 
-#[tool]
-pub async fn transfer_sol(to: String, amount: u64) -> Result<String> {
-    let owner = SignerContext::current().await;
-    let owner_pubkey = Pubkey::from_str(&owner.pubkey())?;
-    println!("I'AM IN TRANSFER");
-    let mut tx = create_transfer_sol_tx(&Pubkey::from_str(&to)?, amount, &owner_pubkey).await?;
-
-    // Запускаем транзакцию в отдельном потоке
-    let result = tokio::task::spawn_blocking(move || {
-        tokio::runtime::Runtime::new()
-            .unwrap()
-            .block_on(owner.sign_and_send_solana_transaction(&mut tx))
+#[tool(description = "
+Transfers SOL to the to address
+
+amount is the amount as the user said it, e.g. 0.5 SOL, half or all, it is
+converted to lamports for you
+")]
+pub async fn transfer_sol(to: String, amount: String) -> Result<String> {
+    let amount = amount::resolve("sol", WSOL, &amount).await?.with_symbol("SOL");
+    let recipient = validation::transfer("sol", &to, WSOL, &amount.raw().to_string()).await?;
+    let assessment = assess(
+        TradeIntent::new("sol")
+            .spend(WSOL, &amount.raw().to_string())
+            .destination(&recipient.to_string()),
+    )
+    .await?;
+    let summary = assessment.annotate(format!("Transfer {} to {}", amount, to));
+    confirm_or_execute("transfer_sol", summary, move || async move {
+        let hash = execute_solana_transaction(move |owner| async move {
+            create_transfer_sol_tx(&recipient.as_solana()?, &amount, &owner).await
+        })
+        .await?;
+        assessment.record().await;
+        Ok(with_explorer_link("sol", &hash))
     })
     .await
-    .map_err(|e| anyhow::anyhow!("Join error: {:?}", e))??;
-
-    Ok(result)
 }
 
 
-/// param amount is token amount, accounting for decimals
-/// e.g. 1 Fartcoin = 1 * 10^6 (6 decimals)
-#[tool]
+#[tool(description = "
+Transfers an SPL token, given by its mint, to the to address
+
+amount is the amount as the user said it, e.g. 100 USDC, half or all, it is
+converted with the decimals and the balance of the token for you
+")]
 pub async fn transfer_spl_token(
     to: String,
-    amount: u64,
+    amount: String,
     mint: String,
 ) -> Result<String> {
-    execute_solana_transaction(move |owner| async move {
-        create_transfer_spl_tx(
-            &Pubkey::from_str(&to)?,
-            amount,
-            &Pubkey::from_str(&mint)?,
-            &owner,
-            &create_rpc(),
-        )
-        .await
+    let amount = amount::resolve("sol", &mint, &amount).await?;
+    let recipient = validation::transfer("sol", &to, &mint, &amount.raw().to_string()).await?;
+    let assessment = assess(
+        TradeIntent::new("sol")
+            .spend(&mint, &amount.raw().to_string())
+            .destination(&recipient.to_string()),
+    )
+    .await?;
+    let summary = assessment.annotate(format!("Transfer {} of token {} to {}", amount, mint, to));
+    confirm_or_execute("transfer_spl_token", summary, move || async move {
+        let hash = execute_solana_transaction(move |owner| async move {
+            create_transfer_spl_tx(
+                &recipient.as_solana()?,
+                &amount,
+                &Pubkey::from_str(&mint)?,
+                &owner,
+                &solana_rpc(),
+            )
+            .await
+        })
+        .await?;
+        assessment.record().await;
+        Ok(with_explorer_link("sol", &hash))
     })
     .await
 }
@@ -162,47 +240,32 @@ pub async fn get_public_key() -> Result<String> {
 }
 
 #[tool]
-pub async fn get_sol_balance() -> Result<f64> {
+pub async fn get_sol_balance() -> Result<Amount> {
     let signer = SignerContext::current().await.clone();
     let owner = Pubkey::from_str(&signer.pubkey())?;
 
-    let result = wrap_unsafe(move || async move {
-        create_rpc()
-            .get_balance(&owner)
-            .await
-            .map_err(|e| anyhow!("{:#?}", e))
-    })
-    .await;
+    let lamports = solana_rpc()
+        .get_balance(&owner)
+        .await
+        .map_err(Error::from)?;
 
-    match result {
-        Ok(lamports) => {
-            let balance_sol = lamports as f64 / 1_000_000_000.0;
-            Ok(balance_sol)
-        }
-        Err(e) => Err(e),
-    }
+    Ok(Amount::new(lamports as u128, 9).with_symbol("SOL"))
 }
 
-/// get_token_balance returns the amount as String and the decimals as u8
-/// in order to convert to UI amount: amount / 10^decimals
 #[tool]
-pub async fn get_spl_token_balance(mint: String) -> Result<(String, u8)> {
+pub async fn get_spl_token_balance(mint: String) -> Result<Amount> {
     let signer = SignerContext::current().await;
     let owner = Pubkey::from_str(&signer.pubkey())?;
     let mint = Pubkey::from_str(&mint)?;
     let ata = spl_associated_token_account::get_associated_token_address(
         &owner, &mint,
     );
-    let balance = wrap_unsafe(move || async move {
-        create_rpc()
-            .get_token_account_balance(&ata)
-            .await
-            .map_err(|e| anyhow!("{:#?}", e))
-    })
-    .await
-    .map_err(|e| anyhow!("{:#?}", e))?;
+    let balance = solana_rpc()
+        .get_token_account_balance(&ata)
+        .await
+        .map_err(Error::from)?;
 
-    Ok((balance.amount, balance.decimals))
+    Ok(Amount::new(balance.amount.parse()?, balance.decimals))
 }
 
 #[tool]
@@ -217,28 +280,39 @@ pub async fn deploy_pump_fun_token(
     image_url: String,
     description: String,
 ) -> Result<String> {
-    execute_solana_transaction(move |owner| async move {
-        create_deploy_token_tx(
-            crate::solana::deploy_token::DeployTokenParams {
-                name,
-                symbol,
-                twitter: Some(twitter),
-                website: Some(website),
-                dev_buy: Some(dev_buy),
-                telegram: Some(telegram),
-                image_url: Some(image_url),
-                description,
-            },
-            &owner,
-        )
-        .await
+    let assessment =
+        assess(TradeIntent::new("sol").spend(WSOL, &dev_buy.to_string())).await?;
+    let summary = assessment.annotate(format!(
+        "Deploy pump.fun token {} ({}) with a dev buy of {} lamports",
+        name, symbol, dev_buy
+    ));
+    confirm_or_execute("deploy_pump_fun_token", summary, move || async move {
+        let hash = execute_solana_transaction(move |owner| async move {
+            create_deploy_token_tx(
+                crate::solana::deploy_token::DeployTokenParams {
+                    name,
+                    symbol,
+                    twitter: Some(twitter),
+                    website: Some(website),
+                    dev_buy: Some(dev_buy),
+                    telegram: Some(telegram),
+                    image_url: Some(image_url),
+                    description,
+                },
+                &owner,
+            )
+            .await
+        })
+        .await?;
+        assessment.record().await;
+        Ok(with_explorer_link("sol", &hash))
     })
     .await
 }
 
 #[tool]
 pub async fn fetch_token_price(mint: String) -> Result<f64> {
-    crate::solana::price::fetch_token_price(mint, &Client::new()).await
+    crate::solana::price::fetch_token_price(mint, &http_client()).await
 }
 
 #[tool]
@@ -247,15 +321,35 @@ pub async fn buy_pump_fun_token(
     sol_amount: f64,
     slippage_bps: u16,
 ) -> Result<String> {
-    execute_solana_transaction(move |owner| async move {
-        create_buy_pump_fun_tx(
-            mint,
-            sol_to_lamports(sol_amount),
-            slippage_bps,
-            &create_rpc(),
-            &owner,
-        )
-        .await
+    validation::address("sol", "mint", &mint)?;
+    validation::positive("sol_amount", sol_amount)?;
+    let slippage_bps = preferences::slippage_bps(slippage_bps).await?;
+    validation::slippage_bps(slippage_bps)?;
+    let assessment = assess(
+        TradeIntent::new("sol")
+            .spend(WSOL, &sol_to_lamports(sol_amount).to_string())
+            .receive(&mint)
+            .slippage_bps(slippage_bps),
+    )
+    .await?;
+    let summary = assessment.annotate(format!(
+        "Buy pump.fun token {} for {} SOL (slippage {} bps)",
+        mint, sol_amount, slippage_bps
+    ));
+    confirm_or_execute("buy_pump_fun_token", summary, move || async move {
+        let hash = execute_solana_transaction(move |owner| async move {
+            create_buy_pump_fun_tx(
+                mint,
+                sol_to_lamports(sol_amount),
+                slippage_bps,
+                &solana_rpc(),
+                &owner,
+            )
+            .await
+        })
+        .await?;
+        assessment.record().await;
+        Ok(with_explorer_link("sol", &hash))
     })
     .await
 }
@@ -265,8 +359,20 @@ pub async fn sell_pump_fun_token(
     mint: String,
     token_amount: u64,
 ) -> Result<String> {
-    execute_solana_transaction(move |owner| async move {
-        create_sell_pump_fun_tx(mint, token_amount, &owner).await
+    validation::address("sol", "mint", &mint)?;
+    validation::positive("token_amount", token_amount)?;
+    let assessment = assess(
+        TradeIntent::new("sol").spend(&mint, &token_amount.to_string()),
+    )
+    .await?;
+    let summary = assessment.annotate(format!("Sell {} of pump.fun token {}", token_amount, mint));
+    confirm_or_execute("sell_pump_fun_token", summary, move || async move {
+        let hash = execute_solana_transaction(move |owner| async move {
+            create_sell_pump_fun_tx(mint, token_amount, &owner).await
+        })
+        .await?;
+        assessment.record().await;
+        Ok(with_explorer_link("sol", &hash))
     })
     .await
 }
@@ -274,13 +380,8 @@ pub async fn sell_pump_fun_token(
 #[tool]
 pub async fn get_portfolio() -> Result<Vec<PortfolioItem>> {
     let owner = Pubkey::from_str(&SignerContext::current().await.pubkey())?;
-    let holdings = wrap_unsafe(move || async move {
-        crate::solana::balance::get_holdings(&create_rpc(), &owner)
-            .await
-            .map_err(|e| anyhow!("{:#?}", e))
-    })
-    .await
-    .map_err(|e| anyhow!("{:#?}", e))?;
+    let holdings =
+        crate::solana::balance::get_holdings(&solana_rpc(), &owner).await?;
 
     holdings_to_portfolio(holdings).await
 }
@@ -1,18 +1,24 @@
+use crate::common::solana_rpc;
+use crate::error::Error;
+use crate::solana::blockhash::BLOCKHASH_CACHE;
+use crate::solana::congestion::SWAP_COMPUTE_UNITS;
 use crate::solana::jup::Jupiter;
-use anyhow::{anyhow, Result};
+use crate::solana::prefetch::PREFETCHER;
+use crate::solana::priority_fees::priority_fee;
+use anyhow::Result;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::transaction::Transaction;
-use solana_client::rpc_client::RpcClient;
 use solana_sdk::signature::{Keypair, Signer};
 use spl_associated_token_account::{
     get_associated_token_address,
     instruction::create_associated_token_account,
 };
 use spl_token::ID as TOKEN_PROGRAM_ID;
-use std::env;
 use std::str::FromStr;
 use solana_program::system_program;
 
+/// Jupiter swap of `owner`, the token account of the output is created in
+/// the same transaction when it is missing, see `Jupiter::swap`
 pub async fn create_trade_transaction(
     input_mint: String,
     input_amount: u64,
@@ -20,52 +26,49 @@ pub async fn create_trade_transaction(
     slippage_bps: u16,
     owner: &Pubkey,
 ) -> Result<Transaction> {
-    let quote = Jupiter::fetch_quote(
+    let quote = PREFETCHER.fetch_quote(
         &input_mint,
         &output_mint,
         input_amount,
         slippage_bps,
     )
     .await
-    .map_err(|e| anyhow!("Failed to fetch quote: {}", e.to_string()))?;
+    .map_err(|e| Error::Quote(format!("Failed to fetch quote: {}", e)))?;
 
-    let tx = Jupiter::swap(quote, owner)
-        .await
-        .map_err(|e| anyhow!("Failed to swap: {}", e.to_string()))?;
-    println!("---------SWAP CREATED-------");
+    // the priority fee follows the fees paid to write the pools of the route
+    let mut accounts = vec![*owner];
+    accounts.extend(
+        quote
+            .route_plan
+            .iter()
+            .filter_map(|plan| Pubkey::from_str(&plan.swap_info.amm_key).ok()),
+    );
+    let priority_fee = priority_fee(&accounts, SWAP_COMPUTE_UNITS).await;
+
+    let tx = Jupiter::default().swap(quote, owner, priority_fee).await?;
 
     Ok(tx)
 }
 
+/// Transaction creating the associated token account of `owner` for
+/// `mint`, None when it exists already
 pub async fn create_ata_if_needed(
     owner: &Pubkey,
     mint: &Pubkey,
-) -> Result<Transaction> {
+) -> Result<Option<Transaction>> {
     let ata = get_associated_token_address(owner, mint);
-    let rpc_url = env::var("SOLANA_RPC_URL").unwrap_or_else(|_| {
-        "https://api.mainnet-beta.solana.com".to_string()
-    });
-
-    let rpc_client = RpcClient::new(rpc_url);
-    if rpc_client.get_account(&ata).is_err() {
-        println!("⚠️ `ATA {}` не найден! Создаём...", ata);
-
-        let ata_ix = create_associated_token_account(
-            owner, owner, mint, &TOKEN_PROGRAM_ID,
-        );
-
-        let blockhash = rpc_client.get_latest_blockhash()?;
-        let tx = Transaction::new_with_payer(
-            &[ata_ix],
-            Some(owner),
-        );
-
-        println!("✅ `ATA {}` создан.", ata);
-        Ok(tx)
-    } else {
-        println!("✅ `ATA {}` найден, используем существующий.", ata);
-        Ok(Transaction::new_with_payer(&[], Some(owner)))
+    let rpc_client = solana_rpc();
+    if rpc_client.get_account(&ata).await.is_ok() {
+        return Ok(None);
     }
+    tracing::debug!(%ata, "creating associated token account");
+
+    let ata_ix = create_associated_token_account(
+        owner, owner, mint, &TOKEN_PROGRAM_ID,
+    );
+    let mut tx = Transaction::new_with_payer(&[ata_ix], Some(owner));
+    tx.message.recent_blockhash = BLOCKHASH_CACHE.get_blockhash().await?;
+    Ok(Some(tx))
 }
 
 #[cfg(test)]
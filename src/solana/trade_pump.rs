@@ -71,13 +71,14 @@ mod tests {
     use solana_sdk::native_token::sol_to_lamports;
 
     use super::*;
-    use crate::solana::util::{make_rpc_client, make_test_signer};
+    use crate::common::solana_rpc;
+    use crate::solana::util::make_test_signer;
 
     #[tokio::test]
     async fn test_buy_pump_fun() {
         let signer = make_test_signer();
-        let rpc_client = make_rpc_client();
-        let mut tx = create_buy_pump_fun_tx(
+        let rpc_client = solana_rpc();
+        let tx = create_buy_pump_fun_tx(
             "76VCegXJdjqHXBdQyeVV3Swt3JgXrBoQpXcvRQsYpump".to_string(),
             sol_to_lamports(0.0001),
             500,
@@ -86,21 +87,21 @@ mod tests {
         )
         .await
         .unwrap();
-        let result = signer.sign_and_send_solana_transaction(&mut tx).await;
+        let result = signer.sign_and_send_solana_transaction(tx).await;
         assert!(result.is_ok(), "{:?}", result);
     }
 
     #[tokio::test]
     async fn test_sell_pump_fun() {
         let signer = make_test_signer();
-        let mut tx = create_sell_pump_fun_tx(
+        let tx = create_sell_pump_fun_tx(
             "76VCegXJdjqHXBdQyeVV3Swt3JgXrBoQpXcvRQsYpump".to_string(),
             (1. * 1e6) as u64,
             &Pubkey::from_str(&signer.pubkey()).unwrap(),
         )
         .await
         .unwrap();
-        let result = signer.sign_and_send_solana_transaction(&mut tx).await;
+        let result = signer.sign_and_send_solana_transaction(tx).await;
         assert!(result.is_ok(), "{:?}", result);
     }
 }
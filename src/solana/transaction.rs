@@ -1,22 +1,24 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use rand::rngs::ThreadRng;
 use rand::thread_rng;
 use rand::Rng;
 use serde::Deserialize;
 use serde_json::json;
-use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::rpc_client::SerializableTransaction;
 use solana_client::rpc_config::RpcSendTransactionConfig;
-use solana_client::rpc_config::RpcSimulateTransactionConfig;
-use solana_sdk::pubkey::Pubkey;
-use solana_sdk::transaction::Transaction;
-use solana_transaction_status::{
-    Encodable, EncodedTransaction, UiTransactionEncoding,
+use solana_client::rpc_config::{
+    RpcSimulateTransactionAccountsConfig, RpcSimulateTransactionConfig,
 };
+use solana_client::rpc_response::RpcSimulateTransactionResult;
+use solana_sdk::pubkey::Pubkey;
 use std::cell::RefCell;
 use std::str::FromStr;
 use tracing::info;
 
-use crate::solana::util::env;
+use crate::common::{http_client, solana_rpc};
+use crate::config::config;
+use crate::error::Error;
 
 #[derive(Debug, Deserialize)]
 pub struct JitoResponse {
@@ -26,13 +28,13 @@ pub struct JitoResponse {
 }
 
 #[timed::timed(duration(printer = "info!"))]
-pub async fn send_jito_tx(tx: Transaction) -> Result<String> {
-    let client = reqwest::Client::new();
+pub async fn send_jito_tx(
+    tx: &(impl SerializableTransaction + Sync),
+) -> Result<String> {
+    let client = http_client();
 
-    let encoded_tx = match tx.encode(UiTransactionEncoding::Binary) {
-        EncodedTransaction::LegacyBinary(b) => b,
-        _ => return Err(anyhow!("Failed to encode transaction")),
-    };
+    // base58 of the wire format, legacy and v0 transactions alike
+    let encoded_tx = bs58::encode(bincode::serialize(tx)?).into_string();
 
     let res = client
         .post("https://mainnet.block-engine.jito.wtf/api/v1/transactions")
@@ -45,17 +47,19 @@ pub async fn send_jito_tx(tx: Transaction) -> Result<String> {
         }))
         .send()
         .await
-        .expect("send tx");
+        .map_err(Error::from)?;
 
     let jito_response = res.json::<JitoResponse>().await.map_err(|e| {
-        anyhow!("Failed to parse jito response: {}", e.to_string())
+        Error::Rpc(format!("Failed to parse jito response: {}", e))
     })?;
 
     Ok(jito_response.result)
 }
 
-pub async fn send_tx_fallback(tx: &Transaction) -> Result<String> {
-    let rpc_client = RpcClient::new(env("SOLANA_RPC_URL"));
+pub async fn send_tx_fallback(
+    tx: &(impl SerializableTransaction + Sync),
+) -> Result<String> {
+    let rpc_client = solana_rpc();
 
     let signature = rpc_client
         .send_transaction_with_config(
@@ -68,7 +72,7 @@ pub async fn send_tx_fallback(tx: &Transaction) -> Result<String> {
         )
         .await
         .map_err(|e| {
-            anyhow!("Failed to send transaction: {}", e.to_string())
+            Error::Rpc(format!("Failed to send transaction: {}", e))
         })?;
 
     tracing::info!(?signature, "send_tx_fallback");
@@ -76,9 +80,13 @@ pub async fn send_tx_fallback(tx: &Transaction) -> Result<String> {
     Ok(signature.to_string())
 }
 
-pub async fn send_tx(tx: &Transaction) -> Result<String> {
-    if std::env::var("SKIP_SIMULATION").is_err() {
-        let simres = RpcClient::new(env("SOLANA_RPC_URL"))
+/// Sends a signed legacy or versioned transaction through Jito, the RPC
+/// when Jito refuses it
+pub async fn send_tx(
+    tx: &(impl SerializableTransaction + Sync),
+) -> Result<String> {
+    if !config().features.skip_simulation {
+        let simres = solana_rpc()
             .simulate_transaction_with_config(
                 tx,
                 RpcSimulateTransactionConfig {
@@ -86,16 +94,14 @@ pub async fn send_tx(tx: &Transaction) -> Result<String> {
                     ..RpcSimulateTransactionConfig::default()
                 },
             )
-            .await?;
+            .await
+            .map_err(Error::from)?;
         if simres.value.err.is_some() {
-            return Err(anyhow!(
-                "Transaction simulation failed: {:?}",
-                simres
-            ));
+            return Err(Error::Simulation(format!("{:?}", simres)).into());
         }
     }
 
-    let signature = send_jito_tx(tx.clone()).await;
+    let signature = send_jito_tx(tx).await;
     if let Ok(signature) = &signature {
         tracing::info!(?signature, "send_jito_tx");
     }
@@ -109,6 +115,33 @@ pub async fn send_tx(tx: &Transaction) -> Result<String> {
     }
 }
 
+/// Simulates a transaction that is not signed (yet) against the current
+/// blockhash, used to dry-run transactions. The states of `accounts` after
+/// the transaction are returned with the result
+pub async fn simulate_unsigned_tx(
+    tx: &(impl SerializableTransaction + Sync),
+    accounts: &[Pubkey],
+) -> Result<RpcSimulateTransactionResult> {
+    let accounts = (!accounts.is_empty()).then(|| {
+        RpcSimulateTransactionAccountsConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            addresses: accounts.iter().map(Pubkey::to_string).collect(),
+        }
+    });
+    let result = solana_rpc()
+        .simulate_transaction_with_config(
+            tx,
+            RpcSimulateTransactionConfig {
+                sig_verify: false,
+                replace_recent_blockhash: true,
+                accounts,
+                ..RpcSimulateTransactionConfig::default()
+            },
+        )
+        .await?;
+    Ok(result.value)
+}
+
 thread_local! {
     static RNG: RefCell<ThreadRng> = RefCell::new(thread_rng());
 }
@@ -118,19 +151,21 @@ pub fn fast_random_0_to_7() -> u8 {
     RNG.with(|rng| rng.borrow_mut().gen_range(0..8))
 }
 
+pub const JITO_TIP_ACCOUNTS: [&str; 8] = [
+    "96gYZGLnJYVFmbjzopPSU6QiEV5fGqZNyN9nmNhvrZU5",
+    "HFqU5x63VTqvQss8hp11i4wVV8bD44PvwucfZ2bU7gRe",
+    "Cw8CFyM9FkoMi7K7Crf6HNQqf4uEMzpKw6QNghXLvLkY",
+    "ADaUMid9yfUytqMBgopwjb2DTLSokTSzL1zt6iGPaS49",
+    "DfXygSm4jCyNCybVYYK6DwvWqjKee8pbDmJGcLWNDXjh",
+    "ADuUkR4vqLUMWXxW9gh6D6L8pMSawimctcNZ5pGwDcEt",
+    "DttWaMuVvTiduZRnguLF7jNxTgiMBZ1hyAumKUiL2KRL",
+    "3AVi9Tg9Uo68tJfuvoKvqKNWKkC5wPdSSdeBnizKZ6jT",
+];
+
 pub fn get_jito_tip_pubkey() -> Pubkey {
-    const PUBKEYS: [&str; 8] = [
-        "96gYZGLnJYVFmbjzopPSU6QiEV5fGqZNyN9nmNhvrZU5",
-        "HFqU5x63VTqvQss8hp11i4wVV8bD44PvwucfZ2bU7gRe",
-        "Cw8CFyM9FkoMi7K7Crf6HNQqf4uEMzpKw6QNghXLvLkY",
-        "ADaUMid9yfUytqMBgopwjb2DTLSokTSzL1zt6iGPaS49",
-        "DfXygSm4jCyNCybVYYK6DwvWqjKee8pbDmJGcLWNDXjh",
-        "ADuUkR4vqLUMWXxW9gh6D6L8pMSawimctcNZ5pGwDcEt",
-        "DttWaMuVvTiduZRnguLF7jNxTgiMBZ1hyAumKUiL2KRL",
-        "3AVi9Tg9Uo68tJfuvoKvqKNWKkC5wPdSSdeBnizKZ6jT",
-    ];
     let index = fast_random_0_to_7();
-    Pubkey::from_str(PUBKEYS[index as usize]).expect("parse tip pubkey")
+    Pubkey::from_str(JITO_TIP_ACCOUNTS[index as usize])
+        .expect("parse tip pubkey")
 }
 
 #[cfg(test)]
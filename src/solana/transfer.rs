@@ -1,28 +1,88 @@
 use anyhow::Result;
 use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::hash::Hash;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::transaction::Transaction;
 
+use crate::amount::Amount;
+use crate::solana::blockhash::BLOCKHASH_CACHE;
+use crate::solana::priority_fees::{
+    compute_unit_price, SPL_TRANSFER_COMPUTE_UNITS, TRANSFER_COMPUTE_UNITS,
+};
+use crate::solana::util::make_compute_budget_ixs;
+
+/// The transfer paying the priority fee of the session strategy
 pub async fn create_transfer_sol_tx(
     to: &Pubkey,
-    amount: u64,
+    amount: &Amount,
     from: &Pubkey,
 ) -> Result<Transaction> {
-    println!("------------------HERE------------------ 9");
-    let tx = Transaction::new_with_payer(
-        &[solana_sdk::system_instruction::transfer(from, to, amount)],
-        Some(from),
+    let (blockhash, price) = tokio::join!(
+        BLOCKHASH_CACHE.get_blockhash(),
+        compute_unit_price(&[*from, *to], TRANSFER_COMPUTE_UNITS),
     );
-    println!("------------------HERE------------------ 10");
+    transfer_sol_tx(to, amount, from, price, blockhash?)
+}
+
+/// The transfer on a given blockhash, builds the same message for the same
+/// inputs. `compute_unit_price` is in micro-lamports, no priority fee is
+/// paid when None
+pub fn transfer_sol_tx(
+    to: &Pubkey,
+    amount: &Amount,
+    from: &Pubkey,
+    compute_unit_price: Option<u64>,
+    blockhash: Hash,
+) -> Result<Transaction> {
+    let lamports = amount.to_u64()?;
+    let mut instructions = compute_unit_price
+        .map(|price| {
+            make_compute_budget_ixs(price, TRANSFER_COMPUTE_UNITS as u32)
+        })
+        .unwrap_or_default();
+    instructions
+        .push(solana_sdk::system_instruction::transfer(from, to, lamports));
+    let mut tx = Transaction::new_with_payer(&instructions, Some(from));
+    tx.message.recent_blockhash = blockhash;
     Ok(tx)
 }
 
+/// The transfer paying the priority fee of the session strategy
 pub async fn create_transfer_spl_tx(
     to: &Pubkey,
-    amount: u64,
+    amount: &Amount,
     mint: &Pubkey,
     from: &Pubkey,
     rpc_client: &RpcClient,
+) -> Result<Transaction> {
+    let from_ata = spl_associated_token_account::get_associated_token_address(
+        from, mint,
+    );
+    let to_ata =
+        spl_associated_token_account::get_associated_token_address(to, mint);
+    // Check if recipient's ATA exists, if not create it
+    let create_ata = rpc_client.get_account(&to_ata).await.is_err();
+    let (blockhash, price) = tokio::join!(
+        BLOCKHASH_CACHE.get_blockhash(),
+        compute_unit_price(
+            &[*from, from_ata, to_ata],
+            SPL_TRANSFER_COMPUTE_UNITS
+        ),
+    );
+    transfer_spl_tx(to, amount, mint, from, create_ata, price, blockhash?)
+}
+
+/// The transfer on a given blockhash, `create_ata` creates the token
+/// account of the recipient first. `compute_unit_price` is in
+/// micro-lamports, no priority fee is paid when None
+pub fn transfer_spl_tx(
+    to: &Pubkey,
+    amount: &Amount,
+    mint: &Pubkey,
+    from: &Pubkey,
+    create_ata: bool,
+    compute_unit_price: Option<u64>,
+    blockhash: Hash,
 ) -> Result<Transaction> {
     let from_ata = spl_associated_token_account::get_associated_token_address(
         from, mint,
@@ -30,10 +90,13 @@ pub async fn create_transfer_spl_tx(
     let to_ata =
         spl_associated_token_account::get_associated_token_address(to, mint);
 
-    let mut instructions = vec![];
+    let mut instructions = compute_unit_price
+        .map(|price| {
+            make_compute_budget_ixs(price, SPL_TRANSFER_COMPUTE_UNITS as u32)
+        })
+        .unwrap_or_default();
 
-    // Check if recipient's ATA exists, if not create it
-    if rpc_client.get_account(&to_ata).await.is_err() {
+    if create_ata {
         instructions.push(
             spl_associated_token_account::instruction::create_associated_token_account(
                 from,
@@ -44,16 +107,21 @@ pub async fn create_transfer_spl_tx(
         );
     }
 
-    instructions.push(spl_token::instruction::transfer(
+    // checked against the decimals of the mint, an amount scaled for
+    // another token fails instead of moving 10^n times as much
+    instructions.push(spl_token::instruction::transfer_checked(
         &spl_token::id(),
         &from_ata,
+        mint,
         &to_ata,
         from,
         &[],
-        amount,
+        amount.to_u64()?,
+        amount.decimals(),
     )?);
 
-    let tx = Transaction::new_with_payer(&instructions, Some(from));
+    let mut tx = Transaction::new_with_payer(&instructions, Some(from));
+    tx.message.recent_blockhash = blockhash;
 
     Ok(tx)
 }
@@ -66,37 +134,76 @@ mod tests {
     use solana_sdk::pubkey;
 
     use super::*;
-    use crate::solana::util::{make_rpc_client, make_test_signer};
+    use crate::common::solana_rpc;
+    use crate::snapshot::{assert_snapshot, render_message};
+    use crate::solana::util::make_test_signer;
+
+    const FROM: Pubkey =
+        pubkey!("6anbDQNCcVh2f6okexjaX1VGj6tEnizJ1kV5UTBS8Zhi");
+    const TO: Pubkey =
+        pubkey!("2wgo94ZaiUNUkFBSKNaKsUgEANgSdex7gRpFKR39DPzw");
+    const USDC: Pubkey =
+        pubkey!("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v");
+
+    fn blockhash() -> Hash {
+        Hash::from_str("8xzrcjogDrqqjV6RNYjo77eERX1n6EtGu4FXgUMUZoyV")
+            .unwrap()
+    }
+
+    #[test]
+    fn test_transfer_snapshots() {
+        let amount = Amount::new(1_000_000, 9);
+        let tx =
+            transfer_sol_tx(&TO, &amount, &FROM, None, blockhash()).unwrap();
+        assert_snapshot("transfer_sol", &render_message(&tx.message));
+        let tx =
+            transfer_sol_tx(&TO, &amount, &FROM, Some(10_000), blockhash())
+                .unwrap();
+        assert_eq!(tx.message.instructions.len(), 3);
+
+        let amount = Amount::parse("1.5", 6).unwrap();
+        let tx = transfer_spl_tx(
+            &TO,
+            &amount,
+            &USDC,
+            &FROM,
+            true,
+            None,
+            blockhash(),
+        )
+        .unwrap();
+        assert_snapshot("transfer_spl", &render_message(&tx.message));
+    }
 
     #[tokio::test]
     async fn test_transfer_sol() {
         let signer = make_test_signer();
         let owner = Pubkey::from_str(&signer.pubkey()).unwrap();
-        let amount = sol_to_lamports(0.0001);
-        let mut tx = create_transfer_sol_tx(&owner, amount, &owner)
+        let amount = Amount::new(sol_to_lamports(0.0001) as u128, 9);
+        let tx = create_transfer_sol_tx(&owner, &amount, &owner)
             .await
             .unwrap();
-        let result = signer.sign_and_send_solana_transaction(&mut tx).await;
+        let result = signer.sign_and_send_solana_transaction(tx).await;
         assert!(result.is_ok(), "{:?}", result);
     }
 
     #[tokio::test]
     async fn test_transfer_spl() {
         let signer = make_test_signer();
-        let rpc_client = make_rpc_client();
+        let rpc_client = solana_rpc();
         let owner = Pubkey::from_str(&signer.pubkey()).unwrap();
         let mint = pubkey!("Cn5Ne1vmR9ctMGY9z5NC71A3NYFvopjXNyxYtfVYpump");
-        let amount = (10. * 1e6) as u64;
-        let mut tx = create_transfer_spl_tx(
+        let amount = Amount::parse("10", 6).unwrap();
+        let tx = create_transfer_spl_tx(
             &owner,
-            amount,
+            &amount,
             &mint,
             &owner,
             &rpc_client,
         )
         .await
         .unwrap();
-        let result = signer.sign_and_send_solana_transaction(&mut tx).await;
+        let result = signer.sign_and_send_solana_transaction(tx).await;
         assert!(result.is_ok(), "{:?}", result);
     }
 }
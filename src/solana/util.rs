@@ -12,16 +12,25 @@ use solana_sdk::compute_budget::ComputeBudgetInstruction;
 use solana_sdk::instruction::Instruction;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Keypair;
-use solana_sdk::transaction::Transaction;
+use solana_sdk::transaction::{Transaction, VersionedTransaction};
 use std::future::Future;
 use std::io::Write;
 use std::str::FromStr;
 use std::sync::Arc;
-
-use crate::common::wrap_unsafe;
-use crate::signer::solana::LocalSolanaSigner;
+use std::time::Instant;
+
+use crate::activity::{self, ActivityEvent};
+use crate::cancellation::begin_signing;
+use crate::chains::{Chain, CHAINS};
+use crate::gas_tank::GAS_TANK;
+use crate::metrics::record_sign;
+use crate::progress::{report, Stage};
+use crate::signer::local_solana::LocalSolanaSigner;
 use crate::signer::{SignerContext, TransactionSigner};
 
+use super::explain::explain_transaction;
+use super::simulate::{simulate, simulation_enabled};
+
 pub fn env(var: &str) -> String {
     std::env::var(var).unwrap_or_else(|_| panic!("{} env var not set", var))
 }
@@ -125,12 +134,7 @@ pub fn load_keypair_for_tests() -> Keypair {
 }
 
 pub fn make_test_signer() -> Arc<dyn TransactionSigner> {
-    Arc::new(LocalSolanaSigner::new(env("SOLANA_PRIVATE_KEY")))
-}
-
-pub fn make_rpc_client() -> RpcClient {
-    let rpc_url = env("SOLANA_RPC_URL");
-    RpcClient::new(rpc_url)
+    Arc::new(LocalSolanaSigner::from_env().expect("no local Solana signer"))
 }
 
 pub async fn verify_transaction(
@@ -160,6 +164,41 @@ pub fn parse_pubkey(s: &str) -> Result<Pubkey> {
     }
 }
 
+/// Tops up the gas of `owner` and simulates `tx` before it is signed,
+/// returns the explanation of what it does
+async fn before_signing(
+    signer: &dyn TransactionSigner,
+    owner: &Pubkey,
+    tx: &VersionedTransaction,
+) -> Result<String> {
+    if let Err(e) = GAS_TANK.ensure_solana(signer, owner, tx).await {
+        tracing::warn!(?e, %owner, "gas tank top up failed");
+    }
+
+    if simulation_enabled() {
+        let simulation = simulate(tx, owner).await?;
+        tracing::info!(?simulation, "simulated solana transaction");
+        if let Some(failure) = simulation.failure() {
+            return Err(failure.into());
+        }
+    }
+    Ok(explain_transaction(tx, &[], Some(owner)).text())
+}
+
+/// Records the transaction `signature` signed `started` ago
+async fn after_broadcast(
+    signer: &dyn TransactionSigner,
+    started: Instant,
+    signature: &str,
+    explanation: String,
+) {
+    record_sign("sol", started.elapsed());
+    report(Stage::Broadcast, Some(signature.to_string()));
+    let event = ActivityEvent::new(signer, "sol", None, signature)
+        .with_description(Some(explanation));
+    activity::record(signer, event).await;
+}
+
 pub async fn execute_solana_transaction<F, Fut>(
     tx_creator: F,
 ) -> Result<String>
@@ -167,17 +206,44 @@ where
     F: FnOnce(Pubkey) -> Fut + Send + 'static,
     Fut: Future<Output = Result<Transaction>> + Send + 'static,
 {
+    CHAINS.require(Chain::Solana)?;
     let signer = SignerContext::current().await;
-    println!("WE ARE IN EXECUTING");
     let owner = Pubkey::from_str(&signer.pubkey())?;
 
-    let mut tx = wrap_unsafe(move || async move { tx_creator(owner).await })
-        .await
-        .map_err(|e| anyhow!("{:#?}", e))?;
+    let tx = tx_creator(owner).await?;
+    let explanation =
+        before_signing(signer.as_ref(), &owner, &tx.clone().into()).await?;
+    let _signing = begin_signing().await?;
+    tracing::info!(%explanation, "signing solana transaction");
+    report(Stage::Signing, Some(explanation.clone()));
+    let started = Instant::now();
+    let signature = signer.sign_and_send_solana_transaction(tx).await?;
+    after_broadcast(signer.as_ref(), started, &signature, explanation).await;
+    Ok(signature)
+}
+
+/// Like `execute_solana_transaction` for v0 transactions, e.g. the
+/// multi-hop Jupiter routes using address lookup tables
+pub async fn execute_versioned_solana_transaction<F, Fut>(
+    tx_creator: F,
+) -> Result<String>
+where
+    F: FnOnce(Pubkey) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<VersionedTransaction>> + Send + 'static,
+{
+    CHAINS.require(Chain::Solana)?;
+    let signer = SignerContext::current().await;
+    let owner = Pubkey::from_str(&signer.pubkey())?;
 
-    wrap_unsafe(move || async move {
-        signer.sign_and_send_solana_transaction(&mut tx).await
-    })
-    .await
-    .map_err(|e| anyhow!("{:#?}", e))
+    let tx = tx_creator(owner).await?;
+    let explanation = before_signing(signer.as_ref(), &owner, &tx).await?;
+    let _signing = begin_signing().await?;
+    tracing::info!(%explanation, "signing versioned solana transaction");
+    report(Stage::Signing, Some(explanation.clone()));
+    let started = Instant::now();
+    let signature = signer
+        .sign_and_send_versioned_solana_transaction(tx)
+        .await?;
+    after_broadcast(signer.as_ref(), started, &signature, explanation).await;
+    Ok(signature)
 }
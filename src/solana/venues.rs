@@ -0,0 +1,247 @@
+//! Solana swaps are quoted on all venues of venues.solana at once, each
+//! within its own time budget, and executed on the one paying the most. A
+//! slow or failing venue only drops out of the comparison, the swap fails
+//! when none of them quoted
+use anyhow::{anyhow, Result};
+use futures::future::join_all;
+use serde_json::Value;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::VersionedTransaction;
+
+use super::jup::{Jupiter, QuoteResponse};
+use super::okx::{Okx, OkxQuote};
+use super::prefetch::PREFETCHER;
+use super::raydium::{Raydium, RaydiumQuote};
+use crate::config::{config, SolanaVenue};
+use crate::error::Error;
+use crate::revenue::CollectedFee;
+
+#[derive(Debug, Clone)]
+pub enum SwapQuote {
+    Jupiter(QuoteResponse),
+    Raydium(RaydiumQuote),
+    Okx(OkxQuote),
+}
+
+impl SwapQuote {
+    pub fn venue(&self) -> &'static str {
+        match self {
+            Self::Jupiter(_) => "jupiter",
+            Self::Raydium(_) => "raydium",
+            Self::Okx(_) => "okx",
+        }
+    }
+
+    pub fn output_mint(&self) -> &str {
+        match self {
+            Self::Jupiter(quote) => &quote.output_mint,
+            Self::Raydium(quote) => &quote.swap.output_mint,
+            Self::Okx(quote) => &quote.to_token.token_contract_address,
+        }
+    }
+
+    /// Expected output in base units, 0 when the venue sent garbage
+    pub fn out_amount(&self) -> u64 {
+        match self {
+            Self::Jupiter(quote) => &quote.out_amount,
+            Self::Raydium(quote) => &quote.swap.output_amount,
+            Self::Okx(quote) => &quote.to_token_amount,
+        }
+        .parse()
+        .unwrap_or_default()
+    }
+
+    pub fn summary(&self) -> Value {
+        let mut summary = match self {
+            Self::Jupiter(quote) => quote.summary(),
+            Self::Raydium(quote) => quote.summary(),
+            Self::Okx(quote) => quote.summary(),
+        };
+        summary["venue"] = self.venue().into();
+        summary
+    }
+
+    /// Pools the swap writes, its priority fee follows the fees paid to
+    /// write them
+    pub fn pools(&self) -> Vec<Pubkey> {
+        let pools = match self {
+            Self::Jupiter(quote) => quote
+                .route_plan
+                .iter()
+                .map(|plan| plan.swap_info.amm_key.as_str())
+                .collect(),
+            Self::Raydium(quote) => quote
+                .swap
+                .route_plan
+                .iter()
+                .map(|route| route.pool_id.as_str())
+                .collect(),
+            Self::Okx(_) => vec![],
+        };
+        pools
+            .into_iter()
+            .filter_map(|pool| pool.parse().ok())
+            .collect()
+    }
+
+    /// Fee of the operator, only Jupiter takes one
+    pub fn collected_fee(&self) -> Option<CollectedFee> {
+        match self {
+            Self::Jupiter(quote) => CollectedFee::jupiter(quote),
+            Self::Raydium(_) | Self::Okx(_) => None,
+        }
+    }
+
+    /// Unsigned swap transaction of `owner`, a v0 transaction using the
+    /// lookup tables of the route on Jupiter
+    pub async fn transaction(
+        self,
+        owner: &Pubkey,
+        priority_fee_lamports: Option<u64>,
+    ) -> Result<VersionedTransaction> {
+        match self {
+            Self::Jupiter(quote) => {
+                Jupiter::default()
+                    .swap_versioned(quote, owner, priority_fee_lamports)
+                    .await
+            }
+            Self::Raydium(quote) => Ok(Raydium::default()
+                .swap(quote, owner, priority_fee_lamports)
+                .await?
+                .into()),
+            Self::Okx(quote) => Ok(Okx::default()
+                .swap(quote, owner, priority_fee_lamports)
+                .await?
+                .into()),
+        }
+    }
+}
+
+async fn quote_on(
+    venue: SolanaVenue,
+    input_mint: &str,
+    output_mint: &str,
+    amount: u64,
+    slippage_bps: u16,
+) -> Result<SwapQuote> {
+    match venue {
+        SolanaVenue::Jupiter => PREFETCHER
+            .fetch_quote(input_mint, output_mint, amount, slippage_bps)
+            .await
+            .map(SwapQuote::Jupiter),
+        SolanaVenue::Raydium => Raydium::default()
+            .fetch_quote(input_mint, output_mint, amount, slippage_bps)
+            .await
+            .map(SwapQuote::Raydium),
+        SolanaVenue::Okx => Okx::default()
+            .fetch_quote(input_mint, output_mint, amount, slippage_bps)
+            .await
+            .map(SwapQuote::Okx),
+    }
+}
+
+/// Quotes the swap on every venue and returns the best quote
+pub async fn best_quote(
+    input_mint: &str,
+    output_mint: &str,
+    amount: u64,
+    slippage_bps: u16,
+) -> Result<SwapQuote> {
+    let config = config();
+    let venues = &config.venues;
+    let quotes = join_all(venues.solana.iter().map(|&venue| async move {
+        let budget = venues.timeout(venue);
+        let quote = tokio::time::timeout(
+            budget,
+            quote_on(venue, input_mint, output_mint, amount, slippage_bps),
+        )
+        .await
+        .unwrap_or_else(|_| {
+            Err(Error::Quote(format!(
+                "{:?} didn't quote within {:?}",
+                venue, budget
+            ))
+            .into())
+        });
+        (venue, quote)
+    }))
+    .await;
+    pick_best(quotes)
+}
+
+/// Highest output wins, ties go to the venue listed first. The error of
+/// the first venue is returned when none quoted
+fn pick_best(
+    quotes: Vec<(SolanaVenue, Result<SwapQuote>)>,
+) -> Result<SwapQuote> {
+    let mut best: Option<SwapQuote> = None;
+    let mut first_error = None;
+    for (venue, quote) in quotes {
+        match quote {
+            Ok(quote) => {
+                tracing::debug!(
+                    ?venue,
+                    out_amount = quote.out_amount(),
+                    "quoted"
+                );
+                if best.as_ref().map_or(true, |best| {
+                    quote.out_amount() > best.out_amount()
+                }) {
+                    best = Some(quote);
+                }
+            }
+            Err(e) => {
+                tracing::warn!(?venue, ?e, "venue didn't quote");
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    best.ok_or_else(|| {
+        first_error.unwrap_or_else(|| anyhow!("No venue to quote on"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::fixture;
+
+    fn jupiter() -> Result<SwapQuote> {
+        Ok(SwapQuote::Jupiter(serde_json::from_value(fixture(
+            "jupiter/quote",
+        ))?))
+    }
+
+    fn raydium() -> Result<SwapQuote> {
+        Ok(SwapQuote::Raydium(RaydiumQuote::from_response(fixture(
+            "raydium/quote",
+        ))?))
+    }
+
+    #[test]
+    fn test_pick_best() {
+        // 236581 on Raydium against 236512 on Jupiter
+        let best = pick_best(vec![
+            (SolanaVenue::Jupiter, jupiter()),
+            (SolanaVenue::Raydium, raydium()),
+        ])
+        .unwrap();
+        assert_eq!(best.venue(), "raydium");
+        assert_eq!(best.summary()["venue"], "raydium");
+
+        let timeout = Err(anyhow!("Raydium didn't quote within 1.5s"));
+        let best = pick_best(vec![
+            (SolanaVenue::Jupiter, jupiter()),
+            (SolanaVenue::Raydium, timeout),
+        ])
+        .unwrap();
+        assert_eq!(best.venue(), "jupiter");
+
+        let error = pick_best(vec![
+            (SolanaVenue::Jupiter, Err(anyhow!("Jupiter Quote Error"))),
+            (SolanaVenue::Raydium, Err(anyhow!("ROUTE_NOT_FOUND"))),
+        ])
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Jupiter Quote Error");
+    }
+}
@@ -0,0 +1,540 @@
+//! Sonic Gateway, the canonical Ethereum <-> Sonic bridge. A transfer is a
+//! deposit (Ethereum) or withdrawal (Sonic) that becomes claimable on the
+//! other chain once its state oracle has caught up with the source block,
+//! the claim carries a storage proof of the transfer from the source chain
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use alloy::network::TransactionBuilder;
+use alloy::primitives::aliases::U96;
+use alloy::primitives::{address, keccak256, Address, Bytes, B256, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::{TransactionReceipt, TransactionRequest};
+use alloy::sol;
+use alloy::sol_types::SolEvent;
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+
+use crate::evm::abi::IERC20;
+use crate::evm::util::{make_provider_for_chain, EvmProvider};
+
+pub const ETHEREUM_CHAIN_ID: u64 = 1;
+pub const SONIC_CHAIN_ID: u64 = 146;
+
+// Ethereum side
+pub const TOKEN_DEPOSIT: Address =
+    address!("a1E2481a9CD0Cb0447EeB1cbc26F1b3fff3bec20");
+pub const TOKEN_PAIRS: Address =
+    address!("f2b1510c2709072C88C5b14db90Ec3b6297193e4");
+pub const ETH_STATE_ORACLE: Address =
+    address!("B7e8CC3F5FeA12443136f0cc13D81F109B2dEd7f");
+
+// Sonic side
+pub const BRIDGE: Address =
+    address!("9Ef7629F9B930168b76283AdD7120777b3c895b3");
+pub const SONIC_STATE_ORACLE: Address =
+    address!("836664B0c0CB29B7877bCcF94159CC996528F2C3");
+
+/// Storage slots of the `deposits` / `withdrawals` mappings the proofs are
+/// generated for
+const DEPOSITS_SLOT: u64 = 7;
+const WITHDRAWALS_SLOT: u64 = 1;
+
+sol! {
+    #[sol(rpc)]
+    interface ITokenPairs {
+        function originalToMinted(address original) external view returns (address);
+        function mintedToOriginal(address minted) external view returns (address);
+    }
+
+    #[sol(rpc)]
+    interface ITokenDeposit {
+        event Deposit(uint256 indexed id, address indexed owner, address token, uint256 amount);
+
+        function deposit(uint96 uid, address token, uint256 amount) external;
+        function claim(uint256 id, address token, uint256 amount, bytes calldata proof) external;
+    }
+
+    #[sol(rpc)]
+    interface IBridge {
+        event Withdrawal(uint256 indexed id, address indexed owner, address token, uint256 amount);
+
+        function withdraw(uint96 uid, address token, uint256 amount) external;
+        function claim(uint256 id, address token, uint256 amount, bytes calldata proof) external;
+    }
+
+    #[sol(rpc)]
+    interface IStateOracle {
+        function lastBlockNum() external view returns (uint256);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GatewayDirection {
+    ToSonic,
+    ToEthereum,
+}
+
+impl FromStr for GatewayDirection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "to_sonic" | "sonic" | "deposit" => Ok(Self::ToSonic),
+            "to_ethereum" | "ethereum" | "eth" | "withdraw" => {
+                Ok(Self::ToEthereum)
+            }
+            _ => Err(anyhow!("Invalid direction: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for GatewayDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ToSonic => write!(f, "to_sonic"),
+            Self::ToEthereum => write!(f, "to_ethereum"),
+        }
+    }
+}
+
+impl GatewayDirection {
+    pub fn source_chain_id(&self) -> u64 {
+        match self {
+            Self::ToSonic => ETHEREUM_CHAIN_ID,
+            Self::ToEthereum => SONIC_CHAIN_ID,
+        }
+    }
+
+    pub fn destination_chain_id(&self) -> u64 {
+        match self {
+            Self::ToSonic => SONIC_CHAIN_ID,
+            Self::ToEthereum => ETHEREUM_CHAIN_ID,
+        }
+    }
+
+    /// Contract on the source chain that holds the transfer record
+    fn source_contract(&self) -> Address {
+        match self {
+            Self::ToSonic => TOKEN_DEPOSIT,
+            Self::ToEthereum => BRIDGE,
+        }
+    }
+
+    /// State oracle on the destination chain tracking the source chain
+    fn destination_oracle(&self) -> Address {
+        match self {
+            Self::ToSonic => SONIC_STATE_ORACLE,
+            Self::ToEthereum => ETH_STATE_ORACLE,
+        }
+    }
+
+    fn mapping_slot(&self) -> u64 {
+        match self {
+            Self::ToSonic => DEPOSITS_SLOT,
+            Self::ToEthereum => WITHDRAWALS_SLOT,
+        }
+    }
+}
+
+/// A deposit or withdrawal as recorded on the source chain, `token` is
+/// always the original (Ethereum) token address
+#[derive(Debug, Clone, Serialize)]
+pub struct GatewayTransfer {
+    pub direction: GatewayDirection,
+    pub id: String,
+    pub token: String,
+    pub amount: String,
+    pub block_number: u64,
+    pub tx_hash: String,
+}
+
+pub fn ethereum_provider() -> Result<EvmProvider> {
+    make_provider_for_chain(ETHEREUM_CHAIN_ID)
+}
+
+pub fn sonic_provider() -> Result<EvmProvider> {
+    make_provider_for_chain(SONIC_CHAIN_ID)
+}
+
+pub async fn original_token(
+    minted: Address,
+    eth_provider: &EvmProvider,
+) -> Result<Address> {
+    let original = ITokenPairs::new(TOKEN_PAIRS, eth_provider)
+        .mintedToOriginal(minted)
+        .call()
+        .await?
+        ._0;
+    if original.is_zero() {
+        return Err(anyhow!(
+            "Token {} is not bridged by the gateway",
+            minted
+        ));
+    }
+    Ok(original)
+}
+
+pub async fn minted_token(
+    original: Address,
+    eth_provider: &EvmProvider,
+) -> Result<Address> {
+    let minted = ITokenPairs::new(TOKEN_PAIRS, eth_provider)
+        .originalToMinted(original)
+        .call()
+        .await?
+        ._0;
+    if minted.is_zero() {
+        return Err(anyhow!(
+            "Token {} is not supported by the gateway",
+            original
+        ));
+    }
+    Ok(minted)
+}
+
+fn make_uid() -> Result<U96> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+    Ok(U96::from(nanos as u64))
+}
+
+async fn make_tx(
+    chain_id: u64,
+    to: Address,
+    owner: Address,
+    call: &impl alloy::sol_types::SolCall,
+    provider: &EvmProvider,
+) -> Result<TransactionRequest> {
+    let gas_price = provider
+        .get_gas_price()
+        .await
+        .context("Failed to get gas price")?;
+
+    Ok(TransactionRequest::default()
+        .with_from(owner)
+        .with_to(to)
+        .with_call(call)
+        .with_chain_id(chain_id)
+        .with_gas_price(gas_price))
+}
+
+/// Returns the approval the transfer needs first, if any. Only deposits
+/// need one as TokenDeposit pulls the token, the Bridge burns minted tokens
+/// directly
+pub async fn create_approve_if_needed_tx(
+    direction: GatewayDirection,
+    token: Address,
+    amount: U256,
+    owner: Address,
+    provider: &EvmProvider,
+) -> Result<Option<TransactionRequest>> {
+    if direction == GatewayDirection::ToEthereum {
+        return Ok(None);
+    }
+    let spender = direction.source_contract();
+    let allowance = IERC20::new(token, provider)
+        .allowance(owner, spender)
+        .call()
+        .await?
+        ._0;
+    if allowance >= amount {
+        return Ok(None);
+    }
+
+    let call = IERC20::approveCall {
+        spender,
+        amount: U256::MAX,
+    };
+    let tx =
+        make_tx(direction.source_chain_id(), token, owner, &call, provider)
+            .await?;
+    Ok(Some(tx))
+}
+
+/// Deposit on Ethereum, `token` is the original token address
+pub async fn create_deposit_tx(
+    token: Address,
+    amount: U256,
+    owner: Address,
+    eth_provider: &EvmProvider,
+) -> Result<TransactionRequest> {
+    let call = ITokenDeposit::depositCall {
+        uid: make_uid()?,
+        token,
+        amount,
+    };
+    make_tx(ETHEREUM_CHAIN_ID, TOKEN_DEPOSIT, owner, &call, eth_provider)
+        .await
+}
+
+/// Withdrawal on Sonic, `original` is the Ethereum token address of the
+/// minted token that gets burned
+pub async fn create_withdraw_tx(
+    original: Address,
+    amount: U256,
+    owner: Address,
+    sonic_provider: &EvmProvider,
+) -> Result<TransactionRequest> {
+    let call = IBridge::withdrawCall {
+        uid: make_uid()?,
+        token: original,
+        amount,
+    };
+    make_tx(SONIC_CHAIN_ID, BRIDGE, owner, &call, sonic_provider).await
+}
+
+/// The signers only return the hash, the receipt is needed for the transfer
+/// id so it is polled from the source chain
+pub async fn wait_for_receipt(
+    tx_hash: &str,
+    provider: &EvmProvider,
+) -> Result<TransactionReceipt> {
+    const MAX_ATTEMPTS: u32 = 60;
+    const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+    let hash = B256::from_str(tx_hash)?;
+    for _ in 0..MAX_ATTEMPTS {
+        if let Some(receipt) = provider.get_transaction_receipt(hash).await? {
+            if !receipt.status() {
+                return Err(anyhow!("Transaction {} reverted", tx_hash));
+            }
+            return Ok(receipt);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    Err(anyhow!("Timed out waiting for transaction {}", tx_hash))
+}
+
+/// Reads the Deposit/Withdrawal event of a gateway transaction
+pub fn parse_transfer(
+    direction: GatewayDirection,
+    receipt: &TransactionReceipt,
+) -> Result<GatewayTransfer> {
+    let block_number = receipt
+        .block_number
+        .ok_or_else(|| anyhow!("Receipt without block number"))?;
+    let tx_hash = receipt.transaction_hash.to_string();
+
+    for log in receipt.inner.logs() {
+        if log.address() != direction.source_contract() {
+            continue;
+        }
+        let (id, token, amount) = match direction {
+            GatewayDirection::ToSonic => {
+                match log.log_decode::<ITokenDeposit::Deposit>() {
+                    Ok(event) => {
+                        let e = event.inner.data;
+                        (e.id, e.token, e.amount)
+                    }
+                    Err(_) => continue,
+                }
+            }
+            GatewayDirection::ToEthereum => {
+                match log.log_decode::<IBridge::Withdrawal>() {
+                    Ok(event) => {
+                        let e = event.inner.data;
+                        (e.id, e.token, e.amount)
+                    }
+                    Err(_) => continue,
+                }
+            }
+        };
+        return Ok(GatewayTransfer {
+            direction,
+            id: id.to_string(),
+            token: token.to_string(),
+            amount: amount.to_string(),
+            block_number,
+            tx_hash,
+        });
+    }
+
+    Err(anyhow!(
+        "No {} event in transaction {}",
+        match direction {
+            GatewayDirection::ToSonic => ITokenDeposit::Deposit::SIGNATURE,
+            GatewayDirection::ToEthereum => IBridge::Withdrawal::SIGNATURE,
+        },
+        tx_hash
+    ))
+}
+
+/// Source chain block the destination state oracle has reached, transfers up
+/// to this block can be claimed
+pub async fn oracle_block(
+    direction: GatewayDirection,
+    destination_provider: &EvmProvider,
+) -> Result<u64> {
+    let block = IStateOracle::new(
+        direction.destination_oracle(),
+        destination_provider,
+    )
+    .lastBlockNum()
+    .call()
+    .await?
+    ._0;
+    Ok(block.to::<u64>())
+}
+
+/// Storage key of `mapping(uint256 => ..)` entry `id` at `slot`
+pub fn mapping_key(id: U256, slot: u64) -> B256 {
+    let mut preimage = [0u8; 64];
+    preimage[..32].copy_from_slice(&id.to_be_bytes::<32>());
+    preimage[32..].copy_from_slice(&U256::from(slot).to_be_bytes::<32>());
+    keccak256(preimage)
+}
+
+/// Proof of the transfer record on the source chain at the block the oracle
+/// has, encoded as rlp([rlp(account_proof), rlp(storage_proof)])
+pub async fn generate_proof(
+    direction: GatewayDirection,
+    id: U256,
+    block_number: u64,
+    source_provider: &EvmProvider,
+) -> Result<Bytes> {
+    let key = mapping_key(id, direction.mapping_slot());
+    let proof = source_provider
+        .get_proof(direction.source_contract(), vec![key])
+        .block_id(block_number.into())
+        .await?;
+    let storage_proof = proof
+        .storage_proof
+        .first()
+        .ok_or_else(|| anyhow!("Empty storage proof"))?;
+
+    Ok(rlp_encode_list(&[
+        rlp_encode_list(&proof.account_proof),
+        rlp_encode_list(&storage_proof.proof),
+    ]))
+}
+
+pub async fn create_claim_tx(
+    transfer: &GatewayTransfer,
+    owner: Address,
+) -> Result<TransactionRequest> {
+    let direction = transfer.direction;
+    let source = make_provider_for_chain(direction.source_chain_id())?;
+    let destination =
+        make_provider_for_chain(direction.destination_chain_id())?;
+
+    let oracle_block = oracle_block(direction, &destination).await?;
+    if oracle_block < transfer.block_number {
+        return Err(anyhow!(
+            "Transfer is not claimable yet, the state oracle is at block {} and the transfer is in block {}",
+            oracle_block,
+            transfer.block_number
+        ));
+    }
+
+    let id = U256::from_str(&transfer.id)?;
+    let proof = generate_proof(direction, id, oracle_block, &source).await?;
+    let token = Address::from_str(&transfer.token)?;
+    let amount = U256::from_str(&transfer.amount)?;
+
+    match direction {
+        GatewayDirection::ToSonic => {
+            let call = IBridge::claimCall {
+                id,
+                token,
+                amount,
+                proof,
+            };
+            make_tx(SONIC_CHAIN_ID, BRIDGE, owner, &call, &destination).await
+        }
+        GatewayDirection::ToEthereum => {
+            let call = ITokenDeposit::claimCall {
+                id,
+                token,
+                amount,
+                proof,
+            };
+            make_tx(
+                ETHEREUM_CHAIN_ID,
+                TOKEN_DEPOSIT,
+                owner,
+                &call,
+                &destination,
+            )
+            .await
+        }
+    }
+}
+
+fn rlp_encode_length(len: usize, offset: u8) -> Vec<u8> {
+    if len < 56 {
+        return vec![offset + len as u8];
+    }
+    let len_bytes = len.to_be_bytes();
+    let len_bytes =
+        &len_bytes[len_bytes.iter().take_while(|b| **b == 0).count()..];
+    let mut out = vec![offset + 55 + len_bytes.len() as u8];
+    out.extend_from_slice(len_bytes);
+    out
+}
+
+fn rlp_encode_bytes(bytes: &[u8]) -> Vec<u8> {
+    if bytes.len() == 1 && bytes[0] < 0x80 {
+        return bytes.to_vec();
+    }
+    let mut out = rlp_encode_length(bytes.len(), 0x80);
+    out.extend_from_slice(bytes);
+    out
+}
+
+/// rlp list of byte strings, the proofs are lists of encoded trie nodes
+fn rlp_encode_list(items: &[Bytes]) -> Bytes {
+    let payload: Vec<u8> = items
+        .iter()
+        .flat_map(|item| rlp_encode_bytes(item))
+        .collect();
+    let mut out = rlp_encode_length(payload.len(), 0xc0);
+    out.extend(payload);
+    out.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rlp_encode_list() {
+        // rlp(["cat", "dog"])
+        let encoded = rlp_encode_list(&[
+            Bytes::from_static(b"cat"),
+            Bytes::from_static(b"dog"),
+        ]);
+        assert_eq!(
+            encoded.to_vec(),
+            vec![0xc8, 0x83, b'c', b'a', b't', 0x83, b'd', b'o', b'g']
+        );
+
+        // long strings use the length-of-length prefix
+        let long = Bytes::from(vec![0xffu8; 60]);
+        let encoded = rlp_encode_list(&[long]);
+        assert_eq!(&encoded[..4], &[0xf8, 62, 0xb8, 60]);
+    }
+
+    #[test]
+    fn test_mapping_key() {
+        // keccak256(abi.encode(uint256(0), uint256(0)))
+        assert_eq!(
+            mapping_key(U256::ZERO, 0).to_string(),
+            "0xad3228b676f7d3cd4284a5443f17f1962b36e491b30a40b2405849e597ba5fb5"
+        );
+    }
+
+    #[test]
+    fn test_direction_from_str() {
+        assert_eq!(
+            GatewayDirection::from_str("to_sonic").unwrap(),
+            GatewayDirection::ToSonic
+        );
+        assert_eq!(
+            GatewayDirection::from_str("ETH").unwrap(),
+            GatewayDirection::ToEthereum
+        );
+        assert!(GatewayDirection::from_str("arb").is_err());
+    }
+}
@@ -0,0 +1,3 @@
+pub mod gateway;
+pub mod points;
+pub mod tools;
@@ -0,0 +1,122 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::common::http_client;
+
+const DEFAULT_POINTS_API_URL: &str =
+    "https://www.data-openblocklabs.com/sonic/user-points-stats";
+
+/// Raw stats as returned by the points API, every field is optional as the
+/// API omits the ones the address never earned
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PointsStats {
+    #[serde(default)]
+    pub sonic_points: f64,
+    #[serde(default)]
+    pub passive_liquidity_points: f64,
+    #[serde(default)]
+    pub active_liquidity_points: f64,
+    #[serde(default)]
+    pub ecosystem_points: f64,
+    #[serde(default)]
+    pub loyalty_multiplier: f64,
+    pub rank: Option<u64>,
+    pub user_activity_last_detected: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SonicPointsSummary {
+    pub address: String,
+    pub total_points: f64,
+    pub passive_points: f64,
+    pub active_points: f64,
+    pub ecosystem_points: f64,
+    pub loyalty_multiplier: f64,
+    pub rank: Option<u64>,
+    pub last_activity: Option<String>,
+    /// activities the address is not earning from yet
+    pub eligible_activities: Vec<String>,
+}
+
+pub async fn fetch_points_stats(address: &str) -> Result<PointsStats> {
+    let url = std::env::var("SONIC_POINTS_API_URL")
+        .unwrap_or_else(|_| DEFAULT_POINTS_API_URL.to_string());
+    let response = http_client()
+        .get(url)
+        .query(&[("wallet_address", address)])
+        .header("accept", "application/json")
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Failed to fetch Sonic points: {}",
+            response.text().await?
+        ));
+    }
+
+    Ok(response.json().await?)
+}
+
+pub fn summarize(address: &str, stats: PointsStats) -> SonicPointsSummary {
+    let mut eligible_activities = vec![];
+    if stats.passive_liquidity_points == 0. {
+        eligible_activities.push(
+            "Hold whitelisted assets (S, wS, stS, USDC.e, scUSD, WETH) in the wallet to earn passive points"
+                .to_string(),
+        );
+    }
+    if stats.active_liquidity_points == 0. {
+        eligible_activities.push(
+            "Deploy whitelisted assets as liquidity on Sonic apps to earn 2x active points"
+                .to_string(),
+        );
+    }
+    if stats.ecosystem_points == 0. {
+        eligible_activities.push(
+            "Use Sonic ecosystem apps that distribute Gems towards the airdrop"
+                .to_string(),
+        );
+    }
+
+    SonicPointsSummary {
+        address: address.to_string(),
+        total_points: stats.sonic_points,
+        passive_points: stats.passive_liquidity_points,
+        active_points: stats.active_liquidity_points,
+        ecosystem_points: stats.ecosystem_points,
+        loyalty_multiplier: stats.loyalty_multiplier,
+        rank: stats.rank,
+        last_activity: stats.user_activity_last_detected,
+        eligible_activities,
+    }
+}
+
+pub async fn get_points_summary(address: &str) -> Result<SonicPointsSummary> {
+    let stats = fetch_points_stats(address).await?;
+    Ok(summarize(address, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize_partial_stats() {
+        let stats: PointsStats = serde_json::from_str(
+            r#"{
+                "sonic_points": 1523.5,
+                "passive_liquidity_points": 1523.5,
+                "loyalty_multiplier": 1.2,
+                "rank": 42017
+            }"#,
+        )
+        .unwrap();
+
+        let summary =
+            summarize("0x2fAA30d5EdDF1e4fa126aEdA79159878D58A2438", stats);
+        assert_eq!(summary.total_points, 1523.5);
+        assert_eq!(summary.rank, Some(42017));
+        assert_eq!(summary.eligible_activities.len(), 2);
+    }
+}
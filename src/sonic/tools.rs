@@ -0,0 +1,251 @@
+use std::str::FromStr;
+
+use alloy::primitives::{Address, U256};
+use anyhow::Result;
+use rig_tool_macro::tool;
+use serde_json::Value;
+
+use crate::common::with_explorer_link;
+use crate::confirmation::confirm_or_execute;
+use crate::evm::util::{execute_evm_transaction, make_provider_for_chain};
+use crate::jobs::{Job, RetryPolicy, JOBS};
+use crate::notify::{Notification, NotificationKind, NOTIFIER};
+use crate::progress::{report, Stage};
+use crate::risk::{assess, TradeIntent};
+use crate::signer::{SignerContext, SignerRef};
+use crate::validation;
+
+use super::gateway::{
+    create_approve_if_needed_tx, create_claim_tx, create_deposit_tx,
+    create_withdraw_tx, ethereum_provider, original_token, parse_transfer,
+    sonic_provider, wait_for_receipt, GatewayDirection,
+};
+use super::points::{get_points_summary, SonicPointsSummary};
+
+pub const GATEWAY_CLAIM_JOB: &str = "gateway_claim";
+/// Claims are tried from 5 minutes after the transfer, for about 5 hours
+const CLAIM_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: 12,
+    backoff_secs: 5 * 60,
+    max_backoff_secs: 30 * 60,
+};
+
+#[tool(description = "
+Bridges a token between Ethereum and Sonic through the canonical Sonic Gateway,
+preferred over third-party bridges for large amounts.
+
+direction is either to_sonic (deposit from Ethereum) or to_ethereum
+(withdrawal from Sonic)
+token_address is the token on the source chain
+amount accounts for decimals, e.g. 1000000 for 1 USDC
+
+The transfer is claimed on the destination chain by a background job once the
+gateway has synced, this takes around 15 minutes to Sonic and up to an hour to
+Ethereum. claim_job is the id of the job, get_job returns its status
+")]
+pub async fn bridge_via_gateway(
+    direction: String,
+    token_address: String,
+    amount: String,
+) -> Result<String> {
+    let summary = format!(
+        "Bridge {} of token {} {} through the Sonic Gateway",
+        amount, token_address, direction
+    );
+    let direction = GatewayDirection::from_str(&direction)?;
+    let source_chain = match direction {
+        GatewayDirection::ToSonic => "eth",
+        GatewayDirection::ToEthereum => "sonic",
+    };
+    validation::address(source_chain, "token_address", &token_address)?;
+    validation::raw_amount("amount", &amount)?;
+    validation::amount_decimals(source_chain, &token_address, &amount)
+        .await?;
+    // the gateway credits the same address on the destination chain
+    let destination = SignerContext::current().await.address();
+    let assessment = assess(
+        TradeIntent::new(source_chain)
+            .spend(&token_address, &amount)
+            .destination(&destination),
+    )
+    .await?;
+    let summary = assessment.annotate(summary);
+    let token = Address::from_str(&token_address)?;
+    let amount = U256::from_str(&amount)?;
+
+    confirm_or_execute("bridge_via_gateway", summary, move || async move {
+        let owner =
+            Address::from_str(&SignerContext::current().await.address())?;
+
+        // withdrawals are keyed by the original token, deposits already are
+        let original = match direction {
+            GatewayDirection::ToSonic => token,
+            GatewayDirection::ToEthereum => {
+                original_token(token, &ethereum_provider()?).await?
+            }
+        };
+
+        let provider = make_provider_for_chain(direction.source_chain_id())?;
+        let approve_tx = create_approve_if_needed_tx(
+            direction, token, amount, owner, &provider,
+        )
+        .await?;
+        if let Some(approve_tx) = approve_tx {
+            let tx_hash =
+                execute_evm_transaction(
+                    move |_| async move { Ok(approve_tx) },
+                )
+                .await?;
+            wait_for_receipt(&tx_hash, &provider).await?;
+        }
+
+        let tx_hash = execute_evm_transaction(move |owner| async move {
+            match direction {
+                GatewayDirection::ToSonic => {
+                    create_deposit_tx(
+                        original,
+                        amount,
+                        owner,
+                        &ethereum_provider()?,
+                    )
+                    .await
+                }
+                GatewayDirection::ToEthereum => {
+                    create_withdraw_tx(
+                        original,
+                        amount,
+                        owner,
+                        &sonic_provider()?,
+                    )
+                    .await
+                }
+            }
+        })
+        .await?;
+
+        let receipt = wait_for_receipt(&tx_hash, &provider).await?;
+        let transfer = parse_transfer(direction, &receipt)?;
+        report(Stage::Confirmed, None);
+        assessment.record().await;
+        notify_bridge(
+            "Sonic Gateway transfer sent",
+            format!(
+                "The transfer ({}) is confirmed on the source chain, it can be claimed once the gateway synced",
+                direction
+            ),
+            serde_json::to_value(&transfer)?,
+        )
+        .await;
+        let mut result = serde_json::to_value(&transfer)?;
+        result["claim_job"] = queue_claim(direction, &tx_hash).await?.into();
+        Ok(result.to_string())
+    })
+    .await
+}
+
+#[tool(description = "
+Claims a Sonic Gateway transfer on the destination chain by hand, transfers
+sent with bridge_via_gateway are claimed by a background job.
+
+direction is the same as in bridge_via_gateway
+tx_hash is the hash of the deposit/withdrawal returned by bridge_via_gateway
+
+Fails with the current sync status when the transfer is not claimable yet
+")]
+pub async fn claim_gateway_transfer(
+    direction: String,
+    tx_hash: String,
+) -> Result<String> {
+    let summary = format!(
+        "Claim the Sonic Gateway transfer {} ({})",
+        tx_hash, direction
+    );
+    let direction = GatewayDirection::from_str(&direction)?;
+
+    confirm_or_execute(
+        "claim_gateway_transfer",
+        summary,
+        move || async move { claim(direction, tx_hash).await },
+    )
+    .await
+}
+
+async fn claim(
+    direction: GatewayDirection,
+    tx_hash: String,
+) -> Result<String> {
+    let provider = make_provider_for_chain(direction.source_chain_id())?;
+    let receipt = wait_for_receipt(&tx_hash, &provider).await?;
+    let transfer = parse_transfer(direction, &receipt)?;
+
+    let hash = execute_evm_transaction(move |owner| async move {
+        create_claim_tx(&transfer, owner).await
+    })
+    .await?;
+    let chain = match direction {
+        GatewayDirection::ToSonic => "sonic",
+        GatewayDirection::ToEthereum => "eth",
+    };
+    notify_bridge(
+        "Sonic Gateway transfer claimed",
+        format!("The transfer ({}) was claimed on {}", direction, chain),
+        serde_json::json!({ "tx_hash": hash, "chain": chain }),
+    )
+    .await;
+    Ok(with_explorer_link(chain, &hash))
+}
+
+/// Queues the claim of a transfer sent by the current signer, it is
+/// retried until the gateway synced. Previews send nothing to claim
+async fn queue_claim(
+    direction: GatewayDirection,
+    tx_hash: &str,
+) -> Result<Option<String>> {
+    let signer = SignerContext::current().await;
+    if signer.is_paper() {
+        return Ok(None);
+    }
+    let job = Job::new(
+        GATEWAY_CLAIM_JOB,
+        serde_json::json!({
+            "direction": direction.to_string(),
+            "tx_hash": tx_hash,
+        }),
+    )
+    .with_owner(SignerRef::of(signer.as_ref()))
+    .with_retry(CLAIM_RETRY)
+    .with_delay(CLAIM_RETRY.delay(1));
+    Ok(Some(JOBS.enqueue(job).await?))
+}
+
+/// Runs a queued gateway claim under the signer of its owner
+pub async fn run_claim_job(job: Job) -> Result<String> {
+    let direction = GatewayDirection::from_str(
+        job.payload["direction"].as_str().unwrap_or_default(),
+    )?;
+    let tx_hash = job.payload["tx_hash"].as_str().unwrap_or_default();
+    claim(direction, tx_hash.to_string()).await
+}
+
+/// Tells the user their gateway transfer changed status
+async fn notify_bridge(title: &str, body: String, data: Value) {
+    let user_id = SignerContext::current().await.user_id();
+    let notification = Notification::new(
+        NotificationKind::BridgeStatus,
+        user_id,
+        title,
+        body,
+    )
+    .with_data(data);
+    NOTIFIER.notify(&notification).await;
+}
+
+#[tool(description = "
+Returns the Sonic points (airdrop) stats of the wallet: total, passive,
+active and ecosystem points, the loyalty multiplier, the rank and the
+activities the wallet could do to earn more
+")]
+pub async fn get_sonic_points() -> Result<SonicPointsSummary> {
+    let address = SignerContext::current().await.address();
+    get_points_summary(&address).await
+}
@@ -0,0 +1,82 @@
+//! Registry of the canonical routers and spenders of the venues the agent
+//! trades on. Approvals and swaps built from third party quotes are checked
+//! against it and a route sending value to a contract that isn't listed
+//! raises a warning for the user to review before confirming
+use alloy::primitives::Address;
+use uniswap_sdk_core::prelude::SWAP_ROUTER_02_ADDRESSES;
+
+use crate::risk::RiskWarning;
+
+/// Program of the Jupiter v6 aggregator
+pub const JUPITER_PROGRAM: &str =
+    "JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4";
+
+/// Spender of EVM routes, on every chain without a chain id
+struct KnownSpender {
+    chain_id: Option<u64>,
+    address: &'static str,
+    name: &'static str,
+}
+
+const KNOWN_SPENDERS: &[KnownSpender] = &[
+    KnownSpender {
+        chain_id: None,
+        address: "0x1231DEB6f5749EF6cE6943a275A1D3E7486F4EaE",
+        name: "LiFi diamond",
+    },
+    KnownSpender {
+        chain_id: None,
+        address: "0x000000000022D473030F116dDEE9F6B43aC78BA3",
+        name: "Permit2",
+    },
+    KnownSpender {
+        chain_id: Some(146),
+        address: "0x1D368773735ee1E678950B7A97bcA2CafB330CDc",
+        name: "Shadow router",
+    },
+];
+
+/// Name of `address` when it is a known spender on `chain_id`
+pub fn known_spender(chain_id: u64, address: &str) -> Option<&'static str> {
+    let address = address.parse::<Address>().ok()?;
+    if SWAP_ROUTER_02_ADDRESSES.get(&chain_id) == Some(&address) {
+        return Some("Uniswap swap router");
+    }
+    KNOWN_SPENDERS
+        .iter()
+        .filter(|spender| spender.chain_id.map_or(true, |id| id == chain_id))
+        .find(|spender| spender.address.parse().ok() == Some(address))
+        .map(|spender| spender.name)
+}
+
+/// Warning for `address` when it isn't a known spender on `chain_id`
+pub fn check_spender(chain_id: u64, address: &str) -> Option<RiskWarning> {
+    known_spender(chain_id, address).is_none().then(|| {
+        RiskWarning::UnknownSpender {
+            chain_id,
+            address: address.to_string(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_spender() {
+        let diamond = "0x1231deb6f5749ef6ce6943a275a1d3e7486f4eae";
+        assert_eq!(known_spender(42161, diamond), Some("LiFi diamond"));
+        let shadow = "0x1D368773735ee1E678950B7A97bcA2CafB330CDc";
+        assert_eq!(known_spender(146, shadow), Some("Shadow router"));
+        assert_eq!(known_spender(1, shadow), None);
+        assert!(check_spender(146, diamond).is_none());
+        assert_eq!(
+            check_spender(146, shadow.replace('1', "2").as_str()),
+            Some(RiskWarning::UnknownSpender {
+                chain_id: 146,
+                address: shadow.replace('1', "2"),
+            })
+        );
+    }
+}
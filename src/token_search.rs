@@ -0,0 +1,319 @@
+//! Token search by name or symbol, to tell apart the dozens of tokens
+//! sharing a symbol like PEPE. Candidates come from the DexScreener pools
+//! and, on Solana, from the Jupiter token search. A token is verified when
+//! Jupiter verified it or, on EVM chains, when it is in the LiFi token list.
+//! Verified tokens come first, then exact symbol matches, then the most
+//! liquid ones
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use futures::future::join;
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+
+use crate::chains::Chain;
+use crate::common::http_client;
+use crate::cross_chain::lifi::LiFi;
+use crate::dexscreener::{search_ticker, PairInfo};
+use crate::metrics::observe_api;
+use crate::pricing::{lifi_chain, networks};
+use crate::tool_error::ToolError;
+
+const JUPITER_SEARCH_API: &str = "https://lite-api.jup.ag/tokens/v2/search";
+/// Chains the search covers, DexScreener pools of other chains are skipped
+const CHAINS: &[&str] = &["sol", "sonic", "eth", "arb", "base"];
+const MAX_CANDIDATES: usize = 10;
+/// How long the LiFi token list of a chain is kept
+const LIST_TTL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TokenCandidate {
+    pub chain: String,
+    pub address: String,
+    pub symbol: String,
+    pub name: String,
+    /// liquidity of the pools of the token
+    pub liquidity_usd: f64,
+    pub volume_24h_usd: f64,
+    pub verified: bool,
+    pub sources: Vec<&'static str>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JupiterToken {
+    id: String,
+    name: String,
+    symbol: String,
+    #[serde(default)]
+    is_verified: Option<bool>,
+    #[serde(default)]
+    liquidity: Option<f64>,
+}
+
+type VerifiedList = (Instant, Arc<HashSet<String>>);
+
+/// Lowercased addresses of the LiFi token lists, by chain
+static LIFI_LISTS: Lazy<Mutex<HashMap<String, VerifiedList>>> =
+    Lazy::new(Default::default);
+
+fn matches(query: &str, symbol: &str, name: &str) -> bool {
+    symbol.to_lowercase().contains(query)
+        || name.to_lowercase().contains(query)
+}
+
+/// Crate name of a DexScreener chain
+fn chain_of(dexscreener_chain: &str) -> Option<&'static str> {
+    CHAINS.iter().copied().find(|chain| {
+        networks(chain).map_or(false, |(name, _)| name == dexscreener_chain)
+    })
+}
+
+/// Tokens of the pools matching the query, with the liquidity and volume
+/// of all their pools
+fn from_pools(query: &str, pairs: &[PairInfo]) -> Vec<TokenCandidate> {
+    let mut candidates = HashMap::<(String, String), TokenCandidate>::new();
+    for pair in pairs {
+        let Some(chain) = chain_of(&pair.chain_id) else {
+            continue;
+        };
+        for token in [&pair.base_token, &pair.quote_token] {
+            if !matches(query, &token.symbol, &token.name) {
+                continue;
+            }
+            let candidate = candidates
+                .entry((chain.to_string(), token.address.to_lowercase()))
+                .or_insert_with(|| TokenCandidate {
+                    chain: chain.to_string(),
+                    address: token.address.clone(),
+                    symbol: token.symbol.clone(),
+                    name: token.name.clone(),
+                    liquidity_usd: 0.,
+                    volume_24h_usd: 0.,
+                    verified: false,
+                    sources: vec!["dexscreener"],
+                });
+            candidate.liquidity_usd += pair.liquidity.usd;
+            candidate.volume_24h_usd += pair.volume.h24;
+        }
+    }
+    candidates.into_values().collect()
+}
+
+/// Merges the candidates of the sources, best first
+fn rank(
+    query: &str,
+    candidates: impl IntoIterator<Item = TokenCandidate>,
+) -> Vec<TokenCandidate> {
+    let mut merged = HashMap::<(String, String), TokenCandidate>::new();
+    for candidate in candidates {
+        let key = (candidate.chain.clone(), candidate.address.to_lowercase());
+        match merged.get_mut(&key) {
+            Some(existing) => {
+                existing.verified |= candidate.verified;
+                existing.liquidity_usd =
+                    existing.liquidity_usd.max(candidate.liquidity_usd);
+                existing.volume_24h_usd =
+                    existing.volume_24h_usd.max(candidate.volume_24h_usd);
+                existing.sources.extend(candidate.sources);
+            }
+            None => {
+                merged.insert(key, candidate);
+            }
+        }
+    }
+    let mut ranked = merged.into_values().collect::<Vec<_>>();
+    ranked.sort_by(|a, b| {
+        b.verified
+            .cmp(&a.verified)
+            .then_with(|| {
+                let exact =
+                    |c: &TokenCandidate| c.symbol.to_lowercase() == query;
+                exact(b).cmp(&exact(a))
+            })
+            .then_with(|| b.liquidity_usd.total_cmp(&a.liquidity_usd))
+    });
+    ranked.truncate(MAX_CANDIDATES);
+    ranked
+}
+
+async fn jupiter_search(query: &str) -> Result<Vec<TokenCandidate>> {
+    let url = reqwest::Url::parse_with_params(
+        JUPITER_SEARCH_API,
+        &[("query", query)],
+    )?;
+    let tokens = observe_api("jupiter", async {
+        Ok(http_client()
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Vec<JupiterToken>>()
+            .await?)
+    })
+    .await?;
+    Ok(tokens
+        .into_iter()
+        .filter(|token| matches(query, &token.symbol, &token.name))
+        .map(|token| TokenCandidate {
+            chain: "sol".to_string(),
+            address: token.id,
+            symbol: token.symbol,
+            name: token.name,
+            liquidity_usd: token.liquidity.unwrap_or_default(),
+            volume_24h_usd: 0.,
+            verified: token.is_verified.unwrap_or(false),
+            sources: vec!["jupiter"],
+        })
+        .collect())
+}
+
+/// Addresses of the LiFi token list of an EVM chain
+async fn lifi_list(chain: &str) -> Result<Arc<HashSet<String>>> {
+    if let Some((fetched_at, list)) = LIFI_LISTS.lock().unwrap().get(chain) {
+        if fetched_at.elapsed() < LIST_TTL {
+            return Ok(list.clone());
+        }
+    }
+    let chain_id = lifi_chain(chain);
+    let response = LiFi::new(None).get_tokens(chain_id, None, None).await?;
+    let list = Arc::new(
+        response
+            .tokens
+            .get(chain_id)
+            .into_iter()
+            .flatten()
+            .map(|token| token.address.to_lowercase())
+            .collect::<HashSet<_>>(),
+    );
+    LIFI_LISTS
+        .lock()
+        .unwrap()
+        .insert(chain.to_string(), (Instant::now(), list.clone()));
+    Ok(list)
+}
+
+/// Candidates for `query` on `chain`, every supported chain when None
+pub async fn search_tokens(
+    query: &str,
+    chain: Option<&str>,
+) -> Result<Vec<TokenCandidate>> {
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return Err(ToolError::invalid_input("query is required").into());
+    }
+    let chain = chain.map(|chain| match Chain::of_key(chain) {
+        Chain::Solana => "sol",
+        Chain::Evm => chain,
+    });
+    if let Some(chain) = chain {
+        if !CHAINS.contains(&chain) {
+            return Err(ToolError::invalid_input(format!(
+                "Token search doesn't cover {}",
+                chain
+            ))
+            .with_hint(format!("Use one of {}", CHAINS.join(", ")))
+            .into());
+        }
+    }
+    let on_solana =
+        chain.map_or(true, |chain| Chain::of_key(chain) == Chain::Solana);
+    let (pools, jupiter) = join(search_ticker(query.clone()), async {
+        if on_solana {
+            jupiter_search(&query).await
+        } else {
+            Ok(vec![])
+        }
+    })
+    .await;
+
+    let mut candidates = from_pools(&query, &pools?.pairs);
+    match jupiter {
+        Ok(tokens) => candidates.extend(tokens),
+        Err(e) => tracing::warn!(?e, "Jupiter token search failed"),
+    }
+    candidates.retain(|candidate| {
+        chain.map_or(true, |chain| candidate.chain == chain)
+    });
+
+    for candidate in candidates
+        .iter_mut()
+        .filter(|c| Chain::of_key(&c.chain) == Chain::Evm)
+    {
+        match lifi_list(&candidate.chain).await {
+            Ok(list) => {
+                candidate.verified =
+                    list.contains(&candidate.address.to_lowercase());
+            }
+            Err(e) => {
+                tracing::warn!(?e, chain = %candidate.chain, "no token list")
+            }
+        }
+    }
+    Ok(rank(&query, candidates))
+}
+
+#[tool(description = "
+Searches tokens by name or symbol and returns the candidates with their
+chain, address, liquidity, 24h volume and whether a token registry verified
+them. Use it whenever the user names a token by symbol, e.g. buy PEPE, many
+tokens share a symbol. Prefer verified and liquid tokens and ask the user
+when several candidates are plausible.
+
+chain is sol, sonic, eth, arb or base, empty searches all of them
+")]
+pub async fn search_token(
+    query: String,
+    chain: String,
+) -> Result<Vec<TokenCandidate>> {
+    let chain = chain.trim().to_lowercase();
+    search_tokens(&query, (!chain.is_empty()).then_some(chain.as_str())).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(
+        symbol: &str,
+        address: &str,
+        liquidity_usd: f64,
+        verified: bool,
+    ) -> TokenCandidate {
+        TokenCandidate {
+            chain: "sol".to_string(),
+            address: address.to_string(),
+            symbol: symbol.to_string(),
+            name: format!("{} token", symbol),
+            liquidity_usd,
+            volume_24h_usd: 0.,
+            verified,
+            sources: vec!["dexscreener"],
+        }
+    }
+
+    #[test]
+    fn test_rank() {
+        let ranked = rank(
+            "pepe",
+            vec![
+                candidate("PEPE2", "a", 9_000_000., false),
+                candidate("PEPE", "b", 10_000., false),
+                candidate("PEPE", "c", 50_000., false),
+                // the same token found by Jupiter
+                TokenCandidate {
+                    verified: true,
+                    sources: vec!["jupiter"],
+                    ..candidate("PEPE", "c", 40_000., false)
+                },
+            ],
+        );
+        let addresses = ranked.iter().map(|c| c.address.as_str());
+        assert_eq!(addresses.collect::<Vec<_>>(), ["c", "b", "a"]);
+        assert_eq!(ranked[0].sources, ["dexscreener", "jupiter"]);
+        assert_eq!(ranked[0].liquidity_usd, 50_000.);
+    }
+}
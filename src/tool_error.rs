@@ -0,0 +1,232 @@
+//! Errors as the LLM sees them. Every failed tool call is turned into a
+//! `ToolError` serialized as `{code, message, hint, retryable}` so the model
+//! can decide between retrying, asking the user and giving up instead of
+//! guessing from a raw error string
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{code_of_message, Error};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidInput,
+    NotFound,
+    Expired,
+    InsufficientFunds,
+    SlippageExceeded,
+    RiskRejected,
+    AccessDenied,
+    Unauthorized,
+    RateLimited,
+    Timeout,
+    Cancelled,
+    Duplicate,
+    Unavailable,
+    Network,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidInput => "invalid_input",
+            Self::NotFound => "not_found",
+            Self::Expired => "expired",
+            Self::InsufficientFunds => "insufficient_funds",
+            Self::SlippageExceeded => "slippage_exceeded",
+            Self::RiskRejected => "risk_rejected",
+            Self::AccessDenied => "access_denied",
+            Self::Unauthorized => "unauthorized",
+            Self::RateLimited => "rate_limited",
+            Self::Timeout => "timeout",
+            Self::Cancelled => "cancelled",
+            Self::Duplicate => "duplicate",
+            Self::Unavailable => "unavailable",
+            Self::Network => "network",
+            Self::Internal => "internal",
+        }
+    }
+
+    fn retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::Expired
+                | Self::SlippageExceeded
+                | Self::RateLimited
+                | Self::Timeout
+                | Self::Network
+        )
+    }
+
+    fn default_hint(&self) -> Option<&'static str> {
+        let hint = match self {
+            Self::InvalidInput => {
+                "Check the arguments against the tool description, ask the user if a value is unclear"
+            }
+            Self::Expired => "Fetch a fresh quote or action and try again",
+            Self::InsufficientFunds => {
+                "Check the balance and ask the user for a smaller amount"
+            }
+            Self::SlippageExceeded => {
+                "The price moved, retry with a fresh quote or ask the user for a higher slippage"
+            }
+            Self::AccessDenied => {
+                "The tool is not available to this user, do not retry"
+            }
+            Self::Unauthorized => "The user has to log in again",
+            Self::Cancelled => {
+                "The user cancelled the call, do not retry unless they ask again"
+            }
+            Self::Duplicate => {
+                "Check the balances and recent transactions with the user before trying again"
+            }
+            Self::RateLimited => "Wait a moment before retrying",
+            Self::Unavailable => {
+                "The service is down, do not retry now, tell the user to try again in a few minutes"
+            }
+            Self::Timeout | Self::Network => "Retry once, then tell the user",
+            _ => return None,
+        };
+        Some(hint)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub hint: Option<String>,
+    pub retryable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+impl ToolError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            hint: code.default_hint().map(str::to_string),
+            retryable: code.retryable(),
+            details: None,
+        }
+    }
+
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidInput, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    /// Turns any error a tool returned into a `ToolError`. Errors that
+    /// already are one are passed through (they arrive wrapped by rig, so
+    /// they are recognized by their JSON), typed `Error`s keep their code
+    /// and the rest is classified by message
+    pub fn from_anyhow(error: &anyhow::Error) -> Self {
+        if let Some(tool_error) = error
+            .chain()
+            .find_map(|cause| serde_json::from_str(&cause.to_string()).ok())
+        {
+            return tool_error;
+        }
+        if let Some(error) = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<Error>())
+        {
+            return error.into();
+        }
+
+        let mut message = error.to_string();
+        while let Some(inner) = message.strip_prefix("ToolCallError: ") {
+            message = inner.to_string();
+        }
+        let code =
+            code_of_message(&message).unwrap_or_else(|| classify(&message));
+        Self::new(code, message)
+    }
+}
+
+/// Best effort mapping of the raw errors of the RPCs, Privy, Jupiter and
+/// LiFi to a code
+pub(crate) fn classify(message: &str) -> ErrorCode {
+    let lower = message.to_lowercase();
+    let has = |needles: &[&str]| needles.iter().any(|n| lower.contains(n));
+
+    if has(&["not available in this session"]) {
+        ErrorCode::AccessDenied
+    } else if has(&["insufficient", "not enough"]) {
+        ErrorCode::InsufficientFunds
+    } else if has(&["slippage", "0x1771"]) {
+        ErrorCode::SlippageExceeded
+    } else if has(&["expired"]) {
+        ErrorCode::Expired
+    } else if has(&["429", "rate limit", "too many requests"]) {
+        ErrorCode::RateLimited
+    } else if has(&["timed out", "timeout"]) {
+        ErrorCode::Timeout
+    } else if has(&["unauthorized", "401", "invalid token"]) {
+        ErrorCode::Unauthorized
+    } else if has(&["error sending request", "connection", "dns"]) {
+        ErrorCode::Network
+    } else if has(&["not found"]) {
+        ErrorCode::NotFound
+    } else if has(&["invalid", "parse", "missing field", "expected"]) {
+        ErrorCode::InvalidInput
+    } else {
+        ErrorCode::Internal
+    }
+}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match serde_json::to_string(self) {
+            Ok(json) => write!(f, "{}", json),
+            Err(_) => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ToolError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_from_anyhow() {
+        let error = ToolError::from_anyhow(&anyhow!(
+            "ToolCallError: ToolCallError: Quote 1a2b expired, fetch a new quote"
+        ));
+        assert_eq!(error.code, ErrorCode::Expired);
+        assert!(error.retryable);
+        assert_eq!(error.message, "Quote 1a2b expired, fetch a new quote");
+
+        let error = ToolError::from_anyhow(&anyhow!("Invalid pubkey"));
+        assert_eq!(error.code, ErrorCode::InvalidInput);
+        assert!(!error.retryable);
+    }
+
+    #[test]
+    fn test_tool_errors_pass_through() {
+        let original = ToolError::not_found("Quote 1a2b not found")
+            .with_hint("List the quotes first");
+        let wrapped = anyhow::Error::new(original.clone()).context("failed");
+        assert_eq!(ToolError::from_anyhow(&wrapped), original);
+    }
+}
@@ -0,0 +1,716 @@
+//! Trade plans: a list of swaps and transfers the user agrees to once and
+//! that run in order, e.g. selling every holding into USDC. Every leg is
+//! validated before anything runs, then each one goes through the dispatch
+//! pipeline on its own (access checks, execution mode, rate limits, audit)
+//! and is quoted when it runs, so amounts like "all" see the balances left
+//! by the previous legs. A single Jupiter swap is one transaction that
+//! fills or reverts as a whole, but a route of chained swaps failing midway
+//! (e.g. a leg exceeding its slippage) leaves the wallet holding the
+//! intermediate token, the report then offers the swaps completing or
+//! unwinding the route for the amount the last executed swap produced
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use solana_sdk::signature::Signature;
+
+use crate::address::Address;
+use crate::amount::{self, UserAmount};
+use crate::chains::{Chain, CHAINS};
+use crate::confirmation::{confirm_or_execute, preconfirmed};
+use crate::dispatch::dispatch;
+use crate::history::tx_hashes;
+use crate::signer::SignerContext;
+use crate::solana::balance::transaction_balances;
+use crate::solana::constants::WSOL;
+use crate::tool_error::{ErrorCode, ToolError};
+use crate::validation;
+
+const MAX_LEGS: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TradeLeg {
+    Swap {
+        chain: String,
+        input_token: String,
+        amount: String,
+        output_token: String,
+        /// 0 uses the default slippage of the session
+        #[serde(default)]
+        slippage_bps: u16,
+    },
+    Transfer {
+        chain: String,
+        token: String,
+        amount: String,
+        recipient: String,
+    },
+}
+
+impl fmt::Display for TradeLeg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Swap {
+                chain,
+                input_token,
+                amount,
+                output_token,
+                ..
+            } => write!(
+                f,
+                "swap {} of {} for {} on {}",
+                amount, input_token, output_token, chain
+            ),
+            Self::Transfer {
+                chain,
+                token,
+                amount,
+                recipient,
+            } => write!(
+                f,
+                "transfer {} of {} to {} on {}",
+                amount, token, recipient, chain
+            ),
+        }
+    }
+}
+
+/// Solana legs run on Jupiter, EVM legs on the chain the agent is
+/// configured for
+fn check_chain(chain: &str) -> Result<()> {
+    if chain == crate::common::evm_chain() || chain == "sol" {
+        return CHAINS.require(Chain::of_key(chain));
+    }
+    Err(ToolError::invalid_input(format!(
+        "Trade plans don't support the chain {}",
+        chain
+    ))
+    .with_hint("Use multichain_swap for legs on other chains")
+    .into())
+}
+
+impl TradeLeg {
+    fn chain(&self) -> &str {
+        match self {
+            Self::Swap { chain, .. } | Self::Transfer { chain, .. } => chain,
+        }
+    }
+
+    /// Checks what can be checked before anything runs, balances are only
+    /// known once the previous legs ran
+    pub fn validate(&self) -> Result<()> {
+        let chain = self.chain();
+        check_chain(chain)?;
+        match self {
+            Self::Swap {
+                input_token,
+                amount,
+                output_token,
+                slippage_bps,
+                ..
+            } => {
+                validation::address(chain, "input_token", input_token)?;
+                validation::address(chain, "output_token", output_token)?;
+                UserAmount::from_str(amount)?;
+                if *slippage_bps != 0 {
+                    validation::slippage_bps(*slippage_bps)?;
+                }
+            }
+            Self::Transfer {
+                token,
+                amount,
+                recipient,
+                ..
+            } => {
+                validation::address(chain, "token", token)?;
+                validation::address(chain, "recipient", recipient)?;
+                UserAmount::from_str(amount)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The tool running the leg and its arguments
+    pub fn tool_call(&self) -> (&'static str, Value) {
+        let native = match self.chain() {
+            "sol" => WSOL,
+            _ => "0x0000000000000000000000000000000000000000",
+        };
+        match self {
+            Self::Swap {
+                chain,
+                input_token,
+                amount,
+                output_token,
+                slippage_bps,
+            } if chain == "sol" => (
+                "perform_jupiter_swap",
+                json!({
+                    "input_mint": input_token,
+                    "input_amount": amount,
+                    "output_mint": output_token,
+                    "slippage_bps": slippage_bps,
+                }),
+            ),
+            Self::Swap {
+                input_token,
+                amount,
+                output_token,
+                ..
+            } => (
+                "trade",
+                json!({
+                    "input_token_address": input_token,
+                    "input_amount": amount,
+                    "output_token_address": output_token,
+                }),
+            ),
+            Self::Transfer {
+                chain,
+                token,
+                amount,
+                recipient,
+            } if chain == "sol" => {
+                if token == native {
+                    (
+                        "transfer_sol",
+                        json!({ "to": recipient, "amount": amount }),
+                    )
+                } else {
+                    (
+                        "transfer_spl_token",
+                        json!({ "to": recipient, "amount": amount, "mint": token }),
+                    )
+                }
+            }
+            Self::Transfer {
+                token,
+                amount,
+                recipient,
+                ..
+            } => {
+                if token.eq_ignore_ascii_case(native) {
+                    (
+                        "transfer_eth",
+                        json!({ "recipient": recipient, "amount": amount }),
+                    )
+                } else {
+                    (
+                        "transfer_erc20",
+                        json!({
+                            "recipient": recipient,
+                            "token_address": token,
+                            "amount": amount,
+                        }),
+                    )
+                }
+            }
+        }
+    }
+}
+
+/// Calls the tool of a leg with the arguments from `TradeLeg::tool_call`
+async fn invoke(tool: &str, args: &Value) -> Result<String> {
+    let arg =
+        |name: &str| args[name].as_str().unwrap_or_default().to_string();
+    match tool {
+        "perform_jupiter_swap" => {
+            crate::solana::tools::perform_jupiter_swap(
+                arg("input_mint"),
+                arg("input_amount"),
+                arg("output_mint"),
+                args["slippage_bps"].as_u64().unwrap_or_default() as u16,
+            )
+            .await
+        }
+        "transfer_sol" => {
+            crate::solana::tools::transfer_sol(arg("to"), arg("amount")).await
+        }
+        "transfer_spl_token" => {
+            crate::solana::tools::transfer_spl_token(
+                arg("to"),
+                arg("amount"),
+                arg("mint"),
+            )
+            .await
+        }
+        "trade" => {
+            crate::evm::tools::trade(
+                arg("input_token_address"),
+                arg("input_amount"),
+                arg("output_token_address"),
+            )
+            .await
+        }
+        "transfer_eth" => {
+            crate::evm::tools::transfer_eth(arg("recipient"), arg("amount"))
+                .await
+        }
+        "transfer_erc20" => {
+            crate::evm::tools::transfer_erc20(
+                arg("recipient"),
+                arg("token_address"),
+                arg("amount"),
+            )
+            .await
+        }
+        tool => Err(anyhow!("{} is not available in this build", tool)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LegStatus {
+    Executed,
+    Failed,
+    /// not run as an earlier leg failed
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LegReport {
+    pub leg: String,
+    pub tool: String,
+    pub status: LegStatus,
+    pub result: Option<Value>,
+    pub error: Option<ToolError>,
+}
+
+/// Follow-up of a route of Solana swaps that failed midway
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Recovery {
+    /// the intermediate token the wallet is left with
+    pub token: String,
+    /// its balance, None when it couldn't be fetched
+    pub held: Option<String>,
+    /// the executed swap that left it, None when the result of the leg has
+    /// no signature
+    pub tx: Option<String>,
+    /// swaps it into the final token of the route
+    pub complete: TradeLeg,
+    /// swaps it back into the token the route started from, None when the
+    /// route started from it
+    pub unwind: Option<TradeLeg>,
+}
+
+impl Recovery {
+    /// Sets the amount both follow-up swaps sell
+    fn set_amount(&mut self, amount: &str) {
+        for leg in std::iter::once(&mut self.complete).chain(&mut self.unwind)
+        {
+            if let TradeLeg::Swap {
+                amount: swapped, ..
+            } = leg
+            {
+                *swapped = amount.to_string();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PlanReport {
+    pub executed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub legs: Vec<LegReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery: Option<Recovery>,
+}
+
+impl PlanReport {
+    fn push(&mut self, leg: &TradeLeg, tool: &str, result: Result<String>) {
+        let (status, result, error) = match result {
+            // tools return serialized JSON, plain strings are passed as is
+            Ok(result) => (
+                LegStatus::Executed,
+                Some(
+                    serde_json::from_str(&result)
+                        .unwrap_or(Value::String(result)),
+                ),
+                None,
+            ),
+            Err(e) => {
+                (LegStatus::Failed, None, Some(ToolError::from_anyhow(&e)))
+            }
+        };
+        match status {
+            LegStatus::Executed => self.executed += 1,
+            LegStatus::Failed => self.failed += 1,
+            LegStatus::Skipped => self.skipped += 1,
+        }
+        self.legs.push(LegReport {
+            leg: leg.to_string(),
+            tool: tool.to_string(),
+            status,
+            result,
+            error,
+        });
+    }
+
+    fn skip(&mut self, leg: &TradeLeg) {
+        self.skipped += 1;
+        self.legs.push(LegReport {
+            leg: leg.to_string(),
+            tool: leg.tool_call().0.to_string(),
+            status: LegStatus::Skipped,
+            result: None,
+            error: None,
+        });
+    }
+}
+
+/// Runs the legs in order, after a failure the remaining ones are skipped
+/// unless `continue_on_error`
+pub async fn run_plan<F, Fut>(
+    legs: &[TradeLeg],
+    continue_on_error: bool,
+    run_leg: F,
+) -> PlanReport
+where
+    F: Fn(&'static str, Value) -> Fut,
+    Fut: std::future::Future<Output = Result<String>>,
+{
+    let mut report = PlanReport::default();
+    for leg in legs {
+        if report.failed > 0 && !continue_on_error {
+            report.skip(leg);
+            continue;
+        }
+        let (tool, args) = leg.tool_call();
+        let result = run_leg(tool, args).await;
+        report.push(leg, tool, result);
+    }
+    report
+}
+
+/// Input and output tokens of a Jupiter swap leg
+fn solana_swap(leg: &TradeLeg) -> Option<(&str, &str)> {
+    match leg {
+        TradeLeg::Swap {
+            chain,
+            input_token,
+            output_token,
+            ..
+        } if chain == "sol" => Some((input_token, output_token)),
+        _ => None,
+    }
+}
+
+/// Recovery of the first failed Jupiter swap whose input token was left by
+/// an executed swap of the plan. The route is followed back through the
+/// executed swaps to the token it started from and forward through the
+/// legs that didn't run to the token it was meant to end in. The swaps
+/// sell the whole balance until `held_recovery` sets the amount produced
+pub fn recovery(legs: &[TradeLeg], report: &PlanReport) -> Option<Recovery> {
+    let status = |i: usize| report.legs.get(i).map(|leg| leg.status);
+    // the executed swap before `before` that output `token`
+    let producer = |before: usize, token: &str| {
+        (0..before).rev().find(|&i| {
+            status(i) == Some(LegStatus::Executed)
+                && solana_swap(&legs[i]).map(|(_, output)| output)
+                    == Some(token)
+        })
+    };
+    (0..legs.len()).find_map(|failed| {
+        if status(failed) != Some(LegStatus::Failed) {
+            return None;
+        }
+        let (token, _) = solana_swap(&legs[failed])?;
+        let mut first = producer(failed, token)?;
+        let tx = report.legs[first]
+            .result
+            .as_ref()
+            .and_then(|result| tx_hashes(&result.to_string()).pop());
+        let mut start = solana_swap(&legs[first])?.0;
+        while let Some(i) = producer(first, start) {
+            first = i;
+            start = solana_swap(&legs[i])?.0;
+        }
+        let mut end = token;
+        for leg in &legs[failed..] {
+            if let Some((input, output)) = solana_swap(leg) {
+                if input == end {
+                    end = output;
+                }
+            }
+        }
+        let slippage_bps = match &legs[failed] {
+            TradeLeg::Swap { slippage_bps, .. } => *slippage_bps,
+            TradeLeg::Transfer { .. } => 0,
+        };
+        let swap_into = |output: &str| TradeLeg::Swap {
+            chain: "sol".to_string(),
+            input_token: token.to_string(),
+            amount: "all".to_string(),
+            output_token: output.to_string(),
+            slippage_bps,
+        };
+        Some(Recovery {
+            token: token.to_string(),
+            held: None,
+            tx,
+            complete: swap_into(end),
+            unwind: (start != token).then(|| swap_into(start)),
+        })
+    })
+}
+
+/// Raw amount of `token` the Solana transaction `tx` of the current
+/// signer left in its wallet
+async fn produced(tx: &str, token: &str) -> Result<u128> {
+    let signer = SignerContext::current().await;
+    let owner = Address::of_signer(signer.as_ref(), Chain::Solana)?;
+    let balances = transaction_balances(&Signature::from_str(tx)?).await?;
+    balances
+        .changes(&owner.to_string())
+        .into_iter()
+        .find(|change| change.mint == token && change.amount > 0)
+        .map(|change| change.amount as u128)
+        .ok_or_else(|| anyhow!("{} didn't leave any {}", tx, token))
+}
+
+/// Recovery of the report with the balance of the intermediate token, None
+/// when the wallet doesn't hold any. The swaps sell what the executed swap
+/// produced, at most the balance, tokens held before the route stay
+async fn held_recovery(mut recovery: Recovery) -> Option<Recovery> {
+    let held = match amount::resolve("sol", &recovery.token, "all").await {
+        Ok(held) => Some(held),
+        Err(e) => {
+            if ToolError::from_anyhow(&e).code == ErrorCode::InsufficientFunds
+            {
+                return None;
+            }
+            tracing::warn!(?e, token = %recovery.token, "balance not fetched");
+            None
+        }
+    };
+    let produced = match &recovery.tx {
+        Some(tx) => match produced(tx, &recovery.token).await {
+            Ok(produced) => Some(produced),
+            Err(e) => {
+                tracing::warn!(?e, %tx, "produced amount not fetched");
+                None
+            }
+        },
+        None => None,
+    };
+    let amount = match (produced, held.as_ref().map(|held| held.raw())) {
+        (Some(produced), Some(held)) => Some(produced.min(held)),
+        (produced, held) => produced.or(held),
+    };
+    if let Some(amount) = amount {
+        recovery.set_amount(&format!("{} raw", amount));
+    }
+    recovery.held = held.map(|held| held.to_string());
+    Some(recovery)
+}
+
+/// Runs a leg through the dispatch pipeline like a tool call of the LLM
+pub(crate) async fn dispatch_leg(
+    tool: &'static str,
+    args: Value,
+) -> Result<String> {
+    dispatch(tool, args.to_string(), move |_| async move {
+        invoke(tool, &args)
+            .await
+            .map_err(|e| ToolError::from_anyhow(&e))
+    })
+    .await
+}
+
+#[tool(description = "
+Validates and executes a list of swaps and transfers in order with a single
+confirmation, e.g. to sell several tokens into USDC. Each swap is quoted when
+its turn comes.
+
+legs is a JSON array, every leg is one of:
+- {\"kind\": \"swap\", \"chain\": \"sol\", \"input_token\": mint,
+  \"amount\": \"all\", \"output_token\": mint, \"slippage_bps\": 50}
+- {\"kind\": \"transfer\", \"chain\": \"sol\", \"token\": mint,
+  \"amount\": \"10\", \"recipient\": address}
+chain is sol or the EVM chain of the agent, tokens are mints or addresses.
+Native SOL is the wrapped SOL mint, the native EVM token is the zero address.
+amount is as the user said it, e.g. 0.5, half, 25% or all. slippage_bps 0
+uses the default slippage of the session
+
+on_error is stop (the default, the remaining legs are skipped after a
+failure) or continue
+
+Returns a report with the result or the error of every leg. When chained
+Solana swaps failed midway the report has a recovery: the intermediate token
+left in the wallet and the legs completing (complete) or unwinding (unwind)
+the route for the amount the route left, offer both to the user and run the
+chosen one as a new plan
+")]
+pub async fn execute_trade_plan(
+    legs: String,
+    on_error: String,
+) -> Result<String> {
+    let legs: Vec<TradeLeg> = serde_json::from_str(&legs).map_err(|e| {
+        ToolError::invalid_input(format!("Invalid legs: {}", e))
+    })?;
+    if legs.is_empty() || legs.len() > MAX_LEGS {
+        return Err(ToolError::invalid_input(format!(
+            "A plan has between 1 and {} legs",
+            MAX_LEGS
+        ))
+        .into());
+    }
+    let continue_on_error = match on_error.trim() {
+        "" | "stop" => false,
+        "continue" => true,
+        other => {
+            return Err(ToolError::invalid_input(format!(
+                "Invalid on_error {}, use stop or continue",
+                other
+            ))
+            .into())
+        }
+    };
+    for (i, leg) in legs.iter().enumerate() {
+        leg.validate().map_err(|e| {
+            anyhow!("Leg {} ({}) is invalid: {}", i + 1, leg, e)
+        })?;
+    }
+
+    let summary = legs
+        .iter()
+        .enumerate()
+        .map(|(i, leg)| format!("{}. {}", i + 1, leg))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let summary = format!(
+        "Run a trade plan, {} on error:\n{}",
+        if continue_on_error {
+            "continue"
+        } else {
+            "stop"
+        },
+        summary
+    );
+    confirm_or_execute("execute_trade_plan", summary, move || async move {
+        let mut report = preconfirmed(async {
+            Ok(run_plan(&legs, continue_on_error, dispatch_leg).await)
+        })
+        .await?;
+        if let Some(recovery) = recovery(&legs, &report) {
+            report.recovery = held_recovery(recovery).await;
+        }
+        Ok(serde_json::to_string(&report)?)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USDC: &str = "EPjFWvd5AuFLSH3nm4pmjaPJ5reT2g5Wxa5NgQh3HfZ6";
+
+    fn swap(input_token: &str) -> TradeLeg {
+        TradeLeg::Swap {
+            chain: "sol".to_string(),
+            input_token: input_token.to_string(),
+            amount: "all".to_string(),
+            output_token: USDC.to_string(),
+            slippage_bps: 0,
+        }
+    }
+
+    #[test]
+    fn test_parse_legs() {
+        let legs: Vec<TradeLeg> = serde_json::from_value(json!([
+            {
+                "kind": "swap",
+                "chain": "sol",
+                "input_token": WSOL,
+                "amount": "half",
+                "output_token": USDC,
+            },
+            {
+                "kind": "transfer",
+                "chain": "sol",
+                "token": WSOL,
+                "amount": "0.1",
+                "recipient": USDC,
+            },
+        ]))
+        .unwrap();
+        assert!(legs.iter().all(|leg| leg.validate().is_ok()));
+        assert_eq!(legs[0].tool_call().0, "perform_jupiter_swap");
+        assert_eq!(legs[1].tool_call().0, "transfer_sol");
+
+        assert!(swap("not a mint").validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_plan() {
+        let legs = [swap(WSOL), swap("bad"), swap(WSOL)];
+        let run_leg = |_, args: Value| async move {
+            match args["input_mint"].as_str() {
+                Some(WSOL) => Ok("signature".to_string()),
+                _ => Err(anyhow!("Invalid mint")),
+            }
+        };
+
+        let report = run_plan(&legs, false, run_leg).await;
+        assert_eq!(
+            (report.executed, report.failed, report.skipped),
+            (1, 1, 1)
+        );
+        assert_eq!(report.legs[2].status, LegStatus::Skipped);
+
+        let report = run_plan(&legs, true, run_leg).await;
+        assert_eq!(
+            (report.executed, report.failed, report.skipped),
+            (2, 1, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recovery() {
+        const BONK: &str = "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263";
+        let hop = |input: &str, output: &str| TradeLeg::Swap {
+            chain: "sol".to_string(),
+            input_token: input.to_string(),
+            amount: "all".to_string(),
+            output_token: output.to_string(),
+            slippage_bps: 50,
+        };
+        let signature = Signature::from([7; 64]).to_string();
+        // SOL -> USDC ran, USDC -> BONK exceeded its slippage
+        let legs = [hop(WSOL, USDC), hop(USDC, BONK)];
+        let run_leg = |_, args: Value| {
+            let signature = signature.clone();
+            async move {
+                match args["input_mint"].as_str() {
+                    Some(WSOL) => Ok(signature),
+                    _ => Err(anyhow!("Slippage tolerance exceeded")),
+                }
+            }
+        };
+        let report = run_plan(&legs, false, run_leg).await;
+        let mut offered = recovery(&legs, &report).unwrap();
+        assert_eq!(offered.token, USDC);
+        assert_eq!(offered.tx, Some(signature.clone()));
+        assert_eq!(offered.complete, hop(USDC, BONK));
+        assert_eq!(offered.unwind, Some(hop(USDC, WSOL)));
+
+        // the swaps sell what SOL -> USDC produced, not the whole balance
+        offered.set_amount("25000000 raw");
+        let TradeLeg::Swap { amount, .. } = offered.unwind.unwrap() else {
+            unreachable!()
+        };
+        assert_eq!(amount, "25000000 raw");
+
+        // nothing is stranded when the route fails on its first swap
+        let legs = [hop(USDC, BONK), hop(BONK, WSOL)];
+        let report = run_plan(&legs, false, run_leg).await;
+        assert!(recovery(&legs, &report).is_none());
+    }
+}
@@ -0,0 +1,268 @@
+//! Trending tokens of a chain from the trending pools of GeckoTerminal, so
+//! "what's hot on Sonic right now" is answered with data. Pools below the
+//! liquidity and volume floors are left out, they are mostly wash traded or
+//! about to be rugged
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use rig_tool_macro::tool;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::common::http_client;
+use crate::metrics::observe_api;
+use crate::pricing::networks;
+use crate::tool_error::ToolError;
+
+const GECKOTERMINAL_API: &str = "https://api.geckoterminal.com/api/v2";
+const MAX_TOKENS: usize = 10;
+/// Floors when the caller passes 0
+const DEFAULT_MIN_LIQUIDITY_USD: f64 = 50_000.;
+const DEFAULT_MIN_VOLUME_USD: f64 = 10_000.;
+
+/// Time window the trend and the volume are measured over
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    M5,
+    H1,
+    H6,
+    H24,
+}
+
+impl Window {
+    /// `duration` of the trending endpoint
+    fn duration(&self) -> &'static str {
+        match self {
+            Self::M5 => "5m",
+            Self::H1 => "1h",
+            Self::H6 => "6h",
+            Self::H24 => "24h",
+        }
+    }
+
+    /// Key of the window in the volume and price change of a pool
+    fn key(&self) -> &'static str {
+        match self {
+            Self::M5 => "m5",
+            Self::H1 => "h1",
+            Self::H6 => "h6",
+            Self::H24 => "h24",
+        }
+    }
+}
+
+impl FromStr for Window {
+    type Err = ToolError;
+
+    fn from_str(s: &str) -> Result<Self, ToolError> {
+        match s.trim().to_lowercase().as_str() {
+            "5m" => Ok(Self::M5),
+            "1h" => Ok(Self::H1),
+            "6h" => Ok(Self::H6),
+            "" | "24h" | "1d" => Ok(Self::H24),
+            other => Err(ToolError::invalid_input(format!(
+                "Invalid window {}",
+                other
+            ))
+            .with_hint("Use 5m, 1h, 6h or 24h")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrendingToken {
+    pub symbol: String,
+    pub name: String,
+    pub address: String,
+    pub pool: String,
+    pub dex: Option<String>,
+    pub price_usd: Option<f64>,
+    pub liquidity_usd: f64,
+    /// volume over the window
+    pub volume_usd: f64,
+    /// price change over the window
+    pub price_change_pct: Option<f64>,
+    pub fdv_usd: Option<f64>,
+}
+
+/// GeckoTerminal sends most numbers as strings
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::String(s) => s.parse().ok(),
+        value => value.as_f64(),
+    }
+}
+
+/// Base tokens of the trending pools, a token trending in several pools is
+/// listed once with its first pool
+fn parse_trending(
+    response: &Value,
+    window: Window,
+) -> Result<Vec<TrendingToken>> {
+    let pools = response["data"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Invalid trending pools response"))?;
+    let included = response["included"].as_array();
+    let token = |id: &str| {
+        included?
+            .iter()
+            .find(|item| item["id"] == id)
+            .map(|item| &item["attributes"])
+    };
+
+    let mut seen = HashSet::new();
+    let mut tokens = vec![];
+    for pool in pools {
+        let attributes = &pool["attributes"];
+        let Some(token_id) =
+            pool["relationships"]["base_token"]["data"]["id"].as_str()
+        else {
+            continue;
+        };
+        let Some(token) = token(token_id) else {
+            continue;
+        };
+        let address = token["address"].as_str().unwrap_or_default();
+        if !seen.insert(address.to_string()) {
+            continue;
+        }
+        tokens.push(TrendingToken {
+            symbol: token["symbol"].as_str().unwrap_or_default().to_string(),
+            name: token["name"].as_str().unwrap_or_default().to_string(),
+            address: address.to_string(),
+            pool: attributes["address"].as_str().unwrap_or_default().into(),
+            dex: pool["relationships"]["dex"]["data"]["id"]
+                .as_str()
+                .map(str::to_string),
+            price_usd: number(&attributes["base_token_price_usd"]),
+            liquidity_usd: number(&attributes["reserve_in_usd"])
+                .unwrap_or_default(),
+            volume_usd: number(&attributes["volume_usd"][window.key()])
+                .unwrap_or_default(),
+            price_change_pct: number(
+                &attributes["price_change_percentage"][window.key()],
+            ),
+            fdv_usd: number(&attributes["fdv_usd"]),
+        });
+    }
+    Ok(tokens)
+}
+
+/// Trending tokens of `chain` over `window` above the liquidity and volume
+/// floors, at most `MAX_TOKENS`
+pub async fn trending_tokens(
+    chain: &str,
+    window: Window,
+    min_liquidity_usd: f64,
+    min_volume_usd: f64,
+) -> Result<Vec<TrendingToken>> {
+    let (_, network) = networks(chain)?;
+    let url = format!(
+        "{}/networks/{}/trending_pools?duration={}&include=base_token",
+        GECKOTERMINAL_API,
+        network,
+        window.duration()
+    );
+    let response = observe_api("geckoterminal", async {
+        Ok(http_client()
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?)
+    })
+    .await?;
+    Ok(parse_trending(&response, window)?
+        .into_iter()
+        .filter(|token| {
+            token.liquidity_usd >= min_liquidity_usd
+                && token.volume_usd >= min_volume_usd
+        })
+        .take(MAX_TOKENS)
+        .collect())
+}
+
+#[tool(description = "
+Lists the tokens trending right now on a chain (sol, sonic, eth, arb or
+base) from the trending pools of GeckoTerminal, with their price, liquidity,
+volume and price change. Use it for questions like what's hot on Sonic
+instead of guessing.
+
+window is 5m, 1h, 6h or 24h, the volume and price change are over it
+min_liquidity_usd and min_volume_usd leave out thin pools, 0 uses 50000 and
+10000
+")]
+pub async fn get_trending_tokens(
+    chain: String,
+    window: String,
+    min_liquidity_usd: f64,
+    min_volume_usd: f64,
+) -> Result<Vec<TrendingToken>> {
+    let floor = |value: f64, default: f64| {
+        if value > 0. {
+            value
+        } else {
+            default
+        }
+    };
+    trending_tokens(
+        &chain.trim().to_lowercase(),
+        Window::from_str(&window)?,
+        floor(min_liquidity_usd, DEFAULT_MIN_LIQUIDITY_USD),
+        floor(min_volume_usd, DEFAULT_MIN_VOLUME_USD),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_trending() {
+        let pool = |token: &str, liquidity: &str| {
+            json!({
+                "id": format!("sonic_pool_{}", token),
+                "type": "pool",
+                "attributes": {
+                    "address": format!("0xpool{}", token),
+                    "base_token_price_usd": "0.0421",
+                    "reserve_in_usd": liquidity,
+                    "fdv_usd": "4210000",
+                    "volume_usd": { "m5": "120", "h1": "8400", "h24": "91000" },
+                    "price_change_percentage": { "h1": "12.5", "h24": "-3.1" },
+                },
+                "relationships": {
+                    "base_token": { "data": { "id": format!("sonic_{}", token) } },
+                    "dex": { "data": { "id": "shadow-exchange" } },
+                },
+            })
+        };
+        let response = json!({
+            "data": [pool("0xa", "250000.5"), pool("0xb", "900"), pool("0xa", "1")],
+            "included": [
+                { "id": "sonic_0xa", "type": "token",
+                  "attributes": { "address": "0xa", "name": "Alpha", "symbol": "ALP" } },
+                { "id": "sonic_0xb", "type": "token",
+                  "attributes": { "address": "0xb", "name": "Beta", "symbol": "BET" } },
+            ],
+        });
+
+        let tokens = parse_trending(&response, Window::H1).unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].symbol, "ALP");
+        assert_eq!(tokens[0].liquidity_usd, 250000.5);
+        assert_eq!(tokens[0].volume_usd, 8400.);
+        assert_eq!(tokens[0].price_change_pct, Some(12.5));
+        assert_eq!(tokens[0].dex.as_deref(), Some("shadow-exchange"));
+        let tokens = parse_trending(&response, Window::H6).unwrap();
+        assert_eq!(tokens[1].volume_usd, 0.);
+        assert_eq!(tokens[1].price_change_pct, None);
+
+        assert_eq!(Window::from_str("").unwrap(), Window::H24);
+        assert!(Window::from_str("1w").is_err());
+    }
+}
@@ -0,0 +1,525 @@
+//! Stop-loss and take-profit orders. An order watches the price of a token
+//! relative to an entry price and runs a swap tool under the signer of its
+//! owner once the threshold is hit. Every state change is written to an
+//! audit log in the kv store
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use rig::agent::Agent;
+use rig::providers::anthropic::completion::CompletionModel;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::kv_store::{KVStore, KV_STORE};
+use crate::pricing::token_price;
+use crate::scheduler::run_tool_as;
+use crate::signer::{SignerContext, SignerRef, SignerResolver};
+
+const ORDERS_KEY: &str = "triggers:orders";
+const AUDIT_KEY: &str = "triggers:audit";
+const MAX_AUDIT_ENTRIES: usize = 500;
+const DEFAULT_TICK_SECS: u64 = 30;
+
+/// Tools an order can run when it triggers
+const SWAP_TOOLS: &[&str] = &[
+    "perform_jupiter_swap",
+    "sell_pump_fun_token",
+    "trade",
+    "multichain_swap",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerKind {
+    StopLoss,
+    TakeProfit,
+}
+
+impl FromStr for TriggerKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "stop_loss" | "stop-loss" | "sl" => Ok(Self::StopLoss),
+            "take_profit" | "take-profit" | "tp" => Ok(Self::TakeProfit),
+            _ => Err(anyhow!(
+                "Invalid kind {}, use stop_loss or take_profit",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TriggerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StopLoss => write!(f, "stop_loss"),
+            Self::TakeProfit => write!(f, "take_profit"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    Active,
+    /// picked up by the watcher, the swap is running
+    Triggered,
+    Executed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriggerOrder {
+    pub id: String,
+    pub owner: SignerRef,
+    pub chain: String,
+    pub token: String,
+    pub symbol: String,
+    pub kind: TriggerKind,
+    /// USD prices
+    pub entry_price: f64,
+    pub trigger_price: f64,
+    pub tool: String,
+    pub args: Value,
+    pub status: OrderStatus,
+    pub created_at: DateTime<Utc>,
+    pub triggered_at: Option<DateTime<Utc>>,
+    pub result: Option<String>,
+}
+
+impl TriggerOrder {
+    pub fn is_hit(&self, price: f64) -> bool {
+        match self.kind {
+            TriggerKind::StopLoss => price <= self.trigger_price,
+            TriggerKind::TakeProfit => price >= self.trigger_price,
+        }
+    }
+
+    fn price_key(&self) -> (String, String) {
+        (self.chain.clone(), self.token.clone())
+    }
+}
+
+/// Price at which an order of `kind` fires, `percent` away from `entry`
+pub fn trigger_price(kind: TriggerKind, entry: f64, percent: f64) -> f64 {
+    match kind {
+        TriggerKind::StopLoss => entry * (1. - percent / 100.),
+        TriggerKind::TakeProfit => entry * (1. + percent / 100.),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub order_id: String,
+    pub owner: SignerRef,
+    pub event: String,
+    pub price: Option<f64>,
+    pub detail: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+pub struct TriggerOrders {
+    store: Arc<dyn KVStore>,
+    /// orders are stored as a single value, writes have to be serialized
+    lock: tokio::sync::Mutex<()>,
+}
+
+pub static TRIGGERS: Lazy<TriggerOrders> =
+    Lazy::new(|| TriggerOrders::new(KV_STORE.clone()));
+
+impl TriggerOrders {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self {
+            store,
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    async fn load(&self) -> Result<Vec<TriggerOrder>> {
+        match self.store.get(ORDERS_KEY).await? {
+            Some(orders) => Ok(serde_json::from_str(&orders)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    async fn save(&self, orders: &[TriggerOrder]) -> Result<()> {
+        self.store
+            .set(ORDERS_KEY, &serde_json::to_string(orders)?)
+            .await
+    }
+
+    async fn audit(
+        &self,
+        order: &TriggerOrder,
+        event: &str,
+        price: Option<f64>,
+        detail: Option<String>,
+    ) -> Result<()> {
+        tracing::info!(
+            id = %order.id,
+            event,
+            symbol = %order.symbol,
+            price,
+            "trigger order"
+        );
+        let mut entries = self.audit_log(None).await?;
+        entries.insert(
+            0,
+            AuditEntry {
+                order_id: order.id.clone(),
+                owner: order.owner.clone(),
+                event: event.to_string(),
+                price,
+                detail,
+                at: Utc::now(),
+            },
+        );
+        entries.truncate(MAX_AUDIT_ENTRIES);
+        self.store
+            .set(AUDIT_KEY, &serde_json::to_string(&entries)?)
+            .await
+    }
+
+    /// Audit entries newest first, of every owner when `owner` is None
+    pub async fn audit_log(
+        &self,
+        owner: Option<&SignerRef>,
+    ) -> Result<Vec<AuditEntry>> {
+        let entries: Vec<AuditEntry> = match self.store.get(AUDIT_KEY).await?
+        {
+            Some(entries) => serde_json::from_str(&entries)?,
+            None => vec![],
+        };
+        Ok(entries
+            .into_iter()
+            .filter(|entry| owner.map_or(true, |o| entry.owner.same_user(o)))
+            .collect())
+    }
+
+    pub async fn add(&self, order: TriggerOrder) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut orders = self.load().await?;
+        orders.push(order.clone());
+        self.save(&orders).await?;
+        self.audit(&order, "created", Some(order.entry_price), None)
+            .await
+    }
+
+    pub async fn list(&self, owner: &SignerRef) -> Result<Vec<TriggerOrder>> {
+        Ok(self
+            .load()
+            .await?
+            .into_iter()
+            .filter(|order| order.owner.same_user(owner))
+            .collect())
+    }
+
+    /// Only active orders can be cancelled, a triggered order is already
+    /// swapping
+    pub async fn cancel(
+        &self,
+        id: &str,
+        owner: &SignerRef,
+    ) -> Result<TriggerOrder> {
+        let _guard = self.lock.lock().await;
+        let mut orders = self.load().await?;
+        let index = orders
+            .iter()
+            .position(|order| order.id == id && order.owner.same_user(owner))
+            .ok_or_else(|| anyhow!("Order {} not found", id))?;
+        if orders[index].status != OrderStatus::Active {
+            return Err(anyhow!("Order {} already triggered", id));
+        }
+        let order = orders.remove(index);
+        self.save(&orders).await?;
+        self.audit(&order, "cancelled", None, None).await?;
+        Ok(order)
+    }
+
+    /// Marks the active orders hit at `prices` (keyed by chain and token) as
+    /// triggered and returns them, so they are never executed twice
+    pub async fn take_triggered(
+        &self,
+        prices: &HashMap<(String, String), f64>,
+    ) -> Result<Vec<TriggerOrder>> {
+        let _guard = self.lock.lock().await;
+        let mut orders = self.load().await?;
+        let mut triggered = vec![];
+        for order in orders
+            .iter_mut()
+            .filter(|order| order.status == OrderStatus::Active)
+        {
+            let Some(price) = prices.get(&order.price_key()) else {
+                continue;
+            };
+            if order.is_hit(*price) {
+                order.status = OrderStatus::Triggered;
+                order.triggered_at = Some(Utc::now());
+                triggered.push((order.clone(), *price));
+            }
+        }
+        if !triggered.is_empty() {
+            self.save(&orders).await?;
+        }
+        for (order, price) in &triggered {
+            self.audit(order, "triggered", Some(*price), None).await?;
+        }
+        Ok(triggered.into_iter().map(|(order, _)| order).collect())
+    }
+
+    async fn record_result(
+        &self,
+        order: &TriggerOrder,
+        result: Result<String>,
+    ) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let (status, detail) = match result {
+            Ok(result) => (OrderStatus::Executed, result),
+            Err(e) => (OrderStatus::Failed, format!("Error: {}", e)),
+        };
+        let mut orders = self.load().await?;
+        if let Some(stored) = orders.iter_mut().find(|o| o.id == order.id) {
+            stored.status = status.clone();
+            stored.result = Some(detail.clone());
+            self.save(&orders).await?;
+        }
+        let event = match status {
+            OrderStatus::Executed => "executed",
+            _ => "failed",
+        };
+        self.audit(order, event, None, Some(detail)).await
+    }
+
+    /// Prices every watched token once, then runs the swaps of the orders
+    /// that were hit
+    pub async fn check(
+        &self,
+        agents: &[Arc<Agent<CompletionModel>>],
+        resolver: &dyn SignerResolver,
+    ) -> Result<()> {
+        let mut prices = HashMap::new();
+        for order in self.load().await? {
+            if order.status != OrderStatus::Active
+                || prices.contains_key(&order.price_key())
+            {
+                continue;
+            }
+            match token_price(&order.chain, &order.token).await {
+                Ok(price) => {
+                    prices.insert(order.price_key(), price.price_usd);
+                }
+                Err(e) => {
+                    tracing::warn!(?e, token = %order.token, "price failed")
+                }
+            }
+        }
+
+        for order in self.take_triggered(&prices).await? {
+            let result = run_tool_as(
+                &order.owner,
+                &order.tool,
+                &order.args,
+                agents,
+                resolver,
+            )
+            .await;
+            self.record_result(&order, result).await?;
+        }
+        Ok(())
+    }
+
+    /// Checks the orders every TRIGGERS_TICK_SECS (30 by default), the swap
+    /// tools are looked up in the given agents
+    pub fn spawn(
+        &'static self,
+        agents: Vec<Arc<Agent<CompletionModel>>>,
+        resolver: Arc<dyn SignerResolver>,
+    ) -> tokio::task::JoinHandle<()> {
+        let tick = std::env::var("TRIGGERS_TICK_SECS")
+            .ok()
+            .and_then(|tick| tick.parse().ok())
+            .unwrap_or(DEFAULT_TICK_SECS);
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(std::time::Duration::from_secs(tick));
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.check(&agents, resolver.as_ref()).await {
+                    tracing::error!(?e, "trigger order check failed");
+                }
+            }
+        })
+    }
+}
+
+async fn current_owner() -> SignerRef {
+    SignerRef::of(SignerContext::current().await.as_ref())
+}
+
+#[tool(description = "
+Sets a stop-loss or take-profit order: once the USD price of the token moves
+percent away from the entry price the given swap tool runs on behalf of the
+user, e.g. sell all BONK if it drops 20% from entry. The swap runs without
+asking again, so confirm the order with the user before calling this.
+
+chain is the chain of the token, e.g. sol, sonic, eth, arb, base
+token is the token address or mint that is watched
+kind is stop_loss or take_profit
+percent is the move from the entry price that triggers the order, e.g. 20
+entry_price is the USD entry price, pass 0 to use the current price
+tool is the swap tool to run, one of perform_jupiter_swap,
+sell_pump_fun_token, trade or multichain_swap
+args is a JSON object with the arguments of the swap tool, perform_jupiter_swap
+and trade take input_amount all to sell the balance held when the order
+triggers
+")]
+pub async fn set_trigger_order(
+    chain: String,
+    token: String,
+    kind: String,
+    percent: f64,
+    entry_price: f64,
+    tool: String,
+    args: String,
+) -> Result<TriggerOrder> {
+    let kind = TriggerKind::from_str(&kind)?;
+    if percent <= 0. || (kind == TriggerKind::StopLoss && percent >= 100.) {
+        return Err(anyhow!("Invalid percent {}", percent));
+    }
+    if !SWAP_TOOLS.contains(&tool.as_str()) {
+        return Err(anyhow!(
+            "{} cannot be used by orders, use one of {}",
+            tool,
+            SWAP_TOOLS.join(", ")
+        ));
+    }
+    let args: Value = serde_json::from_str(&args)
+        .map_err(|e| anyhow!("args has to be a JSON object: {}", e))?;
+    let signer = SignerContext::current().await;
+    signer.tool_access().check(&tool)?;
+
+    let price = token_price(&chain, &token).await?;
+    let entry_price = if entry_price > 0. {
+        entry_price
+    } else {
+        price.price_usd
+    };
+
+    let order = TriggerOrder {
+        id: format!("{:016x}", rand::random::<u64>()),
+        owner: SignerRef::of(signer.as_ref()),
+        chain,
+        token,
+        symbol: price.symbol,
+        kind,
+        entry_price,
+        trigger_price: trigger_price(kind, entry_price, percent),
+        tool,
+        args,
+        status: OrderStatus::Active,
+        created_at: Utc::now(),
+        triggered_at: None,
+        result: None,
+    };
+    if order.is_hit(price.price_usd) {
+        return Err(anyhow!(
+            "{} at {} is already past the {} price {}",
+            order.symbol,
+            price.price_usd,
+            kind,
+            order.trigger_price
+        ));
+    }
+    TRIGGERS.add(order.clone()).await?;
+    Ok(order)
+}
+
+#[tool(description = "
+Lists the stop-loss and take-profit orders of the user with their status and
+the result of the swap for executed orders
+")]
+pub async fn list_trigger_orders() -> Result<Vec<TriggerOrder>> {
+    TRIGGERS.list(&current_owner().await).await
+}
+
+#[tool(description = "
+Cancels an active stop-loss or take-profit order
+")]
+pub async fn cancel_trigger_order(id: String) -> Result<String> {
+    let order = TRIGGERS.cancel(&id, &current_owner().await).await?;
+    Ok(format!(
+        "Cancelled {} for {} at {}",
+        order.kind, order.symbol, order.trigger_price
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_store::InMemoryKVStore;
+
+    fn order(kind: TriggerKind, entry: f64, percent: f64) -> TriggerOrder {
+        TriggerOrder {
+            id: format!("{:016x}", rand::random::<u64>()),
+            owner: SignerRef {
+                user_id: Some("alice".to_string()),
+                session_id: None,
+            },
+            chain: "sol".to_string(),
+            token: "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263".to_string(),
+            symbol: "BONK".to_string(),
+            kind,
+            entry_price: entry,
+            trigger_price: trigger_price(kind, entry, percent),
+            tool: "perform_jupiter_swap".to_string(),
+            args: Value::Null,
+            status: OrderStatus::Active,
+            created_at: Utc::now(),
+            triggered_at: None,
+            result: None,
+        }
+    }
+
+    #[test]
+    fn test_trigger_price() {
+        let stop_loss = order(TriggerKind::StopLoss, 10., 20.);
+        assert_eq!(stop_loss.trigger_price, 8.);
+        assert!(!stop_loss.is_hit(8.5));
+        assert!(stop_loss.is_hit(7.9));
+
+        let take_profit = order(TriggerKind::TakeProfit, 10., 50.);
+        assert_eq!(take_profit.trigger_price, 15.);
+        assert!(take_profit.is_hit(15.));
+    }
+
+    #[tokio::test]
+    async fn test_order_triggers_once() {
+        let orders = TriggerOrders::new(Arc::new(InMemoryKVStore::new()));
+        let stop_loss = order(TriggerKind::StopLoss, 10., 20.);
+        orders.add(stop_loss.clone()).await.unwrap();
+
+        let prices = HashMap::from([(stop_loss.price_key(), 7.)]);
+        assert_eq!(orders.take_triggered(&prices).await.unwrap().len(), 1);
+        assert!(orders.take_triggered(&prices).await.unwrap().is_empty());
+        assert!(orders
+            .cancel(&stop_loss.id, &stop_loss.owner)
+            .await
+            .is_err());
+
+        let events = orders
+            .audit_log(Some(&stop_loss.owner))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.event)
+            .collect::<Vec<_>>();
+        assert_eq!(events, ["triggered", "created"]);
+    }
+}
@@ -0,0 +1,381 @@
+//! TWAP orders: a large swap split into equal slices that run one interval
+//! apart, so thin Sonic and Solana pairs see several small orders instead of
+//! one that moves the price. Every slice is queued as a twap_slice job, is
+//! quoted when its turn comes and goes through the dispatch pipeline like
+//! the legs of a trade plan. The amount received is read from the balance
+//! of the output token before and after the slice, the order reports the
+//! fills and the average execution price
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use rig_tool_macro::tool;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::address::Address;
+use crate::amount::{self, Amount};
+use crate::common::evm_chain;
+use crate::confirmation::{confirm_or_execute, preconfirmed};
+use crate::jobs::{Job, RetryPolicy, JOBS};
+use crate::kv_store::{namespaced, KVStore};
+use crate::scheduler::Schedule;
+use crate::signer::{SignerContext, SignerRef};
+use crate::tool_error::ToolError;
+use crate::trade_plan::{dispatch_leg, TradeLeg};
+
+pub const TWAP_SLICE_JOB: &str = "twap_slice";
+const MAX_SLICES: u32 = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TwapStatus {
+    Running,
+    Completed,
+    /// every slice failed
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TwapFill {
+    pub slice: u32,
+    pub at: DateTime<Utc>,
+    /// in whole tokens
+    pub amount_in: f64,
+    pub amount_out: f64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TwapOrder {
+    pub id: String,
+    pub owner: SignerRef,
+    pub chain: String,
+    pub token_in: String,
+    pub token_out: String,
+    /// raw amount of every slice, the last one takes the remainder
+    pub slice_amounts: Vec<String>,
+    pub decimals_in: u8,
+    pub interval_secs: u64,
+    pub status: TwapStatus,
+    pub fills: Vec<TwapFill>,
+    /// totals of the executed slices, in whole tokens
+    pub amount_in: f64,
+    pub amount_out: f64,
+    /// token_out received per token_in
+    pub average_price: Option<f64>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl TwapOrder {
+    fn record(&mut self, fill: TwapFill) {
+        self.fills.push(fill);
+        let executed = self.fills.iter().filter(|fill| fill.error.is_none());
+        self.amount_in = executed.clone().map(|fill| fill.amount_in).sum();
+        self.amount_out = executed.clone().map(|fill| fill.amount_out).sum();
+        self.average_price =
+            (self.amount_in > 0.).then(|| self.amount_out / self.amount_in);
+        if self.status == TwapStatus::Running
+            && self.fills.len() == self.slice_amounts.len()
+        {
+            self.status = match executed.count() {
+                0 => TwapStatus::Failed,
+                _ => TwapStatus::Completed,
+            };
+        }
+    }
+}
+
+/// Splits `raw` in `slices` equal amounts, the last one takes the
+/// remainder
+fn split(raw: u128, slices: u32) -> Vec<u128> {
+    let slice = raw / slices as u128;
+    let mut amounts = vec![slice; slices as usize];
+    if let Some(last) = amounts.last_mut() {
+        *last += raw % slices as u128;
+    }
+    amounts
+}
+
+pub struct TwapOrders {
+    store: Arc<dyn KVStore>,
+    /// slices of an order update it in turns
+    lock: tokio::sync::Mutex<()>,
+}
+
+pub static TWAP_ORDERS: Lazy<TwapOrders> =
+    Lazy::new(|| TwapOrders::new(Arc::new(namespaced("twap"))));
+
+impl TwapOrders {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self {
+            store,
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<TwapOrder>> {
+        match self.store.get(id).await? {
+            Some(order) => Ok(Some(serde_json::from_str(&order)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(&self, order: &TwapOrder) -> Result<()> {
+        self.store
+            .set(&order.id, &serde_json::to_string(order)?)
+            .await
+    }
+
+    /// The order if it belongs to the user of `owner`
+    async fn owned(&self, id: &str, owner: &SignerRef) -> Result<TwapOrder> {
+        self.get(id)
+            .await?
+            .filter(|order| order.owner.same_user(owner))
+            .ok_or_else(|| {
+                ToolError::not_found(format!("TWAP order {} not found", id))
+                    .into()
+            })
+    }
+
+    async fn update(
+        &self,
+        id: &str,
+        update: impl FnOnce(&mut TwapOrder),
+    ) -> Result<TwapOrder> {
+        let _guard = self.lock.lock().await;
+        let mut order = self.get(id).await?.ok_or_else(|| {
+            ToolError::not_found(format!("TWAP order {} not found", id))
+        })?;
+        update(&mut order);
+        self.save(&order).await?;
+        Ok(order)
+    }
+}
+
+/// Runs a queued slice under the signer of the owner of the order
+pub async fn run_slice(job: Job) -> Result<String> {
+    let id = job.payload["order"].as_str().unwrap_or_default();
+    let slice = job.payload["slice"].as_u64().unwrap_or_default() as u32;
+    let order = TWAP_ORDERS.get(id).await?.ok_or_else(|| {
+        ToolError::not_found(format!("TWAP order {} not found", id))
+    })?;
+    if order.status != TwapStatus::Running {
+        return Ok(format!("Skipped, the order is {:?}", order.status));
+    }
+    let raw = order
+        .slice_amounts
+        .get(slice as usize)
+        .ok_or_else(|| anyhow!("Order {} has no slice {}", id, slice))?;
+    let amount = Amount::new(raw.parse()?, order.decimals_in);
+    let before = amount::balance_of(&order.chain, &order.token_out).await?;
+    let leg = TradeLeg::Swap {
+        chain: order.chain.clone(),
+        input_token: order.token_in.clone(),
+        amount: format!("{} raw", amount.raw()),
+        output_token: order.token_out.clone(),
+        slippage_bps: 0,
+    };
+    let (tool, mut args) = leg.tool_call();
+    // equal slices must not be taken for retries of the same call
+    args["twap_slice"] = json!(format!("{}/{}", id, slice));
+    let outcome = preconfirmed(dispatch_leg(tool, args)).await;
+
+    let amount_out = match &outcome {
+        Ok(_) => {
+            match amount::balance_of(&order.chain, &order.token_out).await {
+                Ok(after) => Amount::new(
+                    after.raw().saturating_sub(before.raw()),
+                    after.decimals(),
+                )
+                .to_f64(),
+                Err(e) => {
+                    tracing::warn!(?e, id, "TWAP output not measured");
+                    0.
+                }
+            }
+        }
+        Err(_) => 0.,
+    };
+    let fill = TwapFill {
+        slice,
+        at: Utc::now(),
+        amount_in: amount.to_f64(),
+        amount_out,
+        error: outcome.as_ref().err().map(|e| e.to_string()),
+    };
+    TWAP_ORDERS.update(id, |order| order.record(fill)).await?;
+    outcome
+}
+
+#[tool(description = "
+Splits a large swap into equal slices executed one interval apart (TWAP) to
+reduce the price impact on thin pairs, on Solana or the EVM chain of the
+agent. Each slice is quoted when it runs. Requires the user's confirmation
+once, the slices are not confirmed again. Returns the order, get_twap_order
+reports its fills and the average execution price.
+
+token_in and token_out are mints on Solana or token addresses on EVM
+total_amount is the amount to sell as the user said it, e.g. 5000 USDC,
+half or all
+slices is the number of slices, 2 to 48
+interval is the time between slices, e.g. 5m, 1h
+")]
+pub async fn twap_swap(
+    token_in: String,
+    token_out: String,
+    total_amount: String,
+    slices: u32,
+    interval: String,
+) -> Result<String> {
+    let chain = match Address::parse(&token_in)? {
+        Address::Solana(_) => "sol".to_string(),
+        Address::Evm(_) => evm_chain(),
+    };
+    TradeLeg::Swap {
+        chain: chain.clone(),
+        input_token: token_in.clone(),
+        amount: total_amount.clone(),
+        output_token: token_out.clone(),
+        slippage_bps: 0,
+    }
+    .validate()?;
+    if !(2..=MAX_SLICES).contains(&slices) {
+        return Err(ToolError::invalid_input(format!(
+            "slices has to be between 2 and {}",
+            MAX_SLICES
+        ))
+        .into());
+    }
+    let schedule = Schedule::from_str(&format!("every {}", interval.trim()))?;
+    let Schedule::Every(every) = schedule else {
+        unreachable!("every schedules are intervals");
+    };
+    let every = every.to_std()?;
+
+    let total = amount::resolve(&chain, &token_in, &total_amount).await?;
+    let amounts = split(total.raw(), slices);
+    if amounts[0] == 0 {
+        return Err(ToolError::invalid_input(format!(
+            "{} is too small to split in {} slices",
+            total, slices
+        ))
+        .into());
+    }
+    let signer = SignerContext::current().await;
+    let order = TwapOrder {
+        id: format!("{:016x}", rand::random::<u64>()),
+        owner: SignerRef::of(signer.as_ref()),
+        chain: chain.clone(),
+        token_in,
+        token_out,
+        slice_amounts: amounts.iter().map(u128::to_string).collect(),
+        decimals_in: total.decimals(),
+        interval_secs: every.as_secs(),
+        status: TwapStatus::Running,
+        fills: vec![],
+        amount_in: 0.,
+        amount_out: 0.,
+        average_price: None,
+        created_at: Utc::now(),
+    };
+
+    let summary = format!(
+        "Swap {} of {} for {} on {} in {} slices of {} {}, the first one now",
+        total,
+        order.token_in,
+        order.token_out,
+        chain,
+        slices,
+        Amount::new(amounts[0], total.decimals()),
+        schedule
+    );
+    confirm_or_execute("twap_swap", summary, move || async move {
+        TWAP_ORDERS.save(&order).await?;
+        for slice in 0..slices {
+            let job = Job::new(
+                TWAP_SLICE_JOB,
+                json!({ "order": order.id, "slice": slice }),
+            )
+            .with_owner(order.owner.clone())
+            .with_retry(RetryPolicy::NONE)
+            .with_delay(every * slice);
+            JOBS.enqueue(job).await?;
+        }
+        Ok(serde_json::to_string(&order)?)
+    })
+    .await
+}
+
+#[tool(description = "
+Returns a TWAP order of the user: its status, the fills of the slices that
+ran so far, the totals and the average execution price (token_out received
+per token_in)
+")]
+pub async fn get_twap_order(id: String) -> Result<TwapOrder> {
+    let owner = SignerRef::of(SignerContext::current().await.as_ref());
+    TWAP_ORDERS.owned(&id, &owner).await
+}
+
+#[tool(description = "
+Cancels a running TWAP order, the slices that didn't run yet are skipped
+")]
+pub async fn cancel_twap_order(id: String) -> Result<TwapOrder> {
+    let owner = SignerRef::of(SignerContext::current().await.as_ref());
+    TWAP_ORDERS.owned(&id, &owner).await?;
+    TWAP_ORDERS
+        .update(&id, |order| {
+            if order.status == TwapStatus::Running {
+                order.status = TwapStatus::Cancelled;
+            }
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_fills() {
+        assert_eq!(split(1_000, 3), [333, 333, 334]);
+        assert_eq!(split(2, 4), [0, 0, 0, 2]);
+
+        let mut order = TwapOrder {
+            id: "order".to_string(),
+            owner: SignerRef {
+                user_id: Some("alice".to_string()),
+                session_id: None,
+            },
+            chain: "sol".to_string(),
+            token_in: "in".to_string(),
+            token_out: "out".to_string(),
+            slice_amounts: vec!["50".to_string(); 3],
+            decimals_in: 0,
+            interval_secs: 60,
+            status: TwapStatus::Running,
+            fills: vec![],
+            amount_in: 0.,
+            amount_out: 0.,
+            average_price: None,
+            created_at: Utc::now(),
+        };
+        let fill = |slice, amount_out, error: Option<&str>| TwapFill {
+            slice,
+            at: Utc::now(),
+            amount_in: 50.,
+            amount_out,
+            error: error.map(str::to_string),
+        };
+        order.record(fill(0, 100., None));
+        order.record(fill(1, 0., Some("slippage exceeded")));
+        assert_eq!(order.status, TwapStatus::Running);
+        order.record(fill(2, 90., None));
+        assert_eq!(order.status, TwapStatus::Completed);
+        assert_eq!(order.amount_in, 100.);
+        assert_eq!(order.average_price, Some(1.9));
+    }
+}
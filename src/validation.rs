@@ -0,0 +1,219 @@
+//! Sanity checks of the arguments the LLM passes to value-moving tools, run
+//! before anything is quoted or built so a bad address or a mis-scaled
+//! amount fails with an actionable `invalid_input` error rather than deep
+//! inside transaction construction
+use anyhow::Result;
+
+use crate::address::Address;
+use crate::chains::Chain;
+use crate::pricing::{to_ui_amount, token_price};
+use crate::tool_error::ToolError;
+
+pub const MAX_SLIPPAGE_BPS: u16 = 5_000;
+
+/// Decimal amounts above this are assumed to be scaled by the decimals twice
+const MAX_UI_AMOUNT: f64 = 1e12;
+/// Decimal amounts below this are assumed to be passed without the decimals
+const MIN_UI_AMOUNT: f64 = 1e-6;
+
+fn invalid(message: String, hint: &str) -> anyhow::Error {
+    ToolError::invalid_input(message).with_hint(hint).into()
+}
+
+/// Parses `value` as an address on `chain`, "sol" or any EVM chain
+pub fn address(chain: &str, field: &str, value: &str) -> Result<Address> {
+    Address::parse_on(chain, value).map_err(|_| {
+        let format = match Chain::of_key(chain) {
+            Chain::Solana => "a base58 Solana public key",
+            Chain::Evm => {
+                "a 0x-prefixed 40 hex digit address, EIP-55 checksummed \
+                 if mixed case"
+            }
+        };
+        invalid(
+            format!("{} {:?} is not a valid {} address", field, value, chain),
+            &format!("{} has to be {}", field, format),
+        )
+    })
+}
+
+/// Like `address` but also accepts a token symbol, for the tools resolving
+/// symbols through LiFi
+pub fn token(chain: &str, field: &str, value: &str) -> Result<()> {
+    let symbol = !value.is_empty()
+        && value.len() <= 12
+        && value.chars().all(|c| c.is_ascii_alphanumeric());
+    if symbol {
+        return Ok(());
+    }
+    address(chain, field, value).map(|_| ()).map_err(|_| {
+        invalid(
+            format!(
+                "{} {:?} is neither a symbol nor an address",
+                field, value
+            ),
+            "Use the token symbol (e.g. USDC) or its address on the chain",
+        )
+    })
+}
+
+/// Parses a raw integer amount, fails for zero, decimals and notations
+/// like 1e6
+pub fn raw_amount(field: &str, value: &str) -> Result<u128> {
+    let hint = "Amounts are integers in the smallest unit, e.g. 1.5 USDC with 6 decimals is 1500000";
+    let amount = value.trim().parse::<u128>().map_err(|_| {
+        invalid(format!("{} {:?} is not an integer", field, value), hint)
+    })?;
+    positive(field, amount)?;
+    Ok(amount)
+}
+
+pub fn positive<T: PartialOrd + Default>(
+    field: &str,
+    value: T,
+) -> Result<()> {
+    if value <= T::default() {
+        return Err(invalid(
+            format!("{} has to be greater than 0", field),
+            "Ask the user for the amount",
+        ));
+    }
+    Ok(())
+}
+
+pub fn percent(field: &str, value: u8) -> Result<()> {
+    if value == 0 || value > 100 {
+        return Err(invalid(
+            format!("{} {} is out of bounds (1-100)", field, value),
+            "Pass a whole percentage, 50 is half",
+        ));
+    }
+    Ok(())
+}
+
+pub fn slippage_bps(value: u16) -> Result<()> {
+    if value == 0 || value > MAX_SLIPPAGE_BPS {
+        return Err(invalid(
+            format!(
+                "slippage_bps {} is out of bounds (1-{})",
+                value, MAX_SLIPPAGE_BPS
+            ),
+            "slippage_bps is in basis points, 50 is 0.5%",
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the decimal value of a raw `amount` of `token` is plausible, the
+/// usual mistakes are a missing or a doubled scaling by the decimals. Tokens
+/// LiFi doesn't know are let through
+pub async fn amount_decimals(
+    chain: &str,
+    token: &str,
+    amount: &str,
+) -> Result<()> {
+    let Ok(info) = token_price(chain, token).await else {
+        return Ok(());
+    };
+    check_ui_amount(amount, &info.symbol, info.decimals)
+}
+
+/// Checks of a swap of a raw `amount` of `input` for `output`, both given
+/// by address
+pub async fn swap(
+    chain: &str,
+    input: &str,
+    amount: &str,
+    output: &str,
+) -> Result<()> {
+    address(chain, "input token", input)?;
+    address(chain, "output token", output)?;
+    raw_amount("amount", amount)?;
+    amount_decimals(chain, input, amount).await
+}
+
+/// Checks of a transfer of a raw `amount` of `token` to `recipient`,
+/// returns the parsed recipient
+pub async fn transfer(
+    chain: &str,
+    recipient: &str,
+    token: &str,
+    amount: &str,
+) -> Result<Address> {
+    let recipient = address(chain, "recipient", recipient)?;
+    address(chain, "token", token)?;
+    raw_amount("amount", amount)?;
+    amount_decimals(chain, token, amount).await?;
+    Ok(recipient)
+}
+
+fn check_ui_amount(amount: &str, symbol: &str, decimals: u8) -> Result<()> {
+    let ui_amount = to_ui_amount(amount, decimals)?;
+    let example = format!(
+        "{} has {} decimals, 1 {} is 1{}",
+        symbol,
+        decimals,
+        symbol,
+        "0".repeat(decimals as usize)
+    );
+    if ui_amount > MAX_UI_AMOUNT {
+        return Err(invalid(
+            format!(
+                "{} {} is {} {}, the amount looks scaled by the decimals twice",
+                amount, symbol, ui_amount, symbol
+            ),
+            &example,
+        ));
+    }
+    if ui_amount < MIN_UI_AMOUNT {
+        return Err(invalid(
+            format!(
+                "{} {} is only {} {}, the amount looks like it misses the decimals",
+                amount, symbol, ui_amount, symbol
+            ),
+            &example,
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tool_error::ErrorCode;
+
+    fn code<T: std::fmt::Debug>(result: Result<T>) -> ErrorCode {
+        ToolError::from_anyhow(&result.unwrap_err()).code
+    }
+
+    #[test]
+    fn test_addresses() {
+        let sol = "So11111111111111111111111111111111111111112";
+        let evm = "0x1f9840a85d5aF5bf1D1762F925BDADdC4201F984";
+        assert!(address("sol", "to", sol).is_ok());
+        assert!(address("arb", "to", evm).is_ok());
+        assert_eq!(code(address("sol", "to", evm)), ErrorCode::InvalidInput);
+        assert!(address("arb", "to", "0x1f98").is_err());
+        let broken_checksum = "0x1f9840a85d5af5bf1D1762F925BDADdC4201F984";
+        assert!(address("arb", "to", broken_checksum).is_err());
+        assert!(token("arb", "from_token_symbol", "USDC").is_ok());
+        assert!(token("arb", "from_token_symbol", "US DC").is_err());
+    }
+
+    #[test]
+    fn test_amounts() {
+        assert_eq!(raw_amount("amount", "1500000").unwrap(), 1_500_000);
+        assert!(raw_amount("amount", "1.5").is_err());
+        assert!(raw_amount("amount", "1e6").is_err());
+        assert!(raw_amount("amount", "0").is_err());
+        assert!(positive("sol_amount", 0.).is_err());
+        assert!(slippage_bps(50).is_ok());
+        assert!(slippage_bps(0).is_err());
+        assert!(slippage_bps(10_000).is_err());
+
+        assert!(check_ui_amount("1000000000", "SOL", 9).is_ok());
+        assert!(check_ui_amount("1", "SOL", 9).is_err());
+        let doubled = format!("1{}", "0".repeat(24));
+        assert!(check_ui_amount(&doubled, "USDC", 6).is_err());
+    }
+}
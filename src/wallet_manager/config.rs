@@ -1,22 +1,57 @@
 use anyhow::Result;
 
+use crate::error::Error;
+
+const PRIVY_API_URL: &str = "https://api.privy.io";
+const PRIVY_AUTH_URL: &str = "https://auth.privy.io";
+
 #[derive(Clone, Debug)]
 pub struct PrivyConfig {
     pub(crate) app_id: String,
     pub(crate) app_secret: String,
     pub(crate) verification_key: String,
+    /// wallets and their RPC
+    pub(crate) api_url: String,
+    /// authentication and users
+    pub(crate) auth_url: String,
 }
 
 impl PrivyConfig {
+    pub fn new(
+        app_id: &str,
+        app_secret: &str,
+        verification_key: &str,
+    ) -> Self {
+        Self {
+            app_id: app_id.to_string(),
+            app_secret: app_secret.to_string(),
+            verification_key: verification_key.to_string(),
+            api_url: PRIVY_API_URL.to_string(),
+            auth_url: PRIVY_AUTH_URL.to_string(),
+        }
+    }
+
+    /// Points the client at other hosts than Privy's, e.g. a mock server
+    pub fn with_urls(mut self, api_url: &str, auth_url: &str) -> Self {
+        self.api_url = api_url.trim_end_matches('/').to_string();
+        self.auth_url = auth_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// The privy section of the config, PRIVY_APP_ID, PRIVY_APP_SECRET
+    /// and PRIVY_VERIFICATION_KEY override it
     pub fn from_env() -> Result<Self> {
-        Ok(Self {
-            app_id: std::env::var("PRIVY_APP_ID")
-                .expect("PRIVY_APP_ID is not set"),
-            app_secret: std::env::var("PRIVY_APP_SECRET")
-                .expect("PRIVY_APP_SECRET is not set"),
-            verification_key: std::env::var("PRIVY_VERIFICATION_KEY")
-                .expect("PRIVY_VERIFICATION_KEY is not set"),
-        })
+        let config = crate::config::init()?;
+        let privy = &config.privy;
+        let (Some(app_id), Some(app_secret), Some(verification_key)) =
+            (&privy.app_id, &privy.app_secret, &privy.verification_key)
+        else {
+            return Err(Error::Privy(
+                "The Privy app is not configured".to_string(),
+            )
+            .into());
+        };
+        Ok(Self::new(app_id, app_secret, verification_key))
     }
 }
 
@@ -0,0 +1,189 @@
+//! Privy wallet ids of the addresses the manager signs for. The wallets are
+//! created and recorded by the frontend, the manager only looks them up in
+//! the store of database.url: Postgres, SQLite or memory for tests and
+//! local runs. Stores own their connection pool, it is opened once and
+//! reused by every signature
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use sqlx::postgres::PgPool;
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+use tokio::sync::OnceCell;
+
+use crate::config::DatabaseSettings;
+use crate::db::{db_pool, Backend};
+
+#[async_trait]
+pub trait WalletStore: Send + Sync {
+    /// Privy id of the current wallet of `address`
+    async fn wallet_id(&self, address: &str) -> Result<Option<String>>;
+}
+
+/// Store of database.url
+pub fn wallet_store(
+    database: &DatabaseSettings,
+) -> Result<Arc<dyn WalletStore>> {
+    match Backend::of(&database.url) {
+        Some(Backend::Postgres) => {
+            Ok(Arc::new(PostgresWalletStore::with_pool(db_pool()?)))
+        }
+        Some(Backend::Sqlite) => Ok(Arc::new(SqliteWalletStore::connect(
+            &database.url,
+            database.max_connections,
+        )?)),
+        Some(Backend::Memory) => Ok(Arc::new(InMemoryWalletStore::new())),
+        None => Err(anyhow!("Unsupported database URL {}", database.url)),
+    }
+}
+
+/// The wallets table of the frontend, shares `db_pool` with the kv_store
+pub struct PostgresWalletStore {
+    pool: PgPool,
+}
+
+impl PostgresWalletStore {
+    pub fn with_pool(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl WalletStore for PostgresWalletStore {
+    async fn wallet_id(&self, address: &str) -> Result<Option<String>> {
+        Ok(sqlx::query(
+            "SELECT wallet_id FROM wallets
+             WHERE address = $1 AND current_wallet = TRUE
+             LIMIT 1",
+        )
+        .bind(address)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|row| row.get("wallet_id")))
+    }
+}
+
+const SQLITE_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS wallets (
+    address TEXT NOT NULL,
+    wallet_id TEXT NOT NULL,
+    current_wallet BOOLEAN NOT NULL DEFAULT TRUE
+)";
+
+/// Same table as Postgres, created on first use
+pub struct SqliteWalletStore {
+    pool: SqlitePool,
+    created: OnceCell<()>,
+}
+
+impl SqliteWalletStore {
+    /// Opens the connections on first use
+    pub fn connect(url: &str, max_connections: u32) -> Result<Self> {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect_lazy(url)?;
+        Ok(Self {
+            pool,
+            created: OnceCell::new(),
+        })
+    }
+
+    async fn pool(&self) -> Result<&SqlitePool> {
+        self.created
+            .get_or_try_init(|| async {
+                sqlx::query(SQLITE_SCHEMA).execute(&self.pool).await?;
+                Ok::<_, anyhow::Error>(())
+            })
+            .await?;
+        Ok(&self.pool)
+    }
+
+    /// Makes `wallet_id` the current wallet of `address`
+    pub async fn insert(&self, address: &str, wallet_id: &str) -> Result<()> {
+        let pool = self.pool().await?;
+        sqlx::query(
+            "UPDATE wallets SET current_wallet = FALSE WHERE address = ?",
+        )
+        .bind(address)
+        .execute(pool)
+        .await?;
+        sqlx::query("INSERT INTO wallets (address, wallet_id) VALUES (?, ?)")
+            .bind(address)
+            .bind(wallet_id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WalletStore for SqliteWalletStore {
+    async fn wallet_id(&self, address: &str) -> Result<Option<String>> {
+        Ok(sqlx::query(
+            "SELECT wallet_id FROM wallets
+             WHERE address = ? AND current_wallet = TRUE
+             LIMIT 1",
+        )
+        .bind(address)
+        .fetch_optional(self.pool().await?)
+        .await?
+        .map(|row| row.get("wallet_id")))
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryWalletStore {
+    wallets: RwLock<HashMap<String, String>>,
+}
+
+impl InMemoryWalletStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `wallet_id` the current wallet of `address`
+    pub fn insert(&self, address: &str, wallet_id: &str) {
+        self.wallets
+            .write()
+            .unwrap()
+            .insert(address.to_string(), wallet_id.to_string());
+    }
+}
+
+#[async_trait]
+impl WalletStore for InMemoryWalletStore {
+    async fn wallet_id(&self, address: &str) -> Result<Option<String>> {
+        Ok(self.wallets.read().unwrap().get(address).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wallet_stores() {
+        let memory = InMemoryWalletStore::new();
+        memory.insert("owner", "wallet");
+        assert_eq!(
+            memory.wallet_id("owner").await.unwrap().as_deref(),
+            Some("wallet")
+        );
+        assert_eq!(memory.wallet_id("other").await.unwrap(), None);
+
+        let path = std::env::temp_dir()
+            .join(format!("wallets-{}.db", std::process::id()));
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+        assert_eq!(Backend::of(&url), Some(Backend::Sqlite));
+        let sqlite = SqliteWalletStore::connect(&url, 1).unwrap();
+        assert_eq!(sqlite.wallet_id("owner").await.unwrap(), None);
+        sqlite.insert("owner", "old").await.unwrap();
+        sqlite.insert("owner", "wallet").await.unwrap();
+        assert_eq!(
+            sqlite.wallet_id("owner").await.unwrap().as_deref(),
+            Some("wallet")
+        );
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -14,7 +14,6 @@ pub struct SignTransactionRequest {
     pub address: String,
     pub chain_type: String,
     pub method: String,
-    // pub caip2: String,
     pub params: SignTransactionParams,
 }
 
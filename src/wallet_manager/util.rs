@@ -38,9 +38,8 @@ pub fn base64encode(data: &[u8]) -> String {
     STANDARD.encode(data)
 }
 
-#[cfg(feature = "solana")]
 pub fn transaction_to_base64(
-    transaction: &solana_sdk::transaction::Transaction,
+    transaction: &impl serde::Serialize,
 ) -> anyhow::Result<String> {
     let serialized = bincode::serialize(transaction)?;
     Ok(base64encode(&serialized))